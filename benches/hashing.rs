@@ -0,0 +1,62 @@
+//! Full vs pseudo hashing across file sizes, and the per-file decision that
+//! lets a sync skip hashing and uploading (`trust_mtime`).
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use phone_sync::file_stamp::StampMatch;
+use phone_sync::fingerprint::{remote_state, RemoteState};
+use phone_sync::hash_store::HashStore;
+use std::hint::black_box;
+use std::time::Duration;
+
+#[path = "../tests/fixture_gen.rs"]
+mod fixture_gen;
+
+fn hashing(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let mut group = c.benchmark_group("hash");
+    for size in [4 * 1024, 1024 * 1024, 16 * 1024 * 1024] {
+        let path = dir.path().join(format!("{}.bin", size));
+        fixture_gen::write_file(&path, size, 1);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("sha256", size), &path, |b, path| {
+            b.iter(|| runtime.block_on(HashStore::compute_hash(path)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("pseudo", size), &path, |b, path| {
+            b.iter(|| runtime.block_on(HashStore::compute_pseudo_hash(path)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// The checks the sync loop makes per file before deciding to skip it, on
+/// a store where every file is unchanged.
+fn skip_decision(c: &mut Criterion) {
+    let entries = 100_000;
+    let store = fixture_gen::store(entries);
+    let files: Vec<_> = (0..entries)
+        .map(|index| {
+            let key = fixture_gen::store_key(index);
+            (store.stamps[&key], store.fingerprints[&key].clone(), store.regular_hashes[&key].clone(), key)
+        })
+        .collect();
+    let mut group = c.benchmark_group("skip_decision");
+    group.throughput(Throughput::Elements(entries as u64));
+    group.bench_function("unchanged", |b| {
+        b.iter(|| {
+            let mut skipped = 0;
+            for (stamp, fingerprint, hash, key) in &files {
+                let trusted = store.stamps.get(key).is_some_and(|s| s.compare(stamp, Duration::ZERO) == StampMatch::Unchanged);
+                let remote = remote_state(Some(fingerprint), store.fingerprints.get(key), Duration::ZERO);
+                if trusted && remote == RemoteState::Unchanged && store.regular_hashes.get(key) == Some(hash) {
+                    skipped += 1;
+                }
+            }
+            black_box(skipped)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, hashing, skip_decision);
+criterion_main!(benches);
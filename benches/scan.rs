@@ -0,0 +1,26 @@
+//! Walking a deep local tree, as every sync does first.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use phone_sync::config::Config;
+use phone_sync::nomedia::NomediaFilter;
+use phone_sync::sync::folder_files;
+
+#[path = "../tests/fixture_gen.rs"]
+mod fixture_gen;
+
+fn scan(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let files = fixture_gen::deep_tree(dir.path(), 4, 4, 10);
+    let config: Config = serde_yaml::from_str("webdav_url: \"x\"\nfolders: []\n").unwrap();
+    let nomedia = NomediaFilter::from_config(&config);
+    let mut group = c.benchmark_group("scan");
+    group.throughput(Throughput::Elements(files as u64));
+    // Sorted deepest first by default, streamed in low-memory mode.
+    for (name, low_memory) in [("sorted", false), ("low_memory", true)] {
+        group.bench_function(name, |b| b.iter(|| assert_eq!(folder_files(dir.path(), low_memory, &nomedia).count(), files)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, scan);
+criterion_main!(benches);
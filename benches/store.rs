@@ -0,0 +1,35 @@
+//! Loading and saving the YAML hash store at growing sizes. It is the only
+//! store format of this crate.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use phone_sync::hash_store::HashStore;
+use phone_sync::store_scope::Scope;
+
+#[path = "../tests/fixture_gen.rs"]
+mod fixture_gen;
+
+fn store(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let mut group = c.benchmark_group("store");
+    group.sample_size(10);
+    for entries in [10_000, 100_000, 1_000_000] {
+        let store = fixture_gen::store(entries);
+        let path = dir.path().join(format!("{}.yaml", entries));
+        store.save(&path).unwrap();
+        group.throughput(Throughput::Elements(entries as u64));
+        group.bench_with_input(BenchmarkId::new("load_yaml", entries), &path, |b, path| {
+            b.iter(|| HashStore::load(path).unwrap())
+        });
+        // A device with another target_dir keeps every entry as text.
+        group.bench_with_input(BenchmarkId::new("load_yaml_other_scope", entries), &path, |b, path| {
+            b.iter(|| HashStore::load_scoped(path, Scope::new(["tablet"]).as_ref()).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("save_yaml", entries), &store, |b, store| {
+            b.iter(|| store.save(&path).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, store);
+criterion_main!(benches);
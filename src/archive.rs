@@ -0,0 +1,128 @@
+//! Local archive of remote files about to be deleted by `mirror_deletions`.
+//!
+//! Before the remote copy of a locally deleted file is removed, it is
+//! downloaded to `local_archive_dir/<YYYY-MM-DD>/<remote path>`, so a mistaken
+//! local deletion can still be undone. Archives older than
+//! `archive_retention` are pruned.
+
+use crate::budget::date_of;
+use crate::config::Config;
+use crate::local_path::resolve_local_destination;
+use crate::webdav_client::{VerifiedDownload, WebDavClient};
+use log::info;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// The configured archive for deleted files.
+#[derive(Debug, Clone)]
+pub struct LocalArchive {
+    dir: PathBuf,
+    retention: Option<Duration>,
+}
+
+impl LocalArchive {
+    pub fn new<P: AsRef<Path>>(dir: P, retention: Option<Duration>) -> Self {
+        LocalArchive { dir: dir.as_ref().to_path_buf(), retention }
+    }
+
+    /// The archive configured by `local_archive_dir`, if any.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config
+            .local_archive_dir
+            .as_ref()
+            .map(|dir| Self::new(dir, config.archive_retention))
+    }
+
+    /// Download `remote_path` into the archive of the day of `now`, unless it
+    /// was archived there already. Returns the archived file, or `None` when
+    /// the remote copy no longer exists.
+    pub async fn archive(
+        &self,
+        client: &WebDavClient,
+        remote_path: &str,
+        now: SystemTime,
+    ) -> Result<Option<PathBuf>, Box<dyn Error>> {
+        let day_dir = self.dir.join(date_of(now));
+        std::fs::create_dir_all(&day_dir)?;
+        let destination = resolve_local_destination(&day_dir, remote_path)?;
+        if destination.exists() {
+            return Ok(Some(destination));
+        }
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Archive the version that is about to be deleted, not one that is
+        // being rewritten on the server right now.
+        let Some(stat) = client.stat(remote_path).await? else {
+            return Ok(None);
+        };
+        match client.download_verified(remote_path, &destination, &stat.fingerprint()).await? {
+            VerifiedDownload::Downloaded(_) => Ok(Some(destination)),
+            VerifiedDownload::Missing => Ok(None),
+            VerifiedDownload::Unstable => {
+                Err(format!("'{}' kept changing on the server while it was archived", remote_path).into())
+            }
+        }
+    }
+
+    /// Remove day archives older than the retention period. Returns the number
+    /// of removed days.
+    pub fn prune(&self, now: SystemTime) -> Result<usize, Box<dyn Error>> {
+        let Some(retention) = self.retention else {
+            return Ok(0);
+        };
+        let oldest_kept = date_of(now - retention);
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let mut removed = 0;
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            // Only touch directories named like a date; YYYY-MM-DD sorts chronologically.
+            if is_date(&name) && name < oldest_kept && entry.file_type()?.is_dir() {
+                std::fs::remove_dir_all(entry.path())?;
+                info!("Pruned archive {}", entry.path().display());
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+fn is_date(name: &str) -> bool {
+    name.len() == 10
+        && name
+            .char_indices()
+            .all(|(i, c)| if i == 4 || i == 7 { c == '-' } else { c.is_ascii_digit() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_prune_keeps_recent_days_and_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["2024-02-01", "2024-02-20", "2024-03-01", "notes"] {
+            std::fs::create_dir(dir.path().join(name)).unwrap();
+        }
+        // 2024-03-01 00:00 UTC
+        let now = UNIX_EPOCH + Duration::from_secs(1_709_251_200);
+
+        let archive = LocalArchive::new(dir.path(), Some(Duration::from_secs(10 * 86_400)));
+        assert_eq!(archive.prune(now).unwrap(), 1);
+        let mut left: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(left, vec!["2024-02-20", "2024-03-01", "notes"]);
+
+        assert_eq!(LocalArchive::new(dir.path(), None).prune(now).unwrap(), 0);
+    }
+}
@@ -0,0 +1,178 @@
+//! Files of the syncer itself, which are never synced as content, and the
+//! lock keeping runs with overlapping folders apart.
+//!
+//! Besides this config's hash store, a folder may hold the artifacts of other
+//! configs syncing an overlapping tree: their hash stores (listed in
+//! `artifact_names`), run locks (`*.phone_sync.lock`) and partial downloads
+//! (`*.part`). While one run scans a folder, another writing its store into
+//! it would race with the scan, so a [`RunLock`] refuses to start a run whose
+//! folders overlap those of a running one, unless both set
+//! `allow_overlapping_runs`. Runs sharing a remote hash store (the same
+//! `remote_hash_path` on the same server) are refused as well, as they would
+//! overwrite each other's store, unless it takes `hash_store_deltas`. Runs
+//! only see each other's locks if they share the temp root (`temp_dir`).
+
+use crate::config::{Config, RemoteHashStore};
+use crate::sync::hash_store_file_name;
+use crate::webdav_client::PART_SUFFIX;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Suffix of run lock files.
+pub const LOCK_SUFFIX: &str = ".phone_sync.lock";
+
+/// Distinguishes the locks taken by one process.
+static LOCK_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// Names of local files that are artifacts of a sync, not content.
+#[derive(Debug, Clone)]
+pub struct SyncArtifacts {
+    /// The file name of this config's hash store, which is always skipped.
+    own_store: String,
+    /// Further names from `artifact_names`; empty with `upload_sync_artifacts`.
+    names: Vec<String>,
+    /// Whether lock and partial download files are skipped.
+    conventions: bool,
+}
+
+impl SyncArtifacts {
+    pub fn from_config(config: &Config) -> Self {
+        let skip = !config.upload_sync_artifacts;
+        SyncArtifacts {
+            own_store: hash_store_file_name(config),
+            names: if skip { config.artifact_names.clone() } else { Vec::new() },
+            conventions: skip,
+        }
+    }
+
+    /// Whether a file named `name` is an artifact to skip.
+    pub fn contains(&self, name: &OsStr) -> bool {
+        let name = name.to_string_lossy();
+        name == self.own_store
+            || self.names.iter().any(|n| *n == name)
+            || (self.conventions && (name.ends_with(LOCK_SUFFIX) || name.ends_with(PART_SUFFIX)))
+    }
+}
+
+/// What a lock file records about its run.
+#[derive(Debug, Serialize, Deserialize)]
+struct LockInfo {
+    pid: u32,
+    folders: Vec<PathBuf>,
+    allow_overlap: bool,
+    /// URL of the remote hash store, if the run uses one.
+    #[serde(default)]
+    remote_store: Option<String>,
+}
+
+/// Marks the folders of a running sync; removed on drop.
+#[derive(Debug)]
+pub struct RunLock {
+    path: PathBuf,
+}
+
+impl RunLock {
+    /// Take the lock for the folders of `config`, failing if a running sync
+    /// has a folder inside one of them or around one of them, or uses the
+    /// same remote hash store.
+    pub fn acquire(config: &Config) -> Result<Self, Box<dyn Error>> {
+        let root = config.temp_dir.as_ref().map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+        fs::create_dir_all(&root)?;
+        let info = LockInfo {
+            pid: std::process::id(),
+            // Folders that do not exist cannot overlap anything now.
+            folders: config.folders.iter().filter_map(|f| Path::new(&f.path).canonicalize().ok()).collect(),
+            allow_overlap: config.allow_overlapping_runs,
+            // Deltas are made for devices writing the store concurrently.
+            remote_store: (config.remote_hash_store == RemoteHashStore::Enabled && config.hash_store_deltas.is_none())
+                .then(|| format!("{}/{}", config.webdav_url.trim_end_matches('/'), config.remote_hash_path.trim_start_matches('/'))),
+        };
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+        let name = format!("{}-{}-{}{}", info.pid, nanos, LOCK_COUNTER.fetch_add(1, Ordering::Relaxed), LOCK_SUFFIX);
+        // Written before looking at the others, so of two runs starting at
+        // once at least one sees the other.
+        let lock = RunLock { path: root.join(name) };
+        fs::write(&lock.path, serde_json::to_string(&info)?)?;
+
+        for entry in fs::read_dir(&root)? {
+            let path = entry?.path();
+            if path == lock.path || !path.to_string_lossy().ends_with(LOCK_SUFFIX) {
+                continue;
+            }
+            // Unreadable locks are being written or removed right now.
+            let Some(other) = fs::read_to_string(&path).ok().and_then(|s| serde_json::from_str::<LockInfo>(&s).ok()) else {
+                continue;
+            };
+            if !is_running(other.pid) {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            if info.remote_store.is_some() && other.remote_store == info.remote_store {
+                return Err(format!(
+                    "Remote hash store {} is used by a running sync (pid {}, lock {}); \
+                     profiles sharing a remote_hash_path cannot run at the same time",
+                    config.remote_hash_path,
+                    other.pid,
+                    path.display()
+                )
+                .into());
+            }
+            if info.allow_overlap && other.allow_overlap {
+                continue;
+            }
+            for ours in &info.folders {
+                if let Some(theirs) = other.folders.iter().find(|t| t.starts_with(ours) || ours.starts_with(t)) {
+                    return Err(format!(
+                        "Folder {} overlaps folder {} of a running sync (pid {}, lock {}); \
+                         set allow_overlapping_runs: true in both configs to run them together",
+                        ours.display(),
+                        theirs.display(),
+                        other.pid,
+                        path.display()
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(lock)
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Whether process `pid` still exists. Without a way to tell, a lock is
+/// assumed to be held; the error names its file for removing it by hand.
+fn is_running(pid: u32) -> bool {
+    pid == std::process::id() || !cfg!(target_os = "linux") || Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_artifact_names() {
+        let mut config = Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\nhash_store_path: /tmp/a.yaml\nartifact_names: [hashes-b.yaml]\n").unwrap();
+        let artifacts = SyncArtifacts::from_config(&config);
+        for name in ["a.yaml", "hashes-b.yaml", "b.phone_sync.lock", "video.mp4.part"] {
+            assert!(artifacts.contains(OsStr::new(name)), "{}", name);
+        }
+        assert!(!artifacts.contains(OsStr::new("hashes.yaml")));
+
+        // Opting out keeps only the own store.
+        config.upload_sync_artifacts = true;
+        let artifacts = SyncArtifacts::from_config(&config);
+        assert!(artifacts.contains(OsStr::new("a.yaml")));
+        assert!(!artifacts.contains(OsStr::new("hashes-b.yaml")));
+        assert!(!artifacts.contains(OsStr::new("video.mp4.part")));
+    }
+}
@@ -0,0 +1,168 @@
+//! How requests authenticate to the server.
+//!
+//! Basic auth comes from `username` and `password`; servers behind an OAuth
+//! proxy take a `bearer_token` instead, which can be read from an environment
+//! variable or a file so it never has to be written into the config file.
+//!
+//! Basic auth credentials go out with every request by default, since some
+//! gateways answer a request without them with a bare 401 instead of a
+//! challenge. `basic_auth: challenge` holds them back until the server asks.
+
+use reqwest::header::{HeaderMap, WWW_AUTHENTICATE};
+use reqwest::{RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+/// Credentials sent with every request.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub enum Auth {
+    #[default]
+    None,
+    Basic { user: String, pass: String },
+    Bearer(String),
+}
+
+impl Auth {
+    /// Basic auth if both `username` and `password` are given, else none.
+    pub fn from_credentials(username: Option<&str>, password: Option<&str>) -> Self {
+        match (username, password) {
+            (Some(user), Some(pass)) => Auth::Basic { user: user.to_string(), pass: pass.to_string() },
+            _ => Auth::None,
+        }
+    }
+
+    /// Add the `Authorization` header of these credentials to `request`.
+    pub fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+        match self {
+            Auth::None => request,
+            Auth::Basic { user, pass } => request.basic_auth(user, Some(pass)),
+            Auth::Bearer(token) => request.bearer_auth(token),
+        }
+    }
+}
+
+/// When basic auth credentials are sent.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BasicAuthMode {
+    /// With every request, without waiting for a challenge.
+    #[default]
+    Preemptive,
+    /// Only once the server asked for them with a `WWW-Authenticate: Basic`
+    /// challenge; from then on with every request, like browsers do.
+    Challenge,
+}
+
+/// Whether a response with `status` and `headers` asks for basic auth.
+pub fn is_basic_challenge(status: StatusCode, headers: &HeaderMap) -> bool {
+    status == StatusCode::UNAUTHORIZED
+        && headers
+            .get_all(WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|challenge| challenge.trim_start().get(..5).is_some_and(|scheme| scheme.eq_ignore_ascii_case("basic")))
+}
+
+impl fmt::Debug for Auth {
+    /// Never prints the secret.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Auth::None => write!(f, "None"),
+            Auth::Basic { user, .. } => write!(f, "Basic {{ user: {:?}, pass: *** }}", user),
+            Auth::Bearer(_) => write!(f, "Bearer(***)"),
+        }
+    }
+}
+
+/// Where a secret is read from: written inline, or `{ env: NAME }` or
+/// `{ file: PATH }`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum SecretSource {
+    Inline(String),
+    Env { env: String },
+    File { file: String },
+}
+
+impl SecretSource {
+    /// The secret, without surrounding whitespace (e.g. a file's trailing newline).
+    pub fn read(&self) -> Result<String, Box<dyn Error>> {
+        let secret = match self {
+            SecretSource::Inline(secret) => secret.clone(),
+            SecretSource::Env { env } => {
+                std::env::var(env).map_err(|e| format!("cannot read environment variable {}: {}", env, e))?
+            }
+            SecretSource::File { file } => fs::read_to_string(file).map_err(|e| format!("cannot read {}: {}", file, e))?,
+        };
+        let secret = secret.trim();
+        if secret.is_empty() {
+            return Err(format!("{} is empty", self).into());
+        }
+        Ok(secret.to_string())
+    }
+}
+
+impl fmt::Display for SecretSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretSource::Inline(_) => write!(f, "the inline secret"),
+            SecretSource::Env { env } => write!(f, "environment variable {}", env),
+            SecretSource::File { file } => write!(f, "{}", file),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::AUTHORIZATION;
+
+    fn header(auth: &Auth) -> Option<String> {
+        let request = auth.apply(reqwest::Client::new().head("http://example.com/a.txt")).build().unwrap();
+        request.headers().get(AUTHORIZATION).map(|v| v.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_authorization_header() {
+        assert_eq!(header(&Auth::None), None);
+        assert_eq!(header(&Auth::from_credentials(Some("user"), None)), None);
+        assert_eq!(header(&Auth::from_credentials(Some("user"), Some("pass"))).unwrap(), "Basic dXNlcjpwYXNz");
+        assert_eq!(header(&Auth::Bearer("abc.def".to_string())).unwrap(), "Bearer abc.def");
+        assert_eq!(format!("{:?}", Auth::Bearer("abc.def".to_string())), "Bearer(***)");
+    }
+
+    #[test]
+    fn test_basic_challenge() {
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(WWW_AUTHENTICATE, value.parse().unwrap());
+            headers
+        };
+        assert!(is_basic_challenge(StatusCode::UNAUTHORIZED, &headers("Basic realm=\"dav\"")));
+        assert!(is_basic_challenge(StatusCode::UNAUTHORIZED, &headers("Bearer realm=\"a\", basic realm=\"b\"")));
+        assert!(!is_basic_challenge(StatusCode::UNAUTHORIZED, &headers("Bearer realm=\"a\"")));
+        assert!(!is_basic_challenge(StatusCode::UNAUTHORIZED, &HeaderMap::new()));
+        assert!(!is_basic_challenge(StatusCode::FORBIDDEN, &headers("Basic realm=\"dav\"")));
+    }
+
+    #[test]
+    fn test_secret_sources() {
+        let parse = |yaml: &str| serde_yaml::from_str::<SecretSource>(yaml).unwrap();
+        assert_eq!(parse("abc").read().unwrap(), "abc");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        fs::write(&path, "from-file\n").unwrap();
+        assert_eq!(parse(&format!("{{ file: {} }}", path.display())).read().unwrap(), "from-file");
+        fs::write(&path, "\n").unwrap();
+        assert!(parse(&format!("{{ file: {} }}", path.display())).read().unwrap_err().to_string().contains("is empty"));
+
+        std::env::set_var("PHONE_SYNC_TEST_TOKEN", "from-env");
+        assert_eq!(parse("{ env: PHONE_SYNC_TEST_TOKEN }").read().unwrap(), "from-env");
+        let err = parse("{ env: PHONE_SYNC_TEST_UNSET }").read().unwrap_err();
+        assert!(err.to_string().contains("PHONE_SYNC_TEST_UNSET"), "{}", err);
+    }
+}
@@ -0,0 +1,435 @@
+//! Uploading many small files without a full round trip each.
+//!
+//! With `small_file_batching`, sync queues files up to `max_file_size` and
+//! sends them `files_per_batch` at a time, while larger files keep the normal
+//! one-at-a-time path. On a Nextcloud server announcing the `bulkupload`
+//! capability, a batch is one multipart request to its bulk endpoint, which
+//! answers per file. Elsewhere, and for files that must be overwritten
+//! conditionally (If-Match), a batch goes out as up to `concurrency` parallel
+//! PUTs; with `http_version: http2` they share one multiplexed connection.
+
+use crate::checksum::Checksum;
+use crate::fingerprint::RemoteFingerprint;
+use crate::spread::fresh_seed;
+use crate::units::byte_size;
+use crate::webdav_client::{RetryEvent, RetryPolicy, Upload, WebDavClient};
+use log::{info, warn};
+use md5::{Digest, Md5};
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// The `small_file_batching` section of the config.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct BatchConfig {
+    /// Files up to this size are batched, e.g. `64KiB`.
+    #[serde(default = "default_max_file_size", with = "byte_size")]
+    pub max_file_size: u64,
+    #[serde(default = "default_files_per_batch")]
+    pub files_per_batch: usize,
+    /// Parallel PUTs of a batch on servers without a bulk endpoint.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+}
+
+fn default_max_file_size() -> u64 {
+    64 * 1024
+}
+
+fn default_files_per_batch() -> usize {
+    100
+}
+
+fn default_concurrency() -> usize {
+    8
+}
+
+impl BatchConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.files_per_batch == 0 {
+            return Err("small_file_batching.files_per_batch must be at least 1".into());
+        }
+        if self.concurrency == 0 {
+            return Err("small_file_batching.concurrency must be at least 1".into());
+        }
+        Ok(())
+    }
+}
+
+/// A Nextcloud bulk upload endpoint and where the WebDAV URL points below
+/// the user's files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BulkEndpoint {
+    /// Server root, before `/remote.php`.
+    pub root: String,
+    /// Decoded path of the WebDAV URL below the user's files, e.g. `/Phone`.
+    pub prefix: String,
+}
+
+impl BulkEndpoint {
+    /// The endpoint for a WebDAV URL of the form
+    /// `<root>/remote.php/dav/files/<user>/...`.
+    pub fn for_webdav_url(webdav_url: &str) -> Option<Self> {
+        let url = Url::parse(webdav_url).ok()?;
+        let path = url.path();
+        let index = path.find("/remote.php/dav/files/")?;
+        let below = &path[index + "/remote.php/dav/files/".len()..];
+        let (_user, files) = below.split_once('/').unwrap_or((below, ""));
+        let files = percent_decode_str(files.trim_matches('/')).decode_utf8_lossy();
+        let mut root = url.clone();
+        root.set_path(&path[..index]);
+        root.set_query(None);
+        Some(BulkEndpoint {
+            root: root.as_str().trim_end_matches('/').to_string(),
+            prefix: if files.is_empty() { String::new() } else { format!("/{}", files) },
+        })
+    }
+
+    pub fn url(&self) -> String {
+        format!("{}/remote.php/dav/bulk", self.root)
+    }
+
+    pub fn capabilities_url(&self) -> String {
+        format!("{}/ocs/v2.php/cloud/capabilities?format=json", self.root)
+    }
+
+    /// The `X-File-Path` of `remote_path`, which is relative to the WebDAV URL
+    /// and percent-encoded.
+    pub fn file_path(&self, remote_path: &str) -> String {
+        format!("{}/{}", self.prefix, percent_decode_str(remote_path).decode_utf8_lossy())
+    }
+}
+
+/// Whether OCS capabilities announce the bulk upload endpoint.
+pub fn supports_bulk_upload(capabilities: &serde_json::Value) -> bool {
+    capabilities["ocs"]["data"]["capabilities"]["dav"]["bulkupload"].is_string()
+}
+
+/// How batches are sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchMode {
+    Bulk(BulkEndpoint),
+    Parallel(usize),
+}
+
+impl BatchMode {
+    /// Bulk uploads when the server announces them, parallel PUTs otherwise.
+    pub async fn detect(client: &WebDavClient, webdav_url: &str, config: &BatchConfig) -> Self {
+        let parallel = BatchMode::Parallel(config.concurrency);
+        let Some(endpoint) = BulkEndpoint::for_webdav_url(webdav_url) else {
+            return parallel;
+        };
+        match client.ocs_get(&endpoint.capabilities_url()).await {
+            Ok(capabilities) if supports_bulk_upload(&capabilities) => {
+                info!("Batching small files through the bulk endpoint {}", endpoint.url());
+                BatchMode::Bulk(endpoint)
+            }
+            Ok(_) => parallel,
+            Err(e) => {
+                warn!("Cannot read the server capabilities ({}), batching small files as parallel uploads", e);
+                parallel
+            }
+        }
+    }
+}
+
+/// One part of a bulk upload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BulkPart {
+    /// `X-File-Path`, below the user's files.
+    pub path: String,
+    /// Path relative to the WebDAV URL, for the journal.
+    pub remote_path: String,
+    pub content: Vec<u8>,
+    /// Unix seconds.
+    pub mtime: u64,
+    pub hash: Option<String>,
+}
+
+/// A new boundary for a multipart body.
+pub fn boundary() -> String {
+    format!("phone_sync_bulk_{:016x}", fresh_seed())
+}
+
+/// The multipart/related body of a bulk upload.
+pub fn pack(parts: &[BulkPart], boundary: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    for part in parts {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nX-File-Path: {}\r\nX-File-MD5: {:x}\r\nX-File-Mtime: {}\r\nContent-Length: {}\r\n\r\n",
+                boundary,
+                part.path,
+                Md5::digest(&part.content),
+                part.mtime,
+                part.content.len()
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&part.content);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    body
+}
+
+#[derive(Deserialize)]
+struct BulkFileResult {
+    #[serde(default)]
+    error: bool,
+    #[serde(default)]
+    etag: Option<String>,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+/// The result of every part of a bulk upload, in the order of `paths`: its
+/// fingerprint, or why the server did not store it.
+pub fn parse_response(body: &str, paths: &[String]) -> Result<Vec<Result<RemoteFingerprint, String>>, Box<dyn Error>> {
+    let results: BTreeMap<String, BulkFileResult> =
+        serde_json::from_str(body).map_err(|e| format!("unexpected bulk upload response: {}", e))?;
+    Ok(paths
+        .iter()
+        .map(|path| match results.get(path) {
+            Some(result) if result.error => {
+                Err(result.message.clone().unwrap_or_else(|| "rejected by the server".to_string()))
+            }
+            Some(result) => Ok(result.etag.as_deref().map_or(RemoteFingerprint::None, |etag| {
+                // Nextcloud sends the bare ETag, headers carry it quoted.
+                match etag.starts_with('"') || etag.starts_with("W/") {
+                    true => RemoteFingerprint::Etag(etag.to_string()),
+                    false => RemoteFingerprint::Etag(format!("\"{}\"", etag)),
+                }
+            })),
+            None => Err("missing from the bulk upload response".to_string()),
+        })
+        .collect())
+}
+
+/// A small file queued for upload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchFile {
+    pub folder_id: String,
+    pub local_path: PathBuf,
+    /// Path below its folder, recorded as the entry's origin.
+    pub relative_path: String,
+    pub remote_path: String,
+    pub hash: String,
+    /// Checksum the server verifies a single PUT against, if `hash` is one.
+    pub checksum: Option<Checksum>,
+    pub size: u64,
+    /// ETag the remote file must still have to be replaced.
+    pub if_match: Option<String>,
+}
+
+/// What became of one file of a batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOutcome {
+    Stored(RemoteFingerprint),
+    Conflict,
+    /// The server stored this many bytes instead of the whole file.
+    Truncated(u64),
+    Failed(String),
+}
+
+/// Outcomes of a batch, in the order of its files, and the retries spent.
+#[derive(Debug, Default)]
+pub struct BatchResult {
+    pub outcomes: Vec<BatchOutcome>,
+    pub retries: u32,
+    pub backoff_ms: u64,
+}
+
+/// Upload `files`, each getting its own outcome.
+pub async fn upload_batch(
+    client: &WebDavClient,
+    mode: &BatchMode,
+    files: &[BatchFile],
+    policy: RetryPolicy,
+    verify_size: bool,
+) -> Result<BatchResult, Box<dyn Error>> {
+    let mut result = BatchResult { outcomes: vec![BatchOutcome::Failed(String::new()); files.len()], ..Default::default() };
+    let (bulk, parallel): (Vec<usize>, Vec<usize>) = match mode {
+        // The bulk endpoint cannot overwrite conditionally.
+        BatchMode::Bulk(_) => (0..files.len()).partition(|&i| files[i].if_match.is_none()),
+        BatchMode::Parallel(_) => (Vec::new(), (0..files.len()).collect()),
+    };
+    if let BatchMode::Bulk(endpoint) = mode {
+        if !bulk.is_empty() {
+            let selected: Vec<&BatchFile> = bulk.iter().map(|&i| &files[i]).collect();
+            for (i, outcome) in bulk.iter().zip(upload_bulk(client, endpoint, &selected).await) {
+                result.outcomes[*i] = outcome;
+            }
+        }
+    }
+    let concurrency = match mode {
+        BatchMode::Parallel(concurrency) => *concurrency,
+        BatchMode::Bulk(_) => 1,
+    };
+    let permits = Arc::new(Semaphore::new(concurrency));
+    let mut tasks = JoinSet::new();
+    for i in parallel {
+        let (client, file, permits) = (client.clone(), files[i].clone(), permits.clone());
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            (i, upload_one(&client, &file, policy, verify_size).await)
+        });
+    }
+    while let Some(joined) = tasks.join_next().await {
+        let (i, (outcome, retries, backoff_ms)) = joined?;
+        result.outcomes[i] = outcome;
+        result.retries += retries;
+        result.backoff_ms += backoff_ms;
+    }
+    Ok(result)
+}
+
+async fn upload_bulk(
+    client: &WebDavClient,
+    endpoint: &BulkEndpoint,
+    files: &[&BatchFile],
+) -> Vec<BatchOutcome> {
+    let failed = |reason: String| vec![BatchOutcome::Failed(reason); files.len()];
+    let mut parents = BTreeSet::new();
+    let mut parts = Vec::new();
+    for file in files {
+        if parents.insert(parent_dir(&file.remote_path)) {
+            if let Err(e) = client.create_parent_dirs(&file.remote_path).await {
+                return failed(e.to_string());
+            }
+        }
+        let content = match tokio::fs::read(&file.local_path).await {
+            Ok(content) => content,
+            Err(e) => return failed(format!("cannot read {}: {}", file.local_path.display(), e)),
+        };
+        let mtime = std::fs::metadata(&file.local_path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        parts.push(BulkPart {
+            path: endpoint.file_path(&file.remote_path),
+            remote_path: file.remote_path.clone(),
+            content,
+            mtime,
+            hash: Some(file.hash.clone()),
+        });
+    }
+    match client.bulk_upload(&endpoint.url(), &parts).await {
+        Ok(stored) => stored
+            .into_iter()
+            .map(|outcome| match outcome {
+                Ok(fingerprint) => BatchOutcome::Stored(fingerprint),
+                Err(reason) => BatchOutcome::Failed(reason),
+            })
+            .collect(),
+        Err(e) => {
+            warn!("Bulk upload of {} files failed: {}", files.len(), e);
+            failed(e.to_string())
+        }
+    }
+}
+
+fn parent_dir(remote_path: &str) -> &str {
+    remote_path.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+/// Upload one file with a PUT, returning its outcome and the retries spent.
+async fn upload_one(
+    client: &WebDavClient,
+    file: &BatchFile,
+    policy: RetryPolicy,
+    verify_size: bool,
+) -> (BatchOutcome, u32, u64) {
+    let (mut retries, mut backoff_ms) = (0, 0);
+    let mut on_retry = |event: &RetryEvent| {
+        warn!("Upload of {} failed, {}", event.path, event);
+        retries += 1;
+        backoff_ms += event.delay.as_millis() as u64;
+    };
+    let upload = client
+        .upload_file_with_retry(
+            &file.local_path,
+            &file.remote_path,
+            Some(&file.hash),
+            file.checksum.as_ref(),
+            file.if_match.as_deref(),
+            policy,
+            &mut on_retry,
+        )
+        .await
+        .map_err(|e| e.to_string());
+    let outcome = match upload {
+        Ok(Upload::Conflict) => BatchOutcome::Conflict,
+        Ok(Upload::Stored(fingerprint)) if verify_size => match client.remote_size(&file.remote_path).await {
+            Ok(Some(stored)) if stored != file.size => BatchOutcome::Truncated(stored),
+            Ok(_) => BatchOutcome::Stored(fingerprint),
+            Err(e) => BatchOutcome::Failed(e.to_string()),
+        },
+        Ok(Upload::Stored(fingerprint)) => BatchOutcome::Stored(fingerprint),
+        Err(e) => BatchOutcome::Failed(e),
+    };
+    (outcome, retries, backoff_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(path: &str, content: &str) -> BulkPart {
+        BulkPart {
+            path: path.to_string(),
+            remote_path: path.trim_start_matches('/').to_string(),
+            content: content.as_bytes().to_vec(),
+            mtime: 1_700_000_000,
+            hash: None,
+        }
+    }
+
+    #[test]
+    fn test_pack_writes_one_part_per_file() {
+        let body = pack(&[part("/Phone/a.txt", "hello"), part("/Phone/b c.txt", "")], "B");
+        let expected = "--B\r\nX-File-Path: /Phone/a.txt\r\nX-File-MD5: 5d41402abc4b2a76b9719d911017c592\r\n\
+                        X-File-Mtime: 1700000000\r\nContent-Length: 5\r\n\r\nhello\r\n\
+                        --B\r\nX-File-Path: /Phone/b c.txt\r\nX-File-MD5: d41d8cd98f00b204e9800998ecf8427e\r\n\
+                        X-File-Mtime: 1700000000\r\nContent-Length: 0\r\n\r\n\r\n--B--\r\n";
+        assert_eq!(String::from_utf8(body).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_response_is_attributed_per_file() {
+        let body = r#"{
+            "/Phone/a.txt": {"error": false, "etag": "e1", "fileid": 12},
+            "/Phone/b.txt": {"error": true, "message": "Computed md5 hash is incorrect."}
+        }"#;
+        let paths = ["/Phone/a.txt", "/Phone/b.txt", "/Phone/c.txt"].map(String::from);
+        let results = parse_response(body, &paths).unwrap();
+        assert_eq!(results[0], Ok(RemoteFingerprint::Etag("\"e1\"".to_string())));
+        assert_eq!(results[1], Err("Computed md5 hash is incorrect.".to_string()));
+        assert_eq!(results[2], Err("missing from the bulk upload response".to_string()));
+        assert!(parse_response("<html>", &paths).is_err());
+    }
+
+    #[test]
+    fn test_bulk_endpoint_of_nextcloud_urls() {
+        let endpoint = BulkEndpoint::for_webdav_url("https://cloud.example.com/nc/remote.php/dav/files/me/My%20Phone/").unwrap();
+        assert_eq!(endpoint.url(), "https://cloud.example.com/nc/remote.php/dav/bulk");
+        assert_eq!(endpoint.capabilities_url(), "https://cloud.example.com/nc/ocs/v2.php/cloud/capabilities?format=json");
+        assert_eq!(endpoint.file_path("DCIM/a%20b.jpg"), "/My Phone/DCIM/a b.jpg");
+        let root = BulkEndpoint::for_webdav_url("https://cloud.example.com/remote.php/dav/files/me").unwrap();
+        assert_eq!(root.file_path("a.jpg"), "/a.jpg");
+        assert_eq!(BulkEndpoint::for_webdav_url("https://dav.example.com/photos"), None);
+
+        let capabilities: serde_json::Value =
+            serde_json::from_str(r#"{"ocs": {"data": {"capabilities": {"dav": {"chunking": "1.0", "bulkupload": "1.0"}}}}}"#).unwrap();
+        assert!(supports_bulk_upload(&capabilities));
+        assert!(!supports_bulk_upload(&serde_json::json!({"ocs": {"data": {"capabilities": {"dav": {}}}}})));
+    }
+}
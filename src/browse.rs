@@ -0,0 +1,228 @@
+//! Navigation and selection model of the remote browser.
+//!
+//! Kept apart from the terminal UI (the `tui` feature) so it can be driven
+//! without a terminal. Collections are listed with a Depth-1 PROPFIND the
+//! first time they are expanded, and the selection is a set of remote paths
+//! in which a selected collection stands for everything below it.
+
+use crate::filter::is_below;
+use crate::propfind::RemoteEntry;
+use crate::webdav_client::WebDavClient;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+
+/// A visible line of the tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub entry: RemoteEntry,
+    /// Nesting level, 0 for entries of the root collection.
+    pub depth: usize,
+    pub expanded: bool,
+    /// Whether the entry is selected itself or through a collection above it.
+    pub selected: bool,
+}
+
+/// The remote tree as far as it was explored, with a cursor and a selection.
+#[derive(Debug, Default)]
+pub struct Browser {
+    /// Children of every listed collection, by collection path (`""` is the root).
+    listings: HashMap<String, Vec<RemoteEntry>>,
+    expanded: BTreeSet<String>,
+    selected: BTreeSet<String>,
+    cursor: usize,
+}
+
+impl Browser {
+    /// Start browsing at the root collection of the server.
+    pub async fn open(client: &WebDavClient) -> Result<Self, Box<dyn Error>> {
+        let mut browser = Browser::default();
+        browser.listings.insert(String::new(), client.list("").await?);
+        Ok(browser)
+    }
+
+    /// The expanded part of the tree, depth first.
+    pub fn rows(&self) -> Vec<Row> {
+        let mut rows = Vec::new();
+        self.push_rows("", 0, &mut rows);
+        rows
+    }
+
+    fn push_rows(&self, dir: &str, depth: usize, rows: &mut Vec<Row>) {
+        for entry in self.listings.get(dir).into_iter().flatten() {
+            let expanded = self.expanded.contains(&entry.path);
+            rows.push(Row { entry: entry.clone(), depth, expanded, selected: self.is_selected(&entry.path) });
+            if expanded {
+                self.push_rows(&entry.path, depth + 1, rows);
+            }
+        }
+    }
+
+    /// Index of the row under the cursor.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Move the cursor by `delta` rows, stopping at the first and last row.
+    pub fn move_cursor(&mut self, delta: isize) {
+        let last = self.rows().len().saturating_sub(1);
+        self.cursor = self.cursor.saturating_add_signed(delta).min(last);
+    }
+
+    /// The row under the cursor, if the tree is not empty.
+    pub fn current(&self) -> Option<Row> {
+        self.rows().into_iter().nth(self.cursor)
+    }
+
+    /// Expand the collection under the cursor, listing it on first use.
+    pub async fn expand(&mut self, client: &WebDavClient) -> Result<(), Box<dyn Error>> {
+        let Some(row) = self.current().filter(|row| row.entry.is_dir) else {
+            return Ok(());
+        };
+        if !self.listings.contains_key(&row.entry.path) {
+            let entries = client.list(&row.entry.path).await?;
+            self.listings.insert(row.entry.path.clone(), entries);
+        }
+        self.expanded.insert(row.entry.path);
+        Ok(())
+    }
+
+    /// Collapse the collection under the cursor, or else move to its parent.
+    pub fn collapse(&mut self) {
+        let Some(row) = self.current() else {
+            return;
+        };
+        if self.expanded.remove(&row.entry.path) {
+            return;
+        }
+        if let Some((parent, _)) = row.entry.path.rsplit_once('/') {
+            if let Some(index) = self.rows().iter().position(|r| r.entry.path == parent) {
+                self.cursor = index;
+            }
+        }
+    }
+
+    /// Select or deselect the entry under the cursor.
+    ///
+    /// Deselecting an entry inside a selected collection selects its loaded
+    /// siblings (and those of its parents up to that collection) instead.
+    pub fn toggle_selection(&mut self) {
+        let Some(path) = self.current().map(|row| row.entry.path) else {
+            return;
+        };
+        if self.selected.remove(&path) {
+            return;
+        }
+        if let Some(ancestor) = self.selected.iter().find(|s| is_below(&path, s)).cloned() {
+            self.selected.remove(&ancestor);
+            let mut dir = ancestor;
+            while dir != path {
+                let mut next = None;
+                for entry in self.listings.get(&dir).into_iter().flatten() {
+                    if is_below(&path, &entry.path) {
+                        next = Some(entry.path.clone());
+                    } else {
+                        self.selected.insert(entry.path.clone());
+                    }
+                }
+                let Some(next) = next else { break };
+                dir = next;
+            }
+        } else {
+            self.selected.retain(|s| !is_below(s, &path));
+            self.selected.insert(path);
+        }
+    }
+
+    /// Whether `path` is selected, itself or through a collection above it.
+    pub fn is_selected(&self, path: &str) -> bool {
+        self.selected.iter().any(|s| is_below(path, s))
+    }
+
+    /// The selected remote paths, without entries covered by a selected collection.
+    pub fn selection(&self) -> Vec<String> {
+        self.selected.iter().cloned().collect()
+    }
+
+    /// Total size of the selected files seen so far. Collections count with
+    /// the files listed below them, which is less than their full size until
+    /// they are expanded.
+    pub fn selected_size(&self) -> u64 {
+        self.listings
+            .values()
+            .flatten()
+            .filter(|e| !e.is_dir && self.is_selected(&e.path))
+            .filter_map(|e| e.size)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(path: &str) -> RemoteEntry {
+        RemoteEntry { path: path.to_string(), is_dir: true, size: None, last_modified: None, etag: None }
+    }
+
+    fn file(path: &str, size: u64) -> RemoteEntry {
+        RemoteEntry { path: path.to_string(), is_dir: false, size: Some(size), last_modified: None, etag: None }
+    }
+
+    /// `photos/{2023/c.jpg, 2024/{a,b}.jpg, d.jpg}` and `notes.txt`, all expanded.
+    fn browser() -> Browser {
+        let mut browser = Browser::default();
+        browser.listings.insert("".into(), vec![dir("photos"), file("notes.txt", 1)]);
+        browser.listings.insert("photos".into(), vec![dir("photos/2023"), dir("photos/2024"), file("photos/d.jpg", 10)]);
+        browser.listings.insert("photos/2023".into(), vec![file("photos/2023/c.jpg", 100)]);
+        browser.listings.insert("photos/2024".into(), vec![file("photos/2024/a.jpg", 1000), file("photos/2024/b.jpg", 10000)]);
+        browser.expanded.extend(["photos".into(), "photos/2023".into(), "photos/2024".into()]);
+        browser
+    }
+
+    fn select(browser: &mut Browser, path: &str) {
+        browser.cursor = browser.rows().iter().position(|r| r.entry.path == path).unwrap();
+        browser.toggle_selection();
+    }
+
+    #[test]
+    fn test_rows_follow_expansion() {
+        let mut browser = browser();
+        let paths: Vec<(String, usize)> = browser.rows().into_iter().map(|r| (r.entry.path, r.depth)).collect();
+        assert_eq!(paths[..3], [("photos".into(), 0), ("photos/2023".into(), 1), ("photos/2023/c.jpg".into(), 2)]);
+        assert_eq!(paths.len(), 8);
+
+        browser.cursor = 6; // photos/d.jpg
+        browser.collapse();
+        assert_eq!(browser.current().unwrap().entry.path, "photos");
+        browser.collapse();
+        assert_eq!(browser.rows().len(), 2);
+        browser.move_cursor(5);
+        assert_eq!(browser.cursor(), 1);
+        browser.move_cursor(-5);
+        assert_eq!(browser.cursor(), 0);
+    }
+
+    #[test]
+    fn test_selecting_a_collection_covers_its_content() {
+        let mut browser = browser();
+        select(&mut browser, "photos/2024/a.jpg");
+        select(&mut browser, "photos");
+        assert_eq!(browser.selection(), vec!["photos"]);
+        assert!(browser.rows().iter().filter(|r| r.entry.path.starts_with("photos")).all(|r| r.selected));
+        assert_eq!(browser.selected_size(), 11110);
+
+        select(&mut browser, "photos");
+        assert!(browser.selection().is_empty());
+        assert_eq!(browser.selected_size(), 0);
+    }
+
+    #[test]
+    fn test_deselecting_inside_a_selected_collection() {
+        let mut browser = browser();
+        select(&mut browser, "photos");
+        select(&mut browser, "photos/2024/b.jpg");
+        assert_eq!(browser.selection(), vec!["photos/2023", "photos/2024/a.jpg", "photos/d.jpg"]);
+        assert!(!browser.is_selected("photos/2024/b.jpg"));
+        assert!(!browser.is_selected("photos/2024"));
+    }
+}
@@ -0,0 +1,299 @@
+//! Monthly transfer budget for uploads.
+//!
+//! Uploaded bytes are accumulated per calendar month (UTC) in a small state
+//! file. A run warns when usage crosses 80% and 100% of the budget and, with
+//! the `stop` action, stops starting new uploads once the next file would
+//! exceed it.
+
+use log::warn;
+use crate::output::HumanDisplay;
+use crate::units::byte_size;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Usage ratios (in percent) at which a warning is logged.
+const THRESHOLDS: [u64; 2] = [80, 100];
+
+/// What to do once the budget is used up.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetAction {
+    #[default]
+    Warn,
+    /// Finish the current file, then upload nothing more in this period.
+    Stop,
+}
+
+/// The `transfer_budget` section of the config.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct TransferBudget {
+    /// Bytes that may be uploaded per calendar month, e.g. `50G` or `500000000`.
+    #[serde(with = "byte_size")]
+    pub bytes_per_month: u64,
+    #[serde(default)]
+    pub action: BudgetAction,
+    /// File holding the usage of the current period.
+    #[serde(default = "default_state_path")]
+    pub state_path: String,
+}
+
+fn default_state_path() -> String {
+    "transfer_usage.yaml".to_string()
+}
+
+/// Bytes uploaded during one calendar month.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferUsage {
+    /// Month as `YYYY-MM`.
+    pub period: String,
+    pub bytes: u64,
+}
+
+impl TransferUsage {
+    /// Load the usage of the period containing `now`, starting over if the
+    /// stored usage belongs to an earlier period.
+    pub fn load<P: AsRef<Path>>(path: P, now: SystemTime) -> Result<Self, Box<dyn Error>> {
+        let usage: TransferUsage = if path.as_ref().exists() {
+            serde_yaml::from_str(&std::fs::read_to_string(path)?)?
+        } else {
+            TransferUsage::default()
+        };
+        Ok(usage.rolled_over(now))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, serde_yaml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Add `bytes` to the period containing `now`.
+    pub fn add(&mut self, bytes: u64, now: SystemTime) {
+        *self = std::mem::take(self).rolled_over(now);
+        self.bytes += bytes;
+    }
+
+    fn rolled_over(self, now: SystemTime) -> Self {
+        let period = period_of(now);
+        if self.period == period {
+            self
+        } else {
+            TransferUsage { period, bytes: 0 }
+        }
+    }
+}
+
+/// Budget bookkeeping for one run.
+pub struct BudgetTracker {
+    budget: TransferBudget,
+    usage: TransferUsage,
+    /// Highest threshold already warned about in this run.
+    warned: u64,
+    /// Start of the run, whose period all its uploads count towards even if
+    /// the clock jumps meanwhile.
+    started: SystemTime,
+}
+
+impl BudgetTracker {
+    /// Load the current usage and warn if it is already above a threshold.
+    pub fn start(budget: &TransferBudget, now: SystemTime) -> Result<Self, Box<dyn Error>> {
+        let usage = TransferUsage::load(&budget.state_path, now)?;
+        let mut tracker = BudgetTracker { budget: budget.clone(), usage, warned: 0, started: now };
+        tracker.warn_thresholds();
+        Ok(tracker)
+    }
+
+    pub fn usage(&self) -> &TransferUsage {
+        &self.usage
+    }
+
+    /// Whether an upload of `bytes` may start.
+    pub fn allows(&self, bytes: u64) -> bool {
+        self.budget.action == BudgetAction::Warn
+            || self.usage.bytes + bytes <= self.budget.bytes_per_month
+    }
+
+    /// Record a finished upload and persist the usage.
+    pub fn record(&mut self, bytes: u64) -> Result<(), Box<dyn Error>> {
+        self.usage.add(bytes, self.started);
+        self.usage.save(&self.budget.state_path)?;
+        self.warn_thresholds();
+        Ok(())
+    }
+
+    fn warn_thresholds(&mut self) {
+        let percent = percent_used(self.usage.bytes, self.budget.bytes_per_month);
+        if let Some(&threshold) = THRESHOLDS.iter().rev().find(|&&t| percent >= t) {
+            if threshold > self.warned {
+                warn!(
+                    "Transfer budget at {}%: {} of {} used in {}",
+                    percent,
+                    format_size(self.usage.bytes),
+                    format_size(self.budget.bytes_per_month),
+                    self.usage.period
+                );
+                self.warned = threshold;
+            }
+        }
+    }
+}
+
+/// Usage of the current period, as printed by `budget status`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BudgetStatus {
+    /// Month as `YYYY-MM`.
+    pub period: String,
+    pub used_bytes: u64,
+    /// `used_bytes` with a unit, e.g. `12.3 GB`.
+    pub used: String,
+    pub limit_bytes: u64,
+    /// `limit_bytes` with a unit.
+    pub limit: String,
+    pub percent_used: u64,
+}
+
+impl BudgetStatus {
+    pub fn new(budget: &TransferBudget, usage: &TransferUsage) -> Self {
+        BudgetStatus {
+            period: usage.period.clone(),
+            used_bytes: usage.bytes,
+            used: format_size(usage.bytes),
+            limit_bytes: budget.bytes_per_month,
+            limit: format_size(budget.bytes_per_month),
+            percent_used: percent_used(usage.bytes, budget.bytes_per_month),
+        }
+    }
+}
+
+impl HumanDisplay for BudgetStatus {
+    fn human(&self) -> String {
+        format!("{}: {} of {} used ({}%)", self.period, self.used, self.limit, self.percent_used)
+    }
+}
+
+fn percent_used(bytes: u64, limit: u64) -> u64 {
+    bytes.saturating_mul(100).checked_div(limit).unwrap_or(100)
+}
+
+/// Calendar month (UTC) containing `now`, as `YYYY-MM`.
+pub fn period_of(now: SystemTime) -> String {
+    let (year, month, _) = civil_date(now);
+    format!("{:04}-{:02}", year, month)
+}
+
+/// Calendar day (UTC) containing `now`, as `YYYY-MM-DD`.
+pub fn date_of(now: SystemTime) -> String {
+    let (year, month, day) = civil_date(now);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Year, month and day (UTC) containing `now`.
+fn civil_date(now: SystemTime) -> (i64, i64, i64) {
+    let days = (now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 86_400) as i64;
+    // Civil-from-days conversion (proleptic Gregorian calendar), see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Render a byte count with a decimal unit, e.g. `12.3 GB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [(u64, &str); 4] =
+        [(1_000_000_000_000, "TB"), (1_000_000_000, "GB"), (1_000_000, "MB"), (1_000, "KB")];
+    for (factor, unit) in UNITS {
+        if bytes >= factor {
+            return format!("{:.1} {}", bytes as f64 / factor as f64, unit);
+        }
+    }
+    format!("{} B", bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{render, OutputFormat};
+    use std::time::Duration;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    // 2024-02-29T12:00:00Z and 2024-03-01T00:00:00Z
+    const LEAP_DAY: u64 = 1_709_208_000;
+    const MARCH_FIRST: u64 = 1_709_251_200;
+
+    #[test]
+    fn test_period_of() {
+        assert_eq!(period_of(at(0)), "1970-01");
+        assert_eq!(period_of(at(LEAP_DAY)), "2024-02");
+        assert_eq!(period_of(at(MARCH_FIRST - 1)), "2024-02");
+        assert_eq!(period_of(at(MARCH_FIRST)), "2024-03");
+    }
+
+    #[test]
+    fn test_date_of() {
+        assert_eq!(date_of(at(0)), "1970-01-01");
+        assert_eq!(date_of(at(LEAP_DAY)), "2024-02-29");
+        assert_eq!(date_of(at(MARCH_FIRST - 1)), "2024-02-29");
+        assert_eq!(date_of(at(MARCH_FIRST)), "2024-03-01");
+    }
+
+    #[test]
+    fn test_status_formats() {
+        let budget = TransferBudget { bytes_per_month: 50_000_000_000, action: BudgetAction::Warn, state_path: String::new() };
+        let status = BudgetStatus::new(&budget, &TransferUsage { period: "2024-02".to_string(), bytes: 12_345_000_000 });
+        assert_eq!(status.human(), "2024-02: 12.3 GB of 50.0 GB used (24%)");
+
+        let json = render(&status, OutputFormat::Json).unwrap();
+        assert_eq!(serde_json::from_str::<BudgetStatus>(&json).unwrap(), status);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!((value["used_bytes"].as_u64(), value["limit"].as_str()), (Some(12_345_000_000), Some("50.0 GB")));
+    }
+
+    #[test]
+    fn test_usage_rolls_over_and_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("usage.yaml");
+
+        let mut usage = TransferUsage::load(&path, at(LEAP_DAY)).unwrap();
+        usage.add(100, at(LEAP_DAY));
+        usage.save(&path).unwrap();
+        assert_eq!(TransferUsage::load(&path, at(LEAP_DAY)).unwrap().bytes, 100);
+
+        let next = TransferUsage::load(&path, at(MARCH_FIRST)).unwrap();
+        assert_eq!(next, TransferUsage { period: "2024-03".to_string(), bytes: 0 });
+
+        usage.add(5, at(MARCH_FIRST));
+        assert_eq!(usage, TransferUsage { period: "2024-03".to_string(), bytes: 5 });
+    }
+
+    #[test]
+    fn test_tracker_stop_action() {
+        let dir = tempfile::tempdir().unwrap();
+        let budget = TransferBudget {
+            bytes_per_month: 10,
+            action: BudgetAction::Stop,
+            state_path: dir.path().join("usage.yaml").display().to_string(),
+        };
+        let mut tracker = BudgetTracker::start(&budget, at(LEAP_DAY)).unwrap();
+        assert!(tracker.allows(10));
+        tracker.record(8).unwrap();
+        assert_eq!(tracker.warned, 80);
+        assert!(!tracker.allows(3));
+        assert!(tracker.allows(2));
+
+        let warn_only = TransferBudget { action: BudgetAction::Warn, ..budget };
+        let tracker = BudgetTracker::start(&warn_only, at(LEAP_DAY)).unwrap();
+        assert_eq!(tracker.usage().bytes, 8);
+        assert!(tracker.allows(100));
+    }
+}
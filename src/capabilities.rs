@@ -0,0 +1,107 @@
+//! What a server announces about itself in its answer to OPTIONS.
+//!
+//! The `DAV` header lists the WebDAV compliance classes (class 2 adds
+//! locking) and `Allow` the methods the server accepts. A client asks once
+//! and consults the answer before using an optional method, so a server
+//! without it gets a fallback or a clear error instead of an odd status.
+//! Servers that answer OPTIONS without `Allow` are assumed to accept every
+//! method.
+
+use crate::output::HumanDisplay;
+use reqwest::header::{HeaderMap, ALLOW};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerCapabilities {
+    /// Status of the OPTIONS response.
+    pub status: u16,
+    /// WebDAV compliance classes from the `DAV` header.
+    pub dav: Vec<String>,
+    /// Methods from the `Allow` header, uppercase; `None` if the server sent
+    /// none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow: Option<Vec<String>>,
+}
+
+/// The comma-separated values of every `name` header.
+fn list(headers: &HeaderMap, name: &str) -> Vec<String> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+impl ServerCapabilities {
+    /// Capabilities of an OPTIONS response; a failed one announces nothing.
+    pub fn from_response(status: StatusCode, headers: &HeaderMap) -> Self {
+        let mut capabilities = ServerCapabilities { status: status.as_u16(), ..Default::default() };
+        if status.is_success() {
+            capabilities.dav = list(headers, "DAV");
+            if headers.contains_key(ALLOW) {
+                capabilities.allow =
+                    Some(list(headers, ALLOW.as_str()).into_iter().map(|m| m.to_ascii_uppercase()).collect());
+            }
+        }
+        capabilities
+    }
+
+    /// Whether the server accepts `method`.
+    pub fn allows(&self, method: &str) -> bool {
+        self.allow.as_ref().is_none_or(|methods| methods.iter().any(|m| m.eq_ignore_ascii_case(method)))
+    }
+
+    /// Whether the server supports WebDAV locks (compliance class 2).
+    pub fn supports_locking(&self) -> bool {
+        self.dav.iter().any(|class| class == "2") && self.allows("LOCK") && self.allows("UNLOCK")
+    }
+}
+
+impl HumanDisplay for ServerCapabilities {
+    fn human(&self) -> String {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        let mut out = format!("OPTIONS: HTTP {}", self.status);
+        out.push_str(&format!(
+            "\nDAV classes: {}",
+            if self.dav.is_empty() { "none announced".to_string() } else { self.dav.join(", ") }
+        ));
+        out.push_str(&format!(
+            "\nallowed methods: {}",
+            match &self.allow {
+                Some(methods) => methods.join(", "),
+                None => "not announced, assuming all".to_string(),
+            }
+        ));
+        out.push_str(&format!("\nlocking (lock_hash_store): {}", yes_no(self.supports_locking())));
+        out.push_str(&format!("\nMOVE (chunked uploads, reconcile, migrate): {}", yes_no(self.allows("MOVE"))));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_headers_are_parsed() {
+        let mut headers = HeaderMap::new();
+        headers.insert("DAV", HeaderValue::from_static("1, 2, <http://apache.org/dav/propset/fs/1>"));
+        headers.insert(ALLOW, HeaderValue::from_static("OPTIONS, GET, put, LOCK, UNLOCK"));
+        let capabilities = ServerCapabilities::from_response(StatusCode::OK, &headers);
+        assert_eq!(capabilities.dav, vec!["1", "2", "<http://apache.org/dav/propset/fs/1>"]);
+        assert!(capabilities.supports_locking());
+        assert!(capabilities.allows("PUT"));
+        assert!(!capabilities.allows("MOVE"));
+
+        headers.remove(ALLOW);
+        assert!(ServerCapabilities::from_response(StatusCode::OK, &headers).allows("MOVE"));
+        let failed = ServerCapabilities::from_response(StatusCode::METHOD_NOT_ALLOWED, &headers);
+        assert_eq!((failed.status, failed.dav.len()), (405, 0));
+        assert!(!failed.supports_locking());
+    }
+}
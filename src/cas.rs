@@ -0,0 +1,311 @@
+//! Content-addressed remote layout (`layout: cas`), for backups that are
+//! restored rather than browsed.
+//!
+//! Every distinct content is stored once, as
+//! `<target_dir>/objects/<first two hex digits>/<SHA-256>`, and a manifest
+//! next to the remote hash store maps the remote path each file would have
+//! in the mirror layout to its object. Identical files share one object, a
+//! renamed file only changes the manifest, and every object can be checked
+//! against its own name.
+//!
+//! Deleting a local file (with `mirror_deletions`) only removes its manifest
+//! entry; `gc --cas` deletes the objects no entry refers to anymore. It must
+//! not run during a sync, whose new objects are only added to the manifest
+//! when the sync ends.
+
+use crate::checksum::Checksum;
+use crate::config::{Config, Layout};
+use crate::external_hasher::FileHasher;
+use crate::file_stamp::{FileStamp, StampMatch};
+use crate::filter::{is_below, FilterSet};
+use crate::folder_kind::FolderKind;
+use crate::fingerprint::RemoteState;
+use crate::hash_store::HashStore;
+use crate::hash_store_guard::HashStoreGuard;
+use crate::mount::check_mounted;
+use crate::output::HumanDisplay;
+use crate::progress::{FileDone, SyncObserver};
+use crate::propfind::Depth;
+use crate::report::{FileOutcome, SyncReport};
+use crate::sync::{remote_path_for, selected_files};
+use crate::webdav_client::{RetryEvent, RetryPolicy, WebDavClient};
+use crate::work_dir::WorkDir;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+/// File name of the manifest, in the directory of `remote_hash_path`.
+pub const MANIFEST_NAME: &str = "manifest.yaml";
+
+/// Object of every synced file, by its remote path in the mirror layout.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// SHA-256 of the content, which names its object.
+    pub hash: String,
+    pub size: u64,
+}
+
+/// Remote path of the manifest.
+pub fn manifest_path(config: &Config) -> String {
+    match config.remote_hash_path.rsplit_once('/') {
+        Some((dir, _)) => format!("{}/{}", dir, MANIFEST_NAME),
+        None => MANIFEST_NAME.to_string(),
+    }
+}
+
+/// Remote collection holding the objects.
+pub fn objects_dir(config: &Config) -> String {
+    remote_path_for(config, "objects")
+}
+
+/// Remote path of the object with content `hash`.
+pub fn object_path(config: &Config, hash: &str) -> String {
+    format!("{}/{}/{}", objects_dir(config), &hash[..hash.len().min(2)], hash)
+}
+
+/// Fail with a clear message if `config` uses the cas layout, for commands
+/// that only work on mirrored files.
+pub fn require_mirror(config: &Config, command: &str) -> Result<(), Box<dyn Error>> {
+    if config.layout == Layout::Cas {
+        return Err(format!("{} is not supported with layout: cas", command).into());
+    }
+    Ok(())
+}
+
+impl Manifest {
+    /// Download the manifest, or start an empty one if there is none yet.
+    pub async fn load(client: &WebDavClient, config: &Config) -> Result<Self, Box<dyn Error>> {
+        let work_dir = WorkDir::create(config.temp_dir.as_deref().map(Path::new))?;
+        let copy = work_dir.file(MANIFEST_NAME);
+        if client.download_file(&manifest_path(config), &copy).await?.is_none() {
+            return Ok(Self::default());
+        }
+        let manifest = serde_yaml::from_str(&fs::read_to_string(&copy)?)
+            .map_err(|e| format!("Invalid manifest '{}': {}", manifest_path(config), e))?;
+        Ok(manifest)
+    }
+
+    pub async fn save(&self, client: &WebDavClient, config: &Config) -> Result<(), Box<dyn Error>> {
+        let work_dir = WorkDir::create(config.temp_dir.as_deref().map(Path::new))?;
+        let copy = work_dir.file(MANIFEST_NAME);
+        fs::write(&copy, serde_yaml::to_string(self)?)?;
+        client.upload_file(&copy, &manifest_path(config)).await
+    }
+
+    /// The manifest as a hash store, e.g. to pull from.
+    pub fn to_store(&self) -> HashStore {
+        HashStore {
+            regular_hashes: self.files.iter().map(|(path, entry)| (path.clone(), entry.hash.clone())).collect(),
+            ..HashStore::default()
+        }
+    }
+
+    /// Whether the object of `remote_path` is on the server with the
+    /// recorded size.
+    pub async fn object_state(
+        &self,
+        client: &WebDavClient,
+        config: &Config,
+        remote_path: &str,
+    ) -> Result<RemoteState, Box<dyn Error>> {
+        let Some(entry) = self.files.get(remote_path) else {
+            return Ok(RemoteState::Missing);
+        };
+        Ok(match client.stat(&object_path(config, &entry.hash)).await? {
+            None => RemoteState::Missing,
+            Some(stat) if stat.size.is_some_and(|size| size != entry.size) => RemoteState::Changed,
+            Some(_) => RemoteState::Unchanged,
+        })
+    }
+}
+
+/// Sync the files selected by `filters` into the object store, recording
+/// them in the manifest and in the store held by `guard`.
+pub async fn sync_cas(
+    config: &Config,
+    client: &WebDavClient,
+    guard: &mut HashStoreGuard,
+    observer: &dyn SyncObserver,
+    use_pseudo_hash: bool,
+    filters: &FilterSet,
+) -> Result<SyncReport, Box<dyn Error>> {
+    if use_pseudo_hash {
+        return Err("layout: cas names objects by their SHA-256 and cannot sync with --pseudo".into());
+    }
+    let start = Instant::now();
+    let hash_store = guard.hash_store_mut();
+    let hasher = FileHasher::from_config(config);
+    let retry_policy = RetryPolicy::uploads(config);
+    let mut report = SyncReport::default();
+    let mut manifest = Manifest::load(client, config).await?;
+    let loaded = manifest.clone();
+    // Objects known to be on the server, which need no existence check.
+    let mut stored: HashSet<String> = manifest.files.values().map(|entry| entry.hash.clone()).collect();
+    let mut seen_remote_paths = HashSet::new();
+    let mut all_folders_scanned = true;
+    for folder in &config.folders {
+        let kind = FolderKind::of(Path::new(&folder.path));
+        report.folder_kinds.insert(folder.path.clone(), kind);
+        if !kind.is_syncable() {
+            warn!("Folder {} is not a directory or file, skipping it and all deletions", folder.path);
+            all_folders_scanned = false;
+        } else if let Err(reason) = check_mounted(folder) {
+            warn!("Folder {} is not available ({}), skipping it and all deletions", folder.path, reason);
+            report.unmounted.push(folder.path.clone());
+            all_folders_scanned = false;
+        }
+    }
+
+    for (local_path, remote_path) in selected_files(config, filters) {
+        let Some(folder) = config.folders.iter().find(|f| local_path.starts_with(&f.path)) else {
+            continue;
+        };
+        if report.unmounted.contains(&folder.path) {
+            continue;
+        }
+        let folder_id = config.folder_id(folder);
+        let metadata = fs::metadata(&local_path)?;
+        let size = metadata.len();
+        observer.file_start(&remote_path, size);
+        seen_remote_paths.insert(remote_path.clone());
+
+        let stamp = if config.trust_mtime { FileStamp::of(&metadata) } else { None };
+        let hash = match (stamp, hash_store.stamps.get(&remote_path), hash_store.regular_hashes.get(&remote_path)) {
+            (Some(stamp), Some(stored), Some(hash)) if stored.compare(&stamp, config.mtime_tolerance) == StampMatch::Unchanged => {
+                hash.clone()
+            }
+            _ => hasher.compute(&local_path, false).await?,
+        };
+        if let Some(stamp) = stamp {
+            hash_store.stamps.insert(remote_path.clone(), stamp);
+        }
+
+        if manifest.files.get(&remote_path).is_some_and(|entry| entry.hash == hash) {
+            observer.file_done(&remote_path, FileDone::Unchanged);
+            report.record(&folder_id, &local_path, FileOutcome::Skipped, size);
+            hash_store.regular_hashes.insert(remote_path, hash);
+            continue;
+        }
+
+        let object = object_path(config, &hash);
+        if stored.contains(&hash) || client.stat(&object).await?.is_some() {
+            info!("{} is stored as existing object {}", remote_path, hash);
+            observer.file_done(&remote_path, FileDone::Unchanged);
+            report.deduplicated.push(remote_path.clone());
+        } else if client.is_read_only() {
+            observer.file_done(&remote_path, FileDone::Planned);
+            report.planned.push(remote_path);
+            continue;
+        } else {
+            let mut on_retry = |event: &RetryEvent| {
+                warn!("Upload of {} failed, {}", event.path, event);
+                observer.retry(event);
+                report.retries += 1;
+                report.backoff_ms += event.delay.as_millis() as u64;
+            };
+            // Without If-Match the upload never conflicts.
+            let checksum = Checksum::sha256(&hash);
+            client
+                .upload_file_with_retry(&local_path, &object, Some(&hash), Some(&checksum), None, retry_policy, &mut on_retry)
+                .await?;
+            observer.file_done(&remote_path, FileDone::Uploaded);
+            report.record(&folder_id, &local_path, FileOutcome::Uploaded, size);
+        }
+        stored.insert(hash.clone());
+        manifest.files.insert(remote_path.clone(), ManifestEntry { hash: hash.clone(), size });
+        hash_store.regular_hashes.insert(remote_path, hash);
+    }
+
+    // Deleting a file only edits the manifest; its object stays until `gc --cas`.
+    if config.mirror_deletions {
+        if !all_folders_scanned || *filters != FilterSet::default() {
+            info!("Not every file was scanned in this run, skipping mirror_deletions");
+        } else {
+            let gone: Vec<String> = manifest
+                .files
+                .keys()
+                .filter(|path| is_below(path, &config.target_dir) && !seen_remote_paths.contains(*path))
+                .cloned()
+                .collect();
+            for remote_path in gone {
+                if client.is_read_only() {
+                    report.planned_deletions.push(remote_path);
+                    continue;
+                }
+                manifest.files.remove(&remote_path);
+                hash_store.forget(&remote_path);
+                report.deleted.push(remote_path);
+            }
+        }
+    }
+
+    if manifest != loaded && !client.is_read_only() {
+        manifest.save(client, config).await?;
+    }
+    report.profile.total_micros = start.elapsed().as_micros() as u64;
+    report.profile.connections = Some(client.connection_stats());
+    observer.summary(&report);
+    Ok(report)
+}
+
+/// Outcome of `gc --cas`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcReport {
+    /// Objects the manifest refers to.
+    pub referenced: usize,
+    /// Remote paths of the unreferenced objects; deleted unless `read_only`.
+    pub unreferenced: Vec<String>,
+    /// Total size of the unreferenced objects.
+    pub bytes: u64,
+    pub read_only: bool,
+}
+
+impl HumanDisplay for GcReport {
+    fn human(&self) -> String {
+        let mut out = format!(
+            "{} {} unreferenced {} ({} bytes), {} referenced",
+            if self.read_only { "Would remove" } else { "Removed" },
+            self.unreferenced.len(),
+            if self.unreferenced.len() == 1 { "object" } else { "objects" },
+            self.bytes,
+            self.referenced
+        );
+        for path in &self.unreferenced {
+            out.push_str(&format!("\n  {}", path));
+        }
+        out
+    }
+}
+
+/// Delete the objects no manifest entry refers to; a read-only client only
+/// lists them.
+pub async fn gc(config: &Config, client: &WebDavClient) -> Result<GcReport, Box<dyn Error>> {
+    let manifest = Manifest::load(client, config).await?;
+    // A missing manifest would make every object look unreferenced.
+    if manifest.files.is_empty() {
+        return Err(format!("No files in manifest '{}', refusing to delete every object", manifest_path(config)).into());
+    }
+    let referenced: HashSet<&str> = manifest.files.values().map(|entry| entry.hash.as_str()).collect();
+    let mut report = GcReport { referenced: referenced.len(), read_only: client.is_read_only(), ..GcReport::default() };
+    for entry in client.list_dir(&objects_dir(config), Depth::Infinity).await? {
+        if entry.is_dir || referenced.contains(entry.name()) {
+            continue;
+        }
+        if !report.read_only {
+            client.delete_file(&entry.path).await?;
+        }
+        report.bytes += entry.size.unwrap_or(0);
+        report.unreferenced.push(entry.path);
+    }
+    Ok(report)
+}
@@ -0,0 +1,147 @@
+//! Local files whose remote paths differ only in case.
+//!
+//! A case-insensitive server stores `Readme.md` and `README.md` as one file,
+//! so uploading both would let the walk order decide which one survives. With
+//! `case_collision_policy` set, such groups are found before any upload and
+//! exactly one file (or none, with `list_only`) is synced to the group's
+//! remote key. The hash store remembers which file backs each key, so ties
+//! keep the previous winner and a change of winner forces a fresh upload.
+
+use crate::config::{CaseCollisionPolicy, Config};
+use crate::filter::FilterSet;
+use crate::hash_store::HashStore;
+use crate::sync::selected_files;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// A group of files that map to the same remote file on a case-insensitive server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaseCollision {
+    /// Remote path the group is synced to.
+    pub key: String,
+    /// Remote path of the file uploaded to `key`; `None` with `list_only`.
+    pub winner: Option<String>,
+    /// Remote paths of the files that were not synced.
+    pub losers: Vec<String>,
+    pub policy: CaseCollisionPolicy,
+}
+
+/// What a local file does in its case collision group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaseRole {
+    /// Upload under `key` instead of the file's own remote path.
+    Winner { key: String },
+    Loser,
+}
+
+/// Roles of the colliding files of a run.
+#[derive(Debug, Default)]
+pub struct CasePlan {
+    roles: HashMap<PathBuf, CaseRole>,
+    pub collisions: Vec<CaseCollision>,
+}
+
+impl CasePlan {
+    /// Role of `local_path`, or `None` if it collides with nothing.
+    pub fn role(&self, local_path: &std::path::Path) -> Option<&CaseRole> {
+        self.roles.get(local_path)
+    }
+}
+
+struct Candidate {
+    local_path: PathBuf,
+    remote_path: String,
+    modified: SystemTime,
+    size: u64,
+}
+
+/// Find the case collisions among the selected files and pick their winners.
+///
+/// The key of a group is the path already recorded in `store` (as a key of
+/// `case_winners`, else of the hashes), or else the smallest of the group's
+/// paths, so it does not move between runs. A winner other than the recorded
+/// one drops the key's hash, so it is uploaded even if size and mtime happen
+/// to match the previous winner.
+pub fn plan_case_collisions(
+    config: &Config,
+    filters: &FilterSet,
+    store: &mut HashStore,
+    policy: CaseCollisionPolicy,
+) -> Result<CasePlan, Box<dyn Error>> {
+    let mut groups: BTreeMap<String, Vec<Candidate>> = BTreeMap::new();
+    for (local_path, remote_path) in selected_files(config, filters) {
+        let metadata = std::fs::metadata(&local_path)?;
+        groups.entry(remote_path.to_lowercase()).or_default().push(Candidate {
+            local_path,
+            remote_path,
+            modified: metadata.modified()?,
+            size: metadata.len(),
+        });
+    }
+
+    let mut plan = CasePlan::default();
+    for mut group in groups.into_values() {
+        group.sort_by(|a, b| a.remote_path.cmp(&b.remote_path));
+        if group.windows(2).all(|w| w[0].remote_path == w[1].remote_path) {
+            continue;
+        }
+        let paths: Vec<&str> = group.iter().map(|c| c.remote_path.as_str()).collect();
+        if policy == CaseCollisionPolicy::Error {
+            return Err(format!("Case collision: {} map to the same remote file", paths.join(", ")).into());
+        }
+        let key = paths
+            .iter()
+            .find(|path| store.case_winners.contains_key(**path))
+            .or_else(|| paths.iter().find(|path| store.hashes(false).contains_key(**path) || store.hashes(true).contains_key(**path)))
+            .unwrap_or(&paths[0])
+            .to_string();
+        let previous = store.case_winners.get(&key).cloned();
+
+        // Ties keep the previous winner, then the smallest path.
+        let rank = |c: &Candidate| (previous.as_deref() == Some(c.remote_path.as_str()), Reverse(c.remote_path.clone()));
+        let winner = match policy {
+            CaseCollisionPolicy::NewestWins => group.iter().max_by_key(|c| (c.modified, rank(c))),
+            CaseCollisionPolicy::LargestWins => group.iter().max_by_key(|c| (c.size, rank(c))),
+            CaseCollisionPolicy::ListOnly | CaseCollisionPolicy::Error => None,
+        };
+
+        let winner_local = winner.map(|w| w.local_path.clone());
+        let winner_path = winner.map(|w| w.remote_path.clone());
+        if let Some(winner) = &winner_path {
+            if previous.as_ref().is_some_and(|p| p != winner) {
+                info!("{} now backs {} (was {}), uploading it again", winner, key, previous.unwrap_or_default());
+                store.regular_hashes.remove(&key);
+                store.pseudo_hashes.remove(&key);
+                store.stamps.remove(&key);
+                store.chunks.remove(&key);
+            }
+            store.case_winners.insert(key.clone(), winner.clone());
+        }
+        warn!(
+            "Case collision on {}: {} ({:?})",
+            key,
+            match &winner_path {
+                Some(winner) => format!("syncing {}", winner),
+                None => "syncing none of them".to_string(),
+            },
+            policy
+        );
+
+        let mut losers = Vec::new();
+        for candidate in group {
+            if winner_local.as_ref() == Some(&candidate.local_path) {
+                plan.roles.insert(candidate.local_path, CaseRole::Winner { key: key.clone() });
+            } else {
+                losers.push(candidate.remote_path);
+                plan.roles.insert(candidate.local_path, CaseRole::Loser);
+            }
+        }
+        plan.collisions.push(CaseCollision { key, winner: winner_path, losers, policy });
+    }
+    Ok(plan)
+}
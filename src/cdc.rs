@@ -0,0 +1,123 @@
+//! Content-defined chunking (FastCDC) of large files.
+//!
+//! Experimental: chunk hashes are stored per file so that a change can be
+//! described as "N% of chunks differ". Files are still uploaded whole; the
+//! analysis tells whether a delta upload strategy would pay off.
+
+use fastcdc::v2020::StreamCDC;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::Read;
+use std::path::Path;
+
+/// Chunk size bounds in bytes: minimum, average, maximum.
+pub const CHUNK_SIZES: (u32, u32, u32) = (256 * 1024, 1024 * 1024, 4 * 1024 * 1024);
+
+/// Hex digits kept of each chunk's SHA-256, to keep the hash store small.
+const CHUNK_HASH_LEN: usize = 16;
+
+/// How much of a changed file differs from its previous version.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkChange {
+    pub path: String,
+    /// Chunks of the new version not present in the previous one.
+    pub changed: usize,
+    pub total: usize,
+}
+
+impl ChunkChange {
+    /// Compare the chunk hashes of the previous and the current version.
+    pub fn between(path: &str, previous: &[String], current: &[String]) -> Self {
+        let known: HashSet<&String> = previous.iter().collect();
+        ChunkChange {
+            path: path.to_string(),
+            changed: current.iter().filter(|c| !known.contains(c)).count(),
+            total: current.len(),
+        }
+    }
+
+    /// Share of changed chunks in percent, rounded up so any change is visible.
+    pub fn percent(&self) -> usize {
+        if self.total == 0 {
+            0
+        } else {
+            // u64 so the product cannot overflow on 32-bit targets.
+            (self.changed as u64 * 100).div_ceil(self.total as u64) as usize
+        }
+    }
+}
+
+/// Chunk hashes of a file, using [`CHUNK_SIZES`].
+pub async fn chunk_file<P: AsRef<Path>>(path: P) -> Result<Vec<String>, Box<dyn Error>> {
+    let path = path.as_ref().to_path_buf();
+    // FastCDC reads synchronously; keep it off the async workers.
+    tokio::task::spawn_blocking(move || -> Result<Vec<String>, String> {
+        let file = std::fs::File::open(&path).map_err(|e| e.to_string())?;
+        chunk_hashes(file, CHUNK_SIZES).map_err(|e| e.to_string())
+    })
+    .await?
+    .map_err(Into::into)
+}
+
+/// Chunk hashes of everything read from `source`.
+pub fn chunk_hashes<R: Read>(source: R, (min, avg, max): (u32, u32, u32)) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut hashes = Vec::new();
+    for chunk in StreamCDC::new(source, min, avg, max) {
+        let digest = format!("{:x}", Sha256::digest(chunk?.data));
+        hashes.push(digest[..CHUNK_HASH_LEN].to_string());
+    }
+    Ok(hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMALL_CHUNKS: (u32, u32, u32) = (2048, 8192, 32768);
+
+    /// Deterministic pseudo-random bytes, so chunk boundaries are stable.
+    fn sample_data(len: usize) -> Vec<u8> {
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunking_is_stable_around_an_edit() {
+        let original = sample_data(1024 * 1024);
+        let mut edited = original.clone();
+        for byte in &mut edited[500_000..500_100] {
+            *byte = !*byte;
+        }
+
+        let before = chunk_hashes(original.as_slice(), SMALL_CHUNKS).unwrap();
+        let again = chunk_hashes(original.as_slice(), SMALL_CHUNKS).unwrap();
+        let after = chunk_hashes(edited.as_slice(), SMALL_CHUNKS).unwrap();
+        assert_eq!(before, again);
+        assert!(before.len() > 20);
+
+        // Chunks before the edit are untouched, and the chunker resynchronises after it.
+        assert_eq!(before[..5], after[..5]);
+        assert_eq!(before.last(), after.last());
+
+        let change = ChunkChange::between("db.sqlite", &before, &after);
+        assert!((1..=3).contains(&change.changed), "{:?}", change);
+        assert!(change.percent() > 0 && change.percent() < 10, "{:?}", change);
+    }
+
+    #[test]
+    fn test_percent() {
+        let hashes = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let change = ChunkChange::between("f", &hashes(&["a", "b", "c"]), &hashes(&["a", "x", "c"]));
+        assert_eq!((change.changed, change.total, change.percent()), (1, 3, 34));
+        assert_eq!(ChunkChange::between("f", &[], &[]).percent(), 0);
+    }
+}
@@ -0,0 +1,181 @@
+//! Parsing and verification of server provided checksums (`OC-Checksum`).
+//!
+//! Nextcloud and ownCloud report checksums as `<ALGORITHM>:<hex>`, possibly
+//! several separated by spaces (e.g. `SHA1:… MD5:… ADLER32:…`).
+
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Name of the header carrying server side checksums.
+pub const CHECKSUM_HEADER: &str = "OC-Checksum";
+
+/// Checksum algorithms we can verify locally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChecksumAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    fn from_prefix(prefix: &str) -> Option<Self> {
+        match prefix.to_ascii_uppercase().as_str() {
+            "MD5" => Some(Self::Md5),
+            "SHA1" => Some(Self::Sha1),
+            "SHA256" => Some(Self::Sha256),
+            _ => None,
+        }
+    }
+
+    fn prefix(self) -> &'static str {
+        match self {
+            Self::Md5 => "MD5",
+            Self::Sha1 => "SHA1",
+            Self::Sha256 => "SHA256",
+        }
+    }
+
+    /// Hex digest of `data` with this algorithm.
+    pub fn digest(self, data: &[u8]) -> String {
+        match self {
+            Self::Md5 => format!("{:x}", Md5::digest(data)),
+            Self::Sha1 => format!("{:x}", Sha1::digest(data)),
+            Self::Sha256 => format!("{:x}", Sha256::digest(data)),
+        }
+    }
+}
+
+/// Incremental computation of a [`Checksum`], for content too large to buffer.
+pub enum ChecksumHasher {
+    Md5(Md5),
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl ChecksumHasher {
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Md5 => Self::Md5(Md5::new()),
+            ChecksumAlgorithm::Sha1 => Self::Sha1(Sha1::new()),
+            ChecksumAlgorithm::Sha256 => Self::Sha256(Sha256::new()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Md5(h) => h.update(data),
+            Self::Sha1(h) => h.update(data),
+            Self::Sha256(h) => h.update(data),
+        }
+    }
+
+    pub fn finish(self) -> Checksum {
+        let (algorithm, value) = match self {
+            Self::Md5(h) => (ChecksumAlgorithm::Md5, format!("{:x}", h.finalize())),
+            Self::Sha1(h) => (ChecksumAlgorithm::Sha1, format!("{:x}", h.finalize())),
+            Self::Sha256(h) => (ChecksumAlgorithm::Sha256, format!("{:x}", h.finalize())),
+        };
+        Checksum { algorithm, value }
+    }
+}
+
+/// A single checksum reported by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    /// Lowercase hex digest.
+    pub value: String,
+}
+
+impl Checksum {
+    /// Parse one `<ALGORITHM>:<hex>` entry. Unknown algorithms yield `None`.
+    pub fn parse(entry: &str) -> Option<Self> {
+        let (prefix, value) = entry.trim().split_once(':')?;
+        let algorithm = ChecksumAlgorithm::from_prefix(prefix)?;
+        let value = value.trim();
+        if value.is_empty() || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(Self { algorithm, value: value.to_ascii_lowercase() })
+    }
+
+    /// Parse a header value and return the strongest checksum it contains.
+    pub fn from_header(header: &str) -> Option<Self> {
+        header
+            .split_whitespace()
+            .filter_map(Self::parse)
+            .max_by_key(|c| c.algorithm)
+    }
+
+    /// The SHA-256 checksum with hex digest `hex`, e.g. a regular hash of
+    /// the hash store.
+    pub fn sha256(hex: &str) -> Self {
+        Self { algorithm: ChecksumAlgorithm::Sha256, value: hex.to_ascii_lowercase() }
+    }
+
+    /// Checksum of `data` with `algorithm`.
+    pub fn compute(algorithm: ChecksumAlgorithm, data: &[u8]) -> Self {
+        Self { algorithm, value: algorithm.digest(data) }
+    }
+
+    /// Whether `data` has this checksum.
+    pub fn matches(&self, data: &[u8]) -> bool {
+        self.algorithm.digest(data) == self.value
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm.prefix(), self.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_prefixes() {
+        let c = Checksum::parse("SHA256:ABCdef01").unwrap();
+        assert_eq!(c.algorithm, ChecksumAlgorithm::Sha256);
+        assert_eq!(c.value, "abcdef01");
+        assert_eq!(Checksum::parse("sha1:00ff").unwrap().algorithm, ChecksumAlgorithm::Sha1);
+        assert_eq!(Checksum::parse("MD5:00ff").unwrap().algorithm, ChecksumAlgorithm::Md5);
+        assert!(Checksum::parse("ADLER32:0a1b2c3d").is_none());
+        assert!(Checksum::parse("SHA1:").is_none());
+        assert!(Checksum::parse("SHA1:not-hex").is_none());
+        assert!(Checksum::parse("deadbeef").is_none());
+    }
+
+    #[test]
+    fn test_from_header_prefers_strongest() {
+        let header = "MD5:0cc175b9c0f1b6a831c399e269772661 SHA1:86f7e437faa5a7fce15d1ddcb9eaeaea377667b8 ADLER32:00620062";
+        let c = Checksum::from_header(header).unwrap();
+        assert_eq!(c.algorithm, ChecksumAlgorithm::Sha1);
+        assert!(c.matches(b"a"));
+        assert!(Checksum::from_header("ADLER32:00620062").is_none());
+    }
+
+    #[test]
+    fn test_matches_each_algorithm() {
+        let data = b"a";
+        for algorithm in [ChecksumAlgorithm::Md5, ChecksumAlgorithm::Sha1, ChecksumAlgorithm::Sha256] {
+            let c = Checksum::compute(algorithm, data);
+            assert!(c.matches(data));
+            assert!(!c.matches(b"b"));
+            assert_eq!(Checksum::parse(&c.to_string()), Some(c));
+        }
+    }
+
+    #[test]
+    fn test_hasher_matches_compute() {
+        for algorithm in [ChecksumAlgorithm::Md5, ChecksumAlgorithm::Sha1, ChecksumAlgorithm::Sha256] {
+            let mut hasher = ChecksumHasher::new(algorithm);
+            hasher.update(b"hello ");
+            hasher.update(b"world");
+            assert_eq!(hasher.finish(), Checksum::compute(algorithm, b"hello world"));
+        }
+    }
+}
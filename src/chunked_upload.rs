@@ -0,0 +1,186 @@
+//! Chunked, resumable uploads of large files.
+//!
+//! With `chunk_size_mb`, a file larger than one chunk is uploaded following
+//! the Nextcloud chunking v2 convention, if the server's capabilities
+//! announce it: the chunks are PUT into a temporary collection below
+//! `remote.php/dav/uploads/<user>`, and a MOVE of its `.file` assembles them
+//! at the destination. Other servers get the file in a single PUT.
+//!
+//! With `resume_uploads`, the temporary collection of every unfinished upload
+//! is kept in `upload_state_path`. The next upload of the same file lists the
+//! chunks the server already has and continues after the last complete one.
+
+use crate::config::Config;
+use crate::spread::fresh_seed;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Smallest chunk Nextcloud accepts (except for the last one).
+pub const MIN_CHUNK_SIZE_MB: u64 = 5;
+
+/// Name of the chunk upload collection's member a MOVE assembles.
+pub const ASSEMBLED_NAME: &str = ".file";
+
+/// How large files are uploaded, from the config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkedUploads {
+    /// Bytes per chunk; only larger files are chunked.
+    pub chunk_size: u64,
+    /// File of unfinished uploads; `None` without `resume_uploads`.
+    pub state_path: Option<PathBuf>,
+}
+
+impl ChunkedUploads {
+    /// The settings of `config`, or `None` if chunking is disabled.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        (config.chunk_size_mb > 0).then(|| ChunkedUploads {
+            chunk_size: config.chunk_size_mb * 1024 * 1024,
+            state_path: config.resume_uploads.then(|| PathBuf::from(&config.upload_state_path)),
+        })
+    }
+
+    /// Number of chunks of a file of `size` bytes.
+    pub fn chunk_count(&self, size: u64) -> u64 {
+        size.div_ceil(self.chunk_size).max(1)
+    }
+
+    /// Offset and length of chunk `index` (starting at 0).
+    pub fn chunk_range(&self, index: u64, size: u64) -> (u64, u64) {
+        let offset = index * self.chunk_size;
+        (offset, self.chunk_size.min(size - offset))
+    }
+}
+
+/// Whether OCS capabilities announce chunked uploads.
+pub fn supports_chunking(capabilities: &serde_json::Value) -> bool {
+    capabilities["ocs"]["data"]["capabilities"]["dav"]["chunking"].is_string()
+}
+
+/// The collection chunked uploads go below, for a WebDAV URL of the form
+/// `<root>/remote.php/dav/files/<user>/...`.
+pub fn uploads_url_for(webdav_url: &str) -> Option<String> {
+    let url = Url::parse(webdav_url).ok()?;
+    let path = url.path();
+    let index = path.find("/remote.php/dav/files/")?;
+    let user = path[index + "/remote.php/dav/files/".len()..].split('/').next().filter(|u| !u.is_empty())?;
+    let mut uploads = url.clone();
+    uploads.set_path(&format!("{}/remote.php/dav/uploads/{}", &path[..index], user));
+    uploads.set_query(None);
+    Some(uploads.as_str().to_string())
+}
+
+/// URL of a new temporary collection below `uploads_url`.
+pub fn new_upload_url(uploads_url: &str) -> String {
+    format!("{}/phone_sync-{:016x}", uploads_url.trim_end_matches('/'), fresh_seed())
+}
+
+/// Name of chunk `index` (starting at 0); Nextcloud numbers them from 1 and
+/// assembles them in name order.
+pub fn chunk_name(index: u64) -> String {
+    format!("{:05}", index + 1)
+}
+
+/// Number of chunks from the start that are complete, given the sizes of
+/// the members of an upload collection by name.
+pub fn completed_chunks(chunks: &ChunkedUploads, size: u64, listed: &BTreeMap<String, u64>) -> u64 {
+    (0..chunks.chunk_count(size))
+        .take_while(|&index| listed.get(&chunk_name(index)) == Some(&chunks.chunk_range(index, size).1))
+        .count() as u64
+}
+
+/// An upload that has not been assembled yet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartialUpload {
+    /// The temporary collection holding its chunks.
+    pub upload_url: String,
+    pub chunk_size: u64,
+    /// Size, mtime and hash of the file when its upload started; a changed
+    /// file starts over.
+    pub size: u64,
+    pub modified: Option<u64>,
+    pub hash: Option<String>,
+}
+
+impl PartialUpload {
+    pub fn new(upload_url: String, chunk_size: u64, metadata: &Metadata, hash: Option<&str>) -> Self {
+        PartialUpload {
+            upload_url,
+            chunk_size,
+            size: metadata.len(),
+            modified: modified_secs(metadata),
+            hash: hash.map(str::to_string),
+        }
+    }
+
+    /// Whether this upload can be continued for the file with `metadata` and
+    /// `hash` in chunks of `chunk_size`.
+    pub fn continues(&self, chunk_size: u64, metadata: &Metadata, hash: Option<&str>) -> bool {
+        self.chunk_size == chunk_size
+            && self.size == metadata.len()
+            && self.modified == modified_secs(metadata)
+            && self.hash.as_deref() == hash
+    }
+}
+
+fn modified_secs(metadata: &Metadata) -> Option<u64> {
+    metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
+
+/// Unfinished uploads by remote path.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadState {
+    #[serde(default)]
+    pub uploads: BTreeMap<String, PartialUpload>,
+}
+
+impl UploadState {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        if !path.as_ref().exists() {
+            return Ok(Self::default());
+        }
+        serde_yaml::from_str(&std::fs::read_to_string(&path)?)
+            .map_err(|e| format!("Invalid upload state '{}': {}", path.as_ref().display(), e).into())
+    }
+
+    /// Write the state, or remove the file once no upload is unfinished.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        if self.uploads.is_empty() {
+            if path.as_ref().exists() {
+                std::fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+        std::fs::write(path, serde_yaml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uploads_url_and_chunks() {
+        assert_eq!(
+            uploads_url_for("https://cloud.example.com/nc/remote.php/dav/files/me/Phone").as_deref(),
+            Some("https://cloud.example.com/nc/remote.php/dav/uploads/me")
+        );
+        assert_eq!(uploads_url_for("https://dav.example.com/webdav"), None);
+        let capabilities: serde_json::Value =
+            serde_json::from_str(r#"{"ocs": {"data": {"capabilities": {"dav": {"chunking": "1.0"}}}}}"#).unwrap();
+        assert!(supports_chunking(&capabilities));
+
+        let chunks = ChunkedUploads { chunk_size: 10, state_path: None };
+        assert_eq!(chunks.chunk_count(25), 3);
+        assert_eq!(chunks.chunk_range(2, 25), (20, 5));
+        assert_eq!(chunk_name(0), "00001");
+        // The second chunk is cut short, so only the first counts.
+        let listed = BTreeMap::from([("00001".to_string(), 10), ("00002".to_string(), 4), ("00003".to_string(), 5)]);
+        assert_eq!(completed_chunks(&chunks, 25, &listed), 1);
+    }
+}
@@ -0,0 +1,132 @@
+//! Wall clock and monotonic clock of a run.
+//!
+//! Devices without a real-time clock often have their time corrected by NTP
+//! shortly after boot, sometimes by hours and in the middle of a sync. So
+//! durations (backoff, budgets, elapsed time) are measured on the monotonic
+//! clock only, and the wall clock is read once when a run starts. Comparing
+//! how far both clocks advanced reveals a jump, after which a run no longer
+//! trusts mtimes.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Difference between wall clock and monotonic time taken as a jump.
+pub const JUMP_THRESHOLD: Duration = Duration::from_secs(60);
+
+/// Source of the current time; injectable so tests can make it jump.
+pub trait Clock: Send + Sync {
+    /// Wall-clock time, which may jump.
+    fn wall(&self) -> SystemTime;
+    /// Monotonic time, which only moves forward at a steady rate.
+    fn monotonic(&self) -> Instant;
+}
+
+/// The clocks of the operating system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn wall(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn monotonic(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to, for tests.
+#[derive(Debug)]
+pub struct ManualClock {
+    base: Instant,
+    /// Wall time and monotonic time since `base`.
+    now: Mutex<(SystemTime, Duration)>,
+}
+
+impl ManualClock {
+    pub fn new(wall: SystemTime) -> Self {
+        ManualClock { base: Instant::now(), now: Mutex::new((wall, Duration::ZERO)) }
+    }
+
+    /// Let `elapsed` pass on both clocks.
+    pub fn advance(&self, elapsed: Duration) {
+        let mut now = self.now.lock().unwrap();
+        now.0 += elapsed;
+        now.1 += elapsed;
+    }
+
+    /// Set the wall clock `secs` seconds forward (or back, if negative), as
+    /// NTP would.
+    pub fn jump(&self, secs: i64) {
+        let mut now = self.now.lock().unwrap();
+        let by = Duration::from_secs(secs.unsigned_abs());
+        now.0 = if secs >= 0 { now.0 + by } else { now.0 - by };
+    }
+}
+
+impl Clock for ManualClock {
+    fn wall(&self) -> SystemTime {
+        self.now.lock().unwrap().0
+    }
+
+    fn monotonic(&self) -> Instant {
+        self.base + self.now.lock().unwrap().1
+    }
+}
+
+/// The times of one run, taken when it starts.
+pub struct RunClock<'a> {
+    clock: &'a dyn Clock,
+    started: SystemTime,
+    start: Instant,
+}
+
+impl<'a> RunClock<'a> {
+    pub fn start(clock: &'a dyn Clock) -> Self {
+        RunClock { clock, started: clock.wall(), start: clock.monotonic() }
+    }
+
+    /// Wall-clock time the run started, the one timestamp it records.
+    pub fn started(&self) -> SystemTime {
+        self.started
+    }
+
+    /// Time since the run started, unaffected by clock jumps.
+    pub fn elapsed(&self) -> Duration {
+        self.clock.monotonic().saturating_duration_since(self.start)
+    }
+
+    /// Seconds the wall clock jumped since the run started (negative if it
+    /// went back), if more than [`JUMP_THRESHOLD`].
+    pub fn jump(&self) -> Option<i64> {
+        let monotonic = self.elapsed().as_secs_f64();
+        let wall = match self.clock.wall().duration_since(self.started) {
+            Ok(ahead) => ahead.as_secs_f64(),
+            Err(behind) => -behind.duration().as_secs_f64(),
+        };
+        let jump = wall - monotonic;
+        (jump.abs() > JUMP_THRESHOLD.as_secs_f64()).then_some(jump.round() as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_jumps_are_detected_and_elapsed_time_is_monotonic() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000));
+        let run = RunClock::start(&clock);
+        clock.advance(Duration::from_secs(600));
+        assert_eq!(run.jump(), None);
+        clock.jump(30);
+        assert_eq!(run.jump(), None);
+
+        // NTP sets the clock back by two hours.
+        clock.jump(-7200);
+        assert_eq!(run.jump(), Some(-7170));
+        assert_eq!(run.elapsed(), Duration::from_secs(600));
+        assert_eq!(run.started(), UNIX_EPOCH + Duration::from_secs(1_000_000));
+    }
+}
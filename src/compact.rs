@@ -0,0 +1,122 @@
+//! Keeping the hash store from outgrowing the synced files.
+//!
+//! Renames, deleted files and `target_dir` experiments leave entries behind
+//! that no sync removes. After a sync, the entries of this run's scope (keys
+//! below `target_dir`) are compared with the files seen; past
+//! `compact_ratio`, the run suggests `hashes prune` or, with `auto_compact`,
+//! prunes right away. Entries outside the scope belong to other devices or
+//! target directories and are never touched.
+
+use crate::config::Config;
+use crate::filter::is_below;
+use crate::hash_store::HashStore;
+use crate::sync::{local_path_for, remote_path_for};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+
+/// Hash store entries of this run's scope compared with the files seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreGrowth {
+    /// Regular and pseudo hashes below `target_dir`.
+    pub entries: usize,
+    pub files: usize,
+}
+
+impl StoreGrowth {
+    pub fn of(config: &Config, store: &HashStore, files: usize) -> Self {
+        let entries = store
+            .regular_hashes
+            .keys()
+            .chain(store.pseudo_hashes.keys())
+            .filter(|key| in_scope(config, key))
+            .count();
+        StoreGrowth { entries, files }
+    }
+
+    /// Entries per file seen; `None` before any file was seen.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.files > 0).then(|| self.entries as f64 / self.files as f64)
+    }
+
+    pub fn exceeds(&self, threshold: f64) -> bool {
+        self.ratio().is_some_and(|ratio| ratio > threshold)
+    }
+}
+
+/// Entries removed by [`compact`], by category.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Compaction {
+    /// Entries whose local file no longer exists in any configured folder.
+    pub missing_local: usize,
+    /// Pseudo hashes of files that also have a regular hash.
+    pub superseded_pseudo: usize,
+}
+
+impl Compaction {
+    pub fn total(&self) -> usize {
+        self.missing_local + self.superseded_pseudo
+    }
+}
+
+/// Remove the stale entries of this run's scope from `store`.
+///
+/// Pseudo hashes only count as superseded when `use_pseudo_hash` is off, as a
+/// pseudo-hash run compares against them. Fails without changing anything if
+/// a configured folder is missing, since its files would all look deleted.
+pub fn compact(config: &Config, store: &mut HashStore, use_pseudo_hash: bool) -> Result<Compaction, Box<dyn Error>> {
+    if let Some(folder) = config.folders.iter().find(|folder| !Path::new(&folder.path).exists()) {
+        return Err(format!("Folder '{}' is missing, not pruning the hash store", folder.path).into());
+    }
+    let mut compaction = Compaction::default();
+    let keys: Vec<String> = store
+        .regular_hashes
+        .keys()
+        .chain(store.pseudo_hashes.keys())
+        .filter(|key| in_scope(config, key))
+        .cloned()
+        .collect();
+    for key in keys {
+        if !store.regular_hashes.contains_key(&key) && !store.pseudo_hashes.contains_key(&key) {
+            // Already forgotten through its other map.
+            continue;
+        }
+        if local_path_for(config, &key).is_none() {
+            compaction.missing_local +=
+                usize::from(store.regular_hashes.contains_key(&key)) + usize::from(store.pseudo_hashes.contains_key(&key));
+            store.forget(&key);
+        } else if !use_pseudo_hash && store.regular_hashes.contains_key(&key) && store.pseudo_hashes.remove(&key).is_some() {
+            compaction.superseded_pseudo += 1;
+        }
+    }
+    Ok(compaction)
+}
+
+/// Whether `key` belongs to the scope of `config`; with an empty `target_dir`
+/// that is the whole store. Files of flattened routes are left alone, as
+/// their local file cannot be told from the key.
+fn in_scope(config: &Config, key: &str) -> bool {
+    let flattened = config.folders.iter().flat_map(|f| &f.routes).filter(|r| r.flatten);
+    is_below(key, &config.target_dir) && !flattened.map(|r| remote_path_for(config, r.subdir())).any(|dir| is_below(key, &dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_growth_ratio_counts_only_this_scope() {
+        let config: Config = serde_yaml::from_str("webdav_url: \"x\"\nfolders: []\ntarget_dir: \"phone/\"\n").unwrap();
+        let mut store = HashStore::default();
+        for key in ["phone/a", "phone/b", "phone/c", "tablet/a", "phoneX/a"] {
+            store.regular_hashes.insert(key.to_string(), "h".to_string());
+        }
+        store.pseudo_hashes.insert("phone/a".to_string(), "p".to_string());
+
+        let growth = StoreGrowth::of(&config, &store, 2);
+        assert_eq!(growth, StoreGrowth { entries: 4, files: 2 });
+        assert_eq!(growth.ratio(), Some(2.0));
+        assert!(growth.exceeds(1.5) && !growth.exceeds(2.0));
+        assert!(!StoreGrowth::of(&config, &store, 0).exceeds(1.0));
+    }
+}
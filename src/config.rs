@@ -1,29 +1,579 @@
+use crate::auth::{Auth, BasicAuthMode, SecretSource};
+use crate::batch::BatchConfig;
+use crate::budget::TransferBudget;
+use crate::chunked_upload::MIN_CHUNK_SIZE_MB;
+use crate::external_hasher::ExternalHasherConfig;
+use crate::filter::wildcard_match;
+use crate::folder_kind::FolderKind;
+use crate::hash_delta::DeltaConfig;
+use crate::network::NetworkConfig;
+use crate::verify_sampling::SamplingConfig;
+use crate::webdav_client::{default_headers, ClientIdentity, PoolSettings};
+use crate::units::{byte_size, duration_days_compat, duration_millis_compat, duration_secs_compat};
+use log::warn;
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Config {
     pub webdav_url: String,
     pub username: Option<String>,
     pub password: Option<String>,
-    pub folders: Vec<String>,
+    /// Token sent as `Authorization: Bearer` instead of basic auth, inline or
+    /// as `{ env: NAME }` or `{ file: PATH }`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bearer_token: Option<SecretSource>,
+    /// `preemptive` sends `username` and `password` with every request;
+    /// `challenge` only once the server asked for them with a 401.
+    #[serde(default)]
+    pub basic_auth: BasicAuthMode,
+    pub folders: Vec<FolderConfig>,
     #[serde(default = "default_hash_path")]
     pub hash_store_path: String,
-    #[serde(default = "default_timeout_secs")]
-    pub timeout_secs: u64,
+    /// Progress of an interrupted `pull`, so the next one continues it.
+    #[serde(default = "default_pull_state_path")]
+    pub pull_state_path: String,
+    /// Unfinished chunked uploads, so the next sync resumes them.
+    #[serde(default = "default_upload_state_path")]
+    pub upload_state_path: String,
+    /// Rename pulled files whose names the local filesystem rejects (too
+    /// long, or invalid characters) instead of skipping them.
+    #[serde(default)]
+    pub pull_sanitize_local: bool,
+    /// HTTP request timeout, e.g. `30s`; a bare number is seconds. Uploads
+    /// get more time for their body, see `min_upload_kbps`.
+    #[serde(default = "default_timeout", alias = "timeout_secs", with = "duration_secs_compat")]
+    pub timeout: Duration,
+    /// Limit for connecting to the server, TLS handshake included.
+    #[serde(default = "default_connect_timeout", with = "duration_secs_compat")]
+    pub connect_timeout: Duration,
+    /// Timeout of the hash store download, e.g. `5m`. Without it, `timeout`
+    /// plus the time the last known size of the store (that of the local
+    /// copy) takes at `min_upload_kbps`.
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "duration_secs_compat::option")]
+    pub hash_store_timeout: Option<Duration>,
+    /// Slowest upload rate in kbit/s a transfer is allowed; an upload times
+    /// out after `timeout` plus the time its size takes at this rate.
+    #[serde(default = "default_min_upload_kbps")]
+    pub min_upload_kbps: u64,
+    /// Idle connections kept open per host, so later requests skip the TCP
+    /// and TLS handshakes.
+    #[serde(default = "default_pool_max_idle_per_host")]
+    pub pool_max_idle_per_host: usize,
+    /// How long an idle connection is kept open, e.g. `5m`.
+    #[serde(default = "default_pool_idle_timeout", with = "duration_secs_compat")]
+    pub pool_idle_timeout: Duration,
+    /// HTTP version to speak; `http2` skips negotiation and requires server support.
+    #[serde(default)]
+    pub http_version: HttpVersion,
+    /// Address family and source address of connections to the server.
+    #[serde(default)]
+    pub network: NetworkConfig,
+    /// Accept any server certificate, e.g. a self-signed one. Trusting it
+    /// via `ca_cert_path` keeps protection against impostors.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub insecure_tls: bool,
+    /// PEM file with CA certificates to trust in addition to the system's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_cert_path: Option<String>,
+    /// PEM certificate presented to servers (or reverse proxies) that
+    /// require mutual TLS; goes with `client_key_path`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert_path: Option<String>,
+    /// PEM private key of `client_cert_path`; PKCS#8 (`BEGIN PRIVATE KEY`)
+    /// with the default TLS backend.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_key_path: Option<String>,
+    /// PKCS#12 (`.p12`/`.pfx`) file with client certificate and key, instead
+    /// of `client_cert_path` and `client_key_path`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_pkcs12_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_pkcs12_passphrase: Option<String>,
+    /// HTTP(S) proxy for all requests, e.g. `http://proxy.corp:3128`;
+    /// without it, `HTTP_PROXY`/`HTTPS_PROXY` from the environment apply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_password: Option<String>,
+    /// User-Agent of every request, for firewalls that block reqwest's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Headers sent with every request, e.g. `X-Requested-With:
+    /// XMLHttpRequest` for Nextcloud's brute-force protection exemption.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra_headers: BTreeMap<String, String>,
     #[serde(default = "default_target_dir")]
     pub target_dir: String,
+    /// Create `target_dir` and its parents at the start of a run if the
+    /// server does not have it yet.
+    #[serde(default = "default_create_target_dir")]
+    pub create_target_dir: bool,
     #[serde(default = "default_remote_hash_path")]
     pub remote_hash_path: String,
+    /// Delete the remote copy of synced files that were deleted locally. Only
+    /// happens on runs that see every folder and use no selection flags.
+    #[serde(default)]
+    pub mirror_deletions: bool,
+    /// Where remote copies are archived before `mirror_deletions` removes them.
+    #[serde(default)]
+    pub local_archive_dir: Option<String>,
+    /// Age after which archived copies are pruned, e.g. `30d`; a bare number
+    /// is days. Kept forever if unset.
+    #[serde(default, alias = "archive_retention_days", with = "duration_days_compat::option")]
+    pub archive_retention: Option<Duration>,
+    /// Remote files found for an empty hash store before the first run asks
+    /// whether to adopt them instead of uploading everything again.
+    #[serde(default = "default_first_run_threshold")]
+    pub first_run_threshold: usize,
+    /// Append-only log of remote mutations (JSON lines), see `journal replay`.
+    #[serde(default)]
+    pub journal_path: Option<String>,
+    /// Size after which the journal is rotated to `<journal_path>.1`, e.g. `10MiB`.
+    #[serde(default = "default_journal_max_bytes", with = "byte_size")]
+    pub journal_max_bytes: u64,
+    /// Directory below which each run keeps its temporary files; defaults to
+    /// the system temp directory.
+    #[serde(default)]
+    pub temp_dir: Option<String>,
+    /// File names of other configs' artifacts (e.g. their hash stores) in
+    /// the synced folders, which are never uploaded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifact_names: Vec<String>,
+    /// Upload `artifact_names`, `*.phone_sync.lock` and `*.part` files like
+    /// any other file; the own hash store is still skipped.
+    #[serde(default)]
+    pub upload_sync_artifacts: bool,
+    /// Allow running while another sync with overlapping folders runs, if
+    /// that one allows it too.
+    #[serde(default)]
+    pub allow_overlapping_runs: bool,
+    /// Rewrite hash store keys with `\` separators (from a store written on
+    /// Windows) to `/` on every sync, see `hashes normalize`.
+    #[serde(default)]
+    pub normalize_store_keys: bool,
+    /// Upload files whose names start or end with whitespace or contain
+    /// control characters under a trimmed name with `_` for each control
+    /// character, see [`crate::problem_names`].
+    #[serde(default)]
+    pub normalize_problem_names: bool,
+    /// Hash store entries per file seen in this run (below `target_dir`)
+    /// above which the run suggests `hashes prune`.
+    #[serde(default = "default_compact_ratio")]
+    pub compact_ratio: f64,
+    /// Prune the hash store instead of only suggesting it when it exceeds
+    /// `compact_ratio`.
+    #[serde(default)]
+    pub auto_compact: bool,
+    /// Refuse every write request to the server; sync then only reports what
+    /// it would upload or delete. Also set by `--read-only`.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Whether the hash store is mirrored to `remote_hash_path`. Disable it for
+    /// a single device, so that only the local store is used.
+    #[serde(default)]
+    pub remote_hash_store: RemoteHashStore,
+    /// Store the remote hash store gzip-compressed, as `remote_hash_path`
+    /// with `.gz` appended (unless it ends in `.gz` already). A plain store
+    /// there from before is read once, then replaced. Every device syncing to
+    /// it needs this version and setting, as older ones only read the plain one.
+    #[serde(default)]
+    pub compress_hash_store: bool,
+    /// Hold a WebDAV lock on `remote_hash_path` during a sync, on servers
+    /// that support locking, so two machines never rewrite it at once.
+    #[serde(default = "default_lock_hash_store")]
+    pub lock_hash_store: bool,
+    /// How long a sync waits for another machine's lock on the remote hash
+    /// store before giving up, e.g. `5m`; a bare number is seconds.
+    #[serde(default = "default_hash_store_lock_wait", with = "duration_secs_compat")]
+    pub hash_store_lock_wait: Duration,
+    /// Stream files straight from the directory walk instead of collecting and
+    /// sorting each folder up front, and spill remote directory listings to
+    /// disk. Trades upload ordering, an exact progress total and the quota
+    /// check for a memory footprint independent of the tree size.
+    #[serde(default)]
+    pub low_memory: bool,
+    /// Rough limit on the resident memory of a sync in MiB, sampled every
+    /// 1000 files (Linux only). Above it, the cached remote listings are
+    /// dropped; if the next sample is still above it, the sync fails.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_ceiling_mb: Option<u64>,
+    /// Skip directories containing a `.nomedia` file (Android's marker for
+    /// non-gallery folders) together with everything below them.
+    #[serde(default)]
+    pub respect_nomedia: bool,
+    /// Wildcard patterns (`*`, `?`) of folder-relative directories synced
+    /// despite their `.nomedia` file, e.g. `*/WhatsApp Images`.
+    #[serde(default)]
+    pub nomedia_allowlist: Vec<String>,
+    /// When to show a desktop notification after a run.
+    #[serde(default)]
+    pub desktop_notifications: NotifyPolicy,
+    /// How often the final hash store upload is retried before giving up.
+    #[serde(default = "default_finalize_retries")]
+    pub finalize_retries: u32,
+    /// Fail the run when the hash store could only be saved locally. By default
+    /// the run succeeds with a warning and the upload is retried on the next run.
+    #[serde(default)]
+    pub fail_on_pending_upload: bool,
+    /// After each upload, compare the size the server reports for the file
+    /// with the local size, to catch proxies that store truncated bodies.
+    #[serde(default = "default_verify_upload_size")]
+    pub verify_upload_size: bool,
+    /// Truncated uploads found by `verify_upload_size` after which a sync
+    /// gives up, since a proxy limiting the body size will cut every large
+    /// file. Raise it to get through the files that fit.
+    #[serde(default = "default_max_truncated_uploads")]
+    pub max_truncated_uploads: usize,
+    /// Abort a sync before its first upload when the server's quota cannot
+    /// hold the files to upload. Without it, the sync only warns. Not
+    /// checked with `low_memory`.
+    #[serde(default = "default_fail_on_insufficient_quota")]
+    pub fail_on_insufficient_quota: bool,
+    /// Delete the remote file before every upload. Without it, the file is
+    /// only deleted first when the server refuses to overwrite it (405/412).
+    #[serde(default)]
+    pub force_delete_before_put: bool,
+    /// Check with a PROPFIND whether a remote directory exists before
+    /// creating it, for servers that log the 405 answering a MKCOL on an
+    /// existing one as an error. Either way each directory is checked at
+    /// most once per run.
+    #[serde(default)]
+    pub check_dirs_before_mkcol: bool,
+    /// Send a Content-Type guessed from the file extension with every
+    /// upload, so the server can preview the file; see [`crate::content_type`].
+    #[serde(default = "default_set_content_type")]
+    pub set_content_type: bool,
+    /// Send the SHA-256 of each upload as `OC-Checksum`, which Nextcloud
+    /// and ownCloud verify before storing the file. Turn off for servers
+    /// that reject requests with headers they do not know.
+    #[serde(default = "default_send_checksum")]
+    pub send_checksum: bool,
+    /// Upload files larger than this many MiB in chunks of this size, on
+    /// Nextcloud servers that support it; elsewhere they go in one PUT.
+    /// `0` disables chunking.
+    #[serde(default = "default_chunk_size_mb")]
+    pub chunk_size_mb: u64,
+    /// Let a sync continue an interrupted chunked upload after its last
+    /// complete chunk instead of starting over.
+    #[serde(default = "default_resume_uploads")]
+    pub resume_uploads: bool,
+    /// How often a failed file upload is retried.
+    #[serde(default = "default_upload_retries")]
+    pub upload_retries: u32,
+    /// Delay before the first upload retry, e.g. `500ms`; doubled after each
+    /// retry. A bare number is milliseconds.
+    #[serde(default = "default_retry_delay", alias = "retry_delay_ms", with = "duration_millis_compat")]
+    pub retry_delay: Duration,
+    /// How often other idempotent requests (HEAD, GET, MKCOL, DELETE,
+    /// PROPFIND) are retried after a timeout, a dropped connection or a
+    /// 429/500/502/503/504, with the same backoff as uploads.
+    #[serde(default = "default_request_retries")]
+    pub request_retries: u32,
+    /// Longest random delay added to every retry backoff, e.g. `250ms`.
+    #[serde(default = "default_retry_jitter", with = "duration_millis_compat")]
+    pub retry_jitter: Duration,
+    /// Allowed difference between Last-Modified values that are still
+    /// considered the same remote version (for servers without ETags). A bare
+    /// number is seconds.
+    #[serde(
+        default = "default_last_modified_tolerance",
+        alias = "last_modified_tolerance_secs",
+        with = "duration_secs_compat"
+    )]
+    pub last_modified_tolerance: Duration,
+    /// Skip hashing files whose size and mtime match the stored ones exactly.
+    #[serde(default)]
+    pub trust_mtime: bool,
+    /// Local mtime differences up to this much (e.g. from copying between
+    /// filesystems of different precision) are resolved by hashing instead of
+    /// counting as a change. A bare number is milliseconds.
+    #[serde(default = "default_mtime_tolerance", alias = "mtime_tolerance_ms", with = "duration_millis_compat")]
+    pub mtime_tolerance: Duration,
+    /// Random delay of up to this long before a sync starts, e.g. `10m`, so
+    /// machines scheduled at the same time do not reach the server together.
+    /// A bare number is seconds.
+    #[serde(default, with = "duration_secs_compat")]
+    pub start_jitter: Duration,
+    /// Order in which the files of a folder are uploaded.
+    #[serde(default)]
+    pub upload_order: UploadOrder,
+    /// Seed for `upload_order: shuffle`; a new one is picked and logged per run if unset.
+    #[serde(default)]
+    pub shuffle_seed: Option<u64>,
+    /// What to do when two local files map to the same remote path.
+    #[serde(default)]
+    pub collision_policy: CollisionPolicy,
+    /// How to sync files whose remote paths differ only in case, for servers
+    /// that treat them as the same file. Unset for case-sensitive servers.
+    #[serde(default)]
+    pub case_collision_policy: Option<CaseCollisionPolicy>,
+    /// Experimental: chunk large files and report how much of them changed.
+    /// Files are still uploaded whole.
+    #[serde(default)]
+    pub cdc_dedup: bool,
+    /// Files at least this large are chunked when `cdc_dedup` is set, e.g. `16MiB`.
+    #[serde(default = "default_cdc_min_file_size", with = "byte_size")]
+    pub cdc_min_file_size: u64,
+    /// Optional monthly limit on uploaded bytes.
+    #[serde(default)]
+    pub transfer_budget: Option<TransferBudget>,
+    /// Command that reports file hashes instead of reading the files.
+    #[serde(default)]
+    pub external_hasher: Option<ExternalHasherConfig>,
+    /// Upload only the changes of each run to the remote hash store, as
+    /// per-device delta files; see [`crate::hash_delta`].
+    #[serde(default)]
+    pub hash_store_deltas: Option<DeltaConfig>,
+    /// Upload small files in batches; see [`crate::batch`].
+    #[serde(default)]
+    pub small_file_batching: Option<BatchConfig>,
+    /// How files are stored on the server; see [`crate::cas`].
+    #[serde(default)]
+    pub layout: Layout,
+    /// Download a sample of each run's uploads to check them; see
+    /// [`crate::verify_sampling`].
+    #[serde(default)]
+    pub verify_sampling: Option<SamplingConfig>,
+}
+
+/// A configured local folder, written either as a plain path or as a map.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(from = "FolderEntry")]
+pub struct FolderConfig {
+    pub path: String,
+    /// Lowercase extensions without leading dot that may be synced from this
+    /// folder; `""` admits files without an extension. `None` admits all files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Vec<String>>,
+    /// Identity for per-folder bookkeeping; see [`Config::folder_id`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Only sync the folder while a filesystem is mounted on it; see [`crate::mount`].
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub require_mounted: bool,
+    /// Only sync the folder while this file exists in it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_marker_file: Option<String>,
+    /// Remote subdirectories for some of the folder's files; see [`Route`].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<Route>,
+}
+
+/// Sends the files of a folder matching `match`, e.g. `"*.mp4|*.mov"`, to
+/// `target_subdir` below `target_dir`, keeping their relative path unless
+/// `flatten` keeps only the file name. The first matching route applies.
+///
+/// A file's remote path is built in this order: route, `target_dir` prefix,
+/// case collision resolution, then `normalize_problem_names`. Loading the
+/// config rejects a `target_subdir` that already is a directory of a folder;
+/// files that still end up on one remote path, flattened ones or those of a
+/// directory created later, are handled per file by `collision_policy`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Route {
+    /// `|`-separated wildcard patterns on the path relative to the folder.
+    #[serde(rename = "match")]
+    pub pattern: String,
+    pub target_subdir: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub flatten: bool,
+}
+
+impl Route {
+    /// Whether the route applies to `relative_path`.
+    pub fn matches(&self, relative_path: &str) -> bool {
+        self.pattern.split('|').any(|pattern| wildcard_match(pattern.trim(), relative_path))
+    }
+
+    /// `target_subdir` without surrounding slashes.
+    pub fn subdir(&self) -> &str {
+        self.target_subdir.trim_matches('/')
+    }
+}
+
+impl FolderConfig {
+    /// A folder with only a path.
+    pub fn new(path: impl Into<String>) -> Self {
+        FolderConfig {
+            path: path.into(),
+            extensions: None,
+            id: None,
+            require_mounted: false,
+            require_marker_file: None,
+            routes: Vec::new(),
+        }
+    }
+
+    /// Path of a file below `target_dir`, given its path relative to the
+    /// folder: rewritten by the first matching route, else unchanged.
+    pub fn route(&self, relative_path: &str) -> String {
+        let path = relative_path.replace('\\', "/");
+        let Some(route) = self.routes.iter().find(|route| route.matches(&path)) else {
+            return relative_path.to_string();
+        };
+        let kept = if route.flatten { path.rsplit('/').next().unwrap_or(&path) } else { &path };
+        format!("{}/{}", route.subdir(), kept)
+    }
+
+    /// Path relative to the folder of the file routed to `routed_path`, if a
+    /// route leads there; flattened routes cannot be reversed.
+    pub fn unroute<'a>(&self, routed_path: &'a str) -> Option<&'a str> {
+        self.routes.iter().filter(|route| !route.flatten).find_map(|route| {
+            let relative_path = routed_path.strip_prefix(route.subdir())?.strip_prefix('/')?;
+            (self.route(relative_path) == routed_path).then_some(relative_path)
+        })
+    }
+
+    /// Whether the extension allowlist admits `path`.
+    pub fn admits(&self, path: &Path) -> bool {
+        let Some(extensions) = &self.extensions else {
+            return true;
+        };
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        extensions.contains(&extension)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FolderEntry {
+    Path(String),
+    Detailed {
+        path: String,
+        #[serde(default)]
+        extensions: Option<Vec<String>>,
+        #[serde(default)]
+        id: Option<String>,
+        #[serde(default)]
+        require_mounted: bool,
+        #[serde(default)]
+        require_marker_file: Option<String>,
+        #[serde(default)]
+        routes: Vec<Route>,
+    },
+}
+
+impl From<FolderEntry> for FolderConfig {
+    fn from(entry: FolderEntry) -> Self {
+        match entry {
+            FolderEntry::Path(path) => FolderConfig::new(path),
+            FolderEntry::Detailed { path, extensions, id, require_mounted, require_marker_file, routes } => FolderConfig {
+                path,
+                id,
+                require_mounted,
+                require_marker_file,
+                routes,
+                extensions: extensions.map(|list| {
+                    list.iter()
+                        .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+                        .collect()
+                }),
+            },
+        }
+    }
+}
+
+/// Handling of local files that resolve to an already claimed remote path.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionPolicy {
+    /// Abort the run.
+    Error,
+    /// Sync the first file, report and skip the others.
+    #[default]
+    FirstWins,
+}
+
+/// Which of several local files differing only in case backs their remote file.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaseCollisionPolicy {
+    /// Abort the run.
+    Error,
+    /// The most recently modified file.
+    NewestWins,
+    /// The largest file.
+    LargestWins,
+    /// Report the collision and upload none of the files.
+    ListOnly,
+}
+
+/// Order in which the files of a folder are uploaded.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadOrder {
+    /// Files in deeper directories first.
+    #[default]
+    DeepestFirst,
+    /// A random order per run, so concurrent machines spread over the files.
+    Shuffle,
+}
+
+/// How synced files are stored on the server.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// Every file at its own remote path below `target_dir`.
+    #[default]
+    Mirror,
+    /// Each distinct content once, named by its hash, with a manifest.
+    Cas,
+}
+
+/// Whether the hash store is kept on the remote in addition to locally.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoteHashStore {
+    #[default]
+    Enabled,
+    Disabled,
+}
+
+/// HTTP version preference of the client.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpVersion {
+    /// HTTP/2 where TLS negotiation (ALPN) offers it, HTTP/1.1 otherwise.
+    #[default]
+    Auto,
+    Http1,
+    /// HTTP/2 with prior knowledge, also over plain HTTP.
+    Http2,
+}
+
+/// Policy for desktop notifications at the end of a run.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyPolicy {
+    Always,
+    Failure,
+    #[default]
+    Never,
 }
 
 impl Config {
     /// Load the configuration from a YAML file and validate its contents.
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
-        let content = fs::read_to_string(path)?;
-        let config: Config = serde_yaml::from_str(&content)?;
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse and validate the YAML of a configuration file.
+    pub fn parse(content: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config: Config = serde_yaml::from_str(content)?;
         config.validate()?;
+        config.webdav_url = normalize_webdav_url(&config.webdav_url)?;
+        config.dedupe_folders();
+        config.validate_folder_ids()?;
         Ok(config)
     }
 
@@ -33,16 +583,234 @@ impl Config {
         if self.webdav_url.trim().is_empty() {
             return Err("webdav_url cannot be empty".into());
         }
+        normalize_webdav_url(&self.webdav_url)?;
         if self.folders.is_empty() {
             return Err("folders list cannot be empty".into());
         }
+        // Shuffling needs the whole file list of a folder in memory.
+        if self.upload_order == UploadOrder::Shuffle && self.low_memory {
+            return Err("upload_order: shuffle cannot be combined with low_memory".into());
+        }
+        if self.memory_ceiling_mb == Some(0) {
+            return Err("memory_ceiling_mb must be at least 1".into());
+        }
+        if self.bearer_token.is_some() && self.password.is_some() {
+            return Err("set either password or bearer_token, not both".into());
+        }
+        if self.basic_auth == BasicAuthMode::Challenge && self.bearer_token.is_some() {
+            return Err("basic_auth: challenge does not apply to bearer_token".into());
+        }
+        if self.proxy_url.is_none() && (self.proxy_username.is_some() || self.proxy_password.is_some()) {
+            return Err("proxy_username and proxy_password need a proxy_url".into());
+        }
+        default_headers(self.user_agent.as_deref(), &self.extra_headers)?;
+        match (&self.client_cert_path, &self.client_key_path, &self.client_pkcs12_path) {
+            (Some(_), None, _) | (None, Some(_), _) => {
+                return Err("client_cert_path and client_key_path need each other".into());
+            }
+            (Some(_), Some(_), Some(_)) => {
+                return Err("set either client_cert_path and client_key_path or client_pkcs12_path, not both".into());
+            }
+            (_, _, None) if self.client_pkcs12_passphrase.is_some() => {
+                return Err("client_pkcs12_passphrase needs a client_pkcs12_path".into());
+            }
+            _ => {}
+        }
+        if self.min_upload_kbps == 0 {
+            return Err("min_upload_kbps must be at least 1".into());
+        }
+        if self.max_truncated_uploads == 0 {
+            return Err("max_truncated_uploads must be at least 1".into());
+        }
+        if self.hash_store_timeout == Some(Duration::ZERO) {
+            return Err("hash_store_timeout must be longer than 0s".into());
+        }
+        if self.chunk_size_mb > 0 && self.chunk_size_mb < MIN_CHUNK_SIZE_MB {
+            return Err(format!("chunk_size_mb must be 0 (off) or at least {}", MIN_CHUNK_SIZE_MB).into());
+        }
+        self.network.validate()?;
+        if let Some(deltas) = &self.hash_store_deltas {
+            deltas.validate()?;
+        }
+        if let Some(batching) = &self.small_file_batching {
+            batching.validate()?;
+        }
+        if let Some(sampling) = &self.verify_sampling {
+            sampling.validate()?;
+        }
+        if self.layout == Layout::Cas {
+            for (set, option) in [
+                (self.small_file_batching.is_some(), "small_file_batching"),
+                (self.cdc_dedup, "cdc_dedup"),
+                (self.case_collision_policy.is_some(), "case_collision_policy"),
+                (self.transfer_budget.is_some(), "transfer_budget"),
+                (self.verify_sampling.is_some(), "verify_sampling"),
+            ] {
+                if set {
+                    return Err(format!("{} is not supported with layout: cas", option).into());
+                }
+            }
+        }
+        if let Some(hasher) = &self.external_hasher {
+            if hasher.command.trim().is_empty() {
+                return Err("external_hasher.command cannot be empty".into());
+            }
+            if hasher.max_concurrent == 0 {
+                return Err("external_hasher.max_concurrent must be at least 1".into());
+            }
+        }
+        let mut listed = HashSet::new();
         for folder in &self.folders {
-            if folder.trim().is_empty() {
+            if folder.path.trim().is_empty() {
                 return Err("folder path cannot be empty".into());
             }
+            if !listed.insert(&folder.path) {
+                return Err(format!("folder '{}' is listed more than once", folder.path).into());
+            }
+            if folder.id.as_ref().is_some_and(|id| id.trim().is_empty()) {
+                return Err(format!("id of folder '{}' cannot be empty", folder.path).into());
+            }
+            if folder.extensions.as_ref().is_some_and(|e| e.is_empty()) {
+                return Err(format!(
+                    "extensions of folder '{}' cannot be empty; use excludes to skip files instead",
+                    folder.path
+                )
+                .into());
+            }
+            if FolderKind::of(Path::new(&folder.path)) == FolderKind::Unsupported {
+                warn!("Folder '{}' is neither a directory nor a regular file and will be skipped", folder.path);
+            }
+            self.validate_routes(folder)?;
+        }
+        Ok(())
+    }
+
+    /// TLS client certificate, if one is configured.
+    pub fn client_identity(&self) -> Option<ClientIdentity> {
+        if let Some(path) = &self.client_pkcs12_path {
+            return Some(ClientIdentity::Pkcs12 { path: path.into(), passphrase: self.client_pkcs12_passphrase.clone() });
+        }
+        let (cert, key) = (self.client_cert_path.as_ref()?, self.client_key_path.as_ref()?);
+        Some(ClientIdentity::Pem { cert_path: cert.into(), key_path: key.into() })
+    }
+
+    /// Credentials for the server; reads the bearer token from its source.
+    pub fn auth(&self) -> Result<Auth, Box<dyn std::error::Error>> {
+        match &self.bearer_token {
+            Some(source) => {
+                let token = source.read().map_err(|e| format!("bearer_token: {}", e))?;
+                Ok(Auth::Bearer(token))
+            }
+            None => Ok(Auth::from_credentials(self.username.as_deref(), self.password.as_deref())),
+        }
+    }
+
+    /// Stable identity of `folder`, keying its per-folder bookkeeping (e.g.
+    /// the report breakdown) instead of the local path, so that it follows the
+    /// folder to another machine. Its explicit `id`, or else a hash of where
+    /// its files land remotely: `target_dir` and the folder's own name.
+    pub fn folder_id(&self, folder: &FolderConfig) -> String {
+        if let Some(id) = &folder.id {
+            return id.clone();
+        }
+        let name = Path::new(&folder.path).file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        let prefix = format!("{}/{}", self.target_dir.trim_matches('/'), name);
+        format!("{:x}", Sha256::digest(prefix.as_bytes()))[..12].to_string()
+    }
+
+    /// Reject routes that are not a plain relative directory, or whose
+    /// `target_subdir` is also a directory in a folder, whose files would
+    /// then share remote paths with the routed ones.
+    ///
+    /// Only directories existing at load time are seen; the sync itself
+    /// detects every clash of remote paths (see `collision_policy`).
+    fn validate_routes(&self, folder: &FolderConfig) -> Result<(), Box<dyn std::error::Error>> {
+        for route in &folder.routes {
+            if route.pattern.split('|').any(|pattern| pattern.trim().is_empty()) {
+                return Err(format!("route of folder '{}' has an empty pattern in '{}'", folder.path, route.pattern).into());
+            }
+            let subdir = Path::new(route.subdir());
+            if route.subdir().is_empty() || !subdir.components().all(|c| matches!(c, Component::Normal(_))) {
+                return Err(format!(
+                    "route target_subdir '{}' of folder '{}' must be a relative directory without '..'",
+                    route.target_subdir, folder.path
+                )
+                .into());
+            }
+            if let Some(other) = self.folders.iter().find(|other| Path::new(&other.path).join(subdir).is_dir()) {
+                return Err(format!(
+                    "route target_subdir '{}' of folder '{}' is also a directory in folder '{}'; \
+                     their files would share remote paths",
+                    route.target_subdir, folder.path, other.path
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Reject folders sharing an id, which would merge their bookkeeping.
+    /// Runs after `dedupe_folders`, as spellings of one directory share it.
+    fn validate_folder_ids(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut ids = HashSet::new();
+        for folder in &self.folders {
+            if !ids.insert(self.folder_id(folder)) {
+                return Err(format!(
+                    "folder '{}' has the same id as an earlier folder; give the folders distinct ids",
+                    folder.path
+                )
+                .into());
+            }
         }
         Ok(())
     }
+
+    /// Drop folders that resolve to the same directory as an earlier entry,
+    /// e.g. through a trailing slash, a `./` prefix or a symlink.
+    pub fn dedupe_folders(&mut self) {
+        let mut seen: Vec<(PathBuf, String)> = Vec::new();
+        self.folders.retain(|folder| {
+            let key = folder_key(&folder.path);
+            if let Some((_, first)) = seen.iter().find(|(k, _)| *k == key) {
+                warn!(
+                    "Folders '{}' and '{}' are the same directory, syncing it once",
+                    first, folder.path
+                );
+                return false;
+            }
+            seen.push((key, folder.path.clone()));
+            true
+        });
+    }
+}
+
+/// `url` with the empty segments and the trailing slash of its path dropped,
+/// e.g. `https://host//remote.php/dav/files/me/` to
+/// `https://host/remote.php/dav/files/me`, so remote paths can be joined
+/// to it with one slash. Fails unless it is an http(s) URL without a query
+/// or fragment.
+pub fn normalize_webdav_url(url: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut parsed = Url::parse(url.trim()).map_err(|e| format!("webdav_url '{}' is not a URL: {}", url, e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!("webdav_url '{}' must start with http:// or https://", url).into());
+    }
+    if parsed.query().is_some() || parsed.fragment().is_some() {
+        return Err(format!("webdav_url '{}' cannot have a query or fragment", url).into());
+    }
+    let path = parsed.path().split('/').filter(|s| !s.is_empty()).collect::<Vec<_>>().join("/");
+    parsed.set_path(&path);
+    Ok(parsed.as_str().trim_end_matches('/').to_string())
+}
+
+/// Identity of a folder: its canonical path, or a lexically normalized one
+/// when the folder cannot be resolved (e.g. it does not exist yet).
+pub fn folder_key(path: &str) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| {
+        Path::new(path)
+            .components()
+            .filter(|c| *c != Component::CurDir)
+            .collect()
+    })
 }
 
 // Provide a default path for the hash store when not specified in the config file.
@@ -50,18 +818,126 @@ fn default_hash_path() -> String {
     "hashes.yaml".to_string()
 }
 
-fn default_timeout_secs() -> u64 {
-    3
+fn default_pull_state_path() -> String {
+    "pull_state.yaml".to_string()
+}
+
+fn default_upload_state_path() -> String {
+    "upload_state.yaml".to_string()
+}
+
+fn default_chunk_size_mb() -> u64 {
+    10
+}
+
+fn default_resume_uploads() -> bool {
+    true
+}
+
+fn default_pool_max_idle_per_host() -> usize {
+    PoolSettings::default().max_idle_per_host
+}
+
+fn default_lock_hash_store() -> bool {
+    true
+}
+
+fn default_hash_store_lock_wait() -> Duration {
+    Duration::from_secs(300)
+}
+
+fn default_pool_idle_timeout() -> Duration {
+    PoolSettings::default().idle_timeout
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(3)
+}
+
+fn default_connect_timeout() -> Duration {
+    PoolSettings::default().connect_timeout
+}
+
+fn default_min_upload_kbps() -> u64 {
+    PoolSettings::default().min_upload_kbps
 }
 
 fn default_target_dir() -> String {
     "".to_string()
 }
 
+fn default_create_target_dir() -> bool {
+    true
+}
+
 fn default_remote_hash_path() -> String {
     "hashes.yaml".to_string()
 }
 
+fn default_first_run_threshold() -> usize {
+    50
+}
+
+fn default_compact_ratio() -> f64 {
+    2.0
+}
+
+fn default_journal_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_finalize_retries() -> u32 {
+    3
+}
+
+fn default_verify_upload_size() -> bool {
+    true
+}
+
+fn default_max_truncated_uploads() -> usize {
+    3
+}
+
+fn default_set_content_type() -> bool {
+    true
+}
+
+fn default_send_checksum() -> bool {
+    true
+}
+
+fn default_fail_on_insufficient_quota() -> bool {
+    true
+}
+
+fn default_upload_retries() -> u32 {
+    3
+}
+
+fn default_retry_delay() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_request_retries() -> u32 {
+    3
+}
+
+fn default_retry_jitter() -> Duration {
+    Duration::from_millis(250)
+}
+
+fn default_last_modified_tolerance() -> Duration {
+    Duration::from_secs(2)
+}
+
+fn default_mtime_tolerance() -> Duration {
+    Duration::from_secs(2)
+}
+
+fn default_cdc_min_file_size() -> u64 {
+    16 * 1024 * 1024
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -85,7 +961,8 @@ folders:
         assert_eq!(config.webdav_url, "http://example.com/webdav");
         assert_eq!(config.username, Some("user".to_string()));
         assert_eq!(config.password, Some("pass".to_string()));
-        assert_eq!(config.folders, vec!["/path/to/folder1", "/path/to/folder2"]);
+        let paths: Vec<_> = config.folders.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["/path/to/folder1", "/path/to/folder2"]);
     }
 
     #[test]
@@ -158,4 +1035,482 @@ folders:
     let config = Config::load(temp_file.path()).unwrap();
     assert_eq!(config.target_dir, "");
 }
-}
\ No newline at end of file
+
+#[test]
+fn test_load_low_memory_defaults_false() {
+    let yaml = r#"
+webdav_url: "http://example.com/webdav"
+folders:
+- "/path/to/folder1"
+"#;
+    let mut temp_file = tempfile::NamedTempFile::new().unwrap();
+    write!(temp_file, "{}", yaml).unwrap();
+    let config = Config::load(temp_file.path()).unwrap();
+    assert!(!config.low_memory);
+    assert_eq!(config.memory_ceiling_mb, None);
+}
+
+#[test]
+fn test_memory_ceiling() {
+    let base = "webdav_url: https://dav.example.com\nfolders: [a]\nlow_memory: true\n";
+    assert_eq!(Config::parse(&format!("{}memory_ceiling_mb: 512\n", base)).unwrap().memory_ceiling_mb, Some(512));
+    let err = Config::parse(&format!("{}memory_ceiling_mb: 0\n", base)).unwrap_err();
+    assert_eq!(err.to_string(), "memory_ceiling_mb must be at least 1");
+}
+
+#[test]
+fn test_max_truncated_uploads() {
+    let base = "webdav_url: https://dav.example.com\nfolders: [a]\n";
+    assert_eq!(Config::parse(base).unwrap().max_truncated_uploads, 3);
+    assert_eq!(Config::parse(&format!("{}max_truncated_uploads: 10\n", base)).unwrap().max_truncated_uploads, 10);
+    let err = Config::parse(&format!("{}max_truncated_uploads: 0\n", base)).unwrap_err();
+    assert_eq!(err.to_string(), "max_truncated_uploads must be at least 1");
+}
+
+#[test]
+fn test_load_desktop_notifications() {
+    let yaml = r#"
+webdav_url: "http://example.com/webdav"
+folders:
+- "/path/to/folder1"
+desktop_notifications: failure
+"#;
+    let mut temp_file = tempfile::NamedTempFile::new().unwrap();
+    write!(temp_file, "{}", yaml).unwrap();
+    let config = Config::load(temp_file.path()).unwrap();
+    assert_eq!(config.desktop_notifications, NotifyPolicy::Failure);
+}
+
+#[test]
+fn test_load_human_durations_and_sizes() {
+    let yaml = r#"
+webdav_url: "http://example.com/webdav"
+folders:
+- "/path/to/folder1"
+timeout: 30s
+connect_timeout: 5s
+retry_delay: 500ms
+retry_jitter: 1s
+last_modified_tolerance: 1m
+mtime_tolerance: 1.5s
+archive_retention: 30d
+journal_max_bytes: 1MiB
+cdc_min_file_size: 64MB
+"#;
+    let mut temp_file = tempfile::NamedTempFile::new().unwrap();
+    write!(temp_file, "{}", yaml).unwrap();
+    let config = Config::load(temp_file.path()).unwrap();
+    assert_eq!(config.timeout, Duration::from_secs(30));
+    assert_eq!(config.connect_timeout, Duration::from_secs(5));
+    assert_eq!(config.retry_delay, Duration::from_millis(500));
+    assert_eq!(config.retry_jitter, Duration::from_secs(1));
+    assert_eq!(config.last_modified_tolerance, Duration::from_secs(60));
+    assert_eq!(config.mtime_tolerance, Duration::from_millis(1500));
+    assert_eq!(config.archive_retention, Some(Duration::from_secs(30 * 86_400)));
+    assert_eq!(config.journal_max_bytes, 1 << 20);
+    assert_eq!(config.cdc_min_file_size, 64_000_000);
+
+    let saved = serde_yaml::to_string(&config).unwrap();
+    assert!(saved.contains("timeout: 30s\n"), "{}", saved);
+    assert!(saved.contains("journal_max_bytes: 1MiB\n"), "{}", saved);
+}
+
+#[test]
+fn test_load_legacy_numeric_keys() {
+    let yaml = r#"
+webdav_url: "http://example.com/webdav"
+folders:
+- "/path/to/folder1"
+timeout_secs: 30
+retry_delay_ms: 500
+last_modified_tolerance_secs: 60
+mtime_tolerance_ms: 1500
+archive_retention_days: 30
+journal_max_bytes: 1048576
+"#;
+    let mut temp_file = tempfile::NamedTempFile::new().unwrap();
+    write!(temp_file, "{}", yaml).unwrap();
+    let config = Config::load(temp_file.path()).unwrap();
+    assert_eq!(config.timeout, Duration::from_secs(30));
+    assert_eq!(config.retry_delay, Duration::from_millis(500));
+    assert_eq!(config.last_modified_tolerance, Duration::from_secs(60));
+    assert_eq!(config.mtime_tolerance, Duration::from_millis(1500));
+    assert_eq!(config.archive_retention, Some(Duration::from_secs(30 * 86_400)));
+    assert_eq!(config.journal_max_bytes, 1 << 20);
+}
+
+#[test]
+fn test_load_rejects_unknown_duration_unit() {
+    let yaml = r#"
+webdav_url: "http://example.com/webdav"
+folders:
+- "/path/to/folder1"
+timeout: 30 fortnights
+"#;
+    let mut temp_file = tempfile::NamedTempFile::new().unwrap();
+    write!(temp_file, "{}", yaml).unwrap();
+    let err = Config::load(temp_file.path()).unwrap_err().to_string();
+    assert!(err.contains("'30 fortnights'"), "{}", err);
+}
+
+#[test]
+fn test_load_shuffle_order_and_jitter() {
+    let yaml = r#"
+webdav_url: "http://example.com/webdav"
+folders:
+- "/path/to/folder1"
+start_jitter: 10m
+upload_order: shuffle
+"#;
+    let mut temp_file = tempfile::NamedTempFile::new().unwrap();
+    write!(temp_file, "{}", yaml).unwrap();
+    let config = Config::load(temp_file.path()).unwrap();
+    assert_eq!(config.start_jitter, Duration::from_secs(600));
+    assert_eq!(config.upload_order, UploadOrder::Shuffle);
+
+    writeln!(temp_file, "low_memory: true").unwrap();
+    let err = Config::load(temp_file.path()).unwrap_err().to_string();
+    assert!(err.contains("low_memory"), "{}", err);
+}
+#[test]
+fn test_load_external_hasher() {
+    let yaml = r#"
+webdav_url: "http://example.com/webdav"
+folders:
+- "/path/to/folder1"
+external_hasher:
+  command: /usr/local/bin/appliance-sum
+  args: ["--api", "nas01"]
+  timeout: 5s
+"#;
+    let mut temp_file = tempfile::NamedTempFile::new().unwrap();
+    write!(temp_file, "{}", yaml).unwrap();
+    let config = Config::load(temp_file.path()).unwrap();
+    let hasher = config.external_hasher.unwrap();
+    assert_eq!(hasher.args, vec!["--api", "nas01"]);
+    assert_eq!(hasher.timeout, Duration::from_secs(5));
+    assert_eq!(hasher.max_concurrent, 4);
+    assert_eq!(hasher.fallback, crate::external_hasher::HasherFallback::Builtin);
+    assert!(!hasher.pseudo);
+
+    writeln!(temp_file, "  max_concurrent: 0").unwrap();
+    let err = Config::load(temp_file.path()).unwrap_err().to_string();
+    assert!(err.contains("max_concurrent"), "{}", err);
+}
+#[test]
+fn test_load_folder_extension_allowlist() {
+    let yaml = r#"
+webdav_url: "http://example.com/webdav"
+folders:
+- "/sdcard/DCIM"
+- path: "/sdcard/Music"
+  extensions: [".FLAC", "mp3", ""]
+"#;
+    let mut temp_file = tempfile::NamedTempFile::new().unwrap();
+    write!(temp_file, "{}", yaml).unwrap();
+    let config = Config::load(temp_file.path()).unwrap();
+    assert_eq!(config.folders[0].extensions, None);
+    let music = &config.folders[1];
+    assert_eq!(music.extensions, Some(vec!["flac".to_string(), "mp3".to_string(), String::new()]));
+    assert!(music.admits(Path::new("/sdcard/Music/song.FLAC")));
+    assert!(music.admits(Path::new("/sdcard/Music/a.tar.mp3")));
+    assert!(music.admits(Path::new("/sdcard/Music/README")));
+    assert!(music.admits(Path::new("/sdcard/Music/.nomedia")));
+    assert!(!music.admits(Path::new("/sdcard/Music/cover.jpg")));
+}
+
+#[test]
+fn test_load_rejects_empty_extension_list() {
+    let yaml = r#"
+webdav_url: "http://example.com/webdav"
+folders:
+- path: "/sdcard/Music"
+  extensions: []
+"#;
+    let mut temp_file = tempfile::NamedTempFile::new().unwrap();
+    write!(temp_file, "{}", yaml).unwrap();
+    let err = Config::load(temp_file.path()).unwrap_err();
+    assert!(err.to_string().contains("extensions"));
+}
+
+fn load_folders(folders: &[String]) -> Result<Config, Box<dyn std::error::Error>> {
+    let yaml = serde_yaml::to_string(&serde_json::json!({
+        "webdav_url": "http://example.com/webdav",
+        "folders": folders,
+    }))
+    .unwrap();
+    let mut temp_file = tempfile::NamedTempFile::new().unwrap();
+    write!(temp_file, "{}", yaml).unwrap();
+    Config::load(temp_file.path())
+}
+
+fn folder_paths(config: &Config) -> Vec<&str> {
+    config.folders.iter().map(|f| f.path.as_str()).collect()
+}
+
+#[test]
+fn test_exact_duplicate_folder_is_rejected() {
+    let folders = ["/data/photos".to_string(), "/data/photos".to_string()];
+    let err = load_folders(&folders).unwrap_err();
+    assert!(err.to_string().contains("more than once"));
+}
+
+#[test]
+fn test_equivalent_folder_spellings_are_deduped() {
+    let dir = tempfile::tempdir().unwrap();
+    let photos = dir.path().join("photos");
+    fs::create_dir(&photos).unwrap();
+    let photos = photos.display().to_string();
+
+    let config = load_folders(&[photos.clone(), format!("{}/", photos)]).unwrap();
+    assert_eq!(folder_paths(&config), vec![photos.as_str()]);
+
+    let config = load_folders(&[format!("{}/./", photos), photos.clone()]).unwrap();
+    assert_eq!(folder_paths(&config), vec![format!("{}/./", photos).as_str()]);
+
+    // Folders that cannot be resolved are compared lexically.
+    let config = load_folders(&["./missing".to_string(), "missing/".to_string()]).unwrap();
+    assert_eq!(folder_paths(&config), vec!["./missing"]);
+}
+
+#[test]
+fn test_symlinked_folder_is_deduped() {
+    let dir = tempfile::tempdir().unwrap();
+    let photos = dir.path().join("photos");
+    let link = dir.path().join("camera");
+    fs::create_dir(&photos).unwrap();
+    std::os::unix::fs::symlink(&photos, &link).unwrap();
+
+    let config = load_folders(&[link.display().to_string(), photos.display().to_string()]).unwrap();
+    assert_eq!(folder_paths(&config), vec![link.display().to_string().as_str()]);
+}
+
+#[test]
+fn test_folders_sharing_a_prefix_are_distinct() {
+    let dir = tempfile::tempdir().unwrap();
+    let photos = dir.path().join("photos");
+    let photos2 = dir.path().join("photos2");
+    fs::create_dir(&photos).unwrap();
+    fs::create_dir(&photos2).unwrap();
+    let folders = [photos.display().to_string(), photos2.display().to_string()];
+
+    let config = load_folders(&folders).unwrap();
+    assert_eq!(config.folders.len(), 2);
+}
+
+#[test]
+fn test_folder_id_follows_the_remote_prefix() {
+    let config: Config = serde_yaml::from_str(
+        "webdav_url: https://dav.example.com\ntarget_dir: phone/\nfolders:\n- /home/me/Pictures\n- /volume1/Pictures/\n- /home/me/Music\n- path: /home/me/Music\n  id: music\n",
+    )
+    .unwrap();
+    let ids: Vec<String> = config.folders.iter().map(|f| config.folder_id(f)).collect();
+    assert_eq!(ids[0], ids[1]);
+    assert_ne!(ids[0], ids[2]);
+    assert_eq!(ids[0].len(), 12);
+    assert_eq!(ids[3], "music");
+
+    let moved = Config { target_dir: "tablet".to_string(), ..config.clone() };
+    assert_ne!(moved.folder_id(&config.folders[0]), ids[0]);
+}
+
+#[test]
+fn test_folders_with_the_same_id_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let folders = [dir.path().join("a/Pictures"), dir.path().join("b/Pictures")];
+    let err = load_folders(&folders.clone().map(|f| f.display().to_string())).unwrap_err();
+    assert!(err.to_string().contains("distinct ids"));
+
+    let yaml = format!(
+        "webdav_url: https://dav.example.com\nfolders:\n- {}\n- path: {}\n  id: nas\n",
+        folders[0].display(),
+        folders[1].display()
+    );
+    let mut temp_file = tempfile::NamedTempFile::new().unwrap();
+    write!(temp_file, "{}", yaml).unwrap();
+    assert_eq!(Config::load(temp_file.path()).unwrap().folders.len(), 2);
+}
+
+#[test]
+fn test_routes() {
+    let config: Config = serde_yaml::from_str(
+        "webdav_url: https://dav.example.com\nfolders:\n- path: /home/me/DCIM\n  routes:\n  - match: \"*.mp4|*.mov\"\n    target_subdir: videos/\n  - match: \"Screenshots/*\"\n    target_subdir: shots\n    flatten: true\n",
+    )
+    .unwrap();
+    let folder = &config.folders[0];
+    assert_eq!(folder.route("Camera/a.mp4"), "videos/Camera/a.mp4");
+    assert_eq!(folder.route("b.mov"), "videos/b.mov");
+    assert_eq!(folder.route("Screenshots/2024/c.png"), "shots/c.png");
+    // The first matching route applies.
+    assert_eq!(folder.route("Screenshots/d.mp4"), "videos/Screenshots/d.mp4");
+    assert_eq!(folder.route("Camera/a.jpg"), "Camera/a.jpg");
+
+    assert_eq!(folder.unroute("videos/Camera/a.mp4"), Some("Camera/a.mp4"));
+    assert_eq!(folder.unroute("videos/a.jpg"), None);
+    assert_eq!(folder.unroute("shots/c.png"), None);
+}
+
+#[test]
+fn test_invalid_routes_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("videos")).unwrap();
+    let load = |route: &str| {
+        let yaml = format!(
+            "webdav_url: https://dav.example.com\nfolders:\n- path: \"{}\"\n  routes:\n  - {}\n",
+            dir.path().display(),
+            route
+        );
+        Config::parse(&yaml).map_err(|e| e.to_string())
+    };
+
+    assert!(load("{ match: \"*.mp4\", target_subdir: movies }").is_ok());
+    let err = load("{ match: \"*.mp4\", target_subdir: videos }").unwrap_err();
+    assert!(err.contains("also a directory") && err.contains("share remote paths"), "{}", err);
+    for subdir in ["\"\"", "../up", "a/../b", "\"/\""] {
+        let err = load(&format!("{{ match: \"*.mp4\", target_subdir: {} }}", subdir)).unwrap_err();
+        assert!(err.contains("must be a relative directory"), "{}: {}", subdir, err);
+    }
+    assert!(load("{ match: \"*.mp4||*.mov\", target_subdir: movies }").unwrap_err().contains("empty pattern"));
+}
+
+#[test]
+fn test_network_section() {
+    let config = Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\nnetwork:\n  prefer: ipv4\n  local_address: 192.168.1.20\n").unwrap();
+    assert_eq!(config.network.prefer, crate::network::AddressFamily::Ipv4);
+    assert_eq!(config.network.local_address, Some("192.168.1.20".parse().unwrap()));
+    assert_eq!(crate::webdav_client::PoolSettings::from_config(&config).network, config.network);
+    assert_eq!(Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\n").unwrap().network, Default::default());
+
+    let err = Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\nnetwork: { prefer: ipv6, local_address: 10.0.0.1 }\n").unwrap_err();
+    assert!(err.to_string().contains("network.local_address"), "{}", err);
+}
+
+#[test]
+fn test_bearer_token_replaces_basic_auth() {
+    let config = Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\nusername: me\nbearer_token: abc\n").unwrap();
+    assert_eq!(config.auth().unwrap(), Auth::Bearer("abc".to_string()));
+    let config = Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\nbearer_token: { env: PHONE_SYNC_CONFIG_TEST_TOKEN }\n").unwrap();
+    assert!(config.auth().unwrap_err().to_string().starts_with("bearer_token: cannot read environment variable"));
+    let config = Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\nusername: me\npassword: pw\n").unwrap();
+    assert_eq!(config.auth().unwrap(), Auth::from_credentials(Some("me"), Some("pw")));
+
+    let err = Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\npassword: pw\nbearer_token: abc\n").unwrap_err();
+    assert!(err.to_string().contains("either password or bearer_token"), "{}", err);
+}
+
+#[test]
+fn test_basic_auth_mode() {
+    let base = "webdav_url: https://dav.example.com\nfolders: [a]\nusername: me\n";
+    assert_eq!(Config::parse(base).unwrap().basic_auth, BasicAuthMode::Preemptive);
+    assert_eq!(Config::parse(&format!("{}basic_auth: challenge\n", base)).unwrap().basic_auth, BasicAuthMode::Challenge);
+
+    let err = Config::parse(&format!("{}basic_auth: challenge\nbearer_token: abc\n", base)).unwrap_err();
+    assert_eq!(err.to_string(), "basic_auth: challenge does not apply to bearer_token");
+}
+
+#[test]
+fn test_proxy_options() {
+    let config = Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\nproxy_url: http://proxy:3128\nproxy_username: me\n").unwrap();
+    let proxy = crate::webdav_client::PoolSettings::from_config(&config).proxy.unwrap();
+    assert_eq!((proxy.url.as_str(), proxy.username.as_deref(), proxy.password), ("http://proxy:3128", Some("me"), None));
+    assert_eq!(crate::webdav_client::PoolSettings::from_config(&Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\n").unwrap()).proxy, None);
+
+    let err = Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\nproxy_username: me\n").unwrap_err();
+    assert!(err.to_string().contains("need a proxy_url"), "{}", err);
+}
+
+#[test]
+fn test_header_options() {
+    let base = "webdav_url: https://dav.example.com\nfolders: [a]\n";
+    let config = Config::parse(&format!(
+        "{}user_agent: Mozilla/5.0 (phone)\nextra_headers:\n  X-Requested-With: XMLHttpRequest\n",
+        base
+    ))
+    .unwrap();
+    let headers = PoolSettings::from_config(&config).default_headers().unwrap();
+    assert_eq!(headers["user-agent"], "Mozilla/5.0 (phone)");
+    assert_eq!(headers["x-requested-with"], "XMLHttpRequest");
+    assert!(PoolSettings::from_config(&Config::parse(base).unwrap()).default_headers().unwrap().is_empty());
+
+    for (extra, message) in [
+        ("extra_headers:\n  \"X Requested\": a\n", "extra_headers: 'X Requested' is not a valid header name"),
+        ("extra_headers:\n  X-Token: \"a\\nb\"\n", "extra_headers: value of 'X-Token' is not a valid header value"),
+        ("user_agent: \"a\\rb\"\n", "user_agent is not a valid header value"),
+    ] {
+        let err = Config::parse(&format!("{}{}", base, extra)).unwrap_err().to_string();
+        assert!(err.contains(message), "{}", err);
+    }
+}
+
+#[test]
+fn test_cas_layout_rejects_mirror_only_options() {
+    let config = Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\nlayout: cas\n").unwrap();
+    assert_eq!(config.layout, Layout::Cas);
+    assert_eq!(Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\n").unwrap().layout, Layout::Mirror);
+
+    let err = Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\nlayout: cas\ncdc_dedup: true\n").unwrap_err();
+    assert_eq!(err.to_string(), "cdc_dedup is not supported with layout: cas");
+}
+
+#[test]
+fn test_tls_options() {
+    let config = Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\ninsecure_tls: true\nca_cert_path: /etc/home-ca.pem\n").unwrap();
+    let tls = crate::webdav_client::PoolSettings::from_config(&config).tls;
+    assert!(tls.insecure);
+    assert_eq!(tls.ca_cert_path, Some(PathBuf::from("/etc/home-ca.pem")));
+
+    let config = Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\n").unwrap();
+    assert_eq!(crate::webdav_client::PoolSettings::from_config(&config).tls, Default::default());
+    let saved = serde_yaml::to_string(&config).unwrap();
+    assert!(!saved.contains("insecure_tls") && !saved.contains("ca_cert_path"), "{}", saved);
+}
+
+#[test]
+fn test_client_certificate_options() {
+    let base = "webdav_url: https://dav.example.com\nfolders: [a]\n";
+    let config = Config::parse(&format!("{}client_cert_path: me.pem\nclient_key_path: me.key\n", base)).unwrap();
+    let identity = ClientIdentity::Pem { cert_path: "me.pem".into(), key_path: "me.key".into() };
+    assert_eq!(crate::webdav_client::PoolSettings::from_config(&config).tls.client_identity, Some(identity));
+
+    let config = Config::parse(&format!("{}client_pkcs12_path: me.p12\nclient_pkcs12_passphrase: pw\n", base)).unwrap();
+    let identity = ClientIdentity::Pkcs12 { path: "me.p12".into(), passphrase: Some("pw".to_string()) };
+    assert_eq!(config.client_identity(), Some(identity));
+    assert!(!format!("{:?}", config.client_identity()).contains("pw"));
+    assert_eq!(Config::parse(base).unwrap().client_identity(), None);
+
+    for (extra, message) in [
+        ("client_cert_path: me.pem\n", "need each other"),
+        ("client_key_path: me.key\n", "need each other"),
+        ("client_cert_path: me.pem\nclient_key_path: me.key\nclient_pkcs12_path: me.p12\n", "not both"),
+        ("client_pkcs12_passphrase: pw\n", "needs a client_pkcs12_path"),
+    ] {
+        let err = Config::parse(&format!("{}{}", base, extra)).unwrap_err().to_string();
+        assert!(err.contains(message), "{}", err);
+    }
+}
+
+#[test]
+fn test_webdav_url_is_normalized() {
+    for (url, expected) in [
+        ("https://dav.example.com", "https://dav.example.com"),
+        ("https://dav.example.com/", "https://dav.example.com"),
+        ("http://nas.local:8080//webdav/", "http://nas.local:8080/webdav"),
+        ("https://cloud.example.com/remote.php/dav/files/me", "https://cloud.example.com/remote.php/dav/files/me"),
+        ("https://cloud.example.com/remote.php//dav/files/me//", "https://cloud.example.com/remote.php/dav/files/me"),
+        (" https://cloud.example.com:443/dav/ ", "https://cloud.example.com/dav"),
+    ] {
+        assert_eq!(normalize_webdav_url(url).unwrap(), expected, "{}", url);
+    }
+    let config = Config::parse("webdav_url: http://nas.local:8080/webdav/\nfolders: [a]\n").unwrap();
+    assert_eq!(config.webdav_url, "http://nas.local:8080/webdav");
+
+    for (url, message) in [
+        ("dav.example.com/webdav", "is not a URL"),
+        ("ftp://dav.example.com", "http:// or https://"),
+        ("https://dav.example.com/dav?user=me", "query or fragment"),
+    ] {
+        let err = Config::parse(&format!("webdav_url: \"{}\"\nfolders: [a]\n", url)).unwrap_err().to_string();
+        assert!(err.contains(message), "{}", err);
+    }
+}
+}
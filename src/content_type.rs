@@ -0,0 +1,84 @@
+//! Content-Type of uploads, guessed from the file extension.
+//!
+//! Servers such as Nextcloud keep the Content-Type of a PUT, and show files
+//! sent without one as `application/octet-stream`: photos do not render in
+//! the web gallery and text files get no preview.
+
+use std::path::Path;
+
+/// Type of files with an unknown or no extension.
+pub const FALLBACK: &str = "application/octet-stream";
+
+/// Types by lowercase extension, for what phones and cameras produce.
+const TYPES: &[(&str, &str)] = &[
+    ("3gp", "video/3gpp"),
+    ("aac", "audio/aac"),
+    ("avi", "video/x-msvideo"),
+    ("bmp", "image/bmp"),
+    ("csv", "text/csv"),
+    ("doc", "application/msword"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("flac", "audio/flac"),
+    ("gif", "image/gif"),
+    ("gpx", "application/gpx+xml"),
+    ("heic", "image/heic"),
+    ("heif", "image/heif"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("ics", "text/calendar"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("json", "application/json"),
+    ("m4a", "audio/mp4"),
+    ("md", "text/markdown"),
+    ("mkv", "video/x-matroska"),
+    ("mov", "video/quicktime"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("oga", "audio/ogg"),
+    ("ogg", "audio/ogg"),
+    ("opus", "audio/opus"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("txt", "text/plain"),
+    ("vcf", "text/vcard"),
+    ("wav", "audio/wav"),
+    ("webm", "video/webm"),
+    ("webp", "image/webp"),
+    ("xls", "application/vnd.ms-excel"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("xml", "application/xml"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("zip", "application/zip"),
+];
+
+/// Content-Type of the file at `path`.
+pub fn for_path(path: &Path) -> &'static str {
+    let Some(extension) = path.extension().and_then(|e| e.to_str()) else {
+        return FALLBACK;
+    };
+    let extension = extension.to_ascii_lowercase();
+    TYPES
+        .binary_search_by(|(known, _)| known.cmp(&extension.as_str()))
+        .map_or(FALLBACK, |index| TYPES[index].1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_types_by_extension() {
+        assert!(TYPES.windows(2).all(|pair| pair[0].0 < pair[1].0), "TYPES must stay sorted");
+        assert_eq!(for_path(Path::new("DCIM/IMG_1.JPG")), "image/jpeg");
+        assert_eq!(for_path(Path::new("notes.txt")), "text/plain");
+        assert_eq!(for_path(Path::new("archive.tar.gz")), FALLBACK);
+        assert_eq!(for_path(Path::new("Makefile")), FALLBACK);
+    }
+}
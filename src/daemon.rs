@@ -0,0 +1,117 @@
+//! Repeated syncs for `sync --every`, e.g. as a systemd service.
+//!
+//! Between runs the process waits for the next one to be due. A `SIGHUP`
+//! (`ExecReload=kill -HUP $MAINPID`) ends the wait: the config file is read
+//! again and the next run starts. One that arrives during a run is kept until
+//! it ends. Ctrl-C stops the loop.
+
+use crate::systemd::SystemdNotifier;
+use log::{info, warn};
+use std::error::Error;
+use std::io;
+use std::time::Duration;
+
+/// Why [`Signals::wait`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wake {
+    /// The next run is due.
+    Due,
+    /// The config file is to be read again first.
+    Reload,
+    /// The process is to exit.
+    Stop,
+}
+
+/// Listens for the signals that end a wait between runs.
+pub struct Signals {
+    #[cfg(unix)]
+    hangup: tokio::signal::unix::Signal,
+}
+
+impl Signals {
+    /// Start listening. From then on `SIGHUP` no longer terminates the
+    /// process. Must be called within a Tokio runtime.
+    pub fn install() -> io::Result<Self> {
+        Ok(Signals {
+            #[cfg(unix)]
+            hangup: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?,
+        })
+    }
+
+    /// Wait up to `delay` for the next run, or less if a signal arrives (or
+    /// arrived since the last wait).
+    pub async fn wait(&mut self, delay: Duration) -> Wake {
+        #[cfg(unix)]
+        let hangup = self.hangup.recv();
+        #[cfg(not(unix))]
+        let hangup = std::future::pending::<Option<()>>();
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => Wake::Due,
+            _ = hangup => Wake::Reload,
+            _ = tokio::signal::ctrl_c() => Wake::Stop,
+        }
+    }
+}
+
+/// Read the config again with `load`, telling the service manager with
+/// `RELOADING=1` before and `READY=1` after. Returns `None` if the config
+/// cannot be loaded; the caller keeps its current one.
+pub fn reload<T>(systemd: &SystemdNotifier, load: impl FnOnce() -> Result<T, Box<dyn Error>>) -> Option<T> {
+    systemd.reloading();
+    let loaded = match load() {
+        Ok(config) => {
+            info!("Reloaded the config");
+            Some(config)
+        }
+        Err(e) => {
+            warn!("Cannot reload the config, keeping the current one: {}", e);
+            None
+        }
+    };
+    systemd.ready();
+    loaded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    fn received(receiver: &UnixDatagram, count: usize) -> Vec<String> {
+        let mut buf = [0u8; 256];
+        (0..count)
+            .map(|_| {
+                let n = receiver.recv(&mut buf).unwrap();
+                String::from_utf8_lossy(&buf[..n]).to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reload_is_announced_to_the_service_manager() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let systemd = SystemdNotifier::at(Some(path.as_os_str()));
+
+        assert_eq!(reload(&systemd, || Ok(2)), Some(2));
+        assert_eq!(received(&receiver, 2), ["RELOADING=1\nSTATUS=Reloading the config", "READY=1\nSTATUS=Syncing"]);
+
+        // A broken config keeps the service running on the current one.
+        assert_eq!(reload::<u8>(&systemd, || Err("bad yaml".into())), None);
+        assert_eq!(received(&receiver, 2), ["RELOADING=1\nSTATUS=Reloading the config", "READY=1\nSTATUS=Syncing"]);
+    }
+
+    #[tokio::test]
+    async fn test_hangup_ends_the_wait_for_a_reload() {
+        let mut signals = Signals::install().unwrap();
+        assert_eq!(signals.wait(Duration::from_millis(10)).await, Wake::Due);
+
+        let status = std::process::Command::new("kill").args(["-HUP", &std::process::id().to_string()]).status().unwrap();
+        assert!(status.success());
+        let wake = tokio::time::timeout(Duration::from_secs(10), signals.wait(Duration::from_secs(3600))).await;
+        assert_eq!(wake.unwrap(), Wake::Reload);
+        // Handled once, the next wait runs its course.
+        assert_eq!(signals.wait(Duration::from_millis(10)).await, Wake::Due);
+    }
+}
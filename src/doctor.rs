@@ -0,0 +1,30 @@
+//! The `doctor` command: what the server supports and whether the remote
+//! tree of a profile is ready for a sync.
+
+use crate::capabilities::ServerCapabilities;
+use crate::config::Config;
+use crate::output::HumanDisplay;
+use crate::target_dir::TargetDirCheck;
+use crate::webdav_client::WebDavClient;
+use serde::Serialize;
+use std::error::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DoctorReport {
+    #[serde(flatten)]
+    pub capabilities: ServerCapabilities,
+    pub target_dir: TargetDirCheck,
+}
+
+pub async fn doctor(client: &WebDavClient, config: &Config) -> Result<DoctorReport, Box<dyn Error>> {
+    Ok(DoctorReport {
+        capabilities: client.capabilities().await?,
+        target_dir: TargetDirCheck::run(client, config).await,
+    })
+}
+
+impl HumanDisplay for DoctorReport {
+    fn human(&self) -> String {
+        format!("{}\n{}", self.capabilities.human(), self.target_dir.human())
+    }
+}
@@ -0,0 +1,161 @@
+//! Resolving the configuration a run uses, and where each setting came from.
+//!
+//! A setting comes from the config file (possibly under a deprecated alias),
+//! from a command line flag overriding it, or from its default. [`resolve`]
+//! is the only way commands load their configuration, so `config show`
+//! prints exactly what a run would use.
+
+use crate::config::Config;
+use crate::output::HumanDisplay;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Deprecated names still accepted for a setting, as (alias, setting).
+pub const DEPRECATED_ALIASES: [(&str, &str); 5] = [
+    ("timeout_secs", "timeout"),
+    ("archive_retention_days", "archive_retention"),
+    ("retry_delay_ms", "retry_delay"),
+    ("last_modified_tolerance_secs", "last_modified_tolerance"),
+    ("mtime_tolerance_ms", "mtime_tolerance"),
+];
+
+/// Settings printed redacted when written inline; a secret read from an
+/// environment variable or file shows where it comes from.
+const SECRETS: [&str; 4] = ["password", "bearer_token", "proxy_password", "client_pkcs12_passphrase"];
+
+/// Where the value of a setting came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    File,
+    /// The config file, under this deprecated name.
+    Alias(&'static str),
+    Cli,
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::File => write!(f, "file"),
+            Source::Alias(alias) => write!(f, "file, deprecated alias {}", alias),
+            Source::Cli => write!(f, "cli"),
+            Source::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// Command line flags that override config settings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Overrides {
+    /// `--read-only`; only ever turns read-only mode on.
+    pub read_only: bool,
+}
+
+/// The effective configuration with the source of every top-level setting.
+#[derive(Debug, Clone)]
+pub struct Resolved {
+    pub config: Config,
+    pub sources: BTreeMap<String, Source>,
+}
+
+impl Resolved {
+    pub fn source(&self, setting: &str) -> Source {
+        self.sources.get(setting).copied().unwrap_or(Source::Default)
+    }
+
+    /// Every setting in config order with its source, secrets replaced by
+    /// `***`.
+    pub fn settings(&self) -> Result<EffectiveSettings, Box<dyn Error>> {
+        let Value::Mapping(settings) = serde_yaml::to_value(&self.config)? else {
+            return Err("configuration does not serialize to a mapping".into());
+        };
+        let settings = settings
+            .into_iter()
+            .map(|(key, mut value)| {
+                let name = key.as_str().unwrap_or_default().to_string();
+                if SECRETS.contains(&name.as_str()) && value.is_string() {
+                    value = Value::String("***".to_string());
+                }
+                let source = self.source(&name).to_string();
+                Setting { name, value, source }
+            })
+            .collect();
+        Ok(EffectiveSettings { settings })
+    }
+
+    /// The effective configuration as YAML, each setting annotated with its
+    /// source and secrets replaced by `***`.
+    pub fn render(&self) -> Result<String, Box<dyn Error>> {
+        Ok(self.settings()?.human())
+    }
+}
+
+/// One setting of [`EffectiveSettings`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Setting {
+    pub name: String,
+    pub value: Value,
+    /// [`Source`] as text, e.g. `file`.
+    pub source: String,
+}
+
+/// The effective configuration as printed by `config show`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EffectiveSettings {
+    pub settings: Vec<Setting>,
+}
+
+impl HumanDisplay for EffectiveSettings {
+    /// Config YAML, each setting followed by its source as a comment.
+    fn human(&self) -> String {
+        let mut out = String::new();
+        for setting in &self.settings {
+            let mut single = Mapping::new();
+            single.insert(Value::String(setting.name.clone()), setting.value.clone());
+            let yaml = serde_yaml::to_string(&single).expect("a YAML value serializes");
+            let (first, rest) = yaml.split_once('\n').unwrap_or((&yaml, ""));
+            out.push_str(&format!("{}  # {}\n{}", first, setting.source, rest));
+        }
+        out
+    }
+}
+
+/// Load the config file at `path` and apply `overrides`.
+pub fn resolve<P: AsRef<Path>>(path: P, overrides: &Overrides) -> Result<Resolved, Box<dyn Error>> {
+    let content = fs::read_to_string(path)?;
+    let mut config = Config::parse(&content)?;
+    let mut sources = BTreeMap::new();
+    if let Ok(Value::Mapping(file)) = serde_yaml::from_str::<Value>(&content) {
+        for key in file.keys().filter_map(Value::as_str) {
+            match DEPRECATED_ALIASES.iter().find(|(alias, _)| *alias == key) {
+                Some((alias, setting)) => sources.insert(setting.to_string(), Source::Alias(alias)),
+                None => sources.insert(key.to_string(), Source::File),
+            };
+        }
+    }
+    if overrides.read_only {
+        config.read_only = true;
+        sources.insert("read_only".to_string(), Source::Cli);
+    }
+    Ok(Resolved { config, sources })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deprecated_aliases_are_accepted() {
+        let defaults = serde_yaml::to_value(Config::parse("webdav_url: https://dav.example.com\nfolders: [a]\n").unwrap()).unwrap();
+        for (alias, setting) in DEPRECATED_ALIASES {
+            let yaml = format!("webdav_url: https://dav.example.com\nfolders: [a]\n{}: 7\n", alias);
+            let value = serde_yaml::to_value(Config::parse(&yaml).unwrap()).unwrap();
+            assert_ne!(value[setting], defaults[setting], "{} does not set {}", alias, setting);
+        }
+    }
+}
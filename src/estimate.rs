@@ -0,0 +1,288 @@
+//! Estimating how much a sync would upload from a random sample of files.
+//!
+//! `estimate` walks the folders without statting anything, keeps a uniform
+//! random sample of the file paths (reservoir sampling), and only stats and,
+//! where the stamp cannot decide, hashes the sampled files. Upload count and
+//! bytes are extrapolated to all walked files with a normal-approximation
+//! confidence interval, corrected for sampling without replacement.
+
+use crate::artifacts::SyncArtifacts;
+use crate::config::Config;
+use crate::external_hasher::FileHasher;
+use crate::file_stamp::{FileStamp, StampMatch};
+use crate::filter::FilterSet;
+use crate::folder_kind;
+use crate::hash_store::HashStore;
+use crate::nomedia::NomediaFilter;
+use crate::output::HumanDisplay;
+use crate::plan::{decide, Decision, FileFacts, Policy, RemoteFacts};
+use crate::spread::Rng;
+use crate::sync::{folder_files, remote_path_in};
+use crate::units::format_byte_size;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Two-sided 95% quantile of the standard normal distribution.
+const Z_95: f64 = 1.959_964;
+
+/// Keeps a uniform random sample of at most `capacity` of the offered items
+/// (Algorithm R).
+#[derive(Debug)]
+pub struct Reservoir<T> {
+    capacity: usize,
+    seen: u64,
+    items: Vec<T>,
+}
+
+impl<T> Reservoir<T> {
+    pub fn new(capacity: usize) -> Self {
+        Reservoir { capacity, seen: 0, items: Vec::new() }
+    }
+
+    pub fn offer(&mut self, item: T, rng: &mut dyn Rng) {
+        self.seen += 1;
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+        } else {
+            let slot = rng.below(self.seen);
+            if let Some(kept) = self.items.get_mut(slot as usize) {
+                *kept = item;
+            }
+        }
+    }
+
+    /// Number of items offered so far.
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+
+    pub fn into_items(self) -> Vec<T> {
+        self.items
+    }
+}
+
+/// An extrapolated total with its 95% confidence interval.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Estimate {
+    pub value: f64,
+    pub low: f64,
+    pub high: f64,
+}
+
+impl Estimate {
+    pub fn contains(&self, actual: f64) -> bool {
+        self.low <= actual && actual <= self.high
+    }
+}
+
+/// Estimate the total of a population of `population` values from a simple
+/// random sample of them. The interval is exact (zero width) when the sample
+/// is the whole population, and never extends below zero.
+pub fn estimate_total(sample: &[f64], population: u64) -> Estimate {
+    let n = sample.len() as f64;
+    let size = population as f64;
+    if sample.is_empty() {
+        return Estimate { value: 0.0, low: 0.0, high: 0.0 };
+    }
+    let mean = sample.iter().sum::<f64>() / n;
+    let variance = if sample.len() > 1 {
+        sample.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0)
+    } else {
+        0.0
+    };
+    let finite_population = (1.0 - n / size).max(0.0);
+    let margin = Z_95 * size * (variance / n * finite_population).sqrt();
+    let value = mean * size;
+    Estimate { value, low: (value - margin).max(0.0), high: value + margin }
+}
+
+/// Extrapolated outcome of a sync.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EstimateReport {
+    /// Files found by the walk, before filters.
+    pub walked: u64,
+    /// Files statted and checked.
+    pub sampled: usize,
+    /// Sampled files that would be uploaded.
+    pub sampled_uploads: usize,
+    pub upload_files: Estimate,
+    pub upload_bytes: Estimate,
+}
+
+impl HumanDisplay for EstimateReport {
+    fn human(&self) -> String {
+        let exact = self.sampled as u64 == self.walked;
+        let mut out = format!(
+            "Estimate from {} of {} files{}:\n  would upload: ~{:.0} files ({:.0}-{:.0}), ~{} ({}-{})",
+            self.sampled,
+            self.walked,
+            if exact { " (all files sampled, exact)" } else { ", 95% interval" },
+            self.upload_files.value,
+            self.upload_files.low,
+            self.upload_files.high,
+            format_byte_size(self.upload_bytes.value.round() as u64),
+            format_byte_size(self.upload_bytes.low.round() as u64),
+            format_byte_size(self.upload_bytes.high.round() as u64),
+        );
+        if !exact && self.sampled_uploads < 10 {
+            out.push_str("\n  few sampled files need uploading, so the interval is unreliable; sample more");
+        }
+        out
+    }
+}
+
+/// Estimate what a sync of the folders would upload, checking a random
+/// sample of up to `sample_size` files against `store`.
+pub async fn estimate(
+    config: &Config,
+    store: &HashStore,
+    filters: &FilterSet,
+    sample_size: usize,
+    rng: &mut dyn Rng,
+) -> Result<EstimateReport, Box<dyn Error>> {
+    let artifacts = SyncArtifacts::from_config(config);
+    let mut reservoir: Reservoir<(usize, PathBuf)> = Reservoir::new(sample_size);
+    let nomedia = NomediaFilter::from_config(config);
+    for (index, folder) in config.folders.iter().enumerate() {
+        let folder_path = Path::new(&folder.path);
+        if !folder_path.exists() {
+            continue;
+        }
+        // The lazy walk only reads directories; nothing is statted yet.
+        for entry in folder_files(folder_path, true, &nomedia) {
+            if !artifacts.contains(entry.file_name()) {
+                reservoir.offer((index, entry.into_path()), rng);
+            }
+        }
+    }
+
+    let walked = reservoir.seen();
+    let hasher = FileHasher::from_config(config);
+    let (mut counts, mut bytes) = (Vec::new(), Vec::new());
+    for (index, local_path) in reservoir.into_items() {
+        let folder = &config.folders[index];
+        let metadata = std::fs::metadata(&local_path)?;
+        let relative_path = folder_kind::relative_path(Path::new(&folder.path), &local_path)
+            .ok_or_else(|| format!("{} is not in folder {}", local_path.display(), folder.path))?
+            .to_string_lossy()
+            .to_string();
+        let selected = folder.admits(&local_path) && filters.matches(&relative_path, &metadata);
+        let upload = selected && would_upload(config, store, &hasher, &local_path, &remote_path_in(config, folder, &relative_path), &metadata).await?;
+        counts.push(if upload { 1.0 } else { 0.0 });
+        bytes.push(if upload { metadata.len() as f64 } else { 0.0 });
+    }
+
+    Ok(EstimateReport {
+        walked,
+        sampled: counts.len(),
+        sampled_uploads: counts.iter().filter(|c| **c > 0.0).count(),
+        upload_files: estimate_total(&counts, walked),
+        upload_bytes: estimate_total(&bytes, walked),
+    })
+}
+
+/// Whether sync would upload the file, taking the remote copy to be as the
+/// store records it.
+async fn would_upload(
+    config: &Config,
+    store: &HashStore,
+    hasher: &FileHasher,
+    local_path: &Path,
+    remote_path: &str,
+    metadata: &std::fs::Metadata,
+) -> Result<bool, Box<dyn Error>> {
+    let policy = Policy { read_only: false, last_modified_tolerance: config.last_modified_tolerance };
+    let mut facts = FileFacts {
+        size: metadata.len(),
+        hash: None,
+        stored_hash: store.regular_hashes.get(remote_path).map(String::as_str),
+        remote: RemoteFacts::Unchecked,
+        stored_fingerprint: None,
+    };
+    if decide(&facts, &policy) != Decision::NeedHash {
+        return Ok(true);
+    }
+    // An unchanged stamp vouches for the stored hash.
+    let unchanged = match (store.stamps.get(remote_path), FileStamp::of(metadata)) {
+        (Some(stored), Some(current)) => stored.compare(&current, config.mtime_tolerance) == StampMatch::Unchanged,
+        _ => false,
+    };
+    let hash = match facts.stored_hash {
+        Some(stored) if unchanged => stored.to_string(),
+        _ => hasher.compute(local_path, false).await?,
+    };
+    facts.hash = Some(&hash);
+    Ok(decide(&facts, &policy) != Decision::Skip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spread::SplitMix64;
+
+    #[test]
+    fn test_reservoir_sample_is_uniform() {
+        let mut rng = SplitMix64::new(42);
+        let mut hits = [0u32; 100];
+        for _ in 0..2000 {
+            let mut reservoir = Reservoir::new(10);
+            for item in 0..100 {
+                reservoir.offer(item, &mut rng);
+            }
+            assert_eq!(reservoir.seen(), 100);
+            for item in reservoir.into_items() {
+                hits[item] += 1;
+            }
+        }
+        // Every item is kept with probability 10/100, i.e. ~200 times.
+        assert!(hits.iter().all(|&h| (140..=260).contains(&h)), "{:?}", hits);
+    }
+
+    #[test]
+    fn test_reservoir_keeps_everything_below_capacity() {
+        let mut reservoir = Reservoir::new(10);
+        for item in 0..4 {
+            reservoir.offer(item, &mut SplitMix64::new(1));
+        }
+        assert_eq!(reservoir.into_items(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_interval_covers_the_total_about_95_percent_of_the_time() {
+        // 10 000 files, 30% of them with sizes between 1 and 1000.
+        let mut rng = SplitMix64::new(7);
+        let population: Vec<f64> =
+            (0..10_000).map(|i| if i % 10 < 3 { (rng.below(1000) + 1) as f64 } else { 0.0 }).collect();
+        let total: f64 = population.iter().sum();
+        let count = population.iter().filter(|v| **v > 0.0).count() as f64;
+
+        let trials = 400;
+        let (mut bytes_covered, mut count_covered) = (0, 0);
+        for _ in 0..trials {
+            let mut reservoir = Reservoir::new(400);
+            for value in &population {
+                reservoir.offer(*value, &mut rng);
+            }
+            let sample = reservoir.into_items();
+            let indicators: Vec<f64> = sample.iter().map(|v| if *v > 0.0 { 1.0 } else { 0.0 }).collect();
+            bytes_covered += estimate_total(&sample, 10_000).contains(total) as u32;
+            count_covered += estimate_total(&indicators, 10_000).contains(count) as u32;
+        }
+        for covered in [bytes_covered, count_covered] {
+            let rate = f64::from(covered) / f64::from(trials);
+            assert!((0.90..=0.99).contains(&rate), "coverage {}", rate);
+        }
+    }
+
+    #[test]
+    fn test_full_sample_is_exact() {
+        let values = [0.0, 5.0, 0.0, 7.0];
+        let estimate = estimate_total(&values, 4);
+        assert_eq!(estimate, Estimate { value: 12.0, low: 12.0, high: 12.0 });
+        assert_eq!(estimate_total(&[], 0).value, 0.0);
+        // Without sampled uploads the interval collapses; the report warns about that.
+        let none = estimate_total(&[0.0; 50], 1000);
+        assert_eq!((none.value, none.low, none.high), (0.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,148 @@
+//! Hashing files with an external command.
+//!
+//! Some storage can report the SHA-256 of a file far cheaper than reading it,
+//! e.g. an appliance exporting checksums through its own API. With
+//! `external_hasher` configured, the command is run as
+//! `command [args...] <path> <mode>` (mode `sha256` or `pseudo`) and the first
+//! word of its output is taken as the hex digest. The digest is stored exactly
+//! like a locally computed one.
+
+use crate::config::Config;
+use crate::hash_store::HashStore;
+use crate::units::{duration_secs_compat, format_duration};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+
+/// What to do when the command fails, times out or prints no digest.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HasherFallback {
+    /// Log a warning and hash the file locally.
+    #[default]
+    Builtin,
+    /// Fail the run.
+    Error,
+}
+
+/// The `external_hasher` section of the config.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ExternalHasherConfig {
+    pub command: String,
+    /// Arguments placed before the file path and mode.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Longest a single invocation may run before it is killed, e.g. `30s`.
+    #[serde(default = "default_timeout", with = "duration_secs_compat")]
+    pub timeout: Duration,
+    /// Invocations running at the same time.
+    #[serde(default = "default_max_concurrent")]
+    pub max_concurrent: usize,
+    #[serde(default)]
+    pub fallback: HasherFallback,
+    /// Also use the command for pseudo hashes; otherwise they are always
+    /// computed locally.
+    #[serde(default)]
+    pub pseudo: bool,
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_max_concurrent() -> usize {
+    4
+}
+
+/// Computes file hashes, through the external command when one is configured.
+#[derive(Debug, Clone, Default)]
+pub struct FileHasher {
+    external: Option<ExternalHasher>,
+}
+
+#[derive(Debug, Clone)]
+struct ExternalHasher {
+    config: ExternalHasherConfig,
+    permits: Arc<Semaphore>,
+}
+
+impl FileHasher {
+    pub fn from_config(config: &Config) -> Self {
+        let external = config.external_hasher.clone().map(|config| ExternalHasher {
+            permits: Arc::new(Semaphore::new(config.max_concurrent)),
+            config,
+        });
+        FileHasher { external }
+    }
+
+    /// The regular or pseudo hash of a file, like [`HashStore::compute`].
+    pub async fn compute(&self, path: &Path, pseudo: bool) -> Result<String, Box<dyn Error>> {
+        if let Some(external) = self.external.as_ref().filter(|e| !pseudo || e.config.pseudo) {
+            match external.run(path, pseudo).await {
+                Ok(digest) => return Ok(digest),
+                Err(e) if external.config.fallback == HasherFallback::Builtin => {
+                    warn!("External hasher failed for {}, hashing it locally: {}", path.display(), e);
+                }
+                Err(e) => return Err(format!("External hasher failed for {}: {}", path.display(), e).into()),
+            }
+        }
+        HashStore::compute(path, pseudo).await
+    }
+}
+
+impl ExternalHasher {
+    async fn run(&self, path: &Path, pseudo: bool) -> Result<String, Box<dyn Error>> {
+        let _permit = self.permits.acquire().await?;
+        let child = Command::new(&self.config.command)
+            .args(&self.config.args)
+            .arg(path)
+            .arg(if pseudo { "pseudo" } else { "sha256" })
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("cannot run '{}': {}", self.config.command, e))?;
+        // Dropping the timed out future kills the child.
+        let output = tokio::time::timeout(self.config.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| format!("timed out after {}", format_duration(self.config.timeout)))??;
+        if !output.status.success() {
+            return Err(format!("{}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()).into());
+        }
+        parse_digest(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+/// The SHA-256 hex digest at the start of `output`, in lowercase. Anything
+/// after the first word (like the file name printed by `sha256sum`) is ignored.
+pub fn parse_digest(output: &str) -> Result<String, Box<dyn Error>> {
+    let word = output.split_whitespace().next().unwrap_or("");
+    if word.len() != 64 || !word.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("malformed output '{}', expected a SHA-256 hex digest", output.trim()).into());
+    }
+    Ok(word.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    #[test]
+    fn test_parse_digest() {
+        assert_eq!(parse_digest(DIGEST).unwrap(), DIGEST);
+        assert_eq!(parse_digest(&format!("{}  /data/a.jpg\n", DIGEST.to_uppercase())).unwrap(), DIGEST);
+        assert!(parse_digest("").is_err());
+        assert!(parse_digest("not a digest").is_err());
+        assert!(parse_digest(&DIGEST[1..]).is_err());
+        assert!(parse_digest(&format!("{}0", DIGEST)).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! Size and modification time of local files, used to skip rehashing.
+//!
+//! Filesystems differ in how precisely they store mtimes (nanoseconds on
+//! ext4, two seconds on FAT/exFAT), so copied files can come back with
+//! rounded timestamps. Stamps are stored at full precision and compared with
+//! a tolerance; differences within the tolerance are resolved by hashing.
+
+use serde::{Deserialize, Serialize};
+use std::fs::Metadata;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Files observed before the apparent mtime granularity is reported.
+const GRANULARITY_SAMPLES: usize = 20;
+
+/// Size and mtime (nanoseconds since the epoch) of a local file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub size: u64,
+    pub mtime_ns: u64,
+}
+
+/// Result of comparing a stored stamp with the current one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StampMatch {
+    /// Same size and exactly the same mtime; the stored hash can be trusted.
+    Unchanged,
+    /// Same size, mtime differs within the tolerance; hash to decide.
+    Ambiguous,
+    Changed,
+}
+
+impl FileStamp {
+    pub fn of(metadata: &Metadata) -> Option<Self> {
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(FileStamp { size: metadata.len(), mtime_ns: u64::try_from(mtime.as_nanos()).ok()? })
+    }
+
+    /// Compare the stored stamp `self` with the `current` one.
+    pub fn compare(&self, current: &FileStamp, tolerance: Duration) -> StampMatch {
+        if self.size != current.size {
+            StampMatch::Changed
+        } else if self.mtime_ns == current.mtime_ns {
+            StampMatch::Unchanged
+        } else if u128::from(self.mtime_ns.abs_diff(current.mtime_ns)) <= tolerance.as_nanos() {
+            StampMatch::Ambiguous
+        } else {
+            StampMatch::Changed
+        }
+    }
+}
+
+/// Guesses the mtime granularity of a filesystem from sampled files.
+#[derive(Debug, Default)]
+pub struct GranularityProbe {
+    samples: usize,
+    /// Whether any sample had a sub-second part.
+    sub_second: bool,
+    /// Whether any sample had an odd number of seconds.
+    odd_second: bool,
+}
+
+impl GranularityProbe {
+    pub fn observe(&mut self, stamp: &FileStamp) {
+        self.samples += 1;
+        self.sub_second |= !stamp.mtime_ns.is_multiple_of(1_000_000_000);
+        self.odd_second |= !(stamp.mtime_ns / 1_000_000_000).is_multiple_of(2);
+    }
+
+    /// The apparent granularity, if enough files were seen and it is coarser
+    /// than a second's fraction.
+    pub fn coarse_granularity(&self) -> Option<Duration> {
+        if self.samples < GRANULARITY_SAMPLES || self.sub_second {
+            None
+        } else if self.odd_second {
+            Some(Duration::from_secs(1))
+        } else {
+            Some(Duration::from_secs(2))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: Duration = Duration::from_millis(2000);
+
+    fn stamp(size: u64, mtime_ns: u64) -> FileStamp {
+        FileStamp { size, mtime_ns }
+    }
+
+    #[test]
+    fn test_compare_matrix() {
+        let stored = stamp(10, 1_000_000_000_123);
+        assert_eq!(stored.compare(&stamp(10, 1_000_000_000_123), TOLERANCE), StampMatch::Unchanged);
+        // Truncated to whole (even) seconds by a FAT filesystem.
+        assert_eq!(stored.compare(&stamp(10, 1_000_000_000_000), TOLERANCE), StampMatch::Ambiguous);
+        assert_eq!(stored.compare(&stamp(10, 1_002_000_000_123), TOLERANCE), StampMatch::Ambiguous);
+        assert_eq!(stored.compare(&stamp(10, 1_002_000_000_124), TOLERANCE), StampMatch::Changed);
+        assert_eq!(stored.compare(&stamp(11, 1_000_000_000_123), TOLERANCE), StampMatch::Changed);
+        assert_eq!(stored.compare(&stamp(10, 999_000_000_000), Duration::ZERO), StampMatch::Changed);
+    }
+
+    #[test]
+    fn test_granularity_probe() {
+        let probe_of = |mtimes: &[u64]| {
+            let mut probe = GranularityProbe::default();
+            for _ in 0..GRANULARITY_SAMPLES {
+                for &m in mtimes {
+                    probe.observe(&stamp(1, m));
+                }
+            }
+            probe.coarse_granularity()
+        };
+        assert_eq!(probe_of(&[2_000_000_000, 4_000_000_000]), Some(Duration::from_secs(2)));
+        assert_eq!(probe_of(&[2_000_000_000, 3_000_000_000]), Some(Duration::from_secs(1)));
+        assert_eq!(probe_of(&[2_000_000_000, 3_000_000_001]), None);
+
+        let mut few = GranularityProbe::default();
+        few.observe(&stamp(1, 2_000_000_000));
+        assert_eq!(few.coarse_granularity(), None);
+    }
+}
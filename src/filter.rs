@@ -0,0 +1,176 @@
+//! File selection shared by the commands that walk local folders.
+//!
+//! A [`FilterSet`] is evaluated against a path relative to its configured
+//! folder before any hashing or network work happens for the file.
+
+use std::fs::Metadata;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Which files a command should process. The default selects everything.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FilterSet {
+    /// Folder-relative path prefixes; empty selects all paths.
+    pub only: Vec<String>,
+    /// Only files modified at or after this time.
+    pub since: Option<SystemTime>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Wildcard patterns (`*`, `?`) of relative paths to skip.
+    pub exclude: Vec<String>,
+}
+
+impl FilterSet {
+    /// Whether the file at `relative_path` with `metadata` is selected.
+    pub fn matches(&self, relative_path: &str, metadata: &Metadata) -> bool {
+        let path = relative_path.replace('\\', "/");
+        if !self.only.is_empty() && !self.only.iter().any(|prefix| is_below(&path, prefix)) {
+            return false;
+        }
+        if self.exclude.iter().any(|pattern| wildcard_match(pattern, &path)) {
+            return false;
+        }
+        let size = metadata.len();
+        if self.min_size.is_some_and(|min| size < min) || self.max_size.is_some_and(|max| size > max) {
+            return false;
+        }
+        match (self.since, metadata.modified()) {
+            (Some(since), Ok(modified)) => modified >= since,
+            _ => true,
+        }
+    }
+}
+
+/// Whether `path` is `prefix` or lies below it; an empty prefix covers
+/// everything.
+pub fn is_below(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_matches('/');
+    prefix.is_empty()
+        || path == prefix
+        || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Match `text` against a pattern where `*` matches any run of characters
+/// (including `/`) and `?` matches exactly one.
+pub(crate) fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star, matched)) = backtrack {
+            pi = star + 1;
+            ti = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// Parse a `--since` value: a date (`YYYY-MM-DD`, UTC) or an age such as
+/// `30d` or `12h`, counted back from `now`.
+pub fn parse_since(value: &str, now: SystemTime) -> Result<SystemTime, String> {
+    let invalid = || format!("Invalid --since '{}', expected YYYY-MM-DD or an age like 30d", value);
+    if let Some((number, unit)) = value.split_at_checked(value.len().saturating_sub(1)) {
+        let secs = match unit {
+            "d" => Some(86_400),
+            "h" => Some(3_600),
+            "m" => Some(60),
+            _ => None,
+        };
+        if let (Some(secs), Ok(n)) = (secs, number.parse::<u64>()) {
+            return now.checked_sub(Duration::from_secs(n * secs)).ok_or_else(invalid);
+        }
+    }
+    let parts: Vec<&str> = value.split('-').collect();
+    let [year, month, day] = parts.as_slice() else {
+        return Err(invalid());
+    };
+    let (year, month, day): (i64, i64, i64) = (
+        year.parse().map_err(|_| invalid())?,
+        month.parse().map_err(|_| invalid())?,
+        day.parse().map_err(|_| invalid())?,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    // Days-from-civil conversion, see
+    // https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    let secs = u64::try_from(days * 86_400).map_err(|_| invalid())?;
+    Ok(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata_of(content: &[u8]) -> (tempfile::NamedTempFile, Metadata) {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), content).unwrap();
+        let metadata = file.path().metadata().unwrap();
+        (file, metadata)
+    }
+
+    #[test]
+    fn test_only_and_exclude() {
+        let (_file, meta) = metadata_of(b"x");
+        let filters = FilterSet {
+            only: vec!["DCIM/".to_string()],
+            exclude: vec!["*.tmp".to_string(), "DCIM/.thumb*".to_string()],
+            ..Default::default()
+        };
+        assert!(filters.matches("DCIM/a.jpg", &meta));
+        assert!(filters.matches("DCIM\\Camera\\b.jpg", &meta));
+        assert!(!filters.matches("DCIMX/a.jpg", &meta));
+        assert!(!filters.matches("Music/a.mp3", &meta));
+        assert!(!filters.matches("DCIM/sub/a.tmp", &meta));
+        assert!(!filters.matches("DCIM/.thumbnails/x.jpg", &meta));
+        assert!(FilterSet::default().matches("anything", &meta));
+    }
+
+    #[test]
+    fn test_size_and_since() {
+        let (_file, meta) = metadata_of(b"12345");
+        let size = |min, max| FilterSet { min_size: min, max_size: max, ..Default::default() };
+        assert!(size(Some(5), Some(5)).matches("a", &meta));
+        assert!(!size(Some(6), None).matches("a", &meta));
+        assert!(!size(None, Some(4)).matches("a", &meta));
+
+        let modified = meta.modified().unwrap();
+        let since = |t| FilterSet { since: Some(t), ..Default::default() };
+        assert!(since(modified - Duration::from_secs(60)).matches("a", &meta));
+        assert!(!since(modified + Duration::from_secs(60)).matches("a", &meta));
+    }
+
+    #[test]
+    fn test_parse_since() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_709_251_200); // 2024-03-01
+        assert_eq!(parse_since("2024-03-01", now), Ok(now));
+        assert_eq!(parse_since("2d", now), Ok(now - Duration::from_secs(2 * 86_400)));
+        assert_eq!(parse_since("1970-01-01", now), Ok(UNIX_EPOCH));
+        assert!(parse_since("2024-13-01", now).is_err());
+        assert!(parse_since("yesterday", now).is_err());
+        assert!(parse_since("", now).is_err());
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.jpg", "a/b/c.jpg"));
+        assert!(wildcard_match("a?c", "abc"));
+        assert!(!wildcard_match("a?c", "ac"));
+        assert!(wildcard_match("*a*b", "xxaxxb"));
+        assert!(!wildcard_match("*a*b", "xxaxxc"));
+    }
+}
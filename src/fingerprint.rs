@@ -0,0 +1,250 @@
+//! Fingerprints of remote files, used to notice changes made on the server.
+//!
+//! Servers that send ETags are compared by ETag. Servers without them fall
+//! back to Last-Modified plus size, compared with a tolerance because those
+//! timestamps only have second resolution and may be rounded by the server.
+
+use crate::propfind::RemoteEntry;
+use log::warn;
+use reqwest::header::{HeaderMap, CONTENT_LENGTH, ETAG, LAST_MODIFIED};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Set once the weak validator warning has been logged.
+static WEAK_VALIDATOR_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// What the server told us about the current version of a remote file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemoteFingerprint {
+    Etag(String),
+    /// Last-Modified as Unix seconds, plus the size in bytes.
+    ModifiedSize { last_modified: u64, size: u64 },
+    /// The server provided nothing usable.
+    None,
+}
+
+impl RemoteFingerprint {
+    /// Build a fingerprint from raw property values, as found in response
+    /// headers or in the `getetag`, `getlastmodified`, and `getcontentlength`
+    /// properties of a PROPFIND response.
+    pub fn from_properties(etag: Option<&str>, last_modified: Option<&str>, size: Option<u64>) -> Self {
+        if let Some(etag) = etag.map(str::trim).filter(|e| !e.is_empty()) {
+            return RemoteFingerprint::Etag(etag.to_string());
+        }
+        let last_modified = last_modified
+            .and_then(|v| httpdate::parse_http_date(v.trim()).ok())
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok());
+        match (last_modified, size) {
+            (Some(t), Some(size)) => RemoteFingerprint::ModifiedSize { last_modified: t.as_secs(), size },
+            _ => RemoteFingerprint::None,
+        }
+    }
+
+    /// Build a fingerprint from the headers of a GET or HEAD response.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+        Self::from_properties(
+            header(ETAG),
+            header(LAST_MODIFIED),
+            header(CONTENT_LENGTH).and_then(|v| v.parse().ok()),
+        )
+    }
+
+    /// Whether both fingerprints describe the same remote version.
+    ///
+    /// Only returns `false` when the fingerprints prove a change: unknown
+    /// fingerprints and fingerprints of different kinds (e.g. after the server
+    /// started sending ETags) are treated as unchanged.
+    pub fn matches(&self, other: &RemoteFingerprint, tolerance: Duration) -> bool {
+        match (self, other) {
+            // Weak comparison: a W/ prefix does not make two tags different.
+            (RemoteFingerprint::Etag(a), RemoteFingerprint::Etag(b)) => {
+                a.trim_start_matches("W/") == b.trim_start_matches("W/")
+            }
+            (
+                RemoteFingerprint::ModifiedSize { last_modified: t1, size: s1 },
+                RemoteFingerprint::ModifiedSize { last_modified: t2, size: s2 },
+            ) => s1 == s2 && t1.abs_diff(*t2) <= tolerance.as_secs(),
+            _ => true,
+        }
+    }
+
+    /// Value for an `If-Match` header, if this fingerprint is a strong validator.
+    ///
+    /// Weak ETags and Last-Modified fingerprints cannot be used with
+    /// `If-Match`; callers then fall back to unconditional requests, and a
+    /// warning is logged once per process.
+    pub fn if_match(&self) -> Option<&str> {
+        match self {
+            RemoteFingerprint::Etag(tag) if !tag.starts_with("W/") => Some(tag),
+            _ => {
+                if !WEAK_VALIDATOR_WARNED.swap(true, Ordering::Relaxed) {
+                    warn!("Server provides no strong ETags, conditional requests are disabled");
+                }
+                None
+            }
+        }
+    }
+
+    /// Value for an `If-Range` header, so a resumed download only continues
+    /// the same version: a strong ETag, or the Last-Modified date.
+    pub fn if_range(&self) -> Option<String> {
+        match self {
+            RemoteFingerprint::Etag(tag) if !tag.starts_with("W/") => Some(tag.clone()),
+            RemoteFingerprint::ModifiedSize { last_modified, .. } => {
+                Some(httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(*last_modified)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Metadata of a remote file, as far as the server reports it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoteStat {
+    /// Size in bytes.
+    pub size: Option<u64>,
+    /// Last-Modified as Unix seconds.
+    pub last_modified: Option<u64>,
+    pub etag: Option<String>,
+}
+
+impl RemoteStat {
+    /// Read the metadata from the headers of a HEAD or GET response.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim);
+        RemoteStat {
+            size: header(CONTENT_LENGTH).and_then(|v| v.parse().ok()),
+            last_modified: header(LAST_MODIFIED)
+                .and_then(|v| httpdate::parse_http_date(v).ok())
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+            etag: header(ETAG).filter(|e| !e.is_empty()).map(str::to_string),
+        }
+    }
+
+    /// The metadata of a file as listed by a PROPFIND.
+    pub fn from_entry(entry: &RemoteEntry) -> Self {
+        RemoteStat { size: entry.size, last_modified: entry.last_modified, etag: entry.etag.clone() }
+    }
+
+    /// The fingerprint of this version: its ETag, else Last-Modified and size.
+    pub fn fingerprint(&self) -> RemoteFingerprint {
+        match (&self.etag, self.last_modified, self.size) {
+            (Some(etag), _, _) => RemoteFingerprint::Etag(etag.clone()),
+            (None, Some(last_modified), Some(size)) => RemoteFingerprint::ModifiedSize { last_modified, size },
+            _ => RemoteFingerprint::None,
+        }
+    }
+}
+
+/// A remote file compared with the fingerprint recorded in the hash store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteState {
+    Missing,
+    /// The fingerprints prove that the file changed on the server.
+    Changed,
+    /// Unchanged, or no recorded fingerprint to tell otherwise.
+    Unchanged,
+}
+
+/// Classify the `current` fingerprint (`None` if the file does not exist)
+/// against the `recorded` one.
+pub fn remote_state(
+    current: Option<&RemoteFingerprint>,
+    recorded: Option<&RemoteFingerprint>,
+    tolerance: Duration,
+) -> RemoteState {
+    match (current, recorded) {
+        (None, _) => RemoteState::Missing,
+        (Some(now), Some(before)) if !now.matches(before, tolerance) => RemoteState::Changed,
+        _ => RemoteState::Unchanged,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    const TOLERANCE: Duration = Duration::from_secs(2);
+
+    #[test]
+    fn test_extraction() {
+        let mut headers = HeaderMap::new();
+        headers.insert(ETAG, HeaderValue::from_static("\"abc\""));
+        headers.insert(LAST_MODIFIED, HeaderValue::from_static("Sun, 06 Nov 1994 08:49:37 GMT"));
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("42"));
+        assert_eq!(RemoteFingerprint::from_headers(&headers), RemoteFingerprint::Etag("\"abc\"".to_string()));
+
+        headers.remove(ETAG);
+        assert_eq!(
+            RemoteFingerprint::from_headers(&headers),
+            RemoteFingerprint::ModifiedSize { last_modified: 784111777, size: 42 }
+        );
+
+        // PROPFIND shapes: empty getetag, unparsable date, missing size.
+        assert_eq!(
+            RemoteFingerprint::from_properties(Some(" "), Some("Sun, 06 Nov 1994 08:49:37 GMT"), Some(1)),
+            RemoteFingerprint::ModifiedSize { last_modified: 784111777, size: 1 }
+        );
+        assert_eq!(
+            RemoteFingerprint::from_properties(None, Some("yesterday"), Some(1)),
+            RemoteFingerprint::None
+        );
+        assert_eq!(
+            RemoteFingerprint::from_properties(None, Some("Sun, 06 Nov 1994 08:49:37 GMT"), None),
+            RemoteFingerprint::None
+        );
+        assert_eq!(RemoteFingerprint::from_headers(&HeaderMap::new()), RemoteFingerprint::None);
+    }
+
+    #[test]
+    fn test_stat_agrees_with_fingerprint() {
+        let mut headers = HeaderMap::new();
+        headers.insert(LAST_MODIFIED, HeaderValue::from_static("Sun, 06 Nov 1994 08:49:37 GMT"));
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("42"));
+        let stat = RemoteStat::from_headers(&headers);
+        assert_eq!(stat, RemoteStat { size: Some(42), last_modified: Some(784111777), etag: None });
+        assert_eq!(stat.fingerprint(), RemoteFingerprint::from_headers(&headers));
+
+        headers.insert(ETAG, HeaderValue::from_static("\"abc\""));
+        assert_eq!(RemoteStat::from_headers(&headers).fingerprint(), RemoteFingerprint::from_headers(&headers));
+        assert_eq!(RemoteStat::default().fingerprint(), RemoteFingerprint::None);
+    }
+
+    #[test]
+    fn test_comparison() {
+        let etag = |t: &str| RemoteFingerprint::Etag(t.to_string());
+        assert!(etag("\"a\"").matches(&etag("W/\"a\""), TOLERANCE));
+        assert!(!etag("\"a\"").matches(&etag("\"b\""), TOLERANCE));
+
+        let ms = |t, s| RemoteFingerprint::ModifiedSize { last_modified: t, size: s };
+        assert!(ms(100, 5).matches(&ms(102, 5), TOLERANCE));
+        assert!(!ms(100, 5).matches(&ms(103, 5), TOLERANCE));
+        assert!(!ms(100, 5).matches(&ms(100, 6), TOLERANCE));
+
+        assert!(RemoteFingerprint::None.matches(&ms(1, 1), TOLERANCE));
+        assert!(etag("\"a\"").matches(&ms(1, 1), TOLERANCE));
+    }
+
+    #[test]
+    fn test_if_match_requires_strong_etag() {
+        assert_eq!(RemoteFingerprint::Etag("\"a\"".to_string()).if_match(), Some("\"a\""));
+        assert_eq!(RemoteFingerprint::Etag("W/\"a\"".to_string()).if_match(), None);
+        assert_eq!(RemoteFingerprint::ModifiedSize { last_modified: 1, size: 1 }.if_match(), None);
+    }
+
+    #[test]
+    fn test_if_range_validators() {
+        assert_eq!(RemoteFingerprint::Etag("\"a\"".to_string()).if_range().as_deref(), Some("\"a\""));
+        assert_eq!(RemoteFingerprint::Etag("W/\"a\"".to_string()).if_range(), None);
+        assert_eq!(
+            RemoteFingerprint::ModifiedSize { last_modified: 1_700_000_000, size: 1 }.if_range().as_deref(),
+            Some("Tue, 14 Nov 2023 22:13:20 GMT")
+        );
+        assert_eq!(RemoteFingerprint::None.if_range(), None);
+    }
+}
@@ -0,0 +1,178 @@
+//! Guidance for the first run against a server that already holds the files.
+//!
+//! With an empty hash store every file counts as changed, so a first sync
+//! against a server populated by other means (e.g. rsync) would upload all of
+//! it again. Before that happens, the local files are probed on the server;
+//! if at least `first_run_threshold` of them exist, the user chooses to adopt
+//! the remote files, re-upload everything, or abort. The answer is kept in the
+//! hash store so the question is asked only once.
+
+use crate::config::{Config, Layout};
+use crate::external_hasher::FileHasher;
+use crate::filter::FilterSet;
+use crate::hash_store::HashStore;
+use crate::sync::selected_files;
+use crate::webdav_client::WebDavClient;
+use clap::ValueEnum;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+
+/// How to treat remote files found on the first run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum FirstRunChoice {
+    /// Record files that already exist remotely as synced, without uploading them.
+    Adopt,
+    /// Upload every file again.
+    Reupload,
+    /// Stop without changing anything.
+    Abort,
+}
+
+/// Whether the store has never seen a sync and was not asked before.
+pub fn needs_guidance(store: &HashStore) -> bool {
+    store.first_run.is_none() && store.regular_hashes.is_empty() && store.pseudo_hashes.is_empty()
+}
+
+/// Number of selected local files that already exist remotely, counting at
+/// most up to `limit`.
+pub async fn count_remote_matches(
+    config: &Config,
+    client: &WebDavClient,
+    filters: &FilterSet,
+    limit: usize,
+) -> Result<usize, Box<dyn Error>> {
+    let mut found = 0;
+    for (_, remote_path) in selected_files(config, filters) {
+        if found >= limit {
+            break;
+        }
+        if client.stat(&remote_path).await?.is_some() {
+            found += 1;
+        }
+    }
+    Ok(found)
+}
+
+/// Message shown when the choice cannot be asked interactively.
+pub fn guidance(found: usize) -> String {
+    format!(
+        "The hash store is empty, but at least {} files already exist on the server. \
+         Rerun with --first-run adopt to record them as synced, --first-run reupload to \
+         upload everything again, or raise first_run_threshold.",
+        found
+    )
+}
+
+/// Ask for a choice on `output`, reading answers from `input` until one is valid.
+/// End of input counts as abort.
+pub fn prompt(found: usize, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<FirstRunChoice> {
+    writeln!(
+        output,
+        "The hash store is empty, but at least {} files already exist on the server.\n  \
+         [a]dopt     record the remote files as synced without uploading them\n  \
+         [r]eupload  upload every file again\n  \
+         [q]uit      abort without changes",
+        found
+    )?;
+    loop {
+        write!(output, "Choice [a/r/q]: ")?;
+        output.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Ok(FirstRunChoice::Abort);
+        }
+        match answer.trim().to_ascii_lowercase().as_str() {
+            "a" | "adopt" => return Ok(FirstRunChoice::Adopt),
+            "r" | "reupload" => return Ok(FirstRunChoice::Reupload),
+            "q" | "quit" | "abort" => return Ok(FirstRunChoice::Abort),
+            _ => writeln!(output, "Please answer a, r or q.")?,
+        }
+    }
+}
+
+/// Record the hash and fingerprint of every selected local file that exists
+/// remotely, so that the following sync skips it. Remote content is trusted,
+/// not compared. Returns the number of adopted files.
+pub async fn adopt(
+    config: &Config,
+    client: &WebDavClient,
+    store: &mut HashStore,
+    filters: &FilterSet,
+    use_pseudo_hash: bool,
+) -> Result<usize, Box<dyn Error>> {
+    let mut adopted = 0;
+    let hasher = FileHasher::from_config(config);
+    for (local_path, remote_path) in selected_files(config, filters) {
+        let Some(stat) = client.stat(&remote_path).await? else {
+            continue;
+        };
+        let hash = hasher.compute(&local_path, use_pseudo_hash).await?;
+        store.hashes_mut(use_pseudo_hash).insert(remote_path.clone(), hash);
+        store.fingerprints.insert(remote_path, stat.fingerprint());
+        adopted += 1;
+    }
+    Ok(adopted)
+}
+
+/// Run the first-run check and apply the answer of `choose`, which is only
+/// called when enough remote files were found.
+pub async fn guide(
+    config: &Config,
+    client: &WebDavClient,
+    store: &mut HashStore,
+    filters: &FilterSet,
+    use_pseudo_hash: bool,
+    choose: impl FnOnce(usize) -> Result<FirstRunChoice, Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    // Objects are not at the remote paths of the files, see `crate::cas`.
+    if !needs_guidance(store) || config.first_run_threshold == 0 || config.layout == Layout::Cas {
+        return Ok(());
+    }
+    let found = count_remote_matches(config, client, filters, config.first_run_threshold).await?;
+    if found < config.first_run_threshold {
+        return Ok(());
+    }
+    match choose(found)? {
+        FirstRunChoice::Abort => return Err("Aborted on first run, nothing was changed".into()),
+        FirstRunChoice::Adopt => {
+            let adopted = adopt(config, client, store, filters, use_pseudo_hash).await?;
+            info!("Adopted {} files that already exist on the server", adopted);
+            store.first_run = Some(FirstRunChoice::Adopt);
+        }
+        FirstRunChoice::Reupload => store.first_run = Some(FirstRunChoice::Reupload),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ask(script: &str) -> FirstRunChoice {
+        let mut output = Vec::new();
+        prompt(3, &mut script.as_bytes(), &mut output).unwrap()
+    }
+
+    #[test]
+    fn test_prompt_reads_choice() {
+        assert_eq!(ask("a\n"), FirstRunChoice::Adopt);
+        assert_eq!(ask("Reupload\n"), FirstRunChoice::Reupload);
+        assert_eq!(ask("maybe\nq\n"), FirstRunChoice::Abort);
+        assert_eq!(ask(""), FirstRunChoice::Abort);
+    }
+
+    #[test]
+    fn test_needs_guidance_only_once() {
+        let mut store = HashStore::default();
+        assert!(needs_guidance(&store));
+        store.first_run = Some(FirstRunChoice::Reupload);
+        assert!(!needs_guidance(&store));
+
+        let mut store = HashStore::default();
+        store.regular_hashes.insert("a.jpg".to_string(), "h".to_string());
+        assert!(!needs_guidance(&store));
+    }
+}
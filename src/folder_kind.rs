@@ -0,0 +1,66 @@
+//! What a configured folder entry points at.
+//!
+//! A folder entry is normally a directory. An entry that is a regular file
+//! syncs just that file, under its file name below `target_dir`. A missing
+//! path is skipped, so its files never look deleted, and anything else (a
+//! socket, a device) cannot be synced and is skipped with a warning.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FolderKind {
+    Directory,
+    File,
+    Missing,
+    /// Neither a directory nor a regular file.
+    Unsupported,
+}
+
+impl FolderKind {
+    /// The kind of `path`, following symlinks.
+    pub fn of(path: &Path) -> Self {
+        match std::fs::metadata(path) {
+            Err(_) => FolderKind::Missing,
+            Ok(metadata) if metadata.is_dir() => FolderKind::Directory,
+            Ok(metadata) if metadata.is_file() => FolderKind::File,
+            Ok(_) => FolderKind::Unsupported,
+        }
+    }
+
+    /// Whether the folder has files to sync.
+    pub fn is_syncable(self) -> bool {
+        matches!(self, FolderKind::Directory | FolderKind::File)
+    }
+}
+
+/// Path of `local_path` relative to the folder at `folder_path`; a folder
+/// that is a single file yields its file name.
+pub fn relative_path<'a>(folder_path: &Path, local_path: &'a Path) -> Option<&'a Path> {
+    let relative = local_path.strip_prefix(folder_path).ok()?;
+    if relative.as_os_str().is_empty() {
+        return local_path.file_name().map(Path::new);
+    }
+    Some(relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_kinds_and_relative_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.txt");
+        fs::write(&file, "x").unwrap();
+        assert_eq!(FolderKind::of(dir.path()), FolderKind::Directory);
+        assert_eq!(FolderKind::of(&file), FolderKind::File);
+        assert_eq!(FolderKind::of(&dir.path().join("gone")), FolderKind::Missing);
+
+        assert_eq!(relative_path(&file, &file), Some(Path::new("notes.txt")));
+        assert_eq!(relative_path(dir.path(), &file), Some(Path::new("notes.txt")));
+        assert_eq!(relative_path(&dir.path().join("sub"), &file), None);
+    }
+}
@@ -0,0 +1,324 @@
+//! Hash store changes uploaded as deltas instead of full rewrites.
+//!
+//! With `hash_store_deltas`, a run uploads only the entries it changed, as
+//! `<stem>.delta.<device>.<seq>.yaml` next to the remote store. Loading
+//! applies the deltas on top of the base store in `(seq, device)` order. A
+//! run numbers its delta one above the highest `seq` present when it
+//! started, so two devices writing concurrently share a `seq` and are
+//! ordered by device name. Once `compact_after` deltas exist, a run uploads
+//! the merged store as the new base and deletes the deltas it merged.
+//!
+//! The base lists the deltas it already contains in `merged_deltas`, so a
+//! delta left behind by an interrupted compaction is not applied twice.
+//! Delta paths are reserved: sync never uploads a local file to one.
+
+use crate::hash_store::HashStore;
+use crate::hash_store_guard::compressed_store_path;
+use crate::store_scope::with_scope;
+use crate::webdav_client::WebDavClient;
+use crate::work_dir::WorkDir;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::path::Path;
+
+/// Store fields that describe one copy of the store and never go into a delta.
+const LOCAL_FIELDS: [&str; 2] = ["remote_upload_pending", "merged_deltas"];
+
+/// The `hash_store_deltas` section of the config.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct DeltaConfig {
+    /// Name of this device in delta file names; letters, digits, `-` and `_`.
+    pub device: String,
+    /// Number of deltas on the server that makes a run merge them into the base.
+    #[serde(default = "default_compact_after")]
+    pub compact_after: usize,
+}
+
+fn default_compact_after() -> usize {
+    20
+}
+
+impl DeltaConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if self.device.is_empty() || !self.device.chars().all(valid) {
+            return Err(format!(
+                "hash_store_deltas.device '{}' may only contain letters, digits, '-' and '_'",
+                self.device
+            )
+            .into());
+        }
+        if self.compact_after == 0 {
+            return Err("hash_store_deltas.compact_after must be at least 1".into());
+        }
+        Ok(())
+    }
+}
+
+/// The changes between two versions of a store.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+pub struct HashDelta {
+    /// Added or changed entries per store section, e.g. `regular_hashes`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub set: BTreeMap<String, BTreeMap<String, Value>>,
+    /// Removed keys per store section.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub removed: BTreeMap<String, Vec<String>>,
+    /// Other store fields that changed, `null` where one was removed.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, Value>,
+}
+
+impl HashDelta {
+    /// What changed from `old` to `new`.
+    pub fn between(old: &HashStore, new: &HashStore) -> Result<Self, Box<dyn Error>> {
+        let (old, new) = (fields_of(old)?, fields_of(new)?);
+        let mut delta = HashDelta::default();
+        let names: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+        for name in names {
+            let (before, after) = (old.get(name), new.get(name));
+            if before == after {
+                continue;
+            }
+            match (entries(before)?, entries(after)?) {
+                (Some(before), Some(after)) => {
+                    let set: BTreeMap<String, Value> =
+                        after.iter().filter(|(k, v)| before.get(*k) != Some(*v)).map(|(k, v)| (k.clone(), v.clone())).collect();
+                    let removed: Vec<String> = before.keys().filter(|k| !after.contains_key(*k)).cloned().collect();
+                    if !set.is_empty() {
+                        delta.set.insert(name.clone(), set);
+                    }
+                    if !removed.is_empty() {
+                        delta.removed.insert(name.clone(), removed);
+                    }
+                }
+                _ => {
+                    delta.fields.insert(name.clone(), after.cloned().unwrap_or(Value::Null));
+                }
+            }
+        }
+        Ok(delta)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.removed.is_empty() && self.fields.is_empty()
+    }
+
+    /// Number of changed entries and fields.
+    pub fn len(&self) -> usize {
+        self.set.values().map(BTreeMap::len).sum::<usize>()
+            + self.removed.values().map(Vec::len).sum::<usize>()
+            + self.fields.len()
+    }
+
+    /// Apply the changes to `store`.
+    pub fn apply(&self, store: &mut HashStore) -> Result<(), Box<dyn Error>> {
+        let mut fields = fields_of(store)?;
+        for (section, set) in &self.set {
+            let entries = fields.entry(section.clone()).or_insert_with(|| Value::Mapping(Mapping::new()));
+            if !entries.is_mapping() {
+                *entries = Value::Mapping(Mapping::new());
+            }
+            if let Value::Mapping(entries) = entries {
+                for (key, value) in set {
+                    entries.insert(Value::String(key.clone()), value.clone());
+                }
+            }
+        }
+        for (section, removed) in &self.removed {
+            if let Some(Value::Mapping(entries)) = fields.get_mut(section) {
+                for key in removed {
+                    entries.remove(key.as_str());
+                }
+            }
+        }
+        for (name, value) in &self.fields {
+            if value.is_null() {
+                fields.remove(name);
+            } else {
+                fields.insert(name.clone(), value.clone());
+            }
+        }
+        let fields = Value::Mapping(fields.into_iter().map(|(k, v)| (Value::String(k), v)).collect());
+        let mut updated: HashStore = with_scope(store.scope.as_ref(), || serde_yaml::from_value(fields))?;
+        updated.scope = store.scope.take();
+        updated.remote_upload_pending = store.remote_upload_pending;
+        updated.merged_deltas = std::mem::take(&mut store.merged_deltas);
+        *store = updated;
+        Ok(())
+    }
+}
+
+/// The top-level fields of `store` that deltas carry.
+fn fields_of(store: &HashStore) -> Result<BTreeMap<String, Value>, Box<dyn Error>> {
+    let Value::Mapping(fields) = serde_yaml::to_value(store)? else {
+        return Err("hash store does not serialize to a mapping".into());
+    };
+    Ok(fields
+        .into_iter()
+        .filter_map(|(k, v)| k.as_str().map(|k| (k.to_string(), v)))
+        .filter(|(k, _)| !LOCAL_FIELDS.contains(&k.as_str()))
+        .collect())
+}
+
+/// The entries of a section keyed by path, or `None` for a plain field. An
+/// absent field counts as an empty section.
+fn entries(field: Option<&Value>) -> Result<Option<BTreeMap<String, Value>>, Box<dyn Error>> {
+    match field {
+        None => Ok(Some(BTreeMap::new())),
+        Some(Value::Mapping(entries)) => entries
+            .iter()
+            .map(|(k, v)| k.as_str().map(|k| (k.to_string(), v.clone())).ok_or_else(|| "hash store key is not a string".into()))
+            .collect::<Result<_, Box<dyn Error>>>()
+            .map(Some),
+        Some(_) => Ok(None),
+    }
+}
+
+/// Directory and file name prefix of the deltas of the store at `remote_hash_path`.
+fn delta_prefix(remote_hash_path: &str) -> (String, String) {
+    let path = Path::new(remote_hash_path.trim_matches('/'));
+    let dir = path.parent().map(|p| p.to_string_lossy().into_owned()).unwrap_or_default();
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    (dir, format!("{}.delta.", stem))
+}
+
+/// Remote path of delta `seq` of `device`.
+pub fn delta_path(remote_hash_path: &str, device: &str, seq: u64) -> String {
+    let (dir, prefix) = delta_prefix(remote_hash_path);
+    let name = format!("{}{}.{}.yaml", prefix, device, seq);
+    if dir.is_empty() {
+        name
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// The `(seq, device)` of a delta file name of the store at `remote_hash_path`.
+pub fn parse_delta_name(remote_hash_path: &str, file_name: &str) -> Option<(u64, String)> {
+    let (_, prefix) = delta_prefix(remote_hash_path);
+    let (device, seq) = file_name.strip_prefix(&prefix)?.strip_suffix(".yaml")?.rsplit_once('.')?;
+    Some((seq.parse().ok()?, device.to_string()))
+}
+
+/// Whether `remote_path` is the remote store, compressed or not, or one of
+/// its deltas.
+pub fn is_reserved(remote_hash_path: &str, remote_path: &str) -> bool {
+    let remote_path = remote_path.trim_matches('/');
+    if remote_path == remote_hash_path.trim_matches('/') || remote_path == compressed_store_path(remote_hash_path.trim_matches('/')) {
+        return true;
+    }
+    let (dir, _) = delta_prefix(remote_hash_path);
+    let (parent, name) = remote_path.rsplit_once('/').unwrap_or(("", remote_path));
+    parent == dir && parse_delta_name(remote_hash_path, name).is_some()
+}
+
+/// Deltas found next to a remote store, in the order they apply.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoteDeltas {
+    /// File names of the deltas.
+    pub names: Vec<String>,
+    /// `seq` for a delta written now.
+    pub next_seq: u64,
+}
+
+/// Apply the remote deltas of the store at `remote_hash_path` that `store`
+/// does not contain yet, and record all of them in its `merged_deltas`.
+///
+/// A delta that cannot be read, e.g. because a compaction removed it in the
+/// meantime, is skipped with a warning.
+pub async fn load_deltas(
+    client: &WebDavClient,
+    remote_hash_path: &str,
+    store: &mut HashStore,
+    work_dir: &WorkDir,
+) -> Result<RemoteDeltas, Box<dyn Error>> {
+    let (dir, _) = delta_prefix(remote_hash_path);
+    let mut found: Vec<(u64, String, String)> = client
+        .list(&dir)
+        .await?
+        .into_iter()
+        .filter(|entry| !entry.is_dir)
+        .filter_map(|entry| {
+            let name = entry.name().to_string();
+            parse_delta_name(remote_hash_path, &name).map(|(seq, device)| (seq, device, name))
+        })
+        .collect();
+    found.sort();
+
+    let download = work_dir.file("hash_delta.yaml");
+    let merged = std::mem::take(&mut store.merged_deltas);
+    for (_, _, name) in found.iter().filter(|(_, _, name)| !merged.contains(name)) {
+        let remote = if dir.is_empty() { name.clone() } else { format!("{}/{}", dir, name) };
+        let applied = async {
+            if client.download_file(&remote, &download).await?.is_none() {
+                return Err("it is gone from the server".into());
+            }
+            let delta: HashDelta = serde_yaml::from_str(&std::fs::read_to_string(&download)?)?;
+            delta.apply(store)
+        };
+        if let Err(e) = applied.await {
+            warn!("Skipping hash store delta {}: {}", remote, e);
+        }
+    }
+    let _ = std::fs::remove_file(&download);
+
+    store.merged_deltas = found.iter().map(|(_, _, name)| name.clone()).collect();
+    Ok(RemoteDeltas {
+        next_seq: found.iter().map(|(seq, _, _)| seq + 1).max().unwrap_or(1),
+        names: found.into_iter().map(|(_, _, name)| name).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(yaml: &str) -> HashStore {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let old = store("regular_hashes: {a: '1', b: '2'}\npseudo_hashes: {a: p}\ntarget_dir: phone\n");
+        let new = store("regular_hashes: {a: '1', b: '3', c: '4'}\npseudo_hashes: {}\ntags: {c: {album: x}}\n");
+
+        let delta = HashDelta::between(&old, &new).unwrap();
+        assert_eq!(delta.set["regular_hashes"].keys().collect::<Vec<_>>(), vec!["b", "c"]);
+        assert_eq!(delta.removed["pseudo_hashes"], vec!["a"]);
+        assert_eq!(delta.fields["target_dir"], Value::Null);
+        assert_eq!(delta.len(), 5);
+
+        let mut applied = old.clone();
+        applied.remote_upload_pending = true;
+        delta.apply(&mut applied).unwrap();
+        assert_eq!(serde_yaml::to_string(&applied).unwrap(), serde_yaml::to_string(&HashStore { remote_upload_pending: true, ..new.clone() }).unwrap());
+        assert!(HashDelta::between(&new, &new).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_delta_names_are_reserved() {
+        assert_eq!(delta_path("hashes.yaml", "laptop", 7), "hashes.delta.laptop.7.yaml");
+        assert_eq!(delta_path("/meta/store.yaml", "nas-1", 12), "meta/store.delta.nas-1.12.yaml");
+        assert_eq!(parse_delta_name("meta/store.yaml", "store.delta.nas-1.12.yaml"), Some((12, "nas-1".to_string())));
+        assert_eq!(parse_delta_name("meta/store.yaml", "store.delta.nas-1.x.yaml"), None);
+        assert_eq!(parse_delta_name("meta/store.yaml", "hashes.delta.nas-1.1.yaml"), None);
+
+        assert!(is_reserved("meta/store.yaml", "meta/store.yaml"));
+        assert!(is_reserved("meta/store.yaml", "meta/store.yaml.gz"));
+        assert!(is_reserved("meta/store.yaml", "meta/store.delta.laptop.3.yaml"));
+        assert!(!is_reserved("meta/store.yaml", "photos/store.delta.laptop.3.yaml"));
+        assert!(!is_reserved("meta/store.yaml", "meta/store.delta.notes.txt"));
+    }
+
+    #[test]
+    fn test_device_name_is_validated() {
+        let config = |device: &str| DeltaConfig { device: device.to_string(), compact_after: 20 };
+        assert!(config("pixel-7_a").validate().is_ok());
+        assert!(config("my.phone").validate().is_err());
+        assert!(config("").validate().is_err());
+    }
+}
@@ -1,36 +1,444 @@
+use crate::file_stamp::FileStamp;
+use crate::fingerprint::RemoteFingerprint;
+use crate::first_run::FirstRunChoice;
+use crate::output::HumanDisplay;
+use crate::reconcile::Origin;
+use crate::store_scope::{with_scope, PathMap, Scope};
+use log::warn;
+use semver::Version;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
+use std::mem::size_of;
 use std::path::Path;
+use std::time::Duration;
 use tokio::fs as async_fs;
 use tokio::io::AsyncReadExt;
 
+/// Estimated bytes per stored string beyond its text, see
+/// [`HashStore::approximate_memory_bytes`].
+const ENTRY_OVERHEAD: usize = 32;
+
+/// Reads of a store file that looks torn (e.g. while another tool rewrites
+/// it) before settling for what it holds.
+const LOAD_ATTEMPTS: u32 = 3;
+
+/// Pause between those reads.
+const LOAD_RETRY_DELAY: Duration = Duration::from_millis(250);
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct HashStore {
     /// Regular SHA‑256 hashes
-    pub regular_hashes: BTreeMap<String, String>,
+    pub regular_hashes: PathMap<String>,
     /// Pseudo hashes (filename, size, first 1 KB)
-    pub pseudo_hashes: BTreeMap<String, String>,
+    pub pseudo_hashes: PathMap<String>,
+    /// Set when the store was saved locally but could not be uploaded to the
+    /// remote. The next run uploads it before doing anything else.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub remote_upload_pending: bool,
+    /// User metadata per remote path. Sync never reads or changes it.
+    #[serde(default, skip_serializing_if = "PathMap::holds_nothing")]
+    pub tags: PathMap<BTreeMap<String, String>>,
+    /// Remote fingerprint per path as seen when the file was last found unchanged.
+    #[serde(default, skip_serializing_if = "PathMap::holds_nothing")]
+    pub fingerprints: PathMap<RemoteFingerprint>,
+    /// Size and mtime of the local file when its hash was last confirmed (`trust_mtime`).
+    #[serde(default, skip_serializing_if = "PathMap::holds_nothing")]
+    pub stamps: PathMap<FileStamp>,
+    /// Content-defined chunk hashes of large files (experimental `cdc_dedup`).
+    #[serde(default, skip_serializing_if = "PathMap::holds_nothing")]
+    pub chunks: PathMap<Vec<String>>,
+    /// Answer to the first-run prompt, so it is only asked once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_run: Option<FirstRunChoice>,
+    /// `target_dir` of the last sync, which prefixes all keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_dir: Option<String>,
+    /// For remote paths claimed by local files differing only in case, the
+    /// file (by its own remote path) that was last uploaded to it.
+    #[serde(default, skip_serializing_if = "PathMap::holds_nothing")]
+    pub case_winners: PathMap<String>,
+    /// For remote paths with problem names that the server stored under
+    /// another name, that name; see [`crate::problem_names`].
+    #[serde(default, skip_serializing_if = "PathMap::holds_nothing")]
+    pub server_names: PathMap<String>,
+    /// Oldest client allowed to rewrite the remote store; see [`crate::store_version`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_client_version: Option<Version>,
+    /// Deltas already contained in this store; see [`crate::hash_delta`].
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub merged_deltas: BTreeSet<String>,
+    /// Folders (by id) in which a verified upload did not match, so the next
+    /// sync verifies every file; see [`crate::verify_sampling`].
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub full_verify_folders: BTreeSet<String>,
+    /// Local file each path was synced from; see [`crate::reconcile`].
+    #[serde(default, skip_serializing_if = "PathMap::holds_nothing")]
+    pub origins: PathMap<Origin>,
+    /// Scope the store was loaded with by [`load_scoped`]; entries of other
+    /// devices are then kept raw, see [`crate::store_scope`].
+    ///
+    /// [`load_scoped`]: HashStore::load_scoped
+    #[serde(skip)]
+    pub scope: Option<Scope>,
+    /// Number of hashes when the file was last saved, which [`load`] checks
+    /// the file against; written by [`save`], not kept up to date.
+    ///
+    /// [`load`]: HashStore::load
+    /// [`save`]: HashStore::save
+    #[serde(default, rename = "entry_count", skip_serializing)]
+    pub saved_entry_count: Option<usize>,
+}
+
+/// A store as written by [`HashStore::save`]. The entry count comes first,
+/// so that a file cut short still has it.
+#[derive(Serialize)]
+struct SavedStore<'a> {
+    entry_count: usize,
+    #[serde(flatten)]
+    store: &'a HashStore,
 }
 
 impl HashStore {
+    /// Load the store at `path`, or an empty one if there is no file.
+    ///
+    /// A file that does not parse or holds far fewer entries than its last
+    /// save recorded may be in the middle of being rewritten by another tool,
+    /// so it is read again after a short delay before failing or settling for
+    /// what it holds.
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
-        if path.as_ref().exists() {
-            let content = fs::read_to_string(path)?;
-            let store: HashStore = serde_yaml::from_str(&content)?;
-            Ok(store)
-        } else {
-            Ok(Self::default())
+        Self::load_scoped(path, None)
+    }
+
+    /// Like [`load`](Self::load), materializing only the entries in `scope`;
+    /// see [`crate::store_scope`].
+    pub fn load_scoped<P: AsRef<Path>>(path: P, scope: Option<&Scope>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let mut attempt = 1;
+        loop {
+            if let Some(store) = Self::read_attempt(path, scope, attempt)? {
+                return Ok(store);
+            }
+            std::thread::sleep(LOAD_RETRY_DELAY);
+            attempt += 1;
+        }
+    }
+
+    /// Like [`load_scoped`](Self::load_scoped), pausing between reads of a
+    /// torn store without blocking the runtime.
+    pub async fn load_scoped_async(path: &Path, scope: Option<&Scope>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut attempt = 1;
+        loop {
+            if let Some(store) = Self::read_attempt(path, scope, attempt)? {
+                return Ok(store);
+            }
+            tokio::time::sleep(LOAD_RETRY_DELAY).await;
+            attempt += 1;
         }
     }
 
+    /// Read the store once, or `None` if it looks torn and is to be read
+    /// again after [`LOAD_RETRY_DELAY`].
+    fn read_attempt(path: &Path, scope: Option<&Scope>, attempt: u32) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if !path.exists() {
+            return Ok(Some(Self::default()));
+        }
+        let content = fs::read_to_string(path)?;
+        let parsed = with_scope(scope, || serde_yaml::from_str::<HashStore>(&content));
+        let problem = match parsed.map(|store| HashStore { scope: scope.cloned(), ..store }) {
+            Ok(store) => match store.torn_reason() {
+                None => {
+                    store.warn_backslash_keys(path);
+                    return Ok(Some(store));
+                }
+                Some(reason) if attempt == LOAD_ATTEMPTS => {
+                    warn!("Hash store '{}' still has {}, using it as it is", path.display(), reason);
+                    store.warn_backslash_keys(path);
+                    return Ok(Some(store));
+                }
+                Some(reason) => reason,
+            },
+            Err(e) if attempt == LOAD_ATTEMPTS => return Err(e.into()),
+            Err(e) => format!("a parse error: {}", e),
+        };
+        warn!(
+            "Hash store '{}' looks torn ({}), it may be being written; reading it again in {} ms (attempt {} of {})",
+            path.display(),
+            problem,
+            LOAD_RETRY_DELAY.as_millis(),
+            attempt + 1,
+            LOAD_ATTEMPTS
+        );
+        Ok(None)
+    }
+
+    /// Why the loaded store looks cut short, if it does: fewer than half
+    /// the entries its last save recorded.
+    fn torn_reason(&self) -> Option<String> {
+        let recorded = self.saved_entry_count?;
+        let count = self.entry_count();
+        (count < recorded / 2).then(|| format!("{} entries where its last save recorded {}", count, recorded))
+    }
+
+    fn warn_backslash_keys(&self, path: &Path) {
+        let backslash_keys = self.backslash_keys().len();
+        if backslash_keys > 0 {
+            warn!(
+                "Hash store '{}' has {} keys with backslash separators (written on Windows?) that \
+                 never match; set normalize_store_keys: true or run `hashes normalize` to fix them",
+                path.display(),
+                backslash_keys
+            );
+        }
+    }
+
+    /// Write the store to `path`, recording its entry count for [`load`](Self::load).
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
-        let content = serde_yaml::to_string(self)?;
+        let content = serde_yaml::to_string(&SavedStore { entry_count: self.entry_count(), store: self })?;
         fs::write(path, content)?;
         Ok(())
     }
 
+    /// Number of regular and pseudo hashes, including those outside the scope.
+    pub fn entry_count(&self) -> usize {
+        self.regular_hashes.len()
+            + self.regular_hashes.foreign_len()
+            + self.pseudo_hashes.len()
+            + self.pseudo_hashes.foreign_len()
+    }
+
+    /// Hashes recorded for the given hashing mode.
+    pub fn hashes(&self, pseudo: bool) -> &BTreeMap<String, String> {
+        if pseudo {
+            &self.pseudo_hashes
+        } else {
+            &self.regular_hashes
+        }
+    }
+
+    /// Mutable hashes for the given hashing mode.
+    pub fn hashes_mut(&mut self, pseudo: bool) -> &mut BTreeMap<String, String> {
+        if pseudo {
+            &mut self.pseudo_hashes
+        } else {
+            &mut self.regular_hashes
+        }
+    }
+
+    /// Set tag `key` of `path` to `value`.
+    pub fn set_tag(&mut self, path: &str, key: &str, value: &str) {
+        self.tags
+            .entry(path.to_string())
+            .or_default()
+            .insert(key.to_string(), value.to_string());
+    }
+
+    /// Whether `path` carries tag `key` with `value`.
+    pub fn has_tag(&self, path: &str, key: &str, value: &str) -> bool {
+        self.tags
+            .get(path)
+            .and_then(|tags| tags.get(key))
+            .is_some_and(|v| v == value)
+    }
+
+    /// The tags of `path`, as printed by `hashes tag get`.
+    pub fn entry_tags(&self, path: &str) -> EntryTags {
+        EntryTags { path: path.to_string(), tags: self.tags.get(path).cloned().unwrap_or_default() }
+    }
+
+    /// Drop everything recorded for `path`.
+    pub fn forget(&mut self, path: &str) {
+        self.regular_hashes.remove(path);
+        self.pseudo_hashes.remove(path);
+        self.tags.remove(path);
+        self.fingerprints.remove(path);
+        self.stamps.remove(path);
+        self.chunks.remove(path);
+        self.case_winners.remove(path);
+        self.origins.remove(path);
+    }
+
+    /// Move everything recorded for `from` to `to`, e.g. after a MOVE on the
+    /// server.
+    pub fn rename(&mut self, from: &str, to: &str) {
+        fn rekey<V>(map: &mut BTreeMap<String, V>, from: &str, to: &str) {
+            if let Some(value) = map.remove(from) {
+                map.insert(to.to_string(), value);
+            }
+        }
+        rekey(&mut self.regular_hashes, from, to);
+        rekey(&mut self.pseudo_hashes, from, to);
+        rekey(&mut self.tags, from, to);
+        rekey(&mut self.fingerprints, from, to);
+        rekey(&mut self.stamps, from, to);
+        rekey(&mut self.chunks, from, to);
+        rekey(&mut self.origins, from, to);
+    }
+
+    /// Paths recorded with `\` separators, in any part of the store.
+    pub fn backslash_keys(&self) -> BTreeSet<String> {
+        self.regular_hashes
+            .keys()
+            .chain(self.pseudo_hashes.keys())
+            .chain(self.tags.keys())
+            .chain(self.fingerprints.keys())
+            .chain(self.stamps.keys())
+            .chain(self.chunks.keys())
+            .chain(self.origins.keys())
+            .filter(|path| path.contains('\\'))
+            .cloned()
+            .collect()
+    }
+
+    /// Rewrite paths with `\` separators to `/`. When several paths end up the
+    /// same, the entry whose stamp equals `local_stamp` of the rewritten path
+    /// wins, otherwise the one that already used `/`; the others only fill in
+    /// what the winner lacks. Nothing on the server is touched.
+    pub fn normalize_keys(&mut self, local_stamp: impl Fn(&str) -> Option<FileStamp>) -> KeyNormalization {
+        let mut outcome = KeyNormalization::default();
+        let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for path in self.backslash_keys() {
+            groups.entry(path.replace('\\', "/")).or_default().push(path);
+        }
+        for (normalized, mut candidates) in groups {
+            outcome.rewritten += candidates.len();
+            if self.knows(&normalized) {
+                candidates.insert(0, normalized.clone());
+            }
+            let winner = local_stamp(&normalized)
+                .and_then(|current| candidates.iter().position(|p| self.stamps.get(p) == Some(&current)))
+                .unwrap_or(0);
+            let winner = candidates.remove(winner);
+            outcome.merged += candidates.len();
+            candidates.insert(0, winner);
+
+            merge_keys(&mut self.regular_hashes, &candidates, &normalized);
+            merge_keys(&mut self.pseudo_hashes, &candidates, &normalized);
+            merge_keys(&mut self.tags, &candidates, &normalized);
+            merge_keys(&mut self.fingerprints, &candidates, &normalized);
+            merge_keys(&mut self.stamps, &candidates, &normalized);
+            merge_keys(&mut self.chunks, &candidates, &normalized);
+            merge_keys(&mut self.origins, &candidates, &normalized);
+        }
+        outcome
+    }
+
+    /// Whether anything is recorded for `path`.
+    fn knows(&self, path: &str) -> bool {
+        self.regular_hashes.contains_key(path)
+            || self.pseudo_hashes.contains_key(path)
+            || self.tags.contains_key(path)
+            || self.fingerprints.contains_key(path)
+            || self.stamps.contains_key(path)
+            || self.chunks.contains_key(path)
+            || self.origins.contains_key(path)
+    }
+
+    /// Rough heap size of the store in memory: the bytes of every key and
+    /// value plus a fixed overhead per string for its header and share of the
+    /// map node. Entries outside the scope count as their text. Meant for
+    /// spotting growth, not for exact accounting.
+    pub fn approximate_memory_bytes(&self) -> u64 {
+        let text = |s: &String| (s.capacity() + ENTRY_OVERHEAD) as u64;
+        let strings = |map: &BTreeMap<String, String>| map.iter().map(|(k, v)| text(k) + text(v)).sum::<u64>();
+        let keyed = |key: &String, value: usize| text(key) + value as u64;
+        strings(&self.regular_hashes)
+            + strings(&self.pseudo_hashes)
+            + strings(&self.case_winners)
+            + strings(&self.server_names)
+            + self.tags.iter().map(|(k, tags)| keyed(k, strings(tags) as usize)).sum::<u64>()
+            + self
+                .fingerprints
+                .iter()
+                .map(|(k, fp)| match fp {
+                    RemoteFingerprint::Etag(tag) => keyed(k, tag.capacity() + size_of::<RemoteFingerprint>()),
+                    _ => keyed(k, size_of::<RemoteFingerprint>()),
+                })
+                .sum::<u64>()
+            + self.stamps.keys().map(|k| keyed(k, size_of::<FileStamp>())).sum::<u64>()
+            + self.chunks.iter().map(|(k, chunks)| keyed(k, chunks.iter().map(|c| text(c) as usize).sum())).sum::<u64>()
+            + self.origins.iter().map(|(k, o)| keyed(k, (text(&o.folder) + text(&o.path)) as usize)).sum::<u64>()
+            + [
+                self.regular_hashes.foreign(),
+                self.pseudo_hashes.foreign(),
+                self.tags.foreign(),
+                self.fingerprints.foreign(),
+                self.stamps.foreign(),
+                self.chunks.foreign(),
+                self.case_winners.foreign(),
+                self.server_names.foreign(),
+                self.origins.foreign(),
+            ]
+            .into_iter()
+            .flatten()
+            .map(|(key, value)| (key.len() + value.len() + 2 * size_of::<Box<str>>()) as u64)
+            .sum::<u64>()
+    }
+
+    /// A copy of the store restricted to entries tagged `key=value`.
+    pub fn filter_by_tag(&self, key: &str, value: &str) -> HashStore {
+        let keep = |map: &BTreeMap<String, String>| -> PathMap<String> {
+            map.iter()
+                .filter(|(path, _)| self.has_tag(path, key, value))
+                .map(|(path, hash)| (path.clone(), hash.clone()))
+                .collect()
+        };
+        HashStore {
+            regular_hashes: keep(&self.regular_hashes),
+            pseudo_hashes: keep(&self.pseudo_hashes),
+            remote_upload_pending: false,
+            tags: self
+                .tags
+                .iter()
+                .filter(|(path, _)| self.has_tag(path, key, value))
+                .map(|(path, tags)| (path.clone(), tags.clone()))
+                .collect(),
+            fingerprints: self
+                .fingerprints
+                .iter()
+                .filter(|(path, _)| self.has_tag(path, key, value))
+                .map(|(path, fp)| (path.clone(), fp.clone()))
+                .collect(),
+            stamps: self
+                .stamps
+                .iter()
+                .filter(|(path, _)| self.has_tag(path, key, value))
+                .map(|(path, stamp)| (path.clone(), *stamp))
+                .collect(),
+            chunks: self
+                .chunks
+                .iter()
+                .filter(|(path, _)| self.has_tag(path, key, value))
+                .map(|(path, chunks)| (path.clone(), chunks.clone()))
+                .collect(),
+            first_run: self.first_run,
+            target_dir: self.target_dir.clone(),
+            case_winners: PathMap::default(),
+            server_names: PathMap::default(),
+            min_client_version: self.min_client_version.clone(),
+            merged_deltas: BTreeSet::new(),
+            full_verify_folders: BTreeSet::new(),
+            origins: self
+                .origins
+                .iter()
+                .filter(|(path, _)| self.has_tag(path, key, value))
+                .map(|(path, origin)| (path.clone(), origin.clone()))
+                .collect(),
+            scope: None,
+            saved_entry_count: None,
+        }
+    }
+
+    /// Compute the regular or pseudo hash of a file.
+    pub async fn compute<P: AsRef<Path>>(path: P, pseudo: bool) -> Result<String, Box<dyn std::error::Error>> {
+        if pseudo {
+            Self::compute_pseudo_hash(path).await
+        } else {
+            Self::compute_hash(path).await
+        }
+    }
+
     pub async fn compute_hash<P: AsRef<Path>>(path: P) -> Result<String, Box<dyn std::error::Error>> {
         let mut file = async_fs::File::open(path).await?;
         let mut hasher = Sha256::new();
@@ -70,7 +478,7 @@ impl HashStore {
         // Combine components into a SHA‑256 hash
         let mut hasher = Sha256::new();
         hasher.update(file_name);
-        hasher.update(&file_size.to_be_bytes());
+        hasher.update(file_size.to_be_bytes());
         hasher.update(&buffer);
         let hash = hasher.finalize();
 
@@ -78,12 +486,161 @@ impl HashStore {
     }
 }
 
+/// Parse a `key=value` tag argument.
+pub fn parse_tag(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("Invalid tag '{}', expected key=value", arg)),
+    }
+}
+
+/// Tags of one entry; see [`HashStore::entry_tags`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryTags {
+    pub path: String,
+    pub tags: BTreeMap<String, String>,
+}
+
+impl HumanDisplay for EntryTags {
+    fn human(&self) -> String {
+        if self.tags.is_empty() {
+            return format!("No tags on '{}'", self.path);
+        }
+        self.tags.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>().join("\n")
+    }
+}
+
+/// Counts of [`HashStore::normalize_keys`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KeyNormalization {
+    /// Paths that contained backslashes.
+    pub rewritten: usize,
+    /// Entries dropped in favour of another one with the same rewritten path.
+    pub merged: usize,
+}
+
+/// Move the first entry of `paths` found in `map` to `normalized`, dropping
+/// the entries of the other paths.
+fn merge_keys<V>(map: &mut BTreeMap<String, V>, paths: &[String], normalized: &str) {
+    let mut values: Vec<V> = paths.iter().filter_map(|path| map.remove(path)).collect();
+    if !values.is_empty() {
+        map.insert(normalized.to_string(), values.swap_remove(0));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::output::{render, OutputFormat};
     use std::io::Write;
     use tempfile::NamedTempFile;
 
+    /// A store with `count` camera files, each with a regular and a pseudo hash.
+    fn generated_store(count: usize) -> (HashStore, u64) {
+        let mut store = HashStore::default();
+        let mut text = 0;
+        for i in 0..count {
+            let key = format!("DCIM/Camera/IMG_2024{:08}.jpg", i);
+            let hash = format!("{:064x}", i);
+            text += 2 * (key.len() + hash.len()) as u64;
+            store.regular_hashes.insert(key.clone(), hash.clone());
+            store.pseudo_hashes.insert(key, hash);
+        }
+        (store, text)
+    }
+
+    #[test]
+    fn test_approximate_memory_grows_linearly_with_entries() {
+        let (small, small_text) = generated_store(20_000);
+        let (large, large_text) = generated_store(40_000);
+        let (small_bytes, large_bytes) = (small.approximate_memory_bytes(), large.approximate_memory_bytes());
+
+        assert_eq!(large_bytes, 2 * small_bytes);
+        // Text dominates; the overhead stays below the text itself.
+        assert!(small_bytes > small_text && small_bytes < 2 * small_text, "{} for {}", small_bytes, small_text);
+        assert!(large_bytes < 2 * large_text);
+        assert_eq!(HashStore::default().approximate_memory_bytes(), 0);
+    }
+
+    /// A store shared by a phone and a tablet, with entries of both in every
+    /// section keyed by path.
+    fn shared_store() -> HashStore {
+        let mut store = HashStore::default();
+        for device in ["Phone", "Tablet"] {
+            for i in 0..3 {
+                let key = format!("{}/DCIM/IMG_{}.jpg", device, i);
+                store.regular_hashes.insert(key.clone(), format!("{:064x}", i));
+                store.pseudo_hashes.insert(key.clone(), format!("{:064x}", i + 7));
+                store.set_tag(&key, "album", device);
+                store.fingerprints.insert(key.clone(), RemoteFingerprint::Etag(format!("\"{}-{}\"", device, i)));
+                store.stamps.insert(key.clone(), FileStamp { size: i, mtime_ns: 1_000 + i });
+                store.chunks.insert(key.clone(), vec![format!("c{}", i), format!("d{}", i)]);
+                store.origins.insert(key.clone(), Origin::new("camera", &format!("IMG_{}.jpg", i)));
+            }
+            let modified = RemoteFingerprint::ModifiedSize { last_modified: 1_700_000_000, size: 5 };
+            store.fingerprints.insert(format!("{}/DCIM/IMG_9.jpg", device), modified);
+            store.case_winners.insert(format!("{}/a.jpg", device), format!("{}/A.jpg", device));
+            store.server_names.insert(format!("{}/a:b.jpg", device), format!("{}/a_b.jpg", device));
+        }
+        store.target_dir = Some("Phone".to_string());
+        store
+    }
+
+    #[test]
+    fn test_scoped_load_keeps_other_devices_byte_identical() {
+        let file = NamedTempFile::new().unwrap();
+        shared_store().save(file.path()).unwrap();
+        let original = fs::read_to_string(file.path()).unwrap();
+
+        let mut store = HashStore::load_scoped(file.path(), Scope::new(["Phone"]).as_ref()).unwrap();
+        assert!(store.regular_hashes.keys().chain(store.fingerprints.keys()).all(|k| k.starts_with("Phone/")));
+        assert_eq!(store.regular_hashes.len(), 3);
+        assert_eq!(store.regular_hashes.foreign_len(), 3);
+        assert_eq!(store.entry_count(), 12);
+        assert_eq!(store.fingerprints.len(), 4);
+        assert!(store.case_winners.contains_key("Phone/a.jpg"));
+        assert!(!store.server_names.contains_key("Tablet/a:b.jpg"));
+
+        // Saved untouched, the file does not change.
+        store.save(file.path()).unwrap();
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), original);
+
+        // Changes in scope come out as they would with everything loaded.
+        let mut full = HashStore::load(file.path()).unwrap();
+        for store in [&mut store, &mut full] {
+            store.forget("Phone/DCIM/IMG_0.jpg");
+            store.rename("Phone/DCIM/IMG_1.jpg", "Phone/DCIM/IMG_5.jpg");
+            store.regular_hashes.insert("Phone/new.jpg".to_string(), "h".to_string());
+        }
+        assert_eq!(serde_yaml::to_string(&store).unwrap(), serde_yaml::to_string(&full).unwrap());
+        store.save(file.path()).unwrap();
+        assert_eq!(HashStore::load(file.path()).unwrap().regular_hashes.len(), 6);
+        assert_eq!(HashStore::load(file.path()).unwrap().tags["Tablet/DCIM/IMG_0.jpg"]["album"], "Tablet");
+    }
+
+    #[test]
+    fn test_scoped_load_of_a_large_shared_store() {
+        let file = NamedTempFile::new().unwrap();
+        let mut shared = HashStore::default();
+        for device in ["Phone", "Watch"] {
+            for i in 0..20_000 {
+                let key = format!("{}/DCIM/Camera/IMG_2024{:08}.jpg", device, i);
+                shared.regular_hashes.insert(key.clone(), format!("{:064x}", i));
+                shared.stamps.insert(key, FileStamp { size: i, mtime_ns: i });
+            }
+        }
+        shared.save(file.path()).unwrap();
+
+        let full = HashStore::load(file.path()).unwrap();
+        let scoped = HashStore::load_scoped(file.path(), Scope::new(["Phone"]).as_ref()).unwrap();
+        assert_eq!((full.regular_hashes.len(), full.stamps.len()), (40_000, 40_000));
+        assert_eq!((scoped.regular_hashes.len(), scoped.stamps.len()), (20_000, 20_000));
+        assert_eq!(scoped.entry_count(), full.entry_count());
+        // The other device's half is kept as text, which costs less than parsed entries.
+        let (full_bytes, scoped_bytes) = (full.approximate_memory_bytes(), scoped.approximate_memory_bytes());
+        assert!(scoped_bytes > full_bytes / 2 && scoped_bytes < full_bytes, "{} of {}", scoped_bytes, full_bytes);
+    }
+
     #[tokio::test]
     async fn test_compute_hash() {
         let content = b"test content for hashing";
@@ -110,4 +667,79 @@ mod tests {
         let loaded = HashStore::load(&temp_path).unwrap();
         assert_eq!(loaded.regular_hashes, store.regular_hashes);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_tags_filter_and_serialization() {
+        let mut store = HashStore::default();
+        assert!(!serde_yaml::to_string(&store).unwrap().contains("tags"));
+
+        store.regular_hashes.insert("a.jpg".to_string(), "h1".to_string());
+        store.regular_hashes.insert("b.jpg".to_string(), "h2".to_string());
+        store.set_tag("a.jpg", "archived", "tape");
+        store.set_tag("b.jpg", "archived", "no");
+
+        let filtered = store.filter_by_tag("archived", "tape");
+        assert_eq!(filtered.regular_hashes.keys().collect::<Vec<_>>(), vec!["a.jpg"]);
+        assert_eq!(filtered.tags.keys().collect::<Vec<_>>(), vec!["a.jpg"]);
+
+        store.set_tag("a.jpg", "camera", "pixel");
+        let tags = store.entry_tags("a.jpg");
+        assert_eq!(tags.human(), "archived=tape\ncamera=pixel");
+        let json = render(&tags, OutputFormat::Json).unwrap();
+        assert_eq!(serde_json::from_str::<EntryTags>(&json).unwrap(), tags);
+        assert_eq!(store.entry_tags("c.jpg").human(), "No tags on 'c.jpg'");
+
+        assert_eq!(parse_tag("k=v=w").unwrap(), ("k".to_string(), "v=w".to_string()));
+        assert!(parse_tag("=v").is_err());
+        assert!(parse_tag("novalue").is_err());
+    }
+
+    /// A store written on Windows, partly synced again from Linux.
+    const MIXED_STORE: &str = r#"
+regular_hashes:
+  'DCIM\a.jpg': old-a
+  'DCIM\sub\b.jpg': h-b
+  DCIM/a.jpg: new-a
+  c.jpg: h-c
+pseudo_hashes: {}
+tags:
+  'DCIM\a.jpg': {album: holiday}
+stamps:
+  'DCIM\a.jpg': {size: 3, mtime_ns: 100}
+  DCIM/a.jpg: {size: 3, mtime_ns: 200}
+"#;
+
+    fn mixed_store() -> HashStore {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(MIXED_STORE.as_bytes()).unwrap();
+        let store = HashStore::load(file.path()).unwrap();
+        assert_eq!(store.backslash_keys().len(), 2);
+        store
+    }
+
+    #[test]
+    fn test_normalize_keys_prefers_forward_slash_entry() {
+        let mut store = mixed_store();
+        let outcome = store.normalize_keys(|_| None);
+
+        assert_eq!(outcome, KeyNormalization { rewritten: 2, merged: 1 });
+        assert!(store.backslash_keys().is_empty());
+        let keys: Vec<_> = store.regular_hashes.keys().map(String::as_str).collect();
+        assert_eq!(keys, vec!["DCIM/a.jpg", "DCIM/sub/b.jpg", "c.jpg"]);
+        assert_eq!(store.regular_hashes["DCIM/a.jpg"], "new-a");
+        assert_eq!(store.stamps["DCIM/a.jpg"].mtime_ns, 200);
+        // The dropped entry still contributes what the kept one lacked.
+        assert!(store.has_tag("DCIM/a.jpg", "album", "holiday"));
+    }
+
+    #[test]
+    fn test_normalize_keys_prefers_entry_matching_local_file() {
+        let mut store = mixed_store();
+        let local = FileStamp { size: 3, mtime_ns: 100 };
+        store.normalize_keys(|path| (path == "DCIM/a.jpg").then_some(local));
+
+        assert_eq!(store.regular_hashes["DCIM/a.jpg"], "old-a");
+        assert_eq!(store.stamps["DCIM/a.jpg"], local);
+        assert_eq!(store.regular_hashes["DCIM/sub/b.jpg"], "h-b");
+    }
+}
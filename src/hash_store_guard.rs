@@ -1,8 +1,35 @@
-use crate::config::Config;
+use crate::artifacts::RunLock;
+use crate::config::{Config, RemoteHashStore};
 use std::error::Error;
+use crate::hash_delta::{delta_path, load_deltas, DeltaConfig, HashDelta, RemoteDeltas};
 use crate::hash_store::HashStore;
-use crate::webdav_client::WebDavClient;
-use std::path::PathBuf;
+use crate::spread::fresh_seed;
+use crate::store_scope::Scope;
+use crate::store_version::{client_version, stamp, unmet_requirement};
+use crate::units::format_duration;
+use crate::webdav_client::{WebDavClient, PART_SUFFIX};
+use crate::work_dir::WorkDir;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{info, warn};
+use semver::Version;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Delay before the first retry of the final hash store upload; doubled after
+/// every further attempt.
+const FINALIZE_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Lifetime asked for the lock on the remote store, after which the server
+/// drops the lock of a machine that crashed. A sync running longer loses the
+/// lock; its store upload then fails and is retried by the next run.
+const STORE_LOCK_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Interval at which a lock held by another machine is asked for again.
+const STORE_LOCK_POLL: Duration = Duration::from_secs(1);
 
 /// Guard that ensures the hash store is saved locally and uploaded to the remote
 /// WebDAV server when it goes out of scope. This guarantees that the hash store
@@ -13,38 +40,255 @@ pub struct HashStoreGuard {
     client: WebDavClient,
     local_path: PathBuf,
     remote_path: String,
+    /// Set with `compress_hash_store`.
+    compress: bool,
+    /// False with `remote_hash_store: disabled`; the store then stays local.
+    remote_enabled: bool,
+    finalize_retries: u32,
+    fail_on_pending_upload: bool,
+    /// Set with `--read-only`; the store is then neither saved nor uploaded.
+    read_only: bool,
+    /// Version compared against the store's `min_client_version`.
+    client_version: Version,
+    /// Requirement of the loaded store this client does not meet; the store
+    /// is then saved locally but never uploaded.
+    remote_locked: Option<Version>,
+    /// Token of the WebDAV lock on the remote store (`lock_hash_store`).
+    store_lock: Option<String>,
+    finalized: bool,
+    /// Temporary files of this run; removed when the guard is dropped.
+    work_dir: WorkDir,
+    /// Set with `hash_store_deltas`.
+    deltas: Option<DeltaConfig>,
+    /// The store as loaded from base and deltas, which the delta of this run
+    /// is computed against; `None` when the next upload rewrites the base.
+    loaded: Option<HashStore>,
+    remote_deltas: RemoteDeltas,
+    /// Keeps runs with overlapping folders out while this one runs; `None`
+    /// in read-only mode, which writes nothing into the folders.
+    _run_lock: Option<RunLock>,
 }
 
 impl HashStoreGuard {
     /// Create a new guard. It downloads the remote hash store (if any) to a
     /// temporary file, loads it (or creates a new empty store), and prepares
     /// for later saving/uploading.
+    ///
+    /// With `hash_store_deltas`, the remote deltas are applied on top of the
+    /// downloaded store.
+    ///
+    /// Fails if the remote store exists but cannot be downloaded.
+    ///
+    /// If the local store is marked as not yet uploaded by a previous run, it
+    /// is uploaded first and used instead of the remote copy. With the remote
+    /// hash store disabled, only the local store is loaded. A read-only
+    /// client never uploads or saves the store, and neither does a client
+    /// older than the store's `min_client_version`.
     pub async fn new(
         client: WebDavClient,
         config: &Config,
     ) -> Result<Self, Box<dyn Error>> {
+        Self::with_client_version(client, config, client_version()).await
+    }
+
+    /// Like [`new`](Self::new), materializing the entries of every
+    /// `target_dir` instead of only those of this device (see
+    /// [`crate::store_scope`]), for commands that work across them.
+    pub async fn whole(client: WebDavClient, config: &Config) -> Result<Self, Box<dyn Error>> {
+        Self::open(client, config, client_version(), false).await
+    }
+
+    /// Like [`new`](Self::new), acting as client `version` (lets tests
+    /// simulate an older or newer binary).
+    pub async fn with_client_version(
+        client: WebDavClient,
+        config: &Config,
+        version: Version,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::open(client, config, version, true).await
+    }
+
+    async fn open(client: WebDavClient, config: &Config, version: Version, scoped: bool) -> Result<Self, Box<dyn Error>> {
         // Determine paths
         let local_path = PathBuf::from(&config.hash_store_path);
         let remote_path = config.remote_hash_path.clone();
 
-        // Download remote hash store to a temporary location.
-        let temp_remote_path = std::env::temp_dir().join("remote_hashes.yaml");
-        let _ = client
-            .download_file(&remote_path, &temp_remote_path)
-            .await;
+        let read_only = client.is_read_only();
+        let mut guard = Self {
+            hash_store: HashStore::default(),
+            client,
+            local_path,
+            remote_path,
+            compress: config.compress_hash_store,
+            remote_enabled: config.remote_hash_store == RemoteHashStore::Enabled,
+            finalize_retries: config.finalize_retries,
+            fail_on_pending_upload: config.fail_on_pending_upload,
+            read_only,
+            client_version: version,
+            remote_locked: None,
+            store_lock: None,
+            finalized: false,
+            work_dir: WorkDir::create(config.temp_dir.as_deref().map(Path::new))?,
+            deltas: config.hash_store_deltas.clone(),
+            loaded: None,
+            remote_deltas: RemoteDeltas::default(),
+            _run_lock: if read_only { None } else { Some(RunLock::acquire(config)?) },
+        };
+
+        let mut scope = Scope::new([config.target_dir.as_str()]).filter(|_| scoped);
+        let mut local_store = load_local(&guard.local_path, scope.as_ref()).await;
+        // The local store records the target_dir of this device's last sync;
+        // after a change, the entries under it are still this device's.
+        if let Some(previous) = local_store.target_dir.clone() {
+            if scope.as_ref().is_some_and(|scope| !scope.contains(&previous)) {
+                scope = Scope::new([config.target_dir.as_str(), previous.as_str()]);
+                local_store = load_local(&guard.local_path, scope.as_ref()).await;
+            }
+        }
+        if !guard.remote_enabled {
+            guard.hash_store = local_store;
+            guard.hash_store.remote_upload_pending = false;
+            return Ok(guard);
+        }
+        if local_store.remote_upload_pending && guard.read_only {
+            warn!("Hash store from a previous run was not uploaded yet, using the local copy (read-only)");
+            guard.hash_store = local_store;
+            return Ok(guard);
+        }
+        if config.lock_hash_store && !guard.read_only {
+            guard.lock_remote(config.hash_store_lock_wait).await?;
+        }
+        if local_store.remote_upload_pending {
+            guard.hash_store = local_store;
+            if guard.lock_if_too_new() {
+                return Ok(guard);
+            }
+            warn!("Hash store from a previous run was not uploaded yet, uploading it now");
+            if let Err(e) = guard.upload_pending().await {
+                warn!("Hash store upload still failing, continuing with the local copy: {}", e);
+            }
+            return Ok(guard);
+        }
+
+        // Download remote hash store to a temporary location; a file left
+        // there must not pass for it.
+        let temp_remote_path = guard.work_dir.file("remote_hashes.yaml");
+        let _ = std::fs::remove_file(&temp_remote_path);
+        let timeout = store_timeout(config);
+        let downloaded = match download_store(&guard.client, &guard.remote_path, guard.compress, timeout, &temp_remote_path).await {
+            Ok(downloaded) => downloaded,
+            Err(e) => {
+                // Nothing was loaded: the empty store saved and uploaded on
+                // drop would replace the real one.
+                guard.unlock_remote().await;
+                guard.finalized = true;
+                return Err(e);
+            }
+        };
 
-        // Load (or create) the hash store from the temporary file.
-        let hash_store = HashStore::load(&temp_remote_path)?;
+        // Load the downloaded store, or start a fresh one if there is none.
+        // Locking a missing store created it empty.
+        let empty = std::fs::metadata(&temp_remote_path).is_ok_and(|m| m.len() == 0);
+        guard.hash_store = if downloaded && !empty {
+            HashStore::load_scoped_async(&temp_remote_path, scope.as_ref()).await.map_err(|e| {
+                format!("{} (while reading the remote hash store from {})", e, guard.client.display_url(&guard.remote_path))
+            })?
+        } else {
+            HashStore::default()
+        };
 
         // Clean up the temporary file – it is no longer needed.
         let _ = std::fs::remove_file(&temp_remote_path);
 
-        Ok(Self {
-            hash_store,
-            client,
-            local_path,
-            remote_path,
-        })
+        if guard.deltas.is_some() {
+            match load_deltas(&guard.client, &guard.remote_path, &mut guard.hash_store, &guard.work_dir).await {
+                Ok(remote_deltas) => {
+                    guard.remote_deltas = remote_deltas;
+                    // Recording the merged deltas is no change of this run.
+                    stamp(&mut guard.hash_store);
+                    guard.loaded = Some(guard.hash_store.clone());
+                }
+                Err(e) => warn!("Failed to list the hash store deltas, rewriting the whole store this run: {}", e),
+            }
+        }
+
+        guard.lock_if_too_new();
+        Ok(guard)
+    }
+
+    /// Lock the remote store, waiting up to `wait` for another machine to
+    /// release its lock. Servers without locking are synced unlocked.
+    async fn lock_remote(&mut self, wait: Duration) -> Result<(), Box<dyn Error>> {
+        match self.client.supports_locking().await {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(e) => {
+                warn!("Cannot tell whether the server supports locking, not locking {}: {}", self.remote_path, e);
+                return Ok(());
+            }
+        }
+        let start = Instant::now();
+        let mut waiting = false;
+        loop {
+            match self.client.lock(&self.remote_path, STORE_LOCK_TIMEOUT).await {
+                Ok(Some(token)) => {
+                    self.store_lock = Some(token);
+                    return Ok(());
+                }
+                Ok(None) if start.elapsed() < wait => {
+                    if !std::mem::replace(&mut waiting, true) {
+                        info!("{} is locked by another machine, waiting up to {}", self.remote_path, format_duration(wait));
+                    }
+                    tokio::time::sleep(STORE_LOCK_POLL.min(wait - start.elapsed())).await;
+                }
+                Ok(None) => {
+                    return Err(format!(
+                        "{} is still locked by another machine syncing to it after {}; not syncing, so that \
+                         its changes are not overwritten (raise hash_store_lock_wait to wait longer)",
+                        self.remote_path,
+                        format_duration(wait)
+                    )
+                    .into())
+                }
+                Err(e) => {
+                    warn!("Failed to lock {}, syncing without the lock: {}", self.remote_path, e);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Release the lock on the remote store, if held.
+    async fn unlock_remote(&mut self) {
+        if let Some(token) = self.store_lock.take() {
+            if let Err(e) = self.client.unlock(&self.remote_path, &token).await {
+                warn!("Failed to unlock {}, the server drops the lock later: {}", self.remote_path, e);
+            }
+        }
+    }
+
+    /// Stop this client from uploading a store that needs a newer version.
+    fn lock_if_too_new(&mut self) -> bool {
+        self.remote_locked = unmet_requirement(&self.hash_store, &self.client_version);
+        if let Some(required) = &self.remote_locked {
+            warn!(
+                "The remote hash store needs phone_sync {} or newer, this is {}: files are still uploaded, \
+                 but {} is left unchanged until this binary is upgraded",
+                required, self.client_version, self.remote_path
+            );
+        }
+        self.remote_locked.is_some()
+    }
+
+    /// The `min_client_version` of the remote store if this client is too
+    /// old to update it.
+    pub fn remote_locked(&self) -> Option<&Version> {
+        self.remote_locked.as_ref()
+    }
+
+    /// Temporary files of this run.
+    pub fn work_dir(&self) -> &WorkDir {
+        &self.work_dir
     }
 
     /// Get a mutable reference to the inner `HashStore`.
@@ -55,32 +299,277 @@ impl HashStoreGuard {
     /// Ensure the hash store is uploaded to the remote location.
     /// This should be called before the guard is dropped to guarantee
     /// that the remote upload has completed.
-    pub async fn finalize(&self) -> Result<(), Box<dyn std::error::Error>> {
-        // Save locally (ignore errors; Drop will also attempt to save)
-        let _ = self.hash_store.save(&self.local_path);
-        // Upload to remote
-        self.client.upload_file(&self.local_path, &self.remote_path).await?;
+    ///
+    /// The store is always persisted locally first; only a failure to do so is
+    /// fatal. If the upload still fails after all retries, the local store is
+    /// marked as pending and the next run uploads it. Unless
+    /// `fail_on_pending_upload` is set, that case is reported as a warning.
+    pub async fn finalize(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.finalized = true;
+        if self.read_only {
+            return Ok(());
+        }
+        let uploaded = self.upload_pending().await;
+        self.unlock_remote().await;
+        if let Err(e) = uploaded {
+            if self.fail_on_pending_upload {
+                return Err(format!("Failed to upload hash store to remote: {}", e).into());
+            }
+            warn!(
+                "Failed to upload hash store to remote, it will be uploaded on the next run: {}",
+                e
+            );
+        }
+        Ok(())
+    }
+
+    /// Save the store locally and upload it, marking it as pending on failure.
+    async fn upload_pending(&mut self) -> Result<(), Box<dyn Error>> {
+        self.hash_store.remote_upload_pending = false;
+        stamp(&mut self.hash_store);
+        self.hash_store.save(&self.local_path)?;
+        if !self.remote_enabled || self.remote_locked.is_some() {
+            return Ok(());
+        }
+
+        let result = match (&self.deltas, &self.loaded) {
+            (Some(deltas), Some(loaded)) if self.remote_deltas.names.len() < deltas.compact_after => {
+                self.upload_delta(&deltas.device.clone(), &loaded.clone()).await
+            }
+            (Some(_), Some(_)) => self.merge_deltas().await,
+            _ => self.upload_with_retries(&self.local_path, &self.remote_path).await,
+        };
+
+        if result.is_err() {
+            self.hash_store.remote_upload_pending = true;
+            self.hash_store.save(&self.local_path)?;
+        }
+        result
+    }
+
+    /// Upload the changes since `loaded` as the next delta of `device`.
+    async fn upload_delta(&mut self, device: &str, loaded: &HashStore) -> Result<(), Box<dyn Error>> {
+        let delta = HashDelta::between(loaded, &self.hash_store)?;
+        if delta.is_empty() {
+            return Ok(());
+        }
+        let local = self.work_dir.file("hash_delta_upload.yaml");
+        std::fs::write(&local, serde_yaml::to_string(&delta)?)?;
+        let remote = delta_path(&self.remote_path, device, self.remote_deltas.next_seq);
+        self.upload_with_retries(&local, &remote).await?;
+        info!("Uploaded hash store delta {} ({} changes)", remote, delta.len());
+        self.remote_deltas.next_seq += 1;
+        self.loaded = Some(self.hash_store.clone());
         Ok(())
     }
+
+    /// Upload the store, which contains every remote delta, as the new base
+    /// and delete the deltas.
+    async fn merge_deltas(&mut self) -> Result<(), Box<dyn Error>> {
+        self.upload_with_retries(&self.local_path, &self.remote_path).await?;
+        let names = std::mem::take(&mut self.remote_deltas.names);
+        for name in &names {
+            let remote = match self.remote_path.rsplit_once('/') {
+                Some((dir, _)) => format!("{}/{}", dir, name),
+                None => name.clone(),
+            };
+            if let Err(e) = self.client.delete_file(&remote).await {
+                warn!("Failed to delete merged hash store delta {}: {}", remote, e);
+            }
+        }
+        info!("Merged {} hash store deltas into {}", names.len(), self.remote_path);
+        self.loaded = Some(self.hash_store.clone());
+        Ok(())
+    }
+
+    /// Upload `local` to `remote`, retrying with a growing delay.
+    async fn upload_with_retries(&self, local: &Path, remote: &str) -> Result<(), Box<dyn Error>> {
+        let mut delay = FINALIZE_RETRY_DELAY;
+        let mut attempt = 0;
+        loop {
+            let uploaded = if remote == self.remote_path {
+                upload_store(&self.client, local, remote, self.compress).await
+            } else {
+                self.client.upload_file(local, remote).await
+            };
+            match uploaded {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < self.finalize_retries => {
+                    attempt += 1;
+                    warn!(
+                        "Hash store upload failed (attempt {}/{}), retrying in {:?}: {}",
+                        attempt,
+                        self.finalize_retries + 1,
+                        delay,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// The local store at `path`, or an empty one if it cannot be read.
+async fn load_local(path: &Path, scope: Option<&Scope>) -> HashStore {
+    HashStore::load_scoped_async(path, scope).await.unwrap_or_else(|e| {
+        warn!("Cannot read the local hash store '{}', starting from an empty one: {}", path.display(), e);
+        HashStore::default()
+    })
+}
+
+/// Timeout of the hash store download: `hash_store_timeout`, or else
+/// `timeout` plus the time the local copy, the last known size of the
+/// store, takes at `min_upload_kbps`.
+pub fn store_timeout(config: &Config) -> Duration {
+    config.hash_store_timeout.unwrap_or_else(|| {
+        let size = std::fs::metadata(&config.hash_store_path).map_or(0, |m| m.len());
+        config.timeout + Duration::from_secs_f64(size as f64 * 8.0 / (config.min_upload_kbps.max(1) as f64 * 1000.0))
+    })
+}
+
+/// Remote name of the store at `remote_hash_path` with
+/// `compress_hash_store`, e.g. `hashes.yaml.gz` for `hashes.yaml`.
+pub fn compressed_store_path(remote_hash_path: &str) -> String {
+    if remote_hash_path.ends_with(".gz") {
+        remote_hash_path.to_string()
+    } else {
+        format!("{}.gz", remote_hash_path)
+    }
+}
+
+/// Download the store at `remote_hash_path` to `local` as plain YAML
+/// within `timeout`, and tell whether there was one. With `compress`, the
+/// gzip copy is read, or the plain store written before compression was
+/// turned on if there is none yet.
+pub async fn download_store(
+    client: &WebDavClient,
+    remote_hash_path: &str,
+    compress: bool,
+    timeout: Duration,
+    local: &Path,
+) -> Result<bool, Box<dyn Error>> {
+    let client = client.clone().with_timeout(timeout);
+    fetch_store(&client, remote_hash_path, compress, local).await.map_err(|e| {
+        format!(
+            "{} (while downloading the remote hash store from {}, hash_store_timeout {})",
+            e,
+            client.display_url(remote_hash_path),
+            format_duration(timeout)
+        )
+        .into()
+    })
+}
+
+async fn fetch_store(client: &WebDavClient, remote_hash_path: &str, compress: bool, local: &Path) -> Result<bool, Box<dyn Error>> {
+    if !compress {
+        return Ok(client.download_file(remote_hash_path, local).await?.is_some());
+    }
+    let plain = remote_hash_path.strip_suffix(".gz").unwrap_or(remote_hash_path);
+    let compressed = compressed_store_path(remote_hash_path);
+    let gz = gz_path(local);
+    if client.download_file(&compressed, &gz).await?.is_none() {
+        return Ok(client.download_file(plain, local).await?.is_some());
+    }
+    let unpacked = (|| {
+        let mut decoder = GzDecoder::new(BufReader::new(File::open(&gz)?));
+        std::io::copy(&mut decoder, &mut BufWriter::new(File::create(local)?))
+    })();
+    let _ = std::fs::remove_file(&gz);
+    unpacked.map_err(|e| format!("Cannot decompress the hash store {}: {}", compressed, e))?;
+    Ok(true)
+}
+
+/// Temporary file of the compressed form of the store file `local`.
+fn gz_path(local: &Path) -> PathBuf {
+    let mut name = local.as_os_str().to_owned();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+/// Upload the store file `local` to `remote`, gzip-compressed to
+/// [`compressed_store_path`] with `compress`.
+async fn upload_store(client: &WebDavClient, local: &Path, remote: &str, compress: bool) -> Result<(), Box<dyn Error>> {
+    send_store(client, local, remote, compress)
+        .await
+        .map_err(|e| format!("{} (while uploading the remote hash store to {})", e, client.display_url(remote)).into())
+}
+
+async fn send_store(client: &WebDavClient, local: &Path, remote: &str, compress: bool) -> Result<(), Box<dyn Error>> {
+    if !compress {
+        return upload_complete(client, local, remote).await;
+    }
+    let gz = gz_path(local);
+    let compressed = (|| {
+        let mut encoder = GzEncoder::new(BufWriter::new(File::create(&gz)?), Compression::default());
+        std::io::copy(&mut BufReader::new(File::open(local)?), &mut encoder)?;
+        encoder.finish()?.into_inner().map_err(|e| e.into_error())?.sync_all()
+    })();
+    let uploaded = match compressed {
+        Ok(()) => upload_complete(client, &gz, &compressed_store_path(remote)).await,
+        Err(e) => Err(format!("Cannot compress the hash store: {}", e).into()),
+    };
+    let _ = std::fs::remove_file(&gz);
+    uploaded
+}
+
+/// Upload `local` under a name of this run next to `remote` and MOVE it
+/// over `remote`, so that `remote` only ever holds a complete store of one
+/// run, even if another run uploads at the same time. Servers that do not
+/// allow MOVE get a plain PUT.
+async fn upload_complete(client: &WebDavClient, local: &Path, remote: &str) -> Result<(), Box<dyn Error>> {
+    if client.capabilities().await.is_ok_and(|c| !c.allows("MOVE")) {
+        return client.upload_file(local, remote).await;
+    }
+    let staged = format!("{}.{:016x}{}", remote, fresh_seed(), PART_SUFFIX);
+    client.upload_file(local, &staged).await?;
+    // As a string, the error can be kept across the cleanup in `Drop`'s task.
+    if let Err(e) = client.move_path(&staged, remote, true).await.map_err(|e| e.to_string()) {
+        let _ = client.delete_file(&staged).await;
+        return Err(e.into());
+    }
+    Ok(())
 }
 
 impl Drop for HashStoreGuard {
     fn drop(&mut self) {
+        // `finalize` already persisted the store.
+        if self.finalized || self.read_only {
+            return;
+        }
+
         // Save the hash store locally.
+        stamp(&mut self.hash_store);
         if let Err(e) = self.hash_store.save(&self.local_path) {
             eprintln!("Failed to save hash store locally: {}", e);
         }
 
+        let client = self.client.clone();
+        let remote = self.remote_path.clone();
+        let store_lock = self.store_lock.take();
+        if !self.remote_enabled || self.remote_locked.is_some() {
+            if let Some(token) = store_lock {
+                tokio::spawn(async move {
+                    let _ = client.unlock(&remote, &token).await;
+                });
+            }
+            return;
+        }
+
         // Upload the hash store to the remote location asynchronously.
         // We cannot block the current Tokio runtime inside an async context,
         // so we spawn a background task to perform the upload.
-        let client = self.client.clone();
         let local = self.local_path.clone();
-        let remote = self.remote_path.clone();
+        let compress = self.compress;
         tokio::spawn(async move {
-            if let Err(e) = client.upload_file(&local, &remote).await {
+            if let Err(e) = upload_store(&client, &local, &remote, compress).await {
                 eprintln!("Failed to upload hash store to remote: {}", e);
             }
+            if let Some(token) = store_lock {
+                let _ = client.unlock(&remote, &token).await;
+            }
         });
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,242 @@
+//! Append-only record of remote mutations, for disaster recovery.
+//!
+//! With `journal_path` set, every PUT, DELETE, MOVE and MKCOL sent to the
+//! server appends one JSON line. The journal is independent of the hash store,
+//! so a lost store can be approximately rebuilt from it. Writing the journal
+//! never fails a run: the first write error is logged and journaling stops.
+
+use crate::config::Config;
+use crate::hash_store::HashStore;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Outcome recorded for a mutation the server accepted.
+pub const OUTCOME_OK: &str = "ok";
+
+/// One remote mutation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Unix seconds.
+    pub timestamp: u64,
+    /// HTTP method, e.g. `PUT`.
+    pub operation: String,
+    pub remote_path: String,
+    /// Destination of a MOVE.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Content hash of an upload made by sync.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// [`OUTCOME_OK`] or a short failure reason.
+    pub outcome: String,
+}
+
+impl JournalEntry {
+    pub fn new(operation: &str, remote_path: &str, outcome: impl Into<String>) -> Self {
+        JournalEntry {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            operation: operation.to_string(),
+            remote_path: remote_path.to_string(),
+            destination: None,
+            size: None,
+            hash: None,
+            outcome: outcome.into(),
+        }
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.outcome == OUTCOME_OK
+    }
+}
+
+struct JournalState {
+    path: PathBuf,
+    max_bytes: u64,
+    writer: Option<BufWriter<File>>,
+    written: u64,
+    failed: bool,
+}
+
+/// Handle to the journal file; clones share the same writer.
+#[derive(Clone)]
+pub struct Journal {
+    state: Arc<Mutex<JournalState>>,
+}
+
+impl Journal {
+    /// Journal at `path`, rotated to `<path>.1` once it grows past `max_bytes`.
+    pub fn open<P: AsRef<Path>>(path: P, max_bytes: u64) -> Self {
+        Journal {
+            state: Arc::new(Mutex::new(JournalState {
+                path: path.as_ref().to_path_buf(),
+                max_bytes,
+                writer: None,
+                written: 0,
+                failed: false,
+            })),
+        }
+    }
+
+    /// The journal configured by `journal_path`, if any.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config.journal_path.as_ref().map(|path| Self::open(path, config.journal_max_bytes))
+    }
+
+    /// Append `entry` and flush it to disk.
+    pub fn record(&self, entry: &JournalEntry) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.failed {
+            return;
+        }
+        if let Err(e) = state.append(entry) {
+            warn!("Failed to write journal {}, journaling disabled for this run: {}", state.path.display(), e);
+            state.failed = true;
+            state.writer = None;
+        }
+    }
+}
+
+impl JournalState {
+    fn append(&mut self, entry: &JournalEntry) -> Result<(), Box<dyn Error>> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        if self.writer.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            self.written = file.metadata()?.len();
+            self.writer = Some(BufWriter::new(file));
+        }
+        if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+            self.writer = None;
+            fs::rename(&self.path, rotated_path(&self.path))?;
+            self.writer = Some(BufWriter::new(File::create(&self.path)?));
+            self.written = 0;
+        }
+        let writer = self.writer.as_mut().expect("journal writer is open");
+        writer.write_all(line.as_bytes())?;
+        writer.flush()?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".1");
+    PathBuf::from(name)
+}
+
+/// All entries of the journal at `path`, oldest first, including the rotated file.
+pub fn read_entries<P: AsRef<Path>>(path: P) -> Result<Vec<JournalEntry>, Box<dyn Error>> {
+    let path = path.as_ref();
+    let mut entries = Vec::new();
+    for file in [rotated_path(path), path.to_path_buf()] {
+        let reader = match File::open(&file) {
+            Ok(f) => BufReader::new(f),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("Failed to read journal '{}': {}", file.display(), e).into()),
+        };
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                // A crash can leave a truncated last line; skip it.
+                Err(e) => warn!("Skipping line {} of journal '{}': {}", number + 1, file.display(), e),
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Replay successful uploads, deletes and moves into the `pseudo` or regular
+/// hashes of `store`. Returns the number of paths with a hash afterwards.
+pub fn rebuild_hashes(entries: &[JournalEntry], store: &mut HashStore, pseudo: bool) -> usize {
+    let hashes = store.hashes_mut(pseudo);
+    for entry in entries.iter().filter(|e| e.succeeded()) {
+        match (entry.operation.as_str(), &entry.hash, &entry.destination) {
+            ("PUT", Some(hash), _) => {
+                hashes.insert(entry.remote_path.clone(), hash.clone());
+            }
+            ("DELETE", _, _) => {
+                hashes.remove(&entry.remote_path);
+            }
+            ("MOVE", _, Some(destination)) => {
+                let from = entry.remote_path.trim_end_matches('/');
+                let to = destination.trim_end_matches('/');
+                let moved: Vec<String> = hashes
+                    .keys()
+                    .filter(|p| *p == from || p.starts_with(&format!("{}/", from)))
+                    .cloned()
+                    .collect();
+                for old in moved {
+                    let hash = hashes.remove(&old).expect("key was just listed");
+                    hashes.insert(format!("{}{}", to, &old[from.len()..]), hash);
+                }
+            }
+            _ => {}
+        }
+    }
+    hashes.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(path: &str, hash: &str) -> JournalEntry {
+        JournalEntry { hash: Some(hash.to_string()), ..JournalEntry::new("PUT", path, OUTCOME_OK) }
+    }
+
+    #[test]
+    fn test_rotates_past_max_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.jsonl");
+        let journal = Journal::open(&path, 200);
+        for i in 0..5 {
+            journal.record(&put(&format!("f{}.jpg", i), "abc"));
+        }
+
+        assert!(rotated_path(&path).exists());
+        assert!(fs::metadata(&path).unwrap().len() <= 200);
+        let paths: Vec<_> = read_entries(&path).unwrap().into_iter().map(|e| e.remote_path).collect();
+        assert_eq!(paths.last().unwrap(), "f4.jpg");
+    }
+
+    #[test]
+    fn test_unwritable_journal_does_not_panic() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::open(dir.path().join("missing/journal.jsonl"), 1024);
+        journal.record(&put("a.jpg", "abc"));
+        journal.record(&put("b.jpg", "abc"));
+    }
+
+    #[test]
+    fn test_rebuild_applies_moves_and_deletes() {
+        let mut entries = vec![
+            put("old/a.jpg", "1"),
+            put("old/b.jpg", "2"),
+            put("c.jpg", "3"),
+            JournalEntry::new("PUT", "failed.jpg", "HTTP 507"),
+            JournalEntry::new("DELETE", "c.jpg", OUTCOME_OK),
+        ];
+        entries.push(JournalEntry {
+            destination: Some("new".to_string()),
+            ..JournalEntry::new("MOVE", "old", OUTCOME_OK)
+        });
+
+        let mut store = HashStore::default();
+        assert_eq!(rebuild_hashes(&entries, &mut store, false), 2);
+        assert_eq!(store.hashes(false).get("new/a.jpg"), Some(&"1".to_string()));
+        assert_eq!(store.hashes(false).get("new/b.jpg"), Some(&"2".to_string()));
+    }
+}
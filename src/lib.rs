@@ -1,5 +1,68 @@
+#[cfg(not(any(feature = "tls-native", feature = "tls-rustls")))]
+compile_error!("enable one TLS backend feature: `tls-native` or `tls-rustls`");
+#[cfg(all(feature = "tls-native", feature = "tls-rustls"))]
+compile_error!("the `tls-native` and `tls-rustls` features are mutually exclusive");
+
+pub mod archive;
+pub mod artifacts;
+pub mod auth;
+pub mod batch;
+pub mod browse;
+pub mod budget;
+pub mod capabilities;
+pub mod cas;
+pub mod case_collision;
+pub mod cdc;
+pub mod checksum;
+pub mod chunked_upload;
+pub mod clock;
+pub mod compact;
 pub mod config;
+pub mod content_type;
+pub mod daemon;
+pub mod doctor;
+pub mod effective_config;
+pub mod estimate;
+pub mod external_hasher;
+pub mod file_stamp;
+pub mod filter;
+pub mod fingerprint;
+pub mod first_run;
+pub mod folder_kind;
+pub mod hash_delta;
 pub mod hash_store_guard;
+pub mod journal;
+pub mod local_path;
+pub mod memory;
+pub mod migrate;
+pub mod mount;
+pub mod network;
+pub mod nomedia;
+pub mod notify;
+pub mod output;
+pub mod plan;
+pub mod problem_names;
+pub mod profile;
+pub mod progress;
+pub mod propfind;
+pub mod pull;
+pub mod reconcile;
+pub mod remote_listing;
+pub mod report;
+pub mod self_test;
+pub mod spill_index;
+pub mod spread;
+pub mod stage;
+pub mod store_scope;
+pub mod store_version;
 pub mod sync;
+pub mod systemd;
+pub mod target_dir;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod units;
+pub mod verify;
+pub mod verify_sampling;
 pub mod webdav_client;
-pub mod hash_store;
\ No newline at end of file
+pub mod work_dir;
+pub mod hash_store;
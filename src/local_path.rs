@@ -0,0 +1,240 @@
+//! Validation of local destinations derived from remote paths.
+//!
+//! Anything that writes files named by the server (pulled files, conflict
+//! copies, sidecars) must resolve its destination through
+//! [`resolve_local_destination`] so a malicious or buggy server cannot make us
+//! write outside the configured folder.
+//!
+//! Names the local filesystem rejects (too long, or with characters a FAT or
+//! NTFS mount does not allow) can be mapped to acceptable ones with
+//! [`sanitize_destination`].
+
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Longest file name, in bytes, common local filesystems accept.
+const MAX_NAME_BYTES: usize = 255;
+
+/// Characters FAT, exFAT and NTFS mounts reject in file names.
+const INVALID_CHARS: [char; 8] = ['<', '>', ':', '"', '\\', '|', '?', '*'];
+
+/// Longest extension kept when a name is shortened.
+const MAX_EXTENSION_BYTES: usize = 16;
+
+/// Map a remote path relative to a folder onto a destination below `root`.
+///
+/// The remote path is percent-decoded and split on both `/` and `\`. Absolute
+/// paths, drive prefixes, `..` components, and NUL bytes are rejected, as is
+/// any destination whose closest existing ancestor resolves (through
+/// symlinks) to a location outside `root`, or which is itself a symlink.
+pub fn resolve_local_destination(root: &Path, remote_path: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let reject = |reason: &str| -> Box<dyn Error> {
+        format!("Refusing unsafe remote path '{}': {}", remote_path, reason).into()
+    };
+
+    let decoded = percent_decode_str(remote_path)
+        .decode_utf8()
+        .map_err(|_| reject("not valid UTF-8"))?;
+    if decoded.starts_with('/') || decoded.starts_with('\\') {
+        return Err(reject("absolute path"));
+    }
+
+    let mut destination = root.to_path_buf();
+    let mut components = 0;
+    for (i, part) in decoded.split(['/', '\\']).enumerate() {
+        match part {
+            "" | "." => continue,
+            ".." => return Err(reject("parent directory component")),
+            p if p.contains('\0') => return Err(reject("NUL byte")),
+            p if i == 0 && is_drive_prefix(p) => return Err(reject("drive prefix")),
+            p => {
+                destination.push(p);
+                components += 1;
+            }
+        }
+    }
+    if components == 0 {
+        return Err(reject("empty path"));
+    }
+
+    // Writing through a symlink at the destination itself would escape as well.
+    if destination.symlink_metadata().map(|m| m.file_type().is_symlink()).unwrap_or(false) {
+        return Err(reject("destination is a symlink"));
+    }
+
+    let canonical_root = root.canonicalize()?;
+    let mut ancestor = destination.parent();
+    while let Some(dir) = ancestor {
+        if dir.symlink_metadata().is_ok() {
+            if !dir.canonicalize()?.starts_with(&canonical_root) {
+                return Err(reject("resolves outside the target folder"));
+            }
+            break;
+        }
+        ancestor = dir.parent();
+    }
+
+    Ok(destination)
+}
+
+/// Whether a first path component looks like a Windows drive (`C:`).
+fn is_drive_prefix(component: &str) -> bool {
+    let bytes = component.as_bytes();
+    bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+/// Whether `e` says a file name is too long (`ENAMETOOLONG`) or not valid
+/// (`EINVAL`) on the local filesystem.
+pub fn is_name_error(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::InvalidFilename | io::ErrorKind::InvalidInput)
+}
+
+/// `name` with the characters common local filesystems reject replaced by
+/// `_`, trailing dots and spaces dropped, and shortened to `max_bytes` while
+/// keeping its extension. A changed name gets `~` and a short hash of the
+/// original before the extension, so distinct names stay distinct and a name
+/// always maps to the same result.
+pub fn sanitize_file_name(name: &str, max_bytes: usize) -> String {
+    let replaced: String = name.chars().map(|c| if c.is_control() || INVALID_CHARS.contains(&c) { '_' } else { c }).collect();
+    let cleaned = replaced.trim_end_matches(['.', ' ']);
+    if cleaned == name && name.len() <= max_bytes {
+        return name.to_string();
+    }
+    let tag = format!("~{}", &format!("{:x}", Sha256::digest(name.as_bytes()))[..8]);
+    let (stem, extension) = match cleaned.rfind('.') {
+        Some(i) if i > 0 && cleaned.len() - i <= MAX_EXTENSION_BYTES => cleaned.split_at(i),
+        _ => (cleaned, ""),
+    };
+    let mut end = stem.len().min(max_bytes.saturating_sub(tag.len() + extension.len()));
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}{}", &stem[..end], tag, extension)
+}
+
+/// `destination` below `root` with every component sanitized, the last one
+/// leaving room for a `suffix_bytes` long suffix (e.g. of a download in
+/// progress); `None` if nothing needs to change.
+pub fn sanitize_destination(root: &Path, destination: &Path, suffix_bytes: usize) -> Option<PathBuf> {
+    let relative = destination.strip_prefix(root).ok()?;
+    let count = relative.components().count();
+    let mut sanitized = root.to_path_buf();
+    for (i, component) in relative.components().enumerate() {
+        let name = component.as_os_str().to_string_lossy();
+        let max_bytes = if i + 1 == count { MAX_NAME_BYTES - suffix_bytes } else { MAX_NAME_BYTES };
+        sanitized.push(sanitize_file_name(&name, max_bytes));
+    }
+    Some(sanitized).filter(|sanitized| sanitized != destination)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepts_plain_relative_paths() {
+        let root = tempfile::tempdir().unwrap();
+        let dest = resolve_local_destination(root.path(), "DCIM/Camera/IMG_1.jpg").unwrap();
+        assert_eq!(dest, root.path().join("DCIM").join("Camera").join("IMG_1.jpg"));
+
+        let dest = resolve_local_destination(root.path(), "./a//b/%20c.txt").unwrap();
+        assert_eq!(dest, root.path().join("a").join("b").join(" c.txt"));
+
+        // A literal "..." or a name merely containing dots is fine.
+        assert!(resolve_local_destination(root.path(), "a/.../b..c").is_ok());
+        // Double encoding decodes once to a harmless literal name.
+        assert!(resolve_local_destination(root.path(), "%252e%252e/x").is_ok());
+    }
+
+    #[test]
+    fn test_rejects_crafted_paths() {
+        let root = tempfile::tempdir().unwrap();
+        let hostile = [
+            "../../.ssh/authorized_keys",
+            "a/../../b",
+            "..",
+            "%2e%2e/%2e%2e/etc/passwd",
+            "%2E%2E%2Fx",
+            "a/%2e%2e/%2e%2e/x",
+            "..\\..\\x",
+            "a\\..\\..\\x",
+            "a/..\\..\\x",
+            "/etc/passwd",
+            "%2fetc/passwd",
+            "\\\\server\\share\\x",
+            "C:\\Windows\\x",
+            "c:/x",
+            "a/b%00.txt",
+            "",
+            "./",
+            "%ff%fe",
+        ];
+        for path in hostile {
+            assert!(
+                resolve_local_destination(root.path(), path).is_err(),
+                "accepted hostile path {:?}",
+                path
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rejects_symlink_escapes() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.path().join("link")).unwrap();
+        std::os::unix::fs::symlink(
+            outside.path().join("file"),
+            root.path().join("file_link"),
+        )
+        .unwrap();
+
+        assert!(resolve_local_destination(root.path(), "link/x.txt").is_err());
+        assert!(resolve_local_destination(root.path(), "link/new/dir/x.txt").is_err());
+        assert!(resolve_local_destination(root.path(), "file_link").is_err());
+
+        // Symlinks that stay inside the root are fine.
+        std::fs::create_dir(root.path().join("real")).unwrap();
+        std::os::unix::fs::symlink(root.path().join("real"), root.path().join("inner")).unwrap();
+        assert!(resolve_local_destination(root.path(), "inner/x.txt").is_ok());
+    }
+    #[test]
+    fn test_sanitize_keeps_acceptable_names() {
+        assert_eq!(sanitize_file_name("IMG_1.jpg", 255), "IMG_1.jpg");
+        assert_eq!(sanitize_file_name(&"ä".repeat(100), 255), "ä".repeat(100));
+    }
+
+    #[test]
+    fn test_sanitize_shortens_and_keeps_names_apart() {
+        let long = format!("{}.jpeg", "x".repeat(300));
+        let short = sanitize_file_name(&long, 250);
+        assert_eq!(short.len(), 250);
+        assert!(short.starts_with("xxx") && short.ends_with(".jpeg"), "{}", short);
+        assert_eq!(sanitize_file_name(&long, 250), short);
+        // Names differing only past the cut still differ.
+        assert_ne!(sanitize_file_name(&format!("{}y.jpeg", "x".repeat(300)), 250), short);
+
+        // Multi-byte characters are never split.
+        let umlauts = sanitize_file_name(&format!("{}.txt", "ü".repeat(200)), 100);
+        assert!(umlauts.len() <= 100 && umlauts.ends_with(".txt"), "{}", umlauts);
+        // An over-long "extension" is not kept whole.
+        assert_eq!(sanitize_file_name(&format!("a.{}", "b".repeat(300)), 50).len(), 50);
+    }
+
+    #[test]
+    fn test_sanitize_replaces_invalid_characters() {
+        let a = sanitize_file_name("what? a: day*.jpg", 255);
+        assert!(a.starts_with("what_ a_ day_~") && a.ends_with(".jpg"), "{}", a);
+        assert_ne!(a, sanitize_file_name("what* a: day?.jpg", 255));
+        assert!(sanitize_file_name("trailing. ", 255).starts_with("trailing~"));
+
+        let root = Path::new("/restore");
+        let dest = sanitize_destination(root, &root.join("a|b").join("c.jpg"), 5).unwrap();
+        assert!(dest.starts_with("/restore") && dest.ends_with("c.jpg"), "{}", dest.display());
+        assert_eq!(sanitize_destination(root, &root.join("ok/c.jpg"), 5), None);
+    }
+}
@@ -1,10 +1,37 @@
-use clap::{Parser, Subcommand};
-use env_logger;
+use clap::{Args, Parser, Subcommand};
 use log::{error, info};
-use phone_sync::config::Config;
-use phone_sync::hash_store::HashStore;
-use phone_sync::sync::sync_with_progress;
+use phone_sync::budget::{BudgetStatus, TransferUsage};
+use phone_sync::cas::{self, require_mirror};
+use phone_sync::compact::compact;
+use phone_sync::config::{Config, Layout, NotifyPolicy, RemoteHashStore};
+use phone_sync::daemon::{self, Signals, Wake};
+use phone_sync::doctor;
+use phone_sync::effective_config::{resolve, Overrides};
+use phone_sync::estimate::estimate;
+use phone_sync::filter::{self, FilterSet};
+use phone_sync::first_run::{self, FirstRunChoice};
+use phone_sync::hash_store::{parse_tag, HashStore};
+use phone_sync::journal;
+use phone_sync::migrate::{self, parse_migration};
+use phone_sync::notify::{notify_outcome, DesktopNotifier};
+use phone_sync::output::{render, HumanDisplay, OutputFormat};
+use phone_sync::profile::Phase;
+use phone_sync::progress::{ByteBarObserver, JsonObserver};
+use phone_sync::pull::{pull, read_manifest, store_for_pull};
+use phone_sync::reconcile;
+use phone_sync::self_test::{self_test, throwaway_dir_name};
+use phone_sync::spread::{fresh_seed, jitter, SplitMix64};
+use phone_sync::stage::{replay, stage};
+use phone_sync::units::{format_duration, parse_duration, parse_size};
+use phone_sync::sync::{normalize_store_keys, sync_observed, sync_with_guard};
+use phone_sync::systemd::{watchdog_interval_from_env, SystemdNotifier};
+use phone_sync::verify::quick_verify;
+use phone_sync::webdav_client::WebDavClient;
+use std::io::IsTerminal;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio_util::sync::CancellationToken;
 use walkdir::WalkDir;
 
 use phone_sync::hash_store_guard::HashStoreGuard;
@@ -14,6 +41,10 @@ use phone_sync::hash_store_guard::HashStoreGuard;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Never change anything on the server: every write request is refused
+    /// before it is sent, and sync only reports what it would upload
+    #[arg(long = "read-only", global = true)]
+    read_only: bool,
 }
 
 #[derive(Subcommand)]
@@ -26,9 +57,175 @@ enum Commands {
         /// Show progress bar for missing files
         #[arg(short = 'p', long = "progress")]
         progress: bool,
+        /// Write progress events as JSON lines to stdout instead of a bar,
+        /// moving all other output to stderr
+        #[arg(long = "progress-json", conflicts_with = "progress")]
+        progress_json: bool,
+        /// Show a progress bar of the bytes synced, with the current file and upload speed
+        #[arg(long = "progress-bytes", conflicts_with_all = ["progress", "progress_json"])]
+        progress_bytes: bool,
         /// Use faster pseudo hash (filename, size, first 1 KB)
         #[arg(long = "pseudo")]
         pseudo: bool,
+        /// Always show a desktop notification when the sync finishes
+        #[arg(long = "notify")]
+        notify: bool,
+        /// Format of the summary printed after the sync
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format: OutputFormat,
+        /// Rewrite hash store keys after target_dir was renamed (old=new)
+        #[arg(long = "migrate-target-dir", value_parser = parse_migration)]
+        migrate_target_dir: Option<(String, String)>,
+        /// With --migrate-target-dir, also move the old remote tree to the new location
+        #[arg(long = "move-remote", requires = "migrate_target_dir")]
+        move_remote: bool,
+        /// Print a phase timing breakdown and the slowest files after the sync
+        #[arg(long = "profile-performance")]
+        profile_performance: bool,
+        /// Answer the first-run question (asked when the hash store is empty but the server has the files)
+        #[arg(long = "first-run", value_enum)]
+        first_run: Option<FirstRunChoice>,
+        /// Seed of the sample of uploads verified (verify_sampling), to repeat an earlier run's sample
+        #[arg(long = "seed")]
+        seed: Option<u64>,
+        /// Keep running and sync again this long after each run (e.g. 15m);
+        /// SIGHUP re-reads the config and starts the next run right away
+        #[arg(long = "every", value_parser = parse_duration)]
+        every: Option<Duration>,
+        #[command(flatten)]
+        filters: FilterArgs,
+    },
+    /// Detect changes offline and stage the files to upload in a local directory
+    Stage {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+        /// Directory receiving the staged files and the replay manifest
+        #[arg(long = "staging-dir")]
+        staging_dir: String,
+        /// Hard-link files into the staging directory instead of copying them
+        #[arg(long = "link")]
+        link: bool,
+        /// Use faster pseudo hash (filename, size, first 1 KB)
+        #[arg(long = "pseudo")]
+        pseudo: bool,
+        #[command(flatten)]
+        filters: FilterArgs,
+    },
+    /// Upload previously staged files and update the hash store
+    Replay {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+        /// Directory created by the stage command
+        #[arg(long = "staging-dir")]
+        staging_dir: String,
+    },
+    /// Check local and remote files against the hash store without changing anything
+    Verify {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+        /// Compare metadata only (sizes, mtimes, ETags), without hashing or downloading
+        #[arg(long = "quick", required = true)]
+        quick: bool,
+        /// Format of the drift report
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format: OutputFormat,
+        #[command(flatten)]
+        filters: FilterArgs,
+    },
+    /// Download the files recorded in the hash store, continuing an interrupted pull
+    Pull {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+        /// Discard the progress of an earlier pull and start from scratch
+        #[arg(long = "restart-pull")]
+        restart_pull: bool,
+        /// Only pull files at or below this remote path (repeatable)
+        #[arg(long = "only")]
+        only: Vec<String>,
+        /// Read more --only paths from a manifest, one per line
+        #[arg(long = "files-from")]
+        files_from: Option<String>,
+        /// Format of the pull report
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format: OutputFormat,
+    },
+    /// Delete unreferenced objects of the content-addressed layout
+    Gc {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+        /// Collect the objects of `layout: cas` no manifest entry refers to
+        #[arg(long = "cas", required = true)]
+        cas: bool,
+        /// Format of the report
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format: OutputFormat,
+    },
+    /// Browse the server and pick files to pull
+    #[cfg(feature = "tui")]
+    Browse {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+    },
+    /// Estimate how much a sync would upload by checking a random sample of the files
+    Estimate {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+        /// Number of files to stat and check
+        #[arg(long = "sample", default_value_t = 5000)]
+        sample: usize,
+        /// Seed of the sample, to reproduce an earlier estimate
+        #[arg(long = "seed")]
+        seed: Option<u64>,
+        /// Format of the estimate
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format: OutputFormat,
+        #[command(flatten)]
+        filters: FilterArgs,
+    },
+    /// Sync a generated test tree into a throwaway remote directory and report what works
+    SelfTest {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+        /// Format of the result matrix
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format: OutputFormat,
+    },
+    /// Show what the server supports, as announced in its answer to OPTIONS,
+    /// and whether target_dir exists, creating it if allowed
+    Doctor {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format: OutputFormat,
+    },
+    /// Inspect and annotate the hash store
+    Hashes {
+        #[command(subcommand)]
+        command: HashesCommand,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Inspect the monthly transfer budget
+    Budget {
+        #[command(subcommand)]
+        command: BudgetCommand,
+    },
+    /// Use the journal of remote mutations
+    Journal {
+        #[command(subcommand)]
+        command: JournalCommand,
     },
     /// Generate SHA‑256 hashes for all files under a directory and write them to a YAML file.
     Hash {
@@ -41,6 +238,188 @@ enum Commands {
         /// Use faster pseudo hash (filename, size, first 1 KB)
         #[arg(long = "pseudo")]
         pseudo: bool,
+        #[command(flatten)]
+        filters: FilterArgs,
+    },
+}
+
+/// Pull the files below `only` (everything if empty), exiting with 1 if the
+/// pull was interrupted or some files failed.
+async fn run_pull(cfg: &Config, only: &[String], restart: bool, format: OutputFormat) -> Result<(), Box<dyn std::error::Error>> {
+    let client = WebDavClient::from_config(cfg)?;
+    let store = store_for_pull(cfg, &client).await?;
+    // Ctrl-C stops after keeping the partial download for the next run.
+    let cancel = CancellationToken::new();
+    let on_interrupt = cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            on_interrupt.cancel();
+        }
+    });
+    let report = pull(cfg, &client, &store, only, restart, &cancel).await?;
+    println!("{}", render(&report, format)?);
+    if report.interrupted || !report.failed.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Load the config at `path`, with `--read-only` overriding its `read_only`.
+fn load_config(path: &str, read_only: bool) -> Result<Config, Box<dyn std::error::Error>> {
+    Ok(resolve(path, &Overrides { read_only })?.config)
+}
+
+/// File selection flags shared by every command that walks local folders.
+#[derive(Args, Debug, Default)]
+struct FilterArgs {
+    /// Only process files below this folder-relative path (repeatable)
+    #[arg(long = "only")]
+    only: Vec<String>,
+    /// Only process files modified since a date (YYYY-MM-DD) or age (e.g. 30d, 12h)
+    #[arg(long = "since", value_parser = parse_since)]
+    since: Option<SystemTime>,
+    /// Skip files smaller than this size (e.g. 10M)
+    #[arg(long = "min-size", value_parser = parse_size)]
+    min_size: Option<u64>,
+    /// Skip files larger than this size (e.g. 2G)
+    #[arg(long = "max-size", value_parser = parse_size)]
+    max_size: Option<u64>,
+    /// Skip folder-relative paths matching this wildcard pattern (repeatable)
+    #[arg(long = "exclude")]
+    exclude: Vec<String>,
+}
+
+impl FilterArgs {
+    fn into_filter_set(self) -> FilterSet {
+        FilterSet {
+            only: self.only,
+            since: self.since,
+            min_size: self.min_size,
+            max_size: self.max_size,
+            exclude: self.exclude,
+        }
+    }
+}
+
+fn parse_since(value: &str) -> Result<SystemTime, String> {
+    filter::parse_since(value, SystemTime::now())
+}
+
+#[derive(Subcommand)]
+enum HashesCommand {
+    /// Read or change the user tags of hash store entries
+    Tag {
+        #[command(subcommand)]
+        command: TagCommand,
+    },
+    /// Rewrite keys with backslash separators (from Windows) to '/' in the local hash store
+    Normalize {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+    },
+    /// Remove entries of files missing locally and superseded pseudo hashes
+    /// below target_dir from the local hash store
+    Prune {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+        /// Keep pseudo hashes (if syncs use --pseudo)
+        #[arg(long = "pseudo")]
+        pseudo: bool,
+    },
+    /// Collapse entries recording a file under an earlier target_dir, deleting
+    /// or moving its remote copies there
+    Reconcile {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+        /// Only print the plan, computed from the local hash store
+        #[arg(long = "dry-run")]
+        dry_run: bool,
+        /// Format of the plan
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format: OutputFormat,
+    },
+    /// Print the local hash store as YAML
+    Export {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+        /// Only export entries carrying this tag (key=value)
+        #[arg(long = "filter-tag", value_parser = parse_tag)]
+        filter_tag: Option<(String, String)>,
+    },
+}
+
+#[derive(Subcommand)]
+enum TagCommand {
+    /// Set a tag on an entry and upload the updated hash store
+    Set {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+        /// Remote path of the entry
+        path: String,
+        /// Tag to set (key=value)
+        #[arg(value_parser = parse_tag)]
+        tag: (String, String),
+    },
+    /// Print the tags of an entry from the local hash store
+    Get {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+        /// Remote path of the entry
+        path: String,
+        /// Format of the tags
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the effective configuration, annotated with the source of each setting
+    Show {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+        /// Format of the configuration; JSON lists each setting with its source
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+enum BudgetCommand {
+    /// Print the transfer usage of the current month
+    Status {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+        /// Format of the usage
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+enum JournalCommand {
+    /// Replay the journal to recover state lost on this device
+    Replay {
+        /// Path to config YAML file
+        #[arg(short, long)]
+        config: String,
+        /// Rebuild the hash store from successful uploads, deletes and moves
+        #[arg(long = "rebuild-hashes", required = true)]
+        rebuild_hashes: bool,
+        /// Store the rebuilt hashes as pseudo hashes (if the runs used --pseudo)
+        #[arg(long = "pseudo")]
+        pseudo: bool,
+        /// Where to write the rebuilt store (default: the configured hash_store_path)
+        #[arg(short, long)]
+        output: Option<String>,
     },
 }
 
@@ -49,89 +428,381 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
 
     let cli = Cli::parse();
+    let read_only = cli.read_only;
 
     match cli.command {
-        Commands::Sync { config, progress, pseudo } => {
-            let cfg = Config::load(&config)?;
+        Commands::Sync {
+            config,
+            progress,
+            progress_json,
+            progress_bytes,
+            pseudo,
+            notify,
+            format,
+            mut migrate_target_dir,
+            move_remote,
+            profile_performance,
+            first_run,
+            seed,
+            every,
+            filters,
+        } => {
+            let filters = filters.into_filter_set();
+            let load_sync_config = || -> Result<Config, Box<dyn std::error::Error>> {
+                let mut cfg = load_config(&config, read_only)?;
+                if let (Some(sampling), Some(seed)) = (cfg.verify_sampling.as_mut(), seed) {
+                    sampling.seed = Some(seed);
+                }
+                Ok(cfg)
+            };
+            let mut cfg = load_sync_config()?;
             info!("Loaded config from {}", config);
+            // Installed up front, so that a SIGHUP during the first run is
+            // kept for the wait after it instead of terminating the process.
+            let mut signals = every.map(|_| Signals::install()).transpose()?;
+            let systemd = SystemdNotifier::from_env();
+            let mut watchdog = None;
+            loop {
+                let notify_policy = if notify { NotifyPolicy::Always } else { cfg.desktop_notifications };
+                if !cfg.start_jitter.is_zero() {
+                    let delay = jitter(cfg.start_jitter, &mut SplitMix64::new(fresh_seed()));
+                    info!("Waiting {} before syncing (start_jitter)", format_duration(delay));
+                    tokio::time::sleep(delay).await;
+                }
 
-            // Create a WebDAV client for the guard.
-            let client = phone_sync::webdav_client::WebDavClient::new(
-                &cfg.webdav_url,
-                cfg.username.as_deref(),
-                cfg.password.as_deref(),
-                cfg.timeout_secs,
-            )?;
-
-            // Initialize the guard which ensures the hash store is saved/uploaded.
-            let guard = HashStoreGuard::new(client.clone(), &cfg).await?;
-
-            // Run sync and listen for Ctrl‑C concurrently.
-            tokio::select! {
-                sync_res = sync_with_progress(&cfg, progress, pseudo) => {
-                    // Sync finished (success or error). Ensure guard is finalized.
-                    if let Err(e) = sync_res {
-                        error!("Sync failed: {}", e);
-                        // Attempt to finalize before exiting with error.
-                        let _ = guard.finalize().await;
-                        std::process::exit(1);
+                // Create a WebDAV client for the guard.
+                let client = WebDavClient::from_config(&cfg)?;
+
+                // Initialize the guard which ensures the hash store is saved/uploaded.
+                // A migration rewrites entries of another target_dir.
+                let mut guard = if migrate_target_dir.is_some() {
+                    HashStoreGuard::whole(client.clone(), &cfg).await?
+                } else {
+                    HashStoreGuard::new(client.clone(), &cfg).await?
+                };
+
+                // Only the first run migrates.
+                if let Some((old, new)) = migrate_target_dir.take() {
+                    require_mirror(&cfg, "--migrate-target-dir")?;
+                    if new != cfg.target_dir.trim_matches('/') {
+                        return Err(format!("target_dir in the config is '{}', not '{}'", cfg.target_dir, new).into());
                     }
-                    // Normal completion – finalize guard.
-                    guard.finalize().await?;
-                    info!("Sync completed successfully");
+                    migrate::migrate_target_dir(&client, guard.hash_store_mut(), &old, &new, move_remote).await?;
                 }
-                _ = tokio::signal::ctrl_c() => {
-                    // On interrupt, finalize the guard and exit.
-                    let _ = guard.finalize().await;
-                    std::process::exit(0);
+
+                first_run::guide(&cfg, &client, guard.hash_store_mut(), &filters, pseudo, |found| match first_run {
+                    Some(choice) => Ok(choice),
+                    None if std::io::stdin().is_terminal() => {
+                        // With --progress-json, stdout carries nothing but events.
+                        let mut out: Box<dyn std::io::Write> =
+                            if progress_json { Box::new(std::io::stderr()) } else { Box::new(std::io::stdout()) };
+                        Ok(first_run::prompt(found, &mut std::io::stdin().lock(), &mut out)?)
+                    }
+                    None => Err(first_run::guidance(found).into()),
+                })
+                .await?;
+
+                // Under a `Type=notify` unit, the service is up once the first scan begins.
+                if watchdog.is_none() {
+                    systemd.ready();
+                    watchdog = Some(watchdog_interval_from_env().filter(|_| systemd.is_enabled()).map(|every| {
+                        let systemd = systemd.clone();
+                        tokio::spawn(async move {
+                            let mut ticks = tokio::time::interval(every);
+                            loop {
+                                ticks.tick().await;
+                                systemd.watchdog();
+                            }
+                        })
+                    }));
                 }
-            }
-        }
-        Commands::Hash { target_dir, output, pseudo } => {
-            let target_path = Path::new(&target_dir);
-            if !target_path.is_dir() {
-                return Err(format!("Target path '{}' is not a directory", target_dir).into());
-            }
-            #[cfg(test)]
-            mod tests {
-                use super::*;
-                use clap::Parser;
-            
-                #[test]
-                fn test_cli_hash_parsing_with_output() {
-                    let args = Cli::parse_from(&[
-                        "my_binary",
-                        "hash",
-                        "-t",
-                        "/tmp/target_dir",
-                        "-o",
-                        "custom_hashes.yaml",
-                        "--pseudo",
-                    ]);
-                    match args.command {
-                        Commands::Hash { target_dir, output, pseudo } => {
-                            assert_eq!(target_dir, "/tmp/target_dir");
-                            assert_eq!(output.unwrap(), "custom_hashes.yaml");
-                            assert!(pseudo);
+
+                // Run sync and listen for Ctrl‑C concurrently.
+                let sync_res = tokio::select! {
+                    res = async {
+                        if progress_json {
+                            let observer = Arc::new(JsonObserver::new(Box::new(std::io::stdout())));
+                            sync_observed(&cfg, &client, &mut guard, observer, pseudo, &filters).await
+                        } else if progress_bytes {
+                            let observer = Arc::new(ByteBarObserver::new(!cfg.low_memory)?);
+                            sync_observed(&cfg, &client, &mut guard, observer, pseudo, &filters).await
+                        } else {
+                            sync_with_guard(&cfg, &client, &mut guard, progress, pseudo, &filters).await
+                        }
+                    } => Some(res),
+                    _ = tokio::signal::ctrl_c() => None,
+                };
+
+                // Sync finished, failed, or was interrupted. Ensure guard is finalized.
+                let finalize_start = Instant::now();
+                let finalize_res = guard.finalize().await;
+                let finalize_time = finalize_start.elapsed();
+                let Some(sync_res) = sync_res else {
+                    systemd.stopping();
+                    // `exit` skips destructors; drop the guard to remove its work dir.
+                    drop(guard);
+                    std::process::exit(0);
+                };
+                let outcome = sync_res
+                    .map(|mut report| {
+                        report.profile.add_time(Phase::Finalize, finalize_time);
+                        report.profile.total_micros += finalize_time.as_micros() as u64;
+                        report
+                    })
+                    .map_err(|e| e.to_string())
+                    .and_then(|report| finalize_res.map(|_| report).map_err(|e| e.to_string()));
+                notify_outcome(&DesktopNotifier, notify_policy, &outcome);
+                systemd.status(&outcome);
+                match outcome {
+                    Ok(report) => {
+                        info!("Sync completed successfully");
+                        let mut output = render(&report, format)?;
+                        if profile_performance {
+                            output.push_str(&format!("\n\n{}", report.profile.table()));
+                        }
+                        if let Some(summary) = client.read_only_summary() {
+                            output.push_str(&format!("\n{}", summary));
+                        }
+                        if progress_json {
+                            eprintln!("{}", output);
+                        } else {
+                            println!("{}", output);
+                        }
+                    }
+                    Err(e) => {
+                        error!("Sync failed: {}", e);
+                        if let Some(summary) = client.read_only_summary() {
+                            eprintln!("{}", summary);
+                        }
+                        // Repeated runs go on; the next one may succeed.
+                        if every.is_none() {
+                            systemd.stopping();
+                            drop(guard);
+                            std::process::exit(1);
                         }
-                        _ => panic!("Expected Hash command"),
                     }
                 }
-            
-                #[test]
-                fn test_cli_hash_parsing_without_output() {
-                    let args = Cli::parse_from(&["my_binary", "hash", "-t", "/tmp/target_dir"]);
-                    match args.command {
-                        Commands::Hash { target_dir, output, pseudo } => {
-                            assert_eq!(target_dir, "/tmp/target_dir");
-                            assert!(output.is_none());
-                            assert!(!pseudo);
+                drop(guard);
+
+                let (Some(every), Some(signals)) = (every, signals.as_mut()) else {
+                    break;
+                };
+                info!("Syncing again in {}", format_duration(every));
+                match signals.wait(every).await {
+                    Wake::Due => {}
+                    Wake::Reload => {
+                        if let Some(reloaded) = daemon::reload(&systemd, load_sync_config) {
+                            cfg = reloaded;
                         }
-                        _ => panic!("Expected Hash command"),
                     }
+                    Wake::Stop => break,
                 }
             }
-    
+            if let Some(Some(watchdog)) = watchdog {
+                watchdog.abort();
+            }
+            systemd.stopping();
+        }
+        Commands::Stage { config, staging_dir, link, pseudo, filters } => {
+            let cfg = load_config(&config, read_only)?;
+            let filters = filters.into_filter_set();
+            let manifest = stage(&cfg, Path::new(&staging_dir), link, pseudo, &filters).await?;
+            println!("Staged {} files in {}", manifest.entries.len(), staging_dir);
+        }
+        Commands::Replay { config, staging_dir } => {
+            let cfg = load_config(&config, read_only)?;
+            let report = replay(&cfg, Path::new(&staging_dir)).await?;
+            println!(
+                "{} files uploaded, {} already uploaded, {} changed since staging",
+                report.uploaded,
+                report.already_uploaded,
+                report.drifted.len()
+            );
+            for path in &report.drifted {
+                println!("  changed: {}", path);
+            }
+        }
+        Commands::Verify { config, quick: _, format, filters } => {
+            let cfg = load_config(&config, read_only)?;
+            let filters = filters.into_filter_set();
+            let client = WebDavClient::from_config(&cfg)?;
+            let store = HashStore::load(&cfg.hash_store_path)?;
+            let report = quick_verify(&cfg, &client, &store, &filters).await?;
+            println!("{}", render(&report, format)?);
+            if let Some(summary) = client.read_only_summary() {
+                println!("{}", summary);
+            }
+            if report.has_drift() {
+                std::process::exit(1);
+            }
+        }
+        Commands::Estimate { config, sample, seed, format, filters } => {
+            let cfg = load_config(&config, read_only)?;
+            let client = WebDavClient::from_config(&cfg)?;
+            let store = store_for_pull(&cfg, &client).await?;
+            let seed = seed.unwrap_or_else(fresh_seed);
+            info!("Sampling with seed {}", seed);
+            let report = estimate(&cfg, &store, &filters.into_filter_set(), sample, &mut SplitMix64::new(seed)).await?;
+            println!("{}", render(&report, format)?);
+        }
+        Commands::Pull { config, restart_pull, mut only, files_from, format } => {
+            let cfg = load_config(&config, read_only)?;
+            if let Some(manifest) = files_from {
+                only.extend(read_manifest(manifest)?);
+            }
+            run_pull(&cfg, &only, restart_pull, format).await?;
+        }
+        Commands::Gc { config, cas: _, format } => {
+            let cfg = load_config(&config, read_only)?;
+            if cfg.layout != Layout::Cas {
+                return Err("gc --cas needs layout: cas in the config".into());
+            }
+            let client = WebDavClient::from_config(&cfg)?;
+            let report = cas::gc(&cfg, &client).await?;
+            println!("{}", render(&report, format)?);
+        }
+        #[cfg(feature = "tui")]
+        Commands::Browse { config } => {
+            use phone_sync::pull::format_manifest;
+            use phone_sync::tui::{self, BrowseAction};
+            let cfg = load_config(&config, read_only)?;
+            let client = WebDavClient::from_config(&cfg)?;
+            match tui::run(&client).await? {
+                BrowseAction::Pull(selection) => run_pull(&cfg, &selection, false, OutputFormat::Human).await?,
+                BrowseAction::PrintManifest(selection) => print!("{}", format_manifest(&selection)),
+                BrowseAction::Quit => {}
+            }
+        }
+        Commands::SelfTest { config, format } => {
+            let cfg = load_config(&config, read_only)?;
+            let report = self_test(&cfg, &throwaway_dir_name()).await?;
+            println!("{}", render(&report, format)?);
+            if !report.passed() {
+                std::process::exit(1);
+            }
+        }
+        Commands::Doctor { config, format } => {
+            let cfg = load_config(&config, read_only)?;
+            let client = WebDavClient::from_config(&cfg)?;
+            println!("{}", render(&doctor::doctor(&client, &cfg).await?, format)?);
+        }
+        Commands::Hashes { command: HashesCommand::Tag { command } } => match command {
+            TagCommand::Set { config, path, tag: (key, value) } => {
+                if read_only {
+                    return Err("hashes tag set changes the hash store and cannot run with --read-only".into());
+                }
+                let cfg = load_config(&config, read_only)?;
+                let client = WebDavClient::from_config(&cfg)?;
+                // Go through the guard so the tag lands in the remote store as well.
+                let mut guard = HashStoreGuard::whole(client, &cfg).await?;
+                let store = guard.hash_store_mut();
+                if !store.regular_hashes.contains_key(&path) && !store.pseudo_hashes.contains_key(&path) {
+                    return Err(format!("No hash store entry for '{}'", path).into());
+                }
+                store.set_tag(&path, &key, &value);
+                guard.finalize().await?;
+            }
+            TagCommand::Get { config, path, format } => {
+                let cfg = load_config(&config, read_only)?;
+                let store = HashStore::load(&cfg.hash_store_path)?;
+                println!("{}", render(&store.entry_tags(&path), format)?);
+            }
+        },
+        Commands::Hashes { command: HashesCommand::Normalize { config } } => {
+            let cfg = load_config(&config, read_only)?;
+            let mut store = HashStore::load(&cfg.hash_store_path)?;
+            let outcome = normalize_store_keys(&cfg, &mut store);
+            if outcome.rewritten == 0 {
+                println!("No keys with backslashes in {}", cfg.hash_store_path);
+            } else {
+                // Offline only: the next sync uploads the fixed store instead of
+                // reading the remote copy.
+                store.remote_upload_pending = cfg.remote_hash_store == RemoteHashStore::Enabled;
+                store.save(&cfg.hash_store_path)?;
+                println!(
+                    "Rewrote {} keys, dropped {} duplicates; the next sync uploads the fixed store",
+                    outcome.rewritten, outcome.merged
+                );
+            }
+        }
+        Commands::Hashes { command: HashesCommand::Prune { config, pseudo } } => {
+            let cfg = load_config(&config, read_only)?;
+            let mut store = HashStore::load(&cfg.hash_store_path)?;
+            let compaction = compact(&cfg, &mut store, pseudo)?;
+            if compaction.total() == 0 {
+                println!("Nothing to prune in {}", cfg.hash_store_path);
+            } else {
+                // Offline like `hashes normalize`: the next sync uploads the pruned store.
+                store.remote_upload_pending = cfg.remote_hash_store == RemoteHashStore::Enabled;
+                store.save(&cfg.hash_store_path)?;
+                println!(
+                    "Removed {} entries of missing files and {} superseded pseudo hashes; the next sync uploads the pruned store",
+                    compaction.missing_local, compaction.superseded_pseudo
+                );
+            }
+        }
+        Commands::Hashes { command: HashesCommand::Reconcile { config, dry_run, format } } => {
+            let cfg = load_config(&config, read_only)?;
+            if dry_run {
+                let store = HashStore::load(&cfg.hash_store_path)?;
+                println!("{}", render(&reconcile::plan(&cfg, &store), format)?);
+                return Ok(());
+            }
+            if read_only {
+                return Err("hashes reconcile changes the server and cannot run with --read-only; use --dry-run".into());
+            }
+            let client = WebDavClient::from_config(&cfg)?;
+            let mut guard = HashStoreGuard::whole(client.clone(), &cfg).await?;
+            let plan = reconcile::plan(&cfg, guard.hash_store_mut());
+            reconcile::execute(&client, guard.hash_store_mut(), &plan).await?;
+            guard.finalize().await?;
+            println!("{}", render(&plan, format)?);
+        }
+        Commands::Hashes { command: HashesCommand::Export { config, filter_tag } } => {
+            let cfg = load_config(&config, read_only)?;
+            let mut store = HashStore::load(&cfg.hash_store_path)?;
+            if let Some((key, value)) = filter_tag {
+                store = store.filter_by_tag(&key, &value);
+            }
+            print!("{}", serde_yaml::to_string(&store)?);
+        }
+        Commands::Config { command: ConfigCommand::Show { config, format } } => {
+            let settings = resolve(&config, &Overrides { read_only })?.settings()?;
+            // The human form is YAML that already ends in a newline.
+            match format {
+                OutputFormat::Human => print!("{}", settings.human()),
+                OutputFormat::Json => println!("{}", render(&settings, format)?),
+            }
+        }
+        Commands::Budget { command: BudgetCommand::Status { config, format } } => {
+            let cfg = load_config(&config, read_only)?;
+            let Some(budget) = &cfg.transfer_budget else {
+                return Err("No transfer_budget configured".into());
+            };
+            let usage = TransferUsage::load(&budget.state_path, SystemTime::now())?;
+            println!("{}", render(&BudgetStatus::new(budget, &usage), format)?);
+        }
+        Commands::Journal { command: JournalCommand::Replay { config, rebuild_hashes: _, pseudo, output } } => {
+            let cfg = load_config(&config, read_only)?;
+            let Some(journal_path) = &cfg.journal_path else {
+                return Err("No journal_path configured".into());
+            };
+            let output = output.unwrap_or_else(|| cfg.hash_store_path.clone());
+            if Path::new(&output).exists() {
+                return Err(format!("'{}' already exists; move it away to rebuild it", output).into());
+            }
+            let entries = journal::read_entries(journal_path)?;
+            let mut store = HashStore::default();
+            let count = journal::rebuild_hashes(&entries, &mut store, pseudo);
+            store.save(&output)?;
+            println!("Rebuilt {} hashes from {} journal entries into {}", count, entries.len(), output);
+        }
+        Commands::Hash { target_dir, output, pseudo, filters } => {
+            let filters = filters.into_filter_set();
+            let target_path = Path::new(&target_dir);
+            if !target_path.is_dir() {
+                return Err(format!("Target path '{}' is not a directory", target_dir).into());
+            }
             let mut store = HashStore::default();
     
             for entry in WalkDir::new(target_path)
@@ -140,15 +811,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .filter(|e| e.file_type().is_file())
             {
                 let file_path = entry.path();
+                let rel_path = file_path
+                    .strip_prefix(target_path)?
+                    .to_string_lossy()
+                    .to_string();
+                if !filters.matches(&rel_path, &entry.metadata()?) {
+                    continue;
+                }
                 let hash = if pseudo {
                     HashStore::compute_pseudo_hash(file_path).await?
                 } else {
                     HashStore::compute_hash(file_path).await?
                 };
-                let rel_path = file_path
-                    .strip_prefix(target_path)?
-                    .to_string_lossy()
-                    .to_string();
                 store.regular_hashes.insert(rel_path, hash);
             }
     
@@ -159,4 +833,240 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_cli_hash_parsing_with_output() {
+        let args = Cli::parse_from([
+            "my_binary",
+            "hash",
+            "-t",
+            "/tmp/target_dir",
+            "-o",
+            "custom_hashes.yaml",
+            "--pseudo",
+        ]);
+        match args.command {
+            Commands::Hash { target_dir, output, pseudo, .. } => {
+                assert_eq!(target_dir, "/tmp/target_dir");
+                assert_eq!(output.unwrap(), "custom_hashes.yaml");
+                assert!(pseudo);
+            }
+            _ => panic!("Expected Hash command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_hashes_reconcile_parsing() {
+        let args = Cli::parse_from(["my_binary", "hashes", "reconcile", "-c", "cfg.yaml", "--dry-run"]);
+        assert!(matches!(args.command, Commands::Hashes { command: HashesCommand::Reconcile { dry_run: true, .. } }));
+    }
+
+    #[test]
+    fn test_cli_hashes_tag_parsing() {
+        let args = Cli::parse_from([
+            "my_binary", "hashes", "tag", "set", "-c", "cfg.yaml", "DCIM/a.jpg", "archived=tape",
+        ]);
+        match args.command {
+            Commands::Hashes { command: HashesCommand::Tag { command: TagCommand::Set { path, tag, .. } } } => {
+                assert_eq!(path, "DCIM/a.jpg");
+                assert_eq!(tag, ("archived".to_string(), "tape".to_string()));
+            }
+            _ => panic!("Expected hashes tag set command"),
+        }
+        assert!(Cli::try_parse_from(["my_binary", "hashes", "tag", "set", "-c", "c", "p", "bad"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_hashes_prune_parsing() {
+        let args = Cli::parse_from(["my_binary", "hashes", "prune", "-c", "cfg.yaml", "--pseudo"]);
+        match args.command {
+            Commands::Hashes { command: HashesCommand::Prune { config, pseudo } } => {
+                assert_eq!(config, "cfg.yaml");
+                assert!(pseudo);
+            }
+            _ => panic!("Expected hashes prune command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_config_show_parsing() {
+        let args = Cli::parse_from(["my_binary", "config", "show", "-c", "cfg.yaml", "--read-only"]);
+        match args.command {
+            Commands::Config { command: ConfigCommand::Show { config, format } } => {
+                assert_eq!(config, "cfg.yaml");
+                assert_eq!(format, OutputFormat::Human);
+            }
+            _ => panic!("Expected config show command"),
+        }
+        assert!(args.read_only);
+    }
+
+    #[test]
+    fn test_cli_read_only_commands_accept_format() {
+        for command in [
+            &["config", "show"][..],
+            &["budget", "status"],
+            &["hashes", "tag", "get", "a.jpg"],
+            &["hashes", "reconcile", "--dry-run"],
+            &["verify", "--quick"],
+            &["doctor"],
+        ] {
+            let args = ["my_binary"].iter().chain(command).chain(&["-c", "cfg.yaml", "--format", "json"]);
+            assert!(Cli::try_parse_from(args).is_ok(), "{:?}", command);
+        }
+    }
+
+    #[test]
+    fn test_cli_journal_replay_parsing() {
+        let args = Cli::parse_from(["my_binary", "journal", "replay", "-c", "cfg.yaml", "--rebuild-hashes", "--pseudo"]);
+        match args.command {
+            Commands::Journal { command: JournalCommand::Replay { config, pseudo, output, .. } } => {
+                assert_eq!(config, "cfg.yaml");
+                assert!(pseudo);
+                assert_eq!(output, None);
+            }
+            _ => panic!("Expected journal replay command"),
+        }
+        assert!(Cli::try_parse_from(["my_binary", "journal", "replay", "-c", "cfg.yaml"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_self_test_parsing() {
+        let args = Cli::parse_from(["my_binary", "self-test", "--config", "cfg.yaml", "--format", "json"]);
+        match args.command {
+            Commands::SelfTest { config, format } => {
+                assert_eq!(config, "cfg.yaml");
+                assert_eq!(format, OutputFormat::Json);
+            }
+            _ => panic!("Expected self-test command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_doctor_parsing() {
+        let args = Cli::parse_from(["my_binary", "doctor", "-c", "cfg.yaml"]);
+        match args.command {
+            Commands::Doctor { config, format } => {
+                assert_eq!(config, "cfg.yaml");
+                assert_eq!(format, OutputFormat::Human);
+            }
+            _ => panic!("Expected doctor command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_pull_parsing() {
+        let args = Cli::parse_from([
+            "my_binary", "pull", "-c", "cfg.yaml", "--restart-pull", "--only", "photos/2024", "--files-from", "sel.txt",
+        ]);
+        match args.command {
+            Commands::Pull { config, restart_pull, only, files_from, format } => {
+                assert_eq!(config, "cfg.yaml");
+                assert!(restart_pull);
+                assert_eq!(only, vec!["photos/2024"]);
+                assert_eq!(files_from.as_deref(), Some("sel.txt"));
+                assert_eq!(format, OutputFormat::Human);
+            }
+            _ => panic!("Expected pull command"),
+        }
+    }
+
+    #[test]
+    fn test_cli_gc_parsing() {
+        let args = Cli::parse_from(["my_binary", "gc", "-c", "cfg.yaml", "--cas"]);
+        assert!(matches!(args.command, Commands::Gc { config, cas: true, .. } if config == "cfg.yaml"));
+        assert!(Cli::try_parse_from(["my_binary", "gc", "-c", "cfg.yaml"]).is_err());
+    }
+
+    #[cfg(feature = "tui")]
+    #[test]
+    fn test_cli_browse_parsing() {
+        let args = Cli::parse_from(["my_binary", "browse", "--config", "cfg.yaml"]);
+        assert!(matches!(args.command, Commands::Browse { config } if config == "cfg.yaml"));
+    }
+
+    #[test]
+    fn test_cli_estimate_parsing() {
+        let args = Cli::parse_from(["my_binary", "estimate", "-c", "cfg.yaml", "--sample", "200", "--seed", "7"]);
+        match args.command {
+            Commands::Estimate { config, sample, seed, .. } => {
+                assert_eq!(config, "cfg.yaml");
+                assert_eq!(sample, 200);
+                assert_eq!(seed, Some(7));
+            }
+            _ => panic!("Expected estimate command"),
+        }
+        let args = Cli::parse_from(["my_binary", "estimate", "-c", "cfg.yaml"]);
+        assert!(matches!(args.command, Commands::Estimate { sample: 5000, seed: None, .. }));
+    }
+
+    #[test]
+    fn test_cli_read_only_flag() {
+        let args = Cli::parse_from(["my_binary", "sync", "-c", "cfg.yaml", "--read-only"]);
+        assert!(args.read_only);
+        assert!(matches!(args.command, Commands::Sync { .. }));
+        let args = Cli::parse_from(["my_binary", "--read-only", "verify", "-c", "cfg.yaml", "--quick"]);
+        assert!(args.read_only);
+        assert!(!Cli::parse_from(["my_binary", "sync", "-c", "cfg.yaml"]).read_only);
+    }
+
+    #[test]
+    fn test_cli_progress_bytes() {
+        let args = Cli::parse_from(["my_binary", "sync", "-c", "cfg.yaml", "--progress-bytes"]);
+        assert!(matches!(args.command, Commands::Sync { progress_bytes: true, progress: false, .. }));
+        assert!(Cli::try_parse_from(["my_binary", "sync", "-c", "c.yaml", "--progress-bytes", "-p"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_sync_seed() {
+        let args = Cli::parse_from(["my_binary", "sync", "-c", "cfg.yaml", "--seed", "42"]);
+        assert!(matches!(args.command, Commands::Sync { seed: Some(42), .. }));
+        let args = Cli::parse_from(["my_binary", "sync", "-c", "cfg.yaml"]);
+        assert!(matches!(args.command, Commands::Sync { seed: None, .. }));
+    }
+
+    #[test]
+    fn test_cli_sync_every() {
+        let args = Cli::parse_from(["my_binary", "sync", "-c", "cfg.yaml", "--every", "15m"]);
+        assert!(matches!(args.command, Commands::Sync { every: Some(d), .. } if d == Duration::from_secs(900)));
+        assert!(Cli::try_parse_from(["my_binary", "sync", "-c", "cfg.yaml", "--every", "15"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_filter_flags() {
+        let args = Cli::parse_from([
+            "my_binary", "hash", "-t", "/tmp/t", "--only", "DCIM", "--only", "Pictures",
+            "--since", "2024-03-01", "--max-size", "2G", "--exclude", "*.tmp",
+        ]);
+        match args.command {
+            Commands::Hash { filters, .. } => {
+                let filters = filters.into_filter_set();
+                assert_eq!(filters.only, vec!["DCIM", "Pictures"]);
+                assert_eq!(filters.since, Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_709_251_200)));
+                assert_eq!(filters.max_size, Some(2_000_000_000));
+                assert_eq!(filters.exclude, vec!["*.tmp"]);
+            }
+            _ => panic!("Expected Hash command"),
+        }
+        assert!(Cli::try_parse_from(["my_binary", "sync", "-c", "c.yaml", "--since", "soon"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_hash_parsing_without_output() {
+        let args = Cli::parse_from(["my_binary", "hash", "-t", "/tmp/target_dir"]);
+        match args.command {
+            Commands::Hash { target_dir, output, pseudo, .. } => {
+                assert_eq!(target_dir, "/tmp/target_dir");
+                assert!(output.is_none());
+                assert!(!pseudo);
+            }
+            _ => panic!("Expected Hash command"),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! Resident memory of the process, as the kernel reports it.
+//!
+//! Read from `/proc/self/status`, so only known on Linux; elsewhere every
+//! reading is `None`.
+
+/// Current resident set size.
+pub fn resident_bytes() -> Option<u64> {
+    status_kib("VmRSS:").map(|kib| kib * 1024)
+}
+
+/// Peak resident set size of the process so far.
+pub fn peak_resident_bytes() -> Option<u64> {
+    status_kib("VmHWM:").map(|kib| kib * 1024)
+}
+
+fn status_kib(field: &str) -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with(field))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peak_is_at_least_current() {
+        let current = resident_bytes().unwrap();
+        assert!(current > 0);
+        assert!(peak_resident_bytes().unwrap() >= current);
+    }
+}
@@ -0,0 +1,152 @@
+//! Migration of hash store keys after `target_dir` changed.
+//!
+//! Store keys are remote paths and therefore embed `target_dir`. When the
+//! remote folder is renamed, rewriting the key prefixes (and optionally moving
+//! the remote tree) avoids re-uploading everything.
+
+use crate::hash_store::HashStore;
+use crate::webdav_client::WebDavClient;
+use log::info;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Parse an `old=new` target_dir migration argument.
+pub fn parse_migration(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((old, new)) if old != new => Ok((normalize(old), normalize(new))),
+        _ => Err(format!("Invalid migration '{}', expected old=new", arg)),
+    }
+}
+
+fn normalize(dir: &str) -> String {
+    dir.trim_matches('/').to_string()
+}
+
+/// Rewrite every key below `old` to the same path below `new`.
+///
+/// Applies to both hash maps and all per-path metadata, records `new` as the
+/// store's target_dir, and returns the number of rewritten hash entries.
+pub fn rewrite_target_dir(store: &mut HashStore, old: &str, new: &str) -> usize {
+    let (old, new) = (normalize(old), normalize(new));
+    let rewrite = |path: &str| -> Option<String> {
+        let rest = if old.is_empty() { Some(path) } else { path.strip_prefix(&format!("{}/", old)) }?;
+        Some(if new.is_empty() { rest.to_string() } else { format!("{}/{}", new, rest) })
+    };
+    fn apply<V>(map: &mut BTreeMap<String, V>, rewrite: &dyn Fn(&str) -> Option<String>) -> usize {
+        let mut count = 0;
+        *map = std::mem::take(map)
+            .into_iter()
+            .map(|(path, value)| match rewrite(&path) {
+                Some(moved) => {
+                    count += 1;
+                    (moved, value)
+                }
+                None => (path, value),
+            })
+            .collect();
+        count
+    }
+    let count = apply(&mut store.regular_hashes, &rewrite) + apply(&mut store.pseudo_hashes, &rewrite);
+    apply(&mut store.tags, &rewrite);
+    apply(&mut store.fingerprints, &rewrite);
+    apply(&mut store.stamps, &rewrite);
+    apply(&mut store.chunks, &rewrite);
+    apply(&mut store.server_names, &rewrite);
+    apply(&mut store.origins, &rewrite);
+    for name in store.server_names.values_mut() {
+        if let Some(moved) = rewrite(name) {
+            *name = moved;
+        }
+    }
+    store.target_dir = Some(new);
+    count
+}
+
+/// Migrate `store` from target_dir `old` to `new`.
+///
+/// With `move_remote`, the old remote tree is moved to the new location when
+/// it still exists. Refuses to touch anything when both trees exist, since
+/// merging them could silently shadow files.
+pub async fn migrate_target_dir(
+    client: &WebDavClient,
+    store: &mut HashStore,
+    old: &str,
+    new: &str,
+    move_remote: bool,
+) -> Result<usize, Box<dyn Error>> {
+    let (old, new) = (normalize(old), normalize(new));
+    if let Some(recorded) = &store.target_dir {
+        if normalize(recorded) != old {
+            return Err(format!(
+                "Hash store was last synced to target_dir '{}', not '{}'",
+                recorded, old
+            )
+            .into());
+        }
+    }
+
+    if move_remote {
+        if old.is_empty() || new.is_empty() {
+            return Err("Cannot move the remote root; migrate without moving instead".into());
+        }
+        let old_exists = client.file_exists(&format!("{}/", old)).await?;
+        let new_exists = client.file_exists(&format!("{}/", new)).await?;
+        match (old_exists, new_exists) {
+            (true, true) => {
+                return Err(format!(
+                    "Both '{}' and '{}' exist on the remote, refusing to migrate",
+                    old, new
+                )
+                .into())
+            }
+            (true, false) => {
+                client.move_path(&format!("{}/", old), &format!("{}/", new), false).await?;
+                info!("Moved remote '{}' to '{}'", old, new);
+            }
+            _ => {}
+        }
+    }
+
+    let count = rewrite_target_dir(store, &old, &new);
+    info!("Rewrote {} hash store entries from '{}' to '{}'", count, old, new);
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_target_dir() {
+        let mut store = HashStore::default();
+        store.regular_hashes.insert("phone/a.jpg".to_string(), "h1".to_string());
+        store.regular_hashes.insert("phone2/b.jpg".to_string(), "h2".to_string());
+        store.pseudo_hashes.insert("phone/DCIM/c.jpg".to_string(), "h3".to_string());
+        store.set_tag("phone/a.jpg", "archived", "tape");
+
+        assert_eq!(rewrite_target_dir(&mut store, "phone", "backups/phone/"), 2);
+        assert_eq!(
+            store.regular_hashes.keys().collect::<Vec<_>>(),
+            vec!["backups/phone/a.jpg", "phone2/b.jpg"]
+        );
+        assert!(store.pseudo_hashes.contains_key("backups/phone/DCIM/c.jpg"));
+        assert!(store.has_tag("backups/phone/a.jpg", "archived", "tape"));
+        assert_eq!(store.target_dir.as_deref(), Some("backups/phone"));
+
+        // From and to the remote root.
+        assert_eq!(rewrite_target_dir(&mut store, "backups/phone", ""), 2);
+        assert!(store.regular_hashes.contains_key("a.jpg"));
+        assert_eq!(rewrite_target_dir(&mut store, "", "x"), 3);
+        assert!(store.regular_hashes.contains_key("x/phone2/b.jpg"));
+    }
+
+    #[test]
+    fn test_parse_migration() {
+        assert_eq!(
+            parse_migration("phone=/backups/phone/"),
+            Ok(("phone".to_string(), "backups/phone".to_string()))
+        );
+        assert!(parse_migration("phone").is_err());
+        assert!(parse_migration("a=a").is_err());
+    }
+}
@@ -0,0 +1,79 @@
+//! Guarding folders on removable or network storage.
+//!
+//! An unmounted disk leaves its mount point behind as an empty directory,
+//! which a sync would take for a folder whose files were all deleted. With
+//! `require_mounted`, a folder is only synced while a filesystem other than
+//! its parent's is mounted on it; with `require_marker_file`, only while the
+//! marker file exists in it, which also catches empty bind mounts and works
+//! on platforms without device ids.
+
+use crate::config::FolderConfig;
+use std::path::Path;
+
+/// Why `folder` must not be synced now, if its mount requirements fail.
+pub fn check_mounted(folder: &FolderConfig) -> Result<(), String> {
+    let path = Path::new(&folder.path);
+    if let Some(marker) = &folder.require_marker_file {
+        if !path.join(marker).is_file() {
+            return Err(format!("{} has no marker file {}", folder.path, marker));
+        }
+    }
+    if folder.require_mounted && !is_mount_point(path)? {
+        return Err(format!("nothing is mounted on {}", folder.path));
+    }
+    Ok(())
+}
+
+/// Whether `path` is the root of a mounted filesystem, i.e. its device
+/// differs from its parent's.
+#[cfg(unix)]
+pub fn is_mount_point(path: &Path) -> Result<bool, String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let path = path.canonicalize().map_err(|e| format!("cannot resolve {}: {}", path.display(), e))?;
+    let Some(parent) = path.parent() else {
+        return Ok(true);
+    };
+    let device = |p: &Path| p.metadata().map(|m| m.dev()).map_err(|e| format!("cannot stat {}: {}", p.display(), e));
+    Ok(device(&path)? != device(parent)?)
+}
+
+#[cfg(not(unix))]
+pub fn is_mount_point(path: &Path) -> Result<bool, String> {
+    Err(format!("cannot check whether {} is mounted on this platform, use require_marker_file", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn folder(path: &Path, require_mounted: bool, marker: Option<&str>) -> FolderConfig {
+        FolderConfig {
+            path: path.display().to_string(),
+            require_mounted,
+            require_marker_file: marker.map(str::to_string),
+            ..FolderConfig::new("")
+        }
+    }
+
+    #[test]
+    fn test_marker_file_must_exist() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(check_mounted(&folder(dir.path(), false, None)).is_ok());
+        let err = check_mounted(&folder(dir.path(), false, Some(".synced_root"))).unwrap_err();
+        assert!(err.contains("no marker file .synced_root"), "{}", err);
+        fs::write(dir.path().join(".synced_root"), "").unwrap();
+        assert!(check_mounted(&folder(dir.path(), false, Some(".synced_root"))).is_ok());
+    }
+
+    #[test]
+    fn test_plain_directory_is_not_a_mount_point() {
+        let dir = tempfile::tempdir().unwrap();
+        let photos = dir.path().join("photos");
+        fs::create_dir(&photos).unwrap();
+        assert!(!is_mount_point(&photos).unwrap());
+        assert!(is_mount_point(Path::new("/")).unwrap());
+        assert!(check_mounted(&folder(&photos, true, None)).unwrap_err().contains("nothing is mounted"));
+    }
+}
@@ -0,0 +1,170 @@
+//! Address family and source address of the connections to the server.
+//!
+//! With `network.prefer` set to `ipv4` or `ipv6`, only the resolved addresses
+//! of that family are tried, e.g. to keep a dual-stack server off a metered
+//! IPv6 tunnel. `network.local_address` binds outgoing connections to one
+//! source address for policy routing; without an explicit `prefer`, it also
+//! restricts the server addresses to its own family.
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+/// Address family of the server addresses to connect to.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressFamily {
+    /// Every resolved address, in the order the system resolver returns them.
+    #[default]
+    Auto,
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    pub fn of(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => AddressFamily::Ipv4,
+            IpAddr::V6(_) => AddressFamily::Ipv6,
+        }
+    }
+
+    pub fn allows(self, ip: IpAddr) -> bool {
+        self == AddressFamily::Auto || self == Self::of(ip)
+    }
+}
+
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressFamily::Auto => write!(f, "any address family"),
+            AddressFamily::Ipv4 => write!(f, "IPv4"),
+            AddressFamily::Ipv6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// The `network` section of the config.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetworkConfig {
+    #[serde(default)]
+    pub prefer: AddressFamily,
+    /// Source address of outgoing connections.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_address: Option<IpAddr>,
+}
+
+impl NetworkConfig {
+    /// The family server addresses are restricted to.
+    pub fn family(&self) -> AddressFamily {
+        match (self.prefer, self.local_address) {
+            (AddressFamily::Auto, Some(local)) => AddressFamily::of(local),
+            (prefer, _) => prefer,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        match self.local_address {
+            Some(local) if !self.prefer.allows(local) => Err(format!(
+                "network.local_address {} is not an {} address, as network.prefer requires",
+                local, self.prefer
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// How connections are made, for error messages; `None` when nothing
+    /// is restricted.
+    pub fn describe(&self) -> Option<String> {
+        match (self.family(), self.local_address) {
+            (AddressFamily::Auto, None) => None,
+            (family, None) => Some(format!("over {}", family)),
+            (family, Some(local)) => Some(format!("over {} from {}", family, local)),
+        }
+    }
+
+    /// The addresses of `addrs` that connections may use.
+    pub fn filter(&self, addrs: impl IntoIterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+        let family = self.family();
+        addrs.into_iter().filter(|addr| family.allows(addr.ip())).collect()
+    }
+
+    /// The addresses of `host` that connections may use; an error if it has
+    /// none.
+    pub async fn resolve(&self, host: &str) -> Result<Vec<SocketAddr>, Box<dyn Error + Send + Sync>> {
+        let resolved: Vec<SocketAddr> = tokio::net::lookup_host((host, 0)).await?.collect();
+        let allowed = self.filter(resolved.iter().copied());
+        if allowed.is_empty() && !resolved.is_empty() {
+            let found: Vec<String> = resolved.iter().map(|a| a.ip().to_string()).collect();
+            return Err(format!("'{}' has no {} address (only {})", host, self.family(), found.join(", ")).into());
+        }
+        Ok(allowed)
+    }
+
+    /// The server addresses of `url` that connections may use. A server
+    /// addressed by IP skips the resolver, so its address is checked here.
+    pub async fn server_addresses(&self, url: &str) -> Result<Vec<IpAddr>, Box<dyn Error>> {
+        let url = Url::parse(url)?;
+        if let Some(ip) = self.literal_server_address(&url)? {
+            return Ok(vec![ip]);
+        }
+        let host = url.host_str().ok_or_else(|| format!("'{}' has no host", url))?;
+        let addrs = self.resolve(host).await.map_err(|e| e.to_string())?;
+        Ok(addrs.iter().map(SocketAddr::ip).collect())
+    }
+
+    /// The address of a server addressed by IP, an error if it is not of the
+    /// allowed family.
+    pub fn literal_server_address(&self, url: &Url) -> Result<Option<IpAddr>, Box<dyn Error>> {
+        let host = url.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']');
+        match host.parse::<IpAddr>() {
+            Ok(ip) if !self.family().allows(ip) => {
+                Err(format!("server address {} is not an {} address", ip, self.family()).into())
+            }
+            Ok(ip) => Ok(Some(ip)),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs() -> Vec<SocketAddr> {
+        ["[2001:db8::1]:0", "192.0.2.1:0", "[2001:db8::2]:0", "192.0.2.2:0"].iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    fn network(yaml: &str) -> NetworkConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_filter_keeps_the_preferred_family_in_order() {
+        let ips = |n: NetworkConfig| n.filter(addrs()).iter().map(|a| a.ip().to_string()).collect::<Vec<_>>();
+        assert_eq!(ips(network("{}")).len(), 4);
+        assert_eq!(ips(network("prefer: ipv4")), vec!["192.0.2.1", "192.0.2.2"]);
+        assert_eq!(ips(network("prefer: ipv6")), vec!["2001:db8::1", "2001:db8::2"]);
+        // A local address restricts to its own family.
+        assert_eq!(ips(network("local_address: 10.0.0.5")), vec!["192.0.2.1", "192.0.2.2"]);
+    }
+
+    #[test]
+    fn test_local_address_must_match_the_preferred_family() {
+        assert!(network("prefer: ipv6\nlocal_address: 10.0.0.5").validate().is_err());
+        assert!(network("prefer: ipv4\nlocal_address: 10.0.0.5").validate().is_ok());
+        assert_eq!(network("{}").describe(), None);
+        assert_eq!(network("local_address: \"fe80::1\"").describe().unwrap(), "over IPv6 from fe80::1");
+    }
+
+    #[tokio::test]
+    async fn test_server_address_literal_is_checked() {
+        let v4 = network("prefer: ipv4");
+        assert_eq!(v4.server_addresses("http://127.0.0.1:8080/dav").await.unwrap(), vec![IpAddr::from([127, 0, 0, 1])]);
+        let err = v4.server_addresses("http://[::1]:8080/dav").await.unwrap_err();
+        assert!(err.to_string().contains("not an IPv4 address"), "{}", err);
+    }
+}
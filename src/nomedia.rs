@@ -0,0 +1,82 @@
+//! Skipping folders marked with `.nomedia` (`respect_nomedia`).
+//!
+//! Android apps mark folders that galleries should ignore (caches, thumbnails)
+//! with an empty `.nomedia` file. With `respect_nomedia`, the walk does not
+//! descend into such a directory at all, unless it matches
+//! `nomedia_allowlist`. Configured folders themselves are never skipped.
+
+use crate::config::Config;
+use crate::filter::wildcard_match;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Name of the marker file.
+pub const NOMEDIA: &str = ".nomedia";
+
+/// Decides which directories a folder walk prunes, and remembers them.
+/// Clones share the list of pruned directories.
+#[derive(Debug, Clone, Default)]
+pub struct NomediaFilter {
+    enabled: bool,
+    allowlist: Vec<String>,
+    skipped: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl NomediaFilter {
+    pub fn from_config(config: &Config) -> Self {
+        NomediaFilter {
+            enabled: config.respect_nomedia,
+            allowlist: config.nomedia_allowlist.clone(),
+            skipped: Arc::default(),
+        }
+    }
+
+    /// Whether the walk of `folder` descends into its subdirectory `dir`.
+    pub fn descends(&self, folder: &Path, dir: &Path) -> bool {
+        if !self.enabled || dir == folder || !dir.join(NOMEDIA).is_file() {
+            return true;
+        }
+        let relative = dir.strip_prefix(folder).unwrap_or(dir).to_string_lossy().replace('\\', "/");
+        if self.allowlist.iter().any(|pattern| wildcard_match(pattern, &relative)) {
+            return true;
+        }
+        self.skipped.lock().unwrap_or_else(|e| e.into_inner()).push(dir.to_path_buf());
+        false
+    }
+
+    /// Directories pruned so far, in walk order.
+    pub fn skipped(&self) -> Vec<PathBuf> {
+        self.skipped.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_marked_directories_are_pruned_unless_allowlisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path();
+        for marked in ["cache", "Media/WhatsApp Images", "Media/Thumbs"] {
+            fs::create_dir_all(folder.join(marked)).unwrap();
+            fs::write(folder.join(marked).join(NOMEDIA), "").unwrap();
+        }
+        fs::write(folder.join(NOMEDIA), "").unwrap();
+        let filter = NomediaFilter {
+            enabled: true,
+            allowlist: vec!["*/WhatsApp Images".to_string()],
+            skipped: Arc::default(),
+        };
+
+        assert!(filter.descends(folder, folder));
+        assert!(filter.descends(folder, &folder.join("Media")));
+        assert!(filter.descends(folder, &folder.join("Media/WhatsApp Images")));
+        assert!(!filter.descends(folder, &folder.join("cache")));
+        assert!(!filter.descends(folder, &folder.join("Media/Thumbs")));
+        assert_eq!(filter.clone().skipped(), vec![folder.join("cache"), folder.join("Media/Thumbs")]);
+
+        assert!(NomediaFilter::default().descends(folder, &folder.join("cache")));
+    }
+}
@@ -0,0 +1,150 @@
+use crate::config::NotifyPolicy;
+use crate::report::{format_summary, SyncReport};
+use log::debug;
+use std::error::Error;
+
+/// Application name used as the notification title.
+const APP_NAME: &str = "phone_sync";
+
+/// A desktop notification describing the outcome of a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub summary: String,
+    pub body: String,
+    pub is_error: bool,
+}
+
+/// Something that can display a [`Notification`] to the user.
+pub trait Notifier {
+    fn show(&self, notification: &Notification) -> Result<(), Box<dyn Error>>;
+}
+
+/// Build the notification for a finished run.
+pub fn build_notification(outcome: &Result<SyncReport, String>) -> Notification {
+    match outcome {
+        Ok(report) => Notification {
+            summary: APP_NAME.to_string(),
+            body: format_summary(report),
+            is_error: false,
+        },
+        Err(e) => Notification {
+            summary: format!("{} failed", APP_NAME),
+            body: e.clone(),
+            is_error: true,
+        },
+    }
+}
+
+/// Show a notification for `outcome` if `policy` asks for it.
+///
+/// Notification failures (e.g. no session bus in a headless environment) are
+/// only logged at debug level and never reported as an error. Returns whether
+/// a notification was actually shown.
+pub fn notify_outcome(
+    notifier: &dyn Notifier,
+    policy: NotifyPolicy,
+    outcome: &Result<SyncReport, String>,
+) -> bool {
+    let wanted = match policy {
+        NotifyPolicy::Always => true,
+        NotifyPolicy::Failure => outcome.is_err(),
+        NotifyPolicy::Never => false,
+    };
+    if !wanted {
+        return false;
+    }
+    match notifier.show(&build_notification(outcome)) {
+        Ok(()) => true,
+        Err(e) => {
+            debug!("Desktop notification unavailable: {}", e);
+            false
+        }
+    }
+}
+
+/// Notifier backed by the desktop's notification daemon.
+#[cfg(feature = "notify-desktop")]
+pub struct DesktopNotifier;
+
+#[cfg(feature = "notify-desktop")]
+impl Notifier for DesktopNotifier {
+    fn show(&self, notification: &Notification) -> Result<(), Box<dyn Error>> {
+        let icon = if notification.is_error { "dialog-error" } else { "dialog-information" };
+        notify_rust::Notification::new()
+            .appname(APP_NAME)
+            .summary(&notification.summary)
+            .body(&notification.body)
+            .icon(icon)
+            .show()?;
+        Ok(())
+    }
+}
+
+/// Fallback used when the binary is built without desktop notification support.
+#[cfg(not(feature = "notify-desktop"))]
+pub struct DesktopNotifier;
+
+#[cfg(not(feature = "notify-desktop"))]
+impl Notifier for DesktopNotifier {
+    fn show(&self, _notification: &Notification) -> Result<(), Box<dyn Error>> {
+        Err("built without the notify-desktop feature".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    struct RecordingNotifier {
+        shown: RefCell<Vec<Notification>>,
+        fail: bool,
+    }
+
+    impl Notifier for RecordingNotifier {
+        fn show(&self, notification: &Notification) -> Result<(), Box<dyn Error>> {
+            if self.fail {
+                return Err("no session bus".into());
+            }
+            self.shown.borrow_mut().push(notification.clone());
+            Ok(())
+        }
+    }
+
+    fn recorder(fail: bool) -> RecordingNotifier {
+        RecordingNotifier { shown: RefCell::new(Vec::new()), fail }
+    }
+
+    #[test]
+    fn test_build_notification_messages() {
+        let ok = build_notification(&Ok(SyncReport { uploaded: 3, skipped: 7, ..Default::default() }));
+        assert_eq!(ok.summary, "phone_sync");
+        assert_eq!(ok.body, "3 files uploaded, 7 unchanged");
+        assert!(!ok.is_error);
+
+        let err = build_notification(&Err("connection refused".to_string()));
+        assert_eq!(err.summary, "phone_sync failed");
+        assert_eq!(err.body, "connection refused");
+        assert!(err.is_error);
+    }
+
+    #[test]
+    fn test_policy_selects_outcomes() {
+        let ok: Result<SyncReport, String> = Ok(SyncReport::default());
+        let err: Result<SyncReport, String> = Err("boom".to_string());
+
+        let n = recorder(false);
+        assert!(notify_outcome(&n, NotifyPolicy::Always, &ok));
+        assert!(!notify_outcome(&n, NotifyPolicy::Failure, &ok));
+        assert!(notify_outcome(&n, NotifyPolicy::Failure, &err));
+        assert!(!notify_outcome(&n, NotifyPolicy::Never, &err));
+        assert_eq!(n.shown.borrow().len(), 2);
+    }
+
+    #[test]
+    fn test_missing_session_is_not_an_error() {
+        let n = recorder(true);
+        assert!(!notify_outcome(&n, NotifyPolicy::Always, &Err("boom".to_string())));
+        assert!(n.shown.borrow().is_empty());
+    }
+}
@@ -0,0 +1,51 @@
+use serde::Serialize;
+use std::error::Error;
+
+/// Output format of subcommands that print results.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable text (default).
+    #[default]
+    Human,
+    /// A single JSON document.
+    Json,
+}
+
+/// Human readable rendering of a value printed by a subcommand.
+pub trait HumanDisplay {
+    fn human(&self) -> String;
+}
+
+/// Render `value` in the requested format.
+pub fn render<T: Serialize + HumanDisplay>(
+    value: &T,
+    format: OutputFormat,
+) -> Result<String, Box<dyn Error>> {
+    match format {
+        OutputFormat::Human => Ok(value.human()),
+        OutputFormat::Json => Ok(serde_json::to_string_pretty(value)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::SyncReport;
+
+    #[test]
+    fn test_render_json_round_trips() {
+        let report = SyncReport { uploaded: 2, skipped: 5, ..Default::default() };
+        let json = render(&report, OutputFormat::Json).unwrap();
+        let parsed: SyncReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, report);
+    }
+
+    #[test]
+    fn test_render_human_uses_summary() {
+        let report = SyncReport { uploaded: 2, skipped: 5, ..Default::default() };
+        assert_eq!(
+            render(&report, OutputFormat::Human).unwrap(),
+            "2 files uploaded, 5 unchanged"
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Deciding what a sync does with a file, apart from the I/O that gathers
+//! the facts and carries the decision out.
+//!
+//! `sync` and `estimate` both ask [`decide`], so an estimate or a read-only
+//! run reports what a sync would do.
+
+use crate::fingerprint::{remote_state, RemoteFingerprint, RemoteState};
+use std::time::Duration;
+
+/// The remote copy of a file, as far as it was looked at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteFacts<'a> {
+    /// Not asked, e.g. by an estimate; taken to be as the store records it.
+    Unchecked,
+    Missing,
+    Present {
+        size: Option<u64>,
+        fingerprint: &'a RemoteFingerprint,
+    },
+}
+
+/// What is known about a local file and its remote copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileFacts<'a> {
+    pub size: u64,
+    /// Hash of the local content; `None` while it is not computed.
+    pub hash: Option<&'a str>,
+    /// Hash the store recorded when the file was last synced.
+    pub stored_hash: Option<&'a str>,
+    pub remote: RemoteFacts<'a>,
+    /// Fingerprint the store recorded for the remote copy.
+    pub stored_fingerprint: Option<&'a RemoteFingerprint>,
+}
+
+/// Settings of the run that change decisions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    /// Nothing is uploaded, only planned.
+    pub read_only: bool,
+    /// See [`crate::config::Config::last_modified_tolerance`].
+    pub last_modified_tolerance: Duration,
+}
+
+/// Why a file is (or would be) uploaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadReason {
+    /// Never synced.
+    New,
+    /// The local content differs from the synced one.
+    Changed,
+    /// Synced, but gone from the server.
+    RemoteMissing,
+    /// The server copy was changed since the last sync; the local version
+    /// replaces it.
+    RemoteChanged,
+    /// The server copy has another size, e.g. after a cut-short upload.
+    SizeDiffers,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// The remote copy holds the local content.
+    Skip,
+    /// Deciding takes the hash of the local content.
+    NeedHash,
+    /// To be uploaded; the hash is still needed to record the file.
+    Upload(UploadReason),
+    /// Would be uploaded, were the run not read-only.
+    Plan(UploadReason),
+}
+
+/// What to do with the file described by `facts`.
+pub fn decide(facts: &FileFacts, policy: &Policy) -> Decision {
+    let (remote_size, remote_fingerprint) = match facts.remote {
+        RemoteFacts::Present { size, fingerprint } => (size, Some(fingerprint)),
+        _ => (None, None),
+    };
+    let size_differs = remote_size.is_some_and(|size| size != facts.size);
+    let act = |reason| if policy.read_only { Decision::Plan(reason) } else { Decision::Upload(reason) };
+
+    // A read-only run needs no hash to know such a file would be sent.
+    if size_differs && policy.read_only {
+        return Decision::Plan(UploadReason::SizeDiffers);
+    }
+    let remote_changed = match facts.remote {
+        RemoteFacts::Unchecked => false,
+        _ => remote_state(remote_fingerprint, facts.stored_fingerprint, policy.last_modified_tolerance) == RemoteState::Changed,
+    };
+    if remote_changed {
+        return act(UploadReason::RemoteChanged);
+    }
+    match (facts.stored_hash, facts.hash) {
+        (None, _) => act(UploadReason::New),
+        (Some(_), None) => Decision::NeedHash,
+        (Some(stored), Some(hash)) if stored != hash => act(UploadReason::Changed),
+        _ if facts.remote == RemoteFacts::Missing => act(UploadReason::RemoteMissing),
+        _ if size_differs => act(UploadReason::SizeDiffers),
+        _ => Decision::Skip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: Duration = Duration::from_secs(2);
+
+    /// Hash, stored hash, remote, stored fingerprint, then the decision of a
+    /// writable and of a read-only run.
+    type Case<'a> = (Option<&'a str>, Option<&'a str>, RemoteFacts<'a>, Option<&'a RemoteFingerprint>, Decision, Decision);
+
+    fn etag(tag: &str) -> RemoteFingerprint {
+        RemoteFingerprint::Etag(tag.to_string())
+    }
+
+    #[test]
+    fn test_decide() {
+        let (v1, v2) = (etag("\"v1\""), etag("\"v2\""));
+        let present = |size, fingerprint| RemoteFacts::Present { size: Some(size), fingerprint };
+        let no_size = RemoteFacts::Present { size: None, fingerprint: &v1 };
+        use Decision::*;
+        use UploadReason::*;
+        let cases: Vec<Case> = vec![
+            (Some("a"), Some("a"), present(3, &v1), Some(&v1), Skip, Skip),
+            (Some("a"), Some("a"), no_size, Some(&v1), Skip, Skip),
+            (Some("a"), Some("a"), present(3, &v1), None, Skip, Skip),
+            (Some("a"), Some("a"), RemoteFacts::Unchecked, None, Skip, Skip),
+            (None, Some("a"), present(3, &v1), Some(&v1), NeedHash, NeedHash),
+            (None, None, RemoteFacts::Missing, None, Upload(New), Plan(New)),
+            (None, Some("a"), present(3, &v2), Some(&v1), Upload(RemoteChanged), Plan(RemoteChanged)),
+            (None, Some("a"), present(4, &v1), Some(&v1), NeedHash, Plan(SizeDiffers)),
+            (Some("a"), None, RemoteFacts::Missing, None, Upload(New), Plan(New)),
+            (Some("a"), None, present(3, &v1), None, Upload(New), Plan(New)),
+            (Some("a"), None, RemoteFacts::Unchecked, None, Upload(New), Plan(New)),
+            (Some("b"), Some("a"), present(3, &v1), Some(&v1), Upload(Changed), Plan(Changed)),
+            (Some("b"), Some("a"), RemoteFacts::Unchecked, None, Upload(Changed), Plan(Changed)),
+            (Some("a"), Some("a"), RemoteFacts::Missing, Some(&v1), Upload(RemoteMissing), Plan(RemoteMissing)),
+            (Some("a"), Some("a"), present(3, &v2), Some(&v1), Upload(RemoteChanged), Plan(RemoteChanged)),
+            (Some("b"), Some("a"), present(3, &v2), Some(&v1), Upload(RemoteChanged), Plan(RemoteChanged)),
+            (Some("a"), Some("a"), present(4, &v1), Some(&v1), Upload(SizeDiffers), Plan(SizeDiffers)),
+            (Some("b"), Some("a"), present(4, &v1), Some(&v1), Upload(Changed), Plan(SizeDiffers)),
+        ];
+        for (hash, stored_hash, remote, stored_fingerprint, writable, read_only) in cases {
+            let facts = FileFacts { size: 3, hash, stored_hash, remote, stored_fingerprint };
+            for (read_only, expected) in [(false, writable), (true, read_only)] {
+                let policy = Policy { read_only, last_modified_tolerance: TOLERANCE };
+                assert_eq!(decide(&facts, &policy), expected, "{:?}, read_only: {}", facts, read_only);
+            }
+        }
+    }
+
+    #[test]
+    fn test_modification_time_within_tolerance_is_no_remote_change() {
+        let recorded = RemoteFingerprint::ModifiedSize { last_modified: 1_700_000_000, size: 3 };
+        let now = RemoteFingerprint::ModifiedSize { last_modified: 1_700_000_001, size: 3 };
+        let facts = FileFacts {
+            size: 3,
+            hash: Some("a"),
+            stored_hash: Some("a"),
+            remote: RemoteFacts::Present { size: Some(3), fingerprint: &now },
+            stored_fingerprint: Some(&recorded),
+        };
+        let policy = |secs| Policy { read_only: false, last_modified_tolerance: Duration::from_secs(secs) };
+        assert_eq!(decide(&facts, &policy(2)), Decision::Skip);
+        assert_eq!(decide(&facts, &policy(0)), Decision::Upload(UploadReason::RemoteChanged));
+    }
+}
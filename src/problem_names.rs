@@ -0,0 +1,103 @@
+//! File names with leading or trailing whitespace or control characters.
+//!
+//! Some WebDAV servers trim or mangle such names on PUT, so the remote path a
+//! sync records never exists on the server and the file is uploaded on every
+//! run. The scan warns about them. With `normalize_problem_names` they are
+//! uploaded under a cleaned-up name instead; otherwise the name the server
+//! actually stored is looked up after the upload and remembered in the hash
+//! store (`server_names`).
+
+use crate::propfind::Depth;
+use crate::webdav_client::WebDavClient;
+use std::error::Error;
+
+/// Whether `name`, one path component, starts or ends with whitespace or
+/// contains a C0 control character.
+pub fn is_problem_name(name: &str) -> bool {
+    name.trim() != name || name.chars().any(|c| c < ' ')
+}
+
+/// Whether any component of `path` is a problem name.
+pub fn has_problem_names(path: &str) -> bool {
+    path.split('/').any(is_problem_name)
+}
+
+/// `path` with its control characters and leading or trailing whitespace
+/// escaped, so a log line shows what is in the name.
+pub fn escape(path: &str) -> String {
+    path.split('/').map(escape_name).collect::<Vec<_>>().join("/")
+}
+
+fn escape_name(name: &str) -> String {
+    let start = name.len() - name.trim_start().len();
+    let end = name.trim_end().len().max(start);
+    name.char_indices()
+        .map(|(i, c)| match c {
+            c if c >= ' ' && (start..end).contains(&i) => c.to_string(),
+            c if c.is_ascii() => format!("\\x{:02x}", c as u32),
+            c => c.escape_unicode().to_string(),
+        })
+        .collect()
+}
+
+/// `path` with every component trimmed and its control characters replaced
+/// by `_`. A component of only whitespace becomes `_`.
+pub fn normalize(path: &str) -> String {
+    path.split('/')
+        .map(|name| {
+            let name: String = name.trim().chars().map(|c| if c < ' ' { '_' } else { c }).collect();
+            if name.is_empty() {
+                "_".to_string()
+            } else {
+                name
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Whether a server could have stored a file named `ours` as `listed`, by
+/// trimming it, dropping its control characters or replacing them.
+fn munged_to(ours: &str, listed: &str) -> bool {
+    let stripped = |name: &str| name.trim().chars().filter(|c| *c >= ' ').collect::<String>();
+    stripped(ours) == stripped(listed) || normalize(ours) == listed
+}
+
+/// Remote path the server stored an upload to `remote_path` under: the path
+/// itself if it exists, else a file in the same collection whose name differs
+/// only in whitespace and control characters.
+pub async fn stored_name(client: &WebDavClient, remote_path: &str) -> Result<Option<String>, Box<dyn Error>> {
+    if client.stat(remote_path).await?.is_some() {
+        return Ok(Some(remote_path.to_string()));
+    }
+    let (dir, name) = remote_path.rsplit_once('/').unwrap_or(("", remote_path));
+    let entries = client.list_dir(dir, Depth::One).await?;
+    Ok(entries.into_iter().find(|entry| !entry.is_dir && munged_to(name, entry.name())).map(|entry| entry.path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_problem_names_are_escaped_and_normalized() {
+        for path in ["a.txt ", " a.txt", "tab\there.txt", "bell\u{7}.txt", "dir /a.txt"] {
+            assert!(has_problem_names(path), "{:?}", path);
+        }
+        assert!(!has_problem_names("DCIM/a b.txt"));
+
+        assert_eq!(escape("dir /a\tb.txt\u{a0}"), "dir\\x20/a\\x09b.txt\\u{a0}");
+        assert_eq!(escape("a b.txt"), "a b.txt");
+        assert_eq!(normalize("dir /a\tb.txt "), "dir/a_b.txt");
+        assert_eq!(normalize("  /a.txt"), "_/a.txt");
+        assert_eq!(normalize(&normalize("bell\u{7}.txt ")), normalize("bell\u{7}.txt "));
+    }
+
+    #[test]
+    fn test_munged_names_are_recognized() {
+        assert!(munged_to("a.txt ", "a.txt"));
+        assert!(munged_to("tab\there.txt", "tabhere.txt"));
+        assert!(munged_to("tab\there.txt", "tab_here.txt"));
+        assert!(!munged_to("a.txt ", "b.txt"));
+    }
+}
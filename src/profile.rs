@@ -0,0 +1,239 @@
+//! Phase timings of a sync run.
+//!
+//! Timings are always collected (a few `Instant::now()` calls per file);
+//! `--profile-performance` only controls whether the breakdown is printed.
+
+use crate::units::format_byte_size;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Number of slowest files kept in the profile.
+const SLOWEST_FILES: usize = 10;
+
+/// A step of the sync pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    Scan,
+    Hash,
+    RemoteCheck,
+    Upload,
+    Finalize,
+}
+
+impl Phase {
+    fn label(self) -> &'static str {
+        match self {
+            Phase::Scan => "scan",
+            Phase::Hash => "hash",
+            Phase::RemoteCheck => "remote-check",
+            Phase::Upload => "upload",
+            Phase::Finalize => "finalize",
+        }
+    }
+}
+
+/// Time and work spent in one phase.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseStats {
+    pub micros: u64,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Time spent on a single file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileTiming {
+    pub path: String,
+    pub micros: u64,
+    /// The phase that took longest for this file.
+    pub dominant: Phase,
+}
+
+/// Connection reuse of the HTTP client of a run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionStats {
+    pub requests: u64,
+    /// New connections. Only counted for servers addressed by host name, as
+    /// they are seen through the DNS lookup of each new connection.
+    pub opened: Option<u64>,
+    /// Requests sent over an already open connection.
+    pub reused: Option<u64>,
+    /// TLS handshakes, one per new HTTPS connection.
+    pub tls_handshakes: Option<u64>,
+}
+
+/// Size of the hash store at the end of a run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreMemory {
+    /// Regular and pseudo hashes.
+    pub entries: u64,
+    /// See [`HashStore::approximate_memory_bytes`](crate::hash_store::HashStore::approximate_memory_bytes).
+    pub approximate_bytes: u64,
+}
+
+/// Phase breakdown of a run.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    /// Wall-clock time of the whole run.
+    pub total_micros: u64,
+    pub phases: BTreeMap<Phase, PhaseStats>,
+    /// The slowest files, slowest first.
+    pub slowest: Vec<FileTiming>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connections: Option<ConnectionStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store_memory: Option<StoreMemory>,
+}
+
+impl Profile {
+    /// Account `elapsed` and `bytes` of work on one file to `phase`.
+    pub fn record(&mut self, phase: Phase, elapsed: Duration, bytes: u64) {
+        let stats = self.phases.entry(phase).or_default();
+        stats.micros += elapsed.as_micros() as u64;
+        stats.files += 1;
+        stats.bytes += bytes;
+    }
+
+    /// Account `elapsed` to `phase` without attributing it to a file.
+    pub fn add_time(&mut self, phase: Phase, elapsed: Duration) {
+        self.phases.entry(phase).or_default().micros += elapsed.as_micros() as u64;
+    }
+
+    /// Add the phases of a finished file and keep track of the slowest files.
+    pub fn record_file(&mut self, path: &str, timings: FileTimings) {
+        let mut total = Duration::ZERO;
+        let mut dominant: Option<(Phase, Duration)> = None;
+        for (phase, elapsed, bytes) in timings.phases {
+            self.record(phase, elapsed, bytes);
+            total += elapsed;
+            if dominant.is_none_or(|(_, longest)| elapsed > longest) {
+                dominant = Some((phase, elapsed));
+            }
+        }
+        let Some((dominant, _)) = dominant else {
+            return;
+        };
+        let timing = FileTiming { path: path.to_string(), micros: total.as_micros() as u64, dominant };
+        let pos = self.slowest.partition_point(|t| t.micros >= timing.micros);
+        if pos < SLOWEST_FILES {
+            self.slowest.insert(pos, timing);
+            self.slowest.truncate(SLOWEST_FILES);
+        }
+    }
+
+    /// Time accounted to any phase.
+    pub fn phase_micros(&self) -> u64 {
+        self.phases.values().map(|s| s.micros).sum()
+    }
+
+    /// Breakdown table printed by `--profile-performance`.
+    pub fn table(&self) -> String {
+        let ms = |micros: u64| micros as f64 / 1000.0;
+        let mut out = format!("{:<14}{:>12}{:>7}{:>8}{:>14}", "phase", "time (ms)", "%", "files", "bytes");
+        for (phase, stats) in &self.phases {
+            let share = if self.total_micros == 0 { 0.0 } else { stats.micros as f64 * 100.0 / self.total_micros as f64 };
+            out.push_str(&format!(
+                "\n{:<14}{:>12.1}{:>7.1}{:>8}{:>14}",
+                phase.label(),
+                ms(stats.micros),
+                share,
+                stats.files,
+                stats.bytes
+            ));
+        }
+        out.push_str(&format!("\n{:<14}{:>12.1}", "total", ms(self.total_micros)));
+        match self.connections {
+            Some(ConnectionStats { requests, opened: Some(opened), reused: Some(reused), tls_handshakes }) => {
+                out.push_str(&format!(
+                    "\n\nconnections: {} opened, {} reused, {} TLS handshakes for {} requests",
+                    opened,
+                    reused,
+                    tls_handshakes.unwrap_or(0),
+                    requests
+                ));
+            }
+            Some(stats) => out.push_str(&format!(
+                "\n\nconnections: not counted for a server addressed by IP ({} requests)",
+                stats.requests
+            )),
+            None => {}
+        }
+        if let Some(memory) = self.store_memory {
+            out.push_str(&format!(
+                "\nhash store: {} entries, ~{} in memory",
+                memory.entries,
+                format_byte_size(memory.approximate_bytes)
+            ));
+        }
+        if !self.slowest.is_empty() {
+            out.push_str("\n\nslowest files:");
+            for timing in &self.slowest {
+                out.push_str(&format!(
+                    "\n{:>10.1} ms  {:<13} {}",
+                    ms(timing.micros),
+                    timing.dominant.label(),
+                    timing.path
+                ));
+            }
+        }
+        out
+    }
+}
+
+/// Phases measured for the file currently being processed.
+#[derive(Debug, Default)]
+pub struct FileTimings {
+    phases: Vec<(Phase, Duration, u64)>,
+}
+
+impl FileTimings {
+    /// Await `future` and account its duration and `bytes` to `phase`.
+    pub async fn time<F: Future>(&mut self, phase: Phase, bytes: u64, future: F) -> F::Output {
+        let start = Instant::now();
+        let output = future.await;
+        self.phases.push((phase, start.elapsed(), bytes));
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timings(phases: &[(Phase, u64)]) -> FileTimings {
+        FileTimings {
+            phases: phases.iter().map(|&(p, ms)| (p, Duration::from_millis(ms), 10)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_slowest_files_are_ordered_and_capped() {
+        let mut profile = Profile::default();
+        for i in 0..15u64 {
+            profile.record_file(&format!("f{}", i), timings(&[(Phase::Hash, i), (Phase::Upload, 1)]));
+        }
+        profile.record_file("big", timings(&[(Phase::Hash, 5), (Phase::Upload, 500)]));
+
+        assert_eq!(profile.slowest.len(), SLOWEST_FILES);
+        assert_eq!(profile.slowest[0].path, "big");
+        assert_eq!(profile.slowest[0].dominant, Phase::Upload);
+        assert_eq!(profile.slowest[1].path, "f14");
+        assert_eq!(profile.slowest[1].dominant, Phase::Hash);
+        assert!(profile.slowest.windows(2).all(|w| w[0].micros >= w[1].micros));
+
+        let hash = &profile.phases[&Phase::Hash];
+        assert_eq!((hash.files, hash.bytes, hash.micros), (16, 160, 110_000));
+    }
+
+    #[test]
+    fn test_table_lists_phases_and_files() {
+        let mut profile = Profile { total_micros: 2_000, ..Default::default() };
+        profile.record_file("a.jpg", timings(&[(Phase::RemoteCheck, 1)]));
+        let table = profile.table();
+        assert!(table.contains("\nremote-check           1.0   50.0       1            10\n"), "{}", table);
+        assert!(table.ends_with("remote-check  a.jpg"), "{}", table);
+    }
+}
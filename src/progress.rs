@@ -0,0 +1,370 @@
+//! Progress of a sync run as it happens.
+//!
+//! The sync reports to a [`SyncObserver`]: the terminal progress bars of
+//! `--progress` (counting files) and `--progress-bytes` are two,
+//! `--progress-json` another, which writes one JSON event per line for UIs
+//! wrapping the binary. For every path, `file_progress` events come between
+//! its `file_start` and `file_done`; files ignored before they are checked
+//! (filtered out, collisions, the hash store itself) only get a `file_done`.
+
+use crate::report::SyncReport;
+use crate::webdav_client::RetryEvent;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Version of the `--progress-json` event schema, sent with every event.
+pub const PROGRESS_SCHEMA_VERSION: u32 = 1;
+
+/// Shortest interval between two `file_progress` events of one file.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How a file was handled.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileDone {
+    Uploaded,
+    Unchanged,
+    /// Not synced by this run: filtered out, a collision, or reserved.
+    Ignored,
+    /// Would be uploaded, but the run is read-only.
+    Planned,
+    /// Changed on the server during the upload; the remote version was kept.
+    Conflict,
+    /// The server stored fewer bytes than were sent.
+    Truncated,
+    Failed,
+    /// Left for a later run, e.g. once the transfer budget is exhausted.
+    Postponed,
+}
+
+/// Receives the progress of a sync run. Every method defaults to doing
+/// nothing.
+pub trait SyncObserver: Send + Sync {
+    /// Whether the sync should count the files before it starts, so
+    /// `scan_progress` can report a total.
+    fn wants_total(&self) -> bool {
+        false
+    }
+
+    /// `files` were found so far by the counting walk; `done` once all were.
+    fn scan_progress(&self, _files: u64, _done: bool) {}
+
+    /// The files found by the counting walk take `bytes`; sent right before
+    /// the last `scan_progress`.
+    fn scan_size(&self, _bytes: u64) {}
+
+    /// `path` (its remote path) of `size` bytes is being checked.
+    fn file_start(&self, _path: &str, _size: u64) {}
+
+    /// `bytes` of `path` were sent so far.
+    fn file_progress(&self, _path: &str, _bytes: u64) {}
+
+    fn file_done(&self, _path: &str, _outcome: FileDone) {}
+
+    /// An upload failed and will be retried.
+    fn retry(&self, _event: &RetryEvent) {}
+
+    /// The run finished with `report`.
+    fn summary(&self, _report: &SyncReport) {}
+}
+
+/// Reports nothing.
+pub struct NoProgress;
+
+impl SyncObserver for NoProgress {}
+
+/// The terminal progress bar of `--progress`.
+pub struct BarObserver {
+    bar: ProgressBar,
+    /// A retry message replaced "Syncing files".
+    retried: AtomicBool,
+}
+
+impl BarObserver {
+    /// A bar filled as files are done; without `counted` files, a spinner
+    /// with a running total instead.
+    pub fn new(counted: bool) -> Result<Self, Box<dyn Error>> {
+        let bar = if counted {
+            let bar = ProgressBar::new(0);
+            bar.set_style(
+                ProgressStyle::default_bar()
+                    .template("{msg} [{bar:40.cyan/blue}] {pos}/{len} ({eta})")?
+                    .progress_chars("=> "),
+            );
+            bar
+        } else {
+            let bar = ProgressBar::new_spinner();
+            bar.set_style(ProgressStyle::default_spinner().template("{spinner} {msg} {pos} files")?);
+            bar
+        };
+        bar.set_message("Syncing files");
+        Ok(BarObserver { bar, retried: AtomicBool::new(false) })
+    }
+}
+
+impl SyncObserver for BarObserver {
+    fn wants_total(&self) -> bool {
+        self.bar.length().is_some()
+    }
+
+    fn scan_progress(&self, files: u64, done: bool) {
+        if done {
+            self.bar.set_length(files);
+        }
+    }
+
+    fn file_done(&self, _path: &str, _outcome: FileDone) {
+        self.bar.inc(1);
+        if self.retried.swap(false, Ordering::Relaxed) {
+            self.bar.set_message("Syncing files");
+        }
+    }
+
+    fn retry(&self, event: &RetryEvent) {
+        self.bar.set_message(format!("{}: {}", event.path, event));
+        self.retried.store(true, Ordering::Relaxed);
+    }
+
+    fn summary(&self, _report: &SyncReport) {
+        self.bar.finish_with_message("Sync complete");
+    }
+}
+
+/// The terminal progress bar of `--progress-bytes`, filled by the bytes of
+/// the files done and sent so far, so a large upload moves it as far as it
+/// takes. Files that need no upload count at once; the speed shown is that
+/// of the uploads alone.
+pub struct ByteBarObserver {
+    bar: ProgressBar,
+    started: Instant,
+    /// Bytes counted so far and size of every file in flight.
+    in_flight: Mutex<HashMap<String, (u64, u64)>>,
+    /// Bytes of uploads counted so far.
+    sent: AtomicU64,
+}
+
+impl ByteBarObserver {
+    /// A bar of the total size of the files; without `counted` files, a
+    /// spinner with the bytes done instead.
+    pub fn new(counted: bool) -> Result<Self, Box<dyn Error>> {
+        let bar = if counted {
+            let bar = ProgressBar::new(0);
+            bar.set_style(
+                ProgressStyle::default_bar()
+                    .template("{msg} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")?
+                    .progress_chars("=> "),
+            );
+            bar
+        } else {
+            let bar = ProgressBar::new_spinner();
+            bar.set_style(ProgressStyle::default_spinner().template("{spinner} {msg} {bytes}")?);
+            bar
+        };
+        bar.set_message("Syncing files");
+        Ok(ByteBarObserver { bar, started: Instant::now(), in_flight: Mutex::default(), sent: AtomicU64::new(0) })
+    }
+
+    /// Show `path` with the upload speed so far.
+    fn show(&self, path: &str) {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let speed = if elapsed > 0.0 { (self.sent.load(Ordering::Relaxed) as f64 / elapsed) as u64 } else { 0 };
+        self.bar.set_message(format!("{} {}/s", path, HumanBytes(speed)));
+    }
+}
+
+impl SyncObserver for ByteBarObserver {
+    fn wants_total(&self) -> bool {
+        self.bar.length().is_some()
+    }
+
+    fn scan_size(&self, bytes: u64) {
+        self.bar.set_length(bytes);
+    }
+
+    fn file_start(&self, path: &str, size: u64) {
+        self.in_flight.lock().unwrap_or_else(|e| e.into_inner()).insert(path.to_string(), (0, size));
+        self.show(path);
+    }
+
+    fn file_progress(&self, path: &str, bytes: u64) {
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        let Some((counted, size)) = in_flight.get_mut(path) else {
+            return;
+        };
+        // A retry starts over; its bytes count once they pass the earlier attempt.
+        let bytes = bytes.min(*size);
+        if bytes > *counted {
+            self.bar.inc(bytes - *counted);
+            self.sent.fetch_add(bytes - *counted, Ordering::Relaxed);
+            *counted = bytes;
+        }
+        drop(in_flight);
+        self.show(path);
+    }
+
+    fn file_done(&self, path: &str, _outcome: FileDone) {
+        let done = self.in_flight.lock().unwrap_or_else(|e| e.into_inner()).remove(path);
+        if let Some((counted, size)) = done {
+            self.bar.inc(size - counted);
+        }
+    }
+
+    fn retry(&self, event: &RetryEvent) {
+        self.bar.set_message(format!("{}: {}", event.path, event));
+    }
+
+    fn summary(&self, _report: &SyncReport) {
+        self.bar.finish_with_message("Sync complete");
+    }
+}
+
+/// One line of `--progress-json` output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProgressLine {
+    /// [`PROGRESS_SCHEMA_VERSION`] of the event.
+    pub v: u32,
+    #[serde(flatten)]
+    pub event: ProgressEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    ScanProgress { files: u64, done: bool },
+    FileStart { path: String, size: u64 },
+    FileProgress { path: String, bytes: u64 },
+    FileDone { path: String, outcome: FileDone },
+    Retry { path: String, attempt: u32, max_retries: u32, delay_ms: u64, reason: String },
+    Summary { report: Box<SyncReport> },
+}
+
+/// Writes every event as a line of JSON, for `--progress-json`.
+pub struct JsonObserver {
+    out: Mutex<Box<dyn Write + Send>>,
+    /// When the last `file_progress` of each file in flight was written.
+    last_progress: Mutex<HashMap<String, Instant>>,
+}
+
+impl JsonObserver {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        JsonObserver { out: Mutex::new(out), last_progress: Mutex::default() }
+    }
+
+    fn emit(&self, event: ProgressEvent) {
+        let line = ProgressLine { v: PROGRESS_SCHEMA_VERSION, event };
+        let Ok(json) = serde_json::to_string(&line) else {
+            return;
+        };
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        // A reader that went away must not fail the sync.
+        let _ = writeln!(out, "{}", json).and_then(|_| out.flush());
+    }
+}
+
+impl SyncObserver for JsonObserver {
+    fn wants_total(&self) -> bool {
+        true
+    }
+
+    fn scan_progress(&self, files: u64, done: bool) {
+        self.emit(ProgressEvent::ScanProgress { files, done });
+    }
+
+    fn file_start(&self, path: &str, size: u64) {
+        self.emit(ProgressEvent::FileStart { path: path.to_string(), size });
+    }
+
+    fn file_progress(&self, path: &str, bytes: u64) {
+        {
+            let mut last = self.last_progress.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            if last.get(path).is_some_and(|at| now.duration_since(*at) < PROGRESS_INTERVAL) {
+                return;
+            }
+            last.insert(path.to_string(), now);
+        }
+        self.emit(ProgressEvent::FileProgress { path: path.to_string(), bytes });
+    }
+
+    fn file_done(&self, path: &str, outcome: FileDone) {
+        self.last_progress.lock().unwrap_or_else(|e| e.into_inner()).remove(path);
+        self.emit(ProgressEvent::FileDone { path: path.to_string(), outcome });
+    }
+
+    fn retry(&self, event: &RetryEvent) {
+        self.emit(ProgressEvent::Retry {
+            path: event.path.clone(),
+            attempt: event.attempt,
+            max_retries: event.max_retries,
+            delay_ms: event.delay.as_millis() as u64,
+            reason: event.reason.clone(),
+        });
+    }
+
+    fn summary(&self, report: &SyncReport) {
+        self.emit(ProgressEvent::Summary { report: Box::new(report.clone()) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Output shared with the test after the observer took it.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_byte_bar_counts_bytes_once() {
+        let observer = ByteBarObserver::new(true).unwrap();
+        observer.scan_size(1_000);
+        observer.file_start("big.mp4", 900);
+        observer.file_progress("big.mp4", 600);
+        // The upload is retried from the start.
+        observer.file_progress("big.mp4", 300);
+        observer.file_progress("big.mp4", 700);
+        assert_eq!(observer.bar.position(), 700);
+        observer.file_done("big.mp4", FileDone::Uploaded);
+        observer.file_start("small.txt", 100);
+        observer.file_done("small.txt", FileDone::Unchanged);
+        assert_eq!(observer.bar.position(), 1_000);
+        assert_eq!(observer.sent.load(Ordering::Relaxed), 700);
+    }
+
+    #[test]
+    fn test_json_progress_is_throttled_per_file() {
+        let out = Shared::default();
+        let observer = JsonObserver::new(Box::new(out.clone()));
+        observer.file_start("a.jpg", 300);
+        for bytes in [100, 200, 300] {
+            observer.file_progress("a.jpg", bytes);
+            observer.file_progress("b.jpg", bytes);
+        }
+        observer.file_done("a.jpg", FileDone::Uploaded);
+
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<ProgressLine> = text.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines.iter().all(|l| l.v == PROGRESS_SCHEMA_VERSION));
+        assert_eq!(lines[1].event, ProgressEvent::FileProgress { path: "a.jpg".to_string(), bytes: 100 });
+        assert_eq!(lines[2].event, ProgressEvent::FileProgress { path: "b.jpg".to_string(), bytes: 100 });
+        assert_eq!(text.lines().last().unwrap(), r#"{"v":1,"event":"file_done","path":"a.jpg","outcome":"uploaded"}"#);
+    }
+}
@@ -0,0 +1,430 @@
+//! Parsing of WebDAV `PROPFIND` responses.
+//!
+//! Servers differ in what they put into a multistatus body: namespace
+//! prefixes, absolute URLs or absolute paths as `href`, percent-encoding and
+//! trailing slashes on collections. Everything is normalised to paths
+//! relative to the WebDAV root, the form used for hash store keys.
+
+use percent_encoding::percent_decode_str;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::time::UNIX_EPOCH;
+
+/// How far below the listed collection a `PROPFIND` reaches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Depth {
+    /// The resource itself.
+    Zero,
+    /// Direct children only.
+    One,
+    /// Everything below. Some servers refuse this for large trees.
+    Infinity,
+}
+
+impl Depth {
+    /// Value of the `Depth` request header.
+    pub fn header(&self) -> &'static str {
+        match self {
+            Depth::Zero => "0",
+            Depth::One => "1",
+            Depth::Infinity => "infinity",
+        }
+    }
+}
+
+/// A file or collection found on the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteEntry {
+    /// Path relative to the WebDAV root, without leading or trailing slash.
+    pub path: String,
+    pub is_dir: bool,
+    /// Size in bytes; `None` for collections and servers that omit it.
+    pub size: Option<u64>,
+    /// Last-Modified as Unix seconds, if the server reports it.
+    #[serde(default)]
+    pub last_modified: Option<u64>,
+    /// `getetag`, if the server reports it.
+    #[serde(default)]
+    pub etag: Option<String>,
+}
+
+impl RemoteEntry {
+    /// Last component of the path.
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+}
+
+/// Properties of the `response` element being parsed.
+#[derive(Default)]
+struct Pending {
+    href: Option<String>,
+    is_dir: bool,
+    size: Option<u64>,
+    last_modified: Option<u64>,
+    etag: Option<String>,
+}
+
+/// Parse a multistatus body into entries relative to the WebDAV root, whose
+/// URL path is `base_path` (e.g. `/remote.php/dav/files/me`).
+///
+/// Entries outside the root are dropped; the root itself is returned with an
+/// empty path, like any other entry.
+pub fn parse_multistatus(xml: &str, base_path: &str) -> Result<Vec<RemoteEntry>, Box<dyn Error>> {
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    let mut entries = Vec::new();
+    let mut current: Option<Pending> = None;
+    let mut element = Vec::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => {
+                let name = e.local_name().as_ref().to_vec();
+                match name.as_slice() {
+                    b"response" => current = Some(Pending::default()),
+                    b"collection" => mark_collection(&mut current),
+                    _ => {}
+                }
+                element = name;
+            }
+            Event::Empty(e) if e.local_name().as_ref() == b"collection" => mark_collection(&mut current),
+            Event::Text(text) => {
+                let text = text.unescape()?;
+                if let Some(pending) = current.as_mut() {
+                    match element.as_slice() {
+                        b"href" => pending.href = Some(text.into_owned()),
+                        b"getcontentlength" => pending.size = text.trim().parse().ok(),
+                        b"getetag" => pending.etag = Some(text.trim().to_string()).filter(|e| !e.is_empty()),
+                        b"getlastmodified" => {
+                            pending.last_modified = httpdate::parse_http_date(text.trim())
+                                .ok()
+                                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                                .map(|d| d.as_secs())
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Event::End(e) => {
+                element.clear();
+                if e.local_name().as_ref() == b"response" {
+                    if let Some(Pending { href: Some(href), is_dir, size, last_modified, etag }) = current.take() {
+                        if let Some(path) = relative_path(&href, base_path) {
+                            let size = if is_dir { None } else { size };
+                            entries.push(RemoteEntry { path, is_dir, size, last_modified, etag });
+                        }
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+/// Storage use reported for a collection (RFC 4331).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quota {
+    pub used: Option<u64>,
+    /// Bytes that can still be stored; `None` when unlimited or unknown,
+    /// which servers report as a negative number (Nextcloud: -3).
+    pub available: Option<u64>,
+}
+
+/// Parse the quota of the first response of a multistatus body, or `None`
+/// if it reports neither quota property.
+pub fn parse_quota(xml: &str) -> Result<Option<Quota>, Box<dyn Error>> {
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    let mut quota: Option<Quota> = None;
+    let mut element = Vec::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => element = e.local_name().as_ref().to_vec(),
+            Event::Empty(e) if matches!(e.local_name().as_ref(), b"quota-used-bytes" | b"quota-available-bytes") => {
+                quota.get_or_insert_with(Quota::default);
+            }
+            Event::Text(text) => {
+                let bytes = || text.unescape().ok().and_then(|t| t.trim().parse::<i64>().ok()).and_then(|n| u64::try_from(n).ok());
+                match element.as_slice() {
+                    b"quota-used-bytes" => quota.get_or_insert_with(Quota::default).used = bytes(),
+                    b"quota-available-bytes" => quota.get_or_insert_with(Quota::default).available = bytes(),
+                    _ => {}
+                }
+            }
+            Event::End(e) => {
+                element.clear();
+                if e.local_name().as_ref() == b"response" {
+                    break;
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(quota)
+}
+
+/// The `checksum` values of the first response of a multistatus body,
+/// joined by spaces like an `OC-Checksum` header, or `None` if it has none.
+pub fn parse_checksums(xml: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    let mut checksums: Vec<String> = Vec::new();
+    let mut element = Vec::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => element = e.local_name().as_ref().to_vec(),
+            Event::Text(text) if element == b"checksum" => checksums.push(text.unescape()?.trim().to_string()),
+            Event::End(e) => {
+                element.clear();
+                if e.local_name().as_ref() == b"response" {
+                    break;
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(Some(checksums.join(" ")).filter(|c| !c.is_empty()))
+}
+
+fn mark_collection(current: &mut Option<Pending>) {
+    if let Some(pending) = current.as_mut() {
+        pending.is_dir = true;
+    }
+}
+
+/// The path of `href` below `base_path`, or `None` if it lies outside.
+fn relative_path(href: &str, base_path: &str) -> Option<String> {
+    // Absolute URLs: keep only the path.
+    let path = match href.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("/", |i| &rest[i..]),
+        None => href,
+    };
+    let path = percent_decode_str(path).decode_utf8_lossy();
+    let base = percent_decode_str(base_path).decode_utf8_lossy();
+    let base = base.trim_end_matches('/');
+    let rest = path.strip_prefix(base)?;
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return None;
+    }
+    Some(rest.trim_matches('/').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NEXTCLOUD: &str = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+  <d:response>
+    <d:href>/remote.php/dav/files/me/Photos/</d:href>
+    <d:propstat>
+      <d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+    <d:propstat>
+      <d:prop><d:getcontentlength/></d:prop>
+      <d:status>HTTP/1.1 404 Not Found</d:status>
+    </d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/remote.php/dav/files/me/Photos/Summer%202024/</d:href>
+    <d:propstat>
+      <d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/remote.php/dav/files/me/Photos/a&amp;b.jpg</d:href>
+    <d:propstat>
+      <d:prop>
+        <d:getlastmodified>Sat, 15 Jun 2024 12:34:56 GMT</d:getlastmodified>
+        <d:resourcetype/><d:getcontentlength>1024</d:getcontentlength>
+        <d:getetag>&quot;6671a3f0&quot;</d:getetag>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>"#;
+
+    #[test]
+    fn test_parse_prefixed_multistatus() {
+        let entries = parse_multistatus(NEXTCLOUD, "/remote.php/dav/files/me/").unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                RemoteEntry { path: "Photos".into(), is_dir: true, size: None, last_modified: None, etag: None },
+                RemoteEntry { path: "Photos/Summer 2024".into(), is_dir: true, size: None, last_modified: None, etag: None },
+                RemoteEntry {
+                    path: "Photos/a&b.jpg".into(),
+                    is_dir: false,
+                    size: Some(1024),
+                    last_modified: Some(1718454896),
+                    etag: Some("\"6671a3f0\"".into()),
+                },
+            ]
+        );
+        assert_eq!(entries[1].name(), "Summer 2024");
+    }
+
+    #[test]
+    fn test_parse_absolute_urls_and_default_namespace() {
+        let xml = r#"<multistatus xmlns="DAV:">
+  <response><href>https://dav.example.com/</href><propstat><prop>
+    <resourcetype><collection></collection></resourcetype></prop></propstat></response>
+  <response><href>https://dav.example.com/notes.txt</href><propstat><prop>
+    <getcontentlength>7</getcontentlength></prop></propstat></response>
+  <response><href>/elsewhere/x</href></response>
+</multistatus>"#;
+        let entries = parse_multistatus(xml, "").unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                RemoteEntry { path: "".into(), is_dir: true, size: None, last_modified: None, etag: None },
+                RemoteEntry { path: "notes.txt".into(), is_dir: false, size: Some(7), last_modified: None, etag: None },
+                RemoteEntry { path: "elsewhere/x".into(), is_dir: false, size: None, last_modified: None, etag: None },
+            ]
+        );
+        assert!(parse_multistatus(xml, "/dav").unwrap().is_empty());
+    }
+
+    /// Depth-1 listing as sent by Apache mod_dav: `D:` and `lp1:` prefixes,
+    /// typed dates and percent-encoded hrefs with a trailing slash on collections.
+    const APACHE: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:multistatus xmlns:D="DAV:" xmlns:ns0="urn:uuid:c2f41010-65b3-11d1-a29f-00aa00c14882/">
+<D:response xmlns:lp1="DAV:" xmlns:lp2="http://apache.org/dav/props/">
+<D:href>/dav/phone/</D:href>
+<D:propstat>
+<D:prop>
+<lp1:resourcetype><D:collection/></lp1:resourcetype>
+<lp1:getlastmodified ns0:dt="dateTime.rfc1123">Mon, 01 Jul 2024 08:00:00 GMT</lp1:getlastmodified>
+</D:prop>
+<D:status>HTTP/1.1 200 OK</D:status>
+</D:propstat>
+</D:response>
+<D:response xmlns:lp1="DAV:" xmlns:lp2="http://apache.org/dav/props/">
+<D:href>/dav/phone/WhatsApp%20Images/</D:href>
+<D:propstat>
+<D:prop>
+<lp1:resourcetype><D:collection/></lp1:resourcetype>
+<lp1:getlastmodified ns0:dt="dateTime.rfc1123">Tue, 02 Jul 2024 09:30:00 GMT</lp1:getlastmodified>
+</D:prop>
+<D:status>HTTP/1.1 200 OK</D:status>
+</D:propstat>
+</D:response>
+<D:response xmlns:lp1="DAV:" xmlns:lp2="http://apache.org/dav/props/">
+<D:href>/dav/phone/IMG_0001%2B1.jpg</D:href>
+<D:propstat>
+<D:prop>
+<lp1:resourcetype/>
+<lp1:getcontentlength>524288</lp1:getcontentlength>
+<lp1:getlastmodified ns0:dt="dateTime.rfc1123">Wed, 03 Jul 2024 10:15:30 GMT</lp1:getlastmodified>
+</D:prop>
+<D:status>HTTP/1.1 200 OK</D:status>
+</D:propstat>
+</D:response>
+</D:multistatus>"#;
+
+    #[test]
+    fn test_parse_apache_multistatus() {
+        let entries = parse_multistatus(APACHE, "/dav").unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                RemoteEntry { path: "phone".into(), is_dir: true, size: None, last_modified: Some(1719820800), etag: None },
+                RemoteEntry {
+                    path: "phone/WhatsApp Images".into(),
+                    is_dir: true,
+                    size: None,
+                    last_modified: Some(1719912600),
+                    etag: None,
+                },
+                RemoteEntry {
+                    path: "phone/IMG_0001+1.jpg".into(),
+                    is_dir: false,
+                    size: Some(524288),
+                    last_modified: Some(1720001730),
+                    etag: None,
+                },
+            ]
+        );
+        assert_eq!(Depth::Infinity.header(), "infinity");
+    }
+
+    #[test]
+    fn test_parse_quota() {
+        let nextcloud = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:s="http://sabredav.org/ns" xmlns:oc="http://owncloud.org/ns">
+  <d:response>
+    <d:href>/remote.php/dav/files/me/</d:href>
+    <d:propstat>
+      <d:prop>
+        <d:quota-used-bytes>5368709120</d:quota-used-bytes>
+        <d:quota-available-bytes>1073741824</d:quota-available-bytes>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>"#;
+        assert_eq!(parse_quota(nextcloud).unwrap(), Some(Quota { used: Some(5368709120), available: Some(1073741824) }));
+
+        let unlimited = nextcloud.replace("1073741824", "-3");
+        assert_eq!(parse_quota(&unlimited).unwrap(), Some(Quota { used: Some(5368709120), available: None }));
+
+        // Apache mod_dav names the unsupported properties in a 404 propstat.
+        let apache = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:multistatus xmlns:D="DAV:">
+<D:response xmlns:lp1="DAV:">
+<D:href>/dav/</D:href>
+<D:propstat>
+<D:prop>
+<D:quota-used-bytes/>
+<D:quota-available-bytes/>
+</D:prop>
+<D:status>HTTP/1.1 404 Not Found</D:status>
+</D:propstat>
+</D:response>
+</D:multistatus>"#;
+        assert_eq!(parse_quota(apache).unwrap(), Some(Quota { used: None, available: None }));
+        assert_eq!(parse_quota(NEXTCLOUD).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_checksums() {
+        let owncloud = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+  <d:response>
+    <d:href>/remote.php/dav/files/me/a.jpg</d:href>
+    <d:propstat>
+      <d:prop>
+        <oc:checksums><oc:checksum>SHA1:86f7e437faa5a7fce15d1ddcb9eaeaea377667b8 MD5:0cc175b9c0f1b6a831c399e269772661</oc:checksum></oc:checksums>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>"#;
+        assert_eq!(
+            parse_checksums(owncloud).unwrap().as_deref(),
+            Some("SHA1:86f7e437faa5a7fce15d1ddcb9eaeaea377667b8 MD5:0cc175b9c0f1b6a831c399e269772661")
+        );
+        // Nextcloud leaves the property empty for files uploaded without one.
+        let empty = owncloud.replace(
+            "<oc:checksum>SHA1:86f7e437faa5a7fce15d1ddcb9eaeaea377667b8 MD5:0cc175b9c0f1b6a831c399e269772661</oc:checksum>",
+            "",
+        );
+        assert_eq!(parse_checksums(&empty).unwrap(), None);
+        assert_eq!(parse_checksums(NEXTCLOUD).unwrap(), None);
+    }
+
+    #[test]
+    fn test_relative_path_needs_a_component_boundary() {
+        assert_eq!(relative_path("/dav/a", "/dav"), Some("a".into()));
+        assert_eq!(relative_path("/dav", "/dav/"), Some("".into()));
+        assert_eq!(relative_path("/davx/a", "/dav"), None);
+    }
+}
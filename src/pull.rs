@@ -0,0 +1,379 @@
+//! Restoring synced files from the server.
+//!
+//! `pull` downloads every file recorded in the hash store into the configured
+//! folders. Progress is checkpointed to a pull-state file after each file, so
+//! an interrupted restore continues where it stopped: completed files whose
+//! local size and hash still match are skipped without a request, and the
+//! `.part` file of the file that was in flight is resumed with a range request.
+//! Existing local files are never overwritten.
+
+use crate::cas::{object_path, Manifest};
+use crate::config::{Config, Layout, RemoteHashStore};
+use crate::filter::is_below;
+use crate::fingerprint::RemoteFingerprint;
+use crate::hash_delta::load_deltas;
+use crate::hash_store::HashStore;
+use crate::hash_store_guard::{download_store, store_timeout};
+use crate::local_path::{is_name_error, resolve_local_destination, sanitize_destination};
+use crate::output::HumanDisplay;
+use crate::sync::local_path_for;
+use crate::webdav_client::{part_path, VerifiedDownload, WebDavClient, PART_SUFFIX};
+use crate::work_dir::WorkDir;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+
+/// Progress of a pull, persisted between runs.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PullState {
+    /// Files downloaded completely, with what was written locally.
+    #[serde(default)]
+    pub completed: BTreeMap<String, PulledFile>,
+    /// The file whose download was started last and has not completed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_flight: Option<InFlight>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PulledFile {
+    pub size: u64,
+    pub hash: String,
+}
+
+/// A download that may have left a `.part` file behind.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InFlight {
+    pub remote_path: String,
+    /// Version being downloaded; the `.part` file is only resumed against it.
+    pub fingerprint: RemoteFingerprint,
+}
+
+impl PullState {
+    /// Load the state, or start empty if the file does not exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        match fs::read_to_string(&path) {
+            Ok(content) => Ok(serde_yaml::from_str(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the state through a temporary file, so a crash never leaves a
+    /// truncated state behind.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        let mut temp_name = path.as_os_str().to_owned();
+        temp_name.push(".tmp");
+        fs::write(&temp_name, serde_yaml::to_string(self)?)?;
+        fs::rename(&temp_name, path)?;
+        Ok(())
+    }
+}
+
+/// Outcome of a pull, as remote paths.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PullReport {
+    pub downloaded: Vec<String>,
+    /// Downloads continued from the `.part` file of an earlier run.
+    pub resumed: Vec<String>,
+    /// Files completed by an earlier run and still intact locally.
+    pub skipped: usize,
+    /// Files recorded in the hash store that no longer exist on the server.
+    pub missing: Vec<String>,
+    /// Files that could not be placed or kept changing during the download.
+    pub failed: Vec<String>,
+    /// Files that exist locally with different content and were left alone.
+    pub kept_local: Vec<String>,
+    /// Files stored under another local name (`pull_sanitize_local`), with
+    /// that local path.
+    pub renamed: BTreeMap<String, String>,
+    /// Set when the pull was cancelled before all files were handled.
+    pub interrupted: bool,
+}
+
+impl HumanDisplay for PullReport {
+    fn human(&self) -> String {
+        let mut out = format!(
+            "{} {} downloaded, {} already restored",
+            self.downloaded.len(),
+            if self.downloaded.len() == 1 { "file" } else { "files" },
+            self.skipped
+        );
+        if self.interrupted {
+            out.push_str("\n  interrupted, run pull again to continue");
+        }
+        for (label, paths) in [
+            ("resumed", &self.resumed),
+            ("missing", &self.missing),
+            ("failed", &self.failed),
+            ("kept local", &self.kept_local),
+        ] {
+            for path in paths {
+                out.push_str(&format!("\n  {}: {}", label, path));
+            }
+        }
+        for (remote_path, local_path) in &self.renamed {
+            out.push_str(&format!("\n  renamed locally: {} -> {}", remote_path, local_path));
+        }
+        out
+    }
+}
+
+/// The hash store listing the files to pull: the remote copy (with its
+/// deltas, if `hash_store_deltas` is set) if the remote store is enabled and
+/// exists, the local one otherwise. With `layout: cas`, the manifest.
+pub async fn store_for_pull(config: &Config, client: &WebDavClient) -> Result<HashStore, Box<dyn Error>> {
+    if config.layout == Layout::Cas {
+        return Ok(Manifest::load(client, config).await?.to_store());
+    }
+    if config.remote_hash_store == RemoteHashStore::Enabled {
+        let work_dir = WorkDir::create(config.temp_dir.as_deref().map(Path::new))?;
+        let copy = work_dir.file("remote_hashes.yaml");
+        let timeout = store_timeout(config);
+        let mut found = download_store(client, &config.remote_hash_path, config.compress_hash_store, timeout, &copy).await?;
+        let mut store = HashStore::load(&copy)?;
+        if config.hash_store_deltas.is_some() {
+            found |= !load_deltas(client, &config.remote_hash_path, &mut store, &work_dir).await?.names.is_empty();
+        }
+        if found {
+            return Ok(store);
+        }
+        info!("No remote hash store found, pulling the files of the local one");
+    }
+    HashStore::load(&config.hash_store_path)
+}
+
+/// Render remote paths as a `--files-from` manifest, one path per line.
+pub fn format_manifest(paths: &[String]) -> String {
+    paths.iter().map(|path| format!("{}\n", path)).collect()
+}
+
+/// Read a `--files-from` manifest. Blank lines and lines starting with `#`
+/// are ignored.
+pub fn read_manifest<P: AsRef<Path>>(path: P) -> Result<Vec<String>, Box<dyn Error>> {
+    let content = fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read manifest '{}': {}", path.as_ref().display(), e))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.trim_matches('/').to_string())
+        .collect())
+}
+
+/// Local destination of a remote path: the existing file in one of the
+/// folders, or a new one below the first folder.
+pub fn pull_destination(config: &Config, remote_path: &str) -> Result<PathBuf, Box<dyn Error>> {
+    if let Some(existing) = local_path_for(config, remote_path) {
+        return Ok(existing);
+    }
+    let target_dir = config.target_dir.trim_end_matches('/');
+    let relative_path = if target_dir.is_empty() {
+        Some(remote_path)
+    } else {
+        remote_path.strip_prefix(target_dir).and_then(|p| p.strip_prefix('/'))
+    };
+    let relative_path = relative_path.ok_or_else(|| format!("'{}' is outside target_dir '{}'", remote_path, target_dir))?;
+    let folder = config.folders.first().ok_or("No folder configured to pull into")?;
+    fs::create_dir_all(&folder.path)?;
+    resolve_local_destination(Path::new(&folder.path), folder.unroute(relative_path).unwrap_or(relative_path))
+}
+
+/// Create the parent directories of `destination` and check that its
+/// download file can be created, without leaving one behind.
+fn probe_destination(destination: &Path) -> std::io::Result<()> {
+    if destination.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let part = part_path(destination);
+    if !part.exists() {
+        fs::File::create(&part)?;
+        fs::remove_file(&part)?;
+    }
+    Ok(())
+}
+
+/// Why the local filesystem rejected a name.
+fn name_error_reason(e: &std::io::Error) -> String {
+    match e.kind() {
+        std::io::ErrorKind::InvalidFilename => format!("name too long for the local filesystem ({})", e),
+        _ => format!("name not valid on the local filesystem ({})", e),
+    }
+}
+
+/// A local name for `destination` the filesystem accepts, after it rejected
+/// the name with `error`; the reason to skip the file otherwise. The name
+/// only depends on the remote path, so later pulls find the file again.
+fn sanitized_destination(config: &Config, destination: &Path, error: &std::io::Error) -> Result<PathBuf, String> {
+    if !config.pull_sanitize_local {
+        return Err(format!("{}, set pull_sanitize_local to rename it", name_error_reason(error)));
+    }
+    let root = config.folders.first().map(|folder| Path::new(&folder.path)).ok_or("no folder configured")?;
+    let sanitized = sanitize_destination(root, destination, PART_SUFFIX.len()).ok_or_else(|| name_error_reason(error))?;
+    match probe_destination(&sanitized) {
+        Ok(()) => Ok(sanitized),
+        Err(e) if is_name_error(&e) => Err(name_error_reason(&e)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Download the files recorded in `store`, continuing the pull recorded at
+/// `pull_state_path` unless `restart` is set. A non-empty `only` restricts
+/// the pull to files at or below these remote paths.
+///
+/// The state is saved before each download starts and after it completes,
+/// so a crash or `cancel` loses at most the progress of the file in flight.
+pub async fn pull(
+    config: &Config,
+    client: &WebDavClient,
+    store: &HashStore,
+    only: &[String],
+    restart: bool,
+    cancel: &CancellationToken,
+) -> Result<PullReport, Box<dyn Error>> {
+    let state_path = Path::new(&config.pull_state_path);
+    let mut state = if restart {
+        if let Some(in_flight) = PullState::load(state_path)?.in_flight {
+            if let Ok(destination) = pull_destination(config, &in_flight.remote_path) {
+                let _ = fs::remove_file(part_path(&destination));
+            }
+        }
+        let _ = fs::remove_file(state_path);
+        PullState::default()
+    } else {
+        PullState::load(state_path)?
+    };
+
+    let remote_paths: BTreeSet<&String> = store
+        .regular_hashes
+        .keys()
+        .chain(store.pseudo_hashes.keys())
+        .filter(|path| only.is_empty() || only.iter().any(|prefix| is_below(path, prefix)))
+        .collect();
+    let mut report = PullReport::default();
+    for remote_path in remote_paths {
+        if cancel.is_cancelled() {
+            report.interrupted = true;
+            break;
+        }
+        let destination = match pull_destination(config, remote_path) {
+            Ok(destination) => destination,
+            Err(e) => {
+                warn!("Not pulling {}: {}", remote_path, e);
+                report.failed.push(remote_path.clone());
+                continue;
+            }
+        };
+        let destination = match probe_destination(&destination) {
+            Ok(()) => destination,
+            Err(e) if is_name_error(&e) => match sanitized_destination(config, &destination, &e) {
+                Ok(sanitized) => {
+                    report.renamed.insert(remote_path.clone(), sanitized.display().to_string());
+                    sanitized
+                }
+                Err(reason) => {
+                    warn!("Not pulling {}: {}", remote_path, reason);
+                    report.failed.push(remote_path.clone());
+                    continue;
+                }
+            },
+            Err(e) => return Err(e.into()),
+        };
+        // Local files are never overwritten, only confirmed as restored.
+        if destination.exists() {
+            if is_restored(&destination, state.completed.get(remote_path), store.regular_hashes.get(remote_path)).await? {
+                report.skipped += 1;
+            } else {
+                warn!("{} exists locally with other content, keeping the local file", destination.display());
+                report.kept_local.push(remote_path.clone());
+            }
+            continue;
+        }
+
+        // With `layout: cas` the content is in the object named by its hash.
+        let source = match config.layout {
+            Layout::Mirror => remote_path.clone(),
+            Layout::Cas => match store.regular_hashes.get(remote_path) {
+                Some(hash) => object_path(config, hash),
+                None => {
+                    warn!("Not pulling {}: no object recorded", remote_path);
+                    report.failed.push(remote_path.clone());
+                    continue;
+                }
+            },
+        };
+        let Some(version) = client.stat(&source).await?.map(|stat| stat.fingerprint()) else {
+            warn!("{} is in the hash store but not on the server", remote_path);
+            report.missing.push(remote_path.clone());
+            continue;
+        };
+        let resumable = state
+            .in_flight
+            .as_ref()
+            .is_some_and(|f| f.remote_path == *remote_path && f.fingerprint == version)
+            && part_path(&destination).exists();
+        if resumable {
+            info!("Resuming the download of {}", remote_path);
+            report.resumed.push(remote_path.clone());
+        } else {
+            let _ = fs::remove_file(part_path(&destination));
+            state.in_flight = Some(InFlight { remote_path: remote_path.clone(), fingerprint: version.clone() });
+            state.save(state_path)?;
+        }
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let outcome = tokio::select! {
+            outcome = client.download_resumable(&source, &destination, &version) => outcome?,
+            _ = cancel.cancelled() => {
+                report.interrupted = true;
+                break;
+            }
+        };
+        match outcome {
+            VerifiedDownload::Downloaded(_) => {}
+            VerifiedDownload::Missing => {
+                report.missing.push(remote_path.clone());
+                continue;
+            }
+            VerifiedDownload::Unstable => {
+                warn!("{} kept changing on the server during the download", remote_path);
+                report.failed.push(remote_path.clone());
+                continue;
+            }
+        }
+
+        let hash = HashStore::compute(&destination, false).await?;
+        if store.regular_hashes.get(remote_path).is_some_and(|recorded| *recorded != hash) {
+            warn!("{} differs from the content recorded in the hash store", remote_path);
+        }
+        let size = fs::metadata(&destination)?.len();
+        state.completed.insert(remote_path.clone(), PulledFile { size, hash });
+        state.in_flight = None;
+        state.save(state_path)?;
+        report.downloaded.push(remote_path.clone());
+    }
+    Ok(report)
+}
+
+/// Whether an existing local file has the content it was pulled with, or
+/// else the content recorded in the hash store.
+async fn is_restored(path: &Path, pulled: Option<&PulledFile>, recorded: Option<&String>) -> Result<bool, Box<dyn Error>> {
+    if let Some(pulled) = pulled {
+        if fs::metadata(path)?.len() != pulled.size {
+            return Ok(false);
+        }
+    }
+    let Some(expected) = pulled.map(|p| &p.hash).or(recorded) else {
+        return Ok(false);
+    };
+    Ok(HashStore::compute(path, false).await? == *expected)
+}
@@ -0,0 +1,168 @@
+//! Reconciliation of hash store entries that record one file under several
+//! keys.
+//!
+//! Store keys are remote paths and embed `target_dir`, so after it changed
+//! (without `--migrate-target-dir`), a file is recorded, and stored on the
+//! server, once per target_dir it was synced to. Syncs therefore record the
+//! origin of every entry: the folder (by id) and the path below it.
+//! `hashes reconcile` groups the entries of the configured folders by origin;
+//! where a file is synced below the current `target_dir`, its entries outside
+//! it are superseded, and their remote copies are deleted. A file not synced
+//! below it yet has its latest copy moved there instead.
+//!
+//! The id derived for a folder without an explicit `id` changes with
+//! `target_dir`, so only folders with an explicit `id` can be reconciled
+//! across such a change. Entries of folders not in the config are never
+//! touched.
+
+use crate::config::{Config, FolderConfig};
+use crate::filter::is_below;
+use crate::hash_store::HashStore;
+use crate::output::HumanDisplay;
+use crate::sync::remote_path_in;
+use crate::webdav_client::WebDavClient;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+/// The local file a hash store entry was synced from.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Origin {
+    /// Id of the folder, see [`Config::folder_id`].
+    pub folder: String,
+    /// Path below the folder, with `/` separators.
+    pub path: String,
+}
+
+impl Origin {
+    pub fn new(folder: &str, path: &str) -> Self {
+        Origin { folder: folder.to_string(), path: path.replace('\\', "/") }
+    }
+}
+
+/// A remote file moved below the current `target_dir`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Move {
+    pub from: String,
+    pub to: String,
+}
+
+/// What `hashes reconcile` does.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReconcilePlan {
+    /// Latest copies of files not synced below the current `target_dir`.
+    pub moves: Vec<Move>,
+    /// Superseded entries, deleted from the server and the store.
+    pub deletions: Vec<String>,
+}
+
+impl ReconcilePlan {
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty() && self.deletions.is_empty()
+    }
+}
+
+impl HumanDisplay for ReconcilePlan {
+    fn human(&self) -> String {
+        if self.is_empty() {
+            return "No superseded hash store entries".to_string();
+        }
+        let mut out = format!("{} to move, {} superseded", self.moves.len(), self.deletions.len());
+        for m in &self.moves {
+            out.push_str(&format!("\n  move {} -> {}", m.from, m.to));
+        }
+        for key in &self.deletions {
+            out.push_str(&format!("\n  delete {}", key));
+        }
+        out
+    }
+}
+
+/// Plan the reconciliation of the entries of `store` for `config`.
+pub fn plan(config: &Config, store: &HashStore) -> ReconcilePlan {
+    let folders: HashMap<String, &FolderConfig> = config.folders.iter().map(|folder| (config.folder_id(folder), folder)).collect();
+    let mut groups: BTreeMap<&Origin, Vec<&String>> = BTreeMap::new();
+    for (key, origin) in &store.origins {
+        let synced = store.regular_hashes.contains_key(key) || store.pseudo_hashes.contains_key(key);
+        if synced && folders.contains_key(&origin.folder) {
+            groups.entry(origin).or_default().push(key);
+        }
+    }
+    let mut plan = ReconcilePlan::default();
+    for (origin, keys) in groups {
+        let (current, mut stale): (Vec<&String>, Vec<&String>) =
+            keys.into_iter().partition(|key| is_below(key, &config.target_dir));
+        if stale.is_empty() {
+            continue;
+        }
+        if current.is_empty() {
+            // The copy stamped last, else the last key.
+            let latest = (0..stale.len()).max_by_key(|&i| (store.stamps.get(stale[i]).map(|s| s.mtime_ns), i)).unwrap_or(0);
+            let from = stale.remove(latest).clone();
+            plan.moves.push(Move { from, to: remote_path_in(config, folders[&origin.folder], &origin.path) });
+        }
+        plan.deletions.extend(stale.into_iter().cloned());
+    }
+    plan
+}
+
+/// Carry out `plan`, updating `store` after every step so an interrupted
+/// run leaves it matching the server.
+pub async fn execute(client: &WebDavClient, store: &mut HashStore, plan: &ReconcilePlan) -> Result<(), Box<dyn Error>> {
+    for m in &plan.moves {
+        client.move_path(&m.from, &m.to, false).await?;
+        store.rename(&m.from, &m.to);
+        info!("Moved {} to {}", m.from, m.to);
+    }
+    for key in &plan.deletions {
+        client.delete_file(key).await?;
+        store.forget(key);
+        info!("Deleted superseded {}", key);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_groups_entries_by_origin() {
+        let config: Config = serde_yaml::from_str(
+            "webdav_url: \"x\"\nfolders: [{path: \"/sdcard/DCIM\", id: cam}]\ntarget_dir: \"phone/2025\"\n",
+        )
+        .unwrap();
+        let mut store = HashStore::default();
+        for (key, folder, path) in [
+            ("phone/2024/a.jpg", "cam", "a.jpg"),
+            ("phone/2025/a.jpg", "cam", "a.jpg"),
+            ("phone/2023/b.jpg", "cam", "b.jpg"),
+            ("phone/2024/b.jpg", "cam", "b.jpg"),
+            ("tablet/c.jpg", "other", "c.jpg"),
+        ] {
+            store.regular_hashes.insert(key.to_string(), "h".to_string());
+            store.origins.insert(key.to_string(), Origin::new(folder, path));
+        }
+
+        let plan = plan(&config, &store);
+        assert_eq!(plan.moves, vec![Move { from: "phone/2024/b.jpg".to_string(), to: "phone/2025/b.jpg".to_string() }]);
+        assert_eq!(plan.deletions, vec!["phone/2024/a.jpg", "phone/2023/b.jpg"]);
+    }
+
+    #[test]
+    fn test_plan_moves_into_routed_path() {
+        let config: Config = serde_yaml::from_str(
+            "webdav_url: \"x\"\nfolders: [{path: \"/sdcard/DCIM\", id: cam, routes: [{match: \"*.mp4\", target_subdir: videos}]}]\n\
+             target_dir: \"phone/2025\"\n",
+        )
+        .unwrap();
+        let mut store = HashStore::default();
+        store.regular_hashes.insert("phone/2024/Camera/a.mp4".to_string(), "h".to_string());
+        store.origins.insert("phone/2024/Camera/a.mp4".to_string(), Origin::new("cam", "Camera/a.mp4"));
+
+        let plan = plan(&config, &store);
+        let to = "phone/2025/videos/Camera/a.mp4".to_string();
+        assert_eq!(plan.moves, vec![Move { from: "phone/2024/Camera/a.mp4".to_string(), to }]);
+    }
+}
@@ -0,0 +1,187 @@
+//! Existence checks of remote files answered from directory listings.
+//!
+//! A HEAD per file costs a round trip each, which for tens of thousands of
+//! unchanged files adds up to many minutes. Instead the directory of a file
+//! is listed with one Depth 1 PROPFIND when the first of its files is
+//! checked, and the other files of that directory are looked up in the
+//! listing. Files the listing cannot answer for get a HEAD as before.
+//!
+//! In low-memory mode every listing is spilled to a [`SpillIndex`] in the
+//! run's work directory, so only the names of the listed directories stay in
+//! memory.
+
+use crate::config::Config;
+use crate::fingerprint::RemoteStat;
+use crate::spill_index::SpillIndex;
+use crate::webdav_client::WebDavClient;
+use crate::work_dir::WorkDir;
+use log::info;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+/// Size, Last-Modified and ETag of a spilled [`RemoteStat`].
+type SpilledStat = (Option<u64>, Option<u64>, Option<String>);
+
+/// The files of one listed directory, by remote path.
+#[derive(Debug)]
+enum Listing {
+    Memory(HashMap<String, RemoteStat>),
+    Spilled(SpillIndex),
+}
+
+/// The files of the remote directories listed so far in a run.
+#[derive(Debug, Default)]
+pub struct RemoteListings {
+    /// Files per directory; `None` where listing failed.
+    dirs: HashMap<String, Option<Listing>>,
+    /// Where listings are spilled to in low-memory mode.
+    spill: Option<WorkDir>,
+}
+
+impl RemoteListings {
+    /// Listings kept in memory, or spilled to disk with `low_memory`.
+    pub fn for_config(config: &Config) -> std::io::Result<Self> {
+        let spill = config.low_memory.then(|| WorkDir::create(config.temp_dir.as_deref().map(Path::new))).transpose()?;
+        Ok(RemoteListings { dirs: HashMap::new(), spill })
+    }
+
+    /// Number of files in the listings spilled to disk.
+    pub fn spilled_files(&self) -> usize {
+        self.dirs.values().flatten().map(|listing| if let Listing::Spilled(index) = listing { index.len() } else { 0 }).sum()
+    }
+
+    /// Drop the listings, which are looked up again when needed; returns
+    /// how many files they held in memory.
+    pub fn release(&mut self) -> usize {
+        let held = self.dirs.values().flatten().map(|listing| if let Listing::Memory(files) = listing { files.len() } else { 0 }).sum();
+        self.dirs.clear();
+        held
+    }
+
+    /// Record `files`, the listing of `dir`, or `None` if it failed.
+    pub fn record(&mut self, dir: &str, files: Option<Vec<(String, RemoteStat)>>) -> std::io::Result<()> {
+        let listing = match (files, &self.spill) {
+            (None, _) => None,
+            (Some(files), None) => Some(Listing::Memory(files.into_iter().collect())),
+            (Some(files), Some(work_dir)) => {
+                let spilled: Vec<(String, SpilledStat)> =
+                    files.into_iter().map(|(path, stat)| (path, (stat.size, stat.last_modified, stat.etag))).collect();
+                let file = work_dir.file(&format!("listing-{}", self.dirs.len()));
+                Some(Listing::Spilled(SpillIndex::write(file, spilled)?))
+            }
+        };
+        self.dirs.insert(dir.to_string(), listing);
+        Ok(())
+    }
+
+    /// What the listing of `dir` says about `remote_path`: `None` if `dir`
+    /// was not listed or its listing failed, else the file's metadata if it
+    /// exists.
+    pub fn lookup(&self, dir: &str, remote_path: &str) -> std::io::Result<Option<Option<RemoteStat>>> {
+        Ok(match self.dirs.get(dir) {
+            Some(Some(Listing::Memory(files))) => Some(files.get(remote_path).cloned()),
+            Some(Some(Listing::Spilled(index))) => Some(
+                index
+                    .get::<SpilledStat>(remote_path)?
+                    .map(|(size, last_modified, etag)| RemoteStat { size, last_modified, etag }),
+            ),
+            _ => None,
+        })
+    }
+
+    /// Remote paths of the files listed in `dir`, or `None` if it was not
+    /// listed or listing it failed.
+    pub fn files(&self, dir: &str) -> std::io::Result<Option<Vec<String>>> {
+        Ok(match self.dirs.get(dir) {
+            Some(Some(Listing::Memory(files))) => Some(files.keys().cloned().collect()),
+            Some(Some(Listing::Spilled(index))) => Some(index.keys()?),
+            _ => None,
+        })
+    }
+
+    /// Directories listed so far, whether or not listing them succeeded.
+    pub fn dirs(&self) -> impl Iterator<Item = &str> {
+        self.dirs.keys().map(String::as_str)
+    }
+
+    /// Metadata of the remote file `remote_path`, or `None` if it does not
+    /// exist, like `WebDavClient::stat`.
+    pub async fn stat(&mut self, client: &WebDavClient, remote_path: &str) -> Result<Option<RemoteStat>, Box<dyn Error>> {
+        let dir = remote_path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        if !self.dirs.contains_key(dir) {
+            let files = match client.list_if_exists(dir).await {
+                Ok(Some(entries)) => Some(
+                    entries.iter().filter(|e| !e.is_dir).map(|e| (e.path.clone(), RemoteStat::from_entry(e))).collect(),
+                ),
+                // Nothing below a directory that does not exist yet.
+                Ok(None) => Some(Vec::new()),
+                Err(e) => {
+                    info!("Cannot list remote directory '{}', checking its files one by one: {}", dir, e);
+                    None
+                }
+            };
+            self.record(dir, files)?;
+        }
+        match self.lookup(dir, remote_path)? {
+            Some(None) => Ok(None),
+            // A listing without sizes cannot tell a changed file apart.
+            Some(Some(stat)) if stat.size.is_some() => Ok(Some(stat)),
+            _ => client.stat(remote_path).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A synthetic listing of one large remote directory.
+    fn listing(count: u64) -> Vec<(String, RemoteStat)> {
+        (0..count)
+            .map(|i| {
+                let stat = RemoteStat {
+                    size: Some(i * 31),
+                    last_modified: (i % 3 != 0).then_some(1_700_000_000 + i),
+                    etag: (i % 2 == 0).then(|| format!("\"{:x}\"", i)),
+                };
+                (format!("phone/DCIM/Camera/IMG_{:06}.jpg", i), stat)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_spilled_listing_answers_like_the_in_memory_one() {
+        let temp = tempfile::tempdir().unwrap();
+        let yaml = format!("webdav_url: \"x\"\nfolders: []\ntemp_dir: \"{}\"\n", temp.path().display());
+        let mut config: Config = serde_yaml::from_str(&yaml).unwrap();
+        let mut memory = RemoteListings::for_config(&config).unwrap();
+        config.low_memory = true;
+        let mut spilled = RemoteListings::for_config(&config).unwrap();
+
+        let files = listing(200_000);
+        let dir = "phone/DCIM/Camera";
+        memory.record(dir, Some(files.clone())).unwrap();
+        spilled.record(dir, Some(files.clone())).unwrap();
+        spilled.record("phone/failed", None).unwrap();
+        assert_eq!(memory.spilled_files(), 0);
+        assert_eq!(spilled.spilled_files(), 200_000);
+
+        for (path, stat) in files.iter().step_by(97) {
+            assert_eq!(spilled.lookup(dir, path).unwrap(), Some(Some(stat.clone())));
+            assert_eq!(spilled.lookup(dir, path).unwrap(), memory.lookup(dir, path).unwrap());
+        }
+        let missing = "phone/DCIM/Camera/IMG_200000.jpg";
+        assert_eq!(spilled.lookup(dir, missing).unwrap(), Some(None));
+        assert_eq!(memory.lookup(dir, missing).unwrap(), Some(None));
+        assert_eq!(spilled.lookup("phone/failed", missing).unwrap(), None);
+        assert_eq!(spilled.lookup("phone/other", missing).unwrap(), None);
+        assert_eq!(spilled.files(dir).unwrap().unwrap().len(), 200_000);
+        assert_eq!(spilled.files("phone/failed").unwrap(), None);
+
+        // Spilled listings hold nothing in memory that dropping them frees.
+        assert_eq!(spilled.release(), 0);
+        assert_eq!(memory.release(), 200_000);
+        assert_eq!(memory.lookup(dir, missing).unwrap(), None);
+    }
+}
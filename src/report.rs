@@ -0,0 +1,368 @@
+use crate::case_collision::CaseCollision;
+use crate::cdc::ChunkChange;
+use crate::compact::Compaction;
+use crate::folder_kind::FolderKind;
+use crate::output::HumanDisplay;
+use crate::problem_names::escape;
+use crate::profile::Profile;
+use crate::units::format_byte_size;
+use crate::verify_sampling::SampleReport;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Outcome of processing a single file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOutcome {
+    Uploaded,
+    Skipped,
+}
+
+/// Counters for a group of files (a folder or an extension).
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferStats {
+    pub uploaded: usize,
+    pub skipped: usize,
+    pub bytes_uploaded: u64,
+}
+
+impl TransferStats {
+    fn record(&mut self, outcome: FileOutcome, bytes: u64) {
+        match outcome {
+            FileOutcome::Uploaded => {
+                self.uploaded += 1;
+                self.bytes_uploaded += bytes;
+            }
+            FileOutcome::Skipped => self.skipped += 1,
+        }
+    }
+}
+
+/// Outcome counters of a single sync run.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncReport {
+    /// Files that were uploaded to the remote.
+    pub uploaded: usize,
+    /// Files whose hash matched the store and that already existed remotely.
+    pub skipped: usize,
+    /// Total size of the uploaded files.
+    #[serde(default)]
+    pub bytes_uploaded: u64,
+    /// Breakdown per configured folder, by its id (`Config::folder_id`).
+    #[serde(default)]
+    pub folders: BTreeMap<String, TransferStats>,
+    /// Breakdown per lowercase file extension ("" for files without one).
+    #[serde(default)]
+    pub extensions: BTreeMap<String, TransferStats>,
+    /// Remote paths claimed by more than one local file; only the first was synced.
+    #[serde(default)]
+    pub collisions: Vec<String>,
+    /// Remote paths claimed by local files differing only in case (`case_collision_policy`).
+    #[serde(default)]
+    pub case_collisions: Vec<CaseCollision>,
+    /// Files with problem names synced under a cleaned-up remote path
+    /// (`normalize_problem_names`), with that path.
+    #[serde(default)]
+    pub renamed: BTreeMap<String, String>,
+    /// Files whose content was already stored as an object (`layout: cas`);
+    /// only the manifest changed.
+    #[serde(default)]
+    pub deduplicated: Vec<String>,
+    /// Upload retries performed across all files.
+    #[serde(default)]
+    pub retries: u32,
+    /// Total time spent waiting between retries.
+    #[serde(default)]
+    pub backoff_ms: u64,
+    /// Set when the transfer budget stopped the run before all files were uploaded.
+    #[serde(default)]
+    pub budget_exhausted: bool,
+    /// Seconds the wall clock jumped during the run (negative: back), if it
+    /// did; `trust_mtime` is suspended from then on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_jump_secs: Option<i64>,
+    /// Uploads verified by downloading them (`verify_sampling`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample: Option<SampleReport>,
+    /// Chunk analysis of changed large files (with `cdc_dedup`).
+    #[serde(default)]
+    pub chunk_changes: Vec<ChunkChange>,
+    /// Uploads the server stored with a different size than the local file
+    /// (`verify_upload_size`); they are not recorded in the hash store.
+    #[serde(default)]
+    pub truncated: Vec<String>,
+    /// Remote paths changed on the server between checking and uploading
+    /// them; the remote version was kept and the local file is retried next run.
+    #[serde(default)]
+    pub conflicts: Vec<String>,
+    /// Files of a batch upload that the server did not store, with the reason;
+    /// they are uploaded again on the next run.
+    #[serde(default)]
+    pub failed: BTreeMap<String, String>,
+    /// Folders skipped because their `require_mounted` or `require_marker_file`
+    /// check failed.
+    #[serde(default)]
+    pub unmounted: Vec<String>,
+    /// What each configured folder path pointed at when the run reached it.
+    #[serde(default)]
+    pub folder_kinds: BTreeMap<String, FolderKind>,
+    /// Remote copies deleted because the local file was deleted (`mirror_deletions`).
+    #[serde(default)]
+    pub deleted: Vec<String>,
+    /// Remote copies kept because archiving or deleting them failed.
+    #[serde(default)]
+    pub deletion_blocked: Vec<String>,
+    /// Remote paths a read-only run would have uploaded.
+    #[serde(default)]
+    pub planned: Vec<String>,
+    /// Remote copies a read-only run would have deleted.
+    #[serde(default)]
+    pub planned_deletions: Vec<String>,
+    /// Files per folder id left out by its extension allowlist or the run's filters.
+    #[serde(default)]
+    pub filtered_out: BTreeMap<String, usize>,
+    /// Peak resident memory of the process at the end of the run; only
+    /// measured with the `memory_stats` feature, on Linux.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_memory_bytes: Option<u64>,
+    /// Directories skipped with everything below them for their `.nomedia` file.
+    #[serde(default)]
+    pub nomedia_skipped: Vec<String>,
+    /// Stale hash store entries removed by `auto_compact`.
+    #[serde(default)]
+    pub compaction: Option<Compaction>,
+    /// Phase timings, printed with `--profile-performance`.
+    #[serde(default)]
+    pub profile: Profile,
+}
+
+impl SyncReport {
+    /// Record the outcome of one file of the folder with id `folder`.
+    pub fn record(&mut self, folder: &str, path: &Path, outcome: FileOutcome, bytes: u64) {
+        match outcome {
+            FileOutcome::Uploaded => {
+                self.uploaded += 1;
+                self.bytes_uploaded += bytes;
+            }
+            FileOutcome::Skipped => self.skipped += 1,
+        }
+        self.folders
+            .entry(folder.to_string())
+            .or_default()
+            .record(outcome, bytes);
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        self.extensions.entry(extension).or_default().record(outcome, bytes);
+    }
+
+    /// The `n` extensions with the most uploaded bytes, largest first.
+    pub fn top_extensions(&self, n: usize) -> Vec<(&str, &TransferStats)> {
+        let mut top: Vec<_> = self
+            .extensions
+            .iter()
+            .map(|(ext, stats)| (ext.as_str(), stats))
+            .collect();
+        top.sort_by(|a, b| b.1.bytes_uploaded.cmp(&a.1.bytes_uploaded).then(a.0.cmp(b.0)));
+        top.truncate(n);
+        top
+    }
+}
+
+/// Render the one-line summary shown on the CLI and in notifications.
+pub fn format_summary(report: &SyncReport) -> String {
+    format!(
+        "{} {} uploaded, {} unchanged",
+        report.uploaded,
+        if report.uploaded == 1 { "file" } else { "files" },
+        report.skipped
+    )
+}
+
+impl HumanDisplay for SyncReport {
+    fn human(&self) -> String {
+        let mut out = format_summary(self);
+        if self.retries > 0 {
+            out.push_str(&format!(
+                "\n  {} {}, {:.1}s spent backing off",
+                self.retries,
+                if self.retries == 1 { "retry" } else { "retries" },
+                self.backoff_ms as f64 / 1000.0
+            ));
+        }
+        if self.budget_exhausted {
+            out.push_str("\n  transfer budget exhausted, remaining uploads postponed");
+        }
+        if let Some(sample) = &self.sample {
+            out.push_str(&format!(
+                "\n  verified {} uploaded {} by downloading, {} mismatched",
+                sample.verified.len(),
+                if sample.verified.len() == 1 { "file" } else { "files" },
+                sample.mismatched.len()
+            ));
+            for folder in &sample.fully_verified {
+                out.push_str(&format!("\n  every file of folder {} verified", folder));
+            }
+        }
+        if let Some(jump) = self.clock_jump_secs {
+            out.push_str(&format!("\n  system clock jumped by {}s during the run", jump));
+        }
+        for change in &self.chunk_changes {
+            out.push_str(&format!(
+                "\n  {}: changed, {}% of chunks differ ({} of {})",
+                change.path,
+                change.percent(),
+                change.changed,
+                change.total
+            ));
+        }
+        for remote_path in &self.truncated {
+            out.push_str(&format!("\n  server stored truncated content: {}", remote_path));
+        }
+        for (remote_path, reason) in &self.failed {
+            out.push_str(&format!("\n  upload failed: {} ({})", remote_path, reason));
+        }
+        for remote_path in &self.conflicts {
+            out.push_str(&format!("\n  changed on the server during upload, kept: {}", remote_path));
+        }
+        for folder in &self.unmounted {
+            out.push_str(&format!("\n  not mounted, skipped: {}", folder));
+        }
+        for (folder, kind) in &self.folder_kinds {
+            match kind {
+                FolderKind::Directory => {}
+                FolderKind::File => out.push_str(&format!("\n  single file: {}", folder)),
+                FolderKind::Missing => out.push_str(&format!("\n  does not exist, skipped: {}", folder)),
+                FolderKind::Unsupported => {
+                    out.push_str(&format!("\n  not a directory or file, skipped: {}", folder))
+                }
+            }
+        }
+        for remote_path in &self.deleted {
+            out.push_str(&format!("\n  deleted: {}", remote_path));
+        }
+        for remote_path in &self.deletion_blocked {
+            out.push_str(&format!("\n  deletion blocked: {}", remote_path));
+        }
+        for remote_path in &self.planned {
+            out.push_str(&format!("\n  would upload: {}", remote_path));
+        }
+        for remote_path in &self.planned_deletions {
+            out.push_str(&format!("\n  would delete: {}", remote_path));
+        }
+        for remote_path in &self.collisions {
+            out.push_str(&format!("\n  collision: {}", remote_path));
+        }
+        for collision in &self.case_collisions {
+            out.push_str(&format!(
+                "\n  case collision: {} from {}, not synced: {}",
+                collision.key,
+                collision.winner.as_deref().unwrap_or("none"),
+                collision.losers.join(", ")
+            ));
+        }
+        for remote_path in &self.deduplicated {
+            out.push_str(&format!("\n  stored as an existing object: {}", remote_path));
+        }
+        for (remote_path, renamed) in &self.renamed {
+            out.push_str(&format!("\n  renamed: {} -> {}", escape(remote_path), renamed));
+        }
+        if self.folders.len() > 1 {
+            for (folder, stats) in &self.folders {
+                out.push_str(&format!(
+                    "\n  {}: {} uploaded ({} bytes), {} unchanged",
+                    folder, stats.uploaded, stats.bytes_uploaded, stats.skipped
+                ));
+            }
+        }
+        for (folder, count) in &self.filtered_out {
+            out.push_str(&format!("\n  {}: {} filtered out", folder, count));
+        }
+        for dir in &self.nomedia_skipped {
+            out.push_str(&format!("\n  skipped (.nomedia): {}", dir));
+        }
+        if let Some(compaction) = &self.compaction {
+            out.push_str(&format!(
+                "\n  hash store compacted: {} entries of missing files, {} superseded pseudo hashes removed",
+                compaction.missing_local, compaction.superseded_pseudo
+            ));
+        }
+        if let Some(peak) = self.peak_memory_bytes {
+            out.push_str(&format!("\n  peak resident memory: {}", format_byte_size(peak)));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_summary() {
+        let report = SyncReport { uploaded: 312, skipped: 4, ..Default::default() };
+        assert_eq!(format_summary(&report), "312 files uploaded, 4 unchanged");
+
+        let report = SyncReport { uploaded: 1, skipped: 0, ..Default::default() };
+        assert_eq!(format_summary(&report), "1 file uploaded, 0 unchanged");
+    }
+
+    #[test]
+    fn test_peak_memory_is_shown_when_measured() {
+        let report = SyncReport { uploaded: 1, ..Default::default() };
+        assert!(!report.human().contains("peak resident memory"));
+        let report = SyncReport { peak_memory_bytes: Some(48 * 1024 * 1024), ..report };
+        assert!(report.human().ends_with("\n  peak resident memory: 48MiB"), "{}", report.human());
+    }
+
+    #[test]
+    fn test_record_breakdowns() {
+        let mut report = SyncReport::default();
+        let outcomes = [
+            ("photos", "a/IMG_1.JPG", FileOutcome::Uploaded, 300),
+            ("photos", "a/IMG_2.jpg", FileOutcome::Uploaded, 200),
+            ("photos", "b/clip.mp4", FileOutcome::Skipped, 9000),
+            ("docs", "notes.txt", FileOutcome::Uploaded, 10),
+            ("docs", "Makefile", FileOutcome::Uploaded, 5),
+            ("docs", "archive.tar.gz", FileOutcome::Uploaded, 1000),
+        ];
+        for (folder, path, outcome, bytes) in outcomes {
+            report.record(folder, Path::new(path), outcome, bytes);
+        }
+
+        assert_eq!(report.uploaded, 5);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(report.bytes_uploaded, 1515);
+        assert_eq!(
+            report.folders["photos"],
+            TransferStats { uploaded: 2, skipped: 1, bytes_uploaded: 500 }
+        );
+        assert_eq!(
+            report.folders["docs"],
+            TransferStats { uploaded: 3, skipped: 0, bytes_uploaded: 1015 }
+        );
+        assert_eq!(report.extensions["jpg"].uploaded, 2);
+        assert_eq!(report.extensions[""].bytes_uploaded, 5);
+
+        let top: Vec<_> = report.top_extensions(2).into_iter().map(|(e, _)| e).collect();
+        assert_eq!(top, vec!["gz", "jpg"]);
+    }
+
+    #[test]
+    fn test_human_output_lists_folders() {
+        let mut report = SyncReport::default();
+        report.record("photos", Path::new("a.jpg"), FileOutcome::Uploaded, 3);
+        report.record("docs", Path::new("b.txt"), FileOutcome::Skipped, 1);
+        assert_eq!(
+            report.human(),
+            "1 file uploaded, 1 unchanged\n  docs: 0 uploaded (0 bytes), 1 unchanged\n  photos: 1 uploaded (3 bytes), 0 unchanged"
+        );
+    }
+
+    #[test]
+    fn test_human_output_counts_retries() {
+        let report = SyncReport { uploaded: 2, retries: 3, backoff_ms: 7500, ..Default::default() };
+        assert_eq!(report.human(), "2 files uploaded, 0 unchanged\n  3 retries, 7.5s spent backing off");
+    }
+}
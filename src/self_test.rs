@@ -0,0 +1,354 @@
+//! End-to-end check of a server and this build, for `self-test`.
+//!
+//! A small local tree (empty, small, nested and unicode-named files) is synced
+//! into a throwaway remote directory next to the configured `target_dir`. The
+//! uploaded content is downloaded and compared, files are changed and synced
+//! again, and the hash store is read back from the server. Deletion is checked
+//! only when `mirror_deletions` is enabled. The remote directory and the local
+//! tree are removed afterwards, also when a check failed.
+
+use crate::config::{Config, FolderConfig, RemoteHashStore};
+use crate::filter::FilterSet;
+use crate::hash_store::HashStore;
+use crate::hash_store_guard::{download_store, store_timeout, HashStoreGuard};
+use crate::output::HumanDisplay;
+use crate::report::SyncReport;
+use crate::sync::{remote_path_for, sync_with_guard};
+use crate::webdav_client::{PoolSettings, WebDavClient};
+use crate::work_dir::WorkDir;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::future::Future;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Files of the local test tree, relative to its root, with their sizes.
+const TREE: [(&str, usize); 6] = [
+    ("empty.txt", 0),
+    ("small.txt", 13),
+    ("Fotos/Größe ünïcødé.jpg", 4096),
+    ("nested/deeper/日本語.bin", 1000),
+    ("nested/medium.bin", 64 * 1024),
+    ("large.bin", 3 * 1024 * 1024 + 7),
+];
+
+/// Files rewritten before the second sync.
+const CHANGED: [&str; 2] = ["small.txt", "nested/medium.bin"];
+
+/// File deleted locally to check `mirror_deletions`.
+const DELETED: &str = "nested/deeper/日本語.bin";
+
+/// Result of one check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+/// One row of the self-test matrix.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    /// Why the check failed or was skipped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Outcome of a self-test run.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SelfTestReport {
+    /// The throwaway remote directory that was used.
+    pub remote_dir: String,
+    /// Server addresses allowed by the `network` settings.
+    #[serde(default)]
+    pub resolved_addresses: Vec<IpAddr>,
+    /// The one of them the checks connected to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connected_address: Option<IpAddr>,
+    pub checks: Vec<CheckResult>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.status != CheckStatus::Failed)
+    }
+
+    fn push(&mut self, name: &str, status: CheckStatus, detail: Option<String>) {
+        self.checks.push(CheckResult { name: name.to_string(), status, detail });
+    }
+
+    /// Run `check` unless an earlier check failed, since later checks build
+    /// on the remote state the earlier ones left behind.
+    async fn run(&mut self, name: &str, check: impl Future<Output = Result<(), Box<dyn Error>>>) {
+        if !self.passed() {
+            self.push(name, CheckStatus::Skipped, Some("an earlier check failed".to_string()));
+            return;
+        }
+        match check.await {
+            Ok(()) => self.push(name, CheckStatus::Passed, None),
+            Err(e) => self.push(name, CheckStatus::Failed, Some(e.to_string())),
+        }
+    }
+}
+
+impl HumanDisplay for SelfTestReport {
+    fn human(&self) -> String {
+        let mut out = format!("Self-test in '{}':", self.remote_dir);
+        let addresses: Vec<String> = self
+            .resolved_addresses
+            .iter()
+            .map(|ip| if Some(*ip) == self.connected_address { format!("{} (connected)", ip) } else { ip.to_string() })
+            .collect();
+        if !addresses.is_empty() {
+            out.push_str(&format!("\n  server addresses: {}", addresses.join(", ")));
+        }
+        for check in &self.checks {
+            let label = match check.status {
+                CheckStatus::Passed => "PASS",
+                CheckStatus::Failed => "FAIL",
+                CheckStatus::Skipped => "SKIP",
+            };
+            out.push_str(&format!("\n  {}  {}", label, check.name));
+            if let Some(detail) = &check.detail {
+                out.push_str(&format!(": {}", detail));
+            }
+        }
+        out.push_str(if self.passed() { "\nAll checks passed" } else { "\nSelf-test failed" });
+        out
+    }
+}
+
+/// A remote directory name that no earlier run used.
+pub fn throwaway_dir_name() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    format!("phone_sync-self-test-{}-{}", std::process::id(), nanos)
+}
+
+/// Run the self-test against the server of `config` in the remote directory
+/// `dir_name` below its `target_dir`, which must not exist yet.
+pub async fn self_test(config: &Config, dir_name: &str) -> Result<SelfTestReport, Box<dyn Error>> {
+    if config.read_only {
+        return Err("The self-test writes to the server and cannot run in read-only mode".into());
+    }
+    let remote_dir = remote_path_for(config, dir_name);
+    // No journal: nothing of the throwaway directory should be replayed later.
+    let client = WebDavClient::with_pool(
+        &config.webdav_url,
+        config.username.as_deref(),
+        config.password.as_deref(),
+        config.timeout,
+        PoolSettings::from_config(config),
+    )?
+    .with_auth(config.auth()?);
+    let resolved_addresses = config.network.server_addresses(&config.webdav_url).await?;
+    let connected_address = client.connected_address().await?.map(|addr| addr.ip());
+    if client.stat(&remote_dir).await?.is_some() {
+        return Err(format!("Remote '{}' already exists, refusing to run the self-test in it", remote_dir).into());
+    }
+
+    let work_dir = WorkDir::create(config.temp_dir.as_deref().map(Path::new))?;
+    let tree = work_dir.file("tree");
+    write_tree(&tree)?;
+    let test = SelfTest {
+        config: test_config(config, &work_dir, &tree, &remote_dir),
+        client,
+        tree,
+        downloads: work_dir.file("downloads"),
+    };
+    std::fs::create_dir(&test.downloads)?;
+
+    let mut report = SelfTestReport { remote_dir: remote_dir.clone(), resolved_addresses, connected_address, checks: Vec::new() };
+    report.run("initial upload", test.initial_upload()).await;
+    report.run("content round trip", test.compare_remote(TREE.map(|(path, _)| path).to_vec())).await;
+    report.run("skip unchanged", test.skip_unchanged()).await;
+    report.run("overwrite changed", test.overwrite_changed()).await;
+    report.run("hash store round trip", test.hash_store_round_trip()).await;
+    if config.mirror_deletions {
+        report.run("mirror deletions", test.mirror_deletion()).await;
+    } else {
+        report.push("mirror deletions", CheckStatus::Skipped, Some("mirror_deletions is not enabled".to_string()));
+    }
+    // Always clean up, whatever failed before.
+    match test.clean_up(&remote_dir).await {
+        Ok(()) => report.push("cleanup", CheckStatus::Passed, None),
+        Err(e) => report.push("cleanup", CheckStatus::Failed, Some(e.to_string())),
+    }
+    Ok(report)
+}
+
+/// The user's config, pointed at the test tree and the throwaway directory.
+fn test_config(config: &Config, work_dir: &WorkDir, tree: &Path, remote_dir: &str) -> Config {
+    Config {
+        folders: vec![FolderConfig::new(tree.display().to_string())],
+        hash_store_path: work_dir.file("hashes.yaml").display().to_string(),
+        target_dir: remote_dir.to_string(),
+        remote_hash_path: format!("{}/hashes.yaml", remote_dir),
+        remote_hash_store: RemoteHashStore::Enabled,
+        local_archive_dir: Some(work_dir.file("archive").display().to_string()),
+        archive_retention: None,
+        first_run_threshold: 0,
+        journal_path: None,
+        trust_mtime: false,
+        transfer_budget: None,
+        ..config.clone()
+    }
+}
+
+/// Deterministic, non-repeating content, so that mixed-up files are noticed.
+fn content(path: &str, size: usize, round: u8) -> Vec<u8> {
+    let mut state = path.bytes().fold(u32::from(round) + 1, |h, b| h.wrapping_mul(31).wrapping_add(b.into()));
+    (0..size)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        })
+        .collect()
+}
+
+fn write_tree(root: &Path) -> Result<(), Box<dyn Error>> {
+    for (path, size) in TREE {
+        let local = root.join(path);
+        std::fs::create_dir_all(local.parent().expect("tree files have a parent"))?;
+        std::fs::write(local, content(path, size, 0))?;
+    }
+    Ok(())
+}
+
+struct SelfTest {
+    config: Config,
+    client: WebDavClient,
+    tree: PathBuf,
+    downloads: PathBuf,
+}
+
+impl SelfTest {
+    /// One sync run with its own hash store guard, like the `sync` command.
+    async fn sync(&self) -> Result<SyncReport, Box<dyn Error>> {
+        let mut guard = HashStoreGuard::new(self.client.clone(), &self.config).await?;
+        let result =
+            sync_with_guard(&self.config, &self.client, &mut guard, false, false, &FilterSet::default()).await;
+        // Finalize even after a failed sync, so no store upload races the cleanup.
+        guard.finalize().await?;
+        result
+    }
+
+    async fn initial_upload(&self) -> Result<(), Box<dyn Error>> {
+        let report = self.sync().await?;
+        expect_counts(&report, TREE.len(), 0)
+    }
+
+    /// Download `paths` and compare them with the local files byte by byte.
+    async fn compare_remote(&self, paths: Vec<&str>) -> Result<(), Box<dyn Error>> {
+        for path in paths {
+            let remote = remote_path_for(&self.config, path);
+            let downloaded = self.downloads.join("file");
+            if self.client.download_file(&remote, &downloaded).await?.is_none() {
+                return Err(format!("'{}' is missing on the server", remote).into());
+            }
+            if std::fs::read(&downloaded)? != std::fs::read(self.tree.join(path))? {
+                return Err(format!("'{}' differs from the uploaded file", remote).into());
+            }
+        }
+        Ok(())
+    }
+
+    async fn skip_unchanged(&self) -> Result<(), Box<dyn Error>> {
+        let report = self.sync().await?;
+        expect_counts(&report, 0, TREE.len())
+    }
+
+    async fn overwrite_changed(&self) -> Result<(), Box<dyn Error>> {
+        for path in CHANGED {
+            let size = TREE.iter().find(|(p, _)| *p == path).map_or(0, |(_, size)| *size);
+            std::fs::write(self.tree.join(path), content(path, size + 1, 1))?;
+        }
+        let report = self.sync().await?;
+        expect_counts(&report, CHANGED.len(), TREE.len() - CHANGED.len())?;
+        self.compare_remote(CHANGED.to_vec()).await
+    }
+
+    /// The store on the server must match the local one and the local files.
+    async fn hash_store_round_trip(&self) -> Result<(), Box<dyn Error>> {
+        let downloaded = self.downloads.join("hashes.yaml");
+        let (remote_path, timeout) = (&self.config.remote_hash_path, store_timeout(&self.config));
+        if !download_store(&self.client, remote_path, self.config.compress_hash_store, timeout, &downloaded).await? {
+            return Err(format!("hash store '{}' is missing on the server", self.config.remote_hash_path).into());
+        }
+        let remote = HashStore::load(&downloaded)?;
+        let local = HashStore::load(&self.config.hash_store_path)?;
+        if remote.regular_hashes != local.regular_hashes {
+            return Err("hash store on the server differs from the local one".into());
+        }
+        for (path, _) in TREE {
+            let expected = HashStore::compute(self.tree.join(path), false).await?;
+            let key = remote_path_for(&self.config, path);
+            if remote.regular_hashes.get(&key) != Some(&expected) {
+                return Err(format!("hash store entry of '{}' does not match the file", key).into());
+            }
+        }
+        Ok(())
+    }
+
+    async fn mirror_deletion(&self) -> Result<(), Box<dyn Error>> {
+        std::fs::remove_file(self.tree.join(DELETED))?;
+        let report = self.sync().await?;
+        let remote = remote_path_for(&self.config, DELETED);
+        if report.deleted != vec![remote.clone()] {
+            return Err(format!("expected only '{}' to be deleted, got {:?}", remote, report.deleted).into());
+        }
+        if self.client.stat(&remote).await?.is_some() {
+            return Err(format!("'{}' still exists on the server", remote).into());
+        }
+        Ok(())
+    }
+
+    async fn clean_up(&self, remote_dir: &str) -> Result<(), Box<dyn Error>> {
+        self.client.delete_file(remote_dir).await?;
+        if self.client.stat(remote_dir).await?.is_some() {
+            return Err(format!("'{}' still exists on the server", remote_dir).into());
+        }
+        Ok(())
+    }
+}
+
+fn expect_counts(report: &SyncReport, uploaded: usize, skipped: usize) -> Result<(), Box<dyn Error>> {
+    if report.uploaded != uploaded || report.skipped != skipped {
+        return Err(format!(
+            "expected {} uploaded and {} skipped, got {} and {}",
+            uploaded, skipped, report.uploaded, report.skipped
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_human_matrix() {
+        let mut report = SelfTestReport { remote_dir: "t".to_string(), ..SelfTestReport::default() };
+        report.push("initial upload", CheckStatus::Passed, None);
+        report.push("mirror deletions", CheckStatus::Skipped, Some("off".to_string()));
+        assert!(report.passed());
+        report.push("cleanup", CheckStatus::Failed, Some("HTTP 500".to_string()));
+        assert!(!report.passed());
+        assert_eq!(
+            report.human(),
+            "Self-test in 't':\n  PASS  initial upload\n  SKIP  mirror deletions: off\n  \
+             FAIL  cleanup: HTTP 500\nSelf-test failed"
+        );
+    }
+
+    #[test]
+    fn test_content_differs_per_file_and_round() {
+        assert_eq!(content("a", 64, 0), content("a", 64, 0));
+        assert_ne!(content("a", 64, 0), content("b", 64, 0));
+        assert_ne!(content("a", 64, 0), content("a", 64, 1));
+    }
+}
@@ -0,0 +1,130 @@
+//! Sorted on-disk index for lookups that would not fit in memory.
+//!
+//! In low-memory mode, data a run would otherwise keep in a `HashMap` for
+//! the whole run (e.g. remote directory listings) is written to a file of
+//! JSON lines sorted by key instead. A lookup binary-searches the file by
+//! byte offset, so only the line being compared is held in memory.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+/// A file of `(key, value)` JSON lines sorted by key.
+#[derive(Debug)]
+pub struct SpillIndex {
+    path: PathBuf,
+    len: u64,
+    entries: usize,
+}
+
+impl SpillIndex {
+    /// Write `entries` to `path`, sorted by key. Of entries sharing a key,
+    /// a lookup finds any one.
+    pub fn write<V: Serialize>(path: PathBuf, mut entries: Vec<(String, V)>) -> io::Result<Self> {
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut out = BufWriter::new(File::create(&path)?);
+        for entry in &entries {
+            serde_json::to_writer(&mut out, entry)?;
+            out.write_all(b"\n")?;
+        }
+        out.flush()?;
+        let len = out.get_ref().metadata()?.len();
+        Ok(SpillIndex { path, len, entries: entries.len() })
+    }
+
+    /// Number of entries written.
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    /// All keys, in order.
+    pub fn keys(&self) -> io::Result<Vec<String>> {
+        let mut keys = Vec::with_capacity(self.entries);
+        for line in BufReader::new(File::open(&self.path)?).split(b'\n') {
+            let (key, _): (String, serde::de::IgnoredAny) = serde_json::from_slice(&line?)?;
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+
+    /// The value stored under `key`, if any.
+    pub fn get<V: DeserializeOwned>(&self, key: &str) -> io::Result<Option<V>> {
+        let mut file = BufReader::new(File::open(&self.path)?);
+        let mut line = Vec::new();
+        // The line of `key`, if there is one, starts in `lo..hi`; `lo` is
+        // always the start of a line.
+        let (mut lo, mut hi) = (0, self.len);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            // Start of the first line at or after `mid`.
+            let start = if mid == lo {
+                file.seek(SeekFrom::Start(lo))?;
+                lo
+            } else {
+                file.seek(SeekFrom::Start(mid - 1))?;
+                line.clear();
+                mid - 1 + file.read_until(b'\n', &mut line)? as u64
+            };
+            if start >= hi {
+                hi = mid;
+                continue;
+            }
+            line.clear();
+            let read = file.read_until(b'\n', &mut line)? as u64;
+            let (found, value): (String, V) = serde_json::from_slice(&line)?;
+            match found.as_str().cmp(key) {
+                std::cmp::Ordering::Less => lo = start + read,
+                std::cmp::Ordering::Equal => return Ok(Some(value)),
+                std::cmp::Ordering::Greater => hi = start,
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_lookups_match_a_map() {
+        let dir = tempfile::tempdir().unwrap();
+        // Lengths vary, and names with a newline or quotes must stay one line.
+        let entries: Vec<(String, Option<u64>)> = (0..2000u64)
+            .map(|i| (format!("DCIM/{}{}\n\"{}\".jpg", "x".repeat((i % 17) as usize), i * 7 % 2003, i), (i % 5 != 0).then_some(i)))
+            .collect();
+        let map: HashMap<String, Option<u64>> = entries.iter().cloned().collect();
+        let index = SpillIndex::write(dir.path().join("index"), entries).unwrap();
+
+        assert_eq!(index.len(), 2000);
+        let mut keys: Vec<&String> = map.keys().collect();
+        keys.sort();
+        assert_eq!(index.keys().unwrap().iter().collect::<Vec<_>>(), keys);
+        for (key, value) in &map {
+            assert_eq!(index.get::<Option<u64>>(key).unwrap().as_ref(), Some(value), "{:?}", key);
+        }
+        for missing in ["", "A", "DCIM/", "DCIM/x", "zzz"] {
+            assert_eq!(index.get::<Option<u64>>(missing).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_empty_and_single_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let empty = SpillIndex::write::<u8>(dir.path().join("empty"), Vec::new()).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.get::<u8>("a").unwrap(), None);
+
+        let one = SpillIndex::write(dir.path().join("one"), vec![("b".to_string(), 1u8)]).unwrap();
+        assert_eq!(one.get::<u8>("b").unwrap(), Some(1));
+        assert_eq!(one.get::<u8>("a").unwrap(), None);
+        assert_eq!(one.get::<u8>("c").unwrap(), None);
+    }
+}
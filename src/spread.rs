@@ -0,0 +1,117 @@
+//! Spreading the load of many machines that sync to the same server.
+//!
+//! `start_jitter` delays the start of a run by a random fraction of the
+//! configured duration, and `upload_order: shuffle` uploads the files of each
+//! folder in a random order, so machines started at the same time do not hit
+//! the same files at the same moment. Randomness comes from a seeded
+//! [`Rng`], so a shuffled run can be reproduced from the logged seed.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Source of random numbers; tests inject a fixed sequence.
+pub trait Rng {
+    fn next_u64(&mut self) -> u64;
+
+    /// A number in `0..bound`, or 0 for an empty range.
+    fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            return 0;
+        }
+        // Rejection sampling avoids the bias of a plain modulo.
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next_u64();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+}
+
+/// SplitMix64, small and good enough for spreading load.
+#[derive(Debug, Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+}
+
+impl Rng for SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// A seed that differs between runs and machines.
+pub fn fresh_seed() -> u64 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    SplitMix64::new(nanos as u64 ^ u64::from(std::process::id()).rotate_left(32)).next_u64()
+}
+
+/// Shuffle `items` in place (Fisher-Yates).
+pub fn shuffle<T>(items: &mut [T], rng: &mut dyn Rng) {
+    for i in (1..items.len()).rev() {
+        let j = rng.below(i as u64 + 1) as usize;
+        items.swap(i, j);
+    }
+}
+
+/// A random delay in `0..=max`, at millisecond resolution.
+pub fn jitter(max: Duration, rng: &mut dyn Rng) -> Duration {
+    let max_millis = u64::try_from(max.as_millis()).unwrap_or(u64::MAX);
+    Duration::from_millis(rng.below(max_millis.saturating_add(1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replays a fixed sequence, wrapping around.
+    struct Fixed(Vec<u64>, usize);
+
+    impl Rng for Fixed {
+        fn next_u64(&mut self) -> u64 {
+            self.1 += 1;
+            self.0[(self.1 - 1) % self.0.len()]
+        }
+    }
+
+    #[test]
+    fn test_shuffle_respects_seed() {
+        let items: Vec<u32> = (0..50).collect();
+        let shuffled = |seed| {
+            let mut copy = items.clone();
+            shuffle(&mut copy, &mut SplitMix64::new(seed));
+            copy
+        };
+        assert_eq!(shuffled(7), shuffled(7));
+        assert_ne!(shuffled(7), shuffled(8));
+        assert_ne!(shuffled(7), items);
+
+        let mut sorted = shuffled(7);
+        sorted.sort();
+        assert_eq!(sorted, items);
+    }
+
+    #[test]
+    fn test_jitter_stays_within_bounds() {
+        let max = Duration::from_secs(90);
+        let mut rng = SplitMix64::new(1);
+        assert!((0..1000).all(|_| jitter(max, &mut rng) <= max));
+        assert_eq!(jitter(Duration::ZERO, &mut rng), Duration::ZERO);
+
+        // The extremes of the random range map to the extremes of the delay.
+        assert_eq!(jitter(max, &mut Fixed(vec![0], 0)), Duration::ZERO);
+        assert_eq!(jitter(max, &mut Fixed(vec![90_000], 0)), max);
+        // Values in the biased tail are redrawn.
+        assert_eq!(jitter(max, &mut Fixed(vec![u64::MAX, 5], 0)), Duration::from_millis(5));
+    }
+}
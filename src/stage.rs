@@ -0,0 +1,184 @@
+//! Offline staging of pending uploads and their later replay to the remote.
+//!
+//! `stage` runs change detection against the local hash store only and copies
+//! (or hard-links) every file that needs uploading into a staging directory
+//! laid out by remote path, together with a manifest. `replay` uploads exactly
+//! the staged content and records it in the real hash store.
+
+use crate::artifacts::SyncArtifacts;
+use crate::cas::require_mirror;
+use crate::config::Config;
+use crate::external_hasher::FileHasher;
+use crate::filter::FilterSet;
+use crate::folder_kind::{self, FolderKind};
+use crate::hash_store::HashStore;
+use crate::hash_store_guard::HashStoreGuard;
+use crate::local_path::resolve_local_destination;
+use crate::nomedia::NomediaFilter;
+use crate::sync::{folder_files, remote_path_in};
+use crate::webdav_client::WebDavClient;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use tokio::fs as async_fs;
+
+/// File name of the manifest inside the staging directory.
+const MANIFEST_FILE: &str = "manifest.yaml";
+/// Subdirectory of the staging directory holding the staged files.
+const FILES_DIR: &str = "files";
+
+/// A staged file waiting to be uploaded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StagedEntry {
+    pub remote_path: String,
+    pub hash: String,
+    pub size: u64,
+    /// Set once the entry was uploaded by `replay`.
+    #[serde(default)]
+    pub uploaded: bool,
+}
+
+/// Description of a staging directory.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageManifest {
+    /// Whether `hash` values are pseudo hashes.
+    pub pseudo: bool,
+    pub entries: Vec<StagedEntry>,
+}
+
+impl StageManifest {
+    pub fn load(staging_dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let path = staging_dir.join(MANIFEST_FILE);
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read manifest '{}': {}", path.display(), e))?;
+        Ok(serde_yaml::from_str(&content)?)
+    }
+
+    pub fn save(&self, staging_dir: &Path) -> Result<(), Box<dyn Error>> {
+        std::fs::write(staging_dir.join(MANIFEST_FILE), serde_yaml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// Result of a replay.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub uploaded: usize,
+    /// Entries uploaded by an earlier, interrupted replay.
+    pub already_uploaded: usize,
+    /// Remote paths whose staged content no longer matches the manifest.
+    pub drifted: Vec<String>,
+}
+
+/// Stage every file selected by `filters` that differs from the local hash store.
+///
+/// No network access happens; with `link` the files are hard-linked instead
+/// of copied. Returns the manifest that was written.
+pub async fn stage(
+    config: &Config,
+    staging_dir: &Path,
+    link: bool,
+    use_pseudo_hash: bool,
+    filters: &FilterSet,
+) -> Result<StageManifest, Box<dyn Error>> {
+    require_mirror(config, "stage")?;
+    let store = HashStore::load(&config.hash_store_path)?;
+    let artifacts = SyncArtifacts::from_config(config);
+    let hasher = FileHasher::from_config(config);
+    let nomedia = NomediaFilter::from_config(config);
+    let mut manifest = StageManifest { pseudo: use_pseudo_hash, entries: Vec::new() };
+
+    for folder_config in &config.folders {
+        let folder = &folder_config.path;
+        let folder_path = Path::new(folder);
+        if !FolderKind::of(folder_path).is_syncable() {
+            warn!("Folder {} does not exist or is not a directory or file, skipping", folder);
+            continue;
+        }
+
+        for entry in folder_files(folder_path, config.low_memory, &nomedia) {
+            if artifacts.contains(entry.file_name()) {
+                continue;
+            }
+            let local_path = entry.path();
+            let relative_path = folder_kind::relative_path(folder_path, local_path)
+                .ok_or_else(|| format!("{} is not in folder {}", local_path.display(), folder))?
+                .to_string_lossy();
+            if !folder_config.admits(local_path) || !filters.matches(&relative_path, &entry.metadata()?) {
+                continue;
+            }
+            let remote_path = remote_path_in(config, folder_config, &relative_path);
+            let hash = hasher.compute(local_path, use_pseudo_hash).await?;
+            if store.hashes(use_pseudo_hash).get(&remote_path) == Some(&hash) {
+                continue;
+            }
+
+            let staged = staged_path(staging_dir, &remote_path);
+            if let Some(parent) = staged.parent() {
+                async_fs::create_dir_all(parent).await?;
+            }
+            let _ = async_fs::remove_file(&staged).await;
+            if link {
+                async_fs::hard_link(local_path, &staged).await?;
+            } else {
+                async_fs::copy(local_path, &staged).await?;
+            }
+            manifest.entries.push(StagedEntry {
+                remote_path,
+                hash,
+                size: entry.metadata()?.len(),
+                uploaded: false,
+            });
+        }
+    }
+
+    async_fs::create_dir_all(staging_dir).await?;
+    manifest.save(staging_dir)?;
+    Ok(manifest)
+}
+
+/// Upload the staged files and record them in the hash store.
+///
+/// Entries whose staged content no longer hashes to the manifest value are
+/// reported as drifted and skipped. Progress is written to the manifest after
+/// every upload, so an interrupted replay resumes where it stopped.
+pub async fn replay(config: &Config, staging_dir: &Path) -> Result<ReplayReport, Box<dyn Error>> {
+    require_mirror(config, "replay")?;
+    let mut manifest = StageManifest::load(staging_dir)?;
+    let client = WebDavClient::from_config(config)?;
+    let mut guard = HashStoreGuard::new(client.clone(), config).await?;
+    let mut report = ReplayReport::default();
+
+    for i in 0..manifest.entries.len() {
+        let entry = manifest.entries[i].clone();
+        if entry.uploaded {
+            report.already_uploaded += 1;
+            continue;
+        }
+        // The manifest is plain YAML on disk, so treat its paths as untrusted.
+        let staged = resolve_local_destination(&staging_dir.join(FILES_DIR), &entry.remote_path)?;
+        let hash = HashStore::compute(&staged, manifest.pseudo).await?;
+        if hash != entry.hash {
+            warn!("Staged file {} changed since staging, skipping", entry.remote_path);
+            report.drifted.push(entry.remote_path);
+            continue;
+        }
+
+        client.upload_file(&staged, &entry.remote_path).await?;
+        guard
+            .hash_store_mut()
+            .hashes_mut(manifest.pseudo)
+            .insert(entry.remote_path.clone(), hash);
+        manifest.entries[i].uploaded = true;
+        manifest.save(staging_dir)?;
+        report.uploaded += 1;
+    }
+
+    guard.finalize().await?;
+    Ok(report)
+}
+
+fn staged_path(staging_dir: &Path, remote_path: &str) -> PathBuf {
+    staging_dir.join(FILES_DIR).join(remote_path)
+}
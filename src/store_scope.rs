@@ -0,0 +1,256 @@
+//! Loading only this device's part of a shared hash store.
+//!
+//! Devices syncing to different `target_dir`s share one remote store, whose
+//! keys all start with the `target_dir` of the device that wrote them. A store
+//! loaded with a scope materializes only the entries under it; the others
+//! are kept as the YAML text of their values, which takes less memory than
+//! the parsed entries. Sync never sees them, and saving the store writes them
+//! back unchanged at their place in key order.
+
+use serde::de::{self, DeserializeOwned, MapAccess, Visitor};
+use serde::ser::{self, SerializeMap};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_yaml::Value;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::iter::Peekable;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+thread_local! {
+    /// Scope of the store being deserialized on this thread, if any.
+    static SCOPE: RefCell<Option<Scope>> = const { RefCell::new(None) };
+}
+
+/// Run `f`, which deserializes a store, keeping entries outside `scope` raw.
+pub(crate) fn with_scope<T>(scope: Option<&Scope>, f: impl FnOnce() -> T) -> T {
+    let previous = SCOPE.with(|s| s.replace(scope.cloned()));
+    let result = f();
+    SCOPE.with(|s| *s.borrow_mut() = previous);
+    result
+}
+
+/// The `target_dir`s whose entries a device materializes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scope {
+    dirs: Vec<String>,
+}
+
+impl Scope {
+    /// The scope of a device syncing to `dirs`, or `None` if one of them is
+    /// the root of the server, so that every key is in it.
+    pub fn new<'a>(dirs: impl IntoIterator<Item = &'a str>) -> Option<Scope> {
+        let dirs: Vec<String> = dirs.into_iter().map(|dir| dir.trim_matches('/').to_string()).collect();
+        (!dirs.iter().any(String::is_empty)).then_some(Scope { dirs })
+    }
+
+    /// Whether `key` is one of the directories or lies below one.
+    pub fn contains(&self, key: &str) -> bool {
+        let key = key.trim_start_matches('/');
+        self.dirs
+            .iter()
+            .any(|dir| key.strip_prefix(dir.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with('/')))
+    }
+}
+
+/// Store entries keyed by remote path. Dereferences to the entries in scope;
+/// those outside it are only kept for saving.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathMap<V> {
+    entries: BTreeMap<String, V>,
+    /// Keys outside the scope with the YAML text of their values, sorted.
+    foreign: Vec<(Box<str>, Box<str>)>,
+}
+
+impl<V> PathMap<V> {
+    /// Number of entries outside the scope.
+    pub fn foreign_len(&self) -> usize {
+        self.foreign.len()
+    }
+
+    /// Entries outside the scope, with their values as YAML text.
+    pub fn foreign(&self) -> &[(Box<str>, Box<str>)] {
+        &self.foreign
+    }
+
+    /// The entries in scope, dropping those outside it.
+    pub fn into_inner(self) -> BTreeMap<String, V> {
+        self.entries
+    }
+
+    /// Whether there is nothing to save, in scope or outside it.
+    pub fn holds_nothing(&self) -> bool {
+        self.entries.is_empty() && self.foreign.is_empty()
+    }
+}
+
+impl<V> Default for PathMap<V> {
+    fn default() -> Self {
+        PathMap { entries: BTreeMap::new(), foreign: Vec::new() }
+    }
+}
+
+impl<V> Deref for PathMap<V> {
+    type Target = BTreeMap<String, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+impl<V> DerefMut for PathMap<V> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.entries
+    }
+}
+
+impl<V> From<BTreeMap<String, V>> for PathMap<V> {
+    fn from(entries: BTreeMap<String, V>) -> Self {
+        PathMap { entries, foreign: Vec::new() }
+    }
+}
+
+impl<V> FromIterator<(String, V)> for PathMap<V> {
+    fn from_iter<I: IntoIterator<Item = (String, V)>>(iter: I) -> Self {
+        BTreeMap::from_iter(iter).into()
+    }
+}
+
+impl<'a, V> IntoIterator for &'a PathMap<V> {
+    type Item = (&'a String, &'a V);
+    type IntoIter = std::collections::btree_map::Iter<'a, String, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+impl<V: PartialEq> PartialEq<BTreeMap<String, V>> for PathMap<V> {
+    fn eq(&self, other: &BTreeMap<String, V>) -> bool {
+        self.foreign.is_empty() && self.entries == *other
+    }
+}
+
+/// One entry to save, from either part of a [`PathMap`].
+enum Saved<'a, V> {
+    Own(&'a V),
+    Foreign(&'a str),
+}
+
+impl<V: Serialize> Serialize for Saved<'_, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Saved::Own(value) => value.serialize(serializer),
+            Saved::Foreign(text) => serde_yaml::from_str::<Value>(text)
+                .map_err(ser::Error::custom)?
+                .serialize(serializer),
+        }
+    }
+}
+
+/// Both parts of a [`PathMap`] in key order; an entry in scope hides a
+/// foreign one of the same key.
+struct Merged<'a, V> {
+    own: Peekable<std::collections::btree_map::Iter<'a, String, V>>,
+    foreign: Peekable<std::slice::Iter<'a, (Box<str>, Box<str>)>>,
+}
+
+impl<'a, V> Iterator for Merged<'a, V> {
+    type Item = (&'a str, Saved<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let order = match (self.own.peek(), self.foreign.peek()) {
+            (Some((own, _)), Some((foreign, _))) => own.as_str().cmp(foreign),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return None,
+        };
+        if order == Ordering::Equal {
+            self.foreign.next();
+        }
+        if order == Ordering::Greater {
+            self.foreign.next().map(|(key, text)| (&**key, Saved::Foreign(text)))
+        } else {
+            self.own.next().map(|(key, value)| (key.as_str(), Saved::Own(value)))
+        }
+    }
+}
+
+impl<V: Serialize> Serialize for PathMap<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let hidden = self.foreign.iter().filter(|(key, _)| self.entries.contains_key(&**key)).count();
+        let mut map = serializer.serialize_map(Some(self.entries.len() + self.foreign.len() - hidden))?;
+        let merged = Merged { own: self.entries.iter().peekable(), foreign: self.foreign.iter().peekable() };
+        for (key, value) in merged {
+            map.serialize_entry(key, &value)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de, V: DeserializeOwned> Deserialize<'de> for PathMap<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PathMapVisitor<V>(PhantomData<V>);
+
+        impl<'de, V: DeserializeOwned> Visitor<'de> for PathMapVisitor<V> {
+            type Value = PathMap<V>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a map keyed by path")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+                let scope = SCOPE.with(|s| s.borrow().clone());
+                let mut entries = BTreeMap::new();
+                let mut foreign = BTreeMap::new();
+                while let Some(key) = access.next_key::<String>()? {
+                    match &scope {
+                        Some(scope) if !scope.contains(&key) => {
+                            let value: Value = access.next_value()?;
+                            let text = serde_yaml::to_string(&value).map_err(de::Error::custom)?;
+                            foreign.insert(Box::from(key), text.into_boxed_str());
+                        }
+                        _ => {
+                            entries.insert(key, access.next_value()?);
+                        }
+                    }
+                }
+                Ok(PathMap { entries, foreign: foreign.into_iter().collect() })
+            }
+        }
+
+        deserializer.deserialize_map(PathMapVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_contains() {
+        let scope = Scope::new(["Phone/", "/backups/old"]).unwrap();
+        assert!(scope.contains("Phone/a.jpg"));
+        assert!(scope.contains("Phone"));
+        assert!(scope.contains("/backups/old/a.jpg"));
+        assert!(scope.contains("backups/old/a.jpg"));
+        assert!(!scope.contains("Phone2/a.jpg"));
+        assert!(!scope.contains("Tablet/a.jpg"));
+        assert!(!scope.contains("backups/a.jpg"));
+        assert_eq!(Scope::new(["Phone", "/"]), None);
+    }
+
+    #[test]
+    fn test_foreign_entries_are_saved_in_key_order() {
+        let yaml = "a/1: x\nb/1: y\nb/2: z\nc/1: w\n";
+        let map: PathMap<String> = with_scope(Scope::new(["b"]).as_ref(), || serde_yaml::from_str(yaml)).unwrap();
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec!["b/1", "b/2"]);
+        assert_eq!(map.foreign_len(), 2);
+        assert_eq!(serde_yaml::to_string(&map).unwrap(), yaml);
+
+        let unscoped: PathMap<String> = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(unscoped.len(), 4);
+    }
+}
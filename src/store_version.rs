@@ -0,0 +1,83 @@
+//! Client version requirements of the shared hash store.
+//!
+//! serde ignores store sections a client does not know, so an older binary
+//! rewriting the remote store would silently drop them. Clients therefore
+//! record in `min_client_version` the oldest version that understands every
+//! section in use, and a client older than that leaves the remote store
+//! alone (see [`HashStoreGuard`](crate::hash_store_guard::HashStoreGuard)).
+
+use crate::hash_store::HashStore;
+use semver::Version;
+
+/// Whether a store section is in use.
+type InUse = fn(&HashStore) -> bool;
+
+/// Store sections that older clients would drop, with the version that
+/// introduced them.
+const SECTIONS: [(InUse, (u64, u64, u64)); 6] = [
+    (|store| !store.chunks.is_empty(), (0, 1, 0)),
+    (|store| !store.case_winners.is_empty(), (0, 1, 0)),
+    (|store| !store.merged_deltas.is_empty(), (0, 1, 0)),
+    (|store| !store.server_names.is_empty(), (0, 1, 0)),
+    (|store| !store.full_verify_folders.is_empty(), (0, 1, 0)),
+    (|store| !store.origins.is_empty(), (0, 1, 0)),
+];
+
+/// Version of this binary.
+pub fn client_version() -> Version {
+    Version::parse(env!("CARGO_PKG_VERSION")).expect("crate version is valid semver")
+}
+
+/// Oldest client that understands every section of `store` in use and
+/// meets the requirement already recorded in it.
+pub fn required_version(store: &HashStore) -> Option<Version> {
+    SECTIONS
+        .iter()
+        .filter(|(in_use, _)| in_use(store))
+        .map(|(_, (major, minor, patch))| Version::new(*major, *minor, *patch))
+        .chain(store.min_client_version.clone())
+        .max()
+}
+
+/// Record the requirement of `store` before it is written. It never drops
+/// below what another client recorded.
+pub fn stamp(store: &mut HashStore) {
+    store.min_client_version = required_version(store);
+}
+
+/// The recorded requirement of `store` if `client` does not meet it.
+pub fn unmet_requirement(store: &HashStore, client: &Version) -> Option<Version> {
+    store.min_client_version.clone().filter(|required| required > client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required_version_follows_sections_and_record() {
+        let mut store = HashStore::default();
+        assert_eq!(required_version(&store), None);
+        store.chunks.insert("a".into(), vec!["c1".into()]);
+        assert_eq!(required_version(&store), Some(Version::new(0, 1, 0)));
+        store.min_client_version = Some(Version::parse("1.4.0-beta.1").unwrap());
+        stamp(&mut store);
+        assert_eq!(store.min_client_version, Some(Version::parse("1.4.0-beta.1").unwrap()));
+    }
+
+    #[test]
+    fn test_unmet_requirement_uses_semver_order() {
+        let mut store = HashStore::default();
+        let client = Version::new(1, 4, 0);
+        assert_eq!(unmet_requirement(&store, &client), None);
+        for (required, unmet) in [("1.3.9", false), ("1.4.0", false), ("1.4.0-rc.1", false), ("1.4.1", true), ("1.10.0", true)] {
+            store.min_client_version = Some(Version::parse(required).unwrap());
+            assert_eq!(unmet_requirement(&store, &client).is_some(), unmet, "{}", required);
+        }
+    }
+
+    #[test]
+    fn test_client_version_is_the_crate_version() {
+        assert_eq!(client_version().to_string(), env!("CARGO_PKG_VERSION"));
+    }
+}
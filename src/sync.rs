@@ -1,13 +1,48 @@
-use crate::config::Config;
-use crate::hash_store::HashStore;
-use crate::webdav_client::WebDavClient;
+use crate::archive::LocalArchive;
+use crate::artifacts::SyncArtifacts;
+use crate::batch::{upload_batch, BatchFile, BatchMode, BatchOutcome, BatchResult};
+use crate::budget::BudgetTracker;
+use crate::cas::sync_cas;
+use crate::case_collision::{plan_case_collisions, CasePlan, CaseRole};
+use crate::cdc::{chunk_file, ChunkChange};
+use crate::checksum::Checksum;
+use crate::clock::{Clock, RunClock, SystemClock};
+use crate::compact::{compact, StoreGrowth};
+use crate::config::{folder_key, CollisionPolicy, Config, FolderConfig, Layout, UploadOrder};
+use crate::external_hasher::FileHasher;
+use crate::file_stamp::{FileStamp, GranularityProbe, StampMatch};
+use crate::filter::{is_below, FilterSet};
+use crate::fingerprint::RemoteFingerprint;
+use crate::folder_kind::{self, FolderKind};
+use crate::hash_delta;
+use crate::hash_store::{HashStore, KeyNormalization};
+use crate::webdav_client::{RetryEvent, RetryPolicy, Upload, WebDavClient};
 use crate::hash_store_guard::HashStoreGuard;
-use indicatif::{ProgressBar, ProgressStyle};
-use log::warn;
-use std::path::Path;
-use walkdir::WalkDir;
+use crate::memory;
+use crate::mount::check_mounted;
+use crate::nomedia::NomediaFilter;
+use crate::plan::{decide, Decision, FileFacts, Policy, RemoteFacts, UploadReason};
+use crate::problem_names;
+use crate::profile::{FileTimings, Phase, StoreMemory};
+use crate::progress::{BarObserver, FileDone, NoProgress, SyncObserver};
+use crate::reconcile::Origin;
+use crate::remote_listing::RemoteListings;
+use crate::report::{FileOutcome, SyncReport};
+use crate::spread::{fresh_seed, shuffle, SplitMix64};
+use crate::target_dir;
+use crate::units::{format_byte_size, format_duration};
+use crate::verify_sampling::{verify_uploads, UploadedFile};
+use log::{error, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+use walkdir::{DirEntry, WalkDir};
 
-pub async fn sync(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+/// Files between two samples of the resident memory (`memory_ceiling_mb`).
+const MEMORY_SAMPLE_FILES: usize = 1000;
+
+pub async fn sync(config: &Config) -> Result<SyncReport, Box<dyn std::error::Error>> {
     // Backward‑compatible wrapper without progress bar
     sync_with_progress(config, false, false).await
 }
@@ -16,147 +51,1022 @@ pub async fn sync_with_progress(
     config: &Config,
     show_progress: bool,
     use_pseudo_hash: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let client = WebDavClient::new(
-        &config.webdav_url,
-        config.username.as_deref(),
-        config.password.as_deref(),
-        config.timeout_secs,
-    )?;
-
-    // Path to local hash store file
-    let hash_store_path = &config.hash_store_path;
+) -> Result<SyncReport, Box<dyn std::error::Error>> {
+    let client = WebDavClient::from_config(config)?;
+
     // Initialize guard which loads the remote hash store and prepares for syncing.
     let mut guard = HashStoreGuard::new(client.clone(), config).await?;
-    let hash_store = guard.hash_store_mut();
-    // Determine the file name of the local hash store so it can be ignored during sync.
-    let hash_store_file_name = std::path::Path::new(hash_store_path)
-        .file_name()
-        .and_then(|s| s.to_str())
-        .unwrap_or("")
-        .to_string();
+    let mut report =
+        sync_with_guard(config, &client, &mut guard, show_progress, use_pseudo_hash, &FilterSet::default()).await?;
+    // Ensure the hash store is saved and uploaded before returning.
+    let finalize_start = Instant::now();
+    guard.finalize().await?;
+    report.profile.add_time(Phase::Finalize, finalize_start.elapsed());
+    report.profile.total_micros += finalize_start.elapsed().as_micros() as u64;
 
-    // Calculate total number of files for progress bar
-    let total_files: usize = config
-        .folders
-        .iter()
-        .filter_map(|folder| {
-            let folder_path = Path::new(folder);
-            if !folder_path.exists() {
-                return None;
-            }
-            Some(
-                WalkDir::new(folder)
-                    .into_iter()
-                    .filter_map(|e| e.ok())
-                    .filter(|e| e.file_type().is_file())
-                    .count(),
-            )
-        })
-        .sum();
-
-    let progress_bar: Option<ProgressBar> = if show_progress {
-        let pb = ProgressBar::new(total_files as u64);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{msg} [{bar:40.cyan/blue}] {pos}/{len} ({eta})")?
-                .progress_chars("=> "),
-        );
-        pb.set_message("Syncing files");
-        Some(pb)
+    Ok(report)
+}
+
+/// Sync the files of all configured folders selected by `filters`, recording
+/// uploads in the store held by `guard`.
+///
+/// The guard is not finalized, so callers that own it (e.g. to finalize it on
+/// interrupt) decide when the store is persisted.
+pub async fn sync_with_guard(
+    config: &Config,
+    client: &WebDavClient,
+    guard: &mut HashStoreGuard,
+    show_progress: bool,
+    use_pseudo_hash: bool,
+    filters: &FilterSet,
+) -> Result<SyncReport, Box<dyn std::error::Error>> {
+    let observer: Arc<dyn SyncObserver> = if show_progress {
+        Arc::new(BarObserver::new(!config.low_memory)?)
     } else {
-        None
+        Arc::new(NoProgress)
+    };
+    sync_observed(config, client, guard, observer, use_pseudo_hash, filters).await
+}
+
+/// Like [`sync_with_guard`], reporting progress to `observer`.
+pub async fn sync_observed(
+    config: &Config,
+    client: &WebDavClient,
+    guard: &mut HashStoreGuard,
+    observer: Arc<dyn SyncObserver>,
+    use_pseudo_hash: bool,
+    filters: &FilterSet,
+) -> Result<SyncReport, Box<dyn std::error::Error>> {
+    sync_with_clock(config, client, guard, observer, use_pseudo_hash, filters, &SystemClock).await
+}
+
+/// Like [`sync_observed`], taking the time from `clock`.
+pub async fn sync_with_clock(
+    config: &Config,
+    client: &WebDavClient,
+    guard: &mut HashStoreGuard,
+    observer: Arc<dyn SyncObserver>,
+    use_pseudo_hash: bool,
+    filters: &FilterSet,
+    clock: &dyn Clock,
+) -> Result<SyncReport, Box<dyn std::error::Error>> {
+    target_dir::ensure(client, config).await?;
+    if config.layout == Layout::Cas {
+        return sync_cas(config, client, guard, observer.as_ref(), use_pseudo_hash, filters).await;
+    }
+    let run_clock = RunClock::start(clock);
+    // Set once the wall clock jumped; stamps are neither trusted nor
+    // recorded for the rest of the run.
+    let mut ignore_mtimes = false;
+    // Files uploaded in this run, for `verify_sampling`.
+    let mut uploaded_files: Vec<UploadedFile> = Vec::new();
+    let hash_store = guard.hash_store_mut();
+    let hasher = FileHasher::from_config(config);
+    // Determine the file name of the local hash store so it can be ignored during sync.
+    let artifacts = SyncArtifacts::from_config(config);
+    let mut report = SyncReport::default();
+    // Remote paths handled in this run and the local file that claimed them.
+    let mut claimed: HashMap<String, PathBuf> = HashMap::new();
+    let mut synced_folders = HashSet::new();
+    // Remote paths of every local file seen, selected or not, and whether every
+    // folder could be scanned; both guard `mirror_deletions`.
+    let mut seen_remote_paths = HashSet::new();
+    let mut all_folders_scanned = true;
+    let nomedia = NomediaFilter::from_config(config);
+    let retry_policy = RetryPolicy::uploads(config);
+    let last_modified_tolerance = config.last_modified_tolerance;
+    let mut order_rng = match config.upload_order {
+        UploadOrder::DeepestFirst => None,
+        UploadOrder::Shuffle => {
+            let seed = config.shuffle_seed.unwrap_or_else(fresh_seed);
+            info!("Uploading in shuffled order, seed {} (set shuffle_seed to repeat it)", seed);
+            Some(SplitMix64::new(seed))
+        }
+    };
+    let mtime_tolerance = config.mtime_tolerance;
+    let target_dir = config.target_dir.trim_matches('/');
+    match &hash_store.target_dir {
+        Some(previous) if previous.trim_matches('/') != target_dir => warn!(
+            "target_dir changed from '{}' to '{}'; stored hashes no longer match and files \
+             will be uploaded again (use --migrate-target-dir {}={} to keep them, or \
+             `hashes reconcile` afterwards to drop the old copies)",
+            previous, target_dir, previous, target_dir
+        ),
+        _ => {}
+    }
+    hash_store.target_dir = Some(target_dir.to_string());
+    if config.normalize_store_keys {
+        normalize_store_keys(config, hash_store);
+    }
+    let case_plan = match config.case_collision_policy {
+        Some(policy) => plan_case_collisions(config, filters, hash_store, policy)?,
+        None => CasePlan::default(),
+    };
+    report.case_collisions = case_plan.collisions.clone();
+    let mut budget = match &config.transfer_budget {
+        Some(budget) => Some(BudgetTracker::start(budget, run_clock.started())?),
+        None => None,
     };
+    // Estimating the upload needs a second full walk, like counting below.
+    if !config.low_memory {
+        check_quota(config, client, hash_store, use_pseudo_hash, filters).await?;
+    }
+    // Small files queued for the next batch upload.
+    let batching = match &config.small_file_batching {
+        Some(batching) if !client.is_read_only() => {
+            Some((batching, BatchMode::detect(client, &config.webdav_url, batching).await))
+        }
+        _ => None,
+    };
+    let mut batch: Vec<BatchFile> = Vec::new();
+
+    // Counting needs a second full walk, which low-memory mode does not afford.
+    if observer.wants_total() && !config.low_memory {
+        let count_start = Instant::now();
+        let (mut total_files, mut total_bytes) = (0, 0);
+        for folder in &config.folders {
+            let folder_path = Path::new(&folder.path);
+            if !FolderKind::of(folder_path).is_syncable() || check_mounted(folder).is_err() {
+                continue;
+            }
+            for entry in folder_files(folder_path, true, &NomediaFilter::from_config(config)) {
+                total_files += 1;
+                total_bytes += entry.metadata().map_or(0, |m| m.len());
+            }
+            observer.scan_progress(total_files, false);
+        }
+        observer.scan_size(total_bytes);
+        observer.scan_progress(total_files, true);
+        report.profile.add_time(Phase::Scan, count_start.elapsed());
+    }
+    // Upload progress of the files of this run.
+    let client = &client.clone().with_upload_progress(Some({
+        let observer = observer.clone();
+        Arc::new(move |path: &str, bytes, _size| observer.file_progress(path, bytes))
+    }));
+    let mut listings = RemoteListings::for_config(config)?;
+    let mut memory_ceiling = config.memory_ceiling_mb.map(MemoryCeiling::new);
 
-    for folder in &config.folders {
+    'folders: for folder_config in &config.folders {
+        let folder = &folder_config.path;
         let folder_path = Path::new(folder);
-        if !folder_path.exists() {
-            warn!("Folder {} does not exist, skipping", folder);
+        let kind = FolderKind::of(folder_path);
+        report.folder_kinds.insert(folder.clone(), kind);
+        match kind {
+            FolderKind::Directory | FolderKind::File => {}
+            FolderKind::Missing => {
+                warn!("Folder {} does not exist, skipping", folder);
+                all_folders_scanned = false;
+                continue;
+            }
+            FolderKind::Unsupported => {
+                warn!("Folder {} is neither a directory nor a regular file, skipping", folder);
+                all_folders_scanned = false;
+                continue;
+            }
+        }
+        // An empty mount point must not look like a folder whose files were deleted.
+        if let Err(reason) = check_mounted(folder_config) {
+            error!("Folder {} is not available ({}), skipping it and all deletions", folder, reason);
+            report.unmounted.push(folder.clone());
+            all_folders_scanned = false;
             continue;
         }
+        // `Config::load` dedupes folders, but configs built elsewhere are not.
+        if !synced_folders.insert(folder_key(folder)) {
+            warn!("Folder {} was already synced in this run, skipping", folder);
+            continue;
+        }
+        let folder_id = config.folder_id(folder_config);
+        let mut granularity = GranularityProbe::default();
 
-        // Collect file entries
-        let mut file_entries: Vec<_> = WalkDir::new(folder)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-            .collect();
-
-        // Sort deeper files first
-        file_entries.sort_by_key(|e| {
-            e.path()
-                .strip_prefix(folder_path)
-                .ok()
-                .map(|p| p.components().count())
-                .unwrap_or(0)
-        });
-        file_entries.reverse();
-
-        for entry in file_entries {
+        let mut entries = folder_files(folder_path, config.low_memory, &nomedia);
+        if let Some(rng) = &mut order_rng {
+            let mut shuffled: Vec<_> = entries.collect();
+            shuffle(&mut shuffled, rng);
+            entries = Box::new(shuffled.into_iter());
+        }
+        loop {
+            let scan_start = Instant::now();
+            let Some(entry) = entries.next() else {
+                break;
+            };
+            report.profile.record(Phase::Scan, scan_start.elapsed(), 0);
+            if let Some(ceiling) = &mut memory_ceiling {
+                ceiling.check(&mut listings)?;
+            }
             let local_path = entry.path();
-            let relative_path = local_path.strip_prefix(folder_path)?.to_string_lossy();
+            let relative_path = folder_kind::relative_path(folder_path, local_path)
+                .ok_or_else(|| format!("{} is not in folder {}", local_path.display(), folder))?
+                .to_string_lossy();
+            let routed_path = remote_path_in(config, folder_config, &relative_path);
+
+            // Skip the hash store, and those of other configs, to avoid uploading them.
+            if artifacts.contains(entry.file_name()) {
+                observer.file_done(&routed_path, FileDone::Ignored);
+                continue;
+            }
+
+            seen_remote_paths.insert(routed_path.clone());
+
+            // Excludes still apply to files admitted by the extension allowlist.
+            if !folder_config.admits(local_path) || !filters.matches(&relative_path, &entry.metadata()?) {
+                *report.filtered_out.entry(folder_id.clone()).or_default() += 1;
+                observer.file_done(&routed_path, FileDone::Ignored);
+                continue;
+            }
+
+            let mut remote_path = match case_plan.role(local_path) {
+                Some(CaseRole::Winner { key }) => key.clone(),
+                Some(CaseRole::Loser) => {
+                    observer.file_done(&routed_path, FileDone::Ignored);
+                    continue;
+                }
+                None => routed_path,
+            };
+
+            // Servers may trim or mangle such names, see `problem_names`.
+            let problem_name = problem_names::has_problem_names(&remote_path);
+            if problem_name {
+                if config.normalize_problem_names {
+                    let normalized = problem_names::normalize(&remote_path);
+                    warn!("{} has whitespace or control characters in its name, syncing it as {}", problem_names::escape(&remote_path), normalized);
+                    report.renamed.insert(remote_path, normalized.clone());
+                    remote_path = normalized;
+                } else if let Some(stored) = hash_store.server_names.get(&remote_path) {
+                    remote_path = stored.clone();
+                } else {
+                    warn!(
+                        "{} has whitespace or control characters in its name, which some servers change; \
+                         set normalize_problem_names: true to sync it under a cleaned-up name",
+                        problem_names::escape(&remote_path)
+                    );
+                }
+                seen_remote_paths.insert(remote_path.clone());
+            }
+
+            // The remote store and its deltas are never overwritten by a file.
+            if hash_delta::is_reserved(&config.remote_hash_path, &remote_path) {
+                warn!("{} maps to {}, which is reserved for the hash store, skipping", local_path.display(), remote_path);
+                observer.file_done(&remote_path, FileDone::Ignored);
+                continue;
+            }
 
-            // Skip the hash store file itself to avoid uploading it.
-            if entry.file_name().to_string_lossy() == hash_store_file_name {
-                if let Some(pb) = &progress_bar {
-                    pb.inc(1);
+            // Another file of this run already resolved to the same remote path.
+            if let Some(first) = claimed.get(&remote_path) {
+                let message = format!(
+                    "{} and {} both map to remote path {}",
+                    first.display(),
+                    local_path.display(),
+                    remote_path
+                );
+                if config.collision_policy == CollisionPolicy::Error {
+                    return Err(format!("Remote path collision: {}", message).into());
                 }
+                warn!("Remote path collision, keeping the first file: {}", message);
+                observer.file_done(&remote_path, FileDone::Ignored);
+                report.collisions.push(remote_path);
                 continue;
             }
+            claimed.insert(remote_path.clone(), local_path.to_path_buf());
 
-            let current_hash = if use_pseudo_hash {
-                HashStore::compute_pseudo_hash(local_path).await?
-            } else {
-                HashStore::compute_hash(local_path).await?
+            let metadata = entry.metadata()?;
+            let file_size = metadata.len();
+            observer.file_start(&remote_path, file_size);
+            let mut timings = FileTimings::default();
+            if config.trust_mtime && !ignore_mtimes {
+                if let Some(jump) = run_clock.jump() {
+                    warn!(
+                        "The system clock jumped by {}s during the sync; not trusting mtimes for the rest of the run",
+                        jump
+                    );
+                    ignore_mtimes = true;
+                }
+            }
+            let stamp = if config.trust_mtime && !ignore_mtimes { FileStamp::of(&metadata) } else { None };
+            if let Some(stamp) = &stamp {
+                granularity.observe(stamp);
+            }
+            let remote = timings.time(Phase::RemoteCheck, 0, listings.stat(client, &remote_path)).await?;
+            let remote_size = remote.as_ref().and_then(|stat| stat.size);
+            let remote = remote.map(|stat| stat.fingerprint());
+            let policy = Policy { read_only: client.is_read_only(), last_modified_tolerance };
+            let mut facts = FileFacts {
+                size: file_size,
+                hash: None,
+                stored_hash: hash_store.hashes(use_pseudo_hash).get(&remote_path).map(String::as_str),
+                remote: match &remote {
+                    Some(fingerprint) => RemoteFacts::Present { size: remote_size, fingerprint },
+                    None => RemoteFacts::Missing,
+                },
+                stored_fingerprint: hash_store.fingerprints.get(&remote_path),
             };
-            let remote_path = if config.target_dir.is_empty() {
-                relative_path.to_string()
+            let mut decision = decide(&facts, &policy);
+            // A plan needs no hash, the store is not updated.
+            let current_hash = if let Decision::Plan(_) = decision {
+                String::new()
             } else {
-                format!("{}/{}", config.target_dir.trim_end_matches('/'), relative_path)
+                // With an exactly matching stamp the stored hash is still valid;
+                // anything else (including mtimes rounded by another filesystem) is rehashed.
+                let stored_stamp = hash_store.stamps.get(&remote_path);
+                let current_hash = match (stamp, stored_stamp, facts.stored_hash) {
+                    (Some(stamp), Some(stored), Some(hash))
+                        if stored.compare(&stamp, mtime_tolerance) == StampMatch::Unchanged =>
+                    {
+                        hash.to_string()
+                    }
+                    _ => {
+                        timings
+                            .time(Phase::Hash, file_size, hasher.compute(local_path, use_pseudo_hash))
+                            .await?
+                    }
+                };
+                facts.hash = Some(&current_hash);
+                decision = decide(&facts, &policy);
+                if let Some(stamp) = stamp {
+                    hash_store.stamps.insert(remote_path.clone(), stamp);
+                }
+                current_hash
+            };
+
+            let reason = match decision {
+                Decision::Upload(reason) | Decision::Plan(reason) => reason,
+                // The hash is known, so it is never needed.
+                Decision::Skip | Decision::NeedHash => {
+                    if let Some(fingerprint) = remote.filter(|f| *f != RemoteFingerprint::None) {
+                        hash_store.fingerprints.insert(remote_path.clone(), fingerprint);
+                    }
+                    hash_store.origins.insert(remote_path.clone(), Origin::new(&folder_id, &relative_path));
+                    observer.file_done(&remote_path, FileDone::Unchanged);
+                    report.record(&folder_id, local_path, FileOutcome::Skipped, file_size);
+                    report.profile.record_file(&remote_path, timings);
+                    continue;
+                }
             };
             
-            // If the file's hash matches the stored hash, skip uploading.
-            let remote_exists = client.file_exists(&remote_path).await?;
-            let stored_hash = if use_pseudo_hash {
-                hash_store.pseudo_hashes.get(&remote_path)
+            let queued: u64 = batch.iter().map(|f| f.size).sum();
+            if budget.as_ref().is_some_and(|b| !b.allows(queued + file_size)) {
+                warn!("Transfer budget exhausted, postponing the remaining uploads");
+                report.budget_exhausted = true;
+                observer.file_done(&remote_path, FileDone::Postponed);
+                report.profile.record_file(&remote_path, timings);
+                break 'folders;
+            }
+
+            match reason {
+                UploadReason::RemoteChanged => {
+                    warn!("{} was changed on the remote since the last sync, uploading the local version", remote_path)
+                }
+                UploadReason::SizeDiffers => {
+                    warn!("{} has another size on the remote than locally, uploading it again", remote_path)
+                }
+                _ => {}
+            }
+
+            // A read-only run only reports what it would send; the store keeps
+            // describing the remote as it is.
+            if let Decision::Plan(_) = decision {
+                observer.file_done(&remote_path, FileDone::Planned);
+                report.planned.push(remote_path.clone());
+                report.profile.record_file(&remote_path, timings);
+                continue;
+            }
+
+            let chunks = if config.cdc_dedup && file_size >= config.cdc_min_file_size {
+                let chunks = timings.time(Phase::Hash, file_size, chunk_file(local_path)).await?;
+                if let Some(previous) = hash_store.chunks.get(&remote_path) {
+                    let change = ChunkChange::between(&remote_path, previous, &chunks);
+                    info!("{} changed, {}% of chunks differ", remote_path, change.percent());
+                    report.chunk_changes.push(change);
+                }
+                Some(chunks)
             } else {
-                hash_store.regular_hashes.get(&remote_path)
+                None
             };
-            if remote_exists && stored_hash == Some(&current_hash) {
-                // Still update the progress bar to reflect that the file was processed.
-                if let Some(pb) = &progress_bar {
-                    pb.inc(1);
+
+            // Pseudo hashes say nothing about the content.
+            let checksum = (!use_pseudo_hash).then(|| Checksum::sha256(&current_hash));
+
+            // Problem names are uploaded alone, to look up where the server stored them.
+            if let Some((batching, mode)) = batching
+                .as_ref()
+                .filter(|(b, _)| file_size <= b.max_file_size && chunks.is_none() && !problem_name)
+            {
+                batch.push(BatchFile {
+                    folder_id: folder_id.clone(),
+                    local_path: local_path.to_path_buf(),
+                    relative_path: relative_path.to_string(),
+                    if_match: remote.as_ref().and_then(RemoteFingerprint::if_match).map(str::to_string),
+                    remote_path: remote_path.clone(),
+                    hash: current_hash,
+                    checksum,
+                    size: file_size,
+                });
+                report.profile.record_file(&remote_path, timings);
+                if batch.len() >= batching.files_per_batch {
+                    let files = std::mem::take(&mut batch);
+                    let result = upload_batch(client, mode, &files, retry_policy, config.verify_upload_size).await?;
+                    uploaded_files.extend(record_batch(
+                        files,
+                        result,
+                        hash_store,
+                        use_pseudo_hash,
+                        &mut report,
+                        &mut budget,
+                        observer.as_ref(),
+                        config.max_truncated_uploads,
+                    )?);
                 }
                 continue;
             }
-            
-            // upload
-            client.upload_file(local_path, &remote_path).await?;
-            
-            // update progress bar
-            if let Some(pb) = &progress_bar {
-                pb.inc(1);
+
+            // upload, surfacing retries instead of stalling silently
+            let mut on_retry = |event: &RetryEvent| {
+                warn!("Upload of {} failed, {}", event.path, event);
+                observer.retry(event);
+                report.retries += 1;
+                report.backoff_ms += event.delay.as_millis() as u64;
+            };
+            // Only replace the version just checked, so a concurrent write by
+            // another device fails with 412 instead of being overwritten.
+            let if_match = remote.as_ref().and_then(RemoteFingerprint::if_match);
+            let upload = client.upload_file_with_retry(
+                local_path,
+                &remote_path,
+                Some(&current_hash),
+                checksum.as_ref(),
+                if_match,
+                retry_policy,
+                &mut on_retry,
+            );
+            let stored = match timings.time(Phase::Upload, file_size, upload).await? {
+                Upload::Stored(fingerprint) => fingerprint,
+                Upload::Conflict => {
+                    warn!("{} was changed on the server during the sync, keeping the remote version", remote_path);
+                    report.conflicts.push(remote_path.clone());
+                    observer.file_done(&remote_path, FileDone::Conflict);
+                    report.profile.record_file(&remote_path, timings);
+                    continue;
+                }
+            };
+            if let Some(budget) = &mut budget {
+                budget.record(file_size)?;
+            }
+            // The key the file is recorded under: where the server stored it.
+            let mut key = remote_path.clone();
+            if problem_name && !config.normalize_problem_names {
+                match problem_names::stored_name(client, &remote_path).await? {
+                    Some(stored) if stored != remote_path => {
+                        warn!("Server stored {} as {}, checking that name from now on", problem_names::escape(&remote_path), stored);
+                        hash_store.server_names.insert(remote_path.clone(), stored.clone());
+                        key = stored;
+                        seen_remote_paths.insert(key.clone());
+                    }
+                    Some(_) => {}
+                    None => warn!("Cannot find {} on the server after uploading it", problem_names::escape(&remote_path)),
+                }
             }
+            if config.verify_upload_size {
+                // Servers without a Content-Length on HEAD cannot be checked.
+                if let Some(stored) = client.remote_size(&key).await?.filter(|s| *s != file_size) {
+                    warn!(
+                        "Server stored {} bytes of {} ({} bytes locally), not recording it as synced",
+                        stored, remote_path, file_size
+                    );
+                    report.truncated.push(remote_path.clone());
+                    observer.file_done(&remote_path, FileDone::Truncated);
+                    report.profile.record_file(&remote_path, timings);
+                    if report.truncated.len() >= config.max_truncated_uploads {
+                        return Err(truncated_uploads_error(&report));
+                    }
+                    continue;
+                }
+            }
+            report.record(&folder_id, local_path, FileOutcome::Uploaded, file_size);
+            observer.file_done(&remote_path, FileDone::Uploaded);
             
-            // update hash
-            if use_pseudo_hash {
-                hash_store
-                    .pseudo_hashes
-                    .insert(remote_path.to_string(), current_hash);
-            } else {
-                hash_store
-                    .regular_hashes
-                    .insert(remote_path.to_string(), current_hash);
+            // update hash; without an ETag in the PUT response, the new remote
+            // fingerprint is recorded on the next unchanged pass
+            match stored {
+                RemoteFingerprint::Etag(_) => hash_store.fingerprints.insert(key.clone(), stored),
+                _ => hash_store.fingerprints.remove(&key),
+            };
+            if let Some(chunks) = chunks {
+                hash_store.chunks.insert(key.clone(), chunks);
             }
+            uploaded_files.push(UploadedFile {
+                folder_id: folder_id.clone(),
+                local_path: local_path.to_path_buf(),
+                remote_path: key.clone(),
+                hash: (!use_pseudo_hash).then(|| current_hash.clone()),
+                size: file_size,
+            });
+            hash_store.origins.insert(key.clone(), Origin::new(&folder_id, &relative_path));
+            hash_store
+                .hashes_mut(use_pseudo_hash)
+                .insert(key, current_hash);
+            report.profile.record_file(&remote_path, timings);
+        }
+
+        if let Some(step) = granularity.coarse_granularity() {
+            info!(
+                "Files in {} have mtimes with {}s granularity (FAT/exFAT?); keep mtime_tolerance \
+                 at least {} to avoid rehashing copied files",
+                folder,
+                step.as_secs(),
+                format_duration(step)
+            );
         }
     }
 
-    if let Some(pb) = progress_bar {
-        pb.finish_with_message("Sync complete");
+    if let Some((_, mode)) = batching.as_ref().filter(|_| !batch.is_empty()) {
+        let result = upload_batch(client, mode, &batch, retry_policy, config.verify_upload_size).await?;
+        uploaded_files.extend(record_batch(
+            batch,
+            result,
+            hash_store,
+            use_pseudo_hash,
+            &mut report,
+            &mut budget,
+            observer.as_ref(),
+            config.max_truncated_uploads,
+        )?);
     }
-    // Ensure the hash store is saved and uploaded before returning.
-    guard.finalize().await?;
 
+    if let Some(sampling) = config.verify_sampling.as_ref().filter(|_| !client.is_read_only()) {
+        let sample = verify_uploads(config, sampling, client, hash_store, use_pseudo_hash, uploaded_files).await?;
+        let mismatched = sample.mismatched.clone();
+        report.sample = Some(sample);
+        if !mismatched.is_empty() {
+            return Err(format!(
+                "Verification found {} uploaded files that differ on the server ({}); they will be uploaded \
+                 again, and the next sync verifies every file of their folders ({})",
+                mismatched.len(),
+                mismatched.join(", "),
+                hash_store.full_verify_folders.iter().cloned().collect::<Vec<_>>().join(", ")
+            )
+            .into());
+        }
+    }
+
+    // Pruned directories were never walked; their synced files still count as
+    // seen, so `mirror_deletions` keeps their remote copies.
+    for dir in nomedia.skipped() {
+        report.nomedia_skipped.push(dir.display().to_string());
+        let Some((folder, relative)) = config.folders.iter().find_map(|f| Some((f, dir.strip_prefix(&f.path).ok()?))) else {
+            continue;
+        };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        // Routed files of the directory are below the route's subdirectory.
+        let prefixes: Vec<String> = std::iter::once(relative.clone())
+            .chain(folder.routes.iter().filter(|r| !r.flatten).map(|r| format!("{}/{}", r.subdir(), relative)))
+            .map(|prefix| remote_path_for(config, &prefix))
+            .collect();
+        let below: Vec<String> =
+            hash_store.hashes(use_pseudo_hash).keys().filter(|k| prefixes.iter().any(|p| is_below(k, p))).cloned().collect();
+        seen_remote_paths.extend(below);
+    }
+
+    if config.mirror_deletions {
+        if !all_folders_scanned || report.budget_exhausted || *filters != FilterSet::default() {
+            info!("Not every file was scanned in this run, skipping mirror_deletions");
+        } else {
+            propagate_deletions(config, client, hash_store, use_pseudo_hash, &seen_remote_paths, &mut report, run_clock.started())
+                .await?;
+        }
+    }
+
+    let growth = StoreGrowth::of(config, hash_store, seen_remote_paths.len());
+    if all_folders_scanned && growth.exceeds(config.compact_ratio) {
+        if config.auto_compact && !client.is_read_only() {
+            match compact(config, hash_store, use_pseudo_hash) {
+                Ok(compaction) => {
+                    info!("Compacted the hash store, removing {} stale entries", compaction.total());
+                    report.compaction = Some(compaction);
+                }
+                Err(e) => warn!("Failed to compact the hash store: {}", e),
+            }
+        } else {
+            warn!(
+                "Hash store has {} entries for {} local files; run `hashes prune` or set auto_compact: true",
+                growth.entries, growth.files
+            );
+        }
+    }
+
+    report.clock_jump_secs = run_clock.jump();
+    report.profile.total_micros = run_clock.elapsed().as_micros() as u64;
+    report.profile.connections = Some(client.connection_stats());
+    report.profile.store_memory = Some(StoreMemory {
+        entries: (hash_store.regular_hashes.len() + hash_store.pseudo_hashes.len()) as u64,
+        approximate_bytes: hash_store.approximate_memory_bytes(),
+    });
+    #[cfg(feature = "memory_stats")]
+    {
+        report.peak_memory_bytes = memory::peak_resident_bytes();
+    }
+    observer.summary(&report);
+    Ok(report)
+}
+
+/// Remote path of a file, given its path relative to its configured folder.
+pub fn remote_path_for(config: &Config, relative_path: &str) -> String {
+    if config.target_dir.is_empty() {
+        relative_path.to_string()
+    } else {
+        format!("{}/{}", config.target_dir.trim_end_matches('/'), relative_path)
+    }
+}
+
+/// Remote path of a file of `folder`, given its path relative to it; see
+/// [`crate::config::Route`].
+pub fn remote_path_in(config: &Config, folder: &FolderConfig, relative_path: &str) -> String {
+    remote_path_for(config, &folder.route(relative_path))
+}
+
+/// Local file of a remote path, if it exists in one of the configured folders.
+/// Files of flattened routes are not found, as their path is not kept.
+pub fn local_path_for(config: &Config, remote_path: &str) -> Option<PathBuf> {
+    let target_dir = config.target_dir.trim_end_matches('/');
+    let relative_path = if target_dir.is_empty() {
+        remote_path
+    } else {
+        remote_path.strip_prefix(target_dir)?.strip_prefix('/')?
+    };
+    config
+        .folders
+        .iter()
+        .find_map(|folder| {
+            let folder_path = Path::new(&folder.path);
+            if folder_path.is_file() {
+                return (folder_path.file_name()? == relative_path).then(|| folder_path.to_path_buf());
+            }
+            let path = folder_path.join(folder.unroute(relative_path).unwrap_or(relative_path));
+            path.is_file().then_some(path)
+        })
+}
+
+/// Rewrite the backslash keys of `store`, preferring entries whose stamp
+/// matches the local file, and log what changed.
+pub fn normalize_store_keys(config: &Config, store: &mut HashStore) -> KeyNormalization {
+    let outcome = store.normalize_keys(|path| {
+        let metadata = std::fs::metadata(local_path_for(config, path)?).ok()?;
+        FileStamp::of(&metadata)
+    });
+    if outcome.rewritten > 0 {
+        info!(
+            "Rewrote {} hash store keys with backslashes to '/', dropping {} duplicates",
+            outcome.rewritten, outcome.merged
+        );
+    }
+    outcome
+}
+
+/// Compare the bytes a sync would upload with the space left on the server,
+/// failing before the first upload if it is not enough and
+/// `fail_on_insufficient_quota` is set (and the run may write at all).
+async fn check_quota(
+    config: &Config,
+    client: &WebDavClient,
+    hash_store: &HashStore,
+    use_pseudo_hash: bool,
+    filters: &FilterSet,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let available = match client.quota().await {
+        Ok(quota) => quota.and_then(|q| q.available),
+        Err(e) => {
+            warn!("Cannot read the quota of the server: {}", e);
+            None
+        }
+    };
+    // Unlimited or unknown.
+    let Some(available) = available else {
+        return Ok(());
+    };
+    let pending = pending_upload_bytes(config, hash_store, use_pseudo_hash, filters);
+    if pending <= available {
+        return Ok(());
+    }
+    let message = format!(
+        "Not enough space on the server: about {} to upload, {} available",
+        format_byte_size(pending),
+        format_byte_size(available)
+    );
+    if config.fail_on_insufficient_quota && !client.is_read_only() {
+        return Err(format!("{} (set fail_on_insufficient_quota: false to sync anyway)", message).into());
+    }
+    warn!("{}", message);
+    Ok(())
+}
+
+/// Bytes of the selected files a sync would upload, judged by metadata:
+/// files without a hash in `store`, and files whose size or mtime differs
+/// from the stamp recorded with their hash. Changed files without a stamp
+/// are not counted.
+fn pending_upload_bytes(config: &Config, store: &HashStore, use_pseudo_hash: bool, filters: &FilterSet) -> u64 {
+    selected_files(config, filters)
+        .filter_map(|(local_path, remote_path)| {
+            let metadata = std::fs::metadata(&local_path).ok()?;
+            let tracked = store.hashes(use_pseudo_hash).contains_key(&remote_path);
+            let changed = match (store.stamps.get(&remote_path), FileStamp::of(&metadata)) {
+                (Some(stored), Some(current)) => stored.compare(&current, config.mtime_tolerance) == StampMatch::Changed,
+                _ => false,
+            };
+            (!tracked || changed).then_some(metadata.len())
+        })
+        .sum()
+}
+
+/// `memory_ceiling_mb`, sampled every [`MEMORY_SAMPLE_FILES`] files.
+struct MemoryCeiling {
+    limit: u64,
+    files: usize,
+    /// Set once the listings were dropped to get below the limit.
+    released: bool,
+}
+
+impl MemoryCeiling {
+    fn new(limit_mb: u64) -> Self {
+        MemoryCeiling { limit: limit_mb * 1024 * 1024, files: 0, released: false }
+    }
+
+    /// Count a file; on every sample above the limit, drop the remote
+    /// listings the first time and fail the sync the next.
+    fn check(&mut self, listings: &mut RemoteListings) -> Result<(), Box<dyn std::error::Error>> {
+        self.files += 1;
+        if !self.files.is_multiple_of(MEMORY_SAMPLE_FILES) {
+            return Ok(());
+        }
+        let Some(resident) = memory::resident_bytes().filter(|resident| *resident > self.limit) else {
+            return Ok(());
+        };
+        if !std::mem::replace(&mut self.released, true) {
+            let files = listings.release();
+            warn!(
+                "Resident memory {} is above memory_ceiling_mb, dropped the cached listings of {} remote files",
+                format_byte_size(resident),
+                files
+            );
+            return Ok(());
+        }
+        Err(format!(
+            "Resident memory {} is above memory_ceiling_mb ({}); set low_memory: true or raise the limit",
+            format_byte_size(resident),
+            format_byte_size(self.limit)
+        )
+        .into())
+    }
+}
+
+/// Record the outcome of every file of an uploaded batch, like a single
+/// upload would; files that failed are left for the next run. Returns the
+/// stored files, or an error once `max_truncated` uploads were truncated.
+#[allow(clippy::too_many_arguments)]
+fn record_batch(
+    files: Vec<BatchFile>,
+    result: BatchResult,
+    hash_store: &mut HashStore,
+    use_pseudo_hash: bool,
+    report: &mut SyncReport,
+    budget: &mut Option<BudgetTracker>,
+    observer: &dyn SyncObserver,
+    max_truncated: usize,
+) -> Result<Vec<UploadedFile>, Box<dyn std::error::Error>> {
+    report.retries += result.retries;
+    report.backoff_ms += result.backoff_ms;
+    let mut stored = Vec::new();
+    for (file, outcome) in files.into_iter().zip(result.outcomes) {
+        if matches!(outcome, BatchOutcome::Stored(_) | BatchOutcome::Truncated(_)) {
+            if let Some(budget) = budget {
+                budget.record(file.size)?;
+            }
+        }
+        match outcome {
+            BatchOutcome::Stored(fingerprint) => {
+                report.record(&file.folder_id, &file.local_path, FileOutcome::Uploaded, file.size);
+                observer.file_done(&file.remote_path, FileDone::Uploaded);
+                match fingerprint {
+                    RemoteFingerprint::Etag(_) => hash_store.fingerprints.insert(file.remote_path.clone(), fingerprint),
+                    _ => hash_store.fingerprints.remove(&file.remote_path),
+                };
+                hash_store.origins.insert(file.remote_path.clone(), Origin::new(&file.folder_id, &file.relative_path));
+                stored.push(UploadedFile {
+                    folder_id: file.folder_id,
+                    local_path: file.local_path,
+                    remote_path: file.remote_path.clone(),
+                    hash: (!use_pseudo_hash).then(|| file.hash.clone()),
+                    size: file.size,
+                });
+                hash_store.hashes_mut(use_pseudo_hash).insert(file.remote_path, file.hash);
+            }
+            BatchOutcome::Conflict => {
+                warn!("{} was changed on the server during the sync, keeping the remote version", file.remote_path);
+                observer.file_done(&file.remote_path, FileDone::Conflict);
+                report.conflicts.push(file.remote_path);
+            }
+            BatchOutcome::Truncated(stored) => {
+                warn!(
+                    "Server stored {} bytes of {} ({} bytes locally), not recording it as synced",
+                    stored, file.remote_path, file.size
+                );
+                observer.file_done(&file.remote_path, FileDone::Truncated);
+                report.truncated.push(file.remote_path);
+            }
+            BatchOutcome::Failed(reason) => {
+                warn!("Upload of {} failed: {}", file.remote_path, reason);
+                observer.file_done(&file.remote_path, FileDone::Failed);
+                report.failed.insert(file.remote_path, reason);
+            }
+        }
+    }
+    if report.truncated.len() >= max_truncated {
+        return Err(truncated_uploads_error(report));
+    }
+    Ok(stored)
+}
+
+fn truncated_uploads_error(report: &SyncReport) -> Box<dyn std::error::Error> {
+    format!(
+        "The server stored truncated content for {} uploads ({}); a proxy in front of \
+         it probably limits the request body size (e.g. nginx client_max_body_size)",
+        report.truncated.len(),
+        report.truncated.join(", ")
+    )
+    .into()
+}
+
+/// File name of the local hash store, which is never uploaded as content.
+pub(crate) fn hash_store_file_name(config: &Config) -> String {
+    Path::new(&config.hash_store_path)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_string()
+}
 
+/// Delete the remote copies of tracked files that no longer exist locally,
+/// archiving each one first if `local_archive_dir` is set (named after `now`,
+/// the start of the run). A failure to archive keeps the remote copy and is
+/// reported. A read-only run only lists the copies it would delete.
+async fn propagate_deletions(
+    config: &Config,
+    client: &WebDavClient,
+    hash_store: &mut HashStore,
+    use_pseudo_hash: bool,
+    seen_remote_paths: &HashSet<String>,
+    report: &mut SyncReport,
+    now: SystemTime,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let archive = LocalArchive::from_config(config);
+    let deleted: Vec<String> = hash_store
+        .hashes(use_pseudo_hash)
+        .keys()
+        .filter(|path| !seen_remote_paths.contains(*path))
+        .cloned()
+        .collect();
+    for remote_path in deleted {
+        if client.is_read_only() {
+            report.planned_deletions.push(remote_path);
+            continue;
+        }
+        if let Some(archive) = &archive {
+            match archive.archive(client, &remote_path, now).await {
+                Ok(Some(copy)) => info!("Archived {} to {}", remote_path, copy.display()),
+                Ok(None) => {
+                    // Already gone remotely; nothing to archive or delete.
+                    hash_store.forget(&remote_path);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Failed to archive {}, keeping the remote copy: {}", remote_path, e);
+                    report.deletion_blocked.push(remote_path);
+                    continue;
+                }
+            }
+        }
+        if let Err(e) = client.delete_file(&remote_path).await {
+            warn!("Failed to delete remote {}: {}", remote_path, e);
+            report.deletion_blocked.push(remote_path);
+            continue;
+        }
+        info!("Deleted remote {}", remote_path);
+        hash_store.forget(&remote_path);
+        report.deleted.push(remote_path);
+    }
+    if let Some(archive) = &archive {
+        archive.prune(now)?;
+    }
     Ok(())
+}
+
+/// Local files selected for syncing, with their remote paths.
+///
+/// Applies the same folder, allowlist and filter rules as a sync, without
+/// collision handling or progress reporting.
+pub(crate) fn selected_files<'a>(
+    config: &'a Config,
+    filters: &'a FilterSet,
+) -> impl Iterator<Item = (PathBuf, String)> + 'a {
+    let artifacts = SyncArtifacts::from_config(config);
+    let nomedia = NomediaFilter::from_config(config);
+    config
+        .folders
+        .iter()
+        .filter(|folder| Path::new(&folder.path).exists())
+        .flat_map(move |folder| {
+            let folder_path = Path::new(&folder.path);
+            let artifacts = artifacts.clone();
+            folder_files(folder_path, true, &nomedia).filter_map(move |entry| {
+                if artifacts.contains(entry.file_name()) {
+                    return None;
+                }
+                let relative_path = folder_kind::relative_path(folder_path, entry.path())?.to_string_lossy().to_string();
+                let metadata = entry.metadata().ok()?;
+                if !folder.admits(entry.path()) || !filters.matches(&relative_path, &metadata) {
+                    return None;
+                }
+                Some((entry.path().to_path_buf(), remote_path_in(config, folder, &relative_path)))
+            })
+        })
+}
+
+/// Iterate over the regular files below `folder_path`, leaving out the
+/// directories pruned by `nomedia`; a `folder_path` that is a regular file
+/// yields just itself.
+///
+/// By default the entries of a folder are collected and sorted so that deeper
+/// files are uploaded first. In low-memory mode the directory walk is consumed
+/// lazily instead, so at most one directory level is held in memory at a time.
+pub fn folder_files(
+    folder_path: &Path,
+    low_memory: bool,
+    nomedia: &NomediaFilter,
+) -> Box<dyn Iterator<Item = DirEntry>> {
+    let (folder, nomedia) = (folder_path.to_path_buf(), nomedia.clone());
+    let files = WalkDir::new(folder_path)
+        .into_iter()
+        // Pruned directories are never read.
+        .filter_entry(move |e| !e.file_type().is_dir() || nomedia.descends(&folder, e.path()))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file());
+    if low_memory {
+        return Box::new(files);
+    }
+
+    // Collect file entries
+    let mut file_entries: Vec<_> = files.collect();
+
+    // Sort deeper files first
+    file_entries.sort_by_key(|e| {
+        e.path()
+            .strip_prefix(folder_path)
+            .ok()
+            .map(|p| p.components().count())
+            .unwrap_or(0)
+    });
+    file_entries.reverse();
+    Box::new(file_entries.into_iter())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_low_memory_walk_yields_same_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..50 {
+            let sub = dir.path().join(format!("d{}", i % 7)).join(format!("e{}", i % 3));
+            std::fs::create_dir_all(&sub).unwrap();
+            std::fs::write(sub.join(format!("f{}.txt", i)), b"x").unwrap();
+        }
+
+        let collect = |low_memory| -> BTreeSet<_> {
+            folder_files(dir.path(), low_memory, &NomediaFilter::default())
+                .map(|e| e.path().to_path_buf())
+                .collect()
+        };
+        let sorted = collect(false);
+        assert_eq!(sorted.len(), 50);
+        assert_eq!(sorted, collect(true));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_memory_ceiling_drops_listings_before_failing() {
+        let mut listings = RemoteListings::default();
+        listings.record("DCIM", Some(vec![("DCIM/a.jpg".to_string(), Default::default())])).unwrap();
+        // Any process is above 1 MiB.
+        let mut ceiling = MemoryCeiling::new(1);
+        for _ in 0..MEMORY_SAMPLE_FILES {
+            ceiling.check(&mut listings).unwrap();
+        }
+        assert_eq!(listings.lookup("DCIM", "DCIM/a.jpg").unwrap(), None);
+        for _ in 1..MEMORY_SAMPLE_FILES {
+            ceiling.check(&mut listings).unwrap();
+        }
+        let err = ceiling.check(&mut listings).unwrap_err().to_string();
+        assert!(err.contains("memory_ceiling_mb"), "{}", err);
+    }
 }
\ No newline at end of file
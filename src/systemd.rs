@@ -0,0 +1,189 @@
+//! Service manager notifications (`sd_notify`) for `Type=notify` units.
+//!
+//! Messages are datagrams written to the socket named by `NOTIFY_SOCKET`;
+//! nothing links against libsystemd. Without the `systemd` feature, or when
+//! the variable is unset (not started by systemd), every call is a no-op.
+
+use crate::report::{format_summary, SyncReport};
+use log::debug;
+use std::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::time::Duration;
+
+/// Sends lifecycle notifications to the service manager.
+#[derive(Debug, Clone, Default)]
+pub struct SystemdNotifier {
+    socket: Option<OsString>,
+}
+
+impl SystemdNotifier {
+    /// The notifier for the socket systemd passed in `NOTIFY_SOCKET`.
+    #[cfg(feature = "systemd")]
+    pub fn from_env() -> Self {
+        Self::at(std::env::var_os("NOTIFY_SOCKET").as_deref())
+    }
+
+    /// Built without the `systemd` feature: never notifies.
+    #[cfg(not(feature = "systemd"))]
+    pub fn from_env() -> Self {
+        Self::default()
+    }
+
+    /// A notifier writing to `socket`, a path or an abstract name starting
+    /// with `@`; `None` disables it.
+    pub fn at(socket: Option<&OsStr>) -> Self {
+        SystemdNotifier { socket: socket.filter(|s| !s.is_empty()).map(OsStr::to_os_string) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Send `message` (newline-separated `KEY=value` assignments). Returns
+    /// whether it was sent.
+    pub fn notify(&self, message: &str) -> Result<bool, Box<dyn Error>> {
+        let Some(socket) = &self.socket else {
+            return Ok(false);
+        };
+        send(socket, message)?;
+        Ok(true)
+    }
+
+    /// Like [`notify`](Self::notify), but failures are only logged: the
+    /// service manager going away must not fail a sync.
+    fn try_notify(&self, message: &str) {
+        if let Err(e) = self.notify(message) {
+            debug!("Cannot notify the service manager: {}", e);
+        }
+    }
+
+    pub fn ready(&self) {
+        self.try_notify("READY=1\nSTATUS=Syncing");
+    }
+
+    pub fn watchdog(&self) {
+        self.try_notify("WATCHDOG=1");
+    }
+
+    /// The config is being read again; see [`crate::daemon::reload`].
+    pub fn reloading(&self) {
+        self.try_notify("RELOADING=1\nSTATUS=Reloading the config");
+    }
+
+    pub fn stopping(&self) {
+        self.try_notify("STOPPING=1");
+    }
+
+    /// Report the outcome of a run as the unit's status line.
+    pub fn status(&self, outcome: &Result<SyncReport, String>) {
+        let line = match outcome {
+            Ok(report) => format_summary(report),
+            Err(e) => format!("sync failed: {}", e),
+        };
+        // The protocol is line based.
+        self.try_notify(&format!("STATUS={}", line.replace('\n', " ")));
+    }
+}
+
+/// How often to send `WATCHDOG=1`: half of `WatchdogSec`, if the watchdog is
+/// enabled for this process (`WATCHDOG_USEC`, `WATCHDOG_PID`).
+pub fn watchdog_interval(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if pid.is_some_and(|p| p.parse() != Ok(own_pid)) {
+        return None;
+    }
+    let usec: u64 = usec?.parse().ok().filter(|u| *u > 0)?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// [`watchdog_interval`] of this process.
+pub fn watchdog_interval_from_env() -> Option<Duration> {
+    let var = |name| std::env::var(name).ok();
+    watchdog_interval(var("WATCHDOG_USEC").as_deref(), var("WATCHDOG_PID").as_deref(), std::process::id())
+}
+
+#[cfg(unix)]
+fn send(socket: &OsStr, message: &str) -> Result<(), Box<dyn Error>> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let sender = UnixDatagram::unbound()?;
+    match socket.as_bytes().strip_prefix(b"@") {
+        Some(name) => send_abstract(&sender, name, message)?,
+        None => {
+            sender.send_to(message.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn send_abstract(sender: &std::os::unix::net::UnixDatagram, name: &[u8], message: &str) -> std::io::Result<()> {
+    #[cfg(target_os = "android")]
+    use std::os::android::net::SocketAddrExt;
+    #[cfg(target_os = "linux")]
+    use std::os::linux::net::SocketAddrExt;
+
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    sender.send_to_addr(message.as_bytes(), &addr)?;
+    Ok(())
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn send_abstract(_sender: &std::os::unix::net::UnixDatagram, _name: &[u8], _message: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "abstract sockets are Linux only"))
+}
+
+#[cfg(not(unix))]
+fn send(_socket: &OsStr, _message: &str) -> Result<(), Box<dyn Error>> {
+    Err("notification sockets need a Unix platform".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn test_without_socket_nothing_is_sent() {
+        let notifier = SystemdNotifier::at(None);
+        assert!(!notifier.is_enabled());
+        assert!(!notifier.notify("READY=1").unwrap());
+        assert!(!SystemdNotifier::at(Some(OsStr::new(""))).is_enabled());
+        // Unreachable sockets never fail the caller.
+        SystemdNotifier::at(Some(OsStr::new("/nonexistent/notify"))).ready();
+    }
+
+    #[test]
+    fn test_messages_of_one_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let notifier = SystemdNotifier::at(Some(path.as_os_str()));
+
+        notifier.ready();
+        notifier.watchdog();
+        notifier.status(&Ok(SyncReport { uploaded: 2, skipped: 5, ..Default::default() }));
+        notifier.stopping();
+
+        let mut buf = [0u8; 256];
+        let mut received = Vec::new();
+        for _ in 0..4 {
+            let n = receiver.recv(&mut buf).unwrap();
+            received.push(String::from_utf8_lossy(&buf[..n]).to_string());
+        }
+        assert_eq!(
+            received,
+            ["READY=1\nSTATUS=Syncing", "WATCHDOG=1", "STATUS=2 files uploaded, 5 unchanged", "STOPPING=1"]
+        );
+    }
+
+    #[test]
+    fn test_watchdog_interval_is_half_the_timeout() {
+        assert_eq!(watchdog_interval(Some("30000000"), None, 7), Some(Duration::from_secs(15)));
+        assert_eq!(watchdog_interval(Some("30000000"), Some("7"), 7), Some(Duration::from_secs(15)));
+        // Meant for another process, disabled or unset.
+        assert_eq!(watchdog_interval(Some("30000000"), Some("8"), 7), None);
+        assert_eq!(watchdog_interval(Some("0"), None, 7), None);
+        assert_eq!(watchdog_interval(None, None, 7), None);
+    }
+}
@@ -0,0 +1,80 @@
+//! Creating `target_dir` on a server that has never seen a sync.
+//!
+//! Without it, the first run relies on every upload creating its parent
+//! collections, and some servers answer requests below a collection that
+//! does not exist with 409 instead of 404. Creating the tree up front gives
+//! such a server nothing to trip over, and a clear error if it cannot be
+//! created at all.
+
+use crate::config::Config;
+use crate::output::HumanDisplay;
+use crate::webdav_client::WebDavClient;
+use log::info;
+use serde::Serialize;
+use std::error::Error;
+
+/// What [`ensure`] found or did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetDirState {
+    /// It was there already; the WebDAV root always is.
+    Existed,
+    Created,
+    /// It is not there and was left alone: `create_target_dir` is off or the
+    /// client is read-only.
+    Missing,
+}
+
+/// Make sure `target_dir` exists, creating it with its parents if
+/// `create_target_dir` allows. Fails if it is still missing after being
+/// created.
+pub async fn ensure(client: &WebDavClient, config: &Config) -> Result<TargetDirState, Box<dyn Error>> {
+    let dir = config.target_dir.trim_matches('/');
+    if dir.is_empty() || client.stat(dir).await?.is_some() {
+        return Ok(TargetDirState::Existed);
+    }
+    if !config.create_target_dir || client.is_read_only() {
+        return Ok(TargetDirState::Missing);
+    }
+    client
+        .create_dir_all(dir)
+        .await
+        .map_err(|e| format!("Cannot create target_dir '{}': {}", dir, e))?;
+    if client.stat(dir).await?.is_none() {
+        return Err(format!("target_dir '{}' is still missing after creating it", dir).into());
+    }
+    info!("Created target_dir '{}'", dir);
+    Ok(TargetDirState::Created)
+}
+
+/// Outcome of [`ensure`] for the `doctor` command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TargetDirCheck {
+    pub path: String,
+    /// `None` if it could not be created.
+    pub state: Option<TargetDirState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl TargetDirCheck {
+    pub async fn run(client: &WebDavClient, config: &Config) -> Self {
+        let path = config.target_dir.trim_matches('/').to_string();
+        match ensure(client, config).await {
+            Ok(state) => TargetDirCheck { path, state: Some(state), error: None },
+            Err(e) => TargetDirCheck { path, state: None, error: Some(e.to_string()) },
+        }
+    }
+}
+
+impl HumanDisplay for TargetDirCheck {
+    fn human(&self) -> String {
+        let state = match (self.state, &self.error) {
+            (Some(TargetDirState::Existed), _) => "exists".to_string(),
+            (Some(TargetDirState::Created), _) => "did not exist, created".to_string(),
+            (Some(TargetDirState::Missing), _) => "does not exist (create_target_dir is off or read-only)".to_string(),
+            (None, error) => format!("does not exist and cannot be created: {}", error.as_deref().unwrap_or_default()),
+        };
+        format!("target_dir '{}': {}", self.path, state)
+    }
+}
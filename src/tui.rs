@@ -0,0 +1,118 @@
+//! Terminal UI of the `browse` command, drawing a [`Browser`].
+//!
+//! The UI only lists the server; what to do with the selection is returned
+//! to the caller as a [`BrowseAction`].
+
+use crate::browse::Browser;
+use crate::units::format_byte_size;
+use crate::webdav_client::WebDavClient;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{Frame, Terminal};
+use std::error::Error;
+use std::io::stdout;
+
+const HELP: &str = "↑/↓ move  →/enter expand  ← collapse  space select  p pull  m print manifest  q quit";
+
+/// What the user chose to do when leaving the browser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BrowseAction {
+    /// Pull the selected remote paths.
+    Pull(Vec<String>),
+    /// Print the selected remote paths as a `--files-from` manifest.
+    PrintManifest(Vec<String>),
+    Quit,
+}
+
+/// Browse the server until the user picks an action.
+pub async fn run(client: &WebDavClient) -> Result<BrowseAction, Box<dyn Error>> {
+    let mut browser = Browser::open(client).await?;
+    enable_raw_mode()?;
+    execute!(stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    let result = event_loop(&mut terminal, &mut browser, client).await;
+    // Restore the terminal even if listing a collection failed.
+    disable_raw_mode()?;
+    execute!(stdout(), LeaveAlternateScreen)?;
+    result
+}
+
+async fn event_loop(
+    terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>,
+    browser: &mut Browser,
+    client: &WebDavClient,
+) -> Result<BrowseAction, Box<dyn Error>> {
+    let mut status = String::new();
+    loop {
+        terminal.draw(|frame| draw(frame, browser, &status))?;
+        let Event::Key(key) = tokio::task::block_in_place(event::read)? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        status.clear();
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => browser.move_cursor(-1),
+            KeyCode::Down | KeyCode::Char('j') => browser.move_cursor(1),
+            KeyCode::PageUp => browser.move_cursor(-20),
+            KeyCode::PageDown => browser.move_cursor(20),
+            KeyCode::Right | KeyCode::Enter | KeyCode::Char('l') => {
+                if let Err(e) = browser.expand(client).await {
+                    status = format!("Listing failed: {}", e);
+                }
+            }
+            KeyCode::Left | KeyCode::Char('h') => browser.collapse(),
+            KeyCode::Char(' ') => browser.toggle_selection(),
+            KeyCode::Char('p') | KeyCode::Char('m') if browser.selection().is_empty() => {
+                status = "Nothing selected".to_string();
+            }
+            KeyCode::Char('p') => return Ok(BrowseAction::Pull(browser.selection())),
+            KeyCode::Char('m') => return Ok(BrowseAction::PrintManifest(browser.selection())),
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(BrowseAction::Quit),
+            _ => {}
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, browser: &Browser, status: &str) {
+    let [tree, footer] = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(1), Constraint::Length(2)])
+        .split(frame.size())[..]
+    else {
+        return;
+    };
+    let items: Vec<ListItem> = browser
+        .rows()
+        .into_iter()
+        .map(|row| {
+            let marker = if row.selected { "[x]" } else { "[ ]" };
+            let icon = match (row.entry.is_dir, row.expanded) {
+                (true, true) => "▾ ",
+                (true, false) => "▸ ",
+                (false, _) => "  ",
+            };
+            let size = row.entry.size.map(format_byte_size).unwrap_or_default();
+            ListItem::new(format!("{} {}{}{}  {}", marker, "  ".repeat(row.depth), icon, row.entry.name(), size))
+        })
+        .collect();
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title("Remote"))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut state = ListState::default().with_selected(Some(browser.cursor()));
+    frame.render_stateful_widget(list, tree, &mut state);
+
+    let summary = format!(
+        "{} selected, {} listed so far. {}",
+        browser.selection().len(),
+        format_byte_size(browser.selected_size()),
+        if status.is_empty() { HELP } else { status }
+    );
+    frame.render_widget(Paragraph::new(summary), footer);
+}
@@ -0,0 +1,351 @@
+//! Human-friendly durations and byte sizes in the config.
+//!
+//! Durations are written as `30s`, `500ms`, `15m` or `1h30m`; sizes as
+//! `10MiB`, `50G` or `1.5 TB` (decimal units, `KiB`-style units are binary).
+//! Bare numbers are still accepted for fields that used to be plain integers
+//! and are read in the field's legacy unit (seconds for `duration_secs_compat`,
+//! bytes for `byte_size`). Values are written back in the human form.
+
+use serde::{Deserialize, Deserializer, Serializer};
+use std::time::Duration;
+
+const DURATION_UNITS: [(&str, u64); 6] = [
+    ("d", 86_400_000_000_000),
+    ("h", 3_600_000_000_000),
+    ("m", 60_000_000_000),
+    ("s", 1_000_000_000),
+    ("ms", 1_000_000),
+    ("us", 1_000),
+];
+
+/// Size units from largest to smallest.
+const SIZE_UNITS: [(&str, u64); 8] = [
+    ("TiB", 1 << 40),
+    ("TB", 1_000_000_000_000),
+    ("GiB", 1 << 30),
+    ("GB", 1_000_000_000),
+    ("MiB", 1 << 20),
+    ("MB", 1_000_000),
+    ("KiB", 1 << 10),
+    ("KB", 1_000),
+];
+
+/// Nanoseconds per duration unit name, accepting common spellings.
+fn duration_unit(unit: &str) -> Option<u64> {
+    let nanos = match unit.to_ascii_lowercase().as_str() {
+        "ns" => 1,
+        "us" | "µs" => 1_000,
+        "ms" => 1_000_000,
+        "s" | "sec" | "secs" | "second" | "seconds" => 1_000_000_000,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60_000_000_000,
+        "h" | "hr" | "hour" | "hours" => 3_600_000_000_000,
+        "d" | "day" | "days" => 86_400_000_000_000,
+        _ => return None,
+    };
+    Some(nanos)
+}
+
+/// Split `value` into `(number, unit)` pairs, e.g. `1h 30m` into two pairs.
+fn number_unit_pairs(value: &str) -> Option<Vec<(&str, &str)>> {
+    let mut pairs = Vec::new();
+    let mut rest = value.trim();
+    while !rest.is_empty() {
+        let number_end = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(number_end);
+        let tail = tail.trim_start();
+        let unit_end = tail.find(|c: char| c.is_ascii_digit() || c.is_whitespace()).unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_end);
+        if number.is_empty() {
+            return None;
+        }
+        pairs.push((number, unit));
+        rest = tail.trim_start();
+    }
+    Some(pairs)
+}
+
+/// Parse a duration like `30s`, `500ms`, `1h30m` or `1.5h`. A bare number is
+/// an error, since its unit depends on the field.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = |reason: &str| format!("Invalid duration '{}': {}", value, reason);
+    let pairs = number_unit_pairs(value).filter(|p| !p.is_empty()).ok_or_else(|| invalid("expected e.g. 30s"))?;
+    let mut nanos = 0f64;
+    for (number, unit) in pairs {
+        if unit.is_empty() {
+            return Err(invalid("missing unit (ms, s, m, h, d)"));
+        }
+        let factor = duration_unit(unit).ok_or_else(|| invalid(&format!("unknown unit '{}'", unit)))?;
+        let number: f64 = number.parse().map_err(|_| invalid("not a number"))?;
+        nanos += number * factor as f64;
+    }
+    if !nanos.is_finite() || nanos > u64::MAX as f64 {
+        return Err(invalid("too large"));
+    }
+    Ok(Duration::from_nanos(nanos.round() as u64))
+}
+
+/// Render a duration in the largest unit that represents it exactly, e.g. `90s`.
+pub fn format_duration(duration: Duration) -> String {
+    let nanos = duration.as_nanos();
+    if nanos == 0 {
+        return "0s".to_string();
+    }
+    for (unit, factor) in DURATION_UNITS {
+        if nanos.is_multiple_of(u128::from(factor)) {
+            return format!("{}{}", nanos / u128::from(factor), unit);
+        }
+    }
+    format!("{}ns", nanos)
+}
+
+/// Parse a size like `50G`, `1.5 TB`, `10MiB`, `500M` or `1024`. Plain and
+/// `B`-suffixed units are decimal, `iB` units binary.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let invalid = |reason: &str| format!("Invalid size '{}': {}", value, reason);
+    let trimmed = value.trim();
+    let split = trimmed
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let number: f64 = number.parse().map_err(|_| invalid("not a number"))?;
+    let unit = unit.trim();
+    let factor: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1_000,
+        "M" | "MB" => 1_000_000,
+        "G" | "GB" => 1_000_000_000,
+        "T" | "TB" => 1_000_000_000_000,
+        "KIB" => 1 << 10,
+        "MIB" => 1 << 20,
+        "GIB" => 1 << 30,
+        "TIB" => 1 << 40,
+        _ => return Err(invalid(&format!("unknown unit '{}'", unit))),
+    };
+    let bytes = number * factor as f64;
+    if !bytes.is_finite() || bytes > u64::MAX as f64 {
+        return Err(invalid("too large"));
+    }
+    Ok(bytes.round() as u64)
+}
+
+/// Render a size in the largest unit that represents it exactly, e.g. `16MiB`
+/// or `50GB`; otherwise in bytes.
+pub fn format_byte_size(bytes: u64) -> String {
+    if bytes > 0 {
+        for (unit, factor) in SIZE_UNITS {
+            if bytes.is_multiple_of(factor) {
+                return format!("{}{}", bytes / factor, unit);
+            }
+        }
+    }
+    format!("{}B", bytes)
+}
+
+/// A config value that is either a bare number in a legacy unit or a string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrText {
+    Integer(u64),
+    Float(f64),
+    Text(String),
+}
+
+fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    legacy_unit: Duration,
+) -> Result<Duration, D::Error> {
+    let legacy = |number: f64| -> Result<Duration, D::Error> {
+        Duration::try_from_secs_f64(number * legacy_unit.as_secs_f64())
+            .map_err(|_| serde::de::Error::custom(format!("Invalid duration '{}': out of range", number)))
+    };
+    match NumberOrText::deserialize(deserializer)? {
+        NumberOrText::Integer(number) => u64::try_from(legacy_unit.as_nanos() * u128::from(number))
+            .map(Duration::from_nanos)
+            .map_err(|_| serde::de::Error::custom(format!("Invalid duration '{}': too large", number))),
+        NumberOrText::Float(number) => legacy(number),
+        NumberOrText::Text(text) => match text.trim().parse::<f64>() {
+            Ok(number) => legacy(number),
+            Err(_) => parse_duration(&text).map_err(serde::de::Error::custom),
+        },
+    }
+}
+
+macro_rules! duration_compat {
+    ($name:ident, $legacy:expr, $doc:literal) => {
+        #[doc = $doc]
+        pub mod $name {
+            use serde::{Deserializer, Serializer};
+            use std::time::Duration;
+
+            pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&super::format_duration(*duration))
+            }
+
+            pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+                super::deserialize_duration(deserializer, $legacy)
+            }
+
+            /// The same for an optional field.
+            pub mod option {
+                use serde::{Deserialize, Deserializer, Serializer};
+                use std::time::Duration;
+
+                pub fn serialize<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+                    match duration {
+                        Some(duration) => super::serialize(duration, serializer),
+                        None => serializer.serialize_none(),
+                    }
+                }
+
+                pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+                    #[derive(Deserialize)]
+                    struct Wrapper(#[serde(with = "super")] Duration);
+                    Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|w| w.0))
+                }
+            }
+        }
+    };
+}
+
+duration_compat!(duration_secs_compat, Duration::from_secs(1), "Durations whose bare numbers are seconds.");
+duration_compat!(duration_millis_compat, Duration::from_millis(1), "Durations whose bare numbers are milliseconds.");
+duration_compat!(duration_days_compat, Duration::from_secs(86_400), "Durations whose bare numbers are days.");
+
+/// Byte sizes; bare numbers are bytes.
+pub mod byte_size {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_byte_size(*bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        match NumberOrText::deserialize(deserializer)? {
+            NumberOrText::Integer(bytes) => Ok(bytes),
+            NumberOrText::Float(number) => parse_size(&number.to_string()).map_err(serde::de::Error::custom),
+            NumberOrText::Text(text) => parse_size(&text).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Fields {
+        #[serde(with = "duration_secs_compat")]
+        timeout: Duration,
+        #[serde(with = "duration_millis_compat")]
+        delay: Duration,
+        #[serde(default, with = "duration_days_compat::option", skip_serializing_if = "Option::is_none")]
+        retention: Option<Duration>,
+        #[serde(with = "byte_size")]
+        size: u64,
+    }
+
+    fn load(yaml: &str) -> Result<Fields, String> {
+        serde_yaml::from_str(yaml).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(900)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("1h 30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration(" 2 days "), Ok(Duration::from_secs(172_800)));
+        assert_eq!(parse_duration("250us"), Ok(Duration::from_micros(250)));
+        assert_eq!(parse_duration("0s"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_parse_duration_errors_echo_input() {
+        for bad in ["", "30", "s", "5 parsecs", "1..5s", "-3s"] {
+            let err = parse_duration(bad).unwrap_err();
+            assert!(err.contains(&format!("'{}'", bad)), "{}", err);
+        }
+        assert!(parse_duration("5 parsecs").unwrap_err().contains("unknown unit 'parsecs'"));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(3)), "3s");
+        assert_eq!(format_duration(Duration::from_secs(90)), "90s");
+        assert_eq!(format_duration(Duration::from_secs(900)), "15m");
+        assert_eq!(format_duration(Duration::from_secs(7200)), "2h");
+        assert_eq!(format_duration(Duration::from_secs(86_400 * 30)), "30d");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1500ms");
+        assert_eq!(format_duration(Duration::from_nanos(7)), "7ns");
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("50G"), Ok(50_000_000_000));
+        assert_eq!(parse_size("1.5 TB"), Ok(1_500_000_000_000));
+        assert_eq!(parse_size("500m"), Ok(500_000_000));
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("10MiB"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("2 gib"), Ok(2 << 30));
+        assert_eq!(parse_size("7B"), Ok(7));
+        assert!(parse_size("10X").unwrap_err().contains("'10X'"));
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("").is_err());
+    }
+
+    #[test]
+    fn test_format_byte_size() {
+        assert_eq!(format_byte_size(16 * 1024 * 1024), "16MiB");
+        assert_eq!(format_byte_size(50_000_000_000), "50GB");
+        assert_eq!(format_byte_size(1024), "1KiB");
+        assert_eq!(format_byte_size(1000), "1KB");
+        assert_eq!(format_byte_size(1023), "1023B");
+        assert_eq!(format_byte_size(0), "0B");
+    }
+
+    #[test]
+    fn test_fields_accept_human_and_legacy_values() {
+        let human = load("timeout: 30s\ndelay: 1.5s\nretention: 2w\nsize: 10MiB\n");
+        assert!(human.unwrap_err().contains("unknown unit 'w'"));
+
+        let human = load("timeout: 30s\ndelay: 1.5s\nretention: 14d\nsize: 10MiB\n").unwrap();
+        let legacy = load("timeout: 30\ndelay: 1500\nretention: 14\nsize: 10485760\n").unwrap();
+        assert_eq!(human, legacy);
+        assert_eq!(
+            human,
+            Fields {
+                timeout: Duration::from_secs(30),
+                delay: Duration::from_millis(1500),
+                retention: Some(Duration::from_secs(14 * 86_400)),
+                size: 10 * 1024 * 1024,
+            }
+        );
+
+        // Quoted numbers and fractions use the legacy unit as well.
+        let quoted = load("timeout: \"30\"\ndelay: 0.5\nsize: \"1024\"\n").unwrap();
+        assert_eq!(quoted.timeout, Duration::from_secs(30));
+        assert_eq!(quoted.delay, Duration::from_micros(500));
+        assert_eq!(quoted.retention, None);
+        assert_eq!(quoted.size, 1024);
+    }
+
+    #[test]
+    fn test_fields_round_trip_in_human_form() {
+        let fields = load("timeout: 90\ndelay: 500\nretention: 7\nsize: 16777216\n").unwrap();
+        let yaml = serde_yaml::to_string(&fields).unwrap();
+        assert_eq!(yaml, "timeout: 90s\ndelay: 500ms\nretention: 7d\nsize: 16MiB\n");
+        assert_eq!(load(&yaml).unwrap(), fields);
+    }
+
+    #[test]
+    fn test_field_errors_echo_input() {
+        let err = load("timeout: soon\ndelay: 1s\nsize: 1\n").unwrap_err();
+        assert!(err.contains("'soon'"), "{}", err);
+        let err = load("timeout: 1s\ndelay: 1s\nsize: lots\n").unwrap_err();
+        assert!(err.contains("'lots'"), "{}", err);
+    }
+}
@@ -0,0 +1,183 @@
+//! Read-only drift check between the local folders, the hash store and the
+//! server, from metadata only.
+//!
+//! `verify --quick` compares local sizes and mtimes with the stamps in the
+//! store and remote fingerprints with the recorded ones. Remote files are
+//! looked up in one listing per directory, which also shows the files there
+//! that nothing tracks. Nothing is hashed, downloaded or written, so it is
+//! cheap enough for a nightly cron job.
+
+use crate::cas::Manifest;
+use crate::config::{Config, Layout};
+use crate::file_stamp::{FileStamp, StampMatch};
+use crate::filter::FilterSet;
+use crate::fingerprint::{remote_state, RemoteState};
+use crate::hash_delta::is_reserved;
+use crate::hash_store::HashStore;
+use crate::output::HumanDisplay;
+use crate::remote_listing::RemoteListings;
+use crate::sync::selected_files;
+use crate::webdav_client::WebDavClient;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+
+/// Differences found by a quick verification, as remote paths.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DriftReport {
+    /// Local files compared.
+    pub checked: usize,
+    /// Tracked files whose size or mtime differs from the recorded stamp.
+    pub local_changed: Vec<String>,
+    /// Local files without a hash in the store, i.e. not synced yet.
+    pub local_untracked: Vec<String>,
+    /// Tracked files that no longer exist on the server.
+    pub remote_missing: Vec<String>,
+    /// Tracked files whose remote size, date or ETag differs from the recorded fingerprint.
+    pub remote_changed: Vec<String>,
+    /// Remote files in the directories of the selected local files that are
+    /// neither tracked by the store nor a selected local file.
+    #[serde(default)]
+    pub remote_untracked: Vec<String>,
+    /// Tracked files without a recorded stamp, whose local changes cannot be
+    /// detected without hashing.
+    pub unstamped: usize,
+}
+
+impl DriftReport {
+    pub fn has_drift(&self) -> bool {
+        !(self.local_changed.is_empty()
+            && self.local_untracked.is_empty()
+            && self.remote_missing.is_empty()
+            && self.remote_changed.is_empty()
+            && self.remote_untracked.is_empty())
+    }
+
+    fn drifted(&self) -> usize {
+        self.local_changed.len()
+            + self.local_untracked.len()
+            + self.remote_missing.len()
+            + self.remote_changed.len()
+            + self.remote_untracked.len()
+    }
+}
+
+impl HumanDisplay for DriftReport {
+    fn human(&self) -> String {
+        let mut out = if self.has_drift() {
+            format!("{} files checked, {} drifted", self.checked, self.drifted())
+        } else {
+            format!("{} files checked, no drift", self.checked)
+        };
+        for (label, paths) in [
+            ("local changed", &self.local_changed),
+            ("local untracked", &self.local_untracked),
+            ("remote missing", &self.remote_missing),
+            ("remote changed", &self.remote_changed),
+            ("remote untracked", &self.remote_untracked),
+        ] {
+            for path in paths {
+                out.push_str(&format!("\n  {}: {}", label, path));
+            }
+        }
+        if self.unstamped > 0 {
+            out.push_str(&format!(
+                "\n  {} files have no recorded stamp; enable trust_mtime to check them locally",
+                self.unstamped
+            ));
+        }
+        out
+    }
+}
+
+/// Compare the selected local files and their remote copies with `store`.
+pub async fn quick_verify(
+    config: &Config,
+    client: &WebDavClient,
+    store: &HashStore,
+    filters: &FilterSet,
+) -> Result<DriftReport, Box<dyn Error>> {
+    let mut report = DriftReport::default();
+    // With `layout: cas`, files are tracked by the manifest and checked by their objects.
+    let manifest = match config.layout {
+        Layout::Mirror => None,
+        Layout::Cas => Some(Manifest::load(client, config).await?),
+    };
+    let mut listings = RemoteListings::for_config(config)?;
+    let mut selected = HashSet::new();
+
+    for (local_path, remote_path) in selected_files(config, filters) {
+        report.checked += 1;
+        selected.insert(remote_path.clone());
+        let tracked = match &manifest {
+            Some(manifest) => manifest.files.contains_key(&remote_path),
+            None => store.regular_hashes.contains_key(&remote_path) || store.pseudo_hashes.contains_key(&remote_path),
+        };
+        if !tracked {
+            report.local_untracked.push(remote_path);
+            continue;
+        }
+
+        let current = FileStamp::of(&std::fs::metadata(&local_path)?);
+        match (store.stamps.get(&remote_path), current) {
+            (Some(stored), Some(current)) => {
+                // Within the tolerance the file is most likely unchanged.
+                if stored.compare(&current, config.mtime_tolerance) == StampMatch::Changed {
+                    report.local_changed.push(remote_path.clone());
+                }
+            }
+            _ => report.unstamped += 1,
+        }
+
+        let state = match &manifest {
+            Some(manifest) => manifest.object_state(client, config, &remote_path).await?,
+            None => {
+                let remote = listings.stat(client, &remote_path).await?.map(|stat| stat.fingerprint());
+                remote_state(remote.as_ref(), store.fingerprints.get(&remote_path), config.last_modified_tolerance)
+            }
+        };
+        match state {
+            RemoteState::Missing => report.remote_missing.push(remote_path),
+            RemoteState::Changed => report.remote_changed.push(remote_path),
+            RemoteState::Unchanged => {}
+        }
+    }
+
+    let dirs: Vec<String> = listings.dirs().map(str::to_string).collect();
+    for dir in dirs {
+        for remote_path in listings.files(&dir)?.unwrap_or_default() {
+            let tracked = store.regular_hashes.contains_key(&remote_path) || store.pseudo_hashes.contains_key(&remote_path);
+            if !tracked && !selected.contains(&remote_path) && !is_reserved(&config.remote_hash_path, &remote_path) {
+                report.remote_untracked.push(remote_path);
+            }
+        }
+    }
+    report.remote_untracked.sort();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_human_output_lists_drift() {
+        let report = DriftReport {
+            checked: 4,
+            local_changed: vec!["a.jpg".to_string()],
+            remote_missing: vec!["b.jpg".to_string()],
+            remote_untracked: vec!["c.jpg".to_string()],
+            unstamped: 2,
+            ..Default::default()
+        };
+        assert!(report.has_drift());
+        assert_eq!(
+            report.human(),
+            "4 files checked, 3 drifted\n  local changed: a.jpg\n  remote missing: b.jpg\n  remote untracked: c.jpg\n  \
+             2 files have no recorded stamp; enable trust_mtime to check them locally"
+        );
+        let clean = DriftReport { checked: 4, ..Default::default() };
+        assert!(!clean.has_drift());
+        assert_eq!(clean.human(), "4 files checked, no drift");
+    }
+}
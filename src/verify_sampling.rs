@@ -0,0 +1,249 @@
+//! Verification of a sample of each run's uploads by downloading them.
+//!
+//! With `verify_sampling`, a sync downloads a share of the files it uploaded
+//! once all uploads are done, hashing the body as it streams in, and
+//! compares it with the local hash. A mismatch fails the run: the hashes of
+//! the mismatched files are dropped from the store, so the next sync uploads
+//! them again, and their folders are flagged (`full_verify_folders`) for the
+//! next sync to verify every synced file in them.
+
+use crate::config::Config;
+use crate::filter::FilterSet;
+use crate::hash_store::HashStore;
+use crate::spread::{fresh_seed, shuffle, SplitMix64};
+use crate::sync::selected_files;
+use crate::units::byte_size;
+use crate::webdav_client::WebDavClient;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Which uploads are sampled first.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SamplePreference {
+    /// The largest files, whatever `max_file_size` says.
+    Largest,
+    #[default]
+    Random,
+    /// The most recently modified files.
+    Newest,
+}
+
+/// The `verify_sampling` section of the config.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct SamplingConfig {
+    /// Share of the uploaded files to verify, between 0 and 1.
+    #[serde(default = "default_fraction")]
+    pub fraction: f64,
+    /// Files verified per run at least, if that many were uploaded.
+    #[serde(default = "default_min_per_run")]
+    pub min_per_run: usize,
+    #[serde(default)]
+    pub prefer: SamplePreference,
+    /// Larger files are not sampled, except with `prefer: largest`.
+    #[serde(default = "default_max_file_size", with = "byte_size")]
+    pub max_file_size: u64,
+    /// Seed of the random sample; a new one is picked and logged per run if
+    /// unset (`sync --seed` sets it).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+fn default_fraction() -> f64 {
+    0.02
+}
+
+fn default_min_per_run() -> usize {
+    5
+}
+
+fn default_max_file_size() -> u64 {
+    256 * 1024 * 1024
+}
+
+impl SamplingConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if !(0.0..=1.0).contains(&self.fraction) {
+            return Err("verify_sampling.fraction must be between 0 and 1".into());
+        }
+        Ok(())
+    }
+}
+
+/// A file uploaded in this run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadedFile {
+    pub folder_id: String,
+    pub local_path: PathBuf,
+    pub remote_path: String,
+    /// SHA-256 of the uploaded content, unless the run used pseudo hashes.
+    pub hash: Option<String>,
+    pub size: u64,
+}
+
+/// Outcome of the verification of a run.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SampleReport {
+    /// Seed of a random sample.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Remote paths of the verified files.
+    pub verified: Vec<String>,
+    /// Verified files whose remote content differs from the local one.
+    pub mismatched: Vec<String>,
+    /// Folders (by id) that had every synced file verified.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fully_verified: Vec<String>,
+}
+
+/// Number of files to verify out of `uploaded`.
+pub fn sample_size(uploaded: usize, config: &SamplingConfig) -> usize {
+    let by_fraction = (uploaded as f64 * config.fraction).ceil() as usize;
+    by_fraction.max(config.min_per_run).min(uploaded)
+}
+
+/// The files of `uploaded` to verify, in the order they are verified.
+pub fn select(uploaded: Vec<UploadedFile>, config: &SamplingConfig, seed: u64) -> Vec<UploadedFile> {
+    let count = sample_size(uploaded.len(), config);
+    let mut eligible: Vec<UploadedFile> = uploaded
+        .into_iter()
+        .filter(|file| config.prefer == SamplePreference::Largest || file.size <= config.max_file_size)
+        .collect();
+    match config.prefer {
+        SamplePreference::Largest => {
+            eligible.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.remote_path.cmp(&b.remote_path)))
+        }
+        SamplePreference::Newest => {
+            let modified = |file: &UploadedFile| std::fs::metadata(&file.local_path).and_then(|m| m.modified()).ok();
+            let mut keyed: Vec<(Option<SystemTime>, UploadedFile)> =
+                eligible.into_iter().map(|file| (modified(&file), file)).collect();
+            keyed.sort_by(|(a, x), (b, y)| b.cmp(a).then_with(|| x.remote_path.cmp(&y.remote_path)));
+            eligible = keyed.into_iter().map(|(_, file)| file).collect();
+        }
+        SamplePreference::Random => {
+            // Sorted first, so the seed alone decides the sample.
+            eligible.sort_by(|a, b| a.remote_path.cmp(&b.remote_path));
+            shuffle(&mut eligible, &mut SplitMix64::new(seed));
+        }
+    }
+    eligible.truncate(count);
+    eligible
+}
+
+/// The synced files of the folders flagged for a full verification.
+fn flagged_files(config: &Config, store: &HashStore, use_pseudo_hash: bool) -> Vec<UploadedFile> {
+    selected_files(config, &FilterSet::default())
+        .filter_map(|(local_path, remote_path)| {
+            let folder = config.folders.iter().find(|f| local_path.starts_with(&f.path))?;
+            let folder_id = config.folder_id(folder);
+            if !store.full_verify_folders.contains(&folder_id) || !store.hashes(use_pseudo_hash).contains_key(&remote_path) {
+                return None;
+            }
+            let size = std::fs::metadata(&local_path).ok()?.len();
+            let hash = if use_pseudo_hash { None } else { store.regular_hashes.get(&remote_path).cloned() };
+            Some(UploadedFile { folder_id, local_path, remote_path, hash, size })
+        })
+        .collect()
+}
+
+/// Verify a sample of `uploaded` plus every synced file of the folders
+/// flagged in `store`, updating the store as described in the module docs.
+pub async fn verify_uploads(
+    config: &Config,
+    sampling: &SamplingConfig,
+    client: &WebDavClient,
+    store: &mut HashStore,
+    use_pseudo_hash: bool,
+    uploaded: Vec<UploadedFile>,
+) -> Result<SampleReport, Box<dyn Error>> {
+    let mut report = SampleReport::default();
+    let seed = match sampling.seed {
+        Some(seed) => seed,
+        None => {
+            let seed = fresh_seed();
+            if sampling.prefer == SamplePreference::Random {
+                info!("Sampling uploads to verify with seed {} (set verify_sampling.seed or --seed to repeat it)", seed);
+            }
+            seed
+        }
+    };
+    if sampling.prefer == SamplePreference::Random {
+        report.seed = Some(seed);
+    }
+    let full = flagged_files(config, store, use_pseudo_hash);
+    let mut files = select(uploaded, sampling, seed);
+    files.retain(|file| !full.iter().any(|f| f.remote_path == file.remote_path));
+    files.extend(full);
+
+    let mut failed_folders = BTreeSet::new();
+    for file in &files {
+        let expected = match &file.hash {
+            Some(hash) => hash.clone(),
+            None => HashStore::compute_hash(&file.local_path).await?,
+        };
+        let actual = client.remote_sha256(&file.remote_path).await?;
+        report.verified.push(file.remote_path.clone());
+        if actual.as_deref() != Some(expected.as_str()) {
+            warn!("{} differs on the server from the uploaded file", file.remote_path);
+            report.mismatched.push(file.remote_path.clone());
+            failed_folders.insert(file.folder_id.clone());
+            store.forget(&file.remote_path);
+        }
+    }
+    report.fully_verified = store.full_verify_folders.difference(&failed_folders).cloned().collect();
+    store.full_verify_folders = failed_folders;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sampling(yaml: &str) -> SamplingConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn uploads(sizes: &[u64]) -> Vec<UploadedFile> {
+        sizes
+            .iter()
+            .enumerate()
+            .map(|(i, size)| UploadedFile {
+                folder_id: "a".to_string(),
+                local_path: PathBuf::from(format!("/nonexistent/{}", i)),
+                remote_path: format!("{}.bin", i),
+                hash: None,
+                size: *size,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sample_size() {
+        let config = sampling("{}");
+        assert_eq!(sample_size(0, &config), 0);
+        assert_eq!(sample_size(3, &config), 3);
+        assert_eq!(sample_size(100, &config), 5);
+        assert_eq!(sample_size(1000, &config), 20);
+        assert_eq!(sample_size(1001, &config), 21);
+        assert_eq!(sample_size(10, &sampling("fraction: 1.0\nmin_per_run: 0")), 10);
+        assert_eq!(sample_size(10, &sampling("fraction: 0.0\nmin_per_run: 0")), 0);
+    }
+
+    #[test]
+    fn test_selection_is_seeded_and_capped() {
+        let config = sampling("min_per_run: 3\nmax_file_size: 100");
+        let files = uploads(&[10, 20, 1000, 30, 40, 50]);
+        let sample = select(files.clone(), &config, 7);
+        assert_eq!(sample.len(), 3);
+        assert!(sample.iter().all(|f| f.size <= 100));
+        assert_eq!(select(files.clone(), &config, 7), sample);
+
+        let largest = sampling("min_per_run: 2\nmax_file_size: 100\nprefer: largest");
+        let sample: Vec<u64> = select(files, &largest, 7).iter().map(|f| f.size).collect();
+        assert_eq!(sample, vec![1000, 50]);
+    }
+}
@@ -0,0 +1,100 @@
+//! Per-run directory for temporary artifacts.
+//!
+//! Every temporary file of a run lives in one directory below the temp root
+//! (`temp_dir` from the config, or the system temp directory). The directory is
+//! removed when the `WorkDir` is dropped, so an aborted run leaves nothing
+//! behind. Artifacts that must survive a crash are registered as persistent and
+//! live directly in the temp root instead.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Prefix of the per-run directory names.
+const RUN_DIR_PREFIX: &str = "phone_sync-";
+
+/// Distinguishes work directories created by the same process.
+static RUN_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// A per-run temporary directory that is removed on drop.
+#[derive(Debug)]
+pub struct WorkDir {
+    root: PathBuf,
+    run_dir: PathBuf,
+}
+
+impl WorkDir {
+    /// Create a fresh run directory below `root`, or below the system temp
+    /// directory when `root` is `None`.
+    pub fn create(root: Option<&Path>) -> io::Result<Self> {
+        let root = root.map(Path::to_path_buf).unwrap_or_else(std::env::temp_dir);
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+        let run_dir = root.join(format!(
+            "{}{}-{}-{}",
+            RUN_DIR_PREFIX,
+            std::process::id(),
+            nanos,
+            RUN_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&run_dir)?;
+        Ok(Self { root, run_dir })
+    }
+
+    /// Directory of this run, removed on drop.
+    pub fn path(&self) -> &Path {
+        &self.run_dir
+    }
+
+    /// Path for a temporary file of this run.
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.run_dir.join(name)
+    }
+
+    /// Path for an artifact that must outlive the run, e.g. resume data. It is
+    /// kept in the temp root and never removed by the `WorkDir`.
+    pub fn persistent(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.run_dir) {
+            if e.kind() != io::ErrorKind::NotFound {
+                eprintln!("Failed to remove work directory {}: {}", self.run_dir.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_dir_is_removed_on_drop() {
+        let root = tempfile::tempdir().unwrap();
+        let work = WorkDir::create(Some(root.path())).unwrap();
+        let other = WorkDir::create(Some(root.path())).unwrap();
+        assert_ne!(work.path(), other.path());
+        std::fs::write(work.file("a.part"), "a").unwrap();
+        std::fs::create_dir(work.file("nested")).unwrap();
+        let run_dir = work.path().to_path_buf();
+
+        drop(work);
+        assert!(!run_dir.exists());
+        assert!(other.path().exists());
+    }
+
+    #[test]
+    fn test_persistent_artifacts_survive() {
+        let root = tempfile::tempdir().unwrap();
+        let work = WorkDir::create(Some(root.path())).unwrap();
+        let resume = work.persistent("resume.yaml");
+        std::fs::write(&resume, "offset: 3").unwrap();
+
+        drop(work);
+        assert_eq!(std::fs::read_to_string(&resume).unwrap(), "offset: 3");
+    }
+}
@@ -0,0 +1,58 @@
+use phone_sync::config::Config;
+use phone_sync::sync::sync;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config(server: &StubServer, work: &Path, extra: &str) -> Config {
+    let camera = work.join("data/DCIM/Camera");
+    fs::create_dir_all(&camera).unwrap();
+    fs::write(camera.join("a.jpg"), b"photo").unwrap();
+    TestConfig::in_work_dir(&server.url, work).yaml("username: me\npassword: secret\n").yaml(extra).build()
+}
+
+#[tokio::test]
+async fn test_credentials_are_sent_without_a_challenge() {
+    let server = StubServer::start().await;
+    server.require_auth(false);
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "force_delete_before_put: true\n");
+
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    let requests = server.requests();
+    for method in ["PROPFIND", "MKCOL", "DELETE", "PUT"] {
+        assert!(requests.iter().any(|r| r.method == method), "no {} in {:?}", method, requests);
+    }
+    assert!(requests.iter().all(|r| r.authorization.as_deref() == Some("Basic bWU6c2VjcmV0")), "{:?}", requests);
+}
+
+#[tokio::test]
+async fn test_challenge_mode_waits_for_the_server_to_ask() {
+    let server = StubServer::start().await;
+    server.require_auth(true);
+    let work = tempfile::tempdir().unwrap();
+    let challenge = "basic_auth: challenge\n";
+    let asked = config(&server, work.path(), challenge);
+
+    assert_eq!(sync(&asked).await.unwrap().uploaded, 1);
+    assert_eq!(server.file("DCIM/Camera/a.jpg").unwrap(), b"photo");
+    // Only the first request goes out without them, then is repeated.
+    let requests = server.requests();
+    assert!(requests[0].authorization.is_none() && requests[1..].iter().all(|r| r.authorization.is_some()), "{:?}", requests);
+    assert_eq!((requests[0].method.as_str(), &requests[0].path), (requests[1].method.as_str(), &requests[1].path));
+
+    // A server that never asks is never sent them.
+    let open = StubServer::start().await;
+    let other = tempfile::tempdir().unwrap();
+    assert_eq!(sync(&config(&open, other.path(), challenge)).await.unwrap().uploaded, 1);
+    assert!(open.requests().iter().all(|r| r.authorization.is_none()));
+
+    // Nor is a gateway that rejects without a challenge.
+    let gateway = StubServer::start().await;
+    gateway.require_auth(false);
+    let third = tempfile::tempdir().unwrap();
+    let err = sync(&config(&gateway, third.path(), challenge)).await.unwrap_err().to_string();
+    assert!(err.contains("Authentication failed"), "{}", err);
+}
@@ -0,0 +1,74 @@
+use phone_sync::config::Config;
+use phone_sync::hash_store::HashStore;
+use phone_sync::sync::sync;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// Ten small chat files and one large photo, each in its own folder, so the
+/// small files come first.
+fn config(webdav_url: &str, work: &Path) -> Config {
+    let (chats, photos) = (work.join("chats"), work.join("photos"));
+    fs::create_dir_all(&chats).unwrap();
+    fs::create_dir_all(&photos).unwrap();
+    for i in 0..10 {
+        fs::write(chats.join(format!("{}.txt", i)), format!("message {}", i)).unwrap();
+    }
+    fs::write(photos.join("big.jpg"), vec![7u8; 4096]).unwrap();
+    TestConfig::new(webdav_url, &work.join("hashes.yaml"))
+        .folders(&[&chats, &photos])
+        .yaml("upload_retries: 0\n")
+        .yaml("small_file_batching:\n  max_file_size: 1KiB\n  files_per_batch: 4\n  concurrency: 3\n")
+        .build()
+}
+
+#[tokio::test]
+async fn test_small_files_go_out_in_parallel_batches() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server.url, work.path());
+    // The first PUT of the run belongs to the first batch of small files.
+    server.fail_next("PUT", 1, 403);
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 10);
+    assert_eq!(report.failed.len(), 1);
+    let (failed, reason) = report.failed.iter().next().unwrap();
+    assert!(reason.contains("403"), "{}", reason);
+    assert!(server.file(failed).is_none());
+    assert!(server.file("big.jpg").is_some());
+    let store = HashStore::load(&config.hash_store_path).unwrap();
+    assert_eq!(store.regular_hashes.len(), 10);
+    assert!(!store.regular_hashes.contains_key(failed));
+
+    // Only the failed file is sent again.
+    let report = sync(&config).await.unwrap();
+    assert_eq!((report.uploaded, report.skipped), (1, 10));
+    assert!(report.failed.is_empty());
+}
+
+#[tokio::test]
+async fn test_nextcloud_batches_use_the_bulk_endpoint() {
+    let server = StubServer::start().await;
+    server.enable_bulk_upload("remote.php/dav/files/me");
+    server.reject_in_bulk("/Phone/3.txt");
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&format!("{}/remote.php/dav/files/me/Phone", server.url), work.path());
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 10);
+    assert_eq!(report.failed["3.txt"], "Insufficient storage");
+    // Three bulk requests of up to four files; only the photo (and the
+    // staged hash store) is a PUT.
+    assert_eq!(server.count("POST"), 3);
+    let puts: Vec<String> = server.requests().into_iter().filter(|r| r.method == "PUT").map(|r| r.path).collect();
+    assert!(puts.iter().all(|p| p.ends_with("big.jpg") || p.contains("/hashes.yaml.")), "{:?}", puts);
+    assert_eq!(server.file("remote.php/dav/files/me/Phone/0.txt").unwrap(), b"message 0");
+    assert!(server.file("remote.php/dav/files/me/Phone/3.txt").is_none());
+
+    let store = HashStore::load(&config.hash_store_path).unwrap();
+    assert!(store.fingerprints["0.txt"].if_match().is_some());
+    assert!(!store.regular_hashes.contains_key("3.txt"));
+}
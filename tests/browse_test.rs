@@ -0,0 +1,138 @@
+use phone_sync::browse::Browser;
+use phone_sync::config::Config;
+use phone_sync::hash_store::HashStore;
+use phone_sync::propfind::{Depth, RemoteEntry};
+use phone_sync::pull::{format_manifest, pull, read_manifest, store_for_pull};
+use phone_sync::webdav_client::WebDavClient;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokio_util::sync::CancellationToken;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+const FILES: [&str; 5] = [
+    "photos/2023/c.jpg",
+    "photos/2024/a.jpg",
+    "photos/2024/b.jpg",
+    "photos/d.jpg",
+    "photos/notes.txt",
+];
+
+/// Serve `FILES` with a remote hash store listing them.
+fn populate(server: &StubServer) {
+    let mut store = HashStore::default();
+    for path in FILES {
+        server.put_file(path, path.as_bytes());
+        store.regular_hashes.insert(path.to_string(), format!("{:x}", Sha256::digest(path.as_bytes())));
+    }
+    server.put_file("hashes.yaml", serde_yaml::to_string(&store).unwrap().as_bytes());
+}
+
+fn config(server: &StubServer, work: &Path) -> Config {
+    TestConfig::new(&server.url, &work.join("hashes.yaml"))
+        .folders(&[&work.join("restore")])
+        .path("pull_state_path", &work.join("pull_state.yaml"))
+        .yaml("target_dir: photos\nread_only: true\n")
+        .build()
+}
+
+/// Move the cursor to the row of `path`.
+fn go_to(browser: &mut Browser, path: &str) {
+    let index = browser.rows().iter().position(|r| r.entry.path == path).unwrap() as isize;
+    browser.move_cursor(index - browser.cursor() as isize);
+}
+
+fn visible(browser: &Browser) -> Vec<String> {
+    browser.rows().into_iter().map(|r| r.entry.path).collect()
+}
+
+#[tokio::test]
+async fn test_browser_lists_collections_lazily() {
+    let server = StubServer::start().await;
+    populate(&server);
+    let work = tempfile::tempdir().unwrap();
+    let client = WebDavClient::from_config(&config(&server, work.path())).unwrap();
+
+    let mut browser = Browser::open(&client).await.unwrap();
+    assert_eq!(visible(&browser), vec!["photos", "hashes.yaml"]);
+    assert_eq!(server.count("PROPFIND"), 1);
+
+    browser.expand(&client).await.unwrap();
+    assert_eq!(
+        visible(&browser),
+        vec!["photos", "photos/2023", "photos/2024", "photos/d.jpg", "photos/notes.txt", "hashes.yaml"]
+    );
+    let row = browser.rows().into_iter().find(|r| r.entry.path == "photos/d.jpg").unwrap();
+    assert_eq!((row.depth, row.entry.size), (1, Some(12)));
+
+    // Collapsing keeps the listing; expanding again needs no request.
+    browser.collapse();
+    assert_eq!(visible(&browser), vec!["photos", "hashes.yaml"]);
+    browser.expand(&client).await.unwrap();
+    assert_eq!(server.count("PROPFIND"), 2);
+
+    // Expanding a file does nothing.
+    go_to(&mut browser, "photos/d.jpg");
+    browser.expand(&client).await.unwrap();
+    assert_eq!(server.count("PROPFIND"), 2);
+    assert!(server.requests().iter().all(|r| r.method == "PROPFIND"));
+    assert_eq!(client.blocked_writes(), vec![]);
+}
+
+#[tokio::test]
+async fn test_selection_drives_pull_and_manifest() {
+    let server = StubServer::start().await;
+    populate(&server);
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path());
+    let client = WebDavClient::from_config(&config).unwrap();
+
+    let mut browser = Browser::open(&client).await.unwrap();
+    browser.expand(&client).await.unwrap();
+    go_to(&mut browser, "photos/2024");
+    browser.expand(&client).await.unwrap();
+    go_to(&mut browser, "photos");
+    browser.toggle_selection();
+    go_to(&mut browser, "photos/2024/b.jpg");
+    browser.toggle_selection();
+    go_to(&mut browser, "photos/notes.txt");
+    browser.toggle_selection();
+    let selection = browser.selection();
+    assert_eq!(selection, vec!["photos/2023", "photos/2024/a.jpg", "photos/d.jpg"]);
+
+    let manifest = work.path().join("selection.txt");
+    std::fs::write(&manifest, format!("# picked in browse\n{}", format_manifest(&selection))).unwrap();
+    assert_eq!(read_manifest(&manifest).unwrap(), selection);
+
+    let store = store_for_pull(&config, &client).await.unwrap();
+    let report = pull(&config, &client, &store, &selection, false, &CancellationToken::new()).await.unwrap();
+    assert_eq!(report.downloaded, vec!["photos/2023/c.jpg", "photos/2024/a.jpg", "photos/d.jpg"]);
+    let restore = work.path().join("restore");
+    assert_eq!(std::fs::read(restore.join("2024/a.jpg")).unwrap(), b"photos/2024/a.jpg");
+    assert!(!restore.join("2024/b.jpg").exists());
+    assert!(!restore.join("notes.txt").exists());
+}
+
+#[tokio::test]
+async fn test_list_dir_reaches_the_requested_depth() {
+    let server = StubServer::start().await;
+    populate(&server);
+    let client = WebDavClient::from_config(&config(&server, tempfile::tempdir().unwrap().path())).unwrap();
+
+    let paths = |entries: Vec<RemoteEntry>| entries.into_iter().map(|e| (e.path, e.is_dir, e.size)).collect::<Vec<_>>();
+    assert_eq!(
+        paths(client.list_dir("photos", Depth::One).await.unwrap()),
+        vec![
+            ("photos/2023".to_string(), true, None),
+            ("photos/2024".to_string(), true, None),
+            ("photos/d.jpg".to_string(), false, Some(12)),
+            ("photos/notes.txt".to_string(), false, Some(16)),
+        ]
+    );
+    let all = paths(client.list_dir("photos/", Depth::Infinity).await.unwrap());
+    assert_eq!(all.len(), 7);
+    assert_eq!(all.iter().filter(|(_, is_dir, _)| !is_dir).count(), FILES.len());
+    assert!(all.contains(&("photos/2024/b.jpg".to_string(), false, Some(17))));
+    assert!(client.list_dir("missing", Depth::One).await.is_err());
+}
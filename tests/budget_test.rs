@@ -0,0 +1,35 @@
+use phone_sync::budget::TransferUsage;
+use phone_sync::hash_store::HashStore;
+use phone_sync::sync::sync;
+use std::fs;
+use std::time::SystemTime;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+#[tokio::test]
+async fn test_stop_action_postpones_uploads_over_budget() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(data.join("a.txt"), "123456").unwrap();
+    fs::write(data.join("b.txt"), "123456").unwrap();
+    let usage_path = work.path().join("usage.yaml");
+    let config = TestConfig::in_work_dir(&server.url, work.path())
+        .yaml(&format!(
+            "transfer_budget:\n  bytes_per_month: 10\n  action: stop\n  state_path: \"{}\"\n",
+            usage_path.display()
+        ))
+        .build();
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 1);
+    assert!(report.budget_exhausted);
+    assert_eq!(server.count("PUT"), 2, "one file plus the hash store");
+    assert_eq!(TransferUsage::load(&usage_path, SystemTime::now()).unwrap().bytes, 6);
+
+    // The store was finalized and only holds the uploaded file.
+    let store: HashStore = serde_yaml::from_slice(&server.file("hashes.yaml").unwrap()).unwrap();
+    assert_eq!(store.regular_hashes.len(), 1);
+}
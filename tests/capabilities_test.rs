@@ -0,0 +1,54 @@
+use phone_sync::config::Config;
+use phone_sync::output::HumanDisplay;
+use phone_sync::sync::sync;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// A 6 MiB file, chunked in 5 MiB pieces on servers that support it.
+fn config(webdav_url: &str, work: &Path) -> Config {
+    let data = work.join("data");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(data.join("video.mp4"), vec![7u8; 6 * 1024 * 1024]).unwrap();
+    TestConfig::in_work_dir(webdav_url, work)
+        .path("upload_state_path", &work.join("upload_state.yaml"))
+        .yaml("chunk_size_mb: 5\n")
+        .build()
+}
+
+#[tokio::test]
+async fn test_capabilities_are_asked_once_per_client() {
+    let server = StubServer::start().await;
+    server.enable_locking();
+    let work = tempfile::tempdir().unwrap();
+    let client = WebDavClient::from_config(&config(&server.url, work.path())).unwrap();
+
+    let capabilities = client.capabilities().await.unwrap();
+    assert_eq!(capabilities.dav, vec!["1", "2"]);
+    assert!(capabilities.supports_locking());
+    assert!(client.clone().supports_locking().await.unwrap());
+    assert_eq!(server.count("OPTIONS"), 1);
+    let human = capabilities.human();
+    assert!(human.contains("locking (lock_hash_store): yes"), "{}", human);
+}
+
+#[tokio::test]
+async fn test_server_without_move_gets_single_puts_and_clear_errors() {
+    let server = StubServer::start().await;
+    server.enable_chunking();
+    server.disallow("MOVE");
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&format!("{}/remote.php/dav/files/me", server.url), work.path());
+
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    assert_eq!(server.count("MOVE"), 0);
+    assert_eq!(server.file("remote.php/dav/files/me/video.mp4").unwrap().len(), 6 * 1024 * 1024);
+
+    let client = WebDavClient::from_config(&config).unwrap();
+    let err = client.move_path("video.mp4", "old/video.mp4", false).await.unwrap_err().to_string();
+    assert!(err.contains("does not allow MOVE"), "{}", err);
+    assert_eq!(server.count("MOVE"), 0);
+}
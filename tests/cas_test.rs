@@ -0,0 +1,134 @@
+use phone_sync::cas::{gc, object_path, Manifest};
+use phone_sync::config::Config;
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store::HashStore;
+use phone_sync::pull::{pull, store_for_pull};
+use phone_sync::sync::sync;
+use phone_sync::verify::quick_verify;
+use phone_sync::webdav_client::WebDavClient;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use tokio_util::sync::CancellationToken;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// A cas config syncing `work/<folder>` into target_dir `backup`.
+fn config(server: &StubServer, work: &Path, folder: &str) -> Config {
+    TestConfig::new(&server.url, &work.join(format!("{}.yaml", folder)))
+        .folders(&[&work.join(folder)])
+        .path("pull_state_path", &work.join(format!("{}-pull.yaml", folder)))
+        .yaml("target_dir: backup\nlayout: cas\nmirror_deletions: true\n")
+        .build()
+}
+
+fn object(config: &Config, content: &[u8]) -> String {
+    object_path(config, &format!("{:x}", Sha256::digest(content)))
+}
+
+async fn manifest(config: &Config) -> Manifest {
+    Manifest::load(&WebDavClient::from_config(config).unwrap(), config).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_identical_files_share_one_object() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(data.join("DCIM")).unwrap();
+    fs::write(data.join("DCIM/a.jpg"), "same").unwrap();
+    fs::write(data.join("copy.jpg"), "same").unwrap();
+    fs::write(data.join("other.jpg"), "other").unwrap();
+    let config = config(&server, work.path(), "data");
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 2);
+    assert_eq!(report.deduplicated.len(), 1);
+    assert_eq!(server.count("PUT"), 2 + 2, "two objects, the manifest and the hash store");
+    let mut objects: Vec<String> = server.paths().into_iter().filter(|p| p.starts_with("backup/")).collect();
+    let mut expected = vec![object(&config, b"other"), object(&config, b"same")];
+    objects.sort();
+    expected.sort();
+    assert_eq!(objects, expected);
+    let manifest = manifest(&config).await;
+    assert_eq!(manifest.files["backup/DCIM/a.jpg"], manifest.files["backup/copy.jpg"]);
+    assert!(!server.paths().contains(&"backup/copy.jpg".to_string()));
+
+    let client = WebDavClient::from_config(&config).unwrap();
+    let drift = quick_verify(&config, &client, &HashStore::load(&config.hash_store_path).unwrap(), &FilterSet::default())
+        .await
+        .unwrap();
+    assert!(!drift.has_drift(), "{:?}", drift);
+    assert_eq!(sync(&config).await.unwrap().skipped, 3);
+}
+
+#[tokio::test]
+async fn test_rename_only_changes_the_manifest() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(data.join("old.jpg"), "photo").unwrap();
+    let config = config(&server, work.path(), "data");
+    sync(&config).await.unwrap();
+
+    fs::rename(data.join("old.jpg"), data.join("new.jpg")).unwrap();
+    server.clear_requests();
+    let report = sync(&config).await.unwrap();
+
+    assert_eq!(report.deduplicated, vec!["backup/new.jpg"]);
+    assert_eq!(report.deleted, vec!["backup/old.jpg"]);
+    let object_traffic: Vec<_> = server.requests().into_iter().filter(|r| r.path.starts_with("backup/")).collect();
+    assert!(object_traffic.is_empty(), "{:?}", object_traffic.iter().map(|r| (&r.method, &r.path)).collect::<Vec<_>>());
+    let manifest = manifest(&config).await;
+    assert_eq!(manifest.files.keys().collect::<Vec<_>>(), vec!["backup/new.jpg"]);
+}
+
+#[tokio::test]
+async fn test_pull_reconstructs_files_from_objects() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(data.join("DCIM")).unwrap();
+    fs::write(data.join("DCIM/a.jpg"), "same").unwrap();
+    fs::write(data.join("b.jpg"), "same").unwrap();
+    fs::write(data.join("c.txt"), "notes").unwrap();
+    sync(&config(&server, work.path(), "data")).await.unwrap();
+
+    let restore = config(&server, work.path(), "restore");
+    let client = WebDavClient::from_config(&restore).unwrap();
+    let store = store_for_pull(&restore, &client).await.unwrap();
+    let report = pull(&restore, &client, &store, &[], false, &CancellationToken::new()).await.unwrap();
+
+    assert_eq!(report.downloaded.len(), 3);
+    assert!(report.failed.is_empty() && report.missing.is_empty());
+    let restored = work.path().join("restore");
+    assert_eq!(fs::read(restored.join("DCIM/a.jpg")).unwrap(), b"same");
+    assert_eq!(fs::read(restored.join("b.jpg")).unwrap(), b"same");
+    assert_eq!(fs::read(restored.join("c.txt")).unwrap(), b"notes");
+}
+
+#[tokio::test]
+async fn test_gc_removes_unreferenced_objects() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(data.join("kept.jpg"), "kept").unwrap();
+    fs::write(data.join("dropped.jpg"), "dropped").unwrap();
+    let config = config(&server, work.path(), "data");
+    sync(&config).await.unwrap();
+
+    fs::remove_file(data.join("dropped.jpg")).unwrap();
+    assert_eq!(sync(&config).await.unwrap().deleted, vec!["backup/dropped.jpg"]);
+    // Deleting a file leaves its object until the collection.
+    assert!(server.file(&object(&config, b"dropped")).is_some());
+
+    let client = WebDavClient::from_config(&config).unwrap();
+    let report = gc(&config, &client).await.unwrap();
+    assert_eq!(report.unreferenced, vec![object(&config, b"dropped")]);
+    assert_eq!(report.referenced, 1);
+    assert!(server.file(&object(&config, b"dropped")).is_none());
+    assert!(server.file(&object(&config, b"kept")).is_some());
+}
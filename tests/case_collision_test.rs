@@ -0,0 +1,149 @@
+use phone_sync::config::{CaseCollisionPolicy, Config};
+use phone_sync::hash_store::HashStore;
+use phone_sync::sync::sync;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn write(path: &Path, content: &str, age_secs: u64) {
+    fs::write(path, content).unwrap();
+    let file = fs::File::options().write(true).open(path).unwrap();
+    file.set_modified(SystemTime::now() - Duration::from_secs(age_secs)).unwrap();
+}
+
+/// `docs/README.md` and `docs/Readme.md`, the latter older and larger.
+fn config(server: &StubServer, work: &Path, policy: &str) -> Config {
+    let data = work.join("data");
+    fs::create_dir_all(data.join("docs")).unwrap();
+    write(&data.join("docs/README.md"), "new", 100);
+    write(&data.join("docs/Readme.md"), "older but longer", 1000);
+    fs::write(data.join("other.txt"), "unrelated").unwrap();
+    TestConfig::in_work_dir(&server.url, work).yaml(&format!("case_collision_policy: {}\n", policy)).build()
+}
+
+/// Paths of the content PUTs since the last call.
+fn puts(server: &StubServer) -> Vec<String> {
+    let puts = server
+        .requests()
+        .into_iter()
+        .filter(|r| r.method == "PUT" && !r.path.starts_with("hashes.yaml"))
+        .map(|r| r.path)
+        .collect();
+    server.clear_requests();
+    puts
+}
+
+#[tokio::test]
+async fn test_newest_wins_is_stable_and_follows_edits() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "newest_wins");
+
+    let report = sync(&config).await.unwrap();
+    let collision = &report.case_collisions[0];
+    assert_eq!(collision.key, "docs/README.md");
+    assert_eq!(collision.winner.as_deref(), Some("docs/README.md"));
+    assert_eq!(collision.losers, vec!["docs/Readme.md"]);
+    assert_eq!(collision.policy, CaseCollisionPolicy::NewestWins);
+    let mut first = puts(&server);
+    first.sort();
+    assert_eq!(first, vec!["docs/README.md", "other.txt"]);
+    assert_eq!(server.file("docs/README.md").unwrap(), b"new");
+
+    for _ in 0..3 {
+        let report = sync(&config).await.unwrap();
+        assert_eq!(report.uploaded, 0);
+        assert_eq!(report.case_collisions[0].winner.as_deref(), Some("docs/README.md"));
+        assert!(puts(&server).is_empty());
+    }
+
+    // Editing the other file makes it the newest; it replaces the remote file once.
+    write(&work.path().join("data/docs/Readme.md"), "edited", 0);
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.case_collisions[0].key, "docs/README.md");
+    assert_eq!(report.case_collisions[0].winner.as_deref(), Some("docs/Readme.md"));
+    assert_eq!(puts(&server), vec!["docs/README.md"]);
+    assert_eq!(server.file("docs/README.md").unwrap(), b"edited");
+    let store = HashStore::load(&config.hash_store_path).unwrap();
+    assert_eq!(store.case_winners["docs/README.md"], "docs/Readme.md");
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 0);
+    assert!(puts(&server).is_empty());
+}
+
+#[tokio::test]
+async fn test_largest_wins() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "largest_wins");
+
+    for run in 0..3 {
+        let report = sync(&config).await.unwrap();
+        assert_eq!(report.case_collisions[0].winner.as_deref(), Some("docs/Readme.md"));
+        assert_eq!(report.uploaded, if run == 0 { 2 } else { 0 });
+    }
+    assert_eq!(server.file("docs/README.md").unwrap(), b"older but longer");
+    assert_eq!(server.file("docs/Readme.md"), None);
+}
+
+#[tokio::test]
+async fn test_ties_keep_the_recorded_winner() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "largest_wins");
+    write(&work.path().join("data/docs/README.md"), "same size", 100);
+    write(&work.path().join("data/docs/Readme.md"), "SAME SIZE", 100);
+    let mut store = HashStore::default();
+    store.case_winners.insert("docs/README.md".to_string(), "docs/Readme.md".to_string());
+    store.save(&config.hash_store_path).unwrap();
+    let mut config = config;
+    config.remote_hash_store = phone_sync::config::RemoteHashStore::Disabled;
+
+    for _ in 0..2 {
+        let report = sync(&config).await.unwrap();
+        assert_eq!(report.case_collisions[0].winner.as_deref(), Some("docs/Readme.md"));
+    }
+    assert_eq!(server.file("docs/README.md").unwrap(), b"SAME SIZE");
+}
+
+#[tokio::test]
+async fn test_list_only_syncs_none_of_the_group() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "list_only");
+
+    for _ in 0..2 {
+        let report = sync(&config).await.unwrap();
+        let collision = &report.case_collisions[0];
+        assert_eq!(collision.winner, None);
+        assert_eq!(collision.losers, vec!["docs/README.md", "docs/Readme.md"]);
+        assert!(report.human_contains("case collision: docs/README.md from none"));
+    }
+    assert_eq!(server.paths(), vec!["hashes.yaml", "other.txt"]);
+}
+
+#[tokio::test]
+async fn test_error_policy_aborts_before_uploading() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "error");
+
+    let err = sync(&config).await.unwrap_err().to_string();
+    assert!(err.contains("docs/README.md, docs/Readme.md"), "{}", err);
+    assert!(puts(&server).is_empty());
+}
+
+trait HumanContains {
+    fn human_contains(&self, text: &str) -> bool;
+}
+
+impl HumanContains for phone_sync::report::SyncReport {
+    fn human_contains(&self, text: &str) -> bool {
+        use phone_sync::output::HumanDisplay;
+        self.human().contains(text)
+    }
+}
@@ -0,0 +1,74 @@
+use phone_sync::archive::LocalArchive;
+use phone_sync::fingerprint::RemoteFingerprint;
+use phone_sync::webdav_client::{VerifiedDownload, WebDavClient};
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+mod stub_server;
+use stub_server::StubServer;
+
+async fn setup() -> (StubServer, WebDavClient, tempfile::TempDir) {
+    let server = StubServer::start().await;
+    server.put_file("a.jpg", b"v1");
+    server.set_header("a.jpg", "ETag", "\"v1\"");
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    (server, client, tempfile::tempdir().unwrap())
+}
+
+#[tokio::test]
+async fn test_download_refetches_file_changed_after_listing() {
+    let (server, client, dir) = setup().await;
+    let listed = client.stat("a.jpg").await.unwrap().unwrap().fingerprint();
+    assert_eq!(listed, RemoteFingerprint::Etag("\"v1\"".to_string()));
+    server.change_on_get("a.jpg", &[(b"version 2", "\"v2\"")]);
+
+    let local = dir.path().join("a.jpg");
+    let outcome = client.download_verified("a.jpg", &local, &listed).await.unwrap();
+
+    assert_eq!(outcome, VerifiedDownload::Downloaded(RemoteFingerprint::Etag("\"v2\"".to_string())));
+    assert_eq!(fs::read(&local).unwrap(), b"version 2");
+    assert_eq!(server.count("GET"), 2);
+}
+
+#[tokio::test]
+async fn test_download_gives_up_on_unstable_file_without_touching_local() {
+    let (server, client, dir) = setup().await;
+    let listed = client.stat("a.jpg").await.unwrap().unwrap().fingerprint();
+    server.change_on_get("a.jpg", &[(b"version 2", "\"v2\""), (b"version 3", "\"v3\"")]);
+    let local = dir.path().join("a.jpg");
+    fs::write(&local, "mine").unwrap();
+
+    let outcome = client.download_verified("a.jpg", &local, &listed).await.unwrap();
+
+    assert_eq!(outcome, VerifiedDownload::Unstable);
+    assert_eq!(fs::read(&local).unwrap(), b"mine");
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1, "no .part file left behind");
+    assert_eq!(server.count("GET"), 2);
+}
+
+#[tokio::test]
+async fn test_download_of_unchanged_file_fetches_once() {
+    let (server, client, dir) = setup().await;
+    let listed = client.stat("a.jpg").await.unwrap().unwrap().fingerprint();
+    let local = dir.path().join("a.jpg");
+
+    let outcome = client.download_verified("a.jpg", &local, &listed).await.unwrap();
+
+    assert_eq!(outcome, VerifiedDownload::Downloaded(listed));
+    assert_eq!(fs::read(&local).unwrap(), b"v1");
+    assert_eq!(server.count("GET"), 1);
+    assert_eq!(
+        client.download_verified("gone.jpg", &local, &RemoteFingerprint::None).await.unwrap(),
+        VerifiedDownload::Missing
+    );
+}
+
+#[tokio::test]
+async fn test_archive_refuses_unstable_file() {
+    let (server, client, dir) = setup().await;
+    server.change_on_get("a.jpg", &[(b"version 2", "\"v2\""), (b"version 3", "\"v3\"")]);
+    let archive = LocalArchive::new(dir.path(), None);
+
+    let err = archive.archive(&client, "a.jpg", SystemTime::now()).await.unwrap_err();
+    assert!(err.to_string().contains("kept changing"), "{}", err);
+}
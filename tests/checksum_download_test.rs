@@ -0,0 +1,37 @@
+use phone_sync::checksum::{Checksum, ChecksumAlgorithm};
+use phone_sync::webdav_client::WebDavClient;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::StubServer;
+
+#[tokio::test]
+async fn test_download_verifies_server_checksum() {
+    let server = StubServer::start().await;
+    let content = b"photo bytes";
+    server.put_file("good.jpg", content);
+    let checksum = Checksum::compute(ChecksumAlgorithm::Sha1, content);
+    server.set_header("good.jpg", "OC-Checksum", &format!("{} ADLER32:1a2b3c4d", checksum));
+
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let local = dir.path().join("good.jpg");
+    client.download_file("good.jpg", &local).await.expect("download");
+    assert_eq!(std::fs::read(&local).unwrap(), content);
+}
+
+#[tokio::test]
+async fn test_download_rejects_checksum_mismatch() {
+    let server = StubServer::start().await;
+    server.put_file("bad.jpg", b"truncated");
+    let expected = Checksum::compute(ChecksumAlgorithm::Sha256, b"the real content");
+    server.set_header("bad.jpg", "OC-Checksum", &expected.to_string());
+
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let local = dir.path().join("bad.jpg");
+    let err = client.download_file("bad.jpg", &local).await.unwrap_err().to_string();
+    assert!(err.contains("Corrupted download"), "{}", err);
+    assert!(err.contains(&expected.to_string()), "{}", err);
+    assert!(!local.exists(), "corrupted content must not be written");
+}
@@ -0,0 +1,69 @@
+use phone_sync::checksum::{Checksum, ChecksumAlgorithm};
+use phone_sync::config::Config;
+use phone_sync::sync::{sync, sync_with_progress};
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config(server: &StubServer, work: &Path, extra: &str) -> Config {
+    let data = work.join("data");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(data.join("a.jpg"), b"photo bytes").unwrap();
+    TestConfig::in_work_dir(&server.url, work).yaml("remote_hash_store: disabled\n").yaml(extra).build()
+}
+
+fn put_checksums(server: &StubServer) -> Vec<Option<String>> {
+    server.requests().into_iter().filter(|r| r.method == "PUT").map(|r| r.checksum).collect()
+}
+
+#[tokio::test]
+async fn test_uploads_send_a_checksum_the_server_reports_back() {
+    let server = StubServer::start().await;
+    server.verify_checksums();
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "");
+
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    let expected = Checksum::compute(ChecksumAlgorithm::Sha256, b"photo bytes");
+    assert_eq!(put_checksums(&server), vec![Some(expected.to_string())]);
+
+    let client = WebDavClient::from_config(&config).unwrap();
+    assert_eq!(client.remote_checksum("a.jpg").await.unwrap(), Some(expected));
+    assert_eq!(client.remote_checksum("missing.jpg").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_no_checksum_when_disabled_or_only_pseudo_hashed() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    sync(&config(&server, work.path(), "send_checksum: false\n")).await.unwrap();
+
+    let other = tempfile::tempdir().unwrap();
+    sync_with_progress(&config(&server, other.path(), ""), false, true).await.unwrap();
+    assert_eq!(put_checksums(&server), vec![None, None]);
+}
+
+#[tokio::test]
+async fn test_checksum_mismatch_is_sent_again_once() {
+    let server = StubServer::start().await;
+    server.verify_checksums();
+    let work = tempfile::tempdir().unwrap();
+    let message = "The computed checksum does not match the one received from the client.";
+    server.fail_next_with_body("PUT", 1, 400, message);
+    let config = config(&server, work.path(), "");
+
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    assert_eq!(server.count("PUT"), 2);
+    assert_eq!(server.file("a.jpg").unwrap(), b"photo bytes");
+
+    // Corrupted on every attempt: give up after the second.
+    server.clear_requests();
+    server.corrupt_uploads(true);
+    fs::write(work.path().join("data/a.jpg"), b"new photo bytes").unwrap();
+    let err = sync(&config).await.unwrap_err().to_string();
+    assert!(err.contains("checksum mismatch"), "{}", err);
+    assert_eq!(server.count("PUT"), 2);
+}
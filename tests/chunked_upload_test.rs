@@ -0,0 +1,98 @@
+use phone_sync::chunked_upload::UploadState;
+use phone_sync::config::Config;
+use phone_sync::sync::sync;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+const MIB: usize = 1024 * 1024;
+
+/// An 11 MiB video, which goes out as chunks of 5, 5 and 1 MiB.
+fn config(webdav_url: &str, work: &Path) -> Config {
+    let data = work.join("data");
+    fs::create_dir_all(&data).unwrap();
+    let video: Vec<u8> = (0..11 * MIB).map(|i| (i % 251) as u8).collect();
+    fs::write(data.join("video.mp4"), video).unwrap();
+    TestConfig::in_work_dir(webdav_url, work)
+        .path("upload_state_path", &work.join("upload_state.yaml"))
+        .yaml("upload_retries: 0\nchunk_size_mb: 5\n")
+        .build()
+}
+
+fn chunk_puts(server: &StubServer) -> Vec<String> {
+    server
+        .requests()
+        .into_iter()
+        .filter(|r| r.method == "PUT" && r.path.starts_with("remote.php/dav/uploads/me/"))
+        .map(|r| r.path.rsplit('/').next().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_large_file_is_uploaded_in_chunks_and_assembled() {
+    let server = StubServer::start().await;
+    server.enable_chunking();
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&format!("{}/remote.php/dav/files/me", server.url), work.path());
+
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    assert_eq!(chunk_puts(&server), vec!["00001", "00002", "00003"]);
+    let assemblies = server.requests().into_iter().filter(|r| r.method == "MOVE" && r.path.ends_with("/.file")).count();
+    assert_eq!(assemblies, 1);
+    let local = fs::read(work.path().join("data/video.mp4")).unwrap();
+    assert!(server.file("remote.php/dav/files/me/video.mp4").unwrap() == local);
+    assert!(!server.paths().iter().any(|p| p.starts_with("remote.php/dav/uploads/")), "{:?}", server.paths());
+    assert!(!work.path().join("upload_state.yaml").exists());
+}
+
+#[tokio::test]
+async fn test_interrupted_chunked_upload_resumes_after_last_chunk() {
+    let server = StubServer::start().await;
+    server.enable_chunking();
+    server.fail_path_once("/00002", 507);
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&format!("{}/remote.php/dav/files/me", server.url), work.path());
+
+    let err = sync(&config).await.err().unwrap().to_string();
+    assert!(err.contains("Failed to upload chunk 2 of 3 of 'video.mp4': 507 Insufficient Storage"), "{}", err);
+    assert!(!err.contains("Storage "), "{}", err);
+    let state = UploadState::load(&config.upload_state_path).unwrap();
+    assert!(state.uploads.contains_key("video.mp4"));
+    assert!(server.file("remote.php/dav/files/me/video.mp4").is_none());
+
+    server.clear_requests();
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    // The first chunk was not sent again.
+    assert_eq!(chunk_puts(&server), vec!["00002", "00003"]);
+    let local = fs::read(work.path().join("data/video.mp4")).unwrap();
+    assert!(server.file("remote.php/dav/files/me/video.mp4").unwrap() == local);
+    assert!(!work.path().join("upload_state.yaml").exists());
+}
+
+#[tokio::test]
+async fn test_servers_without_chunking_get_one_put() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&format!("{}/remote.php/dav/files/me", server.url), work.path());
+
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    assert!(chunk_puts(&server).is_empty());
+    assert_eq!(server.file("remote.php/dav/files/me/video.mp4").unwrap().len(), 11 * MIB);
+}
+
+#[tokio::test]
+async fn test_chunks_follow_redirects_within_the_server() {
+    let server = StubServer::start().await;
+    server.enable_chunking();
+    server.redirect("remote.php/dav/uploads/me", "/remote.php/dav/uploads/moved");
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&format!("{}/remote.php/dav/files/me", server.url), work.path());
+
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    let moved = server.requests().into_iter().filter(|r| r.method == "PUT" && r.path.starts_with("remote.php/dav/uploads/moved/")).count();
+    assert_eq!(moved, 3);
+    let local = fs::read(work.path().join("data/video.mp4")).unwrap();
+    assert!(server.file("remote.php/dav/files/me/video.mp4").unwrap() == local);
+}
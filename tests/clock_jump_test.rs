@@ -0,0 +1,88 @@
+use phone_sync::budget::TransferUsage;
+use phone_sync::clock::ManualClock;
+use phone_sync::config::Config;
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::progress::SyncObserver;
+use phone_sync::report::SyncReport;
+use phone_sync::sync::{sync, sync_with_clock};
+use phone_sync::webdav_client::WebDavClient;
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+// 2024-02-29T12:00:00Z
+const LEAP_DAY: u64 = 1_709_208_000;
+
+/// Sets the wall clock forward by a day when the first file starts, like
+/// NTP correcting a device without a real-time clock.
+struct JumpOnFirstFile {
+    clock: Arc<ManualClock>,
+    jumped: AtomicBool,
+}
+
+impl SyncObserver for JumpOnFirstFile {
+    fn file_start(&self, _path: &str, _size: u64) {
+        if !self.jumped.swap(true, Ordering::Relaxed) {
+            self.clock.jump(86_400);
+        }
+    }
+}
+
+fn set_mtime(path: &Path, mtime: SystemTime) {
+    File::options().write(true).open(path).unwrap().set_modified(mtime).unwrap();
+}
+
+async fn sync_jumping(config: &Config) -> SyncReport {
+    let clock = Arc::new(ManualClock::new(UNIX_EPOCH + Duration::from_secs(LEAP_DAY)));
+    let observer = Arc::new(JumpOnFirstFile { clock: clock.clone(), jumped: AtomicBool::new(false) });
+    let client = WebDavClient::from_config(config).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), config).await.unwrap();
+    let report = sync_with_clock(config, &client, &mut guard, observer, false, &FilterSet::default(), clock.as_ref())
+        .await
+        .unwrap();
+    guard.finalize().await.unwrap();
+    report
+}
+
+#[tokio::test]
+async fn test_clock_jump_suspends_trust_mtime_and_keeps_budget_period() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    let mtime = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    for name in ["a.txt", "b.txt"] {
+        fs::write(data.join(name), "one").unwrap();
+        set_mtime(&data.join(name), mtime);
+    }
+    let usage_path = work.path().join("usage.yaml");
+    let config = TestConfig::in_work_dir(&server.url, work.path())
+        .yaml(&format!(
+            "trust_mtime: true\ntransfer_budget:\n  bytes_per_month: 1G\n  state_path: \"{}\"\n",
+            usage_path.display()
+        ))
+        .build();
+    assert_eq!(sync(&config).await.unwrap().clock_jump_secs, None);
+
+    // Same size and mtime: only rehashing notices the change.
+    for name in ["a.txt", "b.txt"] {
+        fs::write(data.join(name), "two").unwrap();
+        set_mtime(&data.join(name), mtime);
+    }
+    fs::remove_file(&usage_path).unwrap();
+    let report = sync_jumping(&config).await;
+    assert_eq!(report.clock_jump_secs, Some(86_400));
+    assert_eq!(report.uploaded, 2);
+    // Elapsed time comes from the monotonic clock, which did not move.
+    assert!(report.profile.total_micros < 60_000_000);
+
+    // Both uploads count towards the month the run started in.
+    let usage: TransferUsage = serde_yaml::from_str(&fs::read_to_string(&usage_path).unwrap()).unwrap();
+    assert_eq!((usage.period.as_str(), usage.bytes), ("2024-02", 6));
+}
@@ -0,0 +1,47 @@
+use phone_sync::config::{CollisionPolicy, Config};
+use phone_sync::sync::sync;
+use std::fs;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// Two folders that both contain `dup.txt` and sync into the same target.
+fn colliding_config(url: &str, work: &std::path::Path) -> Config {
+    for (folder, content) in [("a", "from a"), ("b", "from b")] {
+        fs::create_dir_all(work.join(folder)).unwrap();
+        fs::write(work.join(folder).join("dup.txt"), content).unwrap();
+    }
+    fs::write(work.join("b").join("only_b.txt"), "unique").unwrap();
+    TestConfig::new(url, &work.join("hashes.yaml")).folders(&[&work.join("a"), &work.join("b")]).build()
+}
+
+#[tokio::test]
+async fn test_colliding_entries_upload_once() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = colliding_config(&server.url, work.path());
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.collisions, vec!["dup.txt".to_string()]);
+    assert_eq!(report.uploaded, 2);
+    let dup_puts = server
+        .requests()
+        .iter()
+        .filter(|r| r.method == "PUT" && r.path == "dup.txt")
+        .count();
+    assert_eq!(dup_puts, 1);
+    assert_eq!(server.file("dup.txt").unwrap(), b"from a");
+    assert_eq!(server.file("only_b.txt").unwrap(), b"unique");
+}
+
+#[tokio::test]
+async fn test_collision_policy_error_aborts() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let mut config = colliding_config(&server.url, work.path());
+    config.collision_policy = CollisionPolicy::Error;
+
+    let err = sync(&config).await.unwrap_err().to_string();
+    assert!(err.contains("collision"), "{}", err);
+    assert!(err.contains("dup.txt"), "{}", err);
+}
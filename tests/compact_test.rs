@@ -0,0 +1,93 @@
+use phone_sync::compact::{compact, Compaction};
+use phone_sync::config::Config;
+use phone_sync::hash_store::HashStore;
+use phone_sync::sync::sync;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config(server: &StubServer, work: &Path, extra: &str) -> Config {
+    TestConfig::in_work_dir(&server.url, work).yaml("target_dir: phone\n").yaml(extra).build()
+}
+
+/// Two local files and a store with three times as many entries below
+/// `phone`, plus the entries of another device below `tablet`.
+fn bloated_store(work: &Path) -> HashStore {
+    let data = work.join("data");
+    fs::create_dir_all(&data).unwrap();
+    let mut store = HashStore::default();
+    for name in ["a.jpg", "b.jpg"] {
+        fs::write(data.join(name), name).unwrap();
+        store.regular_hashes.insert(format!("phone/{}", name), format!("{:x}", Sha256::digest(name)));
+    }
+    for key in ["phone/renamed/a.jpg", "phone/old/1.jpg", "phone/old/2.jpg"] {
+        store.regular_hashes.insert(key.to_string(), "stale".to_string());
+    }
+    store.pseudo_hashes.insert("phone/a.jpg".to_string(), "pseudo".to_string());
+    store.pseudo_hashes.insert("phone/old/1.jpg".to_string(), "pseudo".to_string());
+    store.pseudo_hashes.insert("phone/gone.jpg".to_string(), "pseudo".to_string());
+    for key in ["tablet/a.jpg", "tablet/x.jpg", "phones/y.jpg"] {
+        store.regular_hashes.insert(key.to_string(), "other".to_string());
+        store.pseudo_hashes.insert(key.to_string(), "other".to_string());
+    }
+    store
+}
+
+#[tokio::test]
+async fn test_auto_compact_removes_stale_entries_of_this_scope_only() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let store = bloated_store(work.path());
+    server.put_file("hashes.yaml", serde_yaml::to_string(&store).unwrap().as_bytes());
+    let config = config(&server, work.path(), "auto_compact: true\n");
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.compaction, Some(Compaction { missing_local: 5, superseded_pseudo: 1 }));
+    assert!(phone_sync::output::HumanDisplay::human(&report)
+        .contains("hash store compacted: 5 entries of missing files, 1 superseded pseudo hashes removed"));
+
+    let remote: HashStore = serde_yaml::from_slice(&server.file("hashes.yaml").unwrap()).unwrap();
+    let regular: Vec<&str> = remote.regular_hashes.keys().map(String::as_str).collect();
+    assert_eq!(regular, vec!["phone/a.jpg", "phone/b.jpg", "phones/y.jpg", "tablet/a.jpg", "tablet/x.jpg"]);
+    let pseudo: Vec<&str> = remote.pseudo_hashes.keys().map(String::as_str).collect();
+    assert_eq!(pseudo, vec!["phones/y.jpg", "tablet/a.jpg", "tablet/x.jpg"]);
+}
+
+#[tokio::test]
+async fn test_growth_below_threshold_or_without_auto_compact_keeps_the_store() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let store = bloated_store(work.path());
+    server.put_file("hashes.yaml", serde_yaml::to_string(&store).unwrap().as_bytes());
+
+    // 8 entries for 2 files stays below a ratio of 5.
+    let report = sync(&config(&server, work.path(), "auto_compact: true\ncompact_ratio: 5\n")).await.unwrap();
+    assert_eq!(report.compaction, None);
+    // Above the default ratio, the run only suggests pruning.
+    let report = sync(&config(&server, work.path(), "")).await.unwrap();
+    assert_eq!(report.compaction, None);
+
+    let remote: HashStore = serde_yaml::from_slice(&server.file("hashes.yaml").unwrap()).unwrap();
+    assert_eq!(remote.regular_hashes.len(), store.regular_hashes.len());
+    assert_eq!(remote.pseudo_hashes.len(), store.pseudo_hashes.len());
+}
+
+#[tokio::test]
+async fn test_prune_keeps_pseudo_hashes_for_pseudo_runs_and_needs_every_folder() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let mut store = bloated_store(work.path());
+    let config = config(&server, work.path(), "");
+
+    let compaction = compact(&config, &mut store, true).unwrap();
+    assert_eq!(compaction, Compaction { missing_local: 5, superseded_pseudo: 0 });
+    assert!(store.pseudo_hashes.contains_key("phone/a.jpg"));
+
+    let mut store = bloated_store(work.path());
+    fs::rename(work.path().join("data"), work.path().join("unmounted")).unwrap();
+    assert!(compact(&config, &mut store, false).is_err());
+    assert_eq!(store.regular_hashes.len(), 8);
+}
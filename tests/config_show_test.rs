@@ -0,0 +1,61 @@
+use phone_sync::effective_config::{resolve, EffectiveSettings, Overrides, Source};
+use phone_sync::output::{render, HumanDisplay, OutputFormat};
+use std::fs;
+
+const CONFIG: &str = "webdav_url: \"https://dav.example.com\"
+username: me
+password: hunter2
+folders: [\"/sdcard/DCIM\"]
+timeout_secs: 45
+read_only: false
+";
+
+#[test]
+fn test_every_setting_is_annotated_with_its_source() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.yaml");
+    fs::write(&path, CONFIG).unwrap();
+
+    let resolved = resolve(&path, &Overrides { read_only: true }).unwrap();
+    assert!(resolved.config.read_only);
+    assert_eq!(resolved.source("webdav_url"), Source::File);
+    assert_eq!(resolved.source("timeout"), Source::Alias("timeout_secs"));
+    assert_eq!(resolved.source("read_only"), Source::Cli);
+    assert_eq!(resolved.source("upload_retries"), Source::Default);
+
+    let shown = resolved.render().unwrap();
+    let lines: Vec<&str> = shown.lines().collect();
+    for expected in [
+        "webdav_url: https://dav.example.com  # file",
+        "password: '***'  # file",
+        "folders:  # file",
+        "- path: /sdcard/DCIM",
+        "timeout: 45s  # file, deprecated alias timeout_secs",
+        "read_only: true  # cli",
+        "upload_retries: 3  # default",
+    ] {
+        assert!(lines.contains(&expected), "missing '{}' in:\n{}", expected, shown);
+    }
+    assert!(!shown.contains("hunter2"));
+
+    // What `config show` prints is what a run loads.
+    let reparsed = phone_sync::config::Config::parse(&shown.replace("'***'", "x")).unwrap();
+    assert_eq!(serde_yaml::to_value(&reparsed).unwrap()["timeout"], "45s");
+    assert_eq!(resolve(&path, &Overrides::default()).unwrap().source("read_only"), Source::File);
+}
+
+#[test]
+fn test_settings_render_as_json() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.yaml");
+    fs::write(&path, CONFIG).unwrap();
+
+    let settings = resolve(&path, &Overrides::default()).unwrap().settings().unwrap();
+    let json = render(&settings, OutputFormat::Json).unwrap();
+    assert!(!json.contains("hunter2"));
+    let parsed: EffectiveSettings = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, settings);
+    let timeout = parsed.settings.iter().find(|s| s.name == "timeout").unwrap();
+    assert_eq!((timeout.value.as_str(), timeout.source.as_str()), (Some("45s"), "file, deprecated alias timeout_secs"));
+    assert_eq!(render(&settings, OutputFormat::Human).unwrap(), settings.human());
+}
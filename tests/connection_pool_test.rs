@@ -0,0 +1,90 @@
+use phone_sync::config::HttpVersion;
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::sync::sync_with_guard;
+use phone_sync::webdav_client::{PoolSettings, WebDavClient};
+use std::fs;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// The stub's URL with the host name instead of the IP, so that the client
+/// can count its new connections.
+fn by_name(server: &StubServer) -> String {
+    server.url.replace("127.0.0.1", "localhost")
+}
+
+async fn burst(client: &WebDavClient, server: &StubServer) {
+    server.put_file("a.txt", b"a");
+    for _ in 0..20 {
+        assert!(client.stat("a.txt").await.unwrap().is_some());
+    }
+}
+
+#[tokio::test]
+async fn test_sequential_requests_reuse_one_connection() {
+    let server = StubServer::start().await;
+    let client = WebDavClient::new(&by_name(&server), None, None, Duration::from_secs(3)).unwrap();
+    burst(&client, &server).await;
+
+    assert_eq!(server.connections(), 1);
+    let stats = client.connection_stats();
+    assert_eq!(stats.requests, 20);
+    assert_eq!(stats.opened, Some(1));
+    assert_eq!(stats.reused, Some(19));
+    assert_eq!(stats.tls_handshakes, Some(0));
+}
+
+#[tokio::test]
+async fn test_pool_settings_are_applied() {
+    let server = StubServer::start().await;
+    let pool = PoolSettings { max_idle_per_host: 0, ..PoolSettings::default() };
+    let client = WebDavClient::with_pool(&by_name(&server), None, None, Duration::from_secs(3), pool).unwrap();
+    burst(&client, &server).await;
+
+    assert_eq!(server.connections(), 20);
+    assert_eq!(client.connection_stats().opened, Some(20));
+    assert_eq!(client.connection_stats().reused, Some(0));
+}
+
+#[tokio::test]
+async fn test_connections_are_not_counted_for_ip_addresses() {
+    let server = StubServer::start().await;
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    burst(&client, &server).await;
+
+    let stats = client.connection_stats();
+    assert_eq!(stats.requests, 20);
+    assert_eq!(stats.opened, None);
+    assert_eq!(stats.reused, None);
+}
+
+#[tokio::test]
+async fn test_sync_profile_reports_connection_reuse() {
+    let server = StubServer::start().await;
+    let data = tempfile::tempdir().unwrap();
+    let work = tempfile::tempdir().unwrap();
+    for i in 0..5 {
+        fs::write(data.path().join(format!("{}.txt", i)), "x").unwrap();
+    }
+    let config = TestConfig::new(&by_name(&server), &work.path().join("hashes.yaml"))
+        .folders(&[data.path()])
+        .yaml("http_version: http1\npool_idle_timeout: 10m\n")
+        .build();
+    assert_eq!(config.http_version, HttpVersion::Http1);
+    assert_eq!(config.pool_idle_timeout, Duration::from_secs(600));
+    assert_eq!(config.pool_max_idle_per_host, 32);
+
+    let client = WebDavClient::from_config(&config).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), &config).await.unwrap();
+    let report = sync_with_guard(&config, &client, &mut guard, false, false, &FilterSet::default()).await.unwrap();
+
+    let connections = report.profile.connections.unwrap();
+    assert_eq!(connections.opened, Some(1));
+    assert_eq!(connections.requests as usize, server.requests().len());
+    assert!(report.profile.table().contains(&format!(
+        "connections: 1 opened, {} reused, 0 TLS handshakes",
+        connections.requests - 1
+    )));
+}
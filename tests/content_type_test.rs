@@ -0,0 +1,42 @@
+use phone_sync::sync::sync;
+use std::collections::BTreeMap;
+use std::fs;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// Content-Type of every PUT by path, after syncing one file per extension.
+async fn put_types(extra: &str) -> BTreeMap<String, Option<String>> {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    for name in ["photo.jpg", "notes.txt", "scan.pdf", "data.xyz"] {
+        fs::write(data.join(name), name).unwrap();
+    }
+    let config = TestConfig::in_work_dir(&server.url, work.path()).yaml("remote_hash_store: disabled\n").yaml(extra).build();
+    sync(&config).await.unwrap();
+    server.requests().into_iter().filter(|r| r.method == "PUT").map(|r| (r.path, r.content_type)).collect()
+}
+
+#[tokio::test]
+async fn test_uploads_carry_the_content_type_of_their_extension() {
+    let types = put_types("").await;
+    let expected = [
+        ("data.xyz", "application/octet-stream"),
+        ("notes.txt", "text/plain"),
+        ("photo.jpg", "image/jpeg"),
+        ("scan.pdf", "application/pdf"),
+    ];
+    assert_eq!(
+        types,
+        expected.iter().map(|(path, ty)| (path.to_string(), Some(ty.to_string()))).collect::<BTreeMap<_, _>>()
+    );
+}
+
+#[tokio::test]
+async fn test_content_type_can_be_disabled() {
+    let types = put_types("set_content_type: false\n").await;
+    assert_eq!(types.len(), 4);
+    assert!(types.values().all(Option::is_none), "{:?}", types);
+}
@@ -0,0 +1,25 @@
+use phone_sync::sync::sync;
+use std::fs;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+#[tokio::test]
+async fn test_every_request_carries_the_configured_headers() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(data.join("DCIM")).unwrap();
+    fs::write(data.join("DCIM/a.jpg"), b"photo").unwrap();
+    let config = TestConfig::in_work_dir(&server.url, work.path())
+        .yaml("user_agent: Mozilla/5.0 (phone)\nextra_headers:\n  X-Requested-With: XMLHttpRequest\n")
+        .build();
+
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    let requests = server.requests();
+    assert!(requests.iter().any(|r| r.method == "PUT"), "{:?}", requests);
+    for request in &requests {
+        assert_eq!(request.user_agent.as_deref(), Some("Mozilla/5.0 (phone)"), "{:?}", request);
+        assert_eq!(request.requested_with.as_deref(), Some("XMLHttpRequest"), "{:?}", request);
+    }
+}
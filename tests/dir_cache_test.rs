@@ -0,0 +1,90 @@
+use phone_sync::config::Config;
+use phone_sync::sync::sync;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config(server: &StubServer, work: &Path, extra: &str) -> Config {
+    let camera = work.join("data/DCIM/Camera");
+    fs::create_dir_all(&camera).unwrap();
+    for i in 0..50 {
+        fs::write(camera.join(format!("IMG_{:04}.jpg", i)), format!("photo {}", i)).unwrap();
+    }
+    TestConfig::in_work_dir(&server.url, work).yaml("remote_hash_store: disabled\n").yaml(extra).build()
+}
+
+fn paths(server: &StubServer, method: &str) -> Vec<String> {
+    server.requests().into_iter().filter(|r| r.method == method).map(|r| r.path).collect()
+}
+
+#[tokio::test]
+async fn test_each_directory_is_created_once_per_run() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+
+    assert_eq!(sync(&config(&server, work.path(), "")).await.unwrap().uploaded, 50);
+    // Two per file before the cache.
+    assert_eq!(paths(&server, "MKCOL"), ["DCIM/", "DCIM/Camera/"]);
+}
+
+#[tokio::test]
+async fn test_existing_directories_are_checked_before_mkcol() {
+    let server = StubServer::start().await;
+    server.put_file("DCIM/Camera/old.jpg", b"old");
+    let work = tempfile::tempdir().unwrap();
+
+    let config = config(&server, work.path(), "check_dirs_before_mkcol: true\n");
+    assert_eq!(sync(&config).await.unwrap().uploaded, 50);
+    assert!(paths(&server, "MKCOL").is_empty());
+    // Listing the files' directory already showed that it exists.
+    assert_eq!(paths(&server, "PROPFIND").iter().filter(|p| p.starts_with("DCIM")).collect::<Vec<_>>(), ["DCIM/Camera/"]);
+
+    // Missing ones are still created.
+    server.clear_requests();
+    let client = WebDavClient::from_config(&config).unwrap();
+    client.create_dir_all("DCIM/Screenshots").await.unwrap();
+    assert_eq!(paths(&server, "MKCOL"), ["DCIM/Screenshots/"]);
+}
+
+#[tokio::test]
+async fn test_deleted_directories_are_created_again() {
+    let server = StubServer::start().await;
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+
+    client.create_dir_all("a/b").await.unwrap();
+    client.create_dir_all("a/b").await.unwrap();
+    assert_eq!(server.count("MKCOL"), 2);
+
+    assert!(client.delete_file("a/").await.unwrap());
+    client.create_dir_all("a/b").await.unwrap();
+    assert_eq!(paths(&server, "MKCOL"), ["a/", "a/b/", "a/", "a/b/"]);
+}
+
+#[tokio::test]
+async fn test_file_in_place_of_a_directory_is_reported() {
+    let server = StubServer::start().await;
+    server.put_file("DCIM", b"not a directory");
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+
+    // The 405 alone does not tell a collection from a file.
+    let err = client.create_dir_all("DCIM/Camera").await.unwrap_err().to_string();
+    assert_eq!(err, "Remote path 'DCIM' exists but is not a collection");
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a.jpg"), b"photo").unwrap();
+    let err = client.upload_file(dir.path().join("a.jpg"), "DCIM/a.jpg").await.unwrap_err().to_string();
+    assert!(err.contains("'DCIM' exists but is not a collection"), "{}", err);
+    assert_eq!(server.count("PUT"), 0);
+
+    // An existing collection is checked once.
+    client.create_dir_all("Pictures").await.unwrap();
+    let other = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    server.clear_requests();
+    other.create_dir_all("Pictures").await.unwrap();
+    other.create_dir_all("Pictures").await.unwrap();
+    let methods: Vec<String> = server.requests().into_iter().map(|r| r.method).collect();
+    assert_eq!(methods, ["MKCOL", "PROPFIND"]);
+}
@@ -0,0 +1,72 @@
+use phone_sync::hash_store::HashStore;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::StubServer;
+
+#[tokio::test]
+async fn test_missing_remote_file_leaves_local_file_alone() {
+    let server = StubServer::start().await;
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let local = dir.path().join("remote_hashes.yaml");
+
+    // A copy from an earlier run, with hashes the server never had.
+    let mut stale = HashStore::default();
+    stale.regular_hashes.insert("a.jpg".to_string(), "wrong".to_string());
+    stale.save(&local).unwrap();
+
+    assert_eq!(client.download_file(".sync_hashes.yaml", &local).await.unwrap(), None);
+    assert_eq!(HashStore::load(&local).unwrap().regular_hashes, stale.regular_hashes);
+
+    server.put_file(".sync_hashes.yaml", b"regular_hashes: {}\npseudo_hashes: {}\n");
+    assert_eq!(client.download_file(".sync_hashes.yaml", &local).await.unwrap(), Some(37));
+    assert_eq!(fs::read_to_string(&local).unwrap(), "regular_hashes: {}\npseudo_hashes: {}\n");
+}
+
+#[tokio::test]
+async fn test_large_download_is_streamed_into_place() {
+    let server = StubServer::start().await;
+    let content: Vec<u8> = (0..8 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    server.put_file("big.bin", &content);
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(10)).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let local = dir.path().join("big.bin");
+
+    assert_eq!(client.download_file("big.bin", &local).await.unwrap(), Some(content.len() as u64));
+    assert!(fs::read(&local).unwrap() == content);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1, "no .part file left behind");
+}
+
+#[tokio::test]
+async fn test_interrupted_download_leaves_no_partial_file() {
+    let server = StubServer::start().await;
+    server.put_file("big.bin", &vec![7u8; 1024 * 1024]);
+    server.disconnect("big.bin", 64 * 1024);
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let local = dir.path().join("big.bin");
+
+    assert!(client.download_file("big.bin", &local).await.is_err());
+    assert!(!local.exists());
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0, "no .part file left behind");
+
+    // An earlier complete copy is kept as it was.
+    fs::write(&local, b"earlier").unwrap();
+    assert!(client.download_file("big.bin", &local).await.is_err());
+    assert_eq!(fs::read(&local).unwrap(), b"earlier");
+}
+
+#[tokio::test]
+async fn test_conflict_means_missing_for_remote_hashing() {
+    let server = StubServer::start().await;
+    server.put_file("a/b.txt", b"b");
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+
+    // Answered by some servers for a path below a missing collection.
+    server.fail_next("GET", 1, 409);
+    assert_eq!(client.remote_sha256("a/b.txt").await.unwrap(), None);
+    assert!(client.remote_sha256("a/b.txt").await.unwrap().is_some());
+}
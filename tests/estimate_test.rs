@@ -0,0 +1,88 @@
+use phone_sync::config::Config;
+use phone_sync::estimate::estimate;
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store::HashStore;
+use phone_sync::spread::SplitMix64;
+use phone_sync::sync::remote_path_for;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+const FILES: usize = 1500;
+
+/// A tree of `FILES` files in nested folders and a store in which 60% of them
+/// are synced, 10% changed since and 30% new. Returns the config, the store
+/// and the true upload count and bytes.
+fn synthetic_tree(work: &Path) -> (Config, HashStore, f64, f64) {
+    let data = work.join("data");
+    let yaml = format!("webdav_url: \"http://127.0.0.1:9\"\nfolders: [\"{}\"]\nhash_store_path: \"{}\"\n", data.display(), work.join("hashes.yaml").display());
+    let config = Config::parse(&yaml).unwrap();
+
+    let mut store = HashStore::default();
+    let (mut count, mut bytes) = (0.0, 0.0);
+    for i in 0..FILES {
+        let relative = format!("{}/{}/{}.bin", i % 7, i % 13, i);
+        let content = vec![b'x'; (i * 7919) % 3000 + 1];
+        let path = data.join(&relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, &content).unwrap();
+        let hash = format!("{:x}", Sha256::digest(&content));
+        match i % 10 {
+            0..=5 => {
+                store.regular_hashes.insert(remote_path_for(&config, &relative), hash);
+                continue;
+            }
+            6 => {
+                store.regular_hashes.insert(remote_path_for(&config, &relative), "stale".to_string());
+            }
+            _ => {}
+        }
+        count += 1.0;
+        bytes += content.len() as f64;
+    }
+    (config, store, count, bytes)
+}
+
+#[tokio::test]
+async fn test_estimate_interval_covers_ground_truth() {
+    let work = tempfile::tempdir().unwrap();
+    let (config, store, count, bytes) = synthetic_tree(work.path());
+
+    let seeds = 20;
+    let mut covered = 0;
+    for seed in 0..seeds {
+        let report = estimate(&config, &store, &FilterSet::default(), 150, &mut SplitMix64::new(seed)).await.unwrap();
+        assert_eq!((report.walked, report.sampled), (FILES as u64, 150));
+        if report.upload_files.contains(count) && report.upload_bytes.contains(bytes) {
+            covered += 1;
+        }
+    }
+    // Both 95% intervals hold in most runs.
+    assert!(covered >= 16, "{} of {} estimates covered the truth", covered, seeds);
+}
+
+#[tokio::test]
+async fn test_estimate_is_exact_when_everything_is_sampled() {
+    let work = tempfile::tempdir().unwrap();
+    let (config, store, count, bytes) = synthetic_tree(work.path());
+
+    let report = estimate(&config, &store, &FilterSet::default(), FILES, &mut SplitMix64::new(1)).await.unwrap();
+    assert_eq!(report.upload_files.value, count);
+    assert_eq!((report.upload_bytes.low, report.upload_bytes.high), (bytes, bytes));
+
+    // Filtered-out files never count as uploads.
+    let filters = FilterSet { only: vec!["3".to_string()], ..Default::default() };
+    let report = estimate(&config, &store, &filters, FILES, &mut SplitMix64::new(1)).await.unwrap();
+    assert!(report.upload_files.value < count / 5.0);
+}
+
+#[tokio::test]
+async fn test_same_seed_gives_the_same_estimate() {
+    let work = tempfile::tempdir().unwrap();
+    let (config, store, _, _) = synthetic_tree(work.path());
+
+    let filters = FilterSet::default();
+    let first = estimate(&config, &store, &filters, 100, &mut SplitMix64::new(5)).await.unwrap();
+    assert_eq!(estimate(&config, &store, &filters, 100, &mut SplitMix64::new(5)).await.unwrap(), first);
+    assert_ne!(estimate(&config, &store, &filters, 100, &mut SplitMix64::new(6)).await.unwrap(), first);
+}
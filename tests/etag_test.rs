@@ -0,0 +1,52 @@
+use phone_sync::config::Config;
+use phone_sync::sync::sync;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config(server: &StubServer, work: &Path) -> Config {
+    let data = work.join("data");
+    fs::create_dir_all(&data).unwrap();
+    TestConfig::in_work_dir(&server.url, work).build()
+}
+
+#[tokio::test]
+async fn test_change_during_upload_is_kept_as_conflict() {
+    let server = StubServer::start().await;
+    server.version_uploads();
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path());
+    fs::write(work.path().join("data/a.txt"), "first").unwrap();
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    let before = server.requests().len();
+
+    // Another device writes the file between our check and PUT.
+    fs::write(work.path().join("data/a.txt"), "second").unwrap();
+    server.change_after_check("a.txt", b"other device", "\"other\"");
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 0);
+    assert_eq!(report.conflicts, vec!["a.txt"]);
+    assert_eq!(server.file("a.txt").unwrap(), b"other device");
+    // Overwriting conditionally never deletes first.
+    assert!(!server.requests()[before..].iter().any(|r| r.method == "DELETE" && r.path.ends_with("a.txt")));
+}
+
+#[tokio::test]
+async fn test_etag_of_upload_is_recorded() {
+    let server = StubServer::start().await;
+    server.version_uploads();
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path());
+    fs::write(work.path().join("data/a.txt"), "local").unwrap();
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+
+    // A remote edit right after the upload is not taken as the synced version.
+    server.put_file("a.txt", b"remote edit");
+    server.clear_headers("a.txt");
+    server.set_header("a.txt", "ETag", "\"edited\"");
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    assert_eq!(server.file("a.txt").unwrap(), b"local");
+    assert_eq!(sync(&config).await.unwrap().skipped, 1);
+}
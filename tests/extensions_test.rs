@@ -0,0 +1,48 @@
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::sync::sync_with_guard;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+#[tokio::test]
+async fn test_sync_applies_per_folder_extension_allowlist() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let music = work.path().join("Music");
+    let docs = work.path().join("Documents");
+    fs::create_dir_all(&music).unwrap();
+    fs::create_dir_all(&docs).unwrap();
+    fs::write(music.join("song.FLAC"), "f").unwrap();
+    fs::write(music.join("a.tar.mp3"), "m").unwrap();
+    fs::write(music.join("cover.jpg"), "j").unwrap();
+    fs::write(music.join("draft.mp3"), "d").unwrap();
+    fs::write(music.join("README"), "r").unwrap();
+    fs::write(docs.join("cover.jpg"), "j").unwrap();
+    let config = TestConfig::new(&server.url, &work.path().join("hashes.yaml"))
+        .yaml(&format!(
+            "folders:\n- path: \"{}\"\n  extensions: [flac, .mp3]\n- \"{}\"\n",
+            music.display(),
+            docs.display()
+        ))
+        .build();
+    let filters = FilterSet { exclude: vec!["draft.*".to_string()], ..Default::default() };
+
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), &config).await.unwrap();
+    let report = sync_with_guard(&config, &client, &mut guard, false, false, &filters).await.unwrap();
+    guard.finalize().await.unwrap();
+
+    assert!(server.file("song.FLAC").is_some());
+    assert!(server.file("a.tar.mp3").is_some());
+    assert!(server.file("README").is_none());
+    assert!(server.file("draft.mp3").is_none());
+    // The unrestricted folder still syncs every file.
+    assert!(server.file("cover.jpg").is_some());
+    assert_eq!(report.uploaded, 3);
+    assert_eq!(report.filtered_out[&config.folder_id(&config.folders[0])], 3);
+    assert!(!report.filtered_out.contains_key(&config.folder_id(&config.folders[1])));
+}
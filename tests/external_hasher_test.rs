@@ -0,0 +1,150 @@
+#![cfg(unix)]
+
+use phone_sync::config::Config;
+use phone_sync::external_hasher::FileHasher;
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store::HashStore;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::sync::sync_with_guard;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+const DIGEST: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+
+/// Write an executable shell script to `dir`.
+fn script(dir: &Path, name: &str, body: &str) -> PathBuf {
+    let path = dir.join(name);
+    fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+fn config(server: &StubServer, data: &Path, work: &Path, hasher: &str) -> Config {
+    TestConfig::new(&server.url, &work.join("hashes.yaml"))
+        .folders(&[data])
+        .yaml("external_hasher:\n")
+        .yaml(hasher)
+        .build()
+}
+
+/// Sync `data` once and return the stored hash of `a.txt`.
+async fn synced_hash(hasher: &str, pseudo: bool) -> Result<String, String> {
+    let server = StubServer::start().await;
+    let data = tempfile::tempdir().unwrap();
+    let work = tempfile::tempdir().unwrap();
+    fs::write(data.path().join("a.txt"), "content").unwrap();
+    let config = config(&server, data.path(), work.path(), hasher);
+    let client = WebDavClient::from_config(&config).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), &config).await.unwrap();
+    sync_with_guard(&config, &client, &mut guard, false, pseudo, &FilterSet::default())
+        .await
+        .map_err(|e| e.to_string())?;
+    guard.finalize().await.unwrap();
+    Ok(guard.hash_store.hashes(pseudo)["a.txt"].clone())
+}
+
+async fn builtin_hash(pseudo: bool) -> String {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "content").unwrap();
+    HashStore::compute(dir.path().join("a.txt"), pseudo).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_external_digest_is_stored() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("calls.log");
+    let hasher = script(dir.path(), "hasher.sh", &format!("echo \"$1 $2\" >> {}\necho \"{}  $1\"", log.display(), DIGEST));
+
+    let hash = synced_hash(&format!("  command: \"{}\"\n", hasher.display()), false).await.unwrap();
+    assert_eq!(hash, DIGEST);
+    let calls = fs::read_to_string(&log).unwrap();
+    assert_eq!(calls.lines().count(), 1);
+    assert!(calls.trim().ends_with("a.txt sha256"), "{}", calls);
+}
+
+#[tokio::test]
+async fn test_pseudo_mode_uses_command_only_when_enabled() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("calls.log");
+    let hasher = script(dir.path(), "hasher.sh", &format!("echo \"$2\" >> {}\necho {}", log.display(), DIGEST));
+    let section = format!("  command: \"{}\"\n", hasher.display());
+
+    let hash = synced_hash(&section, true).await.unwrap();
+    assert_eq!(hash, builtin_hash(true).await);
+    assert!(!log.exists());
+
+    let hash = synced_hash(&format!("{}  pseudo: true\n", section), true).await.unwrap();
+    assert_eq!(hash, DIGEST);
+    assert_eq!(fs::read_to_string(&log).unwrap(), "pseudo\n");
+}
+
+#[tokio::test]
+async fn test_failing_hasher_falls_back_to_builtin() {
+    let dir = tempfile::tempdir().unwrap();
+    let failing = script(dir.path(), "failing.sh", "echo 'appliance unreachable' >&2\nexit 3");
+    let malformed = script(dir.path(), "malformed.sh", "echo 'checksum pending'");
+    let expected = builtin_hash(false).await;
+
+    for hasher in [&failing, &malformed] {
+        let hash = synced_hash(&format!("  command: \"{}\"\n", hasher.display()), false).await.unwrap();
+        assert_eq!(hash, expected);
+    }
+
+    let err = synced_hash(&format!("  command: \"{}\"\n  fallback: error\n", failing.display()), false)
+        .await
+        .unwrap_err();
+    assert!(err.contains("appliance unreachable"), "{}", err);
+    let err = synced_hash("  command: /nonexistent/hasher\n  fallback: error\n", false).await.unwrap_err();
+    assert!(err.contains("cannot run"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_slow_hasher_is_killed_after_timeout() {
+    let dir = tempfile::tempdir().unwrap();
+    let slow = script(dir.path(), "slow.sh", &format!("sleep 10\necho {}", DIGEST));
+
+    let start = Instant::now();
+    let hash = synced_hash(&format!("  command: \"{}\"\n  timeout: 200ms\n", slow.display()), false)
+        .await
+        .unwrap();
+    assert_eq!(hash, builtin_hash(false).await);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn test_invocations_are_capped() {
+    let dir = tempfile::tempdir().unwrap();
+    // Fails whenever another invocation holds the lock directory.
+    let hasher = script(
+        dir.path(),
+        "exclusive.sh",
+        &format!("mkdir \"$1\" 2>/dev/null || exit 1\nsleep 0.2\nrmdir \"$1\"\necho {}", DIGEST),
+    );
+    let lock = dir.path().join("lock");
+    fs::write(dir.path().join("a.txt"), "content").unwrap();
+    let config = TestConfig::new("http://localhost", &dir.path().join("hashes.yaml"))
+        .folders(&[dir.path()])
+        .yaml(&format!(
+            "external_hasher:\n  command: \"{}\"\n  args: [\"{}\"]\n  max_concurrent: 1\n  fallback: error\n",
+            hasher.display(),
+            lock.display()
+        ))
+        .build();
+    let file_hasher = FileHasher::from_config(&config);
+
+    let file = dir.path().join("a.txt");
+    let results = tokio::join!(
+        file_hasher.compute(&file, false),
+        file_hasher.compute(&file, false),
+        file_hasher.compute(&file, false),
+    );
+    for result in [results.0, results.1, results.2] {
+        assert_eq!(result.unwrap(), DIGEST);
+    }
+}
@@ -0,0 +1,67 @@
+use phone_sync::config::Config;
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::sync::sync_with_guard;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+#[tokio::test]
+async fn test_sync_only_touches_selected_files() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(data.join("DCIM")).unwrap();
+    fs::create_dir_all(data.join("Music")).unwrap();
+    fs::write(data.join("DCIM/a.jpg"), "a").unwrap();
+    fs::write(data.join("DCIM/b.tmp"), "b").unwrap();
+    fs::write(data.join("Music/c.mp3"), "c").unwrap();
+    let config = TestConfig::in_work_dir(&server.url, work.path()).build();
+    let filters = FilterSet {
+        only: vec!["DCIM".to_string()],
+        exclude: vec!["*.tmp".to_string()],
+        ..Default::default()
+    };
+
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), &config).await.unwrap();
+    let report = sync_with_guard(&config, &client, &mut guard, false, false, &filters).await.unwrap();
+    guard.finalize().await.unwrap();
+
+    assert_eq!(report.uploaded, 1);
+    assert!(server.file("DCIM/a.jpg").is_some());
+    // Filtered files cost no network requests at all.
+    assert!(server.requests().iter().all(|r| !r.path.contains("b.tmp") && !r.path.contains("c.mp3")));
+}
+
+#[tokio::test]
+async fn test_sync_scans_equivalent_folders_once() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(data.join("a.jpg"), "a").unwrap();
+    // Parsed without `Config::parse`, so the folders are not deduped up front.
+    let yaml = format!(
+        "webdav_url: \"{}\"\nfolders: [\"{}\", \"{}/\"]\nhash_store_path: \"{}\"\n",
+        server.url,
+        data.display(),
+        data.display(),
+        work.path().join("hashes.yaml").display()
+    );
+    let config: Config = serde_yaml::from_str(&yaml).unwrap();
+
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), &config).await.unwrap();
+    let report = sync_with_guard(&config, &client, &mut guard, false, false, &FilterSet::default())
+        .await
+        .unwrap();
+    guard.finalize().await.unwrap();
+
+    assert_eq!(report.uploaded, 1);
+    assert_eq!(report.skipped, 0);
+    assert_eq!(report.folders.len(), 1);
+}
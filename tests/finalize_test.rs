@@ -0,0 +1,68 @@
+use phone_sync::{
+    config::Config, hash_store::HashStore, hash_store_guard::HashStoreGuard,
+    webdav_client::WebDavClient,
+};
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config_for(url: &str, hash_store_path: &std::path::Path) -> Config {
+    TestConfig::new(url, hash_store_path).yaml("folders: [\"./test_data\"]\nfinalize_retries: 1\n").build()
+}
+
+fn client_for(config: &Config) -> WebDavClient {
+    WebDavClient::new(&config.webdav_url, None, None, config.timeout).expect("client")
+}
+
+#[tokio::test]
+async fn test_finalize_marks_store_pending_and_next_run_uploads_it() {
+    let dir = tempfile::tempdir().unwrap();
+    let local_path = dir.path().join("hashes.yaml");
+
+    // First run: the remote fails by the time the store is finalized.
+    let failing = StubServer::start().await;
+    let config = config_for(&failing.url, &local_path);
+    let mut guard = HashStoreGuard::new(client_for(&config), &config).await.unwrap();
+    failing.fail_next("PUT", 10, 503);
+    guard
+        .hash_store_mut()
+        .regular_hashes
+        .insert("a.txt".to_string(), "hash-a".to_string());
+    guard.finalize().await.expect("pending upload is only a warning");
+    drop(guard);
+
+    let local = HashStore::load(&local_path).unwrap();
+    assert!(local.remote_upload_pending);
+    assert_eq!(local.regular_hashes.get("a.txt").map(String::as_str), Some("hash-a"));
+
+    // Second run: the remote is back and receives the store before anything else.
+    let server = StubServer::start().await;
+    let config = config_for(&server.url, &local_path);
+    let guard = HashStoreGuard::new(client_for(&config), &config).await.unwrap();
+    assert_eq!(guard.hash_store.regular_hashes.get("a.txt").map(String::as_str), Some("hash-a"));
+    assert!(!guard.hash_store.remote_upload_pending);
+
+    let uploaded = server.file("hashes.yaml").expect("store uploaded on catch-up");
+    let remote: HashStore = serde_yaml::from_slice(&uploaded).unwrap();
+    assert_eq!(remote.regular_hashes.get("a.txt").map(String::as_str), Some("hash-a"));
+    assert!(!remote.remote_upload_pending);
+    // The remote copy was not downloaded, the pending local store took precedence.
+    assert_eq!(server.count("GET"), 0);
+    assert!(!HashStore::load(&local_path).unwrap().remote_upload_pending);
+}
+
+#[tokio::test]
+async fn test_finalize_can_be_made_strict() {
+    let dir = tempfile::tempdir().unwrap();
+    let local_path = dir.path().join("hashes.yaml");
+    let server = StubServer::start().await;
+    let mut config = config_for(&server.url, &local_path);
+    config.finalize_retries = 0;
+    config.fail_on_pending_upload = true;
+
+    let mut guard = HashStoreGuard::new(client_for(&config), &config).await.unwrap();
+    server.fail_next("PUT", 10, 503);
+    assert!(guard.finalize().await.is_err());
+    // The local copy is persisted even though the run fails.
+    assert!(HashStore::load(&local_path).unwrap().remote_upload_pending);
+}
@@ -0,0 +1,63 @@
+use phone_sync::sync::sync;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+#[tokio::test]
+async fn test_remote_change_triggers_reupload() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(data.join("a.txt"), "local").unwrap();
+    let config = TestConfig::in_work_dir(&server.url, work.path()).build();
+
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    // Unchanged pass records the fingerprint the server reports.
+    server.set_header("a.txt", "ETag", "\"v1\"");
+    assert_eq!(sync(&config).await.unwrap().skipped, 1);
+    assert_eq!(sync(&config).await.unwrap().skipped, 1);
+
+    // Someone edits the file on the server.
+    server.put_file("a.txt", b"remote edit");
+    server.clear_headers("a.txt");
+    server.set_header("a.txt", "ETag", "\"v2\"");
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    assert_eq!(server.file("a.txt").unwrap(), b"local");
+}
+
+#[tokio::test]
+async fn test_remote_of_another_size_is_uploaded_again() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(data.join("a.txt"), "local").unwrap();
+    let config = TestConfig::in_work_dir(&server.url, work.path()).build();
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+
+    // Cut short on the server without a fingerprint to tell: the hashes
+    // still match, the size does not.
+    server.put_file("a.txt", b"loc");
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    assert_eq!(server.file("a.txt").unwrap(), b"local");
+    assert_eq!(sync(&config).await.unwrap().skipped, 1);
+}
+
+#[tokio::test]
+async fn test_stat_asks_for_the_size_when_head_has_none() {
+    let server = StubServer::start().await;
+    server.put_file("a.txt", b"content");
+    server.set_header("a.txt", "ETag", "\"v1\"");
+    server.omit_head_length();
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+
+    let stat = client.stat("a.txt").await.unwrap().unwrap();
+    assert_eq!(stat.size, Some(7));
+    assert_eq!(stat.etag.as_deref(), Some("\"v1\""));
+    assert_eq!(server.count("PROPFIND"), 1);
+    assert!(client.stat("missing.txt").await.unwrap().is_none());
+}
@@ -0,0 +1,97 @@
+use phone_sync::config::Config;
+use phone_sync::filter::FilterSet;
+use phone_sync::first_run::{self, FirstRunChoice};
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::sync::sync_with_guard;
+use phone_sync::webdav_client::WebDavClient;
+use std::error::Error;
+use std::fs;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+const FILES: [&str; 3] = ["a.jpg", "b.jpg", "c.jpg"];
+
+struct Setup {
+    server: StubServer,
+    config: Config,
+    _work: tempfile::TempDir,
+}
+
+/// Local files `FILES`, of which the first `on_server` already exist remotely.
+async fn setup(on_server: usize) -> Setup {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    for name in FILES {
+        fs::write(data.join(name), name).unwrap();
+    }
+    for name in &FILES[..on_server] {
+        server.put_file(name, name.as_bytes());
+    }
+    let config = TestConfig::in_work_dir(&server.url, work.path()).yaml("first_run_threshold: 2\n").build();
+    Setup { server, config, _work: work }
+}
+
+/// Run the first-run check answering with `script` on the prompt, then sync.
+/// Returns the number of uploaded files and whether the prompt was shown.
+async fn run(setup: &Setup, script: &str) -> Result<(usize, bool), Box<dyn Error>> {
+    let client = WebDavClient::new(&setup.server.url, None, None, Duration::from_secs(3)).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), &setup.config).await.unwrap();
+    let mut asked = false;
+    first_run::guide(&setup.config, &client, guard.hash_store_mut(), &FilterSet::default(), false, |found| {
+        asked = true;
+        assert_eq!(found, 2);
+        Ok(first_run::prompt(found, &mut script.as_bytes(), &mut Vec::new())?)
+    })
+    .await?;
+    let report = sync_with_guard(&setup.config, &client, &mut guard, false, false, &FilterSet::default()).await?;
+    guard.finalize().await?;
+    Ok((report.uploaded, asked))
+}
+
+fn data_puts(server: &StubServer) -> usize {
+    server
+        .requests()
+        .iter()
+        .filter(|r| r.method == "PUT" && FILES.contains(&r.path.as_str()))
+        .count()
+}
+
+#[tokio::test]
+async fn test_few_remote_files_do_not_prompt() {
+    let setup = setup(1).await;
+    assert_eq!(run(&setup, "").await.unwrap(), (3, false));
+}
+
+#[tokio::test]
+async fn test_adopt_skips_existing_remote_files() {
+    let setup = setup(3).await;
+    assert_eq!(run(&setup, "a\n").await.unwrap(), (0, true));
+    assert_eq!(data_puts(&setup.server), 0);
+
+    // The decision is remembered, so the next run neither asks nor uploads.
+    assert_eq!(run(&setup, "").await.unwrap(), (0, false));
+    let store: phone_sync::hash_store::HashStore =
+        serde_yaml::from_slice(&setup.server.file(&setup.config.remote_hash_path).unwrap()).unwrap();
+    assert_eq!(store.first_run, Some(FirstRunChoice::Adopt));
+}
+
+#[tokio::test]
+async fn test_reupload_sends_everything_once() {
+    let setup = setup(3).await;
+    assert_eq!(run(&setup, "r\n").await.unwrap(), (3, true));
+    assert_eq!(data_puts(&setup.server), 3);
+    assert_eq!(run(&setup, "").await.unwrap(), (0, false));
+}
+
+#[tokio::test]
+async fn test_abort_changes_nothing() {
+    let setup = setup(3).await;
+    let err = run(&setup, "q\n").await.unwrap_err();
+    assert!(err.to_string().contains("Aborted"));
+    assert_eq!(data_puts(&setup.server), 0);
+    assert_eq!(setup.server.count("DELETE"), 0);
+}
@@ -0,0 +1,66 @@
+//! Generated data for tests and benchmarks, so neither needs checked-in
+//! fixtures. Benchmarks include it with `#[path]`.
+#![allow(dead_code)]
+
+use phone_sync::file_stamp::FileStamp;
+use phone_sync::fingerprint::RemoteFingerprint;
+use phone_sync::hash_store::HashStore;
+use std::fs;
+use std::path::Path;
+
+/// Deterministic, poorly compressible bytes.
+pub fn bytes(size: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    (0..size)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+/// Write a file of `size` generated bytes at `path`, creating its parents.
+pub fn write_file(path: &Path, size: usize, seed: u64) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, bytes(size, seed)).unwrap();
+}
+
+/// Build a tree below `root` of `depth` directory levels with `fanout`
+/// subdirectories each and `files` small files per directory; returns the
+/// number of files written.
+pub fn deep_tree(root: &Path, depth: usize, fanout: usize, files: usize) -> usize {
+    fn fill(dir: &Path, depth: usize, fanout: usize, files: usize, written: &mut usize) {
+        for i in 0..files {
+            write_file(&dir.join(format!("IMG_{:04}.jpg", i)), 64, *written as u64);
+            *written += 1;
+        }
+        if depth > 0 {
+            for i in 0..fanout {
+                fill(&dir.join(format!("d{}", i)), depth - 1, fanout, files, written);
+            }
+        }
+    }
+    let mut written = 0;
+    fill(root, depth, fanout, files, &mut written);
+    written
+}
+
+/// Remote path of the `index`th generated store entry.
+pub fn store_key(index: usize) -> String {
+    format!("phone/DCIM/{:03}/IMG_{:07}.jpg", index % 1000, index)
+}
+
+/// A store with `entries` files as a sync records them: hash, stamp and
+/// ETag per path.
+pub fn store(entries: usize) -> HashStore {
+    let mut store = HashStore::default();
+    for index in 0..entries {
+        let key = store_key(index);
+        store.regular_hashes.insert(key.clone(), format!("{:064x}", index as u128 * 0x9e37_79b9_7f4a_7c15));
+        store.stamps.insert(key.clone(), FileStamp { size: 1_000 + index as u64, mtime_ns: 1_700_000_000_000_000_000 + index as u64 });
+        store.fingerprints.insert(key, RemoteFingerprint::Etag(format!("\"{:016x}\"", index)));
+    }
+    store
+}
@@ -0,0 +1,53 @@
+use phone_sync::config::Config;
+use phone_sync::report::TransferStats;
+use phone_sync::sync::sync;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// A config for `machine` syncing its copy of the Pictures folder, with its
+/// own local hash store next to it.
+fn machine(server: &StubServer, work: &Path, machine: &str, folder: &str) -> Config {
+    let pictures = work.join(machine).join("Pictures");
+    fs::create_dir_all(pictures.join("2024")).unwrap();
+    fs::write(pictures.join("a.jpg"), "a").unwrap();
+    fs::write(pictures.join("2024/b.jpg"), "bb").unwrap();
+    TestConfig::new(&server.url, &work.join(machine).join("hashes.yaml"))
+        .yaml(&format!(
+            "folders:\n- {}\ntarget_dir: photos\n",
+            folder.replace("PATH", &format!("\"{}\"", pictures.display()))
+        ))
+        .build()
+}
+
+#[tokio::test]
+async fn test_moved_folder_keeps_its_bookkeeping() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+
+    let laptop = machine(&server, work.path(), "laptop", "PATH");
+    let report = sync(&laptop).await.unwrap();
+    let id = laptop.folder_id(&laptop.folders[0]);
+    assert_eq!(report.folders[&id], TransferStats { uploaded: 2, skipped: 0, bytes_uploaded: 3 });
+
+    // The NAS has the folder at another path but shares the remote store.
+    let nas = machine(&server, work.path(), "nas", "PATH");
+    assert_ne!(nas.folders[0].path, laptop.folders[0].path);
+    assert_eq!(nas.folder_id(&nas.folders[0]), id);
+    let report = sync(&nas).await.unwrap();
+    assert_eq!(report.folders.keys().collect::<Vec<_>>(), vec![&id]);
+    assert_eq!(report.folders[&id], TransferStats { uploaded: 0, skipped: 2, bytes_uploaded: 0 });
+}
+
+#[tokio::test]
+async fn test_explicit_folder_id_is_used() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+
+    let config = machine(&server, work.path(), "laptop", "path: PATH\n  id: pictures\n  extensions: [png]");
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.filtered_out["pictures"], 2);
+    assert!(phone_sync::output::HumanDisplay::human(&report).contains("pictures: 2 filtered out"));
+}
@@ -0,0 +1,127 @@
+use phone_sync::config::Config;
+use phone_sync::hash_delta::HashDelta;
+use phone_sync::hash_store::HashStore;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::sync::sync;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// A config for `device` with its own local store in `work`.
+fn config(server: &StubServer, work: &Path, device: &str, compact_after: usize) -> Config {
+    let data = work.join(device).join("data");
+    fs::create_dir_all(&data).unwrap();
+    TestConfig::new(&server.url, &work.join(device).join("hashes.yaml"))
+        .folders(&[&data])
+        .yaml(&format!("hash_store_deltas:\n  device: {}\n  compact_after: {}\n", device, compact_after))
+        .build()
+}
+
+async fn guard(config: &Config) -> HashStoreGuard {
+    let client = WebDavClient::from_config(config).unwrap();
+    HashStoreGuard::new(client, config).await.unwrap()
+}
+
+fn store(yaml: &str) -> HashStore {
+    serde_yaml::from_str(yaml).unwrap()
+}
+
+fn deltas(server: &StubServer) -> Vec<String> {
+    server.paths().into_iter().filter(|p| p.starts_with("hashes.delta.")).collect()
+}
+
+#[tokio::test]
+async fn test_base_with_deltas_loads_as_the_merged_store() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let base = store("regular_hashes: {a: '1', b: '2'}\npseudo_hashes: {}\n");
+    let second = store("regular_hashes: {a: '1', b: '3', c: '4'}\npseudo_hashes: {}\ntags: {c: {album: x}}\n");
+    let third = store("regular_hashes: {b: '3', c: '5'}\npseudo_hashes: {d: p}\ntags: {c: {album: x}}\n");
+    server.put_file("hashes.yaml", serde_yaml::to_string(&base).unwrap().as_bytes());
+    // Applied in seq order, not in the order of the names.
+    for (name, old, new) in [("hashes.delta.zed.1.yaml", &base, &second), ("hashes.delta.anna.2.yaml", &second, &third)] {
+        server.put_file(name, serde_yaml::to_string(&HashDelta::between(old, new).unwrap()).unwrap().as_bytes());
+    }
+
+    let guard = guard(&config(&server, work.path(), "laptop", 20)).await;
+    assert_eq!(guard.hash_store.regular_hashes, third.regular_hashes);
+    assert_eq!(guard.hash_store.pseudo_hashes, third.pseudo_hashes);
+    assert_eq!(guard.hash_store.tags, third.tags);
+}
+
+#[tokio::test]
+async fn test_runs_upload_deltas_until_they_are_merged() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "laptop", 2);
+    let data = work.path().join("laptop/data");
+
+    fs::write(data.join("a.jpg"), "a").unwrap();
+    sync(&config).await.unwrap();
+    assert_eq!(deltas(&server), vec!["hashes.delta.laptop.1.yaml"]);
+    assert!(server.file("hashes.yaml").is_none());
+    // A run without changes uploads nothing.
+    sync(&config).await.unwrap();
+    assert_eq!(deltas(&server).len(), 1);
+    fs::write(data.join("b.jpg"), "b").unwrap();
+    sync(&config).await.unwrap();
+    assert_eq!(deltas(&server), vec!["hashes.delta.laptop.1.yaml", "hashes.delta.laptop.2.yaml"]);
+    let delta: HashDelta = serde_yaml::from_slice(&server.file("hashes.delta.laptop.2.yaml").unwrap()).unwrap();
+    assert_eq!(delta.set["regular_hashes"].keys().collect::<Vec<_>>(), vec!["b.jpg"]);
+
+    // The third run finds two deltas and merges them into the base.
+    fs::write(data.join("c.jpg"), "c").unwrap();
+    sync(&config).await.unwrap();
+    assert!(deltas(&server).is_empty());
+    let base: HashStore = serde_yaml::from_slice(&server.file("hashes.yaml").unwrap()).unwrap();
+    assert_eq!(base.regular_hashes.keys().collect::<Vec<_>>(), vec!["a.jpg", "b.jpg", "c.jpg"]);
+    assert_eq!(base.merged_deltas.len(), 2);
+
+    // A delta a compaction failed to delete is not applied over newer entries.
+    let stale = HashDelta::between(&HashStore::default(), &store("regular_hashes: {c.jpg: old}\npseudo_hashes: {}\n")).unwrap();
+    server.put_file("hashes.delta.laptop.2.yaml", serde_yaml::to_string(&stale).unwrap().as_bytes());
+    let guard = guard(&config).await;
+    assert_ne!(guard.hash_store.regular_hashes["c.jpg"], "old");
+}
+
+#[tokio::test]
+async fn test_concurrent_devices_both_keep_their_deltas() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let base = store("regular_hashes: {shared: '0'}\npseudo_hashes: {}\n");
+    server.put_file("hashes.yaml", serde_yaml::to_string(&base).unwrap().as_bytes());
+
+    let (laptop, phone) = (config(&server, work.path(), "laptop", 20), config(&server, work.path(), "phone", 20));
+    let (mut first, mut second) = (guard(&laptop).await, guard(&phone).await);
+    first.hash_store_mut().regular_hashes.insert("from_laptop".to_string(), "l".to_string());
+    first.hash_store_mut().regular_hashes.insert("shared".to_string(), "laptop".to_string());
+    second.hash_store_mut().regular_hashes.insert("from_phone".to_string(), "p".to_string());
+    second.hash_store_mut().regular_hashes.insert("shared".to_string(), "phone".to_string());
+    second.finalize().await.unwrap();
+    first.finalize().await.unwrap();
+
+    assert_eq!(deltas(&server), vec!["hashes.delta.laptop.1.yaml", "hashes.delta.phone.1.yaml"]);
+    let merged = guard(&config(&server, work.path(), "nas", 20)).await;
+    let keys: Vec<&str> = merged.hash_store.regular_hashes.keys().map(String::as_str).collect();
+    assert_eq!(keys, vec!["from_laptop", "from_phone", "shared"]);
+    // Equal seqs apply in device name order.
+    assert_eq!(merged.hash_store.regular_hashes["shared"], "phone");
+}
+
+#[tokio::test]
+async fn test_files_never_overwrite_delta_paths() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let mut config = config(&server, work.path(), "laptop", 20);
+    config.hash_store_deltas = None;
+    let data = work.path().join("laptop/data");
+    fs::write(data.join("hashes.delta.phone.3.yaml"), "not a delta").unwrap();
+    fs::write(data.join("a.jpg"), "a").unwrap();
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 1);
+    assert!(server.file("hashes.delta.phone.3.yaml").is_none());
+}
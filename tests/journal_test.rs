@@ -0,0 +1,80 @@
+use phone_sync::config::Config;
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store::HashStore;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::journal::{self, Journal};
+use phone_sync::report::SyncReport;
+use phone_sync::sync::sync_with_guard;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+async fn run(config: &Config, server: &StubServer) -> SyncReport {
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3))
+        .unwrap()
+        .with_journal(Journal::from_config(config));
+    let mut guard = HashStoreGuard::new(client.clone(), config).await.unwrap();
+    let report = sync_with_guard(config, &client, &mut guard, false, false, &FilterSet::default())
+        .await
+        .unwrap();
+    guard.finalize().await.unwrap();
+    report
+}
+
+#[tokio::test]
+async fn test_journal_records_run_and_rebuilds_hash_store() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(data.join("DCIM")).unwrap();
+    fs::write(data.join("DCIM/a.jpg"), "aaa").unwrap();
+    let journal_path = work.path().join("journal.jsonl");
+    let hash_store_path = work.path().join("hashes.yaml");
+    let config = TestConfig::new(&server.url, &hash_store_path)
+        .folders(&[&data])
+        .path("journal_path", &journal_path)
+        .build();
+    server.fail_next("PUT", 1, 503);
+    let config = Config { upload_retries: 1, retry_delay: Duration::from_millis(1), ..config };
+
+    assert_eq!(run(&config, &server).await.uploaded, 1);
+
+    let entries = journal::read_entries(&journal_path).unwrap();
+    // The hash store is staged under a name of the run and moved into place.
+    let summary: Vec<_> = entries
+        .iter()
+        .map(|e| (e.operation.as_str(), e.remote_path.split(".yaml.").next().unwrap(), e.outcome.as_str()))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("MKCOL", "DCIM", "ok"),
+            ("PUT", "DCIM/a.jpg", "HTTP 503"),
+            ("PUT", "DCIM/a.jpg", "ok"),
+            ("PUT", "hashes", "ok"),
+            ("MOVE", "hashes", "ok"),
+        ]
+    );
+    assert_eq!(entries[4].destination.as_deref(), Some("hashes.yaml"));
+    let upload = &entries[2];
+    assert_eq!(upload.size, Some(3));
+    let hash = HashStore::compute_hash(data.join("DCIM/a.jpg")).await.unwrap();
+    assert_eq!(upload.hash.as_deref(), Some(hash.as_str()));
+    assert_eq!(entries[3].hash, None);
+
+    // Lose the hash store, then rebuild it from the journal and restore the remote copy.
+    fs::remove_file(&hash_store_path).unwrap();
+    let mut store = HashStore::default();
+    assert_eq!(journal::rebuild_hashes(&entries, &mut store, false), 1);
+    store.save(&hash_store_path).unwrap();
+    server.put_file("hashes.yaml", &fs::read(&hash_store_path).unwrap());
+
+    server.clear_requests();
+    let report = run(&config, &server).await;
+    assert_eq!(report.uploaded, 0);
+    assert_eq!(report.skipped, 1);
+    assert!(server.requests().iter().all(|r| r.path != "DCIM/a.jpg" || r.method != "PUT"));
+}
@@ -0,0 +1,65 @@
+use phone_sync::file_stamp::FileStamp;
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store::HashStore;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::sync::sync_with_guard;
+use phone_sync::webdav_client::WebDavClient;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// One byte past 4 GiB, so any 32-bit size arithmetic wraps to 1.
+const LARGE_SIZE: u64 = (1 << 32) + 1;
+
+/// Create a sparse file of [`LARGE_SIZE`] ending in a marker byte, or `false`
+/// when the filesystem would have to allocate it.
+fn create_sparse_file(path: &Path) -> bool {
+    let mut file = fs::File::create(path).unwrap();
+    if file.seek(SeekFrom::Start(LARGE_SIZE - 1)).is_err() || file.write_all(b"x").is_err() {
+        return false;
+    }
+    let metadata = file.metadata().unwrap();
+    metadata.len() == LARGE_SIZE && metadata.blocks() * 512 < LARGE_SIZE / 2
+}
+
+#[tokio::test]
+async fn test_sync_file_larger_than_4_gib() {
+    let server = StubServer::start().await;
+    server.discard_uploads();
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    let big = data.join("big.bin");
+    if !create_sparse_file(&big) {
+        eprintln!("skipping: filesystem does not support sparse files");
+        return;
+    }
+
+    assert_eq!(FileStamp::of(&fs::metadata(&big).unwrap()).unwrap().size, LARGE_SIZE);
+    let mut expected = Sha256::new();
+    expected.update(b"big.bin");
+    expected.update(LARGE_SIZE.to_be_bytes());
+    expected.update([0u8; 1024]);
+    let pseudo = HashStore::compute_pseudo_hash(&big).await.unwrap();
+    assert_eq!(pseudo, format!("{:x}", expected.finalize()));
+
+    let config = TestConfig::in_work_dir(&server.url, work.path()).build();
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(600)).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), &config).await.unwrap();
+    let report = sync_with_guard(&config, &client, &mut guard, false, true, &FilterSet::default())
+        .await
+        .unwrap();
+    assert_eq!(guard.hash_store_mut().hashes(true).get("big.bin"), Some(&pseudo));
+
+    assert_eq!(report.bytes_uploaded, LARGE_SIZE);
+    assert_eq!(report.folders[&config.folder_id(&config.folders[0])].bytes_uploaded, LARGE_SIZE);
+    assert_eq!(server.upload_size("big.bin"), Some(LARGE_SIZE));
+    let put = server.requests().into_iter().find(|r| r.method == "PUT" && r.path == "big.bin").unwrap();
+    assert_eq!(put.content_length, Some(LARGE_SIZE));
+}
@@ -0,0 +1,53 @@
+use phone_sync::config::{Config, RemoteHashStore};
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::report::SyncReport;
+use phone_sync::sync::sync_with_guard;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+async fn run(config: &Config, server: &StubServer) -> SyncReport {
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), config).await.unwrap();
+    let report = sync_with_guard(config, &client, &mut guard, false, false, &FilterSet::default())
+        .await
+        .unwrap();
+    guard.finalize().await.unwrap();
+    report
+}
+
+#[tokio::test]
+async fn test_disabled_remote_hash_store_stays_local() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(data.join("DCIM")).unwrap();
+    fs::write(data.join("DCIM/a.jpg"), "a").unwrap();
+    fs::write(data.join("b.txt"), "b").unwrap();
+    let hash_store_path = work.path().join("hashes.yaml");
+    let config = TestConfig::new(&server.url, &hash_store_path)
+        .folders(&[&data])
+        .yaml("remote_hash_store: disabled\n")
+        .build();
+    assert_eq!(config.remote_hash_store, RemoteHashStore::Disabled);
+
+    let first = run(&config, &server).await;
+    assert_eq!(first.uploaded, 2);
+    assert!(hash_store_path.exists());
+    assert!(server.file(&config.remote_hash_path).is_none());
+    assert!(server.requests().iter().all(|r| r.path != config.remote_hash_path));
+
+    server.clear_requests();
+    let second = run(&config, &server).await;
+    assert_eq!(second.uploaded, 0);
+    assert_eq!(second.skipped, 2);
+    // A run without changes writes nothing to the server.
+    for method in ["PUT", "DELETE", "MKCOL", "MOVE"] {
+        assert_eq!(server.count(method), 0, "{} sent", method);
+    }
+    assert!(server.file(&config.remote_hash_path).is_none());
+}
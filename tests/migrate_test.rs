@@ -0,0 +1,90 @@
+use phone_sync::config::Config;
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::migrate::migrate_target_dir;
+use phone_sync::sync::{sync, sync_with_guard};
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config_for(url: &str, work: &std::path::Path, target_dir: &str) -> Config {
+    TestConfig::in_work_dir(url, work).yaml(&format!("target_dir: \"{}\"\n", target_dir)).build()
+}
+
+async fn migrate_and_sync(config: &Config, old: &str, move_remote: bool) -> Result<usize, Box<dyn std::error::Error>> {
+    let client = WebDavClient::new(&config.webdav_url, None, None, config.timeout)?;
+    let mut guard = HashStoreGuard::whole(client.clone(), config).await?;
+    migrate_target_dir(&client, guard.hash_store_mut(), old, &config.target_dir, move_remote).await?;
+    let report = sync_with_guard(config, &client, &mut guard, false, false, &FilterSet::default()).await?;
+    guard.finalize().await?;
+    Ok(report.uploaded)
+}
+
+/// MOVE requests other than those putting the staged hash store in place.
+fn tree_moves(server: &StubServer) -> usize {
+    server.requests().iter().filter(|r| r.method == "MOVE" && !r.path.ends_with(".part")).count()
+}
+
+#[tokio::test]
+async fn test_migration_moves_remote_tree_without_reupload() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    fs::create_dir_all(work.path().join("data/DCIM")).unwrap();
+    fs::write(work.path().join("data/DCIM/a.jpg"), "a").unwrap();
+    fs::write(work.path().join("data/b.jpg"), "b").unwrap();
+
+    assert_eq!(sync(&config_for(&server.url, work.path(), "phone")).await.unwrap().uploaded, 2);
+    server.clear_requests();
+
+    let config = config_for(&server.url, work.path(), "backups/phone");
+    assert_eq!(migrate_and_sync(&config, "phone", true).await.unwrap(), 0);
+    assert_eq!(tree_moves(&server), 1);
+    assert_eq!(server.file("backups/phone/DCIM/a.jpg").unwrap(), b"a");
+    assert!(server.file("phone/b.jpg").is_none());
+}
+
+#[tokio::test]
+async fn test_migration_refuses_when_both_trees_exist() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    fs::create_dir_all(work.path().join("data")).unwrap();
+    fs::write(work.path().join("data/b.jpg"), "b").unwrap();
+
+    sync(&config_for(&server.url, work.path(), "phone")).await.unwrap();
+    sync(&config_for(&server.url, work.path(), "backups/phone")).await.unwrap();
+
+    let config = config_for(&server.url, work.path(), "backups/phone");
+    let err = migrate_and_sync(&config, "phone", true).await.unwrap_err().to_string();
+    // The last sync recorded the new target_dir, so the old one is rejected first.
+    assert!(err.contains("last synced"), "{}", err);
+
+    let mut store = phone_sync::hash_store::HashStore::default();
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    let err = migrate_target_dir(&client, &mut store, "phone", "backups/phone", true)
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("refusing"), "{}", err);
+    assert_eq!(tree_moves(&server), 0);
+}
+
+#[tokio::test]
+async fn test_move_path_encodes_the_destination_and_respects_overwrite() {
+    let server = StubServer::start().await;
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(5)).unwrap();
+    server.put_file("old%20dir/%C3%A4.txt", b"a");
+    server.put_file("taken.txt", b"taken");
+
+    client.move_path("old dir/ä.txt", "new dir/ä.txt", false).await.unwrap();
+    assert_eq!(server.file("new%20dir/%C3%A4.txt").unwrap(), b"a");
+    assert!(server.file("old%20dir/%C3%A4.txt").is_none());
+
+    let err = client.move_path("new dir/ä.txt", "taken.txt", false).await.unwrap_err();
+    assert!(err.to_string().contains("already exists"), "{}", err);
+    assert_eq!(server.file("taken.txt").unwrap(), b"taken");
+    client.move_path("new dir/ä.txt", "taken.txt", true).await.unwrap();
+    assert_eq!(server.file("taken.txt").unwrap(), b"a");
+}
@@ -0,0 +1,111 @@
+use phone_sync::config::Config;
+use phone_sync::hash_store::HashStore;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::sync::sync;
+use phone_sync::webdav_client::WebDavClient;
+use semver::Version;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// A store written by a newer client, with a section this client does not know.
+const NEWER_STORE: &str = "regular_hashes:
+  photos/old.jpg: hash-old
+pseudo_hashes: {}
+tombstones:
+  photos/gone.jpg: 2030-01-01
+min_client_version: 2.1.0
+";
+
+fn config(server: &StubServer, work: &Path) -> Config {
+    TestConfig::in_work_dir(&server.url, work).yaml("target_dir: photos\n").build()
+}
+
+async fn guard(config: &Config, version: &str) -> HashStoreGuard {
+    let client = WebDavClient::from_config(config).unwrap();
+    HashStoreGuard::with_client_version(client, config, Version::parse(version).unwrap()).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_old_client_leaves_newer_remote_store_alone() {
+    let server = StubServer::start().await;
+    server.put_file("hashes.yaml", NEWER_STORE.as_bytes());
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path());
+
+    let mut old = guard(&config, "2.0.5").await;
+    assert_eq!(old.remote_locked(), Some(&Version::new(2, 1, 0)));
+    old.hash_store_mut().regular_hashes.insert("photos/new.jpg".into(), "hash-new".into());
+    old.finalize().await.unwrap();
+    drop(old);
+
+    assert_eq!(server.file("hashes.yaml").unwrap(), NEWER_STORE.as_bytes());
+    assert_eq!(server.count("PUT"), 0);
+    // The local copy is kept, but not queued for upload by the next run.
+    let local = HashStore::load(&config.hash_store_path).unwrap();
+    assert_eq!(local.regular_hashes["photos/new.jpg"], "hash-new");
+    assert!(!local.remote_upload_pending);
+    assert_eq!(local.min_client_version, Some(Version::new(2, 1, 0)));
+}
+
+#[tokio::test]
+async fn test_client_meeting_the_requirement_updates_and_keeps_it() {
+    let server = StubServer::start().await;
+    server.put_file("hashes.yaml", NEWER_STORE.as_bytes());
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path());
+
+    let mut current = guard(&config, "2.1.0").await;
+    assert_eq!(current.remote_locked(), None);
+    current.hash_store_mut().regular_hashes.insert("photos/new.jpg".into(), "hash-new".into());
+    current.finalize().await.unwrap();
+
+    let remote: HashStore = serde_yaml::from_slice(&server.file("hashes.yaml").unwrap()).unwrap();
+    assert_eq!(remote.regular_hashes.len(), 2);
+    assert_eq!(remote.min_client_version, Some(Version::new(2, 1, 0)));
+}
+
+#[tokio::test]
+async fn test_old_client_does_not_upload_a_pending_newer_store() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path());
+    let mut pending: HashStore = serde_yaml::from_str(NEWER_STORE).unwrap();
+    pending.remote_upload_pending = true;
+    pending.save(&config.hash_store_path).unwrap();
+
+    let old = guard(&config, "1.0.0").await;
+    assert!(old.remote_locked().is_some());
+    drop(old);
+    assert_eq!(server.count("PUT"), 0);
+}
+
+#[tokio::test]
+async fn test_sync_uploads_files_but_not_the_store() {
+    let server = StubServer::start().await;
+    server.put_file("hashes.yaml", "regular_hashes: {}\npseudo_hashes: {}\nmin_client_version: 999.0.0\n".as_bytes());
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path());
+    std::fs::create_dir_all(work.path().join("data")).unwrap();
+    std::fs::write(work.path().join("data/a.jpg"), "a").unwrap();
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 1);
+    assert_eq!(server.file("photos/a.jpg").unwrap(), b"a");
+    assert_eq!(server.file("hashes.yaml").unwrap(), b"regular_hashes: {}\npseudo_hashes: {}\nmin_client_version: 999.0.0\n");
+}
+
+#[tokio::test]
+async fn test_writer_of_newer_sections_records_the_requirement() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path());
+
+    let mut current = guard(&config, env!("CARGO_PKG_VERSION")).await;
+    current.hash_store_mut().chunks.insert("photos/big.mov".into(), vec!["c1".into()]);
+    current.finalize().await.unwrap();
+
+    let remote: HashStore = serde_yaml::from_slice(&server.file("hashes.yaml").unwrap()).unwrap();
+    assert_eq!(remote.min_client_version, Some(Version::new(0, 1, 0)));
+}
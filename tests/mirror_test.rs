@@ -0,0 +1,140 @@
+use phone_sync::budget::date_of;
+use phone_sync::config::Config;
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::report::SyncReport;
+use phone_sync::sync::sync_with_guard;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+struct Setup {
+    server: StubServer,
+    config: Config,
+    data: PathBuf,
+    archive: PathBuf,
+    _work: tempfile::TempDir,
+}
+
+/// Sync `a.jpg` and `DCIM/b.jpg` once with `mirror_deletions` and an archive.
+async fn setup() -> Setup {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    let archive = work.path().join("archive");
+    fs::create_dir_all(data.join("DCIM")).unwrap();
+    fs::write(data.join("a.jpg"), "a").unwrap();
+    fs::write(data.join("DCIM/b.jpg"), "b").unwrap();
+    let config = TestConfig::in_work_dir(&server.url, work.path())
+        .yaml("mirror_deletions: true\n")
+        .path("local_archive_dir", &archive)
+        .build();
+    let setup = Setup { server, config, data, archive, _work: work };
+    assert_eq!(run(&setup, &FilterSet::default()).await.uploaded, 2);
+    setup
+}
+
+async fn run(setup: &Setup, filters: &FilterSet) -> SyncReport {
+    let client = WebDavClient::new(&setup.server.url, None, None, Duration::from_secs(3)).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), &setup.config).await.unwrap();
+    let report = sync_with_guard(&setup.config, &client, &mut guard, false, false, filters).await.unwrap();
+    guard.finalize().await.unwrap();
+    report
+}
+
+#[tokio::test]
+async fn test_local_deletion_is_archived_before_remote_delete() {
+    let setup = setup().await;
+    fs::remove_file(setup.data.join("DCIM/b.jpg")).unwrap();
+    setup.server.clear_requests();
+
+    let report = run(&setup, &FilterSet::default()).await;
+    assert_eq!(report.deleted, vec!["DCIM/b.jpg"]);
+    let archived = setup.archive.join(date_of(SystemTime::now())).join("DCIM/b.jpg");
+    assert_eq!(fs::read_to_string(archived).unwrap(), "b");
+    assert!(setup.server.file("DCIM/b.jpg").is_none());
+    assert!(setup.server.file("a.jpg").is_some());
+
+    let requests = setup.server.requests();
+    let position = |method: &str| requests.iter().position(|r| r.method == method && r.path == "DCIM/b.jpg");
+    assert!(position("GET").unwrap() < position("DELETE").unwrap());
+
+    // The entry is gone from the store, so the next run has nothing to delete.
+    assert!(run(&setup, &FilterSet::default()).await.deleted.is_empty());
+}
+
+#[tokio::test]
+async fn test_failed_archive_blocks_remote_delete() {
+    let setup = setup().await;
+    fs::remove_file(setup.data.join("a.jpg")).unwrap();
+    // A file in place of the day directory makes archiving fail.
+    let day = setup.archive.join(date_of(SystemTime::now()));
+    fs::create_dir_all(&setup.archive).unwrap();
+    fs::write(&day, "").unwrap();
+
+    let report = run(&setup, &FilterSet::default()).await;
+    assert!(report.deleted.is_empty());
+    assert_eq!(report.deletion_blocked, vec!["a.jpg"]);
+    assert!(setup.server.file("a.jpg").is_some());
+
+    // Still tracked, so the deletion is retried and succeeds on the next run.
+    fs::remove_file(&day).unwrap();
+    assert_eq!(run(&setup, &FilterSet::default()).await.deleted, vec!["a.jpg"]);
+}
+
+#[tokio::test]
+async fn test_partial_scan_deletes_nothing() {
+    let setup = setup().await;
+    fs::remove_file(setup.data.join("a.jpg")).unwrap();
+    let filters = FilterSet { only: vec!["DCIM".to_string()], ..Default::default() };
+    setup.server.clear_requests();
+
+    let report = run(&setup, &filters).await;
+    assert!(report.deleted.is_empty());
+    assert!(setup.server.file("a.jpg").is_some());
+    assert!(setup.server.requests().iter().all(|r| r.method != "DELETE" || r.path != "a.jpg"));
+}
+
+#[tokio::test]
+async fn test_unmounted_folder_deletes_nothing() {
+    let mut setup = setup().await;
+    // The disk is unmounted: the mount point is left empty, without its marker.
+    fs::remove_dir_all(&setup.data).unwrap();
+    fs::create_dir(&setup.data).unwrap();
+    setup.config.folders[0].require_marker_file = Some(".synced_root".to_string());
+    setup.server.clear_requests();
+
+    let report = run(&setup, &FilterSet::default()).await;
+    assert_eq!(report.unmounted, vec![setup.data.display().to_string()]);
+    assert!(report.deleted.is_empty() && report.planned_deletions.is_empty());
+    assert!(setup.server.file("a.jpg").is_some() && setup.server.file("DCIM/b.jpg").is_some());
+
+    // Mounted again, the folder syncs as before.
+    fs::write(setup.data.join(".synced_root"), "").unwrap();
+    fs::write(setup.data.join("a.jpg"), "a").unwrap();
+    let report = run(&setup, &FilterSet::default()).await;
+    assert!(report.unmounted.is_empty());
+    assert_eq!(report.deleted, vec!["DCIM/b.jpg"]);
+}
+
+#[tokio::test]
+async fn test_delete_file_reports_whether_the_file_existed() {
+    let server = StubServer::start().await;
+    server.put_file("dir/a.txt", b"a");
+    let client = WebDavClient::new(&server.url, Some("user"), Some("secret"), Duration::from_secs(5)).unwrap();
+
+    assert!(client.delete_file("dir/a.txt").await.unwrap());
+    assert!(server.file("dir/a.txt").is_none());
+    assert!(!client.delete_file("dir/a.txt").await.unwrap());
+
+    server.put_file("dir/b.txt", b"b");
+    server.fail_next("DELETE", 1, 423);
+    let err = client.delete_file("dir/b.txt").await.unwrap_err();
+    assert!(err.to_string().contains("423"), "{}", err);
+    assert!(server.file("dir/b.txt").is_some());
+}
@@ -0,0 +1,62 @@
+use phone_sync::config::Config;
+use phone_sync::sync::sync;
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config_for(url: &str, work: &Path) -> Config {
+    TestConfig::in_work_dir(url, work).yaml("trust_mtime: true\n").build()
+}
+
+fn set_mtime(path: &Path, mtime: SystemTime) {
+    File::options().write(true).open(path).unwrap().set_modified(mtime).unwrap();
+}
+
+#[tokio::test]
+async fn test_truncated_mtimes_cause_no_uploads() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    for name in ["a.jpg", "b.jpg"] {
+        fs::write(data.join(name), name).unwrap();
+        set_mtime(&data.join(name), UNIX_EPOCH + Duration::new(1_700_000_001, 123_456_789));
+    }
+    let config = config_for(&server.url, work.path());
+    assert_eq!(sync(&config).await.unwrap().uploaded, 2);
+
+    // Copied through a FAT filesystem: mtimes rounded down to even seconds.
+    for name in ["a.jpg", "b.jpg"] {
+        set_mtime(&data.join(name), UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    }
+    server.clear_requests();
+    let report = sync(&config).await.unwrap();
+    assert_eq!((report.uploaded, report.skipped), (0, 2));
+    assert_eq!(server.count("PUT"), 1, "only the hash store");
+}
+
+#[tokio::test]
+async fn test_exact_stamp_skips_rehashing() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    let file = data.join("a.txt");
+    let mtime = UNIX_EPOCH + Duration::new(1_700_000_000, 42);
+    fs::write(&file, "one").unwrap();
+    set_mtime(&file, mtime);
+    let config = config_for(&server.url, work.path());
+    sync(&config).await.unwrap();
+
+    // Same size and an identical mtime: the stored hash is trusted.
+    fs::write(&file, "two").unwrap();
+    set_mtime(&file, mtime);
+    assert_eq!(sync(&config).await.unwrap().uploaded, 0);
+
+    // A different mtime beyond the tolerance makes it a change.
+    set_mtime(&file, mtime + Duration::from_secs(10));
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+}
@@ -0,0 +1,58 @@
+use phone_sync::config::Config;
+use phone_sync::self_test::self_test;
+use phone_sync::sync::sync;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// A config for the stub addressed as `host` instead of its IP.
+fn config(server: &StubServer, work: &Path, host: &str, network: &str) -> Config {
+    let data = work.join("data");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(data.join("a.jpg"), "a").unwrap();
+    TestConfig::in_work_dir(&server.url.replace("127.0.0.1", host), work)
+        .path("temp_dir", work)
+        .yaml(&format!("network: {}\n", network))
+        .build()
+}
+
+#[tokio::test]
+async fn test_sync_binds_to_the_local_address() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "localhost", "{ prefer: ipv4, local_address: 127.0.0.1 }");
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 1);
+    assert_eq!(server.file("a.jpg").unwrap(), b"a");
+}
+
+#[tokio::test]
+async fn test_wrong_address_family_is_named_in_the_error() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+
+    let err = sync(&config(&server, work.path(), "127.0.0.1", "{ prefer: ipv6 }")).await.unwrap_err().to_string();
+    assert!(err.contains("not an IPv6 address"), "{}", err);
+    // Whether localhost resolves to ::1 or not, nothing reaches the stub over IPv6.
+    let err = sync(&config(&server, work.path(), "localhost", "{ local_address: \"::1\" }")).await.unwrap_err().to_string();
+    assert!(err.contains("IPv6"), "{}", err);
+    assert!(server.paths().is_empty());
+}
+
+#[tokio::test]
+async fn test_self_test_reports_the_server_addresses() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "localhost", "{ prefer: ipv4 }");
+
+    let report = self_test(&config, "st").await.unwrap();
+    assert!(report.passed(), "{:?}", report);
+    let loopback = IpAddr::from([127, 0, 0, 1]);
+    assert_eq!(report.resolved_addresses, vec![loopback]);
+    assert_eq!(report.connected_address, Some(loopback));
+    assert!(phone_sync::output::HumanDisplay::human(&report).contains("server addresses: 127.0.0.1 (connected)"));
+}
@@ -0,0 +1,86 @@
+use phone_sync::config::Config;
+use phone_sync::sync::sync;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// Camera photos, an allowlisted WhatsApp folder, and two marked folders.
+fn build_tree(data: &Path) {
+    for (path, content) in [
+        ("DCIM/Camera/a.jpg", "a"),
+        ("WhatsApp/Media/WhatsApp Images/.nomedia", ""),
+        ("WhatsApp/Media/WhatsApp Images/img.jpg", "img"),
+        ("WhatsApp/Media/WhatsApp Images/Sent/s.jpg", "s"),
+        ("Android/data/app/cache/.nomedia", ""),
+        ("Android/data/app/cache/junk.bin", "junk"),
+        ("Pictures/.thumbnails/.nomedia", ""),
+        ("Pictures/.thumbnails/t.jpg", "t"),
+        ("Pictures/.thumbnails/deep/x.jpg", "x"),
+        ("Pictures/p.jpg", "p"),
+    ] {
+        let path = data.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+}
+
+fn config(server: &StubServer, work: &Path, extra: &str) -> Config {
+    TestConfig::in_work_dir(&server.url, work).yaml(extra).build()
+}
+
+#[tokio::test]
+async fn test_marked_folders_are_skipped_unless_allowlisted() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    build_tree(&work.path().join("data"));
+    let config = config(
+        &server,
+        work.path(),
+        "respect_nomedia: true\nnomedia_allowlist: [\"*/WhatsApp Images\"]\nmirror_deletions: true\n",
+    );
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(
+        server.paths(),
+        vec![
+            "DCIM/Camera/a.jpg",
+            "Pictures/p.jpg",
+            "WhatsApp/Media/WhatsApp%20Images/.nomedia",
+            "WhatsApp/Media/WhatsApp%20Images/Sent/s.jpg",
+            "WhatsApp/Media/WhatsApp%20Images/img.jpg",
+            "hashes.yaml",
+        ]
+    );
+    let data = work.path().join("data");
+    let mut skipped = report.nomedia_skipped.clone();
+    skipped.sort();
+    assert_eq!(
+        skipped,
+        vec![
+            data.join("Android/data/app/cache").display().to_string(),
+            data.join("Pictures/.thumbnails").display().to_string(),
+        ]
+    );
+    assert!(phone_sync::output::HumanDisplay::human(&report).contains("skipped (.nomedia): "));
+}
+
+#[tokio::test]
+async fn test_marking_a_synced_folder_keeps_its_remote_copies() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    build_tree(&work.path().join("data"));
+    sync(&config(&server, work.path(), "")).await.unwrap();
+    assert!(server.file("Pictures/.thumbnails/deep/x.jpg").is_some());
+    server.clear_requests();
+
+    // Turning the marker on later prunes the folder, but mirror_deletions does
+    // not treat its files as deleted.
+    let config = config(&server, work.path(), "respect_nomedia: true\nmirror_deletions: true\n");
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.nomedia_skipped.len(), 3);
+    assert_eq!(report.uploaded, 0);
+    assert!(server.requests().iter().all(|r| r.method != "DELETE" || r.path == "hashes.yaml"));
+    assert!(server.file("Pictures/.thumbnails/deep/x.jpg").is_some());
+}
@@ -0,0 +1,54 @@
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store::HashStore;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::sync::sync_with_guard;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+#[tokio::test]
+async fn test_sync_normalizes_windows_keys_without_reuploading() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(data.join("DCIM")).unwrap();
+    fs::write(data.join("DCIM/a.jpg"), "a").unwrap();
+    server.put_file("DCIM/a.jpg", b"a");
+
+    // The store as written by a Windows machine.
+    let mut windows = HashStore::default();
+    let hash = HashStore::compute(data.join("DCIM/a.jpg"), false).await.unwrap();
+    windows.regular_hashes.insert("DCIM\\a.jpg".to_string(), hash);
+    let yaml = serde_yaml::to_string(&windows).unwrap();
+    server.put_file("hashes.yaml", yaml.as_bytes());
+
+    let hash_store_path = work.path().join("hashes.yaml");
+    let config = TestConfig::new(&server.url, &hash_store_path)
+        .folders(&[&data])
+        .yaml("normalize_store_keys: true\n")
+        .build();
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), &config).await.unwrap();
+    let requests_before_sync = server.requests().len();
+    let report = sync_with_guard(&config, &client, &mut guard, false, false, &FilterSet::default())
+        .await
+        .unwrap();
+    guard.finalize().await.unwrap();
+
+    assert_eq!(report.uploaded, 0);
+    assert_eq!(report.skipped, 1);
+    // Only the listing of the file's directory, no renames on the server
+    // (besides the quota query on the root and the staged hash store).
+    let during_sync: Vec<_> = server.requests()[requests_before_sync..]
+        .iter()
+        .filter(|r| !r.path.starts_with("hashes.yaml") && !r.path.is_empty())
+        .map(|r| r.method.clone())
+        .collect();
+    assert_eq!(during_sync, vec!["PROPFIND"]);
+    let store = HashStore::load(&hash_store_path).unwrap();
+    assert!(store.backslash_keys().is_empty());
+    assert!(store.regular_hashes.contains_key("DCIM/a.jpg"));
+}
@@ -0,0 +1,94 @@
+use phone_sync::config::{Config, FolderConfig};
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::sync::sync;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// Profile A syncs `data`, profile B its subfolder `data/docs` with the store
+/// inside it; both share the temp root, so they see each other's locks.
+fn profiles(server: &StubServer, work: &Path, extra_a: &str, extra_b: &str) -> (Config, Config) {
+    let data = work.join("data");
+    fs::create_dir_all(data.join("docs/.sync")).unwrap();
+    let a = TestConfig::new(&server.url, &work.join("a.yaml"))
+        .folders(&[&data])
+        .path("temp_dir", &work.join("tmp"))
+        .yaml("target_dir: a\nremote_hash_path: a.yaml\nartifact_names: [hashes-b.yaml]\n")
+        .yaml(extra_a)
+        .build();
+    let b = TestConfig::new(&server.url, &data.join("docs/.sync/hashes-b.yaml"))
+        .folders(&[&data.join("docs")])
+        .path("temp_dir", &work.join("tmp"))
+        .yaml("target_dir: b\nremote_hash_path: b.yaml\n")
+        .yaml(extra_b)
+        .build();
+    (a, b)
+}
+
+#[tokio::test]
+async fn test_nested_profile_artifacts_are_not_uploaded() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let (a, b) = profiles(&server, work.path(), "", "");
+    let docs = work.path().join("data/docs");
+    fs::write(docs.join("report.pdf"), "report").unwrap();
+    fs::write(docs.join("scan.pdf.part"), "half a download").unwrap();
+    fs::write(docs.join("b.phone_sync.lock"), "{}").unwrap();
+
+    assert_eq!(sync(&b).await.unwrap().uploaded, 1);
+    assert!(docs.join(".sync/hashes-b.yaml").exists());
+    assert_eq!(sync(&a).await.unwrap().uploaded, 1);
+
+    let uploaded = server.paths();
+    assert!(uploaded.contains(&"a/docs/report.pdf".to_string()), "{:?}", uploaded);
+    assert!(!uploaded.iter().any(|p| p.ends_with("hashes-b.yaml") || p.ends_with(".part") || p.ends_with(".lock")), "{:?}", uploaded);
+}
+
+#[tokio::test]
+async fn test_overlapping_runs_exclude_each_other() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let (a, b) = profiles(&server, work.path(), "", "");
+    let client = WebDavClient::new(&server.url, None, None, std::time::Duration::from_secs(3)).unwrap();
+
+    let running = HashStoreGuard::new(client.clone(), &b).await.unwrap();
+    let err = HashStoreGuard::new(client.clone(), &a).await.err().unwrap().to_string();
+    assert!(err.contains("of a running sync") && err.contains("allow_overlapping_runs"), "{}", err);
+    // The lock goes with the run.
+    drop(running);
+    drop(HashStoreGuard::new(client.clone(), &a).await.unwrap());
+
+    // Only if both opt in.
+    let (a, b) = profiles(&server, work.path(), "allow_overlapping_runs: true\n", "");
+    let running = HashStoreGuard::new(client.clone(), &b).await.unwrap();
+    assert!(HashStoreGuard::new(client.clone(), &a).await.is_err());
+    drop(running);
+    let (a, b) = profiles(&server, work.path(), "allow_overlapping_runs: true\n", "allow_overlapping_runs: true\n");
+    let _running = HashStoreGuard::new(client.clone(), &b).await.unwrap();
+    assert!(HashStoreGuard::new(client, &a).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_profiles_sharing_a_remote_store_exclude_each_other() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    // Separate folders and target_dirs, but both write b.yaml.
+    let (a, b) = profiles(&server, work.path(), "", "");
+    let elsewhere = work.path().join("elsewhere");
+    fs::create_dir_all(&elsewhere).unwrap();
+    let a = Config {
+        folders: vec![FolderConfig::new(elsewhere.display().to_string())],
+        remote_hash_path: "b.yaml".to_string(),
+        ..a
+    };
+    let client = WebDavClient::new(&server.url, None, None, std::time::Duration::from_secs(3)).unwrap();
+
+    let running = HashStoreGuard::new(client.clone(), &b).await.unwrap();
+    let err = HashStoreGuard::new(client.clone(), &a).await.err().unwrap().to_string();
+    assert!(err.contains("Remote hash store b.yaml is used by a running sync"), "{}", err);
+    drop(running);
+    drop(HashStoreGuard::new(client, &a).await.unwrap());
+}
@@ -0,0 +1,20 @@
+//! Guards against order-of-magnitude regressions on machines without the
+//! benchmarks in CI; the ceilings are generous, also for debug builds.
+
+use phone_sync::hash_store::HashStore;
+use std::time::{Duration, Instant};
+
+mod fixture_gen;
+
+#[test]
+fn test_store_of_100k_entries_loads_in_time() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("hashes.yaml");
+    fixture_gen::store(100_000).save(&path).unwrap();
+
+    let start = Instant::now();
+    let store = HashStore::load(&path).unwrap();
+    let elapsed = start.elapsed();
+    assert_eq!(store.regular_hashes.len(), 100_000);
+    assert!(elapsed < Duration::from_secs(30), "loading 100k entries took {:?}", elapsed);
+}
@@ -0,0 +1,75 @@
+use phone_sync::config::Config;
+use phone_sync::sync::sync;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// Names ending in a space, with a tab, and with a control character.
+const NAMES: [&str; 3] = ["trailing.txt ", "tab\there.txt", "bell\u{7}.txt"];
+
+fn config(server: &StubServer, work: &Path, extra: &str) -> Config {
+    let data = work.join("data");
+    fs::create_dir_all(&data).unwrap();
+    for name in NAMES {
+        fs::write(data.join(name), name).unwrap();
+    }
+    TestConfig::in_work_dir(&server.url, work).yaml(extra).build()
+}
+
+#[tokio::test]
+async fn test_names_changed_by_the_server_are_uploaded_once() {
+    let server = StubServer::start().await;
+    server.trim_names();
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "");
+
+    assert_eq!(sync(&config).await.unwrap().uploaded, 3);
+    let report = sync(&config).await.unwrap();
+    assert_eq!((report.uploaded, report.skipped), (0, 3));
+
+    let mut stored = server.paths();
+    stored.retain(|p| p != "hashes.yaml");
+    assert_eq!(stored, vec!["bell.txt", "tabhere.txt", "trailing.txt"]);
+}
+
+#[tokio::test]
+async fn test_names_changed_by_the_server_survive_mirror_deletions() {
+    let server = StubServer::start().await;
+    server.trim_names();
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "mirror_deletions: true\n");
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 3);
+    assert!(report.deleted.is_empty(), "{:?}", report.deleted);
+    for path in ["bell.txt", "tabhere.txt", "trailing.txt"] {
+        assert!(server.file(path).is_some(), "{}", path);
+    }
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!((report.uploaded, report.skipped), (0, 3));
+    assert!(report.deleted.is_empty(), "{:?}", report.deleted);
+}
+
+#[tokio::test]
+async fn test_problem_names_are_normalized() {
+    let server = StubServer::start().await;
+    server.trim_names();
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "normalize_problem_names: true\nmirror_deletions: true\n");
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 3);
+    assert_eq!(report.renamed["tab\there.txt"], "tab_here.txt");
+    assert_eq!(report.renamed["bell\u{7}.txt"], "bell_.txt");
+    assert_eq!(report.renamed["trailing.txt "], "trailing.txt");
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!((report.uploaded, report.skipped), (0, 3));
+    assert!(report.deleted.is_empty(), "{:?}", report.deleted);
+    for path in ["bell_.txt", "tab_here.txt", "trailing.txt"] {
+        assert!(server.file(path).is_some(), "{}", path);
+    }
+}
@@ -0,0 +1,40 @@
+use phone_sync::profile::Phase;
+use phone_sync::sync::sync;
+use std::fs;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+#[tokio::test]
+async fn test_profile_phases_and_slowest_files() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    for i in 0..5 {
+        fs::write(data.join(format!("small{}.txt", i)), "x").unwrap();
+    }
+    fs::write(data.join("large.bin"), vec![7u8; 16 * 1024 * 1024]).unwrap();
+    let config = TestConfig::in_work_dir(&server.url, work.path()).build();
+
+    let profile = sync(&config).await.unwrap().profile;
+
+    for phase in [Phase::Scan, Phase::Hash, Phase::RemoteCheck, Phase::Upload, Phase::Finalize] {
+        assert!(profile.phases.contains_key(&phase), "missing {:?}", phase);
+    }
+    assert_eq!(profile.phases[&Phase::Upload].files, 6);
+    assert_eq!(profile.phases[&Phase::Upload].bytes, 16 * 1024 * 1024 + 5);
+    // Everything outside the phases is bookkeeping and should be small.
+    let accounted = profile.phase_micros();
+    assert!(accounted <= profile.total_micros, "{} > {}", accounted, profile.total_micros);
+    assert!(accounted * 2 >= profile.total_micros, "{} of {}", accounted, profile.total_micros);
+
+    let memory = profile.store_memory.unwrap();
+    assert_eq!(memory.entries, 6);
+    assert!(memory.approximate_bytes > 6 * 64, "{}", memory.approximate_bytes);
+    assert!(profile.table().contains("hash store: 6 entries, ~"));
+
+    assert_eq!(profile.slowest.len(), 6);
+    assert_eq!(profile.slowest[0].path, "large.bin");
+    assert!(profile.slowest.windows(2).all(|w| w[0].micros >= w[1].micros));
+}
@@ -0,0 +1,85 @@
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::progress::{FileDone, JsonObserver, ProgressEvent, ProgressLine, PROGRESS_SCHEMA_VERSION};
+use phone_sync::sync::sync_observed;
+use phone_sync::webdav_client::WebDavClient;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// Output shared with the test after the observer took it.
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_progress_events_are_json_lines_in_order() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(data.join("DCIM")).unwrap();
+    fs::write(data.join("DCIM/big.jpg"), vec![1u8; 256 * 1024]).unwrap();
+    fs::write(data.join("notes.txt"), "notes").unwrap();
+    fs::write(data.join("skip.tmp"), "temporary").unwrap();
+    let config = TestConfig::new(&server.url, &work.path().join("hashes.yaml"))
+        .yaml(&format!("folders:\n- path: \"{}\"\n  extensions: [jpg, txt]\n", data.display()))
+        .build();
+    let client = WebDavClient::from_config(&config).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), &config).await.unwrap();
+    let out = Captured::default();
+    let observer = Arc::new(JsonObserver::new(Box::new(out.clone())));
+    sync_observed(&config, &client, &mut guard, observer, false, &FilterSet::default()).await.unwrap();
+    guard.finalize().await.unwrap();
+
+    let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<ProgressLine> =
+        text.lines().map(|l| serde_json::from_str(l).unwrap_or_else(|e| panic!("{}: {}", l, e))).collect();
+    assert!(lines.iter().all(|l| l.v == PROGRESS_SCHEMA_VERSION));
+    let events: Vec<ProgressEvent> = lines.into_iter().map(|l| l.event).collect();
+
+    // The count comes first and ends with the total.
+    let scans = events.iter().take_while(|e| matches!(e, ProgressEvent::ScanProgress { .. })).count();
+    assert_eq!(events[scans - 1], ProgressEvent::ScanProgress { files: 3, done: true });
+
+    // Per file: start, progress while uploading, exactly one done.
+    let mut open: HashMap<String, u64> = HashMap::new();
+    let mut done: HashMap<String, FileDone> = HashMap::new();
+    let mut progressed = Vec::new();
+    for event in &events[scans..events.len() - 1] {
+        match event {
+            ProgressEvent::FileStart { path, size } => assert!(open.insert(path.clone(), *size).is_none()),
+            ProgressEvent::FileProgress { path, bytes } => {
+                assert!(*bytes <= open[path], "progress of {} outside its start and done", path);
+                progressed.push(path.clone());
+            }
+            ProgressEvent::FileDone { path, outcome } => {
+                open.remove(path);
+                assert!(done.insert(path.clone(), outcome.clone()).is_none(), "{} done twice", path);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    assert!(open.is_empty(), "never done: {:?}", open);
+    assert_eq!(done["DCIM/big.jpg"], FileDone::Uploaded);
+    assert_eq!(done["notes.txt"], FileDone::Uploaded);
+    assert_eq!(done["skip.tmp"], FileDone::Ignored);
+    assert!(progressed.contains(&"DCIM/big.jpg".to_string()));
+
+    let ProgressEvent::Summary { report } = events.last().unwrap() else {
+        panic!("last event is not the summary");
+    };
+    assert_eq!(report.uploaded, 2);
+}
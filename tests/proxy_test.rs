@@ -0,0 +1,55 @@
+use phone_sync::config::Config;
+use phone_sync::webdav_client::WebDavClient;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Plain HTTP proxy that answers every request itself with an empty 200,
+/// keeping the request heads it received.
+async fn start_proxy() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let heads = Arc::new(Mutex::new(Vec::new()));
+    let seen = heads.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let seen = seen.clone();
+            tokio::spawn(async move {
+                let mut head = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => head.extend_from_slice(&buf[..n]),
+                    }
+                }
+                seen.lock().unwrap().push(String::from_utf8_lossy(&head).to_string());
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+            });
+        }
+    });
+    (url, heads)
+}
+
+#[tokio::test]
+async fn test_requests_go_through_the_configured_proxy() {
+    let (proxy_url, heads) = start_proxy().await;
+    let config = Config::parse(&format!(
+        "webdav_url: \"http://dav.example.invalid/remote\"\nfolders: [a]\nproxy_url: \"{}\"\n\
+         proxy_username: me\nproxy_password: secret\n",
+        proxy_url
+    ))
+    .unwrap();
+    let client = WebDavClient::from_config(&config).unwrap();
+    // The server name never resolves; only the proxy can answer.
+    assert!(client.stat("a.txt").await.unwrap().is_some());
+
+    let heads = heads.lock().unwrap();
+    assert_eq!(heads.len(), 1);
+    let head = heads[0].to_lowercase();
+    assert!(head.starts_with("head http://dav.example.invalid/remote/a.txt http/1.1\r\n"), "{}", head);
+    assert!(head.contains("proxy-authorization: basic "), "{}", head);
+    // base64 of "me:secret"
+    assert!(heads[0].contains("bWU6c2VjcmV0\r\n"), "{}", heads[0]);
+}
@@ -0,0 +1,201 @@
+#![cfg(unix)]
+
+use phone_sync::config::Config;
+use phone_sync::hash_store::HashStore;
+use phone_sync::pull::{pull, store_for_pull, PullReport, PullState};
+use phone_sync::webdav_client::{part_path, WebDavClient};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+const FILES: [&str; 4] = ["photos/a.jpg", "photos/b.jpg", "photos/c.jpg", "photos/d.jpg"];
+
+fn content(path: &str) -> Vec<u8> {
+    path.bytes().cycle().take(if path.ends_with("c.jpg") { 30_000 } else { 100 }).collect()
+}
+
+/// Serve `FILES` with ETags and a remote hash store listing them.
+fn populate(server: &StubServer) {
+    let mut store = HashStore::default();
+    for path in FILES {
+        server.put_file(path, &content(path));
+        server.set_header(path, "ETag", &format!("\"{}-v1\"", path));
+        store.regular_hashes.insert(path.to_string(), format!("{:x}", Sha256::digest(content(path))));
+    }
+    server.put_file("hashes.yaml", serde_yaml::to_string(&store).unwrap().as_bytes());
+}
+
+fn config(server: &StubServer, work: &Path) -> Config {
+    TestConfig::new(&server.url, &work.join("hashes.yaml"))
+        .folders(&[&work.join("restore")])
+        .path("pull_state_path", &work.join("pull_state.yaml"))
+        .yaml("target_dir: photos\n")
+        .build()
+}
+
+async fn run(config: &Config, restart: bool) -> PullReport {
+    let client = WebDavClient::from_config(config).unwrap();
+    let store = store_for_pull(config, &client).await.unwrap();
+    pull(config, &client, &store, &[], restart, &CancellationToken::new()).await.unwrap()
+}
+
+/// Pull until the stalled download of `c.jpg` has written its first bytes, then cancel.
+async fn interrupted_run(server: &StubServer, config: &Config) -> PullReport {
+    server.stall("photos/c.jpg", 10_000);
+    let client = WebDavClient::from_config(config).unwrap();
+    let store = store_for_pull(config, &client).await.unwrap();
+    let cancel = CancellationToken::new();
+    let part = part_path(&Path::new(&config.folders[0].path).join("c.jpg"));
+    let interrupt = async {
+        while fs::metadata(&part).map(|m| m.len()).unwrap_or(0) < 10_000 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        cancel.cancel();
+    };
+    let (report, ()) = tokio::join!(pull(config, &client, &store, &[], false, &cancel), interrupt);
+    server.unstall("photos/c.jpg");
+    report.unwrap()
+}
+
+fn gets(server: &StubServer) -> Vec<(String, Option<String>)> {
+    server.requests().into_iter().filter(|r| r.method == "GET").map(|r| (r.path, r.range)).collect()
+}
+
+#[tokio::test]
+async fn test_interrupted_pull_continues_where_it_stopped() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    populate(&server);
+    let config = config(&server, work.path());
+    let restore = work.path().join("restore");
+
+    let report = interrupted_run(&server, &config).await;
+    assert!(report.interrupted);
+    assert_eq!(report.downloaded, vec!["photos/a.jpg", "photos/b.jpg"]);
+    let state = PullState::load(&config.pull_state_path).unwrap();
+    assert_eq!(state.completed.keys().collect::<Vec<_>>(), vec!["photos/a.jpg", "photos/b.jpg"]);
+    assert_eq!(state.in_flight.unwrap().remote_path, "photos/c.jpg");
+    assert!(!restore.join("c.jpg").exists());
+    let before: Vec<(u64, i64)> =
+        ["a.jpg", "b.jpg"].iter().map(|f| fs::metadata(restore.join(f)).unwrap()).map(|m| (m.ino(), m.mtime_nsec())).collect();
+
+    server.clear_requests();
+    let report = run(&config, false).await;
+    assert!(!report.interrupted);
+    assert_eq!(report.skipped, 2);
+    assert_eq!(report.resumed, vec!["photos/c.jpg"]);
+    assert_eq!(report.downloaded, vec!["photos/c.jpg", "photos/d.jpg"]);
+    assert_eq!(
+        gets(&server),
+        vec![
+            ("hashes.yaml".to_string(), None),
+            ("photos/c.jpg".to_string(), Some("bytes=10000-".to_string())),
+            ("photos/d.jpg".to_string(), None),
+        ]
+    );
+    assert!(server.requests().iter().all(|r| !r.path.ends_with("a.jpg") && !r.path.ends_with("b.jpg")));
+    let after: Vec<(u64, i64)> =
+        ["a.jpg", "b.jpg"].iter().map(|f| fs::metadata(restore.join(f)).unwrap()).map(|m| (m.ino(), m.mtime_nsec())).collect();
+    assert_eq!(before, after);
+    for path in FILES {
+        assert_eq!(fs::read(restore.join(&path["photos/".len()..])).unwrap(), content(path));
+    }
+    assert!(!part_path(&restore.join("c.jpg")).exists());
+    assert_eq!(PullState::load(&config.pull_state_path).unwrap().in_flight, None);
+
+    // Nothing is left to download.
+    server.clear_requests();
+    let report = run(&config, false).await;
+    assert_eq!(report.skipped, 4);
+    assert_eq!(gets(&server), vec![("hashes.yaml".to_string(), None)]);
+}
+
+#[tokio::test]
+async fn test_changed_remote_file_is_not_resumed() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    populate(&server);
+    let config = config(&server, work.path());
+    interrupted_run(&server, &config).await;
+
+    let changed: Vec<u8> = b"rewritten".repeat(4000);
+    server.put_file("photos/c.jpg", &changed);
+    server.clear_headers("photos/c.jpg");
+    server.set_header("photos/c.jpg", "ETag", "\"c-v2\"");
+    server.clear_requests();
+
+    let report = run(&config, false).await;
+    assert!(report.resumed.is_empty());
+    assert!(gets(&server).contains(&("photos/c.jpg".to_string(), None)));
+    assert_eq!(fs::read(work.path().join("restore/c.jpg")).unwrap(), changed);
+}
+
+#[tokio::test]
+async fn test_restart_pull_discards_progress() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    populate(&server);
+    let config = config(&server, work.path());
+    interrupted_run(&server, &config).await;
+    server.clear_requests();
+
+    let report = run(&config, true).await;
+    assert!(report.resumed.is_empty());
+    assert_eq!(report.downloaded, vec!["photos/c.jpg", "photos/d.jpg"]);
+    // Files already on disk are confirmed against the hash store, not fetched again.
+    assert_eq!(report.skipped, 2);
+    assert!(gets(&server).contains(&("photos/c.jpg".to_string(), None)));
+    assert_eq!(fs::read(work.path().join("restore/c.jpg")).unwrap(), content("photos/c.jpg"));
+}
+
+#[tokio::test]
+async fn test_pull_keeps_local_files_with_other_content() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    populate(&server);
+    let config = config(&server, work.path());
+    fs::create_dir_all(work.path().join("restore")).unwrap();
+    fs::write(work.path().join("restore/b.jpg"), "edited locally").unwrap();
+
+    let report = run(&config, false).await;
+    assert_eq!(report.kept_local, vec!["photos/b.jpg"]);
+    assert_eq!(report.downloaded.len(), 3);
+    assert_eq!(fs::read(work.path().join("restore/b.jpg")).unwrap(), b"edited locally");
+}
+
+#[tokio::test]
+async fn test_over_long_remote_name_is_skipped_or_renamed() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    populate(&server);
+    let long = format!("photos/{}.jpg", "n".repeat(300));
+    server.put_file(&long, b"long");
+    let mut store: HashStore = serde_yaml::from_slice(&server.file("hashes.yaml").unwrap()).unwrap();
+    store.regular_hashes.insert(long.clone(), format!("{:x}", Sha256::digest(b"long")));
+    server.put_file("hashes.yaml", serde_yaml::to_string(&store).unwrap().as_bytes());
+    let mut config = config(&server, work.path());
+
+    let report = run(&config, false).await;
+    assert_eq!((report.downloaded.len(), report.failed.clone()), (4, vec![long.clone()]));
+    assert!(report.renamed.is_empty());
+
+    config.pull_sanitize_local = true;
+    let report = run(&config, false).await;
+    assert_eq!(report.downloaded, vec![long.clone()]);
+    let local = Path::new(&report.renamed[&long]).to_path_buf();
+    let name = local.file_name().unwrap().to_str().unwrap();
+    assert!(name.len() <= 250 && name.starts_with("nnn") && name.ends_with(".jpg"), "{}", name);
+    assert_eq!(fs::read(&local).unwrap(), b"long");
+    assert!(phone_sync::output::HumanDisplay::human(&report).contains("renamed locally: photos/nnn"));
+
+    // The next pull finds the file under the same name.
+    let report = run(&config, false).await;
+    assert_eq!((report.downloaded.len(), report.skipped), (0, 5));
+    assert_eq!(report.renamed[&long], local.display().to_string());
+}
@@ -0,0 +1,75 @@
+use phone_sync::config::Config;
+use phone_sync::sync::sync;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config(server: &StubServer, work: &Path, extra: &str) -> Config {
+    let data = work.join("data");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(data.join("video.mp4"), vec![b'x'; 5000]).unwrap();
+    TestConfig::in_work_dir(&server.url, work).yaml(extra).build()
+}
+
+#[tokio::test]
+async fn test_sync_stops_before_uploading_without_enough_quota() {
+    let server = StubServer::start().await;
+    server.set_quota(1000);
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "");
+
+    let err = sync(&config).await.err().unwrap().to_string();
+    assert!(err.contains("Not enough space on the server") && err.contains("fail_on_insufficient_quota"), "{}", err);
+    assert_eq!(server.count("PUT"), 0);
+
+    // Enough space once the file is uploaded and recorded.
+    server.set_quota(10_000);
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    server.set_quota(0);
+    assert_eq!(sync(&config).await.unwrap().uploaded, 0);
+}
+
+#[tokio::test]
+async fn test_unlimited_quota_or_opt_out_still_syncs() {
+    let server = StubServer::start().await;
+    server.set_quota(-3);
+    let work = tempfile::tempdir().unwrap();
+    assert_eq!(sync(&config(&server, work.path(), "")).await.unwrap().uploaded, 1);
+
+    let server = StubServer::start().await;
+    server.set_quota(1000);
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "fail_on_insufficient_quota: false\n");
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+}
+
+#[tokio::test]
+async fn test_low_memory_skips_the_quota_walk() {
+    let server = StubServer::start().await;
+    server.set_quota(1000);
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "low_memory: true\n");
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+}
+
+#[tokio::test]
+async fn test_refused_quota_request_is_an_error() {
+    let server = StubServer::start().await;
+    server.set_quota(1000);
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(5)).unwrap();
+
+    server.fail_next("PROPFIND", 1, 403);
+    let err = client.quota().await.err().unwrap().to_string();
+    assert!(err.contains("Authentication failed while reading the quota"), "{}", err);
+    server.fail_next("PROPFIND", 1, 500);
+    assert!(client.quota().await.is_err());
+
+    // Servers that cannot tell have no quota to report.
+    server.fail_next("PROPFIND", 1, 405);
+    assert!(client.quota().await.unwrap().is_none());
+    assert_eq!(client.quota().await.unwrap().unwrap().available, Some(1000));
+}
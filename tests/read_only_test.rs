@@ -0,0 +1,81 @@
+use phone_sync::config::Config;
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::sync::sync_with_guard;
+use phone_sync::webdav_client::{ReadOnlyViolation, WebDavClient};
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config(server: &StubServer, data: &Path, work: &Path, read_only: bool) -> Config {
+    TestConfig::new(&server.url, &work.join("hashes.yaml"))
+        .folders(&[data])
+        .yaml(&format!("mirror_deletions: true\nread_only: {}\n", read_only))
+        .build()
+}
+
+async fn sync(config: &Config) -> (WebDavClient, phone_sync::report::SyncReport) {
+    let client = WebDavClient::from_config(config).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), config).await.unwrap();
+    let report = sync_with_guard(config, &client, &mut guard, false, false, &FilterSet::default()).await.unwrap();
+    guard.finalize().await.unwrap();
+    (client, report)
+}
+
+#[tokio::test]
+async fn test_read_only_sync_sends_no_writes() {
+    let server = StubServer::start().await;
+    let data = tempfile::tempdir().unwrap();
+    let work = tempfile::tempdir().unwrap();
+    fs::write(data.path().join("kept.txt"), "kept").unwrap();
+    fs::write(data.path().join("changed.txt"), "old").unwrap();
+    fs::write(data.path().join("removed.txt"), "removed").unwrap();
+    sync(&config(&server, data.path(), work.path(), false)).await;
+
+    fs::write(data.path().join("changed.txt"), "new content").unwrap();
+    fs::write(data.path().join("added.txt"), "added").unwrap();
+    fs::remove_file(data.path().join("removed.txt")).unwrap();
+    let store_before = fs::read(work.path().join("hashes.yaml")).unwrap();
+    server.clear_requests();
+
+    let (client, report) = sync(&config(&server, data.path(), work.path(), true)).await;
+
+    let methods: Vec<String> = server.requests().into_iter().map(|r| r.method).collect();
+    assert!(!methods.is_empty());
+    // PROPFIND only reads, here the quota of the root.
+    assert!(methods.iter().all(|m| ["GET", "HEAD", "PROPFIND"].contains(&m.as_str())), "{:?}", methods);
+    let mut planned = report.planned.clone();
+    planned.sort();
+    assert_eq!(planned, vec!["added.txt", "changed.txt"]);
+    assert_eq!(report.planned_deletions, vec!["removed.txt"]);
+    assert_eq!(report.uploaded, 0);
+    assert_eq!(report.skipped, 1);
+    assert!(client.blocked_writes().is_empty());
+    assert_eq!(client.read_only_summary().as_deref(), Some("Read-only: 0 write operations attempted"));
+
+    assert_eq!(server.file("changed.txt").as_deref(), Some(&b"old"[..]));
+    assert!(server.file("removed.txt").is_some());
+    assert_eq!(fs::read(work.path().join("hashes.yaml")).unwrap(), store_before);
+}
+
+#[tokio::test]
+async fn test_read_only_client_blocks_writes() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let local = work.path().join("a.txt");
+    fs::write(&local, "a").unwrap();
+    let client = WebDavClient::from_config(&config(&server, work.path(), work.path(), true)).unwrap();
+
+    let err = client.upload_file(&local, "dir/a.txt").await.unwrap_err();
+    let violation = err.downcast_ref::<ReadOnlyViolation>().expect("a ReadOnlyViolation");
+    assert_eq!(violation.method, "MKCOL");
+    assert!(client.delete_file("dir/a.txt").await.is_err());
+
+    assert_eq!(server.requests().len(), 0);
+    assert_eq!(client.blocked_writes().len(), 2);
+    let summary = client.read_only_summary().unwrap();
+    assert!(summary.starts_with("Read-only: 2 write operations blocked"), "{}", summary);
+    assert!(summary.contains("DELETE dir/a.txt"), "{}", summary);
+}
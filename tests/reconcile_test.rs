@@ -0,0 +1,61 @@
+use phone_sync::config::Config;
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store::HashStore;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::reconcile::{self, Move};
+use phone_sync::sync::{sync, sync_with_guard};
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config_for(url: &str, work: &Path, target_dir: &str) -> Config {
+    TestConfig::new(url, &work.join("hashes.yaml"))
+        .yaml(&format!(
+            "folders: [{{path: \"{}\", id: camera}}]\ntarget_dir: \"{}\"\n",
+            work.join("data").display(),
+            target_dir
+        ))
+        .build()
+}
+
+#[tokio::test]
+async fn test_reconcile_collapses_entries_of_an_earlier_target_dir() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    fs::create_dir_all(work.path().join("data/DCIM")).unwrap();
+    fs::write(work.path().join("data/DCIM/a.jpg"), "a").unwrap();
+    fs::write(work.path().join("data/b.jpg"), "b").unwrap();
+    sync(&config_for(&server.url, work.path(), "phone/2024")).await.unwrap();
+
+    // The next run goes to a new target_dir, but b.jpg is filtered out.
+    let config = config_for(&server.url, work.path(), "phone/2025");
+    let client = WebDavClient::from_config(&config).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), &config).await.unwrap();
+    let filters = FilterSet { exclude: vec!["b.jpg".to_string()], ..Default::default() };
+    let report = sync_with_guard(&config, &client, &mut guard, false, false, &filters).await.unwrap();
+    guard.finalize().await.unwrap();
+    drop(guard);
+    assert_eq!(report.uploaded, 1);
+
+    let store = HashStore::load(&config.hash_store_path).unwrap();
+    assert_eq!(store.regular_hashes.len(), 3);
+    let plan = reconcile::plan(&config, &store);
+    assert_eq!(plan.moves, vec![Move { from: "phone/2024/b.jpg".to_string(), to: "phone/2025/b.jpg".to_string() }]);
+    assert_eq!(plan.deletions, vec!["phone/2024/DCIM/a.jpg"]);
+
+    let mut guard = HashStoreGuard::whole(client.clone(), &config).await.unwrap();
+    reconcile::execute(&client, guard.hash_store_mut(), &plan).await.unwrap();
+    guard.finalize().await.unwrap();
+    drop(guard);
+    let files: Vec<String> = server.paths().into_iter().filter(|p| p.starts_with("phone/")).collect();
+    assert_eq!(files, vec!["phone/2025/DCIM/a.jpg", "phone/2025/b.jpg"]);
+    let store = HashStore::load(&config.hash_store_path).unwrap();
+    assert_eq!(store.regular_hashes.keys().collect::<Vec<_>>(), vec!["phone/2025/DCIM/a.jpg", "phone/2025/b.jpg"]);
+    assert!(reconcile::plan(&config, &store).is_empty());
+
+    // Nothing is uploaded again.
+    assert_eq!(sync(&config).await.unwrap().uploaded, 0);
+}
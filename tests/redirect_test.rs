@@ -0,0 +1,55 @@
+use phone_sync::sync::sync;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+#[tokio::test]
+async fn test_redirect_on_the_same_server_keeps_the_credentials() {
+    let server = StubServer::start().await;
+    server.redirect("dav", "/remote.php/dav/files/me");
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(data.join("a.jpg"), b"photo").unwrap();
+    let config = TestConfig::in_work_dir(&format!("{}/dav", server.url), work.path())
+        .yaml("username: me\npassword: secret\n")
+        .build();
+
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    assert_eq!(server.file("remote.php/dav/files/me/a.jpg").unwrap(), b"photo");
+    let requests = server.requests();
+    assert!(requests.iter().all(|r| r.authorization.is_some()), "{:?}", requests);
+    // Once the base URL is known to have moved, requests go there directly.
+    assert_eq!(requests.iter().filter(|r| r.path.starts_with("dav")).count(), 1, "{:?}", requests);
+
+    let client = WebDavClient::from_config(&config).unwrap();
+    let names: Vec<String> = client.list("").await.unwrap().into_iter().map(|e| e.path).collect();
+    assert!(names.contains(&"a.jpg".to_string()), "{:?}", names);
+}
+
+#[tokio::test]
+async fn test_redirect_to_another_server_is_not_followed() {
+    let server = StubServer::start().await;
+    server.redirect("dav", "http://other.invalid/dav");
+    let url = format!("{}/dav", server.url);
+    let client = WebDavClient::new(&url, Some("me"), Some("secret"), Duration::from_secs(3)).unwrap();
+
+    let err = client.stat("a.jpg").await.unwrap_err().to_string();
+    assert!(err.contains("another server") && err.contains("http://other.invalid/dav/a.jpg"), "{}", err);
+    assert_eq!(server.count("HEAD"), 1);
+}
+
+#[tokio::test]
+async fn test_redirect_loop_gives_up() {
+    let server = StubServer::start().await;
+    server.redirect("a", "/b");
+    server.redirect("b", "/a");
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+
+    let err = client.stat("a/x.jpg").await.unwrap_err().to_string();
+    assert!(err.contains("Too many redirects"), "{}", err);
+    assert_eq!(server.count("HEAD"), 6);
+}
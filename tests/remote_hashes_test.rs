@@ -8,7 +8,6 @@ use std::fs;
 use tempfile::NamedTempFile;
 use tokio::time::{sleep, Duration};
 use serial_test::serial;
-use serde_yaml;
 
 use ctor::{ctor, dtor};
 
@@ -76,7 +75,7 @@ async fn test_sync_uses_remote_hashes_yaml() {
         &config.webdav_url,
         config.username.as_deref(),
         config.password.as_deref(),
-        config.timeout_secs,
+        config.timeout,
     )
     .expect("failed to create WebDav client");
     client
@@ -160,7 +159,7 @@ async fn test_sync_uses_remote_pseudo_hashes_yaml() {
         &config.webdav_url,
         config.username.as_deref(),
         config.password.as_deref(),
-        config.timeout_secs,
+        config.timeout,
     )
     .expect("failed to create WebDav client");
     client
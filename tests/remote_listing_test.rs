@@ -0,0 +1,83 @@
+use phone_sync::config::Config;
+use phone_sync::sync::sync;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+const DIRS: [&str; 3] = ["DCIM/Camera", "DCIM/Screenshots", "Pictures"];
+
+fn config(server: &StubServer, work: &Path) -> Config {
+    for dir in DIRS {
+        let local = work.join("data").join(dir);
+        fs::create_dir_all(&local).unwrap();
+        for i in 0..20 {
+            fs::write(local.join(format!("IMG_{:04}.jpg", i)), format!("{} {}", dir, i)).unwrap();
+        }
+    }
+    // Without the HEAD after each upload, every HEAD is an existence check.
+    TestConfig::in_work_dir(&server.url, work).yaml("remote_hash_store: disabled\nverify_upload_size: false\n").build()
+}
+
+fn listings(server: &StubServer) -> Vec<String> {
+    let mut paths: Vec<String> =
+        server.requests().into_iter().filter(|r| r.method == "PROPFIND" && !r.path.is_empty()).map(|r| r.path).collect();
+    paths.sort();
+    paths
+}
+
+#[tokio::test]
+async fn test_unchanged_files_are_checked_with_one_listing_per_directory() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path());
+    assert_eq!(sync(&config).await.unwrap().uploaded, 60);
+    // New directories are listed once, and their files are not asked for.
+    assert_eq!(listings(&server), ["DCIM/Camera/", "DCIM/Screenshots/", "Pictures/"]);
+    assert_eq!(server.count("HEAD"), 0);
+
+    server.clear_requests();
+    let report = sync(&config).await.unwrap();
+    assert_eq!((report.uploaded, report.skipped), (0, 60));
+    assert_eq!(listings(&server), ["DCIM/Camera/", "DCIM/Screenshots/", "Pictures/"]);
+    assert_eq!(server.count("HEAD"), 0);
+    assert_eq!(server.count("MKCOL"), 0);
+
+    // A file changed on the server is still noticed from the listing.
+    server.put_file("Pictures/IMG_0003.jpg", b"edited elsewhere");
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 1);
+    assert_eq!(server.file("Pictures/IMG_0003.jpg").unwrap(), b"Pictures 3");
+}
+
+#[tokio::test]
+async fn test_files_are_checked_one_by_one_without_listings() {
+    let server = StubServer::start().await;
+    server.fail_next("PROPFIND", 100, 501);
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path());
+
+    assert_eq!(sync(&config).await.unwrap().uploaded, 60);
+    assert_eq!(listings(&server).len(), 3);
+    assert_eq!(server.count("HEAD"), 60);
+}
+
+#[tokio::test]
+async fn test_low_memory_listings_give_the_same_results() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let mut config = config(&server, work.path());
+    config.low_memory = true;
+    config.temp_dir = Some(work.path().join("tmp").display().to_string());
+    assert_eq!(sync(&config).await.unwrap().uploaded, 60);
+
+    server.clear_requests();
+    server.put_file("Pictures/IMG_0003.jpg", b"edited elsewhere");
+    let report = sync(&config).await.unwrap();
+    assert_eq!((report.uploaded, report.skipped), (1, 59));
+    assert_eq!(listings(&server), ["DCIM/Camera/", "DCIM/Screenshots/", "Pictures/"]);
+    assert_eq!(server.count("HEAD"), 0);
+    // The spilled listings are removed with the run.
+    assert_eq!(fs::read_dir(work.path().join("tmp")).unwrap().count(), 0);
+}
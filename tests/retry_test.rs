@@ -0,0 +1,174 @@
+use phone_sync::sync::sync;
+use phone_sync::webdav_client::{RetryEvent, RetryPolicy, WebDavClient};
+use std::fs;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+#[tokio::test]
+async fn test_upload_reports_each_retry() {
+    let server = StubServer::start().await;
+    server.fail_next("PUT", 2, 503);
+    let dir = tempfile::tempdir().unwrap();
+    let local = dir.path().join("a.txt");
+    fs::write(&local, "content").unwrap();
+
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    let policy = RetryPolicy { retries: 5, base_delay: Duration::from_millis(10), ..RetryPolicy::NONE };
+    let mut events: Vec<RetryEvent> = Vec::new();
+    client
+        .upload_file_with_retry(&local, "a.txt", None, None, None, policy, &mut |e| events.push(e.clone()))
+        .await
+        .unwrap();
+
+    let seen: Vec<_> = events.iter().map(|e| (e.attempt, e.delay, e.reason.as_str())).collect();
+    assert_eq!(
+        seen,
+        vec![
+            (1, Duration::from_millis(10), "HTTP 503"),
+            (2, Duration::from_millis(20), "HTTP 503"),
+        ]
+    );
+    assert_eq!(events[1].to_string(), "retry 2/5 in 20ms (HTTP 503)");
+    assert_eq!(server.count("PUT"), 3);
+    assert_eq!(server.file("a.txt").unwrap(), b"content");
+}
+
+#[tokio::test]
+async fn test_upload_gives_up_after_retries() {
+    let server = StubServer::start().await;
+    server.fail_next("PUT", 10, 502);
+    let dir = tempfile::tempdir().unwrap();
+    let local = dir.path().join("a.txt");
+    fs::write(&local, "content").unwrap();
+
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    let policy = RetryPolicy { retries: 1, base_delay: Duration::from_millis(1), ..RetryPolicy::NONE };
+    let err = client
+        .upload_file_with_retry(&local, "a.txt", None, None, None, policy, &mut |_| {})
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("gave up after 1 retries"), "{}", err);
+    assert_eq!(server.count("PUT"), 2);
+}
+
+#[tokio::test]
+async fn test_sync_report_counts_retries() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    fs::create_dir_all(work.path().join("data")).unwrap();
+    fs::write(work.path().join("data").join("a.txt"), "content").unwrap();
+    let config = TestConfig::in_work_dir(&server.url, work.path()).yaml("retry_delay_ms: 5\nretry_jitter: 0\n").build();
+    // The remote store download is a GET, so only the file upload fails.
+    server.fail_next("PUT", 2, 503);
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 1);
+    assert_eq!(report.retries, 2);
+    assert_eq!(report.backoff_ms, 15);
+}
+
+#[tokio::test]
+async fn test_error_status_fails_the_upload_with_the_server_message() {
+    for (status, retries, body) in [(423, 3, "file is locked"), (507, 3, "quota exceeded")] {
+        let server = StubServer::start().await;
+        let work = tempfile::tempdir().unwrap();
+        fs::create_dir_all(work.path().join("data")).unwrap();
+        fs::write(work.path().join("data").join("a.txt"), "content").unwrap();
+        let config = TestConfig::in_work_dir(&server.url, work.path())
+            .yaml(&format!("upload_retries: {}\n", retries))
+            .build();
+        server.fail_next_with_body("PUT", 1, status, body);
+
+        let err = sync(&config).await.unwrap_err().to_string();
+        assert!(err.contains(&status.to_string()) && err.contains(body), "{}", err);
+        // Neither a lock nor a full quota is retried.
+        assert_eq!(server.count("PUT"), 1);
+        let store = phone_sync::hash_store::HashStore::load(&config.hash_store_path).unwrap();
+        assert!(!store.regular_hashes.contains_key("a.txt"));
+    }
+}
+
+#[tokio::test]
+async fn test_failed_upload_keeps_the_previous_remote_file() {
+    let server = StubServer::start().await;
+    server.put_file("a.txt", b"previous");
+    server.fail_next("PUT", 1, 500);
+    let dir = tempfile::tempdir().unwrap();
+    let local = dir.path().join("a.txt");
+    fs::write(&local, "new").unwrap();
+
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    assert!(client.upload_file(&local, "a.txt").await.is_err());
+    assert_eq!(server.count("DELETE"), 0);
+    assert_eq!(server.file("a.txt").unwrap(), b"previous");
+
+    // Only a server refusing to overwrite gets the file deleted first.
+    server.fail_next("PUT", 1, 405);
+    client.upload_file(&local, "a.txt").await.unwrap();
+    let methods: Vec<String> = server.requests().into_iter().map(|r| r.method).collect();
+    assert_eq!(methods, vec!["PUT", "PUT", "DELETE", "PUT"]);
+    assert_eq!(server.file("a.txt").unwrap(), b"new");
+
+    let forced = client.with_force_delete_before_put(true);
+    server.clear_requests();
+    forced.upload_file(&local, "a.txt").await.unwrap();
+    assert_eq!(server.count("DELETE"), 1);
+}
+
+#[tokio::test]
+async fn test_idempotent_requests_retry_transient_failures() {
+    let server = StubServer::start().await;
+    server.put_file("a.txt", b"content");
+    let dir = tempfile::tempdir().unwrap();
+    let policy = RetryPolicy { retries: 3, base_delay: Duration::from_millis(1), jitter: Duration::from_millis(2) };
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap().with_retry_policy(policy);
+
+    server.fail_next("HEAD", 2, 503);
+    assert!(client.stat("a.txt").await.unwrap().is_some());
+    assert_eq!(server.count("HEAD"), 3);
+
+    server.fail_next("GET", 1, 504);
+    client.download_file("a.txt", dir.path().join("a.txt")).await.unwrap();
+    assert_eq!(fs::read(dir.path().join("a.txt")).unwrap(), b"content");
+
+    server.fail_next("MKCOL", 3, 502);
+    let local = dir.path().join("a.txt");
+    client.upload_file(&local, "dir/a.txt").await.unwrap();
+    assert_eq!(server.file("dir/a.txt").unwrap(), b"content");
+
+    // Permission errors fail at once, and retries are bounded.
+    server.clear_requests();
+    server.fail_next("GET", 1, 403);
+    assert!(client.download_file("a.txt", dir.path().join("b.txt")).await.is_err());
+    server.fail_next("DELETE", 10, 503);
+    assert!(client.delete_file("a.txt").await.is_err());
+    assert_eq!((server.count("GET"), server.count("DELETE")), (1, 4));
+}
+
+#[tokio::test]
+async fn test_rejected_credentials_abort_the_sync() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        fs::write(data.join(name), name).unwrap();
+    }
+    let config = TestConfig::in_work_dir(&server.url, work.path()).build();
+    server.fail_next("PROPFIND", 10, 401);
+    server.fail_next("HEAD", 10, 401);
+
+    let err = sync(&config).await.unwrap_err().to_string();
+    assert!(err.contains("Authentication failed"), "{}", err);
+    // The first rejection ends the run instead of walking every file.
+    assert_eq!(server.count("HEAD"), 1);
+    assert_eq!(server.count("PUT"), 0);
+
+    // A file that is really missing is still just missing.
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    server.fail_next("HEAD", 0, 401);
+    assert!(client.stat("missing.txt").await.unwrap().is_none());
+}
@@ -0,0 +1,94 @@
+use phone_sync::config::Config;
+use phone_sync::hash_store::HashStore;
+use phone_sync::sync::sync;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config(server: &StubServer, work: &Path, routes: &str) -> Config {
+    TestConfig::new(&server.url, &work.join("hashes.yaml"))
+        .yaml(&format!(
+            "target_dir: phone\nfolders:\n- path: \"{}\"\n  routes:\n{}",
+            work.join("DCIM").display(),
+            routes
+        ))
+        .build()
+}
+
+fn write(root: &Path, files: &[&str]) {
+    for file in files {
+        let path = root.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, file.as_bytes()).unwrap();
+    }
+}
+
+fn remote_files(server: &StubServer) -> Vec<String> {
+    let mut paths: Vec<String> = server.paths().into_iter().filter(|p| !p.ends_with(".yaml")).collect();
+    paths.sort();
+    paths
+}
+
+#[tokio::test]
+async fn test_routed_tree_is_stable() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    write(&work.path().join("DCIM"), &["Camera/a.jpg", "Camera/b.mp4", "Camera/2024/c.MOV", "d.mov", "Screenshots/e.png"]);
+    let routes = "  - match: \"*.mp4|*.mov|*.MOV\"\n    target_subdir: videos\n  - match: \"Screenshots/*\"\n    target_subdir: shots\n    flatten: true\n";
+    let config = config(&server, work.path(), routes);
+
+    assert_eq!(sync(&config).await.unwrap().uploaded, 5);
+    assert_eq!(
+        remote_files(&server),
+        [
+            "phone/Camera/a.jpg",
+            "phone/shots/e.png",
+            "phone/videos/Camera/2024/c.MOV",
+            "phone/videos/Camera/b.mp4",
+            "phone/videos/d.mov",
+        ]
+    );
+    assert_eq!(server.file("phone/videos/Camera/b.mp4").unwrap(), b"Camera/b.mp4");
+    let store = HashStore::load(&config.hash_store_path).unwrap();
+    assert!(store.regular_hashes.contains_key("phone/videos/d.mov"), "{:?}", store.regular_hashes.keys());
+    assert!(!store.regular_hashes.contains_key("phone/d.mov"));
+
+    server.clear_requests();
+    let second = sync(&config).await.unwrap();
+    assert_eq!(second.uploaded, 0);
+    let requests = server.requests();
+    assert!(!requests.iter().any(|r| r.method == "PUT" && r.path.starts_with("phone/")), "{:?}", requests);
+    assert_eq!(remote_files(&server).len(), 5);
+}
+
+#[tokio::test]
+async fn test_flattened_files_on_one_path_collide() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    write(&work.path().join("DCIM"), &["a/clip.mp4", "b/clip.mp4"]);
+    let config = config(&server, work.path(), "  - { match: \"*.mp4\", target_subdir: videos, flatten: true }\n");
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 1);
+    assert_eq!(report.collisions, ["phone/videos/clip.mp4"]);
+    assert_eq!(remote_files(&server), ["phone/videos/clip.mp4"]);
+}
+
+#[tokio::test]
+async fn test_directory_created_after_loading_collides_with_the_route() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    write(&work.path().join("DCIM"), &["Camera/a.jpg"]);
+    let mut config = config(&server, work.path(), "  - { match: \"Camera/*\", target_subdir: videos }\n");
+    write(&work.path().join("DCIM"), &["videos/Camera/a.jpg"]);
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.collisions, ["phone/videos/Camera/a.jpg"]);
+    assert_eq!(remote_files(&server), ["phone/videos/Camera/a.jpg"]);
+
+    config.collision_policy = phone_sync::config::CollisionPolicy::Error;
+    let err = sync(&config).await.unwrap_err().to_string();
+    assert!(err.contains("both map to remote path phone/videos/Camera/a.jpg"), "{}", err);
+}
@@ -0,0 +1,83 @@
+use phone_sync::config::Config;
+use phone_sync::self_test::{self_test, CheckStatus, SelfTestReport};
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config(server: &StubServer, work: &Path, extra: &str) -> Config {
+    TestConfig::in_work_dir(&server.url, work).yaml("target_dir: photos\n").path("temp_dir", work).yaml(extra).build()
+}
+
+fn statuses(report: &SelfTestReport) -> Vec<(&str, CheckStatus)> {
+    report.checks.iter().map(|c| (c.name.as_str(), c.status)).collect()
+}
+
+#[tokio::test]
+async fn test_self_test_passes_and_cleans_up() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "");
+
+    let report = self_test(&config, "st").await.unwrap();
+    assert!(report.passed(), "{:?}", report);
+    assert_eq!(report.remote_dir, "photos/st");
+    assert_eq!(
+        statuses(&report),
+        vec![
+            ("initial upload", CheckStatus::Passed),
+            ("content round trip", CheckStatus::Passed),
+            ("skip unchanged", CheckStatus::Passed),
+            ("overwrite changed", CheckStatus::Passed),
+            ("hash store round trip", CheckStatus::Passed),
+            ("mirror deletions", CheckStatus::Skipped),
+            ("cleanup", CheckStatus::Passed),
+        ]
+    );
+    assert!(server.paths().is_empty(), "{:?}", server.paths());
+    // Neither the user's hash store nor any temp files are left behind.
+    assert_eq!(std::fs::read_dir(work.path()).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn test_self_test_checks_deletion_when_mirroring() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "mirror_deletions: true\n");
+
+    let report = self_test(&config, "st").await.unwrap();
+    assert!(report.passed(), "{:?}", report);
+    assert!(statuses(&report).contains(&("mirror deletions", CheckStatus::Passed)));
+    assert!(server.paths().is_empty(), "{:?}", server.paths());
+}
+
+#[tokio::test]
+async fn test_self_test_cleans_up_after_failure() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "");
+    // The server claims a checksum the uploaded content cannot have.
+    server.set_header("photos/st/small.txt", "OC-Checksum", "SHA1:0000000000000000000000000000000000000000");
+
+    let report = self_test(&config, "st").await.unwrap();
+    assert!(!report.passed());
+    let statuses = statuses(&report);
+    assert_eq!(statuses[1], ("content round trip", CheckStatus::Failed));
+    assert_eq!(statuses[2], ("skip unchanged", CheckStatus::Skipped));
+    assert_eq!(statuses.last(), Some(&("cleanup", CheckStatus::Passed)));
+    assert!(report.checks[1].detail.as_deref().unwrap().contains("Corrupted download"));
+    assert!(server.paths().is_empty(), "{:?}", server.paths());
+}
+
+#[tokio::test]
+async fn test_self_test_refuses_existing_remote_dir() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "");
+    server.put_file("photos/st", b"not mine");
+
+    let err = self_test(&config, "st").await.unwrap_err();
+    assert!(err.to_string().contains("already exists"), "{}", err);
+    assert_eq!(server.file("photos/st").as_deref(), Some(&b"not mine"[..]));
+    assert_eq!(server.count("PUT"), 0);
+}
@@ -0,0 +1,48 @@
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::sync::sync_with_guard;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// Paths of the files uploaded by a first sync of `data`, in upload order.
+async fn upload_order(data: &Path, extra: &str) -> Vec<String> {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = TestConfig::new(&server.url, &work.path().join("hashes.yaml")).folders(&[data]).yaml(extra).build();
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), &config).await.unwrap();
+    sync_with_guard(&config, &client, &mut guard, false, false, &FilterSet::default()).await.unwrap();
+    server
+        .requests()
+        .into_iter()
+        .filter(|r| r.method == "PUT")
+        .map(|r| r.path)
+        .collect()
+}
+
+#[tokio::test]
+async fn test_shuffled_order_follows_seed() {
+    let data = tempfile::tempdir().unwrap();
+    for i in 0..20 {
+        fs::write(data.path().join(format!("f{:02}.jpg", i)), "x").unwrap();
+    }
+
+    let default = upload_order(data.path(), "").await;
+    let first = upload_order(data.path(), "upload_order: shuffle\nshuffle_seed: 42\n").await;
+    let again = upload_order(data.path(), "upload_order: shuffle\nshuffle_seed: 42\n").await;
+    let other = upload_order(data.path(), "upload_order: shuffle\nshuffle_seed: 43\n").await;
+
+    assert_eq!(first, again);
+    assert_ne!(first, other);
+    assert_ne!(first, default);
+    let mut sorted = first.clone();
+    sorted.sort();
+    let mut expected = default.clone();
+    expected.sort();
+    assert_eq!(sorted, expected);
+}
@@ -0,0 +1,91 @@
+use phone_sync::config::Config;
+use phone_sync::filter::FilterSet;
+use phone_sync::folder_kind::FolderKind;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::output::HumanDisplay;
+use phone_sync::progress::SyncObserver;
+use phone_sync::report::TransferStats;
+use phone_sync::sync::{sync, sync_observed};
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// Records the totals of the counting walk.
+#[derive(Default)]
+struct Totals {
+    files: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl SyncObserver for Totals {
+    fn wants_total(&self) -> bool {
+        true
+    }
+
+    fn scan_progress(&self, files: u64, done: bool) {
+        if done {
+            self.files.store(files, Ordering::Relaxed);
+        }
+    }
+
+    fn scan_size(&self, bytes: u64) {
+        self.bytes.store(bytes, Ordering::Relaxed);
+    }
+}
+
+/// A directory with two files, a single file and a path that does not exist.
+fn config(server: &StubServer, work: &Path) -> Config {
+    let photos = work.join("photos");
+    fs::create_dir_all(photos.join("2024")).unwrap();
+    fs::write(photos.join("a.jpg"), "a").unwrap();
+    fs::write(photos.join("2024/b.jpg"), "bb").unwrap();
+    fs::write(work.join("notes.txt"), "notes").unwrap();
+    TestConfig::new(&server.url, &work.join("hashes.yaml"))
+        .folders(&[&photos, &work.join("notes.txt"), &work.join("gone")])
+        .yaml("target_dir: phone\n")
+        .build()
+}
+
+#[tokio::test]
+async fn test_file_folder_is_synced_under_its_name() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path());
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 3);
+    assert_eq!(server.file("phone/notes.txt").unwrap(), b"notes");
+    assert_eq!(server.file("phone/2024/b.jpg").unwrap(), b"bb");
+
+    let kinds: Vec<FolderKind> = config.folders.iter().map(|f| report.folder_kinds[&f.path]).collect();
+    assert_eq!(kinds, vec![FolderKind::Directory, FolderKind::File, FolderKind::Missing]);
+    let notes_id = config.folder_id(&config.folders[1]);
+    assert_eq!(report.folders[&notes_id], TransferStats { uploaded: 1, skipped: 0, bytes_uploaded: 5 });
+    let human = report.human();
+    assert!(human.contains(&format!("single file: {}", config.folders[1].path)), "{}", human);
+    assert!(human.contains(&format!("does not exist, skipped: {}", config.folders[2].path)), "{}", human);
+
+    // Unchanged, the file is skipped like any other.
+    let report = sync(&config).await.unwrap();
+    assert_eq!((report.uploaded, report.skipped), (0, 3));
+}
+
+#[tokio::test]
+async fn test_file_folder_counts_towards_progress_totals() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path());
+
+    let client = WebDavClient::from_config(&config).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), &config).await.unwrap();
+    let totals = Arc::new(Totals::default());
+    sync_observed(&config, &client, &mut guard, totals.clone(), false, &FilterSet::default()).await.unwrap();
+    guard.finalize().await.unwrap();
+    assert_eq!(totals.files.load(Ordering::Relaxed), 3);
+    assert_eq!(totals.bytes.load(Ordering::Relaxed), 8);
+}
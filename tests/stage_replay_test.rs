@@ -0,0 +1,60 @@
+use phone_sync::config::Config;
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store::HashStore;
+use phone_sync::stage::{replay, stage, StageManifest};
+use std::fs;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config_for(url: &str, folder: &std::path::Path, hash_store_path: &std::path::Path) -> Config {
+    TestConfig::new(url, hash_store_path).folders(&[folder]).yaml("target_dir: \"phone\"\n").build()
+}
+
+#[tokio::test]
+async fn test_stage_then_replay_skips_drifted_file() {
+    let work = tempfile::tempdir().unwrap();
+    let folder = work.path().join("photos");
+    fs::create_dir_all(folder.join("sub")).unwrap();
+    fs::write(folder.join("a.txt"), b"alpha").unwrap();
+    fs::write(folder.join("sub/b.txt"), b"beta").unwrap();
+    fs::write(folder.join("known.txt"), b"already synced").unwrap();
+    let store_path = work.path().join("hashes.yaml");
+    let mut store = HashStore::default();
+    store.regular_hashes.insert(
+        "phone/known.txt".to_string(),
+        HashStore::compute_hash(folder.join("known.txt")).await.unwrap(),
+    );
+    store.save(&store_path).unwrap();
+    let staging = work.path().join("staging");
+
+    // Staging works without any server.
+    let offline = config_for("http://127.0.0.1:9", &folder, &store_path);
+    let manifest = stage(&offline, &staging, true, false, &FilterSet::default()).await.unwrap();
+    let mut staged: Vec<_> = manifest.entries.iter().map(|e| e.remote_path.as_str()).collect();
+    staged.sort();
+    assert_eq!(staged, vec!["phone/a.txt", "phone/sub/b.txt"]);
+    assert_eq!(StageManifest::load(&staging).unwrap(), manifest);
+
+    // The hard-linked original changes before the replay.
+    fs::write(folder.join("sub/b.txt"), b"beta, edited").unwrap();
+
+    let server = StubServer::start().await;
+    let config = config_for(&server.url, &folder, &store_path);
+    let report = replay(&config, &staging).await.unwrap();
+    assert_eq!(report.uploaded, 1);
+    assert_eq!(report.drifted, vec!["phone/sub/b.txt".to_string()]);
+    assert_eq!(server.file("phone/a.txt").unwrap(), b"alpha");
+    assert!(server.file("phone/sub/b.txt").is_none());
+
+    let store = HashStore::load(&store_path).unwrap();
+    assert!(store.regular_hashes.contains_key("phone/a.txt"));
+    assert!(!store.regular_hashes.contains_key("phone/sub/b.txt"));
+
+    // Replaying again resumes: nothing is uploaded twice.
+    server.clear_requests();
+    let report = replay(&config, &staging).await.unwrap();
+    assert_eq!(report.uploaded, 0);
+    assert_eq!(report.already_uploaded, 1);
+    assert_eq!(server.count("PUT"), 1, "only the hash store is uploaded");
+}
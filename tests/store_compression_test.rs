@@ -0,0 +1,97 @@
+use flate2::read::GzDecoder;
+use phone_sync::config::Config;
+use phone_sync::hash_store::HashStore;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::sync::sync;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config(server: &StubServer, work: &Path, extra: &str) -> Config {
+    let data = work.join("data");
+    fs::create_dir_all(&data).unwrap();
+    TestConfig::in_work_dir(&server.url, work).yaml("compress_hash_store: true\n").yaml(extra).build()
+}
+
+fn store_with(count: usize) -> HashStore {
+    let mut store = HashStore::default();
+    for i in 0..count {
+        store.regular_hashes.insert(format!("DCIM/IMG_{:05}.jpg", i), format!("{:064x}", i));
+    }
+    store
+}
+
+fn gunzip(server: &StubServer, path: &str) -> HashStore {
+    serde_yaml::from_reader(GzDecoder::new(&server.file(path).unwrap()[..])).unwrap()
+}
+
+fn gets(server: &StubServer) -> Vec<String> {
+    server.requests().into_iter().filter(|r| r.method == "GET").map(|r| r.path).collect()
+}
+
+#[tokio::test]
+async fn test_plain_store_is_migrated_to_gzip() {
+    let server = StubServer::start().await;
+    let plain = serde_yaml::to_string(&store_with(1000)).unwrap();
+    server.put_file("hashes.yaml", plain.as_bytes());
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "");
+
+    let client = WebDavClient::from_config(&config).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), &config).await.unwrap();
+    assert_eq!(guard.hash_store.regular_hashes.len(), 1000);
+    assert_eq!(gets(&server), ["hashes.yaml.gz", "hashes.yaml"]);
+    guard.hash_store_mut().regular_hashes.insert("new.jpg".to_string(), "n".repeat(64));
+    guard.finalize().await.unwrap();
+
+    let compressed = server.file("hashes.yaml.gz").unwrap();
+    assert!(compressed.len() * 4 < plain.len(), "{} of {} bytes", compressed.len(), plain.len());
+    assert_eq!(gunzip(&server, "hashes.yaml.gz").regular_hashes.len(), 1001);
+    // Left for older versions, which no longer see the changes.
+    assert_eq!(server.file("hashes.yaml").unwrap(), plain.as_bytes());
+
+    drop(guard);
+    server.clear_requests();
+    let guard = HashStoreGuard::new(client, &config).await.unwrap();
+    assert!(guard.hash_store.regular_hashes.contains_key("new.jpg"));
+    assert_eq!(gets(&server), ["hashes.yaml.gz"]);
+}
+
+#[tokio::test]
+async fn test_compressed_name_follows_remote_hash_path() {
+    let server = StubServer::start().await;
+    server.put_file("meta/store.yaml", serde_yaml::to_string(&store_with(2)).unwrap().as_bytes());
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "remote_hash_path: meta/store.yaml.gz\n");
+
+    let mut guard = HashStoreGuard::new(WebDavClient::from_config(&config).unwrap(), &config).await.unwrap();
+    assert_eq!(guard.hash_store.regular_hashes.len(), 2);
+    guard.finalize().await.unwrap();
+    assert_eq!(gunzip(&server, "meta/store.yaml.gz").regular_hashes.len(), 2);
+}
+
+#[tokio::test]
+async fn test_sync_with_compressed_store() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let compressed = config(&server, work.path(), "");
+    fs::write(work.path().join("data/a.jpg"), b"photo").unwrap();
+
+    assert_eq!(sync(&compressed).await.unwrap().uploaded, 1);
+    assert!(gunzip(&server, "hashes.yaml.gz").regular_hashes.contains_key("a.jpg"));
+    assert!(server.file("hashes.yaml").is_none());
+
+    // The next run on another machine knows the file from the store.
+    fs::remove_file(work.path().join("hashes.yaml")).unwrap();
+    assert_eq!(sync(&compressed).await.unwrap().uploaded, 0);
+
+    // Not compressed, the store is where it always was.
+    let other = tempfile::tempdir().unwrap();
+    let plain = Config { compress_hash_store: false, ..config(&server, other.path(), "remote_hash_path: plain.yaml\n") };
+    fs::write(other.path().join("data/b.jpg"), b"photo").unwrap();
+    sync(&plain).await.unwrap();
+    assert!(server.file("plain.yaml").is_some() && server.file("plain.yaml.gz").is_none());
+}
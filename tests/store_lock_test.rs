@@ -0,0 +1,63 @@
+use phone_sync::config::Config;
+use phone_sync::sync::sync;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config(url: &str, work: &Path, lock_wait: &str) -> Config {
+    let data = work.join("data");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(data.join("a.txt"), "a").unwrap();
+    TestConfig::in_work_dir(url, work).yaml(&format!("hash_store_lock_wait: {}\n", lock_wait)).build()
+}
+
+#[tokio::test]
+async fn test_remote_store_is_locked_while_syncing() {
+    let server = StubServer::start().await;
+    server.enable_locking();
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server.url, work.path(), "5");
+
+    assert_eq!(sync(&config).await.unwrap().uploaded, 1);
+    let store: Vec<String> = server
+        .requests()
+        .into_iter()
+        .filter(|r| r.path.starts_with(&config.remote_hash_path) && r.method != "HEAD")
+        .map(|r| r.method)
+        .collect();
+    // The store is staged under a name of the run and moved into place.
+    assert_eq!(store, vec!["LOCK", "GET", "PUT", "MOVE", "UNLOCK"]);
+    assert!(!server.is_locked(&config.remote_hash_path));
+    let uploaded = String::from_utf8(server.file(&config.remote_hash_path).unwrap()).unwrap();
+    assert!(uploaded.contains("a.txt"), "{}", uploaded);
+}
+
+#[tokio::test]
+async fn test_sync_gives_up_on_a_store_locked_by_another_machine() {
+    let server = StubServer::start().await;
+    server.enable_locking();
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server.url, work.path(), "1");
+    server.hold_lock(&config.remote_hash_path);
+
+    let err = sync(&config).await.err().unwrap().to_string();
+    assert!(err.contains("still locked by another machine"), "{}", err);
+    assert!(server.file("a.txt").is_none());
+    assert!(server.file(&config.remote_hash_path).is_none());
+
+    // A lock released while waiting lets the sync go ahead.
+    let releaser = {
+        let path = config.remote_hash_path.clone();
+        let server = &server;
+        async move {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            server.release_lock(&path);
+        }
+    };
+    let config = Config { hash_store_lock_wait: Duration::from_secs(10), ..config };
+    let (report, _) = tokio::join!(sync(&config), releaser);
+    assert_eq!(report.unwrap().uploaded, 1);
+}
@@ -0,0 +1,61 @@
+use phone_sync::config::Config;
+use phone_sync::hash_store::HashStore;
+use phone_sync::store_scope::Scope;
+use phone_sync::sync::sync;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// A device syncing `name.jpg` to its own `target_dir`, sharing the remote
+/// store with the others.
+fn device(server: &StubServer, work: &Path, name: &str) -> Config {
+    let data = work.join(name);
+    fs::create_dir_all(&data).unwrap();
+    fs::write(data.join(format!("{}.jpg", name)), name).unwrap();
+    TestConfig::new(&server.url, &work.join(format!("{}.yaml", name)))
+        .yaml(&format!("target_dir: {}\n", name))
+        .folders(&[&data])
+        .path("temp_dir", &work.join(format!("tmp-{}", name)))
+        .yaml("remote_hash_path: shared.yaml\nmirror_deletions: true\n")
+        .build()
+}
+
+/// The entries of `store` under `prefix`, as saved.
+fn section(store: &[u8], prefix: &str) -> Vec<String> {
+    String::from_utf8_lossy(store)
+        .lines()
+        .filter(|line| line.trim_start().starts_with(&format!("{}/", prefix)))
+        .map(str::to_string)
+        .collect()
+}
+
+#[tokio::test]
+async fn test_devices_leave_each_others_entries_alone() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let tablet = device(&server, work.path(), "tablet");
+    let phone = device(&server, work.path(), "phone");
+
+    assert_eq!(sync(&tablet).await.unwrap().uploaded, 1);
+    let written_by_tablet = server.file("shared.yaml").unwrap();
+    assert!(!section(&written_by_tablet, "tablet").is_empty());
+
+    // The tablet's file is not among the phone's, yet mirroring keeps it.
+    let report = sync(&phone).await.unwrap();
+    assert_eq!(report.uploaded, 1);
+    assert!(report.deleted.is_empty(), "{:?}", report.deleted);
+    assert!(server.file("tablet/tablet.jpg").is_some());
+
+    let shared = server.file("shared.yaml").unwrap();
+    assert_eq!(section(&shared, "tablet"), section(&written_by_tablet, "tablet"));
+    let store: HashStore = serde_yaml::from_slice(&shared).unwrap();
+    let keys: Vec<&String> = store.regular_hashes.keys().collect();
+    assert_eq!(keys, vec!["phone/phone.jpg", "tablet/tablet.jpg"]);
+
+    // The phone's local copy only materializes its own entries.
+    let local = HashStore::load_scoped(&phone.hash_store_path, Scope::new(["phone"]).as_ref()).unwrap();
+    assert_eq!(local.regular_hashes.keys().collect::<Vec<_>>(), vec!["phone/phone.jpg"]);
+    assert_eq!(local.regular_hashes.foreign_len(), 1);
+}
@@ -0,0 +1,99 @@
+use phone_sync::config::Config;
+use phone_sync::hash_store::HashStore;
+use phone_sync::hash_store_guard::{download_store, store_timeout, HashStoreGuard};
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config(server: &StubServer, work: &Path, extra: &str) -> Config {
+    TestConfig::new(&server.url, &work.join("hashes.yaml"))
+        .folders(&[work])
+        .yaml("timeout: 1s\nrequest_retries: 0\n")
+        .yaml(extra)
+        .build()
+}
+
+fn store_with(count: usize) -> HashStore {
+    let mut store = HashStore::default();
+    for i in 0..count {
+        store.regular_hashes.insert(format!("DCIM/IMG_{:05}.jpg", i), format!("{:064x}", i));
+    }
+    store
+}
+
+#[tokio::test]
+async fn test_store_download_has_its_own_timeout() {
+    let server = StubServer::start().await;
+    server.put_file("hashes.yaml", serde_yaml::to_string(&store_with(3)).unwrap().as_bytes());
+    server.delay("GET", Duration::from_millis(1500));
+    let work = tempfile::tempdir().unwrap();
+
+    // Longer than `timeout`, but within `hash_store_timeout`.
+    let config = config(&server, work.path(), "hash_store_timeout: 5s\n");
+    let guard = HashStoreGuard::new(WebDavClient::from_config(&config).unwrap(), &config).await.unwrap();
+    assert_eq!(guard.hash_store.regular_hashes.len(), 3);
+}
+
+#[tokio::test]
+async fn test_store_download_timeout_names_the_phase() {
+    let server = StubServer::start().await;
+    server.put_file("hashes.yaml", b"regular_hashes: {}\n");
+    server.delay("GET", Duration::from_secs(3));
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "hash_store_timeout: 1s\n");
+    let client = WebDavClient::from_config(&config).unwrap();
+
+    let local = work.path().join("remote_hashes.yaml");
+    let err = download_store(&client, "hashes.yaml", false, store_timeout(&config), &local).await.unwrap_err().to_string();
+    let phase = format!("while downloading the remote hash store from {}/hashes.yaml, hash_store_timeout 1s", server.url);
+    assert!(err.contains(&phase), "{}", err);
+}
+
+#[tokio::test]
+async fn test_store_download_timeout_fails_the_guard() {
+    let server = StubServer::start().await;
+    let remote = serde_yaml::to_string(&store_with(3)).unwrap();
+    server.put_file("hashes.yaml", remote.as_bytes());
+    server.delay("GET", Duration::from_secs(3));
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "hash_store_timeout: 1s\n");
+
+    let err = match HashStoreGuard::new(WebDavClient::from_config(&config).unwrap(), &config).await {
+        Ok(_) => panic!("the store download did not time out"),
+        Err(e) => e.to_string(),
+    };
+    assert!(err.contains("while downloading the remote hash store"), "{}", err);
+
+    // Neither the local nor the remote store is replaced by an empty one.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!Path::new(&config.hash_store_path).exists());
+    assert_eq!(server.file("hashes.yaml").unwrap(), remote.as_bytes());
+}
+
+#[tokio::test]
+async fn test_default_store_timeout_grows_with_the_local_store() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "min_upload_kbps: 8\n");
+    assert_eq!(store_timeout(&config), Duration::from_secs(1));
+
+    // 10 kB take 10s at 8 kbit/s.
+    fs::write(&config.hash_store_path, vec![b'#'; 10_000]).unwrap();
+    assert_eq!(store_timeout(&config), Duration::from_secs(11));
+}
+
+#[tokio::test]
+async fn test_failed_store_upload_names_the_phase() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = config(&server, work.path(), "finalize_retries: 0\nfail_on_pending_upload: true\n");
+    let mut guard = HashStoreGuard::new(WebDavClient::from_config(&config).unwrap(), &config).await.unwrap();
+    server.fail_next("PUT", 10, 507);
+
+    let err = guard.finalize().await.unwrap_err().to_string();
+    assert!(err.contains(&format!("while uploading the remote hash store to {}/hashes.yaml", server.url)), "{}", err);
+}
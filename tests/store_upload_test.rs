@@ -0,0 +1,75 @@
+use phone_sync::config::Config;
+use phone_sync::hash_store::HashStore;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::webdav_client::WebDavClient;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// A profile with its own temp root, so that it does not see the run lock of
+/// the other one, as on another machine.
+fn profile(server: &StubServer, work: &Path, name: &str) -> Config {
+    let data = work.join(name);
+    fs::create_dir_all(&data).unwrap();
+    TestConfig::new(&server.url, &work.join(format!("{}.yaml", name)))
+        .yaml(&format!("target_dir: {}\n", name))
+        .folders(&[&data])
+        .path("temp_dir", &work.join(format!("tmp-{}", name)))
+        .yaml("remote_hash_path: shared.yaml\nfinalize_retries: 0\n")
+        .build()
+}
+
+async fn guard_with_entries(config: &Config, prefix: &str, count: usize) -> HashStoreGuard {
+    let client = WebDavClient::from_config(config).unwrap();
+    let mut guard = HashStoreGuard::new(client, config).await.unwrap();
+    for i in 0..count {
+        guard.hash_store_mut().regular_hashes.insert(format!("{}/{}.jpg", prefix, i), format!("{:064x}", i));
+    }
+    guard
+}
+
+fn remote_hashes(server: &StubServer) -> BTreeMap<String, String> {
+    let store: HashStore = serde_yaml::from_slice(&server.file("shared.yaml").unwrap()).unwrap();
+    store.regular_hashes.into_inner()
+}
+
+#[tokio::test]
+async fn test_concurrent_finalizes_leave_one_complete_store() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let mut a = guard_with_entries(&profile(&server, work.path(), "a"), "a", 300).await;
+    let mut b = guard_with_entries(&profile(&server, work.path(), "b"), "b", 200).await;
+
+    let (finished_a, finished_b) = tokio::join!(a.finalize(), b.finalize());
+    finished_a.unwrap();
+    finished_b.unwrap();
+    let hashes = remote_hashes(&server);
+    let prefixes: Vec<&str> = hashes.keys().map(|k| &k[..1]).collect();
+    assert!(prefixes.iter().all(|p| *p == prefixes[0]), "entries of both runs in one store");
+    assert_eq!(hashes.len(), if prefixes[0] == "a" { 300 } else { 200 });
+    // The store only ever reached its name through a MOVE.
+    let puts: Vec<String> = server.requests().into_iter().filter(|r| r.method == "PUT").map(|r| r.path).collect();
+    assert!(!puts.contains(&"shared.yaml".to_string()), "{:?}", puts);
+    assert!(!server.paths().iter().any(|p| p.ends_with(".part")), "{:?}", server.paths());
+}
+
+#[tokio::test]
+async fn test_failed_move_keeps_the_previous_store() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let config = profile(&server, work.path(), "a");
+    guard_with_entries(&config, "a", 3).await.finalize().await.unwrap();
+
+    server.fail_next("MOVE", 1, 507);
+    let mut guard = guard_with_entries(&config, "a", 5).await;
+    guard.finalize().await.unwrap();
+    assert_eq!(remote_hashes(&server).len(), 3);
+    assert!(!server.paths().iter().any(|p| p.ends_with(".part")), "{:?}", server.paths());
+    // The next run uploads the pending store.
+    drop(guard);
+    guard_with_entries(&config, "a", 0).await.finalize().await.unwrap();
+    assert_eq!(remote_hashes(&server).len(), 5);
+}
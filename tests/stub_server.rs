@@ -0,0 +1,940 @@
+//! In-process WebDAV stub used by tests that need to control server responses.
+//!
+//! Unlike the docker based dummy server, the stub keeps its files in memory,
+//! records every request it receives, and can be told to fail on demand.
+#![allow(dead_code)]
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use phone_sync::config::Config;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use sha2::Digest;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A request as seen by the stub server.
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    /// Value of the Content-Length request header, if sent.
+    pub content_length: Option<u64>,
+    /// Value of the Range request header, if sent.
+    pub range: Option<String>,
+    /// Value of the Content-Type request header, if sent.
+    pub content_type: Option<String>,
+    /// Value of the OC-Checksum request header, if sent.
+    pub checksum: Option<String>,
+    /// Value of the Authorization request header, if sent.
+    pub authorization: Option<String>,
+    /// Value of the User-Agent request header, if sent.
+    pub user_agent: Option<String>,
+    /// Value of the X-Requested-With request header, if sent.
+    pub requested_with: Option<String>,
+}
+
+#[derive(Default)]
+struct State {
+    files: BTreeMap<String, Vec<u8>>,
+    dirs: BTreeSet<String>,
+    requests: Vec<RecordedRequest>,
+    /// Extra headers sent with GET/HEAD responses, per path.
+    headers: BTreeMap<String, Vec<(String, String)>>,
+    /// Status returned for every request while set.
+    unavailable: Option<StatusCode>,
+    /// Remaining forced failures per method, with their status and body.
+    failures: BTreeMap<String, (usize, StatusCode, String)>,
+    /// Forced one-time failures of requests whose path ends with a suffix.
+    path_failures: Vec<(String, StatusCode)>,
+    /// When set, PUT bodies are counted while streaming instead of stored.
+    discard_uploads: bool,
+    /// Bytes of a PUT body kept while the rest is dropped, like a proxy with
+    /// a body size limit that still answers success.
+    truncate_uploads: Option<usize>,
+    /// Flip the first byte of every PUT body, keeping its length, like
+    /// storage that silently corrupts data.
+    corrupt_uploads: bool,
+    /// Body sizes of discarded uploads, per path.
+    upload_sizes: BTreeMap<String, u64>,
+    /// Versions (content, ETag) a path changes to right before its next GETs.
+    changes: BTreeMap<String, VecDeque<(Vec<u8>, String)>>,
+    /// Bytes of a path's GET body sent before the response stalls.
+    stalls: BTreeMap<String, usize>,
+    /// Bodies of stalled responses, kept open so they never finish.
+    stalled_bodies: Vec<hyper::body::Sender>,
+    /// Bytes of a path's GET body sent before the connection drops.
+    disconnects: BTreeMap<String, usize>,
+    /// When set, every PUT gives its path a new ETag, sent in the response.
+    version_uploads: bool,
+    uploads_versioned: usize,
+    /// Version (content, ETag) a path changes to right after its next HEAD
+    /// or listing, as if another device wrote it.
+    changes_after_check: BTreeMap<String, (Vec<u8>, String)>,
+    /// Answer HEAD without Content-Length, like some gateways do.
+    head_without_length: bool,
+    /// Trim names and drop their control characters on PUT.
+    trim_names: bool,
+    /// `quota-available-bytes` reported for the root collection.
+    quota_available: Option<i64>,
+    /// Files root (e.g. `remote.php/dav/files/me`) bulk uploads are stored
+    /// below, once enabled.
+    bulk_root: Option<String>,
+    /// `X-File-Path`s a bulk upload reports as failed.
+    bulk_rejects: BTreeSet<String>,
+    /// Announce and support WebDAV locks.
+    locking: bool,
+    /// Methods left out of `Allow` and answered with 405.
+    disallowed: BTreeSet<String>,
+    /// Lock tokens by path.
+    locks: BTreeMap<String, String>,
+    locks_granted: usize,
+    /// How long requests of a method wait before they are answered.
+    delays: BTreeMap<String, Duration>,
+    /// Bytes per second PUT bodies are read at, like a slow uplink.
+    upload_rate: Option<usize>,
+    /// Answer requests below a collection that does not exist with 409.
+    require_parents: bool,
+    /// Check the SHA-256 `OC-Checksum` of PUT bodies like Nextcloud, keeping
+    /// it for PROPFIND.
+    verify_checksums: bool,
+    /// Checksums sent with the stored files, per path.
+    checksums: BTreeMap<String, String>,
+    /// Path prefixes answered with a 301 to another prefix or URL.
+    redirects: Vec<(String, String)>,
+    /// Answer requests without an Authorization header with 401.
+    require_auth: bool,
+    /// Send a `WWW-Authenticate: Basic` challenge with that 401.
+    auth_challenge: bool,
+}
+
+/// Handle to a running stub server.
+pub struct StubServer {
+    pub url: String,
+    state: Arc<Mutex<State>>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl StubServer {
+    /// Start a stub server on an ephemeral localhost port.
+    pub async fn start() -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+        let service_state = state.clone();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            accepted.fetch_add(1, Ordering::SeqCst);
+            let state = service_state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req)))
+            }
+        });
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let server = Server::bind(&addr).serve(make_svc);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        Self { url, state, connections }
+    }
+
+    /// Number of TCP connections accepted so far.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Content of a stored remote file.
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().files.get(path.trim_start_matches('/')).cloned()
+    }
+
+    /// Place a file on the server without going through HTTP.
+    pub fn put_file(&self, path: &str, content: &[u8]) {
+        self.state
+            .lock()
+            .unwrap()
+            .files
+            .insert(path.trim_start_matches('/').to_string(), content.to_vec());
+    }
+
+    /// Remove a file from the server without going through HTTP.
+    pub fn remove_file(&self, path: &str) {
+        self.state.lock().unwrap().files.remove(path.trim_start_matches('/'));
+    }
+
+    /// Let `path` change to the next of `versions` (content, ETag) right before
+    /// each of its following GETs, as if another client rewrote it.
+    pub fn change_on_get(&self, path: &str, versions: &[(&[u8], &str)]) {
+        let versions = versions.iter().map(|(content, etag)| (content.to_vec(), etag.to_string())).collect();
+        self.state.lock().unwrap().changes.insert(path.to_string(), versions);
+    }
+
+    /// Give every uploaded file a new ETag, like a real server, and honour
+    /// If-Match on PUT.
+    pub fn version_uploads(&self) {
+        self.state.lock().unwrap().version_uploads = true;
+    }
+
+    /// Store uploads under their names trimmed and without control
+    /// characters, like servers that mangle such names.
+    pub fn trim_names(&self) {
+        self.state.lock().unwrap().trim_names = true;
+    }
+
+    /// Report `available` bytes of quota (negative: unlimited) on the root
+    /// collection.
+    pub fn set_quota(&self, available: i64) {
+        self.state.lock().unwrap().quota_available = Some(available);
+    }
+
+    /// Leave Content-Length out of HEAD responses.
+    pub fn omit_head_length(&self) {
+        self.state.lock().unwrap().head_without_length = true;
+    }
+
+    /// Change `path` to `content` with `etag` right after its next HEAD or
+    /// the next PROPFIND listing it.
+    pub fn change_after_check(&self, path: &str, content: &[u8], etag: &str) {
+        self.state.lock().unwrap().changes_after_check.insert(path.to_string(), (content.to_vec(), etag.to_string()));
+    }
+
+    /// Act as a Nextcloud server with chunked uploads, assembling the chunks
+    /// of an upload collection on a MOVE of its `.file`.
+    pub fn enable_chunking(&self) {
+        let capabilities = br#"{"ocs": {"data": {"capabilities": {"dav": {"chunking": "1.0"}}}}}"#;
+        self.put_file("ocs/v2.php/cloud/capabilities", capabilities);
+    }
+
+    /// Fail the next request whose path ends with `suffix` with `status`.
+    pub fn fail_path_once(&self, suffix: &str, status: u16) {
+        let status = StatusCode::from_u16(status).unwrap();
+        self.state.lock().unwrap().path_failures.push((suffix.to_string(), status));
+    }
+
+    /// Act as a Nextcloud server with the bulk upload endpoint, storing the
+    /// files of bulk uploads below `files_root`.
+    pub fn enable_bulk_upload(&self, files_root: &str) {
+        let capabilities = br#"{"ocs": {"data": {"capabilities": {"dav": {"bulkupload": "1.0"}}}}}"#;
+        self.put_file("ocs/v2.php/cloud/capabilities", capabilities);
+        self.state.lock().unwrap().bulk_root = Some(files_root.trim_matches('/').to_string());
+    }
+
+    /// Report the file at `x_file_path` as failed in bulk uploads.
+    pub fn reject_in_bulk(&self, x_file_path: &str) {
+        self.state.lock().unwrap().bulk_rejects.insert(x_file_path.to_string());
+    }
+
+    /// Send only the first `bytes` of the body of the following GETs of
+    /// `path`, then stall without ending the response.
+    pub fn stall(&self, path: &str, bytes: usize) {
+        self.state.lock().unwrap().stalls.insert(path.to_string(), bytes);
+    }
+
+    /// Drop the connection of GETs of `path` after `bytes` of the body.
+    pub fn disconnect(&self, path: &str, bytes: usize) {
+        self.state.lock().unwrap().disconnects.insert(path.to_string(), bytes);
+    }
+
+    /// Answer GETs of `path` normally again.
+    pub fn unstall(&self, path: &str) {
+        self.state.lock().unwrap().stalls.remove(path);
+    }
+
+    /// Paths of all stored files.
+    pub fn paths(&self) -> Vec<String> {
+        self.state.lock().unwrap().files.keys().cloned().collect()
+    }
+
+    /// Send `name: value` with every GET/HEAD response for `path`.
+    pub fn set_header(&self, path: &str, name: &str, value: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .headers
+            .entry(path.trim_start_matches('/').to_string())
+            .or_default()
+            .push((name.to_string(), value.to_string()));
+    }
+
+    /// Stop sending the extra headers set for `path`.
+    pub fn clear_headers(&self, path: &str) {
+        self.state.lock().unwrap().headers.remove(path.trim_start_matches('/'));
+    }
+
+    /// Answer every request with `status` (or behave normally again with `None`).
+    pub fn set_unavailable(&self, status: Option<u16>) {
+        self.state.lock().unwrap().unavailable =
+            status.map(|s| StatusCode::from_u16(s).expect("valid status"));
+    }
+
+    /// Answer the next `count` requests using `method` with `status`.
+    pub fn fail_next(&self, method: &str, count: usize, status: u16) {
+        self.fail_next_with_body(method, count, status, "");
+    }
+
+    /// Answer the next `count` requests using `method` with `status` and `body`.
+    pub fn fail_next_with_body(&self, method: &str, count: usize, status: u16, body: &str) {
+        self.state.lock().unwrap().failures.insert(
+            method.to_string(),
+            (count, StatusCode::from_u16(status).expect("valid status"), body.to_string()),
+        );
+    }
+
+    /// Count PUT bodies instead of storing them, for uploads too large to keep in memory.
+    pub fn discard_uploads(&self) {
+        self.state.lock().unwrap().discard_uploads = true;
+    }
+
+    /// Store at most `limit` bytes of each following PUT body.
+    pub fn truncate_uploads(&self, limit: usize) {
+        self.state.lock().unwrap().truncate_uploads = Some(limit);
+    }
+
+    /// Announce WebDAV locking (DAV class 2) and honour LOCK and UNLOCK.
+    pub fn enable_locking(&self) {
+        self.state.lock().unwrap().locking = true;
+    }
+
+    /// Leave `method` out of the `Allow` header of OPTIONS and answer it with 405.
+    pub fn disallow(&self, method: &str) {
+        self.state.lock().unwrap().disallowed.insert(method.to_string());
+    }
+
+    /// Answer requests of `method` only after `delay`, like a stalled server.
+    pub fn delay(&self, method: &str, delay: Duration) {
+        self.state.lock().unwrap().delays.insert(method.to_string(), delay);
+    }
+
+    /// Read PUT bodies at `bytes_per_sec`.
+    pub fn throttle_uploads(&self, bytes_per_sec: usize) {
+        self.state.lock().unwrap().upload_rate = Some(bytes_per_sec);
+    }
+
+    /// Answer HEAD, GET, PROPFIND, PUT and MKCOL below a collection that does
+    /// not exist with 409 instead of 404 or creating it.
+    pub fn require_parents(&self) {
+        self.state.lock().unwrap().require_parents = true;
+    }
+
+    /// Refuse PUT bodies that do not match their SHA-256 `OC-Checksum`
+    /// with 400, and report the checksums of stored files on PROPFIND.
+    pub fn verify_checksums(&self) {
+        self.state.lock().unwrap().verify_checksums = true;
+    }
+
+    /// Answer requests below `prefix` with a 301 to the same path below
+    /// `location`, e.g. `/remote.php/dav/files/me` or a URL on another host.
+    pub fn redirect(&self, prefix: &str, location: &str) {
+        self.state.lock().unwrap().redirects.push((prefix.trim_matches('/').to_string(), location.to_string()));
+    }
+
+    /// Answer every request without an Authorization header with 401, like
+    /// an auth_request gateway; with a basic auth challenge only if
+    /// `challenge`.
+    pub fn require_auth(&self, challenge: bool) {
+        let mut st = self.state.lock().unwrap();
+        st.require_auth = true;
+        st.auth_challenge = challenge;
+    }
+
+    /// Lock `path` as another client would.
+    pub fn hold_lock(&self, path: &str) {
+        self.state.lock().unwrap().locks.insert(path.to_string(), "opaquelocktoken:other".to_string());
+    }
+
+    /// Drop every lock on `path`.
+    pub fn release_lock(&self, path: &str) {
+        self.state.lock().unwrap().locks.remove(path);
+    }
+
+    /// Whether `path` is locked.
+    pub fn is_locked(&self, path: &str) -> bool {
+        self.state.lock().unwrap().locks.contains_key(path)
+    }
+
+    /// Corrupt (or stop corrupting) the following PUT bodies.
+    pub fn corrupt_uploads(&self, corrupt: bool) {
+        self.state.lock().unwrap().corrupt_uploads = corrupt;
+    }
+
+    /// Number of bytes received for a discarded upload.
+    pub fn upload_size(&self, path: &str) -> Option<u64> {
+        self.state.lock().unwrap().upload_sizes.get(path.trim_start_matches('/')).copied()
+    }
+
+    /// All requests received so far.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Number of received requests using `method`.
+    pub fn count(&self, method: &str) -> usize {
+        self.requests().iter().filter(|r| r.method == method).count()
+    }
+
+    /// Forget the recorded requests.
+    pub fn clear_requests(&self) {
+        self.state.lock().unwrap().requests.clear();
+    }
+}
+
+/// Config of a test, written as YAML and loaded with [`Config::parse`] like
+/// a config file, so that it is validated and normalized as for users.
+pub struct TestConfig {
+    yaml: String,
+}
+
+impl TestConfig {
+    /// Syncing to `url`, keeping the local hash store at `hash_store_path`.
+    /// Needs [`folders`](Self::folders) or a `folders` list in [`yaml`](Self::yaml).
+    pub fn new(url: &str, hash_store_path: &Path) -> Self {
+        TestConfig { yaml: format!("webdav_url: \"{}\"\n", url) }.path("hash_store_path", hash_store_path)
+    }
+
+    /// The usual layout of a test: backing up `work/data`, with the local
+    /// hash store at `work/hashes.yaml`.
+    pub fn in_work_dir(url: &str, work: &Path) -> Self {
+        TestConfig::new(url, &work.join("hashes.yaml")).folders(&[&work.join("data")])
+    }
+
+    /// Back up `folders`.
+    pub fn folders(self, folders: &[&Path]) -> Self {
+        let quoted: Vec<String> = folders.iter().map(|folder| format!("\"{}\"", folder.display())).collect();
+        self.yaml(&format!("folders: [{}]\n", quoted.join(", ")))
+    }
+
+    /// Set `key` to `path`.
+    pub fn path(self, key: &str, path: &Path) -> Self {
+        self.yaml(&format!("{}: \"{}\"\n", key, path.display()))
+    }
+
+    /// Add YAML lines as they are, e.g. `"target_dir: phone\n"`.
+    pub fn yaml(mut self, yaml: &str) -> Self {
+        self.yaml.push_str(yaml);
+        self
+    }
+
+    /// Load the config, panicking with its YAML if it is rejected.
+    pub fn build(self) -> Config {
+        Config::parse(&self.yaml).unwrap_or_else(|e| panic!("{}:\n{}", e, self.yaml))
+    }
+}
+
+async fn handle(state: Arc<Mutex<State>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let method = req.method().as_str().to_string();
+    let mut path = req.uri().path().trim_start_matches('/').to_string();
+    let content_length = req
+        .headers()
+        .get("Content-Length")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    let range = req.headers().get("Range").and_then(|v| v.to_str().ok()).map(str::to_string);
+    let content_type = req.headers().get("Content-Type").and_then(|v| v.to_str().ok()).map(str::to_string);
+    let checksum = req.headers().get("OC-Checksum").and_then(|v| v.to_str().ok()).map(str::to_string);
+    let authorization = req.headers().get("Authorization").and_then(|v| v.to_str().ok()).map(str::to_string);
+    let authenticated = authorization.is_some();
+    let user_agent = req.headers().get("User-Agent").and_then(|v| v.to_str().ok()).map(str::to_string);
+    let requested_with = req.headers().get("X-Requested-With").and_then(|v| v.to_str().ok()).map(str::to_string);
+    let discard;
+    let (delay, upload_rate);
+    {
+        let mut st = state.lock().unwrap();
+        st.requests.push(RecordedRequest {
+            method: method.clone(),
+            path: path.clone(),
+            content_length,
+            range: range.clone(),
+            content_type,
+            checksum: checksum.clone(),
+            authorization,
+            user_agent,
+            requested_with,
+        });
+        if st.trim_names && method == "PUT" {
+            path = trimmed_name(&path);
+        }
+        discard = st.discard_uploads && method == "PUT";
+        delay = st.delays.get(&method).copied();
+        upload_rate = st.upload_rate.filter(|_| method == "PUT");
+        if let Some(status) = st.unavailable {
+            return Ok(status_response(status));
+        }
+        if st.require_auth && !authenticated {
+            let mut response = Response::builder().status(StatusCode::UNAUTHORIZED);
+            if st.auth_challenge {
+                response = response.header("WWW-Authenticate", "Basic realm=\"stub\"");
+            }
+            return Ok(response.body(Body::empty()).unwrap());
+        }
+        if let Some(index) = st.path_failures.iter().position(|(suffix, _)| path.ends_with(suffix.as_str())) {
+            return Ok(status_response(st.path_failures.remove(index).1));
+        }
+        let redirect = st.redirects.iter().find_map(|(prefix, location)| {
+            let rest = path.strip_prefix(prefix.as_str())?;
+            (rest.is_empty() || rest.starts_with('/')).then(|| format!("{}{}", location.trim_end_matches('/'), rest))
+        });
+        if let Some(location) = redirect {
+            let response = Response::builder().status(StatusCode::MOVED_PERMANENTLY).header("Location", location);
+            return Ok(response.body(Body::empty()).unwrap());
+        }
+        if let Some((remaining, status, body)) = st.failures.get_mut(&method) {
+            if *remaining > 0 {
+                *remaining -= 1;
+                return Ok(Response::builder().status(*status).body(Body::from(body.clone())).unwrap());
+            }
+        }
+    }
+
+    if let Some(delay) = delay {
+        tokio::time::sleep(delay).await;
+    }
+
+    if discard {
+        let mut body = req.into_body();
+        let mut size = 0u64;
+        while let Some(Ok(chunk)) = hyper::body::HttpBody::data(&mut body).await {
+            size += chunk.len() as u64;
+        }
+        state.lock().unwrap().upload_sizes.insert(path, size);
+        return Ok(status_response(StatusCode::CREATED));
+    }
+
+    let headers = req.headers().clone();
+    let body = match upload_rate {
+        Some(rate) => {
+            let mut body = req.into_body();
+            let mut content = Vec::new();
+            while let Some(Ok(chunk)) = hyper::body::HttpBody::data(&mut body).await {
+                content.extend_from_slice(&chunk);
+                tokio::time::sleep(Duration::from_secs_f64(chunk.len() as f64 / rate as f64)).await;
+            }
+            hyper::body::Bytes::from(content)
+        }
+        None => hyper::body::to_bytes(req.into_body()).await.unwrap_or_default(),
+    };
+    let mut st = state.lock().unwrap();
+    if method == "GET" {
+        if let Some((content, etag)) = st.changes.get_mut(&path).and_then(|versions| versions.pop_front()) {
+            st.files.insert(path.clone(), content);
+            st.headers.insert(path.clone(), vec![("ETag".to_string(), etag)]);
+        }
+    }
+    if st.disallowed.contains(&method) {
+        return Ok(status_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    // Writes to a locked path, or a MOVE onto one, must name its token.
+    if matches!(method.as_str(), "PUT" | "DELETE" | "MOVE") {
+        let destination = headers
+            .get("Destination")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<hyper::Uri>().ok())
+            .map(|uri| uri.path().trim_start_matches('/').to_string());
+        for written in std::iter::once(&path).chain(destination.as_ref()) {
+            if let Some(token) = st.locks.get(written) {
+                let submitted = headers.get("If").and_then(|v| v.to_str().ok()).unwrap_or_default();
+                if !submitted.contains(token.as_str()) {
+                    return Ok(status_response(StatusCode::LOCKED));
+                }
+            }
+        }
+    }
+    if st.require_parents
+        && matches!(method.as_str(), "HEAD" | "GET" | "PROPFIND" | "PUT" | "MKCOL")
+        && !parent_exists(&st, &path)
+    {
+        return Ok(status_response(StatusCode::CONFLICT));
+    }
+    let response = match method.as_str() {
+        "OPTIONS" => {
+            let mut methods = vec!["OPTIONS", "GET", "HEAD", "PUT", "POST", "DELETE", "MKCOL", "MOVE", "PROPFIND"];
+            if st.locking {
+                methods.extend(["LOCK", "UNLOCK"]);
+            }
+            methods.retain(|m| !st.disallowed.contains(*m));
+            Response::builder()
+                .header("DAV", if st.locking { "1, 2" } else { "1" })
+                .header("Allow", methods.join(", "))
+                .body(Body::empty())
+                .unwrap()
+        }
+        "LOCK" if st.locking => {
+            if st.locks.contains_key(&path) {
+                status_response(StatusCode::LOCKED)
+            } else {
+                st.locks_granted += 1;
+                let token = format!("opaquelocktoken:stub-{}", st.locks_granted);
+                st.locks.insert(path.clone(), token.clone());
+                st.files.entry(path).or_default();
+                Response::builder().header("Lock-Token", format!("<{}>", token)).body(Body::empty()).unwrap()
+            }
+        }
+        "UNLOCK" if st.locking => {
+            let submitted = headers.get("Lock-Token").and_then(|v| v.to_str().ok()).unwrap_or_default();
+            match st.locks.get(&path) {
+                Some(token) if submitted.contains(token.as_str()) => {
+                    st.locks.remove(&path);
+                    status_response(StatusCode::NO_CONTENT)
+                }
+                _ => status_response(StatusCode::CONFLICT),
+            }
+        }
+        "GET" => match st.files.get(&path).cloned() {
+            Some(content) => get_response(&mut st, &path, content, range.as_deref(), &headers),
+            None => status_response(StatusCode::NOT_FOUND),
+        },
+        "HEAD" => {
+            let response = match st.files.get(&path) {
+                Some(_) if st.head_without_length => {
+                    with_headers(&st, &path, Response::builder()).body(Body::empty()).unwrap()
+                }
+                Some(content) => with_headers(&st, &path, Response::builder())
+                    .header("Content-Length", content.len())
+                    .body(Body::empty())
+                    .unwrap(),
+                None if st.dirs.contains(path.trim_end_matches('/')) => Response::new(Body::empty()),
+                None => status_response(StatusCode::NOT_FOUND),
+            };
+            if let Some((content, etag)) = st.changes_after_check.remove(&path) {
+                st.files.insert(path.clone(), content);
+                st.headers.insert(path, vec![("ETag".to_string(), etag)]);
+            }
+            response
+        }
+        "PUT" if st.version_uploads => {
+            let current = st.files.contains_key(&path).then(|| etag_of(&st, &path)).flatten();
+            match headers.get("If-Match").and_then(|v| v.to_str().ok()) {
+                Some(expected) if current.as_deref() != Some(expected) => status_response(StatusCode::PRECONDITION_FAILED),
+                _ => {
+                    st.uploads_versioned += 1;
+                    let etag = format!("\"v{}\"", st.uploads_versioned);
+                    let existed = st.files.insert(path.clone(), body.to_vec()).is_some();
+                    st.headers.insert(path, vec![("ETag".to_string(), etag.clone())]);
+                    Response::builder()
+                        .status(if existed { StatusCode::NO_CONTENT } else { StatusCode::CREATED })
+                        .header("ETag", etag)
+                        .body(Body::empty())
+                        .unwrap()
+                }
+            }
+        }
+        "PUT" => {
+            let kept = st.truncate_uploads.map_or(body.len(), |limit| body.len().min(limit));
+            let mut stored = body[..kept].to_vec();
+            if st.corrupt_uploads {
+                if let Some(first) = stored.first_mut() {
+                    *first ^= 0xff;
+                }
+            }
+            if let Some(checksum) = checksum.filter(|_| st.verify_checksums) {
+                let expected = checksum.strip_prefix("SHA256:").unwrap_or_default().to_ascii_lowercase();
+                if expected != format!("{:x}", sha2::Sha256::digest(&stored)) {
+                    let message = "The computed checksum does not match the one received from the client.";
+                    return Ok(Response::builder().status(StatusCode::BAD_REQUEST).body(Body::from(message)).unwrap());
+                }
+                st.checksums.insert(path.clone(), checksum);
+            }
+            let existed = st.files.insert(path, stored).is_some();
+            status_response(if existed { StatusCode::NO_CONTENT } else { StatusCode::CREATED })
+        }
+        "POST" if path == "remote.php/dav/bulk" && st.bulk_root.is_some() => bulk_response(&mut st, &headers, &body),
+        "DELETE" => match st.files.remove(&path) {
+            Some(_) => status_response(StatusCode::NO_CONTENT),
+            // Deleting a collection removes everything below it.
+            None if st.dirs.contains(path.trim_end_matches('/')) => {
+                let root = path.trim_end_matches('/').to_string();
+                let prefix = format!("{}/", root);
+                st.files.retain(|p, _| !p.starts_with(&prefix));
+                st.dirs.retain(|d| *d != root && !d.starts_with(&prefix));
+                status_response(StatusCode::NO_CONTENT)
+            }
+            None => status_response(StatusCode::NOT_FOUND),
+        },
+        "MKCOL" => {
+            // Like Apache's mod_dav, a file in the way gets the same 405 as
+            // an existing collection.
+            let dir = path.trim_end_matches('/').to_string();
+            if !st.files.contains_key(&dir) && st.dirs.insert(dir) {
+                status_response(StatusCode::CREATED)
+            } else {
+                status_response(StatusCode::METHOD_NOT_ALLOWED)
+            }
+        }
+        "MOVE" if path.ends_with("/.file") => {
+            let root = path.trim_end_matches("/.file").to_string();
+            let prefix = format!("{}/", root);
+            let chunks: Vec<String> = st.files.keys().filter(|p| p.starts_with(&prefix)).cloned().collect();
+            let content: Vec<u8> = chunks.iter().flat_map(|chunk| st.files.remove(chunk).unwrap()).collect();
+            st.dirs.remove(&root);
+            let destination = headers
+                .get("Destination")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<hyper::Uri>().ok())
+                .map(|uri| uri.path().trim_start_matches('/').to_string())
+                .unwrap_or_default();
+            let existed = st.files.insert(destination, content).is_some();
+            status_response(if existed { StatusCode::NO_CONTENT } else { StatusCode::CREATED })
+        }
+        "MOVE" => {
+            let destination = headers
+                .get("Destination")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<hyper::Uri>().ok())
+                .map(|uri| uri.path().trim_start_matches('/').to_string())
+                .unwrap_or_default();
+            let moved: Vec<String> =
+                st.files.keys().filter(|p| p.starts_with(&path)).cloned().collect();
+            let no_overwrite = headers.get("Overwrite").is_some_and(|v| v.as_bytes() == b"F");
+            let target_exists =
+                st.files.contains_key(&destination) || st.dirs.contains(destination.trim_end_matches('/'));
+            if moved.is_empty() && !st.dirs.contains(path.trim_end_matches('/')) {
+                status_response(StatusCode::NOT_FOUND)
+            } else if no_overwrite && target_exists {
+                status_response(StatusCode::PRECONDITION_FAILED)
+            } else {
+                for old in moved {
+                    let content = st.files.remove(&old).unwrap();
+                    st.files.insert(format!("{}{}", destination, &old[path.len()..]), content);
+                }
+                let root = path.trim_end_matches('/');
+                let dirs: Vec<String> = st
+                    .dirs
+                    .iter()
+                    .filter(|d| *d == root || d.starts_with(&format!("{}/", root)))
+                    .cloned()
+                    .collect();
+                for old in dirs {
+                    st.dirs.remove(&old);
+                    let rest = &old[root.len()..];
+                    st.dirs.insert(format!("{}{}", destination.trim_end_matches('/'), rest));
+                }
+                status_response(if target_exists { StatusCode::NO_CONTENT } else { StatusCode::CREATED })
+            }
+        }
+        "PROPFIND" => {
+            let infinite = headers.get("Depth").is_some_and(|d| d.as_bytes().eq_ignore_ascii_case(b"infinity"));
+            let dir = path.trim_end_matches('/').to_string();
+            let response = propfind_response(&st, &dir, infinite);
+            let listing = headers.get("Depth").is_some_and(|d| d.as_bytes() != b"0");
+            let listed: Vec<String> = st
+                .changes_after_check
+                .keys()
+                .filter(|p| listing && p.rsplit_once('/').map_or("", |(parent, _)| parent) == dir)
+                .cloned()
+                .collect();
+            for listed in listed {
+                let (content, etag) = st.changes_after_check.remove(&listed).unwrap();
+                st.files.insert(listed.clone(), content);
+                st.headers.insert(listed, vec![("ETag".to_string(), etag)]);
+            }
+            response
+        }
+        _ => status_response(StatusCode::METHOD_NOT_ALLOWED),
+    };
+    Ok(response)
+}
+
+/// `path` (percent-encoded) with each component trimmed and its control
+/// characters dropped.
+fn trimmed_name(path: &str) -> String {
+    const UNSAFE: &AsciiSet = &CONTROLS.add(b' ');
+    let decoded = percent_decode_str(path).decode_utf8_lossy();
+    let trimmed: Vec<String> =
+        decoded.split('/').map(|name| name.trim().chars().filter(|c| *c >= ' ').collect()).collect();
+    utf8_percent_encode(&trimmed.join("/"), UNSAFE).to_string()
+}
+
+/// Answer a PROPFIND with `path` and its direct children, or everything
+/// below it when `infinite`. Collections exist explicitly (MKCOL) or
+/// implicitly as parents of stored files.
+fn propfind_response(st: &State, path: &str, infinite: bool) -> Response<Body> {
+    let prefix = if path.is_empty() { String::new() } else { format!("{}/", path) };
+    let mut children: BTreeMap<String, Option<usize>> = BTreeMap::new();
+    let dirs = st.dirs.iter().map(|d| (d, None));
+    for (entry, size) in st.files.iter().map(|(p, c)| (p, Some(c.len()))).chain(dirs) {
+        let Some(rest) = entry.strip_prefix(&prefix).filter(|rest| !rest.is_empty()) else {
+            continue;
+        };
+        let parts: Vec<&str> = rest.split('/').collect();
+        let levels = if infinite { parts.len() } else { 1 };
+        for level in 1..=levels {
+            let leaf = level == parts.len();
+            children.insert(format!("{}{}", prefix, parts[..level].join("/")), if leaf { size } else { None });
+        }
+    }
+    let is_file = st.files.contains_key(path);
+    if children.is_empty() && !is_file && !path.is_empty() && !st.dirs.contains(path) {
+        return status_response(StatusCode::NOT_FOUND);
+    }
+    let response = |href: &str, size: Option<usize>| match size {
+        Some(size) => format!(
+            "<d:response><d:href>/{}</d:href><d:propstat><d:prop><d:resourcetype/><d:getcontentlength>{}</d:getcontentlength>{}{}</d:prop></d:propstat></d:response>",
+            href,
+            size,
+            validator_properties(st, href),
+            st.checksums
+                .get(href)
+                .map(|c| format!("<oc:checksums><oc:checksum>{}</oc:checksum></oc:checksums>", c))
+                .unwrap_or_default()
+        ),
+        None => format!(
+            "<d:response><d:href>/{}/</d:href><d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat></d:response>",
+            href
+        ),
+    };
+    let mut body = String::from("<?xml version=\"1.0\"?><d:multistatus xmlns:d=\"DAV:\" xmlns:oc=\"http://owncloud.org/ns\">");
+    match st.quota_available.filter(|_| path.is_empty()) {
+        Some(available) => body.push_str(&format!(
+            "<d:response><d:href>/</d:href><d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype><d:quota-available-bytes>{}</d:quota-available-bytes></d:prop></d:propstat></d:response>",
+            available
+        )),
+        None => body.push_str(&response(path, st.files.get(path).map(Vec::len))),
+    }
+    if !is_file {
+        for (child, size) in children {
+            body.push_str(&response(&child, size));
+        }
+    }
+    body.push_str("</d:multistatus>");
+    Response::builder().status(StatusCode::MULTI_STATUS).body(Body::from(body)).unwrap()
+}
+
+/// `getetag` and `getlastmodified` of `path`, as sent in its headers.
+fn validator_properties(st: &State, path: &str) -> String {
+    let mut properties = String::new();
+    for (name, value) in st.headers.get(path).into_iter().flatten() {
+        let property = match name.to_ascii_lowercase().as_str() {
+            "etag" => "getetag",
+            "last-modified" => "getlastmodified",
+            _ => continue,
+        };
+        properties.push_str(&format!("<d:{}>{}</d:{}>", property, value.replace('&', "&amp;").replace('<', "&lt;"), property));
+    }
+    properties
+}
+
+/// Answer a GET, honouring `Range: bytes=N-` while `If-Range` (if sent)
+/// matches the ETag or Last-Modified header of `path`.
+fn get_response(
+    st: &mut State,
+    path: &str,
+    content: Vec<u8>,
+    range: Option<&str>,
+    headers: &hyper::HeaderMap,
+) -> Response<Body> {
+    let validators: Vec<&str> = st
+        .headers
+        .get(path)
+        .into_iter()
+        .flatten()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("ETag") || name.eq_ignore_ascii_case("Last-Modified"))
+        .map(|(_, value)| value.as_str())
+        .collect();
+    let if_range_holds = headers
+        .get("If-Range")
+        .and_then(|v| v.to_str().ok())
+        .is_none_or(|v| validators.contains(&v));
+    let start = range
+        .and_then(|r| r.strip_prefix("bytes="))
+        .and_then(|r| r.strip_suffix('-'))
+        .and_then(|r| r.parse::<usize>().ok())
+        .filter(|_| if_range_holds);
+
+    let mut builder = with_headers(st, path, Response::builder());
+    let body = match start {
+        Some(start) if start >= content.len() => return status_response(StatusCode::RANGE_NOT_SATISFIABLE),
+        Some(start) => {
+            builder = builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header("Content-Range", format!("bytes {}-{}/{}", start, content.len() - 1, content.len()));
+            content[start..].to_vec()
+        }
+        None => content,
+    };
+    builder = builder.header("Content-Length", body.len());
+    if let Some(&sent) = st.disconnects.get(path) {
+        let (mut sender, cut) = Body::channel();
+        let head = body[..sent.min(body.len())].to_vec();
+        tokio::spawn(async move {
+            let _ = sender.send_data(head.into()).await;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            sender.abort();
+        });
+        return builder.body(cut).unwrap();
+    }
+    match st.stalls.get(path) {
+        Some(&sent) => {
+            let (mut sender, stalled) = Body::channel();
+            let _ = sender.try_send_data(body[..sent.min(body.len())].to_vec().into());
+            st.stalled_bodies.push(sender);
+            builder.body(stalled).unwrap()
+        }
+        None => builder.body(Body::from(body)).unwrap(),
+    }
+}
+
+fn with_headers(st: &State, path: &str, mut builder: hyper::http::response::Builder) -> hyper::http::response::Builder {
+    for (name, value) in st.headers.get(path).into_iter().flatten() {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    builder
+}
+
+/// Store the parts of a bulk upload and answer per file, like Nextcloud.
+fn bulk_response(st: &mut State, headers: &hyper::HeaderMap, body: &[u8]) -> Response<Body> {
+    let boundary = headers
+        .get("Content-Type")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split_once("boundary="))
+        .map(|(_, b)| b.to_string())
+        .unwrap_or_default();
+    let root = st.bulk_root.clone().unwrap_or_default();
+    let mut results = serde_json::Map::new();
+    let mut rest = body;
+    let delimiter = format!("--{}\r\n", boundary);
+    while let Some(after) = rest.strip_prefix(delimiter.as_bytes()) {
+        let end = after.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let part_headers = String::from_utf8_lossy(&after[..end]).to_string();
+        let header = |name: &str| {
+            part_headers
+                .lines()
+                .find_map(|l| l.strip_prefix(name).map(|v| v.trim_start_matches(':').trim().to_string()))
+                .unwrap_or_default()
+        };
+        let length: usize = header("Content-Length").parse().unwrap();
+        let content = after[end + 4..end + 4 + length].to_vec();
+        rest = &after[end + 4 + length + 2..];
+        let file_path = header("X-File-Path");
+        let result = if st.bulk_rejects.contains(&file_path) {
+            serde_json::json!({"error": true, "message": "Insufficient storage"})
+        } else {
+            let etag = format!("{:x}", sha2::Sha256::digest(&content));
+            st.files.insert(format!("{}{}", root, file_path), content);
+            serde_json::json!({"error": false, "etag": &etag[..16]})
+        };
+        results.insert(file_path, result);
+    }
+    Response::new(Body::from(serde_json::Value::Object(results).to_string()))
+}
+
+/// The ETag sent for `path`, if any.
+fn etag_of(st: &State, path: &str) -> Option<String> {
+    st.headers.get(path)?.iter().find(|(name, _)| name.eq_ignore_ascii_case("ETag")).map(|(_, value)| value.clone())
+}
+
+/// Whether the collection above `path` exists, explicitly or as the parent
+/// of a stored file.
+fn parent_exists(st: &State, path: &str) -> bool {
+    match path.trim_end_matches('/').rsplit_once('/') {
+        Some((parent, _)) => {
+            let prefix = format!("{}/", parent);
+            st.dirs.contains(parent) || st.files.keys().any(|p| p.starts_with(&prefix))
+        }
+        None => true,
+    }
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    Response::builder().status(status).body(Body::empty()).unwrap()
+}
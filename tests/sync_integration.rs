@@ -29,7 +29,7 @@ async fn test_sync_upload_when_missing() {
     delete_remote_file(REMOTE_PATH).await;
     sleep(Duration::from_secs(1)).await;
 
-    let config = Config::load(&TEST_CONFIG).expect("load config");
+    let config = Config::load(TEST_CONFIG).expect("load config");
     let _ = std::fs::remove_file("hashes.yaml");
 
     sync(&config).await.expect("Initial sync (upload) failed");
@@ -45,7 +45,7 @@ async fn test_sync_upload_when_missing() {
 #[tokio::test]
 #[serial]
 async fn test_sync_no_change_when_already_present() {
-    let config = Config::load(&TEST_CONFIG).expect("load config");
+    let config = Config::load(TEST_CONFIG).expect("load config");
     let _ = std::fs::remove_file("hashes.yaml");
 
     sync(&config).await.expect("Initial sync (upload) failed");
@@ -80,7 +80,7 @@ async fn test_sync_overwrites_changed_remote_file() {
     // Ensure the broken file is removed before sync to test overwrite behavior.
     delete_remote_file(REMOTE_PATH).await;
 
-    let config = Config::load(&TEST_CONFIG).expect("load config");
+    let config = Config::load(TEST_CONFIG).expect("load config");
     let _ = std::fs::remove_file("hashes.yaml");
 
     sync(&config).await.expect("Sync failed to overwrite remote file");
@@ -101,7 +101,7 @@ async fn test_sync_creates_remote_directory() {
     delete_remote_file(remote_path).await;
     let _ = std::fs::remove_file("hashes.yaml");
 
-    let config = Config::load(&TEST_CONFIG).expect("load config");
+    let config = Config::load(TEST_CONFIG).expect("load config");
     sync(&config).await.expect("Sync failed");
 
     let remote_content = fetch_remote_file(remote_path)
@@ -124,7 +124,7 @@ async fn test_sync_respects_target_dir() {
     sleep(Duration::from_secs(1)).await;
 
     // Create a temporary config with target_dir set.
-    let mut config = Config::load(&TEST_CONFIG).expect("load config");
+    let mut config = Config::load(TEST_CONFIG).expect("load config");
     config.target_dir = "remote/dir".to_string();
 
     // Perform sync.
@@ -150,7 +150,7 @@ async fn test_sync_does_not_upload_hash_store() {
     delete_remote_file("hashes.yaml").await;
     // Ensure local hash store does not exist before sync.
     let _ = std::fs::remove_file("hashes.yaml");
-    let config = Config::load(&TEST_CONFIG).expect("load config");
+    let config = Config::load(TEST_CONFIG).expect("load config");
     sync(&config).await.expect("sync failed");
     // The remote hash store should not be present.
     let remote_hash = fetch_remote_file("hashes.yaml").await;
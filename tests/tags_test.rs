@@ -0,0 +1,28 @@
+use phone_sync::hash_store::HashStore;
+use phone_sync::sync::sync;
+use std::fs;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+#[tokio::test]
+async fn test_tags_survive_sync_updating_the_hash() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(data.join("a.txt"), "new content").unwrap();
+
+    let mut remote = HashStore::default();
+    remote.regular_hashes.insert("a.txt".to_string(), "stale".to_string());
+    remote.set_tag("a.txt", "archived", "tape");
+    server.put_file("hashes.yaml", serde_yaml::to_string(&remote).unwrap().as_bytes());
+
+    let config = TestConfig::in_work_dir(&server.url, work.path()).build();
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 1);
+
+    let uploaded: HashStore = serde_yaml::from_slice(&server.file("hashes.yaml").unwrap()).unwrap();
+    assert_ne!(uploaded.regular_hashes["a.txt"], "stale");
+    assert!(uploaded.has_tag("a.txt", "archived", "tape"));
+}
@@ -0,0 +1,63 @@
+use phone_sync::config::Config;
+use phone_sync::doctor::doctor;
+use phone_sync::output::HumanDisplay;
+use phone_sync::sync::sync;
+use phone_sync::target_dir::TargetDirState;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+fn config(server: &StubServer, work: &Path, extra: &str) -> Config {
+    let data = work.join("data");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(data.join("a.jpg"), b"first").unwrap();
+    fs::write(data.join("b.jpg"), b"second").unwrap();
+    TestConfig::new(&server.url, &work.join("hashes.yaml"))
+        .yaml("target_dir: /phone/camera/2024/\n")
+        .folders(&[&data])
+        .yaml("remote_hash_path: phone/camera/2024/.hashes.yaml\n")
+        .yaml(extra)
+        .build()
+}
+
+#[tokio::test]
+async fn test_first_sync_creates_nested_target_dir() {
+    let server = StubServer::start().await;
+    server.require_parents();
+    let work = tempfile::tempdir().unwrap();
+
+    let report = sync(&config(&server, work.path(), "")).await.unwrap();
+    assert_eq!(report.uploaded, 2);
+    let mkcols: Vec<String> = server.requests().into_iter().filter(|r| r.method == "MKCOL").map(|r| r.path).collect();
+    assert_eq!(mkcols[..3], ["phone/", "phone/camera/", "phone/camera/2024/"], "{:?}", mkcols);
+    assert_eq!(server.file("phone/camera/2024/a.jpg").unwrap(), b"first");
+    assert!(server.file("phone/camera/2024/.hashes.yaml").is_some());
+}
+
+#[tokio::test]
+async fn test_doctor_reports_target_dir() {
+    let server = StubServer::start().await;
+    server.require_parents();
+    let work = tempfile::tempdir().unwrap();
+
+    let check = |extra: &str| {
+        let config = config(&server, work.path(), extra);
+        async move { doctor(&WebDavClient::from_config(&config).unwrap(), &config).await.unwrap().target_dir }
+    };
+    assert_eq!(check("read_only: true\n").await.state, Some(TargetDirState::Missing));
+    assert_eq!(check("create_target_dir: false\n").await.state, Some(TargetDirState::Missing));
+    assert_eq!(server.count("MKCOL"), 0);
+
+    server.fail_next("MKCOL", 1, 507);
+    let failed = check("").await;
+    assert_eq!(failed.state, None);
+    assert!(failed.human().contains("cannot be created: Cannot create target_dir 'phone/camera/2024'"), "{}", failed.human());
+
+    assert_eq!(check("").await.state, Some(TargetDirState::Created));
+    let existing = check("").await;
+    assert_eq!(existing.state, Some(TargetDirState::Existed));
+    assert_eq!(existing.human(), "target_dir 'phone/camera/2024': exists");
+}
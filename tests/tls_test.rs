@@ -0,0 +1,131 @@
+use phone_sync::webdav_client::{ClientIdentity, PoolSettings, TlsSettings, WebDavClient};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+/// HTTPS server with a self-signed certificate for 127.0.0.1 that answers
+/// every request with an empty 200.
+async fn start_self_signed_server() -> String {
+    let cert = std::fs::read(fixture("self_signed.pem")).unwrap();
+    let key = std::fs::read(fixture("self_signed.key")).unwrap();
+    let certs = rustls_pemfile::certs(&mut cert.as_slice()).unwrap().into_iter().map(Certificate).collect();
+    let key = PrivateKey(rustls_pemfile::pkcs8_private_keys(&mut key.as_slice()).unwrap().remove(0));
+    let config = ServerConfig::builder().with_safe_defaults().with_no_client_auth().with_single_cert(certs, key).unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("https://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        loop {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                // Rejected handshakes are what some tests expect.
+                let Ok(mut tls) = acceptor.accept(tcp).await else { return };
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match tls.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let _ = tls.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+                let _ = tls.shutdown().await;
+            });
+        }
+    });
+    url
+}
+
+fn client(url: &str, tls: TlsSettings) -> Result<WebDavClient, Box<dyn std::error::Error>> {
+    WebDavClient::with_pool(url, None, None, Duration::from_secs(3), PoolSettings { tls, ..PoolSettings::default() })
+}
+
+#[tokio::test]
+async fn test_self_signed_certificate_is_rejected_by_default() {
+    let url = start_self_signed_server().await;
+    let client = client(&url, TlsSettings::default()).unwrap();
+    assert!(client.stat("a.txt").await.is_err());
+}
+
+#[tokio::test]
+async fn test_trusted_ca_certificate_connects() {
+    let url = start_self_signed_server().await;
+    let tls = TlsSettings { ca_cert_path: Some(fixture("self_signed.pem")), ..TlsSettings::default() };
+    assert!(client(&url, tls).unwrap().stat("a.txt").await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_insecure_tls_accepts_any_certificate() {
+    let url = start_self_signed_server().await;
+    let tls = TlsSettings { insecure: true, ..TlsSettings::default() };
+    assert!(client(&url, tls).unwrap().stat("a.txt").await.unwrap().is_some());
+}
+
+#[test]
+fn test_unusable_ca_certificate_names_the_path() {
+    let missing = fixture("missing.pem");
+    let err = client("https://127.0.0.1", TlsSettings { ca_cert_path: Some(missing.clone()), ..TlsSettings::default() })
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(err.contains(&missing.display().to_string()), "{}", err);
+
+    let not_pem = fixture("self_signed.key");
+    let err = client("https://127.0.0.1", TlsSettings { ca_cert_path: Some(not_pem.clone()), ..TlsSettings::default() })
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(err.contains(&not_pem.display().to_string()), "{}", err);
+}
+
+fn client_identity(identity: ClientIdentity) -> Result<WebDavClient, Box<dyn std::error::Error>> {
+    client("https://127.0.0.1", TlsSettings { client_identity: Some(identity), ..TlsSettings::default() })
+}
+
+fn pem(cert: &str, key: &str) -> ClientIdentity {
+    ClientIdentity::Pem { cert_path: fixture(cert), key_path: fixture(key) }
+}
+
+fn pkcs12(passphrase: &str) -> ClientIdentity {
+    ClientIdentity::Pkcs12 { path: fixture("client.p12"), passphrase: Some(passphrase.to_string()) }
+}
+
+#[test]
+fn test_client_certificate_is_loaded() {
+    assert!(client_identity(pem("client.pem", "client.key")).is_ok());
+    #[cfg(feature = "tls-native")]
+    assert!(client_identity(pkcs12("secret")).is_ok());
+    #[cfg(not(feature = "tls-native"))]
+    assert!(client_identity(pkcs12("secret")).err().unwrap().to_string().contains("need the tls-native feature"));
+}
+
+#[test]
+fn test_unusable_client_certificate_is_named() {
+    let err = client_identity(pem("missing.pem", "client.key")).err().unwrap().to_string();
+    assert!(err.contains("Cannot read client certificate") && err.contains("missing.pem"), "{}", err);
+    let err = client_identity(pem("client.pem", "missing.key")).err().unwrap().to_string();
+    assert!(err.contains("Cannot read client key") && err.contains("missing.key"), "{}", err);
+    let err = client_identity(pem("client.key", "client.key")).err().unwrap().to_string();
+    assert!(err.contains("is not a PEM certificate"), "{}", err);
+    let err = client_identity(pem("client.pem", "client.pem")).err().unwrap().to_string();
+    assert!(err.contains("Invalid client key") && err.contains("client.pem"), "{}", err);
+
+    // The key of another certificate; rustls only finds out in the handshake.
+    #[cfg(feature = "tls-native")]
+    {
+        let err = client_identity(pem("client.pem", "self_signed.key")).err().unwrap().to_string();
+        assert!(err.contains("does not belong to certificate"), "{}", err);
+
+        let err = client_identity(pkcs12("wrong")).err().unwrap().to_string();
+        assert!(err.contains("client.p12") && err.contains("wrong passphrase"), "{}", err);
+    }
+}
@@ -0,0 +1,133 @@
+use log::{Log, Metadata, Record};
+use phone_sync::hash_store::HashStore;
+use std::fs;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// Keeps the messages of every log record.
+struct Capture(Mutex<Vec<String>>);
+
+impl Log for Capture {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.0.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGS: Capture = Capture(Mutex::new(Vec::new()));
+
+/// Log messages about `path` so far.
+fn logs_about(path: &str) -> Vec<String> {
+    LOGS.0.lock().unwrap().iter().filter(|m| m.contains(path)).cloned().collect()
+}
+
+fn init_logs() {
+    let _ = log::set_logger(&LOGS);
+    log::set_max_level(log::LevelFilter::Warn);
+}
+
+fn store(entries: usize) -> HashStore {
+    let mut store = HashStore::default();
+    for i in 0..entries {
+        store.regular_hashes.insert(format!("photos/{:03}.jpg", i), format!("{:064x}", i));
+    }
+    store
+}
+
+/// Restore `full` to `path` after a moment, like a script finishing its rewrite.
+fn finish_rewrite_soon(path: &std::path::Path, full: String) -> thread::JoinHandle<()> {
+    let path = path.to_path_buf();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        fs::write(path, full).unwrap();
+    })
+}
+
+#[test]
+fn test_truncated_store_is_read_again() {
+    init_logs();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("truncated.yaml");
+    store(40).save(&path).unwrap();
+    let full = fs::read_to_string(&path).unwrap();
+    assert!(full.starts_with("entry_count: 40\n"), "{}", full);
+
+    // Cut after 10 of the 40 entries, on a line boundary, so it still parses.
+    let cut: String = full.lines().take(12).map(|l| format!("{}\n", l)).collect();
+    fs::write(&path, format!("{}pseudo_hashes: {{}}\n", cut)).unwrap();
+    let writer = finish_rewrite_soon(&path, full);
+
+    let loaded = HashStore::load(&path).unwrap();
+    writer.join().unwrap();
+    assert_eq!(loaded.regular_hashes.len(), 40);
+    let logs = logs_about("truncated.yaml");
+    assert_eq!(logs.len(), 1, "{:?}", logs);
+    assert!(logs[0].contains("looks torn (10 entries where its last save recorded 40)"), "{}", logs[0]);
+    assert!(logs[0].contains("attempt 2 of 3"), "{}", logs[0]);
+}
+
+#[test]
+fn test_unparsable_store_is_read_again_then_fails() {
+    init_logs();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("half.yaml");
+    store(5).save(&path).unwrap();
+    let full = fs::read_to_string(&path).unwrap();
+
+    // Half rewritten: the pseudo_hashes section is not there yet.
+    let half: String = full.lines().take(4).map(|l| format!("{}\n", l)).collect();
+    fs::write(&path, &half).unwrap();
+    let writer = finish_rewrite_soon(&path, full);
+    assert_eq!(HashStore::load(&path).unwrap().regular_hashes.len(), 5);
+    writer.join().unwrap();
+    let logs = logs_about("half.yaml");
+    assert!(logs[0].contains("looks torn (a parse error: missing field `pseudo_hashes`"), "{:?}", logs);
+
+    // A store that stays broken fails after the last attempt.
+    fs::write(&path, &half).unwrap();
+    assert!(HashStore::load(&path).unwrap_err().to_string().contains("pseudo_hashes"));
+    assert_eq!(logs_about("half.yaml").len(), 3);
+}
+
+#[test]
+fn test_store_that_stays_small_is_used() {
+    init_logs();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("trimmed.yaml");
+    fs::write(&path, "entry_count: 100\nregular_hashes:\n  a.jpg: h\npseudo_hashes: {}\n").unwrap();
+
+    assert_eq!(HashStore::load(&path).unwrap().regular_hashes.len(), 1);
+    let logs = logs_about("trimmed.yaml");
+    assert_eq!(logs.len(), 3, "{:?}", logs);
+    assert!(logs[2].contains("still has 1 entries where its last save recorded 100, using it as it is"), "{}", logs[2]);
+}
+
+#[tokio::test]
+async fn test_async_load_leaves_the_runtime_running_between_reads() {
+    init_logs();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("waiting.yaml");
+    fs::write(&path, "entry_count: 100\nregular_hashes:\n  a.jpg: h\npseudo_hashes: {}\n").unwrap();
+
+    // A single-threaded runtime only runs this while the load is paused.
+    let ticks = tokio::spawn(async {
+        let mut ticks = 0;
+        loop {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            ticks += 1;
+            if ticks == 5 {
+                return ticks;
+            }
+        }
+    });
+    let store = HashStore::load_scoped_async(&path, None).await.unwrap();
+    assert_eq!(store.regular_hashes.len(), 1);
+    assert!(ticks.is_finished(), "the runtime was blocked by the retries");
+    assert_eq!(logs_about("waiting.yaml").len(), 3);
+}
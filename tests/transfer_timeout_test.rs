@@ -0,0 +1,55 @@
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// A client with a 1s request timeout that gives uploads time for 2 Mbit/s.
+fn client(server: &StubServer, work: &Path) -> WebDavClient {
+    let config = TestConfig::new(&server.url, &work.join("hashes.yaml"))
+        .folders(&[work])
+        .yaml("timeout: 1s\nmin_upload_kbps: 2000\nrequest_retries: 0\n")
+        .build();
+    WebDavClient::from_config(&config).unwrap()
+}
+
+#[tokio::test]
+async fn test_slow_large_upload_outlasts_the_request_timeout() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let client = client(&server, work.path());
+    let local = work.path().join("video.mp4");
+    fs::write(&local, vec![7u8; 1024 * 1024]).unwrap();
+
+    // 2s at 512 KiB/s, well within the 1s + 4.2s the upload is given.
+    server.throttle_uploads(512 * 1024);
+    let started = Instant::now();
+    client.upload_file(&local, "video.mp4").await.unwrap();
+    assert!(started.elapsed() > Duration::from_millis(1500), "{:?}", started.elapsed());
+    assert_eq!(server.file("video.mp4").unwrap().len(), 1024 * 1024);
+}
+
+#[tokio::test]
+async fn test_stalled_requests_still_time_out() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let client = client(&server, work.path());
+    server.put_file("a.jpg", b"jpeg");
+
+    server.delay("HEAD", Duration::from_secs(10));
+    let started = Instant::now();
+    let err = client.file_exists("a.jpg").await.unwrap_err().to_string();
+    assert!(err.contains("timed out"), "{}", err);
+    assert!(started.elapsed() < Duration::from_secs(3), "{:?}", started.elapsed());
+
+    // A small upload gets little more than the request timeout.
+    let local = work.path().join("note.txt");
+    fs::write(&local, b"note").unwrap();
+    server.delay("PUT", Duration::from_secs(10));
+    let started = Instant::now();
+    let err = client.upload_file(&local, "note.txt").await.unwrap_err().to_string();
+    assert!(err.contains("timeout"), "{}", err);
+    assert!(started.elapsed() < Duration::from_secs(3), "{:?}", started.elapsed());
+}
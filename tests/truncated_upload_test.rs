@@ -0,0 +1,95 @@
+use phone_sync::config::Config;
+use phone_sync::hash_store::HashStore;
+use phone_sync::sync::sync;
+use std::fs;
+use std::path::Path;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+/// Bytes a PUT body may have before the stub drops the rest.
+const LIMIT: usize = 1000;
+
+fn config(server: &StubServer, work: &Path, extra: &str) -> Config {
+    TestConfig::in_work_dir(&server.url, work).yaml(extra).build()
+}
+
+fn write_files(work: &Path, files: &[(&str, usize)]) {
+    let data = work.join("data");
+    fs::create_dir_all(&data).unwrap();
+    for (name, size) in files {
+        fs::write(data.join(name), vec![b'x'; *size]).unwrap();
+    }
+}
+
+fn recorded(config: &Config) -> Vec<String> {
+    HashStore::load(&config.hash_store_path).unwrap().regular_hashes.keys().cloned().collect()
+}
+
+#[tokio::test]
+async fn test_truncated_upload_is_reported_and_not_recorded() {
+    let server = StubServer::start().await;
+    server.truncate_uploads(LIMIT);
+    let work = tempfile::tempdir().unwrap();
+    write_files(work.path(), &[("a.jpg", 10), ("big.mp4", 2 * LIMIT), ("c.jpg", 20)]);
+    let config = config(&server, work.path(), "");
+
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.truncated, vec!["big.mp4"]);
+    assert_eq!(report.uploaded, 2);
+    assert!(phone_sync::output::HumanDisplay::human(&report).contains("server stored truncated content: big.mp4"));
+    assert_eq!(recorded(&config), vec!["a.jpg", "c.jpg"]);
+
+    // The next run tries again, and succeeds once the limit is gone.
+    server.truncate_uploads(usize::MAX);
+    let report = sync(&config).await.unwrap();
+    assert_eq!((report.uploaded, report.truncated.len()), (1, 0));
+    assert_eq!(server.file("big.mp4").unwrap().len(), 2 * LIMIT);
+    assert_eq!(recorded(&config), vec!["a.jpg", "big.mp4", "c.jpg"]);
+}
+
+#[tokio::test]
+async fn test_repeated_truncation_aborts_the_run() {
+    let server = StubServer::start().await;
+    server.truncate_uploads(LIMIT);
+    let work = tempfile::tempdir().unwrap();
+    write_files(work.path(), &[("1.mp4", 2 * LIMIT), ("2.mp4", 3 * LIMIT), ("3.mp4", 4 * LIMIT), ("4.mp4", 5 * LIMIT)]);
+    let config = config(&server, work.path(), "");
+
+    let err = sync(&config).await.unwrap_err().to_string();
+    assert!(err.contains("truncated content for 3 uploads"), "{}", err);
+    assert!(err.contains("body size"), "{}", err);
+    // The run stopped right after the third truncated upload.
+    assert_eq!(server.requests().iter().filter(|r| r.method == "PUT" && r.path.ends_with(".mp4")).count(), 3);
+    assert!(!Path::new(&config.hash_store_path).exists() || recorded(&config).is_empty());
+}
+
+#[tokio::test]
+async fn test_truncation_limit_is_configurable() {
+    let server = StubServer::start().await;
+    server.truncate_uploads(LIMIT);
+    let work = tempfile::tempdir().unwrap();
+    write_files(work.path(), &[("1.mp4", 2 * LIMIT), ("2.mp4", 3 * LIMIT), ("3.mp4", 4 * LIMIT), ("a.jpg", 10)]);
+
+    let mut report = sync(&config(&server, work.path(), "max_truncated_uploads: 4\n")).await.unwrap();
+    report.truncated.sort();
+    assert_eq!(report.truncated, vec!["1.mp4", "2.mp4", "3.mp4"]);
+    assert_eq!(report.uploaded, 1);
+
+    let err = sync(&config(&server, work.path(), "max_truncated_uploads: 1\n")).await.unwrap_err().to_string();
+    assert!(err.contains("truncated content for 1 uploads"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_size_check_can_be_disabled() {
+    let server = StubServer::start().await;
+    server.truncate_uploads(LIMIT);
+    let work = tempfile::tempdir().unwrap();
+    write_files(work.path(), &[("big.mp4", 2 * LIMIT)]);
+    let config = config(&server, work.path(), "verify_upload_size: false\n");
+
+    let report = sync(&config).await.unwrap();
+    assert!(report.truncated.is_empty());
+    assert_eq!(server.count("HEAD"), 0);
+    assert_eq!(recorded(&config), vec!["big.mp4"]);
+}
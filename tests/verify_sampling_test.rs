@@ -0,0 +1,46 @@
+use phone_sync::hash_store::HashStore;
+use phone_sync::sync::sync;
+use std::fs;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+#[tokio::test]
+async fn test_corrupted_uploads_fail_the_run_and_flag_their_folder() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        fs::write(data.join(name), name).unwrap();
+    }
+    let store_path = work.path().join("hashes.yaml");
+    let config = TestConfig::new(&server.url, &store_path)
+        .folders(&[&data])
+        .yaml("remote_hash_store: disabled\nverify_sampling:\n  fraction: 0.5\n  min_per_run: 1\n  seed: 7\n")
+        .build();
+
+    server.corrupt_uploads(true);
+    let err = sync(&config).await.err().unwrap().to_string();
+    assert!(err.contains("Verification found 2 uploaded files"), "{}", err);
+    let store = HashStore::load(&store_path).unwrap();
+    assert_eq!(store.regular_hashes.len(), 1);
+    assert_eq!(store.full_verify_folders.len(), 1);
+
+    // The next run uploads the two files again and verifies the whole folder,
+    // which finds the corrupted file the sample missed.
+    server.corrupt_uploads(false);
+    let err = sync(&config).await.err().unwrap().to_string();
+    assert!(err.contains("Verification found 1 uploaded files that differ on the server (a.txt)"), "{}", err);
+    assert_eq!(HashStore::load(&store_path).unwrap().regular_hashes.len(), 2);
+
+    server.clear_requests();
+    let report = sync(&config).await.unwrap();
+    assert_eq!(report.uploaded, 1);
+    let sample = report.sample.unwrap();
+    assert_eq!(sample.verified.len(), 3);
+    assert!(sample.mismatched.is_empty());
+    assert_eq!(sample.fully_verified.len(), 1);
+    assert_eq!(server.count("GET"), 3);
+    assert!(HashStore::load(&store_path).unwrap().full_verify_folders.is_empty());
+}
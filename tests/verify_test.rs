@@ -0,0 +1,62 @@
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store::HashStore;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::sync::sync_with_guard;
+use phone_sync::verify::quick_verify;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+#[tokio::test]
+async fn test_quick_verify_reports_each_drift_category() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    fs::create_dir_all(&data).unwrap();
+    for name in ["a.jpg", "b.jpg", "c.jpg", "d.jpg"] {
+        fs::write(data.join(name), name).unwrap();
+        server.set_header(name, "ETag", "\"v1\"");
+    }
+    let hash_store_path = work.path().join("hashes.yaml");
+    let config = TestConfig::new(&server.url, &hash_store_path).folders(&[&data]).yaml("trust_mtime: true\n").build();
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    // Fingerprints are recorded once a run finds the uploaded files unchanged.
+    for _ in 0..2 {
+        let mut guard = HashStoreGuard::new(client.clone(), &config).await.unwrap();
+        sync_with_guard(&config, &client, &mut guard, false, false, &FilterSet::default()).await.unwrap();
+        guard.finalize().await.unwrap();
+    }
+
+    let store = HashStore::load(&hash_store_path).unwrap();
+    let clean = quick_verify(&config, &client, &store, &FilterSet::default()).await.unwrap();
+    assert!(!clean.has_drift(), "{:?}", clean);
+    assert_eq!((clean.checked, clean.unstamped), (4, 0));
+
+    fs::write(data.join("a.jpg"), "a.jpg, edited").unwrap();
+    server.remove_file("b.jpg");
+    server.clear_headers("c.jpg");
+    server.set_header("c.jpg", "ETag", "\"v2\"");
+    fs::write(data.join("e.jpg"), "e").unwrap();
+    server.put_file("e.jpg", b"e");
+    server.put_file("f.jpg", b"only on the server");
+    let stored_before = fs::read(&hash_store_path).unwrap();
+    server.clear_requests();
+
+    let report = quick_verify(&config, &client, &store, &FilterSet::default()).await.unwrap();
+    assert!(report.has_drift());
+    assert_eq!(report.checked, 5);
+    assert_eq!(report.local_changed, vec!["a.jpg"]);
+    assert_eq!(report.remote_missing, vec!["b.jpg"]);
+    assert_eq!(report.remote_changed, vec!["c.jpg"]);
+    assert_eq!(report.local_untracked, vec!["e.jpg"]);
+    assert_eq!(report.remote_untracked, vec!["f.jpg"]);
+
+    // Read-only: one listing and HEADs of listed files without a size, and
+    // the store is untouched.
+    assert!(server.requests().iter().all(|r| r.method == "HEAD" || r.method == "PROPFIND"));
+    assert_eq!(server.count("PROPFIND"), 1);
+    assert_eq!(fs::read(&hash_store_path).unwrap(), stored_before);
+}
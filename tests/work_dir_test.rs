@@ -0,0 +1,40 @@
+use phone_sync::filter::FilterSet;
+use phone_sync::hash_store_guard::HashStoreGuard;
+use phone_sync::sync::sync_with_guard;
+use phone_sync::webdav_client::WebDavClient;
+use std::fs;
+use std::time::Duration;
+
+mod stub_server;
+use stub_server::{StubServer, TestConfig};
+
+#[tokio::test]
+async fn test_failed_run_removes_its_work_dir() {
+    let server = StubServer::start().await;
+    let work = tempfile::tempdir().unwrap();
+    let data = work.path().join("data");
+    let temp_root = work.path().join("tmp");
+    fs::create_dir_all(&data).unwrap();
+    fs::write(data.join("a.jpg"), "a").unwrap();
+    let config = TestConfig::in_work_dir(&server.url, work.path())
+        .path("temp_dir", &temp_root)
+        .yaml("upload_retries: 0\n")
+        .build();
+    server.fail_next("PUT", 1, 403);
+
+    let client = WebDavClient::new(&server.url, None, None, Duration::from_secs(3)).unwrap();
+    let mut guard = HashStoreGuard::new(client.clone(), &config).await.unwrap();
+    let run_dir = guard.work_dir().path().to_path_buf();
+    assert!(run_dir.starts_with(&temp_root));
+    let resume = guard.work_dir().persistent("resume.yaml");
+    fs::write(&resume, "offset: 1").unwrap();
+    fs::write(guard.work_dir().file("upload.part"), "a").unwrap();
+
+    let result = sync_with_guard(&config, &client, &mut guard, false, false, &FilterSet::default()).await;
+    assert!(result.is_err());
+    drop(guard);
+
+    assert!(!run_dir.exists());
+    let left: Vec<_> = fs::read_dir(&temp_root).unwrap().map(|e| e.unwrap().path()).collect();
+    assert_eq!(left, vec![resume]);
+}